@@ -0,0 +1,160 @@
+//! This program queries a Cincinnati endpoint for the updates available from
+//! a release, or for the update path between two releases.
+//!
+//! The graph of the requested channel is fetched in the version 1 JSON
+//! format. See `docs/user/cincinnati-client.md`.
+
+#[macro_use]
+extern crate serde_derive;
+
+mod query;
+
+use cincinnati::metadata_keys::MetadataKeys;
+use cincinnati::plugins::internal::versioned_graph::VersionedGraph;
+use cincinnati::v2::GraphV2;
+use cincinnati::Graph;
+use commons::outbound::ClientIdentity;
+use commons::prelude_errors::*;
+use reqwest::header::{HeaderValue, ACCEPT};
+use std::time::Duration;
+use structopt::StructOpt;
+
+/// Output format.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Output {
+    Table,
+    Json,
+}
+
+impl std::str::FromStr for Output {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Fallible<Self> {
+        match s {
+            "table" => Ok(Output::Table),
+            "json" => Ok(Output::Json),
+            _ => bail!("unknown output format '{}', expected table or json", s),
+        }
+    }
+}
+
+/// Graph to query.
+#[derive(Debug, StructOpt)]
+struct GraphOptions {
+    /// URL of the graph endpoint
+    #[structopt(long = "url")]
+    url: String,
+
+    /// Channel of the graph
+    #[structopt(long = "channel")]
+    channel: String,
+
+    /// Architecture of the releases
+    #[structopt(long = "arch", default_value = "amd64")]
+    arch: String,
+
+    /// Output format, `table` or `json`
+    #[structopt(long = "output", default_value = "table")]
+    output: Output,
+
+    /// Timeout of the graph request, in seconds
+    #[structopt(long = "timeout", default_value = "30")]
+    timeout: u64,
+
+    /// Prefix of the release metadata keys
+    #[structopt(long = "key-prefix", default_value = "io.openshift.upgrades.graph")]
+    key_prefix: String,
+}
+
+/// Query a Cincinnati endpoint.
+#[derive(Debug, StructOpt)]
+#[structopt(name = "cincinnati-client")]
+enum Command {
+    /// List the updates available from a release
+    Updates {
+        /// Version of the release to update from
+        #[structopt(long = "from")]
+        from: String,
+
+        #[structopt(flatten)]
+        graph: GraphOptions,
+    },
+
+    /// Show the shortest update path between two releases
+    Path {
+        /// Version of the release to update from
+        #[structopt(long = "from")]
+        from: String,
+
+        /// Version of the release to update to
+        #[structopt(long = "to")]
+        to: String,
+
+        #[structopt(flatten)]
+        graph: GraphOptions,
+    },
+}
+
+/// Fetch the graph of the channel.
+async fn fetch(options: &GraphOptions) -> Fallible<Graph> {
+    let response = commons::outbound::client_builder()
+        .timeout(Duration::from_secs(options.timeout))
+        .build()?
+        .get(&options.url)
+        .query(&[("channel", &options.channel), ("arch", &options.arch)])
+        .header(ACCEPT, HeaderValue::from_static(cincinnati::CONTENT_TYPE))
+        .send()
+        .await
+        .context(format!("fetching graph from {}", options.url))?;
+    ensure!(
+        response.status().is_success(),
+        "fetching graph from {}: unexpected status {}",
+        options.url,
+        response.status()
+    );
+
+    let body = response.bytes().await?;
+    let graph: VersionedGraph =
+        serde_json::from_slice(&body).context(format!("parsing graph from {}", options.url))?;
+    Ok(graph.graph)
+}
+
+fn main() -> Fallible<()> {
+    let command = Command::from_args();
+    commons::outbound::set_identity(&ClientIdentity::new(
+        "cincinnati-client",
+        env!("CARGO_PKG_VERSION"),
+    ))?;
+
+    let options = match &command {
+        Command::Updates { graph, .. } | Command::Path { graph, .. } => graph,
+    };
+    let runtime = tokio::runtime::Runtime::new()?;
+    let graph = runtime.block_on(fetch(options))?;
+
+    match &command {
+        Command::Updates { from, .. } => {
+            let v2 = GraphV2::new(
+                &graph,
+                None,
+                &MetadataKeys::with_prefix(&options.key_prefix),
+            );
+            let updates = query::updates(&v2, from)?;
+            match options.output {
+                Output::Table if updates.updates.is_empty() => {
+                    println!("no updates available from {}", from)
+                }
+                Output::Table => print!("{}", updates.to_table()),
+                Output::Json => println!("{}", serde_json::to_string_pretty(&updates)?),
+            }
+        }
+        Command::Path { from, to, .. } => {
+            let path = query::path(&graph, from, to)?;
+            match options.output {
+                Output::Table => print!("{}", path.to_table()),
+                Output::Json => println!("{}", serde_json::to_string_pretty(&path)?),
+            }
+        }
+    }
+    Ok(())
+}
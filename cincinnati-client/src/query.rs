@@ -0,0 +1,220 @@
+//! Questions answered from a graph.
+//!
+//! Updates are listed as in the version 2 graph format, so that conditional
+//! updates show up along with their risks rather than being left out.
+
+use cincinnati::v2::GraphV2;
+use cincinnati::{ConditionalUpdateRisk, Graph};
+use commons::prelude_errors::*;
+use semver::Version;
+
+/// An update available from a release.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Update<'a> {
+    pub version: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub payload: Option<&'a str>,
+    /// Risks of the update, empty if it is recommended unconditionally.
+    pub risks: Vec<&'a ConditionalUpdateRisk>,
+}
+
+/// Updates available from a release.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Updates<'a> {
+    pub from: &'a str,
+    /// Available updates, by ascending version.
+    pub updates: Vec<Update<'a>>,
+}
+
+/// An update along a path.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Step<'a> {
+    pub from: &'a str,
+    pub to: &'a str,
+    /// Risks of the update, empty if it is recommended unconditionally.
+    pub risks: Vec<&'a ConditionalUpdateRisk>,
+}
+
+/// Update path between two releases.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Path<'a> {
+    pub from: &'a str,
+    pub to: &'a str,
+    /// Updates along the path, in update order.
+    pub path: Vec<Step<'a>>,
+}
+
+/// Returns the updates available from the release `from`.
+pub fn updates<'a>(graph: &'a GraphV2<'a>, from: &'a str) -> Fallible<Updates<'a>> {
+    ensure!(
+        graph.nodes.iter().any(|node| node.version == from),
+        "release {} is not in the graph",
+        from
+    );
+
+    let mut updates: Vec<Update> = graph
+        .edges
+        .iter()
+        .filter(|edge| edge.from == from)
+        .map(|edge| Update {
+            version: edge.to,
+            payload: graph
+                .nodes
+                .iter()
+                .find(|node| node.version == edge.to)
+                .and_then(|node| node.payload),
+            risks: edge.risks.clone(),
+        })
+        .collect();
+    updates.sort_by(|a, b| {
+        Version::parse(a.version)
+            .ok()
+            .cmp(&Version::parse(b.version).ok())
+            .then_with(|| a.version.cmp(b.version))
+    });
+
+    Ok(Updates { from, updates })
+}
+
+/// Returns the shortest update path from the release `from` to the release `to`.
+pub fn path<'a>(graph: &'a Graph, from: &'a str, to: &'a str) -> Fallible<Path<'a>> {
+    for version in &[from, to] {
+        ensure!(
+            graph.find_by_version(version).is_some(),
+            "release {} is not in the graph",
+            version
+        );
+    }
+
+    let path = graph
+        .shortest_path(from, to)
+        .ok_or_else(|| format_err!("no update path from {} to {}", from, to))?
+        .into_iter()
+        .map(|step| Step {
+            from: step.from,
+            to: step.to,
+            risks: step.risks,
+        })
+        .collect();
+
+    Ok(Path { from, to, path })
+}
+
+/// Format rows as a table with aligned columns, headers first.
+pub fn table(headers: &[&str], rows: &[Vec<String>]) -> String {
+    let mut widths: Vec<usize> = headers.iter().map(|header| header.len()).collect();
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.len());
+        }
+    }
+
+    let line = |cells: Vec<&str>| {
+        let mut line = cells
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{:width$}", cell, width = width))
+            .collect::<Vec<_>>()
+            .join("  ");
+        line.truncate(line.trim_end().len());
+        line.push('\n');
+        line
+    };
+
+    let mut table = line(headers.to_vec());
+    for row in rows {
+        table.push_str(&line(row.iter().map(String::as_str).collect()));
+    }
+    table
+}
+
+/// Returns the names of `risks`, for the table output.
+pub fn risk_names(risks: &[&ConditionalUpdateRisk]) -> String {
+    if risks.is_empty() {
+        return "-".to_string();
+    }
+    risks
+        .iter()
+        .map(|risk| risk.name.as_str())
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+impl Updates<'_> {
+    /// Format the updates as a table.
+    pub fn to_table(&self) -> String {
+        let rows: Vec<_> = self
+            .updates
+            .iter()
+            .map(|update| {
+                vec![
+                    update.version.to_string(),
+                    risk_names(&update.risks),
+                    update.payload.unwrap_or("-").to_string(),
+                ]
+            })
+            .collect();
+        table(&["VERSION", "RISKS", "PAYLOAD"], &rows)
+    }
+}
+
+impl Path<'_> {
+    /// Format the path as a table.
+    pub fn to_table(&self) -> String {
+        let rows: Vec<_> = self
+            .path
+            .iter()
+            .map(|step| {
+                vec![
+                    step.from.to_string(),
+                    step.to.to_string(),
+                    risk_names(&step.risks),
+                ]
+            })
+            .collect();
+        table(&["FROM", "TO", "RISKS"], &rows)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cincinnati::metadata_keys::MetadataKeys;
+    use cincinnati::testing::generate_graph;
+
+    #[test]
+    fn available_updates() -> Fallible<()> {
+        let graph = generate_graph(true, false);
+        let v2 = GraphV2::new(&graph, None, &MetadataKeys::default());
+
+        let updates = updates(&v2, "1.0.0")?;
+        let versions: Vec<_> = updates.updates.iter().map(|u| u.version).collect();
+        assert_eq!(versions, vec!["2.0.0", "3.0.0"]);
+        assert_eq!(updates.updates[0].risks[0].name, "BrokenUpdates");
+        assert_eq!(
+            updates.to_table(),
+            "VERSION  RISKS          PAYLOAD\n\
+             2.0.0    BrokenUpdates  image/2.0.0\n\
+             3.0.0    -              image/3.0.0\n"
+        );
+
+        assert!(super::updates(&v2, "3.0.0")?.updates.is_empty());
+        assert!(super::updates(&v2, "4.0.0").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn update_path() -> Fallible<()> {
+        let graph = generate_graph(true, false);
+
+        let path = path(&graph, "1.0.0", "3.0.0")?;
+        let steps: Vec<_> = path.path.iter().map(|s| (s.from, s.to)).collect();
+        assert_eq!(steps, vec![("1.0.0", "3.0.0")]);
+        assert_eq!(path.to_table(), "FROM   TO     RISKS\n1.0.0  3.0.0  -\n");
+
+        let err = super::path(&graph, "3.0.0", "1.0.0").unwrap_err();
+        assert_eq!(err.to_string(), "no update path from 3.0.0 to 1.0.0");
+        assert!(super::path(&graph, "1.0.0", "4.0.0").is_err());
+        Ok(())
+    }
+}
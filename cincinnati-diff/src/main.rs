@@ -0,0 +1,114 @@
+//! This program compares two Cincinnati graphs and prints their differences.
+//!
+//! Graphs are fetched from Cincinnati endpoints or read from files, in either
+//! case in the version 1 JSON format. See `docs/user/cincinnati-diff.md`.
+
+use cincinnati::diff::GraphDiff;
+use cincinnati::plugins::internal::versioned_graph::VersionedGraph;
+use cincinnati::Graph;
+use commons::outbound::ClientIdentity;
+use commons::prelude_errors::*;
+use reqwest::header::{HeaderValue, ACCEPT};
+use std::time::Duration;
+use structopt::StructOpt;
+
+/// Exit status when `--fail-on-removed-edges` is set and edges were removed.
+const REMOVED_EDGES_STATUS: i32 = 2;
+
+/// Output format.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Output {
+    Text,
+    Json,
+}
+
+impl std::str::FromStr for Output {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Fallible<Self> {
+        match s {
+            "text" => Ok(Output::Text),
+            "json" => Ok(Output::Json),
+            _ => bail!("unknown output format '{}', expected text or json", s),
+        }
+    }
+}
+
+/// Compare two Cincinnati graphs.
+#[derive(Debug, StructOpt)]
+#[structopt(name = "cincinnati-diff")]
+struct Options {
+    /// URL or path of the old graph
+    old: String,
+
+    /// URL or path of the new graph
+    new: String,
+
+    /// Output format, `text` or `json`
+    #[structopt(long = "output", default_value = "text")]
+    output: Output,
+
+    /// Exit with status 2 if any edge was removed
+    #[structopt(long = "fail-on-removed-edges")]
+    fail_on_removed_edges: bool,
+
+    /// Timeout of graph requests, in seconds
+    #[structopt(long = "timeout", default_value = "30")]
+    timeout: u64,
+}
+
+/// Fetch a graph from an URL, or read it from a file.
+async fn load(source: &str, timeout: Duration) -> Fallible<Graph> {
+    let body = if source.starts_with("http://") || source.starts_with("https://") {
+        let response = commons::outbound::client_builder()
+            .timeout(timeout)
+            .build()?
+            .get(source)
+            .header(ACCEPT, HeaderValue::from_static(cincinnati::CONTENT_TYPE))
+            .send()
+            .await
+            .context(format!("fetching graph from {}", source))?;
+        ensure!(
+            response.status().is_success(),
+            "fetching graph from {}: unexpected status {}",
+            source,
+            response.status()
+        );
+        response.bytes().await?.to_vec()
+    } else {
+        tokio::fs::read(source)
+            .await
+            .context(format!("reading graph from {}", source))?
+    };
+
+    let graph: VersionedGraph =
+        serde_json::from_slice(&body).context(format!("parsing graph from {}", source))?;
+    Ok(graph.graph)
+}
+
+fn main() -> Fallible<()> {
+    let options = Options::from_args();
+    commons::outbound::set_identity(&ClientIdentity::new(
+        "cincinnati-diff",
+        env!("CARGO_PKG_VERSION"),
+    ))?;
+
+    let timeout = Duration::from_secs(options.timeout);
+    let runtime = tokio::runtime::Runtime::new()?;
+    let (old, new) = runtime.block_on(async {
+        let (old, new) = tokio::join!(load(&options.old, timeout), load(&options.new, timeout));
+        Ok::<_, Error>((old?, new?))
+    })?;
+
+    let diff = GraphDiff::between(&old, &new);
+    match options.output {
+        Output::Text if diff.is_empty() => println!("no differences"),
+        Output::Text => print!("{}", diff),
+        Output::Json => println!("{}", serde_json::to_string_pretty(&diff)?),
+    }
+
+    if options.fail_on_removed_edges && !diff.edges_removed.is_empty() {
+        std::process::exit(REMOVED_EDGES_STATUS);
+    }
+    Ok(())
+}
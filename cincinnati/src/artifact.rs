@@ -0,0 +1,103 @@
+//! Compact binary encoding of a published graph, exchanged between Cincinnati services.
+//!
+//! The artifact wraps the graph along with the revision it was published as,
+//! and is encoded as CBOR. Compared to the public JSON representation it is
+//! both smaller on the wire and cheaper to parse, which matters for
+//! policy-engine fetching the full graph from graph-builder on every refresh.
+
+use crate::Graph;
+use commons::prelude_errors::*;
+
+/// Media type of the encoded artifact.
+pub const CONTENT_TYPE: &str = "application/vnd.cincinnati.graph-artifact+cbor";
+
+/// Version of the artifact format, bumped on incompatible changes.
+pub const FORMAT_VERSION: u32 = 1;
+
+/// A published graph along with its revision metadata.
+#[derive(Debug, Clone, Deserialize)]
+pub struct GraphArtifact {
+    /// Version of the artifact format.
+    pub format: u32,
+    /// Revision of the published graph.
+    pub revision: u64,
+    /// UTC timestamp at which the revision was published.
+    pub published: i64,
+    /// The published graph, after all plugins ran.
+    pub graph: Graph,
+}
+
+/// Borrowed counterpart of `GraphArtifact`, to encode without cloning the graph.
+#[derive(Serialize)]
+struct GraphArtifactRef<'a> {
+    format: u32,
+    revision: u64,
+    published: i64,
+    graph: &'a Graph,
+}
+
+/// Encode a published graph as artifact.
+pub fn encode(revision: u64, published: i64, graph: &Graph) -> Fallible<Vec<u8>> {
+    let artifact = GraphArtifactRef {
+        format: FORMAT_VERSION,
+        revision,
+        published,
+        graph,
+    };
+    let mut encoded = Vec::new();
+    ciborium::ser::into_writer(&artifact, &mut encoded).context("encoding graph artifact")?;
+    Ok(encoded)
+}
+
+/// Decode an artifact, refusing unknown format versions.
+pub fn decode(bytes: &[u8]) -> Fallible<GraphArtifact> {
+    let artifact: GraphArtifact =
+        ciborium::de::from_reader(bytes).context("decoding graph artifact")?;
+    ensure!(
+        artifact.format == FORMAT_VERSION,
+        "unsupported graph artifact format {}, expected {}",
+        artifact.format,
+        FORMAT_VERSION
+    );
+    Ok(artifact)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn artifact_roundtrip() -> Fallible<()> {
+        let graph: Graph = serde_json::from_str(
+            r#"{
+                "nodes": [
+                    {"version": "4.10.1", "payload": "quay.io/ocp/release@sha256:1", "metadata": {"io.openshift.upgrades.graph.release.channels": "stable-4.10"}},
+                    {"version": "4.10.2", "payload": "quay.io/ocp/release@sha256:2", "metadata": {}}
+                ],
+                "edges": [],
+                "conditionalEdges": [
+                    {
+                        "edges": [{"from": "4.10.1", "to": "4.10.2"}],
+                        "risks": [{"url": "https://example.com", "name": "Risk", "message": "Risky", "matchingRules": [{"type": "Always"}]}]
+                    }
+                ]
+            }"#,
+        )?;
+
+        let bytes = encode(7, 1_665_000_000, &graph)?;
+        assert!(bytes.len() < serde_json::to_vec(&graph)?.len());
+
+        let artifact = decode(&bytes)?;
+        assert_eq!(artifact.format, FORMAT_VERSION);
+        assert_eq!(artifact.revision, 7);
+        assert_eq!(artifact.published, 1_665_000_000);
+        assert_eq!(
+            serde_json::to_value(&artifact.graph)?,
+            serde_json::to_value(&graph)?
+        );
+
+        assert!(decode(b"not cbor").is_err());
+
+        Ok(())
+    }
+}
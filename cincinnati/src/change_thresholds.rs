@@ -0,0 +1,190 @@
+//! Limits on how much a graph may shrink between two publications.
+//!
+//! A graph losing many releases or edges at once is more likely the result of
+//! a broken scrape than of an intended change. Both the graph-builder
+//! validation and the `graph-sanity-check` plugin compare each new graph with
+//! the last accepted one against these thresholds.
+
+use crate::{Graph, Release};
+use commons::prelude_errors::*;
+use smart_default::SmartDefault;
+
+/// Plugin parameter set when the change thresholds are overridden for the
+/// current scrape.
+pub static OVERRIDE_PARAM_KEY: &str = "io.openshift.upgrades.graph.change_thresholds.override";
+
+/// Size of an accepted graph, used as reference for the change thresholds.
+///
+/// Abstract releases and the edges involving them are not counted, since they
+/// are placeholders which don't get published.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GraphSummary {
+    /// Number of concrete releases.
+    pub releases: u64,
+    /// Number of edges between concrete releases.
+    pub edges: u64,
+}
+
+impl GraphSummary {
+    /// Summarize the given graph.
+    pub fn of(graph: &Graph) -> Self {
+        let is_concrete = |release: &Release| matches!(release, Release::Concrete(_));
+        Self {
+            releases: graph.iter_releases().filter(|r| is_concrete(r)).count() as u64,
+            edges: graph
+                .iter_edges()
+                .filter(|(from, to)| is_concrete(from) && is_concrete(to))
+                .count() as u64,
+        }
+    }
+}
+
+/// Maximum tolerated shrinkage between two accepted graphs, in percent.
+#[derive(Clone, Copy, Debug, Deserialize, SmartDefault)]
+#[serde(default)]
+pub struct Thresholds {
+    /// Maximum percentage of releases which may disappear.
+    #[default(20.0)]
+    pub max_releases_removed_percent: f64,
+    /// Maximum percentage of edges which may disappear.
+    #[default(20.0)]
+    pub max_edges_removed_percent: f64,
+}
+
+impl Thresholds {
+    /// Ensure both percentages are between 0 and 100.
+    pub fn validate(&self, prefix: &str) -> Fallible<()> {
+        for (name, percent) in &[
+            (
+                "max_releases_removed_percent",
+                self.max_releases_removed_percent,
+            ),
+            ("max_edges_removed_percent", self.max_edges_removed_percent),
+        ] {
+            ensure!(
+                (0.0..=100.0).contains(percent),
+                "{}{} must be between 0 and 100, got {}",
+                prefix,
+                name,
+                percent
+            );
+        }
+        Ok(())
+    }
+
+    /// Compare a graph with the previous one, returning the violated
+    /// thresholds along with a description of the violation.
+    pub fn check(
+        &self,
+        previous: &GraphSummary,
+        current: &GraphSummary,
+    ) -> Vec<(Threshold, String)> {
+        let mut violations = vec![];
+
+        for (threshold, what, before, after, max_percent) in &[
+            (
+                Threshold::Releases,
+                "releases",
+                previous.releases,
+                current.releases,
+                self.max_releases_removed_percent,
+            ),
+            (
+                Threshold::Edges,
+                "edges",
+                previous.edges,
+                current.edges,
+                self.max_edges_removed_percent,
+            ),
+        ] {
+            let removed = removed_percent(*before, *after);
+            if removed > *max_percent {
+                violations.push((
+                    *threshold,
+                    format!(
+                        "{:.1}% of {} disappeared ({} -> {}), maximum is {}%",
+                        removed, what, before, after, max_percent
+                    ),
+                ));
+            }
+        }
+
+        violations
+    }
+}
+
+/// A change threshold.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Threshold {
+    Releases,
+    Edges,
+}
+
+/// Percentage by which a count decreased, 0 if it didn't.
+fn removed_percent(before: u64, after: u64) -> f64 {
+    if before == 0 || after >= before {
+        0.0
+    } else {
+        (before - after) as f64 * 100.0 / before as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::generate_custom_graph;
+
+    #[test]
+    fn summary_ignores_abstract_releases() {
+        let mut graph = generate_custom_graph(
+            "image",
+            (0..3).map(|i| (i, Default::default())).collect(),
+            Some(vec![(0, 1), (1, 2)]),
+        );
+        let summary = GraphSummary::of(&graph);
+        assert_eq!(
+            summary,
+            GraphSummary {
+                releases: 3,
+                edges: 2
+            }
+        );
+
+        let abstract_release = graph
+            .add_release(Release::Abstract(crate::AbstractRelease {
+                version: "3.0.0".into(),
+            }))
+            .unwrap();
+        let last = graph.find_by_version("2.0.0").unwrap();
+        graph.add_edge(&last, &abstract_release).unwrap();
+        assert_eq!(GraphSummary::of(&graph), summary);
+    }
+
+    #[test]
+    fn tolerates_growth_and_small_changes() {
+        let thresholds = Thresholds::default();
+        let previous = GraphSummary {
+            releases: 10,
+            edges: 10,
+        };
+        let current = GraphSummary {
+            releases: 9,
+            edges: 20,
+        };
+        assert!(thresholds.check(&previous, &current).is_empty());
+        assert!(thresholds
+            .check(&GraphSummary::default(), &current)
+            .is_empty());
+
+        let current = GraphSummary {
+            releases: 2,
+            edges: 10,
+        };
+        let violations = thresholds.check(&previous, &current);
+        assert_eq!(violations.len(), 1);
+        assert_eq!(violations[0].0, Threshold::Releases);
+        assert!(violations[0]
+            .1
+            .contains("80.0% of releases disappeared (10 -> 2)"));
+    }
+}
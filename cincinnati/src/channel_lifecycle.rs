@@ -0,0 +1,114 @@
+//! Lifecycle of the channels.
+//!
+//! graph-data can declare a channel deprecated or end-of-life, optionally with
+//! the date it stops receiving updates. The lifecycle is recorded on the graph
+//! itself, once per channel, so that it goes through the plugins along with
+//! the releases, and is surfaced to clients on the graphs of that channel and
+//! by the channels endpoint.
+
+use commons::prelude_errors::*;
+
+/// Header carrying the lifecycle of the requested channel, e.g. `deprecated; sunset=2024-06-30`.
+pub static LIFECYCLE_HEADER: &str = "cincinnati-channel-lifecycle";
+
+/// Format of the sunset dates.
+pub static SUNSET_FORMAT: &str = "%Y-%m-%d";
+
+/// Status of a channel which is being retired.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Status {
+    /// The channel still receives updates, but clients should move off it.
+    Deprecated,
+    /// The channel doesn't receive updates anymore.
+    EndOfLife,
+}
+
+impl Status {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Status::Deprecated => "deprecated",
+            Status::EndOfLife => "end-of-life",
+        }
+    }
+}
+
+impl std::str::FromStr for Status {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Fallible<Self> {
+        match s {
+            "deprecated" => Ok(Status::Deprecated),
+            "end-of-life" => Ok(Status::EndOfLife),
+            _ => bail!(
+                "unknown channel lifecycle '{}', expected 'deprecated' or 'end-of-life'",
+                s
+            ),
+        }
+    }
+}
+
+/// Lifecycle of a channel which is being retired.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct Lifecycle {
+    #[serde(rename = "lifecycle")]
+    pub status: Status,
+    /// Date the channel stops receiving updates, as `YYYY-MM-DD`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sunset: Option<String>,
+}
+
+impl Lifecycle {
+    /// Create a lifecycle, checking the format of the sunset date.
+    pub fn new(status: Status, sunset: Option<String>) -> Fallible<Self> {
+        if let Some(sunset) = &sunset {
+            chrono::NaiveDate::parse_from_str(sunset, SUNSET_FORMAT).context(format!(
+                "invalid sunset date '{}', expected YYYY-MM-DD",
+                sunset
+            ))?;
+        }
+        Ok(Self { status, sunset })
+    }
+
+    /// Returns the value of the lifecycle header.
+    pub fn header_value(&self) -> String {
+        match &self.sunset {
+            Some(sunset) => format!("{}; sunset={}", self.status.as_str(), sunset),
+            None => self.status.as_str().to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn graph_roundtrip() {
+        let lifecycle = Lifecycle::new(Status::Deprecated, Some("2024-06-30".to_string())).unwrap();
+        let mut graph = crate::Graph::default();
+        graph.set_channel_lifecycle("stable-4.10", lifecycle.clone());
+
+        let json = serde_json::to_value(&graph).unwrap();
+        assert_eq!(
+            json["channelLifecycles"],
+            serde_json::json!({"stable-4.10": {"lifecycle": "deprecated", "sunset": "2024-06-30"}})
+        );
+        let graph: crate::Graph = serde_json::from_value(json).unwrap();
+        assert_eq!(graph.channel_lifecycle("stable-4.10"), Some(&lifecycle));
+        assert_eq!(graph.channel_lifecycle("fast-4.10"), None);
+        assert!(
+            serde_json::to_value(crate::Graph::default()).unwrap()["channelLifecycles"].is_null()
+        );
+
+        assert_eq!(lifecycle.header_value(), "deprecated; sunset=2024-06-30");
+        assert_eq!(
+            Lifecycle::new(Status::EndOfLife, None)
+                .unwrap()
+                .header_value(),
+            "end-of-life"
+        );
+
+        assert!(Lifecycle::new(Status::Deprecated, Some("30/06/2024".to_string())).is_err());
+    }
+}
@@ -1,4 +1,5 @@
 use smart_default::SmartDefault;
+use std::collections::BTreeMap;
 
 /// ConditionalEdge stores the conditional edges
 #[derive(Debug, Serialize, Deserialize, SmartDefault, Clone)]
@@ -27,6 +28,9 @@ pub struct ConditionalUpdateRisk {
     pub message: String,
     #[serde(rename = "matchingRules")]
     pub matching_rules: Vec<ClusterCondition>,
+    /// Fleet-wide PromQL condition, evaluated server-side before serving the graph
+    #[serde(rename = "fleetEvaluation", skip_serializing)]
+    pub fleet_evaluation: Option<PromQLClusterCondition>,
 }
 
 /// ClusterCondition has the Type and PromQL query used to identify the blocked clusters
@@ -37,6 +41,11 @@ pub struct ClusterCondition {
     pub condition_type: String,
     #[serde(skip_serializing_if = "PromQLClusterCondition::is_empty")]
     pub promql: PromQLClusterCondition,
+    #[serde(
+        rename = "clusterProfile",
+        skip_serializing_if = "ClusterProfileCondition::is_empty"
+    )]
+    pub cluster_profile: ClusterProfileCondition,
 }
 
 /// Contains the PromQL string
@@ -46,6 +55,16 @@ pub struct PromQLClusterCondition {
     pub promql: String,
 }
 
+/// Contains the cluster properties targeted by a `ClusterProfile` matching rule
+#[derive(Debug, Serialize, Deserialize, SmartDefault, Clone, Eq, PartialEq, Hash)]
+#[serde(default)]
+pub struct ClusterProfileCondition {
+    /// Client parameters and the values they must have, e.g. `platform = "vsphere"`
+    pub properties: BTreeMap<String, String>,
+    /// Client parameters holding component versions, and the SemVer requirements they must match
+    pub versions: BTreeMap<String, String>,
+}
+
 impl ConditionalEdge {
     /// gets the mutable vector of edges
     pub fn mut_edges(&mut self) -> &mut Vec<ConditionalUpdateEdge> {
@@ -59,3 +78,10 @@ impl PromQLClusterCondition {
         self.promql.is_empty()
     }
 }
+
+impl ClusterProfileCondition {
+    /// returns true if there is no cluster property to serialize.
+    pub fn is_empty(&self) -> bool {
+        self.properties.is_empty() && self.versions.is_empty()
+    }
+}
@@ -0,0 +1,231 @@
+//! Differences between two graphs.
+//!
+//! Releases are compared by version, and edges by the versions they connect,
+//! so that the order of the nodes doesn't matter. Conditional edges are
+//! compared apart from the regular ones, along with the names of the risks of
+//! the conditional edges found in both graphs.
+
+use crate::Graph;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+/// An edge between two versions.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct Edge {
+    /// Source version.
+    pub from: String,
+    /// Target version.
+    pub to: String,
+}
+
+impl fmt::Display for Edge {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} -> {}", self.from, self.to)
+    }
+}
+
+/// Change of the risks of a conditional edge present in both graphs.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RiskChange {
+    /// Source version.
+    pub from: String,
+    /// Target version.
+    pub to: String,
+    /// Names of the risks in the old graph, sorted.
+    pub risks_before: Vec<String>,
+    /// Names of the risks in the new graph, sorted.
+    pub risks_after: Vec<String>,
+}
+
+/// Changes between two graphs, by version.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GraphDiff {
+    pub releases_added: Vec<String>,
+    pub releases_removed: Vec<String>,
+    pub edges_added: Vec<Edge>,
+    pub edges_removed: Vec<Edge>,
+    pub conditional_edges_added: Vec<Edge>,
+    pub conditional_edges_removed: Vec<Edge>,
+    /// Conditional edges whose risks changed.
+    pub risks_changed: Vec<RiskChange>,
+}
+
+impl GraphDiff {
+    /// Compare `new` against `old`.
+    pub fn between(old: &Graph, new: &Graph) -> Self {
+        fn releases(graph: &Graph) -> BTreeSet<String> {
+            graph
+                .iter_releases()
+                .map(|release| release.version().to_string())
+                .collect()
+        }
+
+        fn edges(graph: &Graph) -> BTreeSet<Edge> {
+            graph
+                .iter_edges()
+                .map(|(from, to)| Edge {
+                    from: from.version().to_string(),
+                    to: to.version().to_string(),
+                })
+                .collect()
+        }
+
+        fn conditional_edges(graph: &Graph) -> BTreeMap<Edge, Vec<String>> {
+            graph
+                .conditional_edges()
+                .into_iter()
+                .map(|(from, to, risks)| {
+                    let mut risks: Vec<String> = risks.into_iter().map(str::to_string).collect();
+                    risks.sort();
+                    risks.dedup();
+                    let edge = Edge {
+                        from: from.to_string(),
+                        to: to.to_string(),
+                    };
+                    (edge, risks)
+                })
+                .collect()
+        }
+
+        fn diff<T: Clone + Ord>(old: &BTreeSet<T>, new: &BTreeSet<T>) -> (Vec<T>, Vec<T>) {
+            (
+                new.difference(old).cloned().collect(),
+                old.difference(new).cloned().collect(),
+            )
+        }
+
+        let (releases_added, releases_removed) = diff(&releases(old), &releases(new));
+        let (edges_added, edges_removed) = diff(&edges(old), &edges(new));
+
+        let (old_conditional, new_conditional) = (conditional_edges(old), conditional_edges(new));
+        let (conditional_edges_added, conditional_edges_removed) = diff(
+            &old_conditional.keys().cloned().collect(),
+            &new_conditional.keys().cloned().collect(),
+        );
+        let risks_changed = new_conditional
+            .into_iter()
+            .filter_map(|(edge, risks_after)| {
+                let risks_before = old_conditional.get(&edge)?;
+                (*risks_before != risks_after).then(|| RiskChange {
+                    from: edge.from,
+                    to: edge.to,
+                    risks_before: risks_before.clone(),
+                    risks_after,
+                })
+            })
+            .collect();
+
+        Self {
+            releases_added,
+            releases_removed,
+            edges_added,
+            edges_removed,
+            conditional_edges_added,
+            conditional_edges_removed,
+            risks_changed,
+        }
+    }
+
+    /// Returns whether the graphs are the same.
+    pub fn is_empty(&self) -> bool {
+        self == &Self::default()
+    }
+}
+
+/// One change per line, prefixed with `+` if added, `-` if removed or `~` if changed.
+impl fmt::Display for GraphDiff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for version in &self.releases_added {
+            writeln!(f, "+ node {}", version)?;
+        }
+        for version in &self.releases_removed {
+            writeln!(f, "- node {}", version)?;
+        }
+        for edge in &self.edges_added {
+            writeln!(f, "+ edge {}", edge)?;
+        }
+        for edge in &self.edges_removed {
+            writeln!(f, "- edge {}", edge)?;
+        }
+        for edge in &self.conditional_edges_added {
+            writeln!(f, "+ conditional edge {}", edge)?;
+        }
+        for edge in &self.conditional_edges_removed {
+            writeln!(f, "- conditional edge {}", edge)?;
+        }
+        for change in &self.risks_changed {
+            writeln!(
+                f,
+                "~ risks on {} -> {}: [{}] -> [{}]",
+                change.from,
+                change.to,
+                change.risks_before.join(", "),
+                change.risks_after.join(", ")
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{generate_custom_graph, generate_graph};
+
+    fn edge(from: &str, to: &str) -> Edge {
+        Edge {
+            from: from.to_string(),
+            to: to.to_string(),
+        }
+    }
+
+    #[test]
+    fn diff_graphs() {
+        let releases = |count: usize| (0..count).map(|i| (i, Default::default())).collect();
+        let old = generate_custom_graph("image", releases(3), Some(vec![(0, 1), (1, 2)]));
+        let new = generate_custom_graph("image", releases(4), Some(vec![(0, 1), (0, 3)]));
+
+        let diff = GraphDiff::between(&old, &new);
+        assert_eq!(
+            diff,
+            GraphDiff {
+                releases_added: vec!["3.0.0".to_string()],
+                edges_added: vec![edge("0.0.0", "3.0.0")],
+                edges_removed: vec![edge("1.0.0", "2.0.0")],
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            diff.to_string(),
+            "+ node 3.0.0\n+ edge 0.0.0 -> 3.0.0\n- edge 1.0.0 -> 2.0.0\n"
+        );
+        assert!(GraphDiff::between(&new, &new).is_empty());
+    }
+
+    #[test]
+    fn diff_conditional_edges() {
+        let promql = generate_graph(true, false);
+        let always = generate_graph(true, true);
+
+        assert!(GraphDiff::between(&promql, &promql).is_empty());
+        assert_eq!(
+            GraphDiff::between(&generate_graph(false, false), &promql).conditional_edges_added,
+            vec![edge("1.0.0", "2.0.0")]
+        );
+
+        let diff = GraphDiff::between(&promql, &always);
+        assert_eq!(
+            diff.risks_changed,
+            vec![RiskChange {
+                from: "1.0.0".to_string(),
+                to: "2.0.0".to_string(),
+                risks_before: vec!["BrokenUpdates".to_string()],
+                risks_after: vec!["AllBrokenUpdates".to_string()],
+            }]
+        );
+        assert_eq!(
+            diff.to_string(),
+            "~ risks on 1.0.0 -> 2.0.0: [BrokenUpdates] -> [AllBrokenUpdates]\n"
+        );
+    }
+}
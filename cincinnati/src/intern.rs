@@ -0,0 +1,227 @@
+//! Interned strings of the graph model.
+//!
+//! Release versions and metadata keys and values repeat across thousands of
+//! releases, e.g. the channels or architecture keys, and across the copies of
+//! a graph made along the plugin pipeline. They are stored as reference-counted
+//! strings taken from a process-wide pool, so that equal strings share a single
+//! allocation and copying them is cheap.
+
+use serde::de::{self, Deserialize, Deserializer, Visitor};
+use serde::ser::{Serialize, Serializer};
+use std::borrow::Borrow;
+use std::collections::HashSet;
+use std::fmt;
+use std::ops::Deref;
+use std::sync::{Arc, Mutex};
+
+/// Minimum number of pooled strings before unused ones are pruned.
+static MIN_PRUNE_SIZE: usize = 64 * 1024;
+
+struct Pool {
+    strings: HashSet<Arc<str>>,
+    prune_at: usize,
+}
+
+lazy_static::lazy_static! {
+    static ref POOL: Mutex<Pool> = Mutex::new(Pool {
+        strings: HashSet::new(),
+        prune_at: MIN_PRUNE_SIZE,
+    });
+}
+
+/// Returns the pooled string equal to `s`, adding it to the pool if needed.
+///
+/// Strings only referenced by the pool are dropped once it doubled in size
+/// since the last pruning.
+pub fn intern(s: &str) -> IStr {
+    let mut pool = POOL.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    if let Some(interned) = pool.strings.get(s) {
+        return IStr(interned.clone());
+    }
+
+    if pool.strings.len() >= pool.prune_at {
+        pool.strings
+            .retain(|interned| Arc::strong_count(interned) > 1);
+        pool.prune_at = MIN_PRUNE_SIZE.max(2 * pool.strings.len());
+    }
+
+    let interned: Arc<str> = Arc::from(s);
+    pool.strings.insert(interned.clone());
+    IStr(interned)
+}
+
+/// An interned, immutable string.
+///
+/// It dereferences to `str` and compares, hashes and orders like one, so that
+/// maps keyed by `IStr` can be looked up with a `&str`.
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct IStr(Arc<str>);
+
+impl IStr {
+    /// Returns the string slice.
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Default for IStr {
+    fn default() -> Self {
+        intern("")
+    }
+}
+
+impl Deref for IStr {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl AsRef<str> for IStr {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Borrow<str> for IStr {
+    fn borrow(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Debug for IStr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&*self.0, f)
+    }
+}
+
+impl fmt::Display for IStr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&*self.0, f)
+    }
+}
+
+impl From<&str> for IStr {
+    fn from(s: &str) -> Self {
+        intern(s)
+    }
+}
+
+impl From<&String> for IStr {
+    fn from(s: &String) -> Self {
+        intern(s)
+    }
+}
+
+impl From<String> for IStr {
+    fn from(s: String) -> Self {
+        intern(&s)
+    }
+}
+
+impl From<IStr> for String {
+    fn from(s: IStr) -> Self {
+        s.0.to_string()
+    }
+}
+
+impl PartialEq<str> for IStr {
+    fn eq(&self, other: &str) -> bool {
+        &*self.0 == other
+    }
+}
+
+impl PartialEq<&str> for IStr {
+    fn eq(&self, other: &&str) -> bool {
+        &*self.0 == *other
+    }
+}
+
+impl PartialEq<String> for IStr {
+    fn eq(&self, other: &String) -> bool {
+        &*self.0 == other.as_str()
+    }
+}
+
+impl PartialEq<IStr> for str {
+    fn eq(&self, other: &IStr) -> bool {
+        self == &*other.0
+    }
+}
+
+impl PartialEq<IStr> for &str {
+    fn eq(&self, other: &IStr) -> bool {
+        *self == &*other.0
+    }
+}
+
+impl PartialEq<IStr> for String {
+    fn eq(&self, other: &IStr) -> bool {
+        self.as_str() == &*other.0
+    }
+}
+
+impl Serialize for IStr {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&self.0)
+    }
+}
+
+impl<'de> Deserialize<'de> for IStr {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct IStrVisitor;
+
+        impl<'de> Visitor<'de> for IStrVisitor {
+            type Value = IStr;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("a string")
+            }
+
+            fn visit_str<E>(self, value: &str) -> Result<IStr, E>
+            where
+                E: de::Error,
+            {
+                Ok(intern(value))
+            }
+        }
+
+        deserializer.deserialize_str(IStrVisitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn equal_strings_are_shared() {
+        let a = intern("io.openshift.upgrades.graph.release.channels");
+        let b: IStr = "io.openshift.upgrades.graph.release.channels"
+            .to_string()
+            .into();
+        assert!(Arc::ptr_eq(&a.0, &b.0));
+        assert_eq!(a, "io.openshift.upgrades.graph.release.channels");
+        assert_ne!(a, intern("io.openshift.upgrades.graph.release.arch"));
+
+        let map: std::collections::BTreeMap<IStr, IStr> = vec![(a.clone(), intern("stable-4.14"))]
+            .into_iter()
+            .collect();
+        assert_eq!(
+            map.get("io.openshift.upgrades.graph.release.channels")
+                .map(IStr::as_str),
+            Some("stable-4.14")
+        );
+
+        let json = serde_json::to_string(&map).unwrap();
+        let parsed: std::collections::BTreeMap<IStr, IStr> = serde_json::from_str(&json).unwrap();
+        assert!(Arc::ptr_eq(&parsed.keys().next().unwrap().0, &a.0));
+    }
+}
@@ -17,16 +17,29 @@ extern crate serde_derive;
 
 #[macro_use]
 pub mod plugins;
+pub mod artifact;
+pub mod change_thresholds;
+pub mod channel_lifecycle;
 mod conditional_edges;
-
+pub mod diff;
+pub mod intern;
+pub mod metadata_keys;
+pub mod provenance;
+pub mod semver_cache;
+pub mod v2;
+
+use crate::channel_lifecycle::Lifecycle;
 use crate::conditional_edges::*;
 use commons::prelude_errors::*;
 use daggy::petgraph::visit::{IntoNodeReferences, NodeRef};
 use daggy::{Dag, EdgeIndex, Walker};
 use serde::de::{self, Deserialize, Deserializer, MapAccess, Visitor};
 use serde::ser::{Serialize, SerializeStruct, Serializer};
+use std::sync::Arc;
 use std::{collections, fmt};
 
+pub use crate::conditional_edges::ConditionalUpdateRisk;
+pub use crate::intern::IStr;
 pub use daggy::{self, WouldCycle};
 
 pub const CONTENT_TYPE: &str = "application/json";
@@ -45,10 +58,18 @@ pub use std::collections::BTreeMap as MapImpl;
 pub use std::collections::BTreeSet as SetImpl;
 
 /// Graph type which stores `Release` as node-weights and `Empty` as edge-weights.
+///
+/// Releases are reference-counted, so that copies of a graph, e.g. along the
+/// plugin pipeline, share the version, payload and metadata strings of their
+/// releases instead of duplicating them. A release is only copied when it is
+/// mutated in a graph which shares it. Versions and metadata keys and values
+/// are interned, so that strings repeated across releases are shared too.
 #[derive(Debug, Clone)]
 pub struct Graph {
-    dag: Dag<Release, Empty>,
+    dag: Dag<Arc<Release>, Empty>,
     conditional_edges: Option<Vec<ConditionalEdge>>,
+    /// Lifecycle of the channels being retired, by channel name.
+    channel_lifecycles: collections::BTreeMap<String, Lifecycle>,
 }
 
 /// Wrapper enum for the concrete and abstract release types.
@@ -69,7 +90,7 @@ impl Release {
     }
 
     /// Get a mutable borrow of the release metadata if any
-    pub fn get_metadata_mut(&mut self) -> Option<&mut MapImpl<String, String>> {
+    pub fn get_metadata_mut(&mut self) -> Option<&mut MapImpl<IStr, IStr>> {
         match self {
             Release::Abstract(_) => None,
             Release::Concrete(release) => Some(&mut release.metadata),
@@ -77,10 +98,10 @@ impl Release {
     }
 
     /// Returns the `manifestref` of a given `Release`
-    pub fn manifestref(&self) -> Result<&String, Error> {
-        let digestkey = String::from("io.openshift.upgrades.graph.release.manifestref");
+    pub fn manifestref(&self) -> Result<&str, Error> {
+        let digestkey = "io.openshift.upgrades.graph.release.manifestref";
         match self {
-            Release::Concrete(release) => match release.metadata.get(&digestkey) {
+            Release::Concrete(release) => match release.metadata.get(digestkey) {
                 Some(d) => Ok(d),
                 None => bail!("could not get manifest reference"),
             },
@@ -89,13 +110,13 @@ impl Release {
     }
 
     /// return the arch identifier set in metadata or returns `none`
-    pub fn metadata_arch_id(&mut self) -> String {
+    pub fn metadata_arch_id(&self) -> String {
         let metadata_arch_key = "release.openshift.io/architecture";
         let no_arch = String::from("none");
-        let arch = self
-            .get_metadata_mut()
-            .map(|metadata| metadata.get(metadata_arch_key))
-            .unwrap_or(None);
+        let arch = match self {
+            Release::Concrete(release) => release.metadata.get(metadata_arch_key),
+            Release::Abstract(_) => None,
+        };
         match arch {
             Some(arch) => arch.to_string(),
             _ => no_arch,
@@ -106,9 +127,9 @@ impl Release {
 /// Type to represent a Release with all its information.
 #[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone)]
 pub struct ConcreteRelease {
-    pub version: String,
+    pub version: IStr,
     pub payload: String,
-    pub metadata: MapImpl<String, String>,
+    pub metadata: MapImpl<IStr, IStr>,
 }
 
 /// Abtract release only storing a version.
@@ -118,19 +139,28 @@ pub struct ConcreteRelease {
 /// the graph is completed.
 #[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone)]
 pub struct AbstractRelease {
-    pub version: String,
+    pub version: IStr,
 }
 
 /// Abstraction over a node in the graph representing a `Release`
 #[derive(Debug, PartialEq, Eq, Hash, Clone)]
 pub struct ReleaseId(daggy::NodeIndex);
 
+/// An edge along an update path.
+#[derive(Debug, Serialize, PartialEq, Eq)]
+pub struct PathStep<'a> {
+    pub from: &'a str,
+    pub to: &'a str,
+    /// Risks of the edge, empty unless the edge is conditional.
+    pub risks: Vec<&'a ConditionalUpdateRisk>,
+}
+
 /// Can be used to iterate over all direct children of the given release.
 ///
 /// See the `next_releases` method for more information.
 pub struct NextReleases<'a> {
-    children: daggy::Children<Release, Empty, daggy::petgraph::graph::DefaultIx>,
-    dag: &'a Dag<Release, Empty>,
+    children: daggy::Children<Arc<Release>, Empty, daggy::petgraph::graph::DefaultIx>,
+    dag: &'a Dag<Arc<Release>, Empty>,
 }
 
 impl<'a> Iterator for NextReleases<'a> {
@@ -143,7 +173,10 @@ impl<'a> Iterator for NextReleases<'a> {
                 (
                     edge_index,
                     node_index,
-                    self.dag.node_weight(node_index).expect(EXPECT_NODE_WEIGHT),
+                    self.dag
+                        .node_weight(node_index)
+                        .expect(EXPECT_NODE_WEIGHT)
+                        .as_ref(),
                 )
             })
     }
@@ -153,8 +186,8 @@ impl<'a> Iterator for NextReleases<'a> {
 ///
 /// See the `previous_releases` method for more information.
 pub struct PreviousReleases<'a> {
-    parents: daggy::Parents<Release, Empty, daggy::petgraph::graph::DefaultIx>,
-    dag: &'a Dag<Release, Empty>,
+    parents: daggy::Parents<Arc<Release>, Empty, daggy::petgraph::graph::DefaultIx>,
+    dag: &'a Dag<Arc<Release>, Empty>,
 }
 
 impl<'a> Iterator for PreviousReleases<'a> {
@@ -167,7 +200,10 @@ impl<'a> Iterator for PreviousReleases<'a> {
                 (
                     edge_index,
                     node_index,
-                    self.dag.node_weight(node_index).expect(EXPECT_NODE_WEIGHT),
+                    self.dag
+                        .node_weight(node_index)
+                        .expect(EXPECT_NODE_WEIGHT)
+                        .as_ref(),
                 )
             })
     }
@@ -203,11 +239,30 @@ pub mod errors {
     pub struct NodeWeightMissing(pub(crate) usize);
 }
 
+/// Run `f` on a release, which is only copied if it is shared with another
+/// graph and `f` changed it.
+fn mutate_release<T, F>(release: &mut Arc<Release>, f: F) -> T
+where
+    F: FnOnce(&mut Release) -> T,
+{
+    if let Some(release) = Arc::get_mut(release) {
+        return f(release);
+    }
+
+    let mut copy = Release::clone(release);
+    let result = f(&mut copy);
+    if copy != **release {
+        *release = Arc::new(copy);
+    }
+    result
+}
+
 impl Default for Graph {
     fn default() -> Self {
         Graph {
             dag: Default::default(),
             conditional_edges: Some(vec![]),
+            channel_lifecycles: Default::default(),
         }
     }
 }
@@ -220,23 +275,23 @@ impl Graph {
     where
         R: Into<Release>,
     {
-        let missing_manifest_ref = String::from("none");
-        let mut release = release.into();
+        let missing_manifest_ref = "none";
+        let release = release.into();
         match self.find_by_version(release.version()) {
             Some(id) => {
                 let node = self.dag.node_weight_mut(id.0).expect(EXPECT_NODE_WEIGHT);
-                if let Release::Concrete(_) = node {
+                if let Release::Concrete(_) = node.as_ref() {
                     // check if release digest and node digest are same
-                    if release.manifestref().unwrap_or(&missing_manifest_ref)
-                        != node.manifestref().unwrap_or(&missing_manifest_ref)
+                    if release.manifestref().unwrap_or(missing_manifest_ref)
+                        != node.manifestref().unwrap_or(missing_manifest_ref)
                     {
                         let release_arch = release.metadata_arch_id();
                         if release_arch == node.metadata_arch_id() {
                             bail!(
                                 "mismatched manifest ref for concrete release {}: {}, {}",
                                 release.version(),
-                                release.manifestref().unwrap_or(&missing_manifest_ref),
-                                node.manifestref().unwrap_or(&missing_manifest_ref)
+                                release.manifestref().unwrap_or(missing_manifest_ref),
+                                node.manifestref().unwrap_or(missing_manifest_ref)
                             )
                         }
                         if release_arch == "multi" {
@@ -244,10 +299,10 @@ impl Graph {
                         }
                     }
                 }
-                *node = release;
+                *node = Arc::new(release);
                 Ok(id)
             }
-            None => Ok(ReleaseId(self.dag.add_node(release))),
+            None => Ok(ReleaseId(self.dag.add_node(Arc::new(release)))),
         }
     }
 
@@ -290,7 +345,7 @@ impl Graph {
         self.dag
             .node_references()
             .filter(|nr| {
-                let v: &str = &nr.weight().version().to_string();
+                let v: &str = nr.weight().version();
                 if v == version {
                     true
                 } else {
@@ -306,6 +361,7 @@ impl Graph {
     pub fn find_by_releaseid(&self, id: &ReleaseId) -> Fallible<&Release> {
         self.dag
             .node_weight(id.0)
+            .map(AsRef::as_ref)
             .ok_or_else(move || format_err!("could not find Release with id: {:?}", id))
     }
 
@@ -355,10 +411,24 @@ impl Graph {
             .try_for_each(|ei| self.remove_edge_by_index(*ei))
     }
 
+    /// Returns tuples of ReleaseId and its version String for releases for which
+    /// filter_fn returns true.
+    pub fn find_by_fn<F>(&self, mut filter_fn: F) -> Vec<(ReleaseId, String)>
+    where
+        F: FnMut(&Release) -> bool,
+    {
+        self.dag
+            .node_references()
+            .filter(|nr| filter_fn(nr.weight()))
+            .map(|nr| (ReleaseId(nr.id()), nr.weight().version().to_string()))
+            .collect()
+    }
+
     /// Returns tuples of ReleaseId and its version String for releases for which
     /// filter_fn returns true.
     ///
     /// filter_fn is able to mutate the release as it receives a mutable borrow.
+    /// Releases shared with other graphs are only copied if they are mutated.
     pub fn find_by_fn_mut<F>(&mut self, mut filter_fn: F) -> Vec<(ReleaseId, String)>
     where
         F: FnMut(&mut Release) -> bool,
@@ -367,7 +437,7 @@ impl Graph {
             .node_weights_mut()
             .enumerate()
             .filter_map(|(i, nw)| {
-                if filter_fn(nw) {
+                if mutate_release(nw, &mut filter_fn) {
                     Some((
                         ReleaseId(daggy::NodeIndex::from(i as u32)),
                         nw.version().to_string(),
@@ -385,7 +455,7 @@ impl Graph {
         self.dag
             .node_references()
             .filter(|nr| {
-                if let Release::Concrete(release) = nr.weight() {
+                if let Release::Concrete(release) = nr.weight().as_ref() {
                     if let Some(found_value) = release.metadata.get(key) {
                         return found_value == value;
                     }
@@ -402,12 +472,12 @@ impl Graph {
         self.dag
             .node_references()
             .filter_map(|nr| {
-                if let Release::Concrete(release) = nr.weight() {
+                if let Release::Concrete(release) = nr.weight().as_ref() {
                     if let Some(value) = release.metadata.get(key) {
                         return Some((
                             ReleaseId(nr.id()),
-                            release.version.to_owned(),
-                            value.to_owned(),
+                            release.version.to_string(),
+                            value.to_string(),
                         ));
                     }
                 }
@@ -420,8 +490,8 @@ impl Graph {
     pub fn get_metadata_as_ref_mut(
         &mut self,
         release_id: &ReleaseId,
-    ) -> Result<&mut MapImpl<String, String>, Error> {
-        match self.dag.node_weight_mut(release_id.0) {
+    ) -> Result<&mut MapImpl<IStr, IStr>, Error> {
+        match self.dag.node_weight_mut(release_id.0).map(Arc::make_mut) {
             Some(Release::Concrete(release)) => Ok(&mut release.metadata),
             _ => bail!("could not get metadata reference"),
         }
@@ -430,7 +500,7 @@ impl Graph {
     /// Returns `NextReleases` for the given release.
     ///
     /// `NextReleases` can be used to iterate over all direct children of the given release.
-    pub fn next_releases(&self, source: &ReleaseId) -> NextReleases {
+    pub fn next_releases(&self, source: &ReleaseId) -> NextReleases<'_> {
         NextReleases {
             children: self.dag.children(source.0),
             dag: &self.dag,
@@ -440,7 +510,7 @@ impl Graph {
     /// Returns `PreviousReleases` for the given release.
     ///
     /// `PreviousReleases` can be used to iterate over all direct parents of the given release.
-    pub fn previous_releases(&self, source: &ReleaseId) -> PreviousReleases {
+    pub fn previous_releases(&self, source: &ReleaseId) -> PreviousReleases<'_> {
         PreviousReleases {
             parents: self.dag.parents(source.0),
             dag: &self.dag,
@@ -452,6 +522,11 @@ impl Graph {
         self.dag.node_count() as u64
     }
 
+    /// Return the number of edges in the graph.
+    pub fn edges_count(&self) -> u64 {
+        self.dag.edge_count() as u64
+    }
+
     /// Removes the nodes with the given ReleaseIds and returns the number of
     /// removed releases.
     ///
@@ -459,7 +534,7 @@ impl Graph {
     /// This is required because `daggy::Dag::remove_node()` shifts the NodeIndices
     /// and therefore invalidates all the ones which are higher than the removed one.
     pub fn remove_releases(&mut self, mut to_remove: Vec<ReleaseId>) -> usize {
-        to_remove.sort_by(|a, b| a.0.cmp(&b.0));
+        to_remove.sort_by_key(|a| a.0);
 
         self.remove_nodes(to_remove.into_iter().map(|ri| ri.0).collect())
     }
@@ -482,7 +557,7 @@ impl Graph {
             .dag
             .node_references()
             .filter_map(|nr| {
-                if let Release::Abstract(_) = nr.weight() {
+                if let Release::Abstract(_) = nr.weight().as_ref() {
                     Some(nr.0)
                 } else {
                     None
@@ -496,14 +571,310 @@ impl Graph {
             .count()
     }
 
+    /// Iterates over all releases
+    pub fn iter_releases(&self) -> impl Iterator<Item = &Release> {
+        self.dag.raw_nodes().iter().map(|node| node.weight.as_ref())
+    }
+
+    /// Returns a view of the graph which serializes only the given fields.
+    ///
+    /// Like the graph itself, it is serialized without intermediate copies.
+    pub fn project(&self, fields: GraphFields) -> ProjectedGraph<'_> {
+        ProjectedGraph {
+            graph: self,
+            fields,
+        }
+    }
+
+    /// Returns the lifecycle of `channel`, if it is being retired.
+    pub fn channel_lifecycle(&self, channel: &str) -> Option<&Lifecycle> {
+        self.channel_lifecycles.get(channel)
+    }
+
+    /// Records the lifecycle of a channel being retired.
+    pub fn set_channel_lifecycle(&mut self, channel: &str, lifecycle: Lifecycle) {
+        self.channel_lifecycles
+            .insert(channel.to_string(), lifecycle);
+    }
+
+    /// Returns the conditional edges as `(from, to)` versions, with the names
+    /// of the risks which apply to them.
+    pub fn conditional_edges(&self) -> Vec<(&str, &str, Vec<&str>)> {
+        self.conditional_edges
+            .iter()
+            .flatten()
+            .flat_map(|conditional_edge| {
+                let risks: Vec<&str> = conditional_edge
+                    .risks
+                    .iter()
+                    .map(|risk| risk.name.as_str())
+                    .collect();
+                conditional_edge
+                    .edges
+                    .iter()
+                    .map(move |edge| (edge.from.as_str(), edge.to.as_str(), risks.clone()))
+            })
+            .collect()
+    }
+
+    /// Adds the edges of a conditional edge as regular, recommended edges,
+    /// once none of its risks apply.
+    pub(crate) fn recommend_conditional_edge(&mut self, conditional_edge: &ConditionalEdge) {
+        for edge in &conditional_edge.edges {
+            match (
+                self.find_by_version(&edge.from),
+                self.find_by_version(&edge.to),
+            ) {
+                (Some(from), Some(to)) => {
+                    if let Err(e) = self.add_edge(&from, &to) {
+                        log::debug!("not adding edge {} -> {}: {}", edge.from, edge.to, e);
+                    }
+                }
+                _ => log::warn!(
+                    "couldn't find releases of conditional edge {} -> {} in graph",
+                    edge.from,
+                    edge.to
+                ),
+            }
+        }
+    }
+
+    /// Returns the edges along the shortest update path between two versions.
+    ///
+    /// Conditional edges are followed as well, with their risks attached to
+    /// the step; among paths of the same length, unconditional edges are
+    /// preferred. Returns `None` if either version is unknown or if `to` can't
+    /// be reached from `from`.
+    pub fn shortest_path(&self, from: &str, to: &str) -> Option<Vec<PathStep<'_>>> {
+        let from = self.find_by_version(from)?.0;
+        let to = self.find_by_version(to)?.0;
+
+        let mut conditional: collections::HashMap<&str, Vec<(&str, &[ConditionalUpdateRisk])>> =
+            collections::HashMap::new();
+        for conditional_edge in self.conditional_edges.iter().flatten() {
+            for edge in &conditional_edge.edges {
+                conditional
+                    .entry(edge.from.as_str())
+                    .or_default()
+                    .push((edge.to.as_str(), conditional_edge.risks.as_slice()));
+            }
+        }
+        let by_version: collections::HashMap<&str, daggy::NodeIndex> = if conditional.is_empty() {
+            Default::default()
+        } else {
+            self.dag
+                .node_references()
+                .map(|(id, release)| (release.version(), id))
+                .collect()
+        };
+
+        // Breadth-first search, remembering the predecessor of each visited
+        // release along with the risks of the edge leading to it.
+        let mut predecessors: collections::HashMap<
+            daggy::NodeIndex,
+            Option<(daggy::NodeIndex, &[ConditionalUpdateRisk])>,
+        > = collections::HashMap::new();
+        predecessors.insert(from, None);
+        let mut queue = collections::VecDeque::new();
+        queue.push_back(from);
+        while let Some(current) = queue.pop_front() {
+            if current == to {
+                break;
+            }
+
+            let version = self
+                .dag
+                .node_weight(current)
+                .expect(EXPECT_NODE_WEIGHT)
+                .version();
+            let unconditional = self
+                .dag
+                .children(current)
+                .iter(&self.dag)
+                .map(|(_, next)| (next, &[][..]));
+            let conditional_next = conditional
+                .get(version)
+                .into_iter()
+                .flatten()
+                .filter_map(|(next, risks)| by_version.get(next).map(|id| (*id, *risks)));
+
+            for (next, risks) in unconditional.chain(conditional_next) {
+                if let std::collections::hash_map::Entry::Vacant(e) = predecessors.entry(next) {
+                    e.insert(Some((current, risks)));
+                    queue.push_back(next);
+                }
+            }
+        }
+
+        if !predecessors.contains_key(&to) {
+            return None;
+        }
+
+        let version = |node| {
+            self.dag
+                .node_weight(node)
+                .expect(EXPECT_NODE_WEIGHT)
+                .version()
+        };
+        let mut steps = vec![];
+        let mut current = to;
+        while let Some(Some((previous, risks))) = predecessors.get(&current) {
+            steps.push(PathStep {
+                from: version(*previous),
+                to: version(current),
+                risks: risks.iter().collect(),
+            });
+            current = *previous;
+        }
+        steps.reverse();
+
+        Some(steps)
+    }
+
+    /// Returns the subgraph of the releases which can be reached from the
+    /// given version, following conditional edges as well.
+    ///
+    /// The release itself is included, and so are the conditional edges
+    /// between the included releases. The releases are shared with this graph
+    /// rather than copied. Returns `None` if the version is unknown.
+    pub fn reachable_from(&self, version: &str) -> Option<Graph> {
+        let start = self.find_by_version(version)?.0;
+
+        let mut conditional: collections::HashMap<&str, Vec<&str>> = collections::HashMap::new();
+        for edge in self
+            .conditional_edges
+            .iter()
+            .flatten()
+            .flat_map(|conditional_edge| conditional_edge.edges.iter())
+        {
+            conditional
+                .entry(edge.from.as_str())
+                .or_default()
+                .push(edge.to.as_str());
+        }
+        let by_version: collections::HashMap<&str, daggy::NodeIndex> = if conditional.is_empty() {
+            Default::default()
+        } else {
+            self.dag
+                .node_references()
+                .map(|(id, release)| (release.version(), id))
+                .collect()
+        };
+
+        let mut reachable = vec![false; self.dag.node_count()];
+        reachable[start.index()] = true;
+        let mut queue = vec![start];
+        while let Some(current) = queue.pop() {
+            let version = self
+                .dag
+                .node_weight(current)
+                .expect(EXPECT_NODE_WEIGHT)
+                .version();
+            let unconditional = self
+                .dag
+                .children(current)
+                .iter(&self.dag)
+                .map(|(_, next)| next);
+            let conditional_next = conditional
+                .get(version)
+                .into_iter()
+                .flatten()
+                .filter_map(|next| by_version.get(next).copied());
+
+            for next in unconditional.chain(conditional_next) {
+                if !reachable[next.index()] {
+                    reachable[next.index()] = true;
+                    queue.push(next);
+                }
+            }
+        }
+
+        let mut subgraph = Graph {
+            channel_lifecycles: self.channel_lifecycles.clone(),
+            ..Graph::default()
+        };
+        let mut included = collections::HashSet::new();
+        let indices: Vec<Option<daggy::NodeIndex>> = self
+            .dag
+            .raw_nodes()
+            .iter()
+            .zip(&reachable)
+            .map(|(node, reachable)| {
+                if !reachable {
+                    return None;
+                }
+                included.insert(node.weight.version());
+                Some(subgraph.dag.add_node(node.weight.clone()))
+            })
+            .collect();
+        subgraph
+            .dag
+            .add_edges(self.dag.raw_edges().iter().filter_map(|edge| {
+                match (
+                    indices[edge.source().index()],
+                    indices[edge.target().index()],
+                ) {
+                    (Some(from), Some(to)) => Some((from, to, Empty {})),
+                    _ => None,
+                }
+            }))
+            .expect("a subgraph of a DAG has no cycles");
+
+        subgraph.conditional_edges = self.conditional_edges.as_ref().map(|conditional_edges| {
+            conditional_edges
+                .iter()
+                .filter_map(|conditional_edge| {
+                    let edges: Vec<ConditionalUpdateEdge> = conditional_edge
+                        .edges
+                        .iter()
+                        .filter(|edge| {
+                            included.contains(edge.from.as_str())
+                                && included.contains(edge.to.as_str())
+                        })
+                        .cloned()
+                        .collect();
+                    if edges.is_empty() {
+                        return None;
+                    }
+                    Some(ConditionalEdge {
+                        edge_regex: conditional_edge.edge_regex.clone(),
+                        edges,
+                        risks: conditional_edge.risks.clone(),
+                    })
+                })
+                .collect()
+        });
+
+        Some(subgraph)
+    }
+
+    /// Iterates over all edges as `(from, to)` releases.
+    pub fn iter_edges(&self) -> impl Iterator<Item = (&Release, &Release)> {
+        self.dag.raw_edges().iter().map(move |edge| {
+            (
+                self.dag
+                    .node_weight(edge.source())
+                    .expect(EXPECT_NODE_WEIGHT)
+                    .as_ref(),
+                self.dag
+                    .node_weight(edge.target())
+                    .expect(EXPECT_NODE_WEIGHT)
+                    .as_ref(),
+            )
+        })
+    }
+
     /// Iterates over all releases mutably
     ///
     /// f is able to mutate the release as it receives a mutable borrow.
-    pub fn iter_releases_mut<F>(&mut self, f: F) -> Result<(), Error>
+    /// Releases shared with other graphs are only copied if they are mutated.
+    pub fn iter_releases_mut<F>(&mut self, mut f: F) -> Result<(), Error>
     where
         F: FnMut(&mut Release) -> Result<(), Error>,
     {
-        self.dag.node_weights_mut().try_for_each(f)
+        self.dag
+            .node_weights_mut()
+            .try_for_each(|nw| mutate_release(nw, &mut f))
     }
 
     /// Get the edges expressed as version -> versions; optionally include edges from/to `Release::Abstract`.
@@ -528,7 +899,7 @@ impl Graph {
 
                         if include_abstract
                             || matches!(
-                                (source, target),
+                                (source.as_ref(), target.as_ref()),
                                 (Release::Concrete(_), Release::Concrete(_))
                             )
                         {
@@ -564,6 +935,8 @@ impl<'a> Deserialize<'a> for Graph {
             Nodes,
             #[serde(rename = "conditionalEdges")]
             ConditionalEdges,
+            #[serde(rename = "channelLifecycles")]
+            ChannelLifecycles,
         }
 
         struct GraphVisitor;
@@ -582,6 +955,7 @@ impl<'a> Deserialize<'a> for Graph {
                 let mut edges: Option<Vec<(daggy::NodeIndex, daggy::NodeIndex)>> = None;
                 let mut nodes: Option<Vec<Release>> = None;
                 let mut conditional_edges: Option<Vec<ConditionalEdge>> = None;
+                let mut channel_lifecycles: Option<collections::BTreeMap<String, Lifecycle>> = None;
                 while let Some(key) = map.next_key()? {
                     match key {
                         Field::Edges => {
@@ -602,6 +976,12 @@ impl<'a> Deserialize<'a> for Graph {
                             }
                             conditional_edges = Some(map.next_value()?);
                         }
+                        Field::ChannelLifecycles => {
+                            if channel_lifecycles.is_some() {
+                                return Err(de::Error::duplicate_field("channelLifecycles"));
+                            }
+                            channel_lifecycles = Some(map.next_value()?);
+                        }
                     }
                 }
                 let edges = edges.ok_or_else(|| de::Error::missing_field("edges"))?;
@@ -610,6 +990,7 @@ impl<'a> Deserialize<'a> for Graph {
                 let mut graph = Graph {
                     dag: Dag::with_capacity(nodes.len(), edges.len()),
                     conditional_edges: Some(Vec::with_capacity(conditional_edges.len())),
+                    channel_lifecycles: channel_lifecycles.unwrap_or_default(),
                 };
                 let mut versions = collections::HashSet::with_capacity(nodes.len());
                 for node in nodes {
@@ -627,7 +1008,7 @@ impl<'a> Deserialize<'a> for Graph {
                             &"a unique string version",
                         ));
                     }
-                    graph.dag.add_node(node);
+                    graph.dag.add_node(Arc::new(node));
                 }
                 graph
                     .dag
@@ -647,43 +1028,179 @@ impl<'a> Deserialize<'a> for Graph {
 
         deserializer.deserialize_struct(
             "Graph",
-            &["nodes", "edges", "conditional_edges"],
+            &["nodes", "edges", "conditional_edges", "channelLifecycles"],
             GraphVisitor,
         )
     }
 }
 
+/// Streams the edges of a graph as `(source, target)` index pairs.
+struct Edges<'a>(&'a [daggy::petgraph::graph::Edge<Empty>]);
+
+impl<'a> Serialize for Edges<'a> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.collect_seq(self.0.iter().map(|edge| (edge.source(), edge.target())))
+    }
+}
+
 impl Serialize for Graph {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
         S: Serializer,
     {
-        struct Edges<'a>(&'a [daggy::petgraph::graph::Edge<Empty>]);
-        struct Nodes<'a>(&'a [daggy::petgraph::graph::Node<Release>]);
+        struct Nodes<'a>(&'a [daggy::petgraph::graph::Node<Arc<Release>>]);
 
-        impl<'a> Serialize for Edges<'a> {
+        impl<'a> Serialize for Nodes<'a> {
             fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
             where
                 S: Serializer,
             {
-                serializer.collect_seq(self.0.iter().map(|edge| (edge.source(), edge.target())))
+                serializer.collect_seq(self.0.iter().map(|node| node.weight.as_ref()))
             }
         }
 
+        // The length is encoded up front by some formats, e.g. CBOR, and must
+        // match the number of fields actually serialized.
+        let len = 2
+            + usize::from(self.conditional_edges.is_some())
+            + usize::from(!self.channel_lifecycles.is_empty());
+        let mut state = serializer.serialize_struct("Graph", len)?;
+        state.serialize_field("nodes", &Nodes(self.dag.raw_nodes()))?;
+        state.serialize_field("edges", &Edges(self.dag.raw_edges()))?;
+        if self.conditional_edges.is_some() {
+            state.serialize_field("conditionalEdges", &self.conditional_edges)?;
+        }
+        if !self.channel_lifecycles.is_empty() {
+            state.serialize_field("channelLifecycles", &self.channel_lifecycles)?;
+        }
+        state.end()
+    }
+}
+
+/// Selection of the graph fields to serialize, see `Graph::project`.
+///
+/// The nodes are always serialized, as the edges refer to them by index.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct GraphFields {
+    pub version: bool,
+    pub payload: bool,
+    pub metadata: bool,
+    pub edges: bool,
+    pub conditional_edges: bool,
+}
+
+impl GraphFields {
+    /// Names of the fields which can be selected.
+    pub const NAMES: &'static [&'static str] = &[
+        "version",
+        "payload",
+        "metadata",
+        "edges",
+        "conditionalEdges",
+    ];
+
+    /// Select all fields.
+    pub fn all() -> Self {
+        Self {
+            version: true,
+            payload: true,
+            metadata: true,
+            edges: true,
+            conditional_edges: true,
+        }
+    }
+}
+
+/// Parse a comma-separated list of field names.
+impl std::str::FromStr for GraphFields {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Fallible<Self> {
+        let mut fields = Self {
+            version: false,
+            payload: false,
+            metadata: false,
+            edges: false,
+            conditional_edges: false,
+        };
+
+        for name in s.split(',').map(str::trim).filter(|name| !name.is_empty()) {
+            match name {
+                "version" => fields.version = true,
+                "payload" => fields.payload = true,
+                "metadata" => fields.metadata = true,
+                "edges" => fields.edges = true,
+                "conditionalEdges" => fields.conditional_edges = true,
+                _ => bail!(
+                    "unknown field '{}', expected any of: {}",
+                    name,
+                    Self::NAMES.join(", ")
+                ),
+            }
+        }
+
+        Ok(fields)
+    }
+}
+
+/// A graph which serializes only the selected fields.
+pub struct ProjectedGraph<'a> {
+    graph: &'a Graph,
+    fields: GraphFields,
+}
+
+impl<'a> Serialize for ProjectedGraph<'a> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        struct Nodes<'a>(
+            &'a [daggy::petgraph::graph::Node<Arc<Release>>],
+            GraphFields,
+        );
+        struct Node<'a>(&'a Release, GraphFields);
+
         impl<'a> Serialize for Nodes<'a> {
             fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
             where
                 S: Serializer,
             {
-                serializer.collect_seq(self.0.iter().map(|node| &node.weight))
+                serializer.collect_seq(self.0.iter().map(|node| Node(node.weight.as_ref(), self.1)))
             }
         }
 
-        let mut state = serializer.serialize_struct("Graph", 2)?;
-        state.serialize_field("nodes", &Nodes(self.dag.raw_nodes()))?;
-        state.serialize_field("edges", &Edges(self.dag.raw_edges()))?;
-        if self.conditional_edges.is_some() {
-            state.serialize_field("conditionalEdges", &self.conditional_edges)?;
+        impl<'a> Serialize for Node<'a> {
+            fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+            where
+                S: Serializer,
+            {
+                let fields = self.1;
+                let mut state = serializer.serialize_struct("Release", 3)?;
+                if fields.version {
+                    state.serialize_field("version", self.0.version())?;
+                }
+                if let Release::Concrete(release) = self.0 {
+                    if fields.payload {
+                        state.serialize_field("payload", &release.payload)?;
+                    }
+                    if fields.metadata {
+                        state.serialize_field("metadata", &release.metadata)?;
+                    }
+                }
+                state.end()
+            }
+        }
+
+        let mut state = serializer.serialize_struct("Graph", 3)?;
+        state.serialize_field("nodes", &Nodes(self.graph.dag.raw_nodes(), self.fields))?;
+        if self.fields.edges {
+            state.serialize_field("edges", &Edges(self.graph.dag.raw_edges()))?;
+        }
+        if self.fields.conditional_edges && self.graph.conditional_edges.is_some() {
+            state.serialize_field("conditionalEdges", &self.graph.conditional_edges)?;
         }
         state.end()
     }
@@ -695,14 +1212,14 @@ impl PartialEq for Graph {
         let mut releases = self
             .dag
             .node_references()
-            .map(|node_ref| node_ref.1)
+            .map(|node_ref| node_ref.1.as_ref())
             .collect::<Vec<&Release>>();
         releases.sort();
 
         let mut releases_other = other
             .dag
             .node_references()
-            .map(|node_ref| node_ref.1)
+            .map(|node_ref| node_ref.1.as_ref())
             .collect::<Vec<&Release>>();
         releases_other.sort();
 
@@ -726,7 +1243,7 @@ impl PartialEq for Graph {
             return false;
         }
 
-        true
+        self.channel_lifecycles == other.channel_lifecycles
     }
 }
 
@@ -741,11 +1258,15 @@ impl From<plugins::interface::Graph> for Graph {
         for node in graph.take_nodes().into_iter() {
             graph_converted
                 .dag
-                .add_node(Release::Concrete(ConcreteRelease {
-                    version: node.version,
+                .add_node(Arc::new(Release::Concrete(ConcreteRelease {
+                    version: node.version.into(),
                     payload: node.payload,
-                    metadata: node.metadata.into_iter().collect(),
-                }));
+                    metadata: node
+                        .metadata
+                        .into_iter()
+                        .map(|(key, value)| (key.into(), value.into()))
+                        .collect(),
+                })));
         }
 
         // Convert edges
@@ -781,12 +1302,17 @@ impl From<Graph> for plugins::interface::Graph {
 
             // Convert and push node
             let mut node_converted = plugins::interface::Graph_Node::new();
-            match release {
+            match release.as_ref() {
                 Concrete(concrete_release) => {
                     // TODO(steveeJ): avoid cloning all release content
-                    node_converted.set_version(concrete_release.version.clone());
-                    node_converted
-                        .set_metadata(concrete_release.metadata.clone().into_iter().collect());
+                    node_converted.set_version(concrete_release.version.to_string());
+                    node_converted.set_metadata(
+                        concrete_release
+                            .metadata
+                            .iter()
+                            .map(|(key, value)| (key.to_string(), value.to_string()))
+                            .collect(),
+                    );
                     node_converted.set_payload(concrete_release.payload.clone());
                 }
                 Abstract(_) => panic!("found Abstract release type"),
@@ -841,7 +1367,9 @@ pub mod testing {
                             promql: "cluster_infrastructure_provider{type=\"CloudProvider\"}"
                                 .to_string(),
                         },
+                        ..Default::default()
                     }],
+                    fleet_evaluation: None,
                 }],
             };
             if include_always_condition {
@@ -851,8 +1379,9 @@ pub mod testing {
                     message: "All Updates are broken".to_string(),
                     matching_rules: vec![ClusterCondition {
                         condition_type: "Always".to_string(),
-                        promql: Default::default(),
+                        ..Default::default()
                     }],
+                    fleet_evaluation: None,
                 }]
             }
             graph.conditional_edges = Some(vec![ce]);
@@ -861,21 +1390,27 @@ pub mod testing {
             graph.conditional_edges = None;
         }
 
-        let v1 = graph.dag.add_node(Release::Concrete(ConcreteRelease {
-            version: String::from("1.0.0"),
-            payload: String::from("image/1.0.0"),
-            metadata: MapImpl::new(),
-        }));
-        let v2 = graph.dag.add_node(Release::Concrete(ConcreteRelease {
-            version: String::from("2.0.0"),
-            payload: String::from("image/2.0.0"),
-            metadata: MapImpl::new(),
-        }));
-        let v3 = graph.dag.add_node(Release::Concrete(ConcreteRelease {
-            version: String::from("3.0.0"),
-            payload: String::from("image/3.0.0"),
-            metadata: MapImpl::new(),
-        }));
+        let v1 = graph
+            .dag
+            .add_node(Arc::new(Release::Concrete(ConcreteRelease {
+                version: "1.0.0".into(),
+                payload: String::from("image/1.0.0"),
+                metadata: MapImpl::new(),
+            })));
+        let v2 = graph
+            .dag
+            .add_node(Arc::new(Release::Concrete(ConcreteRelease {
+                version: "2.0.0".into(),
+                payload: String::from("image/2.0.0"),
+                metadata: MapImpl::new(),
+            })));
+        let v3 = graph
+            .dag
+            .add_node(Arc::new(Release::Concrete(ConcreteRelease {
+                version: "3.0.0".into(),
+                payload: String::from("image/3.0.0"),
+                metadata: MapImpl::new(),
+            })));
         graph.dag.add_edge(v1, v2, Empty {}).unwrap();
         graph.dag.add_edge(v2, v3, Empty {}).unwrap();
         graph.dag.add_edge(v1, v3, Empty {}).unwrap();
@@ -960,11 +1495,14 @@ pub mod testing {
                     );
 
                     let release = Release::Concrete(ConcreteRelease {
-                        version,
+                        version: version.into(),
                         payload,
-                        metadata,
+                        metadata: metadata
+                            .into_iter()
+                            .map(|(key, value)| (key.into(), value.into()))
+                            .collect(),
                     });
-                    graph.dag.add_node(release)
+                    graph.dag.add_node(Arc::new(release))
                 })
                 .collect();
 
@@ -1003,7 +1541,7 @@ pub mod testing {
 
     impl std::cmp::PartialOrd for Release {
         fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-            Some(self.version().cmp(other.version()))
+            Some(self.cmp(other))
         }
     }
 
@@ -1127,14 +1665,12 @@ pub mod testing {
 
         let _ = graph.iter_releases_mut(|mut release| {
             for key in keys {
-                let key = (*key).to_string();
-
                 if let Release::Concrete(concrete_release) = &mut release {
-                    if let Some(removed_value) = concrete_release.metadata.remove(&key) {
+                    if let Some(removed_value) = concrete_release.metadata.remove(*key) {
                         removed_metadata
                             .entry(release.version().to_string())
                             .or_default()
-                            .insert(key, removed_value);
+                            .insert((*key).to_string(), removed_value.to_string());
                     }
                 }
             }
@@ -1184,6 +1720,14 @@ pub mod testing {
             })
             .context("removing registry and repo from the payload string")
     }
+
+    /// Return the InternalIO of a plugin run, failing on an ExternalIO.
+    pub fn internal_io(io: crate::plugins::PluginIO) -> Fallible<crate::plugins::InternalIO> {
+        match io {
+            crate::plugins::PluginIO::InternalIO(io) => Ok(io),
+            _ => bail!("expected plugin to return InternalIO"),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -1222,6 +1766,37 @@ mod tests {
         );
     }
 
+    #[test]
+    fn serialize_projected_graph() {
+        let graph = generate_graph(true, false);
+
+        let fields: GraphFields = "version,edges".parse().unwrap();
+        assert_eq!(
+            serde_json::to_string(&graph.project(fields)).unwrap(),
+            r#"{"nodes":[{"version":"1.0.0"},{"version":"2.0.0"},{"version":"3.0.0"}],"edges":[[0,1],[1,2],[0,2]]}"#
+        );
+
+        let fields: GraphFields = "payload".parse().unwrap();
+        assert_eq!(
+            serde_json::to_string(&graph.project(fields)).unwrap(),
+            r#"{"nodes":[{"payload":"image/1.0.0"},{"payload":"image/2.0.0"},{"payload":"image/3.0.0"}]}"#
+        );
+
+        assert_eq!(
+            serde_json::to_string(&graph.project(GraphFields::all())).unwrap(),
+            serde_json::to_string(&graph).unwrap()
+        );
+    }
+
+    #[test]
+    fn parse_graph_fields() {
+        let fields: GraphFields = "version, payload,conditionalEdges".parse().unwrap();
+        assert!(fields.version && fields.payload && fields.conditional_edges);
+        assert!(!fields.metadata && !fields.edges);
+
+        assert!("version,nodes".parse::<GraphFields>().is_err());
+    }
+
     #[test]
     fn deserialize_graph() {
         let json = r#"{"nodes":[{"version":"1.0.0","payload":"image/1.0.0","metadata":{}},{"version":"2.0.0","payload":"image/2.0.0","metadata":{}},{"version":"3.0.0","payload":"image/3.0.0","metadata":{}}],"edges":[[0,1],[1,2],[0,2]],"conditionalEdges":[]}"#;
@@ -1237,32 +1812,40 @@ mod tests {
     fn test_graph_eq_false_for_unequal_graphs() {
         let graph1 = {
             let mut graph = Graph::default();
-            let v1 = graph.dag.add_node(Release::Concrete(ConcreteRelease {
-                version: String::from("1.0.0"),
-                payload: String::from("image/1.0.0"),
-                metadata: MapImpl::new(),
-            }));
-            let v2 = graph.dag.add_node(Release::Concrete(ConcreteRelease {
-                version: String::from("2.0.0"),
-                payload: String::from("image/2.0.0"),
-                metadata: MapImpl::new(),
-            }));
+            let v1 = graph
+                .dag
+                .add_node(Arc::new(Release::Concrete(ConcreteRelease {
+                    version: "1.0.0".into(),
+                    payload: String::from("image/1.0.0"),
+                    metadata: MapImpl::new(),
+                })));
+            let v2 = graph
+                .dag
+                .add_node(Arc::new(Release::Concrete(ConcreteRelease {
+                    version: "2.0.0".into(),
+                    payload: String::from("image/2.0.0"),
+                    metadata: MapImpl::new(),
+                })));
             graph.dag.add_edge(v1, v2, Empty {}).unwrap();
 
             graph
         };
         let graph2 = {
             let mut graph = Graph::default();
-            let v3 = graph.dag.add_node(Release::Concrete(ConcreteRelease {
-                version: String::from("3.0.0"),
-                payload: String::from("image/3.0.0"),
-                metadata: MapImpl::new(),
-            }));
-            let v2 = graph.dag.add_node(Release::Concrete(ConcreteRelease {
-                version: String::from("2.0.0"),
-                payload: String::from("image/2.0.0"),
-                metadata: MapImpl::new(),
-            }));
+            let v3 = graph
+                .dag
+                .add_node(Arc::new(Release::Concrete(ConcreteRelease {
+                    version: "3.0.0".into(),
+                    payload: String::from("image/3.0.0"),
+                    metadata: MapImpl::new(),
+                })));
+            let v2 = graph
+                .dag
+                .add_node(Arc::new(Release::Concrete(ConcreteRelease {
+                    version: "2.0.0".into(),
+                    payload: String::from("image/2.0.0"),
+                    metadata: MapImpl::new(),
+                })));
             graph.dag.add_edge(v2, v3, Empty {}).unwrap();
 
             graph
@@ -1277,22 +1860,22 @@ mod tests {
 
     #[test]
     fn test_graph_eq_is_agnostic_to_node_and_edge_order() {
-        let r1 = Release::Concrete(ConcreteRelease {
-            version: String::from("1.0.0"),
+        let r1 = Arc::new(Release::Concrete(ConcreteRelease {
+            version: "1.0.0".into(),
             payload: String::from("image/1.0.0"),
             metadata: MapImpl::new(),
-        });
-        let r2 = Release::Concrete(ConcreteRelease {
-            version: String::from("2.0.0"),
+        }));
+        let r2 = Arc::new(Release::Concrete(ConcreteRelease {
+            version: "2.0.0".into(),
             payload: String::from("image/2.0.0"),
             metadata: MapImpl::new(),
-        });
+        }));
 
-        let r3 = Release::Concrete(ConcreteRelease {
-            version: String::from("3.0.0"),
+        let r3 = Arc::new(Release::Concrete(ConcreteRelease {
+            version: "3.0.0".into(),
             payload: String::from("image/3.0.0"),
             metadata: MapImpl::new(),
-        });
+        }));
 
         let graph1 = {
             let mut graph = Graph::default();
@@ -1321,22 +1904,22 @@ mod tests {
 
     #[test]
     fn test_graph_eq_detects_exceeding_nodes() {
-        let r1 = Release::Concrete(ConcreteRelease {
-            version: String::from("1.0.0"),
+        let r1 = Arc::new(Release::Concrete(ConcreteRelease {
+            version: "1.0.0".into(),
             payload: String::from("image/1.0.0"),
             metadata: MapImpl::new(),
-        });
-        let r2 = Release::Concrete(ConcreteRelease {
-            version: String::from("2.0.0"),
+        }));
+        let r2 = Arc::new(Release::Concrete(ConcreteRelease {
+            version: "2.0.0".into(),
             payload: String::from("image/2.0.0"),
             metadata: MapImpl::new(),
-        });
+        }));
 
-        let r3 = Release::Concrete(ConcreteRelease {
-            version: String::from("3.0.0"),
+        let r3 = Arc::new(Release::Concrete(ConcreteRelease {
+            version: "3.0.0".into(),
             payload: String::from("image/3.0.0"),
             metadata: MapImpl::new(),
-        });
+        }));
 
         let graph1 = {
             let mut graph = Graph::default();
@@ -1366,6 +1949,34 @@ mod tests {
         assert_eq!(generate_graph(false, false), graph_native_converted);
     }
 
+    #[test]
+    fn cloned_graph_shares_releases() {
+        let shared = |a: &Graph, b: &Graph| {
+            a.dag
+                .raw_nodes()
+                .iter()
+                .zip(b.dag.raw_nodes())
+                .filter(|(a, b)| Arc::ptr_eq(&a.weight, &b.weight))
+                .count()
+        };
+
+        let graph = generate_graph(false, false);
+        let mut cloned = graph.clone();
+        assert_eq!(shared(&graph, &cloned), 3);
+
+        assert_eq!(cloned.find_by_fn_mut(|_| true).len(), 3);
+        cloned.iter_releases_mut(|_| Ok(())).unwrap();
+        assert_eq!(shared(&graph, &cloned), 3);
+
+        let id = cloned.find_by_version("2.0.0").unwrap();
+        cloned
+            .get_metadata_as_ref_mut(&id)
+            .unwrap()
+            .insert("key".into(), "value".into());
+        assert_eq!(shared(&graph, &cloned), 2);
+        assert_ne!(graph, cloned);
+    }
+
     fn get_test_metadata_fn_mut(key_prefix: &str, key_suffix: &str) -> TestMetadata {
         vec![
             (
@@ -1450,8 +2061,10 @@ mod tests {
 
         let result = graph.find_by_fn_mut(|release| match release {
             Release::Concrete(concrete_release) => {
-                *concrete_release.metadata.get_mut(&metadata_key).unwrap() =
-                    expected_metadata_value.to_string();
+                *concrete_release
+                    .metadata
+                    .get_mut(metadata_key.as_str())
+                    .unwrap() = expected_metadata_value.into();
                 true
             }
             _ => true,
@@ -1464,7 +2077,7 @@ mod tests {
                 graph
                     .get_metadata_as_ref_mut(&release_id)
                     .unwrap()
-                    .get(&metadata_key)
+                    .get(metadata_key.as_str())
                     .unwrap(),
                 expected_metadata_value
             )
@@ -1546,4 +2159,114 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn shortest_path_follows_conditional_edges() -> TestResult<()> {
+        let graph: Graph = serde_json::from_str(
+            r#"{
+                "nodes": [
+                    {"version": "1.0.0", "payload": "image/1.0.0", "metadata": {}},
+                    {"version": "2.0.0", "payload": "image/2.0.0", "metadata": {}},
+                    {"version": "3.0.0", "payload": "image/3.0.0", "metadata": {}},
+                    {"version": "4.0.0", "payload": "image/4.0.0", "metadata": {}}
+                ],
+                "edges": [[0, 1], [1, 2], [0, 2]],
+                "conditionalEdges": [
+                    {
+                        "edges": [{"from": "3.0.0", "to": "4.0.0"}, {"from": "1.0.0", "to": "3.0.0"}],
+                        "risks": [{"url": "https://example.com", "name": "Risk", "message": "Risky", "matchingRules": [{"type": "Always"}]}]
+                    }
+                ]
+            }"#,
+        )?;
+        let path = |from, to| {
+            graph.shortest_path(from, to).map(|steps| {
+                steps
+                    .into_iter()
+                    .map(|step| {
+                        let risks: Vec<&str> =
+                            step.risks.iter().map(|risk| risk.name.as_str()).collect();
+                        (step.from, step.to, risks)
+                    })
+                    .collect::<Vec<_>>()
+            })
+        };
+
+        assert_eq!(
+            path("1.0.0", "3.0.0"),
+            Some(vec![("1.0.0", "3.0.0", vec![])])
+        );
+        assert_eq!(
+            path("2.0.0", "4.0.0"),
+            Some(vec![
+                ("2.0.0", "3.0.0", vec![]),
+                ("3.0.0", "4.0.0", vec!["Risk"])
+            ])
+        );
+        assert_eq!(path("1.0.0", "1.0.0"), Some(vec![]));
+        assert_eq!(path("3.0.0", "1.0.0"), None);
+        assert_eq!(path("1.0.0", "5.0.0"), None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn reachable_from() -> TestResult<()> {
+        let graph: Graph = serde_json::from_str(
+            r#"{
+                "nodes": [
+                    {"version": "1.0.0", "payload": "image/1.0.0", "metadata": {}},
+                    {"version": "2.0.0", "payload": "image/2.0.0", "metadata": {}},
+                    {"version": "3.0.0", "payload": "image/3.0.0", "metadata": {}},
+                    {"version": "4.0.0", "payload": "image/4.0.0", "metadata": {}},
+                    {"version": "5.0.0", "payload": "image/5.0.0", "metadata": {}}
+                ],
+                "edges": [[0, 1], [1, 2], [0, 4]],
+                "conditionalEdges": [
+                    {
+                        "edges": [{"from": "3.0.0", "to": "4.0.0"}, {"from": "1.0.0", "to": "3.0.0"}],
+                        "risks": [{"url": "https://example.com", "name": "Risk", "message": "Risky", "matchingRules": [{"type": "Always"}]}]
+                    }
+                ]
+            }"#,
+        )?;
+        let versions = |graph: &Graph| {
+            let mut versions: Vec<String> = graph
+                .iter_releases()
+                .map(|release| release.version().to_string())
+                .collect();
+            versions.sort();
+            versions
+        };
+
+        let reachable = graph.reachable_from("2.0.0").unwrap();
+        assert_eq!(versions(&reachable), vec!["2.0.0", "3.0.0", "4.0.0"]);
+        assert_eq!(reachable.edges_count(), 1);
+        assert_eq!(
+            reachable.conditional_edges(),
+            vec![("3.0.0", "4.0.0", vec!["Risk"])]
+        );
+
+        let shared = graph
+            .dag
+            .raw_nodes()
+            .iter()
+            .filter(|node| {
+                reachable
+                    .dag
+                    .raw_nodes()
+                    .iter()
+                    .any(|other| Arc::ptr_eq(&node.weight, &other.weight))
+            })
+            .count();
+        assert_eq!(shared, 3);
+
+        let reachable = graph.reachable_from("4.0.0").unwrap();
+        assert_eq!(versions(&reachable), vec!["4.0.0"]);
+        assert!(reachable.conditional_edges().is_empty());
+
+        assert!(graph.reachable_from("6.0.0").is_none());
+
+        Ok(())
+    }
 }
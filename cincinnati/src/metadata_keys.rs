@@ -0,0 +1,94 @@
+//! Keys of the well-known release metadata.
+//!
+//! The keys share a prefix, `io.openshift.upgrades.graph` by default, which the
+//! plugins filtering or assigning releases by their channels or architecture
+//! take as `key_prefix` setting. Code outside of the plugin chain reads the
+//! metadata with the prefix of the configured plugins, so that a deployment
+//! with another prefix is handled the same throughout.
+
+use crate::plugins::catalog::PluginSettings;
+use crate::plugins::internal::arch_filter::{ArchKeys, DEFAULT_ARCH_KEY, DEFAULT_KEY_FILTER};
+use crate::plugins::internal::channel_filter::DEFAULT_CHANNEL_KEY;
+
+/// Keys of the well-known release metadata.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MetadataKeys {
+    prefix: String,
+    /// Architecture of the release.
+    pub arch: String,
+    /// Channels the release is part of.
+    pub channels: String,
+    /// Date the release was published at.
+    pub date: String,
+    /// Identifier of the errata of the release.
+    pub errata: String,
+    /// URL of the release notes.
+    pub notes_url: String,
+}
+
+impl Default for MetadataKeys {
+    fn default() -> Self {
+        Self::with_prefix(DEFAULT_KEY_FILTER)
+    }
+}
+
+impl MetadataKeys {
+    /// Keys with the given prefix.
+    pub fn with_prefix(prefix: &str) -> Self {
+        Self {
+            prefix: prefix.to_string(),
+            arch: format!("{}.{}", prefix, DEFAULT_ARCH_KEY),
+            channels: format!("{}.{}", prefix, DEFAULT_CHANNEL_KEY),
+            date: format!("{}.release.date", prefix),
+            errata: format!("{}.release.errata", prefix),
+            notes_url: format!("{}.release.notes_url", prefix),
+        }
+    }
+
+    /// Keys with the prefix of the first plugin which has one, or the default prefix.
+    pub fn from_plugins(settings: &[Box<dyn PluginSettings>]) -> Self {
+        settings
+            .iter()
+            .find_map(|settings| settings.key_prefix())
+            .map_or_else(Self::default, Self::with_prefix)
+    }
+
+    /// Prefix of the keys.
+    pub fn prefix(&self) -> &str {
+        &self.prefix
+    }
+
+    /// Keys of the architectures of the release.
+    pub fn arch_keys(&self) -> ArchKeys {
+        ArchKeys::with_prefix(&self.prefix)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plugins::catalog::deserialize_config;
+
+    #[test]
+    fn keys_of_configured_plugins() {
+        let settings = |cfg: &str| deserialize_config(toml::from_str(cfg).unwrap()).unwrap();
+
+        assert_eq!(
+            MetadataKeys::from_plugins(&[settings(r#"name = "node-remove""#)]).channels,
+            "io.openshift.upgrades.graph.release.channels"
+        );
+
+        let keys = MetadataKeys::from_plugins(&[
+            settings(r#"name = "node-remove""#),
+            settings(
+                r#"
+                    name = "channel-filter"
+                    key_prefix = "com.example.graph"
+                "#,
+            ),
+        ]);
+        assert_eq!(keys.prefix(), "com.example.graph");
+        assert_eq!(keys.channels, "com.example.graph.release.channels");
+        assert_eq!(keys.arch_keys().arch, "com.example.graph.release.arch");
+    }
+}
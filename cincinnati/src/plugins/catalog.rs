@@ -7,9 +7,20 @@ use crate as cincinnati;
 
 use self::cincinnati::plugins::BoxedPlugin;
 
+use super::external::grpc::{ExternalGrpcPlugin, ExternalGrpcSettings};
+use super::guard::{GuardSettings, GuardedSettings};
 use super::internal::arch_filter::ArchFilterPlugin;
+use super::internal::candidate_channel_derive::{
+    CandidateChannelDerivePlugin, CandidateChannelDeriveSettings,
+};
 use super::internal::channel_filter::ChannelFilterPlugin;
 use super::internal::cincinnati_graph_fetch::CincinnatiGraphFetchPlugin;
+use super::internal::cluster_profile_filter::{
+    ClusterProfileFilterPlugin, ClusterProfileFilterSettings,
+};
+use super::internal::conditional_risk_evaluator::{
+    ConditionalRiskEvaluatorPlugin, ConditionalRiskEvaluatorSettings,
+};
 use super::internal::dkrv2_openshift_secondary_metadata_scraper::{
     DkrV2OpenshiftSecondaryMetadataScraperPlugin, DkrV2OpenshiftSecondaryMetadataScraperSettings,
 };
@@ -17,14 +28,38 @@ use super::internal::edge_add_remove::EdgeAddRemovePlugin;
 use super::internal::github_openshift_secondary_metadata_scraper::{
     GithubOpenshiftSecondaryMetadataScraperPlugin, GithubOpenshiftSecondaryMetadataScraperSettings,
 };
+use super::internal::graph_file_load::{GraphFileLoadPlugin, GraphFileLoadSettings};
+use super::internal::graph_sanity_check::{GraphSanityCheckPlugin, GraphSanityCheckSettings};
+use super::internal::local_openshift_secondary_metadata_scraper::{
+    LocalOpenshiftSecondaryMetadataScraperPlugin, LocalOpenshiftSecondaryMetadataScraperSettings,
+};
 use super::internal::metadata_fetch_quay::QuayMetadataFetchPlugin;
+use super::internal::multiarch_publication_gate::{
+    MultiArchPublicationGatePlugin, MultiArchPublicationGateSettings,
+};
 use super::internal::node_remove::NodeRemovePlugin;
 use super::internal::openshift_secondary_metadata_parser::{
     OpenshiftSecondaryMetadataParserPlugin, OpenshiftSecondaryMetadataParserSettings,
 };
+use super::internal::phased_rollout::{PhasedRolloutPlugin, PhasedRolloutSettings};
+use super::internal::release_cosign_verify::{
+    ReleaseCosignVerifyPlugin, ReleaseCosignVerifySettings,
+};
+use super::internal::release_enrichment_hook::{
+    ReleaseEnrichmentHookPlugin, ReleaseEnrichmentHookSettings,
+};
+use super::internal::release_notes_enrich::{ReleaseNotesEnrichPlugin, ReleaseNotesEnrichSettings};
 use super::internal::release_scrape_dockerv2::{
     ReleaseScrapeDockerv2Plugin, ReleaseScrapeDockerv2Settings,
 };
+use super::internal::s3_openshift_secondary_metadata_scraper::{
+    S3OpenshiftSecondaryMetadataScraperPlugin, S3OpenshiftSecondaryMetadataScraperSettings,
+};
+use super::internal::synthetic_releases::{SyntheticReleasesPlugin, SyntheticReleasesSettings};
+use super::internal::time_window::{TimeWindowPlugin, TimeWindowSettings};
+use super::internal::version_filter::{VersionFilterPlugin, VersionFilterSettings};
+#[cfg(feature = "wasm")]
+use super::internal::wasm_transform::{WasmTransformPlugin, WasmTransformSettings};
 use commons::prelude_errors::*;
 use std::fmt::Debug;
 
@@ -35,18 +70,27 @@ static CONFIG_PLUGIN_NAME_KEY: &str = "name";
 pub trait PluginSettings: Debug + Send {
     /// Build the corresponding plugin for this configuration.
     fn build_plugin(&self, registry: Option<&prometheus::Registry>) -> Fallible<BoxedPlugin>;
+
+    /// Prefix of the metadata keys the plugin reads or writes, if it has one.
+    fn key_prefix(&self) -> Option<&str> {
+        None
+    }
 }
 
 /// Validate configuration for a plugin and fill in defaults.
-pub fn deserialize_config(cfg: toml::Value) -> Fallible<Box<dyn PluginSettings>> {
+///
+/// The timeout and circuit breaker settings common to all plugins are handled here.
+pub fn deserialize_config(mut cfg: toml::Value) -> Fallible<Box<dyn PluginSettings>> {
     let name = cfg
         .get(CONFIG_PLUGIN_NAME_KEY)
         .ok_or_else(|| format_err!("missing plugin name"))?
         .as_str()
         .ok_or_else(|| format_err!("invalid plugin name value"))?
         .to_string();
+    let guard =
+        GuardSettings::extract(&mut cfg).context(format!("invalid settings of '{}'", name))?;
 
-    match name.as_str() {
+    let settings = match name.as_str() {
         ChannelFilterPlugin::PLUGIN_NAME => ChannelFilterPlugin::deserialize_config(cfg),
         EdgeAddRemovePlugin::PLUGIN_NAME => EdgeAddRemovePlugin::deserialize_config(cfg),
         NodeRemovePlugin::PLUGIN_NAME => NodeRemovePlugin::deserialize_config(cfg),
@@ -67,15 +111,68 @@ pub fn deserialize_config(cfg: toml::Value) -> Fallible<Box<dyn PluginSettings>>
         DkrV2OpenshiftSecondaryMetadataScraperPlugin::PLUGIN_NAME => {
             DkrV2OpenshiftSecondaryMetadataScraperSettings::deserialize_config(cfg)
         }
+        LocalOpenshiftSecondaryMetadataScraperPlugin::PLUGIN_NAME => {
+            LocalOpenshiftSecondaryMetadataScraperSettings::deserialize_config(cfg)
+        }
+        S3OpenshiftSecondaryMetadataScraperPlugin::PLUGIN_NAME => {
+            S3OpenshiftSecondaryMetadataScraperSettings::deserialize_config(cfg)
+        }
+        ReleaseEnrichmentHookPlugin::PLUGIN_NAME => {
+            ReleaseEnrichmentHookSettings::deserialize_config(cfg)
+        }
+        ReleaseCosignVerifyPlugin::PLUGIN_NAME => {
+            ReleaseCosignVerifySettings::deserialize_config(cfg)
+        }
+        ConditionalRiskEvaluatorPlugin::PLUGIN_NAME => {
+            ConditionalRiskEvaluatorSettings::deserialize_config(cfg)
+        }
+        CandidateChannelDerivePlugin::PLUGIN_NAME => {
+            CandidateChannelDeriveSettings::deserialize_config(cfg)
+        }
+        MultiArchPublicationGatePlugin::PLUGIN_NAME => {
+            MultiArchPublicationGateSettings::deserialize_config(cfg)
+        }
+        ReleaseNotesEnrichPlugin::PLUGIN_NAME => {
+            ReleaseNotesEnrichSettings::deserialize_config(cfg)
+        }
+        SyntheticReleasesPlugin::PLUGIN_NAME => SyntheticReleasesSettings::deserialize_config(cfg),
+        VersionFilterPlugin::PLUGIN_NAME => VersionFilterSettings::deserialize_config(cfg),
+        PhasedRolloutPlugin::PLUGIN_NAME => PhasedRolloutSettings::deserialize_config(cfg),
+        TimeWindowPlugin::PLUGIN_NAME => TimeWindowSettings::deserialize_config(cfg),
+        ClusterProfileFilterPlugin::PLUGIN_NAME => {
+            ClusterProfileFilterSettings::deserialize_config(cfg)
+        }
+        #[cfg(feature = "wasm")]
+        WasmTransformPlugin::PLUGIN_NAME => WasmTransformSettings::deserialize_config(cfg),
+        #[cfg(not(feature = "wasm"))]
+        "wasm-transform" => bail!("the 'wasm-transform' plugin requires the 'wasm' feature"),
+        GraphFileLoadPlugin::PLUGIN_NAME => GraphFileLoadSettings::deserialize_config(cfg),
+        ExternalGrpcPlugin::PLUGIN_NAME => ExternalGrpcSettings::deserialize_config(cfg),
+        GraphSanityCheckPlugin::PLUGIN_NAME => GraphSanityCheckSettings::deserialize_config(cfg),
         x => bail!("unknown plugin '{}'", x),
+    }?;
+
+    if guard.is_unguarded() {
+        Ok(settings)
+    } else {
+        Ok(Box::new(GuardedSettings {
+            inner: settings,
+            guard,
+        }))
     }
 }
 
 /// Bulid a vector of plugins from PluginSettings
+///
+/// The metrics of the plugin runs are registered to the registry, if any.
 pub fn build_plugins(
     settings: &[Box<dyn PluginSettings>],
     registry: Option<&prometheus::Registry>,
 ) -> Fallible<Vec<BoxedPlugin>> {
+    if let Some(registry) = registry {
+        cincinnati::plugins::register_metrics(registry)?;
+    }
+
     let mut plugins = Vec::with_capacity(settings.len());
     for setting in settings {
         let plugin = setting.build_plugin(registry)?;
@@ -0,0 +1,640 @@
+//! This plugin hands the graph to an external plugin reached over gRPC, so
+//! that plugins written in other languages can run out of process.
+//!
+//! The external plugin implements the `Plugin` service of
+//! `plugin_service.proto`. Instead of a single message holding the whole
+//! graph, the graph is streamed in chunks of at most `chunk_size` nodes or
+//! edges in both directions. This keeps every message far below the gRPC
+//! message size limits, and chunks are built while they are sent, so large
+//! graphs are never serialized at once. The protocol has no conditional edges
+//! nor abstract releases, graphs containing them are refused instead of being
+//! silently stripped. Each call
+//! is bounded by a deadline, and calls failing with a transient status are
+//! retried with an exponential backoff.
+
+use crate as cincinnati;
+
+use self::cincinnati::plugins::interface::{self, PluginExchange};
+use self::cincinnati::plugins::prelude::*;
+use self::cincinnati::plugins::prelude_plugin_impl::*;
+use self::cincinnati::{ConcreteRelease, Release};
+
+use bytes::{Buf, BufMut};
+use protobuf::Message;
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+use tonic::codec::{Codec, DecodeBuf, Decoder, EncodeBuf, Encoder};
+use tonic::codegen::http::uri::PathAndQuery;
+use tonic::transport::{Certificate, ClientTlsConfig, Endpoint, Identity};
+use tonic::{Code, Status};
+
+/// Default maximum number of nodes or edges in a chunk.
+pub static DEFAULT_CHUNK_SIZE: usize = 1000;
+
+/// Default deadline of a call, in seconds.
+pub static DEFAULT_TIMEOUT_SECS: u64 = 60;
+
+/// Default timeout for connecting to the plugin, in seconds.
+pub static DEFAULT_CONNECT_TIMEOUT_SECS: u64 = 10;
+
+/// Default number of attempts of a call, including the first one.
+pub static DEFAULT_MAX_ATTEMPTS: usize = 3;
+
+/// Default delay before the first retry, in seconds.
+pub static DEFAULT_RETRY_DELAY_SECS: u64 = 1;
+
+/// Path of the `Process` method of the plugin service.
+static PROCESS_PATH: &str = "/cincinnati.plugin.Plugin/Process";
+
+/// Status codes of transient failures, which are retried.
+static RETRIED_CODES: &[Code] = &[
+    Code::Unavailable,
+    Code::DeadlineExceeded,
+    Code::ResourceExhausted,
+];
+
+/// Plugin settings.
+#[derive(Clone, Debug, Deserialize, SmartDefault)]
+#[serde(default)]
+pub struct ExternalGrpcSettings {
+    /// URL of the plugin, with the `http` or `https` scheme.
+    pub url: String,
+
+    /// Deadline of a call, from connecting to receiving the whole output graph.
+    #[default(DEFAULT_TIMEOUT_SECS)]
+    pub timeout_secs: u64,
+
+    /// Timeout for connecting to the plugin.
+    #[default(DEFAULT_CONNECT_TIMEOUT_SECS)]
+    pub connect_timeout_secs: u64,
+
+    /// Number of attempts of a call, including the first one.
+    #[default(DEFAULT_MAX_ATTEMPTS)]
+    pub max_attempts: usize,
+
+    /// Delay before the first retry, doubled on each further retry.
+    #[default(DEFAULT_RETRY_DELAY_SECS)]
+    pub retry_delay_secs: u64,
+
+    /// Maximum number of nodes or edges in a chunk.
+    #[default(DEFAULT_CHUNK_SIZE)]
+    pub chunk_size: usize,
+
+    /// File containing PEM-encoded CA certificates to trust for the plugin.
+    pub ca_cert_path: Option<PathBuf>,
+
+    /// Files containing the PEM-encoded client certificate and key presented to the plugin.
+    pub client_cert_path: Option<PathBuf>,
+    pub client_key_path: Option<PathBuf>,
+}
+
+impl PluginSettings for ExternalGrpcSettings {
+    fn build_plugin(&self, registry: Option<&prometheus::Registry>) -> Fallible<BoxedPlugin> {
+        let plugin = ExternalGrpcPlugin::try_new(self.clone(), registry)?;
+        Ok(new_plugin!(InternalPluginWrapper(plugin)))
+    }
+}
+
+impl ExternalGrpcSettings {
+    /// Validate plugin configuration and fill in defaults.
+    pub fn deserialize_config(cfg: toml::Value) -> Fallible<Box<dyn PluginSettings>> {
+        let settings: Self = cfg.try_into()?;
+
+        let url = url::Url::parse(&settings.url)
+            .context(format!("parsing plugin url '{}'", settings.url))?;
+        ensure!(
+            url.scheme() == "http" || url.scheme() == "https",
+            "unsupported scheme '{}' of plugin url, expected http or https",
+            url.scheme()
+        );
+        ensure!(settings.timeout_secs > 0, "timeout_secs must be positive");
+        ensure!(
+            settings.connect_timeout_secs > 0,
+            "connect_timeout_secs must be positive"
+        );
+        ensure!(settings.max_attempts > 0, "max_attempts must be positive");
+        ensure!(settings.chunk_size > 0, "chunk_size must be positive");
+        ensure!(
+            settings.client_cert_path.is_some() == settings.client_key_path.is_some(),
+            "client_cert_path and client_key_path must be set together"
+        );
+
+        Ok(Box::new(settings))
+    }
+}
+
+/// Codec of the plugin service, whose messages are generated by rust-protobuf.
+#[derive(Clone, Copy, Debug, Default)]
+struct ExchangeCodec;
+
+impl Codec for ExchangeCodec {
+    type Encode = PluginExchange;
+    type Decode = PluginExchange;
+    type Encoder = ExchangeCodec;
+    type Decoder = ExchangeCodec;
+
+    fn encoder(&mut self) -> Self::Encoder {
+        *self
+    }
+
+    fn decoder(&mut self) -> Self::Decoder {
+        *self
+    }
+}
+
+impl Encoder for ExchangeCodec {
+    type Item = PluginExchange;
+    type Error = Status;
+
+    fn encode(&mut self, item: Self::Item, dst: &mut EncodeBuf<'_>) -> Result<(), Self::Error> {
+        let bytes = item
+            .write_to_bytes()
+            .map_err(|e| Status::internal(format!("encoding chunk: {}", e)))?;
+        dst.put_slice(&bytes);
+        Ok(())
+    }
+}
+
+impl Decoder for ExchangeCodec {
+    type Item = PluginExchange;
+    type Error = Status;
+
+    fn decode(&mut self, src: &mut DecodeBuf<'_>) -> Result<Option<Self::Item>, Self::Error> {
+        let bytes = src.copy_to_bytes(src.remaining());
+        PluginExchange::parse_from_bytes(&bytes)
+            .map(Some)
+            .map_err(|e| Status::internal(format!("decoding chunk: {}", e)))
+    }
+}
+
+/// Chunks of the graph and the parameters, of at most `chunk_size` nodes or
+/// edges, nodes first. Chunks are built as they are sent, so the graph is never
+/// converted as a whole.
+struct Chunks {
+    graph: cincinnati::Graph,
+    parameters: Option<HashMap<String, String>>,
+    chunk_size: usize,
+    nodes_sent: usize,
+    edges_sent: usize,
+}
+
+/// Refuse graphs which the plugin protocol can't carry, which are graphs with
+/// conditional edges or abstract releases.
+fn ensure_representable(graph: &cincinnati::Graph) -> Fallible<()> {
+    let conditional_edges = graph.conditional_edges().len();
+    ensure!(
+        conditional_edges == 0,
+        "the graph has {} conditional edges, which the plugin protocol can't carry",
+        conditional_edges
+    );
+    if let Some(release) = graph
+        .iter_releases()
+        .find(|release| matches!(release, Release::Abstract(_)))
+    {
+        bail!(
+            "the graph has the abstract release {}, which the plugin protocol can't carry",
+            release.version()
+        );
+    }
+    Ok(())
+}
+
+impl Chunks {
+    fn new(io: InternalIO, chunk_size: usize) -> Self {
+        Self {
+            graph: io.graph,
+            parameters: Some(io.parameters),
+            chunk_size,
+            nodes_sent: 0,
+            edges_sent: 0,
+        }
+    }
+}
+
+impl Iterator for Chunks {
+    type Item = PluginExchange;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let nodes = self.graph.dag.raw_nodes();
+        let edges = self.graph.dag.raw_edges();
+
+        let mut chunk = PluginExchange::new();
+        if self.nodes_sent < nodes.len() {
+            let end = nodes.len().min(self.nodes_sent + self.chunk_size);
+            let nodes: Vec<_> = nodes[self.nodes_sent..end]
+                .iter()
+                .map(|node| {
+                    let mut converted = interface::Graph_Node::new();
+                    if let Release::Concrete(release) = node.weight.as_ref() {
+                        converted.set_version(release.version.to_string());
+                        converted.set_payload(release.payload.clone());
+                        converted.set_metadata(
+                            release
+                                .metadata
+                                .iter()
+                                .map(|(key, value)| (key.to_string(), value.to_string()))
+                                .collect(),
+                        );
+                    }
+                    converted
+                })
+                .collect();
+            chunk.mut_graph().set_nodes(nodes.into());
+            self.nodes_sent = end;
+        } else if self.edges_sent < edges.len() {
+            let end = edges.len().min(self.edges_sent + self.chunk_size);
+            let edges: Vec<_> = edges[self.edges_sent..end]
+                .iter()
+                .map(|edge| {
+                    let mut converted = interface::Graph_Edge::new();
+                    converted.set_from(edge.source().index() as u64);
+                    converted.set_to(edge.target().index() as u64);
+                    converted
+                })
+                .collect();
+            chunk.mut_graph().set_edges(edges.into());
+            self.edges_sent = end;
+        } else if self.parameters.is_none() {
+            return None;
+        }
+
+        // An empty graph is still sent as one chunk carrying the parameters.
+        if let Some(parameters) = self.parameters.take() {
+            chunk.set_parameters(parameters);
+        }
+        Some(chunk)
+    }
+}
+
+/// Graph received in chunks.
+#[derive(Debug, Default)]
+struct Assembly {
+    graph: cincinnati::Graph,
+    /// Releases by index in the received graph.
+    releases: Vec<ReleaseId>,
+    versions: HashSet<String>,
+    edges_received: bool,
+    parameters: HashMap<String, String>,
+}
+
+impl Assembly {
+    /// Add the nodes, edges and parameters of a chunk.
+    fn push(&mut self, mut chunk: PluginExchange) -> Fallible<()> {
+        self.parameters.extend(chunk.take_parameters());
+
+        let mut graph = chunk.take_graph();
+        let nodes = graph.take_nodes();
+        ensure!(
+            nodes.is_empty() || !self.edges_received,
+            "received nodes after edges"
+        );
+        for node in nodes.into_iter() {
+            ensure!(
+                self.versions.insert(node.version.clone()),
+                "duplicate release {}",
+                node.version
+            );
+            let release = self.graph.add_release(Release::Concrete(ConcreteRelease {
+                version: node.version.into(),
+                payload: node.payload,
+                metadata: node
+                    .metadata
+                    .into_iter()
+                    .map(|(key, value)| (key.into(), value.into()))
+                    .collect(),
+            }))?;
+            self.releases.push(release);
+        }
+
+        for edge in graph.take_edges().into_iter() {
+            self.edges_received = true;
+            let release = |index: u64| {
+                self.releases
+                    .get(index as usize)
+                    .cloned()
+                    .ok_or_else(|| format_err!("edge refers to unknown node {}", index))
+            };
+            let (from, to) = (release(edge.from)?, release(edge.to)?);
+            self.graph.add_edge(&from, &to)?;
+        }
+
+        Ok(())
+    }
+
+    fn finish(self) -> InternalIO {
+        InternalIO {
+            graph: self.graph,
+            parameters: self.parameters,
+        }
+    }
+}
+
+/// Runs an external plugin over gRPC.
+#[derive(CustomDebug)]
+pub struct ExternalGrpcPlugin {
+    settings: ExternalGrpcSettings,
+
+    #[debug(skip)]
+    endpoint: Endpoint,
+}
+
+impl ExternalGrpcPlugin {
+    /// Plugin name, for configuration.
+    pub const PLUGIN_NAME: &'static str = "external-grpc";
+
+    pub fn try_new(
+        settings: ExternalGrpcSettings,
+        _prometheus_registry: Option<&prometheus::Registry>,
+    ) -> Fallible<Self> {
+        let mut endpoint = Endpoint::from_shared(settings.url.clone())
+            .context(format!("parsing plugin url '{}'", settings.url))?
+            .connect_timeout(Duration::from_secs(settings.connect_timeout_secs))
+            .user_agent(commons::outbound::user_agent())?;
+
+        if settings.url.starts_with("https://") {
+            let mut tls = ClientTlsConfig::new();
+            if let Some(path) = &settings.ca_cert_path {
+                let pem = std::fs::read(path)
+                    .context(format!("reading CA certificates from {}", path.display()))?;
+                tls = tls.ca_certificate(Certificate::from_pem(pem));
+            }
+            if let (Some(cert_path), Some(key_path)) =
+                (&settings.client_cert_path, &settings.client_key_path)
+            {
+                let cert = std::fs::read(cert_path).context(format!(
+                    "reading client certificate from {}",
+                    cert_path.display()
+                ))?;
+                let key = std::fs::read(key_path)
+                    .context(format!("reading client key from {}", key_path.display()))?;
+                tls = tls.identity(Identity::from_pem(cert, key));
+            }
+            endpoint = endpoint.tls_config(tls)?;
+        }
+
+        Ok(Self { settings, endpoint })
+    }
+
+    /// Stream the graph to the plugin and receive its output graph.
+    async fn call(&self, io: InternalIO) -> Result<InternalIO, Status> {
+        let channel = self
+            .endpoint
+            .connect()
+            .await
+            .map_err(|e| Status::unavailable(format!("connecting to plugin: {}", e)))?;
+        let mut client = tonic::client::Grpc::new(channel);
+        client
+            .ready()
+            .await
+            .map_err(|e| Status::unavailable(format!("plugin not ready: {}", e)))?;
+
+        let chunks = Chunks::new(io, self.settings.chunk_size);
+        let mut request = tonic::Request::new(futures::stream::iter(chunks));
+        request.set_timeout(Duration::from_secs(self.settings.timeout_secs));
+        let mut response = client
+            .streaming(
+                request,
+                PathAndQuery::from_static(PROCESS_PATH),
+                ExchangeCodec,
+            )
+            .await?
+            .into_inner();
+
+        let mut assembly = Assembly::default();
+        while let Some(chunk) = response.message().await? {
+            assembly
+                .push(chunk)
+                .map_err(|e| Status::internal(format!("invalid output graph: {}", e)))?;
+        }
+        Ok(assembly.finish())
+    }
+
+    /// Call the plugin within the deadline.
+    async fn call_with_deadline(&self, io: InternalIO) -> Result<InternalIO, Status> {
+        let deadline = Duration::from_secs(self.settings.timeout_secs);
+        match tokio::time::timeout(deadline, self.call(io)).await {
+            Ok(result) => result,
+            Err(_) => Err(Status::deadline_exceeded(format!(
+                "no complete answer within {}s",
+                deadline.as_secs()
+            ))),
+        }
+    }
+}
+
+#[async_trait]
+impl InternalPlugin for ExternalGrpcPlugin {
+    const PLUGIN_NAME: &'static str = Self::PLUGIN_NAME;
+
+    async fn run_internal(&self, io: InternalIO) -> Fallible<InternalIO> {
+        // Refuse before connecting, such graphs would fail every attempt.
+        ensure_representable(&io.graph).context(format!(
+            "handing the graph to the external plugin at {}",
+            self.settings.url
+        ))?;
+
+        let mut delay = Duration::from_secs(self.settings.retry_delay_secs);
+        let mut attempt = 1;
+        loop {
+            // Releases are shared between graph copies, cloning them is cheap.
+            let status = match self.call_with_deadline(io.clone()).await {
+                Ok(output) => return Ok(output),
+                Err(status) => status,
+            };
+
+            if attempt >= self.settings.max_attempts || !RETRIED_CODES.contains(&status.code()) {
+                bail!(
+                    "external plugin at {} failed: {:?}: {}",
+                    self.settings.url,
+                    status.code(),
+                    status.message()
+                );
+            }
+            debug!(
+                "[{}] attempt {} failed with {:?}, retrying in {}s: {}",
+                Self::PLUGIN_NAME,
+                attempt,
+                status.code(),
+                delay.as_secs(),
+                status.message()
+            );
+            tokio::time::sleep(delay).await;
+            delay *= 2;
+            attempt += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cincinnati::testing::generate_custom_graph;
+    use tonic::codegen::{http, Body, BoxFuture, Context, Poll, Service, StdError};
+
+    fn input() -> InternalIO {
+        InternalIO {
+            graph: generate_custom_graph(
+                "image",
+                (0..5).map(|i| (i, Default::default())).collect(),
+                Some(vec![(0, 1), (1, 2), (2, 3), (3, 4), (0, 4)]),
+            ),
+            parameters: [("channel".to_string(), "stable-4.11".to_string())]
+                .iter()
+                .cloned()
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn chunked_roundtrip() -> Fallible<()> {
+        let chunks: Vec<_> = Chunks::new(input(), 2).collect();
+        let sizes: Vec<_> = chunks
+            .iter()
+            .map(|chunk| (chunk.get_graph().nodes.len(), chunk.get_graph().edges.len()))
+            .collect();
+        assert_eq!(sizes, vec![(2, 0), (2, 0), (1, 0), (0, 2), (0, 2), (0, 1)]);
+        assert_eq!(chunks[0].get_parameters().len(), 1);
+        assert!(chunks[1].get_parameters().is_empty());
+
+        let mut assembly = Assembly::default();
+        for chunk in chunks {
+            assembly.push(chunk)?;
+        }
+        assert_eq!(assembly.finish(), input());
+
+        let empty = Chunks::new(
+            InternalIO {
+                graph: Default::default(),
+                parameters: Default::default(),
+            },
+            2,
+        );
+        assert_eq!(empty.count(), 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_chunks() {
+        let mut chunks = Chunks::new(input(), 3).collect::<Vec<_>>();
+        let mut assembly = Assembly::default();
+        assembly.push(chunks.remove(0)).unwrap();
+
+        let mut edge = interface::Graph_Edge::new();
+        edge.set_from(0);
+        edge.set_to(7);
+        let mut chunk = PluginExchange::new();
+        chunk.mut_graph().set_edges(vec![edge].into());
+        assert_eq!(
+            assembly.push(chunk).unwrap_err().to_string(),
+            "edge refers to unknown node 7"
+        );
+        assert_eq!(
+            assembly.push(chunks.remove(0)).unwrap_err().to_string(),
+            "received nodes after edges"
+        );
+
+        let mut assembly = Assembly::default();
+        let chunk = Chunks::new(input(), 3).collect::<Vec<_>>().remove(0);
+        assembly.push(chunk.clone()).unwrap();
+        assert_eq!(
+            assembly.push(chunk).unwrap_err().to_string(),
+            "duplicate release 0.0.0"
+        );
+    }
+
+    #[test]
+    fn unrepresentable_graphs() {
+        let graph = cincinnati::testing::generate_graph(true, false);
+        assert_eq!(
+            ensure_representable(&graph).unwrap_err().to_string(),
+            "the graph has 1 conditional edges, which the plugin protocol can't carry"
+        );
+        assert!(ensure_representable(&input().graph).is_ok());
+    }
+
+    /// Plugin service answering with its input graph.
+    #[derive(Clone)]
+    struct EchoPlugin;
+
+    impl tonic::server::StreamingService<PluginExchange> for EchoPlugin {
+        type Response = PluginExchange;
+        type ResponseStream = tonic::Streaming<PluginExchange>;
+        type Future = BoxFuture<tonic::Response<Self::ResponseStream>, Status>;
+
+        fn call(
+            &mut self,
+            request: tonic::Request<tonic::Streaming<PluginExchange>>,
+        ) -> Self::Future {
+            Box::pin(async move { Ok(tonic::Response::new(request.into_inner())) })
+        }
+    }
+
+    impl<B> Service<http::Request<B>> for EchoPlugin
+    where
+        B: Body + Send + 'static,
+        B::Error: Into<StdError> + Send + 'static,
+    {
+        type Response = http::Response<tonic::body::BoxBody>;
+        type Error = std::convert::Infallible;
+        type Future = BoxFuture<Self::Response, Self::Error>;
+
+        fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, request: http::Request<B>) -> Self::Future {
+            assert_eq!(request.uri().path(), PROCESS_PATH);
+            Box::pin(async move {
+                let mut grpc = tonic::server::Grpc::new(ExchangeCodec);
+                Ok(grpc.streaming(EchoPlugin, request).await)
+            })
+        }
+    }
+
+    impl tonic::transport::NamedService for EchoPlugin {
+        const NAME: &'static str = "cincinnati.plugin.Plugin";
+    }
+
+    #[tokio::test]
+    async fn call_plugin() -> Fallible<()> {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let url = format!("http://{}", listener.local_addr()?);
+        tokio::spawn(
+            tonic::transport::Server::builder()
+                .add_service(EchoPlugin)
+                .serve_with_incoming(tokio_stream::wrappers::TcpListenerStream::new(listener)),
+        );
+
+        let plugin = ExternalGrpcPlugin::try_new(
+            ExternalGrpcSettings {
+                url,
+                chunk_size: 2,
+                ..Default::default()
+            },
+            None,
+        )?;
+        assert_eq!(plugin.run_internal(input()).await?, input());
+
+        let refused = InternalIO {
+            graph: cincinnati::testing::generate_graph(true, false),
+            parameters: Default::default(),
+        };
+        assert!(plugin.run_internal(refused).await.is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn deserialize_settings() {
+        let settings = |cfg: &str| ExternalGrpcSettings::deserialize_config(toml::from_str(cfg)?);
+
+        assert!(settings(r#"url = "http://localhost:50051""#).is_ok());
+        assert!(settings(r#"url = "ftp://localhost:50051""#).is_err());
+        assert!(settings("url = \"http://localhost:50051\"\nchunk_size = 0").is_err());
+        assert!(settings(
+            "url = \"https://localhost:50051\"\nclient_cert_path = \"/etc/plugin/tls.crt\""
+        )
+        .is_err());
+    }
+}
@@ -1,3 +1,4 @@
 //! This module references the available external plugins
 
+pub mod grpc;
 pub mod web;
@@ -0,0 +1,528 @@
+//! Timeouts and circuit breaking of single plugins.
+//!
+//! A plugin talking to a slow or broken external service would otherwise
+//! stall the whole chain on every run. Any plugin can be given a run timeout,
+//! and a circuit breaker which opens after a number of consecutive failures.
+//! While open, the plugin is not run: it either returns the output of its last
+//! successful run, or fails fast. After the cooldown, the circuit is half-open:
+//! a single trial run decides whether it closes again, while other runs are
+//! still rejected.
+//!
+//! The settings are read from the configuration entry of the plugin, next to
+//! its own settings.
+
+use crate as cincinnati;
+
+use self::cincinnati::plugins::catalog::PluginSettings;
+use self::cincinnati::plugins::{BoxedPlugin, Plugin, PluginIO};
+
+use async_trait::async_trait;
+use commons::prelude_errors::*;
+use log::{debug, info, warn};
+use prometheus::{IntCounterVec, IntGaugeVec, Opts, Registry};
+use serde::Deserialize;
+use smart_default::SmartDefault;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Default time a circuit stays open before a trial run.
+pub static DEFAULT_CIRCUIT_BREAKER_COOLDOWN: Duration = Duration::from_secs(300);
+
+/// Configuration keys of the guard, removed from the plugin configuration.
+static GUARD_KEYS: &[&str] = &[
+    "run_timeout_secs",
+    "circuit_breaker_threshold",
+    "circuit_breaker_cooldown_secs",
+    "circuit_breaker_action",
+];
+
+lazy_static::lazy_static! {
+    static ref PLUGIN_TIMEOUTS: IntCounterVec = IntCounterVec::new(
+        Opts::new(
+            "plugin_timeouts_total",
+            "Total number of plugin runs which exceeded their timeout"
+        ),
+        &["plugin"]
+    )
+    .unwrap();
+    static ref PLUGIN_FAILURES: IntCounterVec = IntCounterVec::new(
+        Opts::new(
+            "plugin_failures_total",
+            "Total number of failed plugin runs, including timeouts"
+        ),
+        &["plugin"]
+    )
+    .unwrap();
+    static ref PLUGIN_REJECTED_RUNS: IntCounterVec = IntCounterVec::new(
+        Opts::new(
+            "plugin_circuit_rejected_runs_total",
+            "Total number of plugin runs skipped or failed fast because the circuit was open"
+        ),
+        &["plugin"]
+    )
+    .unwrap();
+    static ref PLUGIN_CIRCUIT_OPEN: IntGaugeVec = IntGaugeVec::new(
+        Opts::new(
+            "plugin_circuit_open",
+            "Whether the circuit breaker of a plugin is open, set to 1"
+        ),
+        &["plugin"]
+    )
+    .unwrap();
+}
+
+/// Register relevant metrics to a prometheus registry.
+///
+/// The metrics are shared by all guarded plugins, so registering them again is not an error.
+fn register_metrics(registry: &Registry) -> Fallible<()> {
+    let collectors: Vec<Box<dyn prometheus::core::Collector>> = vec![
+        Box::new(PLUGIN_TIMEOUTS.clone()),
+        Box::new(PLUGIN_FAILURES.clone()),
+        Box::new(PLUGIN_REJECTED_RUNS.clone()),
+        Box::new(PLUGIN_CIRCUIT_OPEN.clone()),
+    ];
+    for collector in collectors {
+        match registry.register(collector) {
+            Ok(()) | Err(prometheus::Error::AlreadyReg) => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(())
+}
+
+/// Behavior of a plugin while its circuit is open.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CircuitBreakerAction {
+    /// Return the output of the last successful run, failing if there is none.
+    KeepLast,
+    /// Fail without running the plugin.
+    #[default]
+    Fail,
+}
+
+/// Timeout and circuit breaker settings of a plugin.
+#[derive(Clone, Debug, Deserialize, PartialEq, SmartDefault)]
+#[serde(default)]
+pub struct GuardSettings {
+    /// Timeout of a single run, in seconds.
+    pub run_timeout_secs: Option<u64>,
+
+    /// Number of consecutive failures opening the circuit, disabled if unset.
+    pub circuit_breaker_threshold: Option<u32>,
+
+    /// Time the circuit stays open before a trial run, in seconds.
+    #[default(DEFAULT_CIRCUIT_BREAKER_COOLDOWN.as_secs())]
+    pub circuit_breaker_cooldown_secs: u64,
+
+    /// Behavior while the circuit is open.
+    pub circuit_breaker_action: CircuitBreakerAction,
+}
+
+impl GuardSettings {
+    /// Remove the guard settings from a plugin configuration entry.
+    pub fn extract(cfg: &mut toml::Value) -> Fallible<Self> {
+        let mut guard = toml::value::Table::new();
+        if let Some(table) = cfg.as_table_mut() {
+            for key in GUARD_KEYS {
+                if let Some(value) = table.remove(*key) {
+                    guard.insert(key.to_string(), value);
+                }
+            }
+        }
+        let settings: Self = toml::Value::Table(guard).try_into()?;
+
+        ensure!(
+            settings.run_timeout_secs != Some(0),
+            "run_timeout_secs must be positive"
+        );
+        ensure!(
+            settings.circuit_breaker_threshold != Some(0),
+            "circuit_breaker_threshold must be positive"
+        );
+        ensure!(
+            settings.circuit_breaker_cooldown_secs > 0,
+            "circuit_breaker_cooldown_secs must be positive"
+        );
+
+        Ok(settings)
+    }
+
+    /// Returns whether neither a timeout nor a circuit breaker is configured.
+    pub(crate) fn is_unguarded(&self) -> bool {
+        self.run_timeout_secs.is_none() && self.circuit_breaker_threshold.is_none()
+    }
+}
+
+/// Settings of a plugin along with its guard settings.
+#[derive(Debug)]
+pub struct GuardedSettings {
+    pub inner: Box<dyn PluginSettings>,
+    pub guard: GuardSettings,
+}
+
+impl PluginSettings for GuardedSettings {
+    fn build_plugin(&self, registry: Option<&Registry>) -> Fallible<BoxedPlugin> {
+        let inner = self.inner.build_plugin(registry)?;
+        if self.guard.is_unguarded() {
+            return Ok(inner);
+        }
+
+        if let Some(registry) = registry {
+            register_metrics(registry)?;
+        }
+        Ok(Box::new(GuardedPlugin::new(inner, self.guard.clone())))
+    }
+
+    fn key_prefix(&self) -> Option<&str> {
+        self.inner.key_prefix()
+    }
+}
+
+/// State of a circuit breaker.
+#[derive(Debug, Default)]
+struct Circuit {
+    consecutive_failures: u32,
+    /// Time the circuit opened at, if open.
+    opened_at: Option<Instant>,
+    /// Time the trial run started at, if half-open.
+    trial_started_at: Option<Instant>,
+    /// Output of the last successful run, only kept with `KeepLast`.
+    last_good: Option<PluginIO>,
+}
+
+/// Whether a run may go ahead.
+#[derive(Debug, PartialEq, Eq)]
+enum Admission {
+    /// The circuit is closed.
+    Run,
+    /// The circuit is half-open and this run is the trial.
+    Trial,
+    /// The circuit is open, or half-open with a trial in progress.
+    Reject,
+}
+
+/// Plugin wrapper enforcing a timeout and a circuit breaker.
+#[derive(Debug)]
+pub struct GuardedPlugin {
+    inner: BoxedPlugin,
+    settings: GuardSettings,
+    circuit: Mutex<Circuit>,
+}
+
+impl GuardedPlugin {
+    fn new(inner: BoxedPlugin, settings: GuardSettings) -> Self {
+        Self {
+            inner,
+            settings,
+            circuit: Default::default(),
+        }
+    }
+
+    /// Decide whether a run may go ahead, starting the trial run once the cooldown is over.
+    ///
+    /// A trial which didn't record its outcome within a cooldown, e.g. because
+    /// its run was cancelled, is given up and another one may start.
+    fn admit(&self) -> Fallible<Admission> {
+        let mut circuit = self.circuit.lock().map_err(|e| format_err!("{}", e))?;
+        let cooldown = Duration::from_secs(self.settings.circuit_breaker_cooldown_secs);
+        let opened_at = match circuit.opened_at {
+            None => return Ok(Admission::Run),
+            Some(opened_at) => opened_at,
+        };
+        let trial_pending = circuit
+            .trial_started_at
+            .is_some_and(|started_at| started_at.elapsed() < cooldown);
+        if opened_at.elapsed() < cooldown || trial_pending {
+            return Ok(Admission::Reject);
+        }
+        circuit.trial_started_at = Some(Instant::now());
+        Ok(Admission::Trial)
+    }
+
+    /// Returns the output of the last successful run, if kept.
+    fn last_good(&self) -> Fallible<Option<PluginIO>> {
+        let circuit = self.circuit.lock().map_err(|e| format_err!("{}", e))?;
+        Ok(circuit.last_good.clone())
+    }
+
+    /// Record the outcome of a run, opening or closing the circuit.
+    fn record(&self, result: &Fallible<PluginIO>) -> Fallible<()> {
+        let name = self.inner.get_name();
+        let mut circuit = self.circuit.lock().map_err(|e| format_err!("{}", e))?;
+        circuit.trial_started_at = None;
+        if let Ok(output) = result {
+            if circuit.opened_at.take().is_some() {
+                info!("closing the circuit of plugin '{}'", name);
+            }
+            circuit.consecutive_failures = 0;
+            if self.settings.circuit_breaker_action == CircuitBreakerAction::KeepLast {
+                circuit.last_good = Some(output.clone());
+            }
+        } else {
+            PLUGIN_FAILURES.with_label_values(&[name]).inc();
+            circuit.consecutive_failures = circuit.consecutive_failures.saturating_add(1);
+            let threshold = self.settings.circuit_breaker_threshold;
+            if threshold.is_some_and(|threshold| circuit.consecutive_failures >= threshold) {
+                if circuit.opened_at.is_none() {
+                    warn!(
+                        "opening the circuit of plugin '{}' after {} consecutive failures",
+                        name, circuit.consecutive_failures
+                    );
+                }
+                circuit.opened_at = Some(Instant::now());
+            }
+        }
+        PLUGIN_CIRCUIT_OPEN
+            .with_label_values(&[name])
+            .set(circuit.opened_at.is_some() as i64);
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Plugin<PluginIO> for GuardedPlugin {
+    async fn run(&self, io: PluginIO) -> Fallible<PluginIO> {
+        let name = self.inner.get_name();
+
+        match self.admit()? {
+            Admission::Run => {}
+            Admission::Trial => info!("trial run of plugin '{}' with a half-open circuit", name),
+            Admission::Reject => {
+                PLUGIN_REJECTED_RUNS.with_label_values(&[name]).inc();
+                return match self.settings.circuit_breaker_action {
+                    CircuitBreakerAction::KeepLast => match self.last_good()? {
+                        Some(output) => {
+                            debug!(
+                                "circuit of plugin '{}' is open, returning its last good output",
+                                name
+                            );
+                            Ok(output)
+                        }
+                        None => bail!(
+                            "circuit of plugin '{}' is open and it never succeeded, failing fast",
+                            name
+                        ),
+                    },
+                    CircuitBreakerAction::Fail => {
+                        bail!("circuit of plugin '{}' is open, failing fast", name)
+                    }
+                };
+            }
+        }
+
+        let result = match self.settings.run_timeout_secs.map(Duration::from_secs) {
+            None => self.inner.run(io).await,
+            Some(timeout) => match tokio::time::timeout(timeout, self.inner.run(io)).await {
+                Ok(result) => result,
+                Err(_) => {
+                    PLUGIN_TIMEOUTS.with_label_values(&[name]).inc();
+                    Err(format_err!(
+                        "plugin '{}' exceeded its timeout of {:?}",
+                        name,
+                        timeout
+                    ))
+                }
+            },
+        };
+
+        self.record(&result)?;
+        result
+    }
+
+    fn get_name(&self) -> &'static str {
+        self.inner.get_name()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plugins::{InternalIO, InternalPlugin, InternalPluginWrapper};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    /// Plugin failing or hanging on the runs following its first successes.
+    ///
+    /// Successful runs record their index in the `run` parameter.
+    #[derive(Debug, Default)]
+    struct FlakyPlugin {
+        runs: Arc<AtomicUsize>,
+        successes: usize,
+        failures: usize,
+        hang: bool,
+    }
+
+    #[async_trait]
+    impl InternalPlugin for FlakyPlugin {
+        const PLUGIN_NAME: &'static str = "flaky";
+
+        async fn run_internal(&self, mut io: InternalIO) -> Fallible<InternalIO> {
+            let run = self.runs.fetch_add(1, Ordering::SeqCst);
+            if run < self.successes || run >= self.successes + self.failures {
+                io.parameters.insert("run".to_string(), run.to_string());
+                return Ok(io);
+            }
+            if self.hang {
+                futures::future::pending::<()>().await;
+            }
+            bail!("flaky failure")
+        }
+    }
+
+    fn guarded(plugin: FlakyPlugin, settings: GuardSettings) -> GuardedPlugin {
+        GuardedPlugin::new(Box::new(InternalPluginWrapper(plugin)), settings)
+    }
+
+    fn io() -> PluginIO {
+        PluginIO::InternalIO(InternalIO {
+            graph: Default::default(),
+            parameters: Default::default(),
+        })
+    }
+
+    fn run_parameter(io: PluginIO) -> Option<String> {
+        match io {
+            PluginIO::InternalIO(io) => io.parameters.get("run").cloned(),
+            PluginIO::ExternalIO(_) => None,
+        }
+    }
+
+    #[test]
+    fn extract_settings() -> Fallible<()> {
+        let mut cfg: toml::Value = toml::from_str(
+            r#"
+            name = "release-enrichment-hook"
+            timeout_secs = 10
+            run_timeout_secs = 60
+            circuit_breaker_threshold = 3
+            circuit_breaker_action = "keep_last"
+            "#,
+        )?;
+        let settings = GuardSettings::extract(&mut cfg)?;
+        assert_eq!(
+            settings,
+            GuardSettings {
+                run_timeout_secs: Some(60),
+                circuit_breaker_threshold: Some(3),
+                circuit_breaker_action: CircuitBreakerAction::KeepLast,
+                ..Default::default()
+            }
+        );
+        assert_eq!(cfg.get("timeout_secs"), Some(&toml::Value::Integer(10)));
+        assert!(cfg.get("run_timeout_secs").is_none());
+
+        let mut cfg: toml::Value = toml::from_str("run_timeout_secs = 0")?;
+        assert!(GuardSettings::extract(&mut cfg).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn circuit_breaker() -> Fallible<()> {
+        let rt = commons::testing::init_runtime()?;
+        let runs = Arc::new(AtomicUsize::new(0));
+        let plugin = guarded(
+            FlakyPlugin {
+                runs: runs.clone(),
+                successes: 1,
+                failures: 3,
+                ..Default::default()
+            },
+            GuardSettings {
+                circuit_breaker_threshold: Some(2),
+                circuit_breaker_action: CircuitBreakerAction::KeepLast,
+                ..Default::default()
+            },
+        );
+
+        assert_eq!(
+            run_parameter(rt.block_on(plugin.run(io()))?),
+            Some("0".into())
+        );
+        assert!(rt.block_on(plugin.run(io())).is_err());
+        assert!(rt.block_on(plugin.run(io())).is_err());
+        // The circuit is open, the output of the last successful run is returned.
+        assert_eq!(
+            run_parameter(rt.block_on(plugin.run(io()))?),
+            Some("0".into())
+        );
+        assert_eq!(runs.load(Ordering::SeqCst), 3);
+
+        // After the cooldown, the circuit is half-open and runs are rejected
+        // while a trial is in progress.
+        let expired = Instant::now() - DEFAULT_CIRCUIT_BREAKER_COOLDOWN;
+        plugin.circuit.lock().unwrap().opened_at = Some(expired);
+        assert_eq!(plugin.admit()?, Admission::Trial);
+        assert_eq!(plugin.admit()?, Admission::Reject);
+
+        // A failed trial opens the circuit for another cooldown.
+        plugin.circuit.lock().unwrap().trial_started_at = None;
+        assert!(rt.block_on(plugin.run(io())).is_err());
+        assert_eq!(runs.load(Ordering::SeqCst), 4);
+        assert_eq!(plugin.admit()?, Admission::Reject);
+
+        // A successful trial closes the circuit.
+        plugin.circuit.lock().unwrap().opened_at = Some(expired);
+        assert_eq!(
+            run_parameter(rt.block_on(plugin.run(io()))?),
+            Some("4".into())
+        );
+        assert!(plugin.circuit.lock().unwrap().opened_at.is_none());
+        assert_eq!(plugin.admit()?, Admission::Run);
+
+        let plugin = guarded(
+            FlakyPlugin {
+                failures: 1,
+                ..Default::default()
+            },
+            GuardSettings {
+                circuit_breaker_threshold: Some(1),
+                ..Default::default()
+            },
+        );
+        assert!(rt.block_on(plugin.run(io())).is_err());
+        let failed_fast = rt.block_on(plugin.run(io())).unwrap_err();
+        assert!(failed_fast.to_string().contains("failing fast"));
+
+        // Without a successful run, there is no output to keep.
+        let plugin = guarded(
+            FlakyPlugin {
+                failures: 1,
+                ..Default::default()
+            },
+            GuardSettings {
+                circuit_breaker_threshold: Some(1),
+                circuit_breaker_action: CircuitBreakerAction::KeepLast,
+                ..Default::default()
+            },
+        );
+        assert!(rt.block_on(plugin.run(io())).is_err());
+        let failed_fast = rt.block_on(plugin.run(io())).unwrap_err();
+        assert!(failed_fast.to_string().contains("never succeeded"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn run_timeout() -> Fallible<()> {
+        let rt = commons::testing::init_runtime()?;
+        let plugin = guarded(
+            FlakyPlugin {
+                failures: 1,
+                hang: true,
+                ..Default::default()
+            },
+            GuardSettings {
+                run_timeout_secs: Some(1),
+                ..Default::default()
+            },
+        );
+
+        let timed_out = rt.block_on(plugin.run(io())).unwrap_err();
+        assert!(timed_out.to_string().contains("exceeded its timeout"));
+        assert!(rt.block_on(plugin.run(io())).is_ok());
+
+        Ok(())
+    }
+}
@@ -13,9 +13,13 @@ use self::cincinnati::plugins::prelude_plugin_impl::*;
 
 use commons::GraphError;
 use lazy_static::lazy_static;
+use std::collections::{BTreeSet, HashSet};
 
 pub static DEFAULT_KEY_FILTER: &str = "io.openshift.upgrades.graph";
 pub static DEFAULT_ARCH_KEY: &str = "release.arch";
+pub static DEFAULT_ARCHITECTURES_KEY: &str = "release.architectures";
+/// Architecture of the releases published as a multi-arch image index.
+pub static MULTI_ARCH: &str = "multi";
 pub static DEFAULT_DEFAULT_ARCH: &str = "amd64";
 pub static DEFAULT_DEFAULT_ARCH_THRESHOLD_VERSION: &str = "4.2.0-rc.0";
 
@@ -28,6 +32,10 @@ pub struct ArchFilterPlugin {
     #[default(DEFAULT_ARCH_KEY.to_string())]
     pub key_suffix: String,
 
+    /// Suffix of the key listing the architectures of multi-arch image indexes.
+    #[default(DEFAULT_ARCHITECTURES_KEY.to_string())]
+    pub architectures_key_suffix: String,
+
     #[default(DEFAULT_DEFAULT_ARCH.to_string())]
     pub default_arch: String,
 }
@@ -36,6 +44,10 @@ impl PluginSettings for ArchFilterPlugin {
     fn build_plugin(&self, _: Option<&prometheus::Registry>) -> Fallible<BoxedPlugin> {
         Ok(new_plugin!(InternalPluginWrapper(self.clone())))
     }
+
+    fn key_prefix(&self) -> Option<&str> {
+        Some(&self.key_prefix)
+    }
 }
 
 impl ArchFilterPlugin {
@@ -48,6 +60,10 @@ impl ArchFilterPlugin {
 
         ensure!(!plugin.key_prefix.is_empty(), "empty arch-key prefix");
         ensure!(!plugin.key_suffix.is_empty(), "empty arch-key suffix");
+        ensure!(
+            !plugin.architectures_key_suffix.is_empty(),
+            "empty architectures-key suffix"
+        );
 
         Ok(Box::new(plugin))
     }
@@ -59,22 +75,13 @@ impl ArchFilterPlugin {
         ce: &mut Vec<cincinnati::ConditionalEdge>,
         arch: String,
     ) -> Fallible<i32> {
-        let mut edges_removed: usize = 0;
-        ce.iter_mut()
-            .for_each(|ce: &mut cincinnati::ConditionalEdge| {
-                let total_edges = ce.edges.len();
-                ce.edges
-                    .retain(|e| e.from.contains(&arch) && e.to.contains(&arch));
-                edges_removed += total_edges - ce.edges.len();
-            });
-        ce.retain(|ce| !ce.edges.is_empty());
-        Ok(edges_removed as i32)
+        Ok(retain_conditional_edges(ce, &arch) as i32)
     }
 
     /// Remove the arch identifier from the conditional edges.
     pub fn rm_conditional_edges_arch_identifier(
         &self,
-        vce: &mut Vec<cincinnati::ConditionalEdge>,
+        vce: &mut [cincinnati::ConditionalEdge],
         arch: String,
     ) -> Fallible<()> {
         vce.iter_mut()
@@ -90,7 +97,7 @@ impl ArchFilterPlugin {
 
     /// gets the string and arch identifier and removes the arch identifier from the string
     fn remove_arch_info(&self, v: &str, arch: String) -> Result<String, Error> {
-        semver::Version::parse(v)
+        crate::semver_cache::parse_version(v)
             .context(v.to_string())
             .map(|mut version| {
                 version.build.retain(|elem| elem.to_string() != arch);
@@ -100,6 +107,151 @@ impl ArchFilterPlugin {
     }
 }
 
+/// Remove all conditional edges which don't contain the arch identifier.
+///
+/// Returns the number of removed edges.
+fn retain_conditional_edges(ce: &mut Vec<cincinnati::ConditionalEdge>, arch: &str) -> usize {
+    let mut edges_removed: usize = 0;
+    ce.iter_mut()
+        .for_each(|ce: &mut cincinnati::ConditionalEdge| {
+            let total_edges = ce.edges.len();
+            ce.edges
+                .retain(|e| e.from.contains(arch) && e.to.contains(arch));
+            edges_removed += total_edges - ce.edges.len();
+        });
+    ce.retain(|ce| !ce.edges.is_empty());
+    edges_removed
+}
+
+/// Metadata keys of the architecture of a release.
+#[derive(Clone, Debug)]
+pub struct ArchKeys {
+    /// Key of the architecture, or architectures, of the release, `multi` for image indexes.
+    pub arch: String,
+    /// Key listing the architectures of the images of an image index.
+    pub architectures: String,
+}
+
+impl ArchKeys {
+    /// Keys with the given prefix and the default suffixes.
+    pub fn with_prefix(prefix: &str) -> Self {
+        Self {
+            arch: format!("{}.{}", prefix, DEFAULT_ARCH_KEY),
+            architectures: format!("{}.{}", prefix, DEFAULT_ARCHITECTURES_KEY),
+        }
+    }
+
+    /// Returns the architectures a release is published as.
+    ///
+    /// Those are the values of the arch key, e.g. `amd64` or `multi`.
+    pub fn release_archs<'a>(&self, release: &'a cincinnati::Release) -> Vec<&'a str> {
+        release_metadata(release)
+            .and_then(|metadata| metadata.get(self.arch.as_str()))
+            .map(|values| split_archs(values))
+            .unwrap_or_default()
+    }
+
+    /// Returns the architectures a release can be installed on.
+    ///
+    /// Those are the architectures it is published as, with the architectures
+    /// of its images for a multi-arch image index.
+    pub fn covered_archs<'a>(&self, release: &'a cincinnati::Release) -> Vec<&'a str> {
+        let mut archs = self.release_archs(release);
+        if archs.contains(&MULTI_ARCH) {
+            archs.extend(
+                release_metadata(release)
+                    .and_then(|metadata| metadata.get(self.architectures.as_str()))
+                    .map(|values| split_archs(values))
+                    .unwrap_or_default(),
+            );
+        }
+        archs
+    }
+}
+
+fn release_metadata(
+    release: &cincinnati::Release,
+) -> Option<&cincinnati::MapImpl<cincinnati::IStr, cincinnati::IStr>> {
+    match release {
+        cincinnati::Release::Concrete(release) => Some(&release.metadata),
+        _ => None,
+    }
+}
+
+fn split_archs(values: &str) -> Vec<&str> {
+    values
+        .split(',')
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+        .collect()
+}
+
+/// Returns the architectures of all releases, including those of the images of
+/// multi-arch image indexes and `multi` itself.
+pub fn architectures(graph: &cincinnati::Graph, keys: &ArchKeys) -> BTreeSet<String> {
+    graph
+        .iter_releases()
+        .flat_map(|release| keys.covered_archs(release))
+        .map(str::to_string)
+        .collect()
+}
+
+/// Remove all releases which can't be installed on `arch`, along with their conditional edges.
+///
+/// Releases published as `arch` are kept, and so are multi-arch image indexes
+/// with an image of `arch` for versions without such a release. Releases
+/// without architecture are removed. Returns the number of removed releases.
+pub fn retain_arch(graph: &mut cincinnati::Graph, keys: &ArchKeys, arch: &str) -> usize {
+    let base_version = |release: &cincinnati::Release| -> String {
+        release
+            .version()
+            .split('+')
+            .next()
+            .unwrap_or_default()
+            .to_string()
+    };
+    let published: HashSet<String> = graph
+        .iter_releases()
+        .filter(|release| keys.release_archs(release).contains(&arch))
+        .map(base_version)
+        .collect();
+
+    let to_remove = graph
+        .find_by_fn(|release| {
+            if keys.release_archs(release).contains(&arch) {
+                return false;
+            }
+            !keys.covered_archs(release).contains(&arch)
+                || published.contains(&base_version(release))
+        })
+        .into_iter()
+        .map(|(release_id, version)| {
+            trace!("queuing '{}' for removal", version);
+            release_id
+        })
+        .collect();
+
+    let removed = graph.remove_releases(to_remove);
+
+    let versions: HashSet<String> = graph
+        .iter_releases()
+        .map(|release| release.version().to_string())
+        .collect();
+    if let Some(ce) = graph.conditional_edges.as_mut() {
+        let mut removed_ce: usize = 0;
+        ce.iter_mut().for_each(|ce| {
+            let total_edges = ce.edges.len();
+            ce.edges
+                .retain(|e| versions.contains(&e.from) && versions.contains(&e.to));
+            removed_ce += total_edges - ce.edges.len();
+        });
+        ce.retain(|ce| !ce.edges.is_empty());
+        trace!("removed {} conditional edges", removed_ce);
+    }
+
+    removed
+}
+
 /// Evaluate an architecture from the given "arch" parameters.
 fn infer_arch(arch: Option<String>, default_arch: String) -> Result<String, GraphError> {
     match arch {
@@ -142,50 +294,27 @@ impl InternalPlugin for ArchFilterPlugin {
         )?;
 
         let mut graph = internal_io.graph;
-
-        // iterate over all releases attempt to remove the arch metadata key
-        // 1. if it exists, keep every release which matches the given `arch`
-        // 2. collect all other releases to be removed
-        let to_remove = {
-            graph
-                .find_by_fn_mut(|release| {
-                    match release {
-                        cincinnati::Release::Concrete(concrete_release) => concrete_release
-                            .metadata
-                            .remove(&format!("{}.{}", self.key_prefix, self.key_suffix))
-                            .map_or(true, |values| {
-                                !values.split(',').any(|value| value.trim() == arch)
-                            }),
-                        // remove if it's not a ConcreteRelease
-                        _ => true,
-                    }
-                })
-                .into_iter()
-                .map(|(release_id, version)| {
-                    trace!("queuing '{}' for removal", version);
-                    release_id
-                })
-                .collect()
+        let keys = ArchKeys {
+            arch: format!("{}.{}", self.key_prefix, self.key_suffix),
+            architectures: format!("{}.{}", self.key_prefix, self.architectures_key_suffix),
         };
 
-        // remove all matches from the Graph
-        let removed = graph.remove_releases(to_remove);
-        let removed_ce: i32 =
-            self.remove_conditional_edges(graph.conditional_edges.as_mut().unwrap(), arch.clone())?;
-
-        trace!(
-            "removed {} releases and {} conditional edges",
-            removed,
-            removed_ce
-        );
+        // keep every release which can be installed on the given `arch`
+        let removed = retain_arch(&mut graph, &keys, &arch);
+        trace!("removed {} releases", removed);
 
-        // remove the build suffix from the version
+        // remove the arch metadata keys and the build suffix from the version
         graph
             .iter_releases_mut(|mut release| {
+                if let Some(metadata) = release.get_metadata_mut() {
+                    metadata.remove(keys.arch.as_str());
+                    metadata.remove(keys.architectures.as_str());
+                }
+
                 let version = {
                     let release_version = release.version().to_owned();
 
-                    semver::Version::parse(&release_version)
+                    crate::semver_cache::parse_version(&release_version)
                         .context(release_version.clone())
                         .map(|mut version| {
                             version.build.retain(|elem| elem.to_string() != arch);
@@ -195,8 +324,8 @@ impl InternalPlugin for ArchFilterPlugin {
                 };
 
                 match &mut release {
-                    cincinnati::Release::Abstract(release) => release.version = version,
-                    cincinnati::Release::Concrete(release) => release.version = version,
+                    cincinnati::Release::Abstract(release) => release.version = version.into(),
+                    cincinnati::Release::Concrete(release) => release.version = version.into(),
                 };
 
                 Ok(())
@@ -283,6 +412,7 @@ mod tests {
         let plugin = Box::new(ArchFilterPlugin {
             key_prefix: "release".to_string(),
             key_suffix: "arch".to_string(),
+            architectures_key_suffix: "architectures".to_string(),
             default_arch: "amd64".to_string(),
         });
         let future_processed_graph = plugin.run_internal(InternalIO {
@@ -300,6 +430,69 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn arch_subgraph_keeps_metadata() {
+        let keys = ArchKeys {
+            arch: "release.arch".to_string(),
+            architectures: "release.architectures".to_string(),
+        };
+        let arch_metadata = |arch: &str| {
+            [
+                (String::from("version_suffix"), format!("+{}", arch)),
+                (String::from("release.arch"), arch.to_string()),
+            ]
+            .iter()
+            .cloned()
+            .collect()
+        };
+        let multi_metadata = || {
+            let mut metadata: cincinnati::MapImpl<String, String> = arch_metadata("multi");
+            metadata.insert(
+                String::from("release.architectures"),
+                String::from("amd64,arm64"),
+            );
+            metadata
+        };
+        let input_metadata: TestMetadata = vec![
+            (0, arch_metadata("amd64")),
+            (1, arch_metadata("amd64")),
+            (2, arch_metadata("arm64")),
+            (2, multi_metadata()),
+            (3, multi_metadata()),
+        ];
+        let graph = generate_custom_graph("image", input_metadata, Some(vec![(0, 1)]));
+        let versions = |graph: &cincinnati::Graph| {
+            let mut versions: Vec<String> = graph
+                .iter_releases()
+                .map(|release| release.version().to_string())
+                .collect();
+            versions.sort();
+            versions
+        };
+
+        assert_eq!(
+            architectures(&graph, &keys).into_iter().collect::<Vec<_>>(),
+            vec!["amd64", "arm64", "multi"]
+        );
+
+        let mut amd64 = graph.clone();
+        assert_eq!(retain_arch(&mut amd64, &keys, "amd64"), 1);
+        assert_eq!(amd64.edges_count(), 1);
+        assert_eq!(
+            versions(&amd64),
+            vec!["0.0.0+amd64", "1.0.0+amd64", "2.0.0+multi", "3.0.0+multi"]
+        );
+
+        // Single-arch releases are preferred over image indexes of the same version.
+        let mut arm64 = graph.clone();
+        assert_eq!(retain_arch(&mut arm64, &keys, "arm64"), 3);
+        assert_eq!(versions(&arm64), vec!["2.0.0+arm64", "3.0.0+multi"]);
+
+        let mut multi = graph;
+        assert_eq!(retain_arch(&mut multi, &keys, MULTI_ARCH), 3);
+        assert_eq!(versions(&multi), vec!["2.0.0+multi", "3.0.0+multi"]);
+    }
+
     #[test]
     fn ensure_infer_arch() -> Fallible<()> {
         // (arch, default_arch), expecteded_arch
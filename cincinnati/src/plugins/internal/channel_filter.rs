@@ -11,8 +11,10 @@ use self::cincinnati::plugins::prelude_plugin_impl::*;
 use commons::GraphError;
 use lazy_static::lazy_static;
 
-static DEFAULT_KEY_FILTER: &str = "io.openshift.upgrades.graph";
-static DEFAULT_CHANNEL_KEY: &str = "release.channels";
+/// Default prefix of the channels metadata key.
+pub static DEFAULT_KEY_FILTER: &str = "io.openshift.upgrades.graph";
+/// Default suffix of the channels metadata key.
+pub static DEFAULT_CHANNEL_KEY: &str = "release.channels";
 
 #[derive(Clone, Debug, Deserialize, SmartDefault)]
 #[serde(default)]
@@ -28,6 +30,10 @@ impl PluginSettings for ChannelFilterPlugin {
     fn build_plugin(&self, _: Option<&prometheus::Registry>) -> Fallible<BoxedPlugin> {
         Ok(new_plugin!(InternalPluginWrapper(self.clone())))
     }
+
+    fn key_prefix(&self) -> Option<&str> {
+        Some(&self.key_prefix)
+    }
 }
 
 impl ChannelFilterPlugin {
@@ -91,14 +97,15 @@ impl InternalPlugin for ChannelFilterPlugin {
         let mut graph = internal_io.graph;
         let mut releases_version: HashSet<String> = HashSet::new();
 
+        let channel_key = format!("{}.{}", self.key_prefix, self.key_suffix);
         let to_remove: Vec<ReleaseId> = {
             graph
-                .find_by_fn_mut(|release| {
+                .find_by_fn(|release| {
                     match release {
                         cincinnati::Release::Concrete(concrete_release) => concrete_release
                             .metadata
-                            .get_mut(&format!("{}.{}", self.key_prefix, self.key_suffix))
-                            .map_or(true, |values| {
+                            .get(channel_key.as_str())
+                            .is_none_or(|values| {
                                 !values.split(',').any(|value| value.trim() == channel)
                             }),
                         // remove if it's not a ConcreteRelease
@@ -151,9 +158,11 @@ mod tests {
             key_suffix: "".to_string(),
         });
 
+        type AssertFn = Box<dyn Fn(&Fallible<InternalIO>)>;
+
         struct Datum {
             channels: std::vec::Vec<&'static str>,
-            assert_fn: Box<dyn Fn(&Fallible<InternalIO>)>,
+            assert_fn: AssertFn,
         }
 
         for datum in &mut [
@@ -2,14 +2,25 @@
 //!
 //! Instead of processing the input graph, this plugin fetches a graph from a
 //! remote endpoint, which makes it effectively discard any given input graph.
+//!
+//! The graph can alternatively be fetched as binary artifact from the internal
+//! endpoint of graph-builder, which requires a client certificate.
+//!
+//! Several upstreams can be configured, in order of preference: an upstream
+//! which is unreachable, fails or serves a stale graph is skipped for a while,
+//! and the next one is used instead. Upstreams are probed concurrently, so
+//! that slow ones don't add up.
 
 use crate as cincinnati;
 
+use self::cincinnati::plugins::internal::release_scrape_dockerv2::registry;
 use self::cincinnati::plugins::prelude::*;
 use self::cincinnati::plugins::prelude_plugin_impl::*;
-use self::cincinnati::CONTENT_TYPE;
+use self::cincinnati::provenance::{self, Provenance};
+use self::cincinnati::{artifact, CONTENT_TYPE};
 
 use commons::prelude_errors::*;
+use commons::request_id;
 use commons::tracing::{get_tracer, set_context};
 use opentelemetry::{
     trace::{get_active_span, mark_span_as_active, Tracer},
@@ -17,12 +28,15 @@ use opentelemetry::{
 };
 
 use cached::{proc_macro::cached, Return};
+use chrono::{DateTime, Utc};
 use commons::prelude_errors::Context;
 use commons::GraphError;
-use prometheus::Counter;
+use prometheus::{Counter, IntGaugeVec, Opts};
 use reqwest;
 use reqwest::header::{HeaderMap, HeaderValue, ACCEPT};
-use std::time::Duration;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
 
 /// Default URL to upstream graph provider.
 pub static DEFAULT_UPSTREAM_URL: &str = "http://localhost:8080/graph";
@@ -30,6 +44,20 @@ pub static DEFAULT_UPSTREAM_URL: &str = "http://localhost:8080/graph";
 /// Default graph-builder connection timeout in seconds.
 pub static DEFAULT_TIMEOUT_SECS: u64 = 30;
 
+/// Default duration for which a failed upstream is skipped, in seconds.
+pub static DEFAULT_RETRY_AFTER_SECS: u64 = 30;
+
+/// Representation of the graph served by the upstream.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, SmartDefault)]
+#[serde(rename_all = "lowercase")]
+pub enum UpstreamFormat {
+    /// Public JSON graph, as served on `/graph`.
+    #[default]
+    Json,
+    /// Binary graph artifact, as served by graph-builder on `/internal/v1/graph`.
+    Artifact,
+}
+
 /// Plugin settings.
 #[derive(Clone, CustomDebug, Deserialize, SmartDefault)]
 #[serde(default)]
@@ -37,16 +65,88 @@ struct CincinnatiGraphFetchSettings {
     #[default(DEFAULT_UPSTREAM_URL.to_string())]
     upstream: String,
 
+    /// Upstreams in order of preference, replacing `upstream` if not empty.
+    upstreams: Vec<String>,
+
+    /// Time since the last successful scrape of an upstream, in seconds, after which the next upstream is preferred.
+    max_staleness_secs: Option<u64>,
+
+    /// Duration for which an upstream is skipped after a failure, in seconds.
+    #[default(DEFAULT_RETRY_AFTER_SECS)]
+    retry_after_secs: u64,
+
     #[default(DEFAULT_TIMEOUT_SECS)]
     timeout: u64,
+
+    format: UpstreamFormat,
+
+    /// PEM file with the client certificate presented to the upstream.
+    client_cert_path: Option<PathBuf>,
+
+    /// PEM file with the PKCS#8 private key of the client certificate.
+    client_key_path: Option<PathBuf>,
+
+    /// PEM file with CA certificates to trust for the upstream.
+    ca_bundle_path: Option<PathBuf>,
+}
+
+impl CincinnatiGraphFetchSettings {
+    /// Returns the upstreams in order of preference.
+    fn upstreams(&self) -> Vec<String> {
+        if self.upstreams.is_empty() {
+            vec![self.upstream.clone()]
+        } else {
+            self.upstreams.clone()
+        }
+    }
+}
+
+/// An upstream along with its health.
+#[derive(Debug)]
+struct Upstream {
+    url: String,
+    /// Time until which the upstream is skipped, after a failure.
+    unhealthy_until: Mutex<Option<Instant>>,
+}
+
+impl Upstream {
+    fn new(url: String) -> Self {
+        Self {
+            url,
+            unhealthy_until: Mutex::new(None),
+        }
+    }
+
+    fn is_healthy(&self, now: Instant) -> bool {
+        match *self.unhealthy_until.lock().unwrap() {
+            Some(until) => until <= now,
+            None => true,
+        }
+    }
+
+    fn set_unhealthy_until(&self, until: Option<Instant>) {
+        *self.unhealthy_until.lock().unwrap() = until;
+    }
 }
 
 /// Graph fetcher for Cincinnati `/graph` endpoints.
 #[derive(CustomDebug)]
 pub struct CincinnatiGraphFetchPlugin {
-    /// The upstream from which to fetch the graph
+    /// The preferred upstream from which to fetch the graph
     pub upstream: String,
 
+    /// All upstreams, in order of preference
+    upstreams: Vec<Upstream>,
+
+    /// The time since the last successful scrape after which a graph is stale
+    max_staleness: Option<Duration>,
+
+    /// The duration for which a failed upstream is skipped
+    retry_after: Duration,
+
+    /// The representation of the graph served by the upstream
+    pub format: UpstreamFormat,
+
     /// The optional metric for counting upstream requests
     #[debug(skip)]
     pub http_upstream_reqs: Counter,
@@ -55,14 +155,17 @@ pub struct CincinnatiGraphFetchPlugin {
     #[debug(skip)]
     pub http_upstream_errors_total: Counter,
 
+    /// The optional metric flagging the upstream the graph was last fetched from
+    #[debug(skip)]
+    pub http_upstream_active: IntGaugeVec,
+
     // graph-builder connection client
     client: reqwest::Client,
 }
 
 impl PluginSettings for CincinnatiGraphFetchSettings {
     fn build_plugin(&self, registry: Option<&prometheus::Registry>) -> Fallible<BoxedPlugin> {
-        let cfg = self.clone();
-        let plugin = CincinnatiGraphFetchPlugin::try_new(cfg.upstream, cfg.timeout, registry)?;
+        let plugin = CincinnatiGraphFetchPlugin::try_from_settings(self, registry)?;
         Ok(new_plugin!(InternalPluginWrapper(plugin)))
     }
 }
@@ -75,15 +178,42 @@ impl CincinnatiGraphFetchPlugin {
     pub fn deserialize_config(cfg: toml::Value) -> Fallible<Box<dyn PluginSettings>> {
         let settings: CincinnatiGraphFetchSettings = cfg.try_into()?;
 
-        ensure!(!settings.upstream.is_empty(), "empty upstream");
+        ensure!(
+            settings
+                .upstreams()
+                .iter()
+                .all(|upstream| !upstream.is_empty()),
+            "empty upstream"
+        );
+        ensure!(
+            settings.max_staleness_secs != Some(0),
+            "max_staleness_secs must be positive"
+        );
+        ensure!(
+            settings.client_cert_path.is_some() == settings.client_key_path.is_some(),
+            "client_cert_path and client_key_path must be set together"
+        );
 
         Ok(Box::new(settings))
     }
 
+    #[cfg(test)]
     fn try_new(
         upstream: String,
         timeout: u64,
         prometheus_registry: Option<&prometheus::Registry>,
+    ) -> Fallible<Self> {
+        let settings = CincinnatiGraphFetchSettings {
+            upstream,
+            timeout,
+            ..Default::default()
+        };
+        Self::try_from_settings(&settings, prometheus_registry)
+    }
+
+    fn try_from_settings(
+        settings: &CincinnatiGraphFetchSettings,
+        prometheus_registry: Option<&prometheus::Registry>,
     ) -> Fallible<Self> {
         let http_upstream_reqs = Counter::new(
             "http_upstream_requests_total",
@@ -95,41 +225,88 @@ impl CincinnatiGraphFetchPlugin {
             "Total number of HTTP upstream unreachable errors",
         )?;
 
+        let http_upstream_active = IntGaugeVec::new(
+            Opts::new(
+                "http_upstream_active",
+                "Whether the graph was last fetched from this upstream",
+            ),
+            &["upstream"],
+        )?;
+        let upstreams = settings.upstreams();
+        for upstream in &upstreams {
+            http_upstream_active.with_label_values(&[upstream]).set(0);
+        }
+
         if let Some(registry) = &prometheus_registry {
             registry.register(Box::new(http_upstream_reqs.clone()))?;
             registry.register(Box::new(http_upstream_errors_total.clone()))?;
+            registry.register(Box::new(http_upstream_active.clone()))?;
         };
 
-        let client = reqwest::ClientBuilder::new()
+        let mut builder = commons::outbound::client_builder()
             .gzip(true)
-            .timeout(Duration::from_secs(timeout))
-            .build()
-            .context("Building reqwest client")?;
+            .timeout(Duration::from_secs(settings.timeout));
+        if let Some(ca_bundle_path) = &settings.ca_bundle_path {
+            for certificate in registry::read_ca_bundle(ca_bundle_path)? {
+                builder = builder.add_root_certificate(certificate);
+            }
+        }
+        if let (Some(cert_path), Some(key_path)) =
+            (&settings.client_cert_path, &settings.client_key_path)
+        {
+            builder = builder.identity(registry::read_identity(cert_path, key_path)?);
+        }
+        let client = builder.build().context("Building reqwest client")?;
 
         Ok(Self {
-            upstream,
+            upstream: upstreams[0].clone(),
+            upstreams: upstreams.into_iter().map(Upstream::new).collect(),
+            max_staleness: settings.max_staleness_secs.map(Duration::from_secs),
+            retry_after: Duration::from_secs(settings.retry_after_secs),
+            format: settings.format,
             http_upstream_reqs,
             http_upstream_errors_total,
+            http_upstream_active,
             client,
         })
     }
 }
 
-// Cache successful responses, ignoring input, invalidating after 60 seconds
+/// A graph fetched from the upstream.
+#[derive(Clone, Debug)]
+struct FetchedGraph {
+    graph: crate::Graph,
+    /// Provenance of the graph, as announced by the upstream.
+    provenance: Option<Provenance>,
+    /// Time of the last successful scrape of the upstream, if announced.
+    refreshed: Option<DateTime<Utc>>,
+}
+
+// Cache successful responses of the upstreams, ignoring input, invalidating
+// after 60 seconds. The cache isn't locked while fetching, so that requests
+// don't queue up behind slow upstreams.
 #[cached(
     size = 1,
     time = 60,
-    key = "bool",
-    convert = r#"{ true }"#,
-    sync_writes = true,
+    key = "String",
+    convert = r#"{ plugin.upstreams.iter().map(|upstream| upstream.url.as_str()).collect::<Vec<_>>().join(" ") }"#,
     with_cached_flag = true,
     result = true
 )]
 async fn cached_graph(
+    plugin: &CincinnatiGraphFetchPlugin,
+    headers: HeaderMap,
+) -> Fallible<Return<FetchedGraph>, GraphError> {
+    plugin.fetch_with_failover(headers).await.map(Return::new)
+}
+
+/// Fetch the graph from an upstream.
+async fn fetch_graph(
     client: &reqwest::Client,
     upstream: &str,
+    format: UpstreamFormat,
     headers: HeaderMap,
-) -> Fallible<Return<crate::Graph>, GraphError> {
+) -> Fallible<FetchedGraph, GraphError> {
     let res = client
         .get(upstream)
         .headers(headers)
@@ -140,38 +317,158 @@ async fn cached_graph(
     if !res.status().is_success() {
         return Err(GraphError::FailedUpstreamFetch(res.status().to_string()));
     }
-    let graph = res
-        .json()
-        .map_err(|e| GraphError::FailedJsonIn(e.to_string()))
+    let provenance = Provenance::from_headers(res.headers());
+    let refreshed = res
+        .headers()
+        .get(provenance::REFRESHED_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| DateTime::parse_from_rfc3339(value).ok())
+        .map(|refreshed| refreshed.with_timezone(&Utc));
+    let body = res
+        .bytes()
+        .map_err(|e| GraphError::FailedUpstreamFetch(e.to_string()))
         .await?;
-    Ok(Return::new(graph))
+    let graph = parse_graph(format, &body)?;
+    Ok(FetchedGraph {
+        graph,
+        provenance,
+        refreshed,
+    })
+}
+
+/// Parse the graph served by the upstream.
+fn parse_graph(format: UpstreamFormat, body: &[u8]) -> Fallible<crate::Graph, GraphError> {
+    match format {
+        UpstreamFormat::Json => {
+            serde_json::from_slice(body).map_err(|e| GraphError::FailedJsonIn(e.to_string()))
+        }
+        UpstreamFormat::Artifact => {
+            let artifact = artifact::decode(body)
+                .map_err(|e| GraphError::FailedUpstreamFetch(e.to_string()))?;
+            trace!("fetched graph artifact at revision {}", artifact.revision);
+            Ok(artifact.graph)
+        }
+    }
 }
 
 impl CincinnatiGraphFetchPlugin {
+    /// Fetch the graph from the first healthy upstream serving a fresh graph.
+    ///
+    /// Healthy upstreams are probed concurrently, and unhealthy ones only if
+    /// none of them serves a fresh graph. A stale graph is only used if no
+    /// upstream serves a fresh one.
+    async fn fetch_with_failover(&self, headers: HeaderMap) -> Fallible<FetchedGraph, GraphError> {
+        let now = Instant::now();
+        let (healthy, unhealthy): (Vec<_>, Vec<_>) = self
+            .upstreams
+            .iter()
+            .enumerate()
+            .partition(|(_, upstream)| upstream.is_healthy(now));
+
+        let mut stale = None;
+        let mut last_error = None;
+        for candidates in [healthy, unhealthy] {
+            let results = futures::future::join_all(candidates.iter().map(|(_, upstream)| {
+                trace!("getting graph from upstream at {}", upstream.url);
+                self.http_upstream_reqs.inc();
+                fetch_graph(&self.client, &upstream.url, self.format, headers.clone())
+            }))
+            .await;
+
+            let mut fresh = None;
+            for ((index, upstream), result) in candidates.into_iter().zip(results) {
+                match result {
+                    Ok(fetched) if self.is_stale(&fetched, Utc::now()) => {
+                        warn!("upstream {} serves a stale graph", upstream.url);
+                        upstream.set_unhealthy_until(Some(now + self.retry_after));
+                        stale.get_or_insert((index, fetched));
+                    }
+                    Ok(fetched) => {
+                        upstream.set_unhealthy_until(None);
+                        fresh.get_or_insert((index, fetched));
+                    }
+                    Err(e) => {
+                        warn!(
+                            "failed to fetch graph from upstream {}: {}",
+                            upstream.url, e
+                        );
+                        upstream.set_unhealthy_until(Some(now + self.retry_after));
+                        last_error = Some(e);
+                    }
+                }
+            }
+            if let Some((index, fetched)) = fresh {
+                self.set_active(index);
+                return Ok(fetched);
+            }
+        }
+
+        match (stale, last_error) {
+            (Some((index, fetched)), _) => {
+                self.set_active(index);
+                Ok(fetched)
+            }
+            (None, Some(e)) => Err(e),
+            (None, None) => Err(GraphError::FailedUpstreamFetch(
+                "no upstream configured".to_string(),
+            )),
+        }
+    }
+
+    /// Returns whether the upstream last scraped the graph longer than the
+    /// maximum staleness ago.
+    ///
+    /// The generation time of the graph doesn't tell, since it only changes
+    /// along with the graph.
+    fn is_stale(&self, fetched: &FetchedGraph, now: DateTime<Utc>) -> bool {
+        let max_staleness = match self.max_staleness.map(chrono::Duration::from_std) {
+            Some(Ok(max_staleness)) => max_staleness,
+            _ => return false,
+        };
+        fetched
+            .refreshed
+            .is_some_and(|refreshed| now - refreshed > max_staleness)
+    }
+
+    /// Flag the upstream the graph was last fetched from.
+    fn set_active(&self, active: usize) {
+        for (index, upstream) in self.upstreams.iter().enumerate() {
+            self.http_upstream_active
+                .with_label_values(&[&upstream.url])
+                .set(i64::from(index == active));
+        }
+    }
+
     async fn do_run_internal(&self, io: InternalIO) -> Fallible<InternalIO> {
         // extract current trace ID from headers
         // this is required to make graph-builder trace a child of police-engine request
         let mut headers = HeaderMap::new();
-        headers.insert(ACCEPT, HeaderValue::from_static(CONTENT_TYPE));
+        let accept = match self.format {
+            UpstreamFormat::Json => CONTENT_TYPE,
+            UpstreamFormat::Artifact => artifact::CONTENT_TYPE,
+        };
+        headers.insert(ACCEPT, HeaderValue::from_static(accept));
         {
             let span = get_tracer().start("");
             let _active_span = mark_span_as_active(span);
             let cx = ot_context::current();
             set_context(cx, &mut headers).context("failed to set the tracing context")?;
         }
-
-        trace!("getting graph from upstream at {}", self.upstream);
-        let call_result = cached_graph(&self.client, &self.upstream, headers).await?;
-        // Increase request counter only if actual call was made
-        if !call_result.was_cached {
-            self.http_upstream_reqs.inc();
+        if let Some(id) = request_id::current().and_then(|id| HeaderValue::from_str(&id).ok()) {
+            headers.insert(request_id::REQUEST_ID_HEADER, id);
         }
+
+        let call_result = cached_graph(self, headers).await?;
         get_active_span(|span| {
             span.set_attribute(Key::new("cached").bool(call_result.was_cached));
         });
+        let mut parameters = io.parameters;
+        if let Some(provenance) = &call_result.value.provenance {
+            provenance.insert_into(&mut parameters);
+        }
         Ok(InternalIO {
-            graph: call_result.value,
-            parameters: io.parameters,
+            graph: call_result.value.graph,
+            parameters,
         })
     }
 }
@@ -195,6 +492,7 @@ impl InternalPlugin for CincinnatiGraphFetchPlugin {
 mod tests {
     use super::*;
     use actix_web::body::MessageBody;
+    use cached::Cached;
     use cincinnati::testing::generate_custom_graph;
     use commons::metrics::{self, RegistryWrapper};
     use commons::testing::{self, init_runtime};
@@ -219,6 +517,9 @@ mod tests {
                     .with_body($mock_body.to_string())
                     .create();
 
+                // The cache is shared by all instances, drop graphs of previous tests.
+                runtime.block_on(CACHED_GRAPH.lock()).cache_clear();
+
                 let timeout: u64 = 30;
                 let plugin =
                     CincinnatiGraphFetchPlugin::try_new(mockito::server_url(), timeout, None)?;
@@ -258,12 +559,12 @@ mod tests {
         name: fetch_success_simple_graph_fetch,
         mock_body: &serde_json::to_string(&generate_custom_graph(
             "image",
-            (0..3).into_iter().map(|i|(i, Default::default())).collect(),
+            (0..3).map(|i|(i, Default::default())).collect(),
             Some(vec![(0, 1), (1, 2)]),
         ))?,
         expected_graph: generate_custom_graph(
             "image",
-            (0..3).into_iter().map(|i|(i, Default::default())).collect(),
+            (0..3).map(|i|(i, Default::default())).collect(),
             Some(vec![(0, 1), (1, 2)]),
         ),
     );
@@ -340,6 +641,108 @@ mod tests {
         mock_body: "{not a valid graph}",
     );
 
+    #[test]
+    fn parse_graph_formats() -> Fallible<()> {
+        let graph = generate_custom_graph(
+            "image",
+            (0..3).map(|i| (i, Default::default())).collect(),
+            Some(vec![(0, 1), (1, 2)]),
+        );
+
+        let json = serde_json::to_vec(&graph)?;
+        assert_eq!(parse_graph(UpstreamFormat::Json, &json)?, graph);
+        assert!(parse_graph(UpstreamFormat::Artifact, &json).is_err());
+
+        let encoded = artifact::encode(1, 0, &graph)?;
+        assert_eq!(parse_graph(UpstreamFormat::Artifact, &encoded)?, graph);
+        assert!(parse_graph(UpstreamFormat::Json, &encoded).is_err());
+
+        let settings: CincinnatiGraphFetchSettings = toml::from_str(
+            r#"
+                upstream = "https://graph-builder:8080/internal/v1/graph"
+                format = "artifact"
+            "#,
+        )?;
+        assert_eq!(settings.format, UpstreamFormat::Artifact);
+        assert!(
+            CincinnatiGraphFetchPlugin::deserialize_config(toml::from_str(
+                r#"
+                upstream = "https://graph-builder:8080/internal/v1/graph"
+                client_cert_path = "/etc/tls/tls.crt"
+            "#,
+            )?)
+            .is_err()
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn fail_over_upstreams() -> Fallible<()> {
+        let runtime = init_runtime()?;
+        let body = serde_json::to_string(&generate_custom_graph(
+            "image",
+            (0..2).map(|i| (i, Default::default())).collect(),
+            Some(vec![(0, 1)]),
+        ))?;
+        let _stale = mockito::mock("GET", "/stale")
+            .with_status(200)
+            .with_header(provenance::REFRESHED_HEADER, "2022-01-01T00:00:00Z")
+            .with_body(&body)
+            .create();
+        // An unchanged graph is fresh as long as the upstream scrapes successfully.
+        let _fresh = mockito::mock("GET", "/fresh")
+            .with_status(200)
+            .with_header(provenance::GENERATED_HEADER, "2022-01-01T00:00:00Z")
+            .with_header(provenance::REFRESHED_HEADER, &Utc::now().to_rfc3339())
+            .with_body(&body)
+            .create();
+
+        let upstream = |path: &str| format!("{}{}", mockito::server_url(), path);
+        let settings = CincinnatiGraphFetchSettings {
+            upstreams: vec![
+                "http://not.reachable.test".to_string(),
+                upstream("/stale"),
+                upstream("/fresh"),
+            ],
+            max_staleness_secs: Some(3600),
+            ..Default::default()
+        };
+        let plugin = CincinnatiGraphFetchPlugin::try_from_settings(&settings, None)?;
+        let active = |url: &str| plugin.http_upstream_active.with_label_values(&[url]).get();
+
+        runtime.block_on(plugin.fetch_with_failover(HeaderMap::new()))?;
+        assert_eq!(active(&upstream("/fresh")), 1);
+        assert_eq!(active(&upstream("/stale")), 0);
+        assert_eq!(plugin.http_upstream_reqs.get() as u64, 3);
+        let now = Instant::now();
+        assert!(!plugin.upstreams[0].is_healthy(now));
+        assert!(!plugin.upstreams[1].is_healthy(now));
+        assert!(plugin.upstreams[2].is_healthy(now));
+
+        // Unhealthy upstreams are tried last.
+        runtime.block_on(plugin.fetch_with_failover(HeaderMap::new()))?;
+        assert_eq!(plugin.http_upstream_reqs.get() as u64, 4);
+
+        // A stale graph is better than none.
+        let settings = CincinnatiGraphFetchSettings {
+            upstreams: vec!["http://not.reachable.test".to_string(), upstream("/stale")],
+            max_staleness_secs: Some(3600),
+            ..Default::default()
+        };
+        let plugin = CincinnatiGraphFetchPlugin::try_from_settings(&settings, None)?;
+        runtime.block_on(plugin.fetch_with_failover(HeaderMap::new()))?;
+        assert_eq!(
+            plugin
+                .http_upstream_active
+                .with_label_values(&[&upstream("/stale")])
+                .get(),
+            1
+        );
+
+        Ok(())
+    }
+
     #[test]
     fn register_metrics() -> Fallible<()> {
         let rt = testing::init_runtime()?;
@@ -0,0 +1,360 @@
+//! This plugin resolves the cluster profile matching rules of conditional update risks.
+//!
+//! Clusters declare properties like their platform or topology, and the
+//! versions of their components, as client parameters. A `ClusterProfile`
+//! matching rule targets the clusters declaring all the listed values, and
+//! component versions matching all the listed SemVer requirements:
+//!
+//! ```yaml
+//! matchingRules:
+//! - type: ClusterProfile
+//!   clusterProfile:
+//!     properties:
+//!       platform: vsphere
+//!       topology: SingleReplica
+//!     versions:
+//!       etcd_version: ">=3.5.0, <3.5.3"
+//! ```
+//!
+//! Clusters don't know about these rules, so they are resolved before the
+//! graph is served. Clusters evaluate matching rules in order, the first one
+//! which can be evaluated deciding whether the risk applies, hence a matching
+//! profile rule and the rules after it are replaced by an `Always` rule, and
+//! profile rules which don't match are dropped. Profile rules targeting
+//! properties the cluster didn't declare are dropped too, but if no rule is
+//! left, the risk applies, as clusters do when no rule can be evaluated.
+//! Risks whose profile rules didn't match are removed, and conditional edges
+//! left without risks become regular, recommended edges.
+
+use crate as cincinnati;
+
+use self::cincinnati::plugins::prelude::*;
+use self::cincinnati::plugins::prelude_plugin_impl::*;
+use self::cincinnati::{ClusterCondition, ClusterProfileCondition, ConditionalUpdateRisk};
+
+use std::collections::HashMap;
+
+/// Condition type of the matching rules on cluster properties.
+pub static CLUSTER_PROFILE_CONDITION_TYPE: &str = "ClusterProfile";
+
+/// Condition type of matching rules which apply to all clusters.
+static ALWAYS_CONDITION_TYPE: &str = "Always";
+
+/// Plugin settings.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct ClusterProfileFilterSettings {}
+
+impl PluginSettings for ClusterProfileFilterSettings {
+    fn build_plugin(&self, _: Option<&prometheus::Registry>) -> Fallible<BoxedPlugin> {
+        Ok(new_plugin!(InternalPluginWrapper(
+            ClusterProfileFilterPlugin
+        )))
+    }
+}
+
+impl ClusterProfileFilterSettings {
+    /// Validate plugin configuration and fill in defaults.
+    pub fn deserialize_config(cfg: toml::Value) -> Fallible<Box<dyn PluginSettings>> {
+        let settings: Self = cfg.try_into()?;
+
+        Ok(Box::new(settings))
+    }
+}
+
+/// Outcome of a profile rule for a cluster.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Evaluation {
+    Matches,
+    DoesNotMatch,
+    /// The cluster didn't declare all the targeted properties.
+    Undeclared,
+}
+
+/// Evaluate a profile rule against the client parameters.
+fn evaluate(
+    profile: &ClusterProfileCondition,
+    parameters: &HashMap<String, String>,
+) -> Fallible<Evaluation> {
+    let mut undeclared = false;
+
+    for (name, expected) in &profile.properties {
+        match parameters.get(name) {
+            Some(value) if value.eq_ignore_ascii_case(expected) => {}
+            Some(_) => return Ok(Evaluation::DoesNotMatch),
+            None => undeclared = true,
+        }
+    }
+
+    for (name, requirement) in &profile.versions {
+        let requirement = semver::VersionReq::parse(requirement).context(format!(
+            "parsing version requirement '{}' of '{}'",
+            requirement, name
+        ))?;
+        match parameters
+            .get(name)
+            .and_then(|version| semver::Version::parse(version).ok())
+        {
+            Some(version) if requirement.matches(&version) => {}
+            Some(_) => return Ok(Evaluation::DoesNotMatch),
+            None => undeclared = true,
+        }
+    }
+
+    if undeclared {
+        Ok(Evaluation::Undeclared)
+    } else {
+        Ok(Evaluation::Matches)
+    }
+}
+
+/// Resolve the profile rules of a risk, returning whether the risk may still apply.
+fn resolve_risk(risk: &mut ConditionalUpdateRisk, parameters: &HashMap<String, String>) -> bool {
+    let is_profile_rule =
+        |rule: &ClusterCondition| rule.condition_type == CLUSTER_PROFILE_CONDITION_TYPE;
+    if !risk.matching_rules.iter().any(is_profile_rule) {
+        return true;
+    }
+
+    let always = || ClusterCondition {
+        condition_type: ALWAYS_CONDITION_TYPE.to_string(),
+        ..Default::default()
+    };
+    let mut rules = Vec::with_capacity(risk.matching_rules.len());
+    let mut unresolved = false;
+    for rule in std::mem::take(&mut risk.matching_rules) {
+        if !is_profile_rule(&rule) {
+            rules.push(rule);
+            continue;
+        }
+
+        match evaluate(&rule.cluster_profile, parameters) {
+            Ok(Evaluation::Matches) => {
+                rules.push(always());
+                break;
+            }
+            Ok(Evaluation::DoesNotMatch) => {}
+            Ok(Evaluation::Undeclared) => unresolved = true,
+            Err(e) => {
+                warn!("evaluating cluster profile of risk {}: {:#}", risk.name, e);
+                unresolved = true;
+            }
+        }
+    }
+
+    if rules.is_empty() {
+        if !unresolved {
+            return false;
+        }
+        rules.push(always());
+    }
+    risk.matching_rules = rules;
+
+    true
+}
+
+/// Resolves the cluster profile matching rules for the requesting cluster.
+#[derive(Debug)]
+pub struct ClusterProfileFilterPlugin;
+
+impl ClusterProfileFilterPlugin {
+    /// Plugin name, for configuration.
+    pub const PLUGIN_NAME: &'static str = "cluster-profile-filter";
+}
+
+#[async_trait]
+impl InternalPlugin for ClusterProfileFilterPlugin {
+    const PLUGIN_NAME: &'static str = Self::PLUGIN_NAME;
+
+    async fn run_internal(&self, io: InternalIO) -> Fallible<InternalIO> {
+        let InternalIO {
+            mut graph,
+            parameters,
+        } = io;
+
+        if let Some(conditional_edges) = graph.conditional_edges.take() {
+            let mut remaining = Vec::with_capacity(conditional_edges.len());
+            for mut conditional_edge in conditional_edges {
+                if conditional_edge.risks.is_empty() {
+                    remaining.push(conditional_edge);
+                    continue;
+                }
+
+                let risks = std::mem::take(&mut conditional_edge.risks);
+                conditional_edge.risks = risks
+                    .into_iter()
+                    .filter_map(|mut risk| {
+                        if resolve_risk(&mut risk, &parameters) {
+                            Some(risk)
+                        } else {
+                            None
+                        }
+                    })
+                    .collect();
+
+                if conditional_edge.risks.is_empty() {
+                    graph.recommend_conditional_edge(&conditional_edge);
+                } else {
+                    remaining.push(conditional_edge);
+                }
+            }
+            graph.conditional_edges = Some(remaining);
+        }
+
+        Ok(InternalIO { graph, parameters })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use self::cincinnati::Graph;
+    use super::*;
+    use commons::testing::init_runtime;
+
+    fn profile_rule(properties: &[(&str, &str)], versions: &[(&str, &str)]) -> ClusterCondition {
+        let map = |pairs: &[(&str, &str)]| {
+            pairs
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect()
+        };
+        ClusterCondition {
+            condition_type: CLUSTER_PROFILE_CONDITION_TYPE.to_string(),
+            cluster_profile: ClusterProfileCondition {
+                properties: map(properties),
+                versions: map(versions),
+            },
+            ..Default::default()
+        }
+    }
+
+    fn parameters(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    /// Graph with a conditional 1.0.0 -> 2.0.0 edge whose risk targets single-node vSphere clusters.
+    fn input_graph() -> Graph {
+        let mut graph = cincinnati::testing::generate_graph(true, false);
+        let conditional_edges = graph.conditional_edges.as_mut().unwrap();
+        conditional_edges[0].risks[0].matching_rules = vec![profile_rule(
+            &[("platform", "vsphere"), ("topology", "SingleReplica")],
+            &[],
+        )];
+        let (from, to) = (
+            graph.find_by_version("1.0.0").unwrap(),
+            graph.find_by_version("2.0.0").unwrap(),
+        );
+        graph.remove_edge(&from, &to).unwrap();
+        graph
+    }
+
+    #[test]
+    fn evaluate_profiles() -> Fallible<()> {
+        let rule = profile_rule(
+            &[("platform", "vsphere")],
+            &[("etcd_version", ">=3.5.0, <3.5.3")],
+        );
+        let evaluate = |pairs| evaluate(&rule.cluster_profile, &parameters(pairs));
+
+        assert_eq!(
+            evaluate(&[("platform", "vSphere"), ("etcd_version", "3.5.1")])?,
+            Evaluation::Matches
+        );
+        assert_eq!(
+            evaluate(&[("platform", "vsphere"), ("etcd_version", "3.5.3")])?,
+            Evaluation::DoesNotMatch
+        );
+        assert_eq!(evaluate(&[("platform", "aws")])?, Evaluation::DoesNotMatch);
+        assert_eq!(
+            evaluate(&[("platform", "vsphere"), ("etcd_version", "latest")])?,
+            Evaluation::Undeclared
+        );
+        assert_eq!(evaluate(&[])?, Evaluation::Undeclared);
+
+        let invalid = profile_rule(&[], &[("etcd_version", "three")]);
+        assert!(super::evaluate(&invalid.cluster_profile, &parameters(&[])).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn resolve_rules_in_order() {
+        let promql = ClusterCondition {
+            condition_type: "PromQL".to_string(),
+            promql: cincinnati::PromQLClusterCondition {
+                promql: "cluster_infrastructure_provider".to_string(),
+            },
+            ..Default::default()
+        };
+        let risk = |rules: Vec<ClusterCondition>| ConditionalUpdateRisk {
+            name: "Risk".to_string(),
+            matching_rules: rules,
+            ..Default::default()
+        };
+        let types = |risk: &ConditionalUpdateRisk| -> Vec<String> {
+            risk.matching_rules
+                .iter()
+                .map(|rule| rule.condition_type.clone())
+                .collect()
+        };
+        let sno = parameters(&[("topology", "SingleReplica")]);
+
+        // Rules before a matching profile are kept, the ones after it are replaced.
+        let mut matching = risk(vec![
+            promql.clone(),
+            profile_rule(&[("topology", "SingleReplica")], &[]),
+            promql.clone(),
+        ]);
+        assert!(resolve_risk(&mut matching, &sno));
+        assert_eq!(types(&matching), vec!["PromQL", "Always"]);
+
+        // Profiles which don't match are dropped, as are risks left without rules.
+        let mut other = risk(vec![
+            profile_rule(&[("topology", "HighlyAvailable")], &[]),
+            promql.clone(),
+        ]);
+        assert!(resolve_risk(&mut other, &sno));
+        assert_eq!(types(&other), vec!["PromQL"]);
+        let mut other = risk(vec![profile_rule(&[("topology", "HighlyAvailable")], &[])]);
+        assert!(!resolve_risk(&mut other, &sno));
+
+        // Risks which can't be evaluated apply.
+        let mut undeclared = risk(vec![profile_rule(&[("platform", "vsphere")], &[])]);
+        assert!(resolve_risk(&mut undeclared, &sno));
+        assert_eq!(types(&undeclared), vec!["Always"]);
+
+        // Risks without profiles are left alone.
+        let mut unrelated = risk(vec![promql]);
+        assert!(resolve_risk(&mut unrelated, &sno));
+        assert_eq!(types(&unrelated), vec!["PromQL"]);
+    }
+
+    #[test]
+    fn filter_risks() -> Fallible<()> {
+        let runtime = init_runtime()?;
+        let run = |pairs: &[(&str, &str)]| -> Fallible<Graph> {
+            let io = runtime.block_on(ClusterProfileFilterPlugin.run_internal(InternalIO {
+                graph: input_graph(),
+                parameters: parameters(pairs),
+            }))?;
+            Ok(io.graph)
+        };
+
+        // Targeted clusters aren't recommended the update.
+        let graph = run(&[("platform", "vsphere"), ("topology", "SingleReplica")])?;
+        assert_eq!(graph.conditional_edges().len(), 1);
+        let risk = &graph.conditional_edges.as_ref().unwrap()[0].risks[0];
+        assert_eq!(risk.matching_rules[0].condition_type, "Always");
+        assert!(risk.matching_rules[0].cluster_profile.is_empty());
+
+        // Other clusters get a regular edge.
+        let input = input_graph();
+        let graph = run(&[("platform", "vsphere"), ("topology", "HighlyAvailable")])?;
+        assert!(graph.conditional_edges().is_empty());
+        assert_eq!(graph.edges_count(), input.edges_count() + 1);
+
+        Ok(())
+    }
+}
@@ -116,7 +116,7 @@ impl EdgeAddRemovePlugin {
                     if self.remove_consumed_metadata {
                         graph
                             .get_metadata_as_ref_mut(&to)
-                            .map(|metadata| metadata.remove(&previous_remove_key))?;
+                            .map(|metadata| metadata.remove(previous_remove_key.as_str()))?;
                     }
 
                     if from_csv.trim() == self.remove_all_edges_value {
@@ -156,7 +156,7 @@ impl EdgeAddRemovePlugin {
                     if self.remove_consumed_metadata {
                         graph
                             .get_metadata_as_ref_mut(&to)
-                            .map(|metadata| metadata.remove(&previous_remove_regex_key))?;
+                            .map(|metadata| metadata.remove(previous_remove_regex_key.as_str()))?;
                     }
 
                     let from_regex = regex::Regex::new(&from_regex_string)
@@ -176,7 +176,7 @@ impl EdgeAddRemovePlugin {
                         return graph.remove_edges_by_index(&parents);
                     };
 
-                    let froms = graph.find_by_fn_mut(|release| {
+                    let froms = graph.find_by_fn(|release| {
                         if from_regex.is_match(release.version()) {
                             debug!(
                                 "Regex '{}' matches version '{}'",
@@ -210,7 +210,7 @@ impl EdgeAddRemovePlugin {
                     if self.remove_consumed_metadata {
                         graph
                             .get_metadata_as_ref_mut(&from)
-                            .map(|metadata| metadata.remove(&next_remove_key))?;
+                            .map(|metadata| metadata.remove(next_remove_key.as_str()))?;
                     }
 
                     for to_version in to_csv.split(',').map(str::trim) {
@@ -256,7 +256,7 @@ impl EdgeAddRemovePlugin {
                 if self.remove_consumed_metadata {
                     graph
                         .get_metadata_as_ref_mut(&to)
-                        .map(|metadata| metadata.remove(&previous_add_key))?;
+                        .map(|metadata| metadata.remove(previous_add_key.as_str()))?;
                 }
 
                 for from_version in from_csv.split(',').map(str::trim) {
@@ -287,7 +287,7 @@ impl EdgeAddRemovePlugin {
                 if self.remove_consumed_metadata {
                     graph
                         .get_metadata_as_ref_mut(&from)
-                        .map(|metadata| metadata.remove(&next_add_key))?;
+                        .map(|metadata| metadata.remove(next_add_key.as_str()))?;
                 }
 
                 for to_version in to_csv.split(',').map(str::trim) {
@@ -366,7 +366,7 @@ impl EdgeAddRemovePlugin {
                             to_version, froms
                         );
                         froms.iter().for_each(|from| {
-                            if let true = from_regex.is_match(from) {
+                            if from_regex.is_match(from) {
                                 debug!(
                                     "Regex '{}' matches version '{}'",
                                     &from_regex,
@@ -433,7 +433,7 @@ fn try_annotate_semver_build(
         .get_metadata_as_ref_mut(arch_reference)?
         .get("io.openshift.upgrades.graph.release.arch")
     {
-        let mut version = semver::Version::parse(version)?;
+        let mut version = crate::semver_cache::parse_version(version)?;
         version.build = vec![semver::Identifier::AlphaNumeric(arch.to_string())];
         version.to_string()
     } else {
@@ -777,7 +777,7 @@ mod tests {
     label_processing_order_test!(
         name: contradicting_inter_node_labels,
         input_metadata:
-            vec![
+            [
                 (
                     0,
                     vec![
@@ -809,7 +809,7 @@ mod tests {
     label_processing_order_test!(
         name: contradicting_intra_node_labels,
         input_metadata:
-            vec![
+            [
                 (0, vec![("next.add", "1.0.0"), ("next.remove", "1.0.0")]),
                 (1, vec![]),
                 (
@@ -828,7 +828,7 @@ mod tests {
     label_processing_order_test!(
         name: contradicting_inter_and_intra_node_labels,
         input_metadata:
-            vec![
+            [
                 (
                     0,
                     vec![
@@ -870,10 +870,8 @@ mod tests {
     label_processing_order_test!(
         name: dont_add_duplicate_edges,
         input_metadata:
-            vec![
-                (0, vec![("next.add", "1.0.0"),],),
-                (1, vec![("previous.add", "0.0.0"),],),
-            ],
+            [(0, vec![("next.add", "1.0.0"),],),
+                (1, vec![("previous.add", "0.0.0"),],)],
         input_edges: Some(vec![(0, 1)]),
         expected_edges: Some(vec![(0, 1)]),
     );
@@ -881,10 +879,8 @@ mod tests {
     label_processing_order_test!(
         name: gracefully_handle_nonexistent_edge_removal,
         input_metadata:
-            vec![
-                (0, vec![("next.remove", "1.0.0")]),
-                (1, vec![("previous.remove", "1.0.0"),])
-            ],
+            [(0, vec![("next.remove", "1.0.0")]),
+                (1, vec![("previous.remove", "1.0.0"),])],
         input_edges: Some(vec![]),
         expected_edges: Some(vec![]),
     );
@@ -892,7 +888,7 @@ mod tests {
     label_processing_order_test!(
         name: gracefully_handle_nonexistent_release_references,
         input_metadata:
-            vec![(
+            [(
                 0,
                 vec![
                     ("next.add", "1.0.0"),
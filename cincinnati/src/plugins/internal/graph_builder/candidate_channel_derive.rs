@@ -0,0 +1,292 @@
+//! This plugin derives candidate channel membership from version naming conventions.
+//!
+//! Releases without any channel metadata whose version carries one of the
+//! configured pre-release identifiers, e.g. `4.11.0-rc.1` or `4.12.0-ec.3`,
+//! are placed in the `<channel_prefix>-<major>.<minor>` channel. It is meant to
+//! run after the graph-data parser, so that explicit channel assignments
+//! always take precedence.
+
+use crate as cincinnati;
+
+use self::cincinnati::plugins::prelude::*;
+use self::cincinnati::plugins::prelude_plugin_impl::*;
+use self::cincinnati::{IStr, Release};
+
+/// Default prefix of the metadata keys.
+pub static DEFAULT_KEY_PREFIX: &str = "io.openshift.upgrades.graph";
+
+/// Default prefix of the derived channels.
+pub static DEFAULT_CHANNEL_PREFIX: &str = "candidate";
+
+/// Default pre-release identifiers of early-access releases.
+pub static DEFAULT_PRERELEASE_IDENTIFIERS: &[&str] = &["rc", "ec"];
+
+/// Derivation rule for the releases of a repository.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RepositoryRule {
+    /// Repository of the release payloads, e.g. `quay.io/openshift-release-dev/ocp-release`.
+    pub repository: String,
+
+    /// Pre-release identifiers for this repository, overriding the plugin ones.
+    #[serde(default)]
+    pub prerelease_identifiers: Option<Vec<String>>,
+
+    /// Channel prefix for this repository, overriding the plugin one.
+    #[serde(default)]
+    pub channel_prefix: Option<String>,
+}
+
+/// Plugin settings.
+#[derive(Clone, Debug, Deserialize, SmartDefault)]
+#[serde(default)]
+pub struct CandidateChannelDeriveSettings {
+    /// Prefix of the metadata keys.
+    #[default(DEFAULT_KEY_PREFIX.to_string())]
+    pub key_prefix: String,
+
+    /// Prefix of the derived channels.
+    #[default(DEFAULT_CHANNEL_PREFIX.to_string())]
+    pub channel_prefix: String,
+
+    /// First pre-release identifiers which mark early-access releases.
+    #[default(DEFAULT_PRERELEASE_IDENTIFIERS.iter().map(|s| s.to_string()).collect())]
+    pub prerelease_identifiers: Vec<String>,
+
+    /// Repositories to derive channels for. All releases are considered if empty.
+    pub repositories: Vec<RepositoryRule>,
+}
+
+impl PluginSettings for CandidateChannelDeriveSettings {
+    fn build_plugin(&self, _: Option<&prometheus::Registry>) -> Fallible<BoxedPlugin> {
+        let plugin = CandidateChannelDerivePlugin {
+            settings: self.clone(),
+        };
+        Ok(new_plugin!(InternalPluginWrapper(plugin)))
+    }
+
+    fn key_prefix(&self) -> Option<&str> {
+        Some(&self.key_prefix)
+    }
+}
+
+impl CandidateChannelDeriveSettings {
+    /// Validate plugin configuration and fill in defaults.
+    pub fn deserialize_config(cfg: toml::Value) -> Fallible<Box<dyn PluginSettings>> {
+        let settings: Self = cfg.try_into()?;
+
+        ensure!(!settings.key_prefix.is_empty(), "empty key_prefix");
+        ensure!(!settings.channel_prefix.is_empty(), "empty channel_prefix");
+        ensure!(
+            !settings.prerelease_identifiers.is_empty(),
+            "no prerelease_identifiers configured"
+        );
+        for rule in &settings.repositories {
+            ensure!(!rule.repository.is_empty(), "empty repository");
+            ensure!(
+                rule.channel_prefix.as_ref().is_none_or(|p| !p.is_empty()),
+                "empty channel_prefix for repository '{}'",
+                rule.repository
+            );
+            ensure!(
+                rule.prerelease_identifiers
+                    .as_ref()
+                    .is_none_or(|ids| !ids.is_empty()),
+                "no prerelease_identifiers configured for repository '{}'",
+                rule.repository
+            );
+        }
+
+        Ok(Box::new(settings))
+    }
+
+    /// Returns the pre-release identifiers and channel prefix applying to a payload.
+    fn rule_for(&self, payload: &str) -> Option<(&[String], &str)> {
+        if self.repositories.is_empty() {
+            return Some((&self.prerelease_identifiers, &self.channel_prefix));
+        }
+
+        self.repositories
+            .iter()
+            .find(|rule| {
+                payload
+                    .strip_prefix(&rule.repository)
+                    .is_some_and(|rest| rest.starts_with('@') || rest.starts_with(':'))
+            })
+            .map(|rule| {
+                (
+                    rule.prerelease_identifiers
+                        .as_deref()
+                        .unwrap_or(&self.prerelease_identifiers),
+                    rule.channel_prefix
+                        .as_deref()
+                        .unwrap_or(&self.channel_prefix),
+                )
+            })
+    }
+}
+
+/// Derives candidate channels for early-access releases.
+#[derive(Debug)]
+pub struct CandidateChannelDerivePlugin {
+    settings: CandidateChannelDeriveSettings,
+}
+
+impl CandidateChannelDerivePlugin {
+    /// Plugin name, for configuration.
+    pub const PLUGIN_NAME: &'static str = "candidate-channel-derive";
+}
+
+/// Returns the candidate channel of a version, if its first pre-release identifier is one of `identifiers`.
+fn candidate_channel(
+    version: &str,
+    identifiers: &[String],
+    channel_prefix: &str,
+) -> Option<String> {
+    let version = match cincinnati::semver_cache::parse_version(version) {
+        Ok(version) => version,
+        Err(e) => {
+            warn!("Parsing {} as SemVer: {}", version, e);
+            return None;
+        }
+    };
+
+    let first = version.pre.first()?.to_string();
+    if !identifiers.contains(&first) {
+        return None;
+    }
+
+    Some(format!(
+        "{}-{}.{}",
+        channel_prefix, version.major, version.minor
+    ))
+}
+
+#[async_trait]
+impl InternalPlugin for CandidateChannelDerivePlugin {
+    const PLUGIN_NAME: &'static str = Self::PLUGIN_NAME;
+
+    async fn run_internal(&self, io: InternalIO) -> Fallible<InternalIO> {
+        let mut graph = io.graph;
+        let channels_key: IStr = format!("{}.release.channels", self.settings.key_prefix).into();
+
+        let mut derived = 0;
+        graph.iter_releases_mut(|release| {
+            if let Release::Concrete(release) = release {
+                let has_channels = release
+                    .metadata
+                    .get(&channels_key)
+                    .is_some_and(|channels| !channels.is_empty());
+                if has_channels {
+                    return Ok(());
+                }
+
+                let channel = self.settings.rule_for(&release.payload).and_then(
+                    |(identifiers, channel_prefix)| {
+                        candidate_channel(&release.version, identifiers, channel_prefix)
+                    },
+                );
+                if let Some(channel) = channel {
+                    trace!("placing {} in channel {}", release.version, channel);
+                    release
+                        .metadata
+                        .insert(channels_key.clone(), channel.into());
+                    derived += 1;
+                }
+            }
+            Ok(())
+        })?;
+        debug!("Derived candidate channels of {} releases.", derived);
+
+        Ok(InternalIO {
+            graph,
+            parameters: io.parameters,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use commons::testing::init_runtime;
+
+    fn release(version: &str, payload: &str, channels: Option<&str>) -> Release {
+        let mut metadata = cincinnati::MapImpl::new();
+        if let Some(channels) = channels {
+            metadata.insert(
+                "io.openshift.upgrades.graph.release.channels".into(),
+                channels.into(),
+            );
+        }
+        Release::Concrete(cincinnati::ConcreteRelease {
+            version: version.into(),
+            payload: payload.to_string(),
+            metadata,
+        })
+    }
+
+    #[test]
+    fn derive_candidate_channels() -> Fallible<()> {
+        let runtime = init_runtime()?;
+
+        let mut graph = cincinnati::Graph::default();
+        for release in [
+            release("4.11.0-rc.1", "quay.io/ocp/release@sha256:1", None),
+            release("4.12.0-ec.3", "quay.io/ocp/release@sha256:2", None),
+            release(
+                "4.11.0-rc.2",
+                "quay.io/ocp/release@sha256:3",
+                Some("fast-4.11"),
+            ),
+            release("4.11.0", "quay.io/ocp/release@sha256:4", None),
+            release("4.12.0-ec.4", "quay.io/okd/release@sha256:5", None),
+            release("4.12.0-fc.1", "quay.io/okd/release@sha256:6", None),
+        ] {
+            graph.add_release(release)?;
+        }
+
+        let settings = CandidateChannelDeriveSettings::deserialize_config(toml::from_str(
+            r#"
+                name = "candidate-channel-derive"
+
+                [[repositories]]
+                repository = "quay.io/ocp/release"
+
+                [[repositories]]
+                repository = "quay.io/okd/release"
+                prerelease_identifiers = ["fc"]
+                channel_prefix = "next"
+            "#,
+        )?)?;
+        let plugin = settings.build_plugin(None)?;
+
+        let io = runtime.block_on(plugin.run(cincinnati::plugins::PluginIO::InternalIO(
+            InternalIO {
+                graph,
+                parameters: Default::default(),
+            },
+        )))?;
+        let graph = cincinnati::testing::internal_io(io)?.graph;
+
+        let channels = |version: &str| {
+            graph
+                .find_by_version(version)
+                .and_then(|id| graph.find_by_releaseid(&id).ok())
+                .and_then(|release| match release {
+                    Release::Concrete(release) => release
+                        .metadata
+                        .get("io.openshift.upgrades.graph.release.channels")
+                        .cloned(),
+                    _ => None,
+                })
+        };
+        assert_eq!(channels("4.11.0-rc.1").as_deref(), Some("candidate-4.11"));
+        assert_eq!(channels("4.12.0-ec.3").as_deref(), Some("candidate-4.12"));
+        assert_eq!(channels("4.11.0-rc.2").as_deref(), Some("fast-4.11"));
+        assert_eq!(channels("4.11.0"), None);
+        assert_eq!(channels("4.12.0-ec.4"), None);
+        assert_eq!(channels("4.12.0-fc.1").as_deref(), Some("next-4.12"));
+
+        Ok(())
+    }
+}
@@ -1,10 +1,10 @@
 #[cfg(test)]
+#[cfg(feature = "test-net")]
 pub mod tests {
     //! Common functionality for graph-builder tests
 
     use crate as cincinnati;
 
-    use cincinnati::plugins::internal::graph_builder::release;
     use cincinnati::plugins::internal::graph_builder::release_scrape_dockerv2::registry;
 
     fn init_logger() {
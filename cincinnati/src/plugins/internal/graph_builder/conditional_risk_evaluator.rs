@@ -0,0 +1,455 @@
+//! This plugin resolves conditional update risks fleet-wide, by evaluating
+//! their PromQL expressions against a Prometheus or Thanos query API.
+//!
+//! Risks declare the expression as `fleetEvaluation.promql` in the graph-data.
+//! A risk applies if the query returns a non-zero sample. Its matching rules
+//! are then replaced by an `Always` rule, so that the update is not
+//! recommended to any cluster. If none of the risks of a conditional edge
+//! apply, its edges become regular, recommended edges.
+//!
+//! Risks without an expression, or whose evaluation fails, are left as they
+//! are, so that clusters keep evaluating their own matching rules. Results are
+//! cached for the configured evaluation interval.
+
+use crate as cincinnati;
+
+use self::cincinnati::plugins::prelude::*;
+use self::cincinnati::plugins::prelude_plugin_impl::*;
+use self::cincinnati::{ClusterCondition, Graph};
+
+use prometheus::IntCounter;
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+use tokio::sync::RwLock;
+
+/// Default interval between evaluations of the same expression, in seconds.
+pub static DEFAULT_EVALUATION_INTERVAL_SECS: u64 = 300;
+
+/// Default timeout for a single query, in seconds.
+pub static DEFAULT_QUERY_TIMEOUT_SECS: u64 = 30;
+
+/// Condition type of matching rules which apply to all clusters.
+static ALWAYS_CONDITION_TYPE: &str = "Always";
+
+/// Plugin settings.
+#[derive(Clone, Debug, Deserialize, SmartDefault)]
+#[serde(default)]
+pub struct ConditionalRiskEvaluatorSettings {
+    /// Base URL of the Prometheus or Thanos query API.
+    pub url: String,
+
+    /// Path to a file containing the bearer token for the query API.
+    pub token_path: Option<PathBuf>,
+
+    /// Interval between evaluations of the same expression, in seconds.
+    #[default(DEFAULT_EVALUATION_INTERVAL_SECS)]
+    pub interval_secs: u64,
+
+    /// Timeout for a single query, in seconds.
+    #[default(DEFAULT_QUERY_TIMEOUT_SECS)]
+    pub timeout_secs: u64,
+}
+
+impl PluginSettings for ConditionalRiskEvaluatorSettings {
+    fn build_plugin(&self, registry: Option<&prometheus::Registry>) -> Fallible<BoxedPlugin> {
+        let plugin = ConditionalRiskEvaluatorPlugin::try_new(self.clone(), registry)?;
+        Ok(new_plugin!(InternalPluginWrapper(plugin)))
+    }
+}
+
+impl ConditionalRiskEvaluatorSettings {
+    /// Validate plugin configuration and fill in defaults.
+    pub fn deserialize_config(cfg: toml::Value) -> Fallible<Box<dyn PluginSettings>> {
+        let settings: Self = cfg.try_into()?;
+
+        ensure!(!settings.url.is_empty(), "empty url");
+        url::Url::parse(&settings.url).context(format!("parsing url '{}'", settings.url))?;
+        ensure!(settings.interval_secs > 0, "interval_secs must be positive");
+        ensure!(settings.timeout_secs > 0, "timeout_secs must be positive");
+
+        Ok(Box::new(settings))
+    }
+}
+
+/// Fleet-wide state of a risk.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum RiskState {
+    /// The risk applies, the update is not recommended.
+    Applies,
+    /// The risk does not apply.
+    DoesNotApply,
+    /// The risk could not be evaluated.
+    Unknown,
+}
+
+/// Response of the instant query API.
+#[derive(Debug, Deserialize)]
+struct QueryResponse {
+    status: String,
+    #[serde(default)]
+    error: Option<String>,
+    #[serde(default)]
+    data: Option<QueryData>,
+}
+
+/// Result of an instant query, as `(timestamp, value)` samples.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "resultType", content = "result", rename_all = "lowercase")]
+enum QueryData {
+    Vector(Vec<VectorSample>),
+    Scalar((f64, String)),
+}
+
+#[derive(Debug, Deserialize)]
+struct VectorSample {
+    value: (f64, String),
+}
+
+impl QueryData {
+    /// Whether any sample has a non-zero value.
+    fn is_positive(&self) -> Fallible<bool> {
+        let is_positive = |(_, value): &(f64, String)| -> Fallible<bool> {
+            let value: f64 = value
+                .parse()
+                .context(format!("parsing sample value '{}'", value))?;
+            Ok(value != 0.0)
+        };
+
+        match self {
+            QueryData::Vector(samples) => {
+                for sample in samples {
+                    if is_positive(&sample.value)? {
+                        return Ok(true);
+                    }
+                }
+                Ok(false)
+            }
+            QueryData::Scalar(sample) => is_positive(sample),
+        }
+    }
+}
+
+/// Resolves conditional update risks with fleet-wide PromQL expressions.
+#[derive(CustomDebug)]
+pub struct ConditionalRiskEvaluatorPlugin {
+    settings: ConditionalRiskEvaluatorSettings,
+
+    #[debug(skip)]
+    client: reqwest::Client,
+
+    #[debug(skip)]
+    token: Option<String>,
+
+    /// Last evaluation of each expression, with its time.
+    #[debug(skip)]
+    evaluations: RwLock<HashMap<String, (Instant, RiskState)>>,
+
+    #[debug(skip)]
+    queries: IntCounter,
+
+    #[debug(skip)]
+    query_errors: IntCounter,
+}
+
+impl ConditionalRiskEvaluatorPlugin {
+    /// Plugin name, for configuration.
+    pub const PLUGIN_NAME: &'static str = "conditional-risk-evaluator";
+
+    pub fn try_new(
+        settings: ConditionalRiskEvaluatorSettings,
+        prometheus_registry: Option<&prometheus::Registry>,
+    ) -> Fallible<Self> {
+        let queries = IntCounter::new(
+            "conditional_risk_evaluator_queries_total",
+            "Total number of queries evaluating conditional update risks",
+        )?;
+        let query_errors = IntCounter::new(
+            "conditional_risk_evaluator_query_errors_total",
+            "Total number of failed queries evaluating conditional update risks",
+        )?;
+
+        if let Some(registry) = &prometheus_registry {
+            registry.register(Box::new(queries.clone()))?;
+            registry.register(Box::new(query_errors.clone()))?;
+        }
+
+        let token = settings
+            .token_path
+            .as_ref()
+            .map(|path| {
+                std::fs::read_to_string(path).context(format!("Reading token from {:?}", path))
+            })
+            .transpose()?
+            .and_then(|token| token.lines().next().map(|line| line.trim().to_owned()));
+
+        let client = commons::outbound::client_builder()
+            .timeout(Duration::from_secs(settings.timeout_secs))
+            .build()
+            .context("Building reqwest client")?;
+
+        Ok(Self {
+            settings,
+            client,
+            token,
+            evaluations: Default::default(),
+            queries,
+            query_errors,
+        })
+    }
+
+    /// Run an instant query and return whether it has a non-zero result.
+    async fn query(&self, promql: &str) -> Fallible<bool> {
+        self.queries.inc();
+
+        let url = format!("{}/api/v1/query", self.settings.url.trim_end_matches('/'));
+        let mut request = self.client.get(&url).query(&[("query", promql)]);
+        if let Some(token) = &self.token {
+            request = request.bearer_auth(token);
+        }
+
+        let response = request.send().await.context(format!("querying {}", url))?;
+        let status = response.status();
+        let body = response.bytes().await?;
+        let response: QueryResponse = serde_json::from_slice(&body)
+            .context(format!("parsing query response with status {}", status))?;
+
+        ensure!(
+            response.status == "success",
+            "query failed: {}",
+            response.error.unwrap_or(response.status)
+        );
+
+        response
+            .data
+            .ok_or_else(|| format_err!("query response has no data"))?
+            .is_positive()
+    }
+
+    /// Evaluate an expression, unless it was evaluated recently.
+    async fn evaluate(&self, promql: &str) -> RiskState {
+        let interval = Duration::from_secs(self.settings.interval_secs);
+        if let Some((evaluated, state)) = self.evaluations.read().await.get(promql) {
+            if evaluated.elapsed() < interval {
+                return *state;
+            }
+        }
+
+        let state = match self.query(promql).await {
+            Ok(true) => RiskState::Applies,
+            Ok(false) => RiskState::DoesNotApply,
+            Err(e) => {
+                self.query_errors.inc();
+                warn!("evaluating conditional risk '{}': {:#}", promql, e);
+                RiskState::Unknown
+            }
+        };
+
+        self.evaluations
+            .write()
+            .await
+            .insert(promql.to_string(), (Instant::now(), state));
+
+        state
+    }
+}
+
+/// Resolve the risks of the conditional edges according to `states`, keyed by expression.
+fn resolve_risks(graph: &mut Graph, states: &HashMap<String, RiskState>) {
+    let conditional_edges = match graph.conditional_edges.take() {
+        Some(conditional_edges) => conditional_edges,
+        None => return,
+    };
+
+    let mut remaining = Vec::with_capacity(conditional_edges.len());
+    for mut conditional_edge in conditional_edges {
+        let mut recommended = true;
+
+        for risk in &mut conditional_edge.risks {
+            let state = risk
+                .fleet_evaluation
+                .as_ref()
+                .and_then(|evaluation| states.get(&evaluation.promql))
+                .copied()
+                .unwrap_or(RiskState::Unknown);
+
+            match state {
+                RiskState::Applies => {
+                    risk.matching_rules = vec![ClusterCondition {
+                        condition_type: ALWAYS_CONDITION_TYPE.to_string(),
+                        ..Default::default()
+                    }];
+                    recommended = false;
+                }
+                RiskState::DoesNotApply => {}
+                RiskState::Unknown => recommended = false,
+            }
+        }
+
+        if recommended {
+            graph.recommend_conditional_edge(&conditional_edge);
+        } else {
+            remaining.push(conditional_edge);
+        }
+    }
+
+    graph.conditional_edges = Some(remaining);
+}
+
+#[async_trait]
+impl InternalPlugin for ConditionalRiskEvaluatorPlugin {
+    const PLUGIN_NAME: &'static str = Self::PLUGIN_NAME;
+
+    async fn run_internal(&self, io: InternalIO) -> Fallible<InternalIO> {
+        let mut graph = io.graph;
+
+        let expressions: HashSet<String> = graph
+            .conditional_edges
+            .iter()
+            .flatten()
+            .flat_map(|conditional_edge| conditional_edge.risks.iter())
+            .filter_map(|risk| risk.fleet_evaluation.as_ref())
+            .map(|evaluation| evaluation.promql.clone())
+            .collect();
+
+        let mut states = HashMap::with_capacity(expressions.len());
+        for promql in expressions {
+            let state = self.evaluate(&promql).await;
+            states.insert(promql, state);
+        }
+
+        resolve_risks(&mut graph, &states);
+
+        Ok(InternalIO {
+            graph,
+            parameters: io.parameters,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PromQLClusterCondition;
+    use commons::testing::init_runtime;
+
+    static PROMQL: &str = "cluster_infrastructure_provider{type=\"CloudProvider\"}";
+
+    fn settings(path: &str) -> ConditionalRiskEvaluatorSettings {
+        ConditionalRiskEvaluatorSettings {
+            url: format!("{}{}", mockito::server_url(), path),
+            ..Default::default()
+        }
+    }
+
+    /// Graph with a conditional 1.0.0 -> 2.0.0 edge whose risk has a fleet-wide expression.
+    fn input_graph() -> Graph {
+        let mut graph = cincinnati::testing::generate_graph(true, false);
+        let conditional_edges = graph.conditional_edges.as_mut().unwrap();
+        conditional_edges[0].risks[0].fleet_evaluation = Some(PromQLClusterCondition {
+            promql: PROMQL.to_string(),
+        });
+        let (from, to) = (
+            graph.find_by_version("1.0.0").unwrap(),
+            graph.find_by_version("2.0.0").unwrap(),
+        );
+        graph.remove_edge(&from, &to).unwrap();
+        graph
+    }
+
+    fn mock_query(path: &str, value: &str) -> mockito::Mock {
+        mockito::mock("GET", format!("{}/api/v1/query", path).as_str())
+            .match_query(mockito::Matcher::UrlEncoded(
+                "query".to_string(),
+                PROMQL.to_string(),
+            ))
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(format!(
+                r#"{{"status":"success","data":{{"resultType":"vector","result":[{{"metric":{{}},"value":[1650000000.0,"{}"]}}]}}}}"#,
+                value
+            ))
+    }
+
+    fn run(plugin: &ConditionalRiskEvaluatorPlugin) -> Fallible<Graph> {
+        let runtime = init_runtime()?;
+        let io = runtime.block_on(plugin.run_internal(InternalIO {
+            graph: input_graph(),
+            parameters: Default::default(),
+        }))?;
+        Ok(io.graph)
+    }
+
+    #[test]
+    fn resolve_risks_by_state() {
+        let states = |state| -> HashMap<String, RiskState> {
+            vec![(PROMQL.to_string(), state)].into_iter().collect()
+        };
+
+        let mut graph = input_graph();
+        resolve_risks(&mut graph, &states(RiskState::Applies));
+        let risk = &graph.conditional_edges.as_ref().unwrap()[0].risks[0];
+        assert_eq!(risk.matching_rules.len(), 1);
+        assert_eq!(risk.matching_rules[0].condition_type, "Always");
+        assert!(risk.matching_rules[0].promql.is_empty());
+
+        let mut graph = input_graph();
+        resolve_risks(&mut graph, &states(RiskState::Unknown));
+        assert_eq!(graph, input_graph());
+
+        let mut graph = input_graph();
+        let edges_count = graph.edges_count();
+        resolve_risks(&mut graph, &states(RiskState::DoesNotApply));
+        assert!(graph.conditional_edges().is_empty());
+        assert_eq!(graph.edges_count(), edges_count + 1);
+    }
+
+    #[test]
+    fn evaluates_and_caches_risks() -> Fallible<()> {
+        let _m = mock_query("/cached", "0").expect(1).create();
+
+        let plugin = ConditionalRiskEvaluatorPlugin::try_new(settings("/cached"), None)?;
+        for _ in 0..2 {
+            let graph = run(&plugin)?;
+            assert!(graph.conditional_edges().is_empty());
+        }
+
+        _m.assert();
+        assert_eq!(plugin.queries.get(), 1);
+        assert_eq!(plugin.query_errors.get(), 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn failed_evaluation_keeps_risks() -> Fallible<()> {
+        let _m = mockito::mock("GET", "/failing/api/v1/query")
+            .match_query(mockito::Matcher::Any)
+            .with_status(400)
+            .with_header("content-type", "application/json")
+            .with_body(r#"{"status":"error","errorType":"bad_data","error":"parse error"}"#)
+            .create();
+
+        let plugin = ConditionalRiskEvaluatorPlugin::try_new(settings("/failing"), None)?;
+        assert_eq!(run(&plugin)?, input_graph());
+        assert_eq!(plugin.query_errors.get(), 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn deserialize_config_validation() {
+        let cfg = |s: &str| toml::from_str::<toml::Value>(s).unwrap();
+
+        assert!(ConditionalRiskEvaluatorSettings::deserialize_config(cfg(
+            r#"url = "http://thanos-querier:9091""#
+        ))
+        .is_ok());
+        assert!(ConditionalRiskEvaluatorSettings::deserialize_config(cfg("")).is_err());
+        assert!(ConditionalRiskEvaluatorSettings::deserialize_config(cfg(r#"
+            url = "http://thanos-querier:9091"
+            interval_secs = 0
+            "#))
+        .is_err());
+    }
+}
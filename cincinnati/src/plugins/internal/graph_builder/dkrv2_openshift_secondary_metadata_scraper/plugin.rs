@@ -1,7 +1,7 @@
 use crate as cincinnati;
 use crate::plugins::internal::dkrv2_openshift_secondary_metadata_scraper::gpg;
 use crate::plugins::internal::release_scrape_dockerv2::registry;
-use reqwest::{Client, ClientBuilder};
+use reqwest::Client;
 use std::path::{Path, PathBuf};
 use std::time::Duration;
 use tempfile::TempDir;
@@ -66,6 +66,20 @@ pub struct DkrV2OpenshiftSecondaryMetadataScraperSettings {
     #[default(Option::None)]
     credentials_path: Option<PathBuf>,
 
+    /// File containing PEM-encoded CA certificates to trust for the registry,
+    /// in addition to the system ones.
+    #[default(Option::None)]
+    ca_bundle_path: Option<PathBuf>,
+
+    /// File containing the PEM-encoded client certificate presented to the
+    /// registry and the signature server.
+    #[default(Option::None)]
+    client_cert_path: Option<PathBuf>,
+
+    /// File containing the PKCS#8 PEM-encoded key of the client certificate.
+    #[default(Option::None)]
+    client_key_path: Option<PathBuf>,
+
     /// Ensure signatures are verified
     #[default(false)]
     verify_signature: bool,
@@ -122,7 +136,7 @@ impl DkrV2OpenshiftSecondaryMetadataScraperSettings {
                 "invalid signature base url",
             );
             ensure!(
-                !settings.public_keys_path.is_none(),
+                settings.public_keys_path.is_some(),
                 "empty public keys path",
             );
         }
@@ -148,6 +162,7 @@ pub struct DkrV2OpenshiftSecondaryMetadataScraperPlugin {
     state: FuturesMutex<State>,
     http_client: Client,
     registry: registry::Registry,
+    tls: registry::ClientTls,
 }
 
 impl DkrV2OpenshiftSecondaryMetadataScraperPlugin {
@@ -190,7 +205,14 @@ impl DkrV2OpenshiftSecondaryMetadataScraperPlugin {
             settings.username = username;
             settings.password = password;
         }
-        let http_client = ClientBuilder::new()
+        let tls = registry::ClientTls::try_new(
+            settings.ca_bundle_path.as_deref(),
+            settings.client_cert_path.as_deref(),
+            settings.client_key_path.as_deref(),
+        )?;
+
+        let http_client = tls
+            .configure(commons::outbound::client_builder())
             .gzip(true)
             .timeout(Duration::from_secs(DEFAULT_SIGNATURE_FETCH_TIMEOUT_SECS))
             .build()
@@ -202,6 +224,7 @@ impl DkrV2OpenshiftSecondaryMetadataScraperPlugin {
             data_dir,
             http_client,
             registry,
+            tls,
             state: FuturesMutex::new(State::default()),
         })
     }
@@ -219,28 +242,19 @@ impl InternalPlugin for DkrV2OpenshiftSecondaryMetadataScraperPlugin {
     const PLUGIN_NAME: &'static str = Self::PLUGIN_NAME;
 
     async fn run_internal(&self, mut io: InternalIO) -> Fallible<InternalIO> {
-        let registry_client = registry::new_registry_client(
+        let registry_client = registry::http::RepositoryClient::try_new(
             &self.registry,
             &self.settings.repository,
             self.settings.username.as_deref(),
             self.settings.password.as_deref(),
-        )
-        .await?;
+            &self.tls,
+        )?;
 
-        let (manifest, reference) = registry_client
-            .get_manifest_and_ref(&self.settings.repository, &self.settings.tag)
-            .await?;
+        let (manifest, reference) =
+            registry::manifest::fetch(&registry_client, &self.settings.tag).await?;
         trace!("manifest: {:?}, reference: {:?}", manifest, reference);
 
         if self.settings.verify_signature {
-            let reference = reference.ok_or_else(|| {
-                format_err!(
-                    "no manifestref found for {}:{}",
-                    &self.settings.repository,
-                    &self.settings.tag
-                )
-            })?;
-
             let public_keys = self.settings.public_keys_path.as_ref().unwrap();
             let base_url = Url::parse(self.settings.signature_baseurl.as_str()).unwrap();
 
@@ -260,7 +274,7 @@ impl InternalPlugin for DkrV2OpenshiftSecondaryMetadataScraperPlugin {
             use futures::TryStreamExt;
             layers
                 .iter()
-                .map(|layer| registry_client.get_blob(&self.settings.repository, layer))
+                .map(|layer| registry_client.get_blob(layer))
                 .collect::<futures::stream::FuturesOrdered<_>>()
                 .try_collect::<Vec<_>>()
                 .await?
@@ -329,7 +343,7 @@ impl DkrV2OpenshiftSecondaryMetadataScraperPlugin {
         P: AsRef<Path>,
         P: std::fmt::Debug,
     {
-        dkregistry::render::unpack(layers_blobs, data_dir.as_ref())?;
+        unpack(layers_blobs, data_dir.as_ref())?;
         trace!(
             "Unpacked {}/{} with {} layers to {:?}",
             self.settings.registry,
@@ -355,7 +369,7 @@ impl DkrV2OpenshiftSecondaryMetadataScraperPlugin {
             .try_for_each(|entry_result| -> Fallible<()> {
                 let entry = entry_result?;
                 let path = entry.path();
-                let path_stripped = path.strip_prefix(&data_dir)?;
+                let path_stripped = path.strip_prefix(data_dir)?;
                 if let Some(path_stripped_str) = path_stripped.to_str() {
                     if !path_stripped_str.is_empty()
                         && !self
@@ -388,6 +402,113 @@ impl DkrV2OpenshiftSecondaryMetadataScraperPlugin {
     }
 }
 
+/// Unpack the gzipped tar layers of an image into `target`, in order.
+///
+/// Files hidden by a whiteout entry of a later layer are removed, opaque
+/// whiteouts are ignored.
+fn unpack(layers: &[Vec<u8>], target: &Path) -> Fallible<()> {
+    use flate2::read::GzDecoder;
+    use tar::Archive;
+
+    ensure!(
+        target.is_absolute() && target.is_dir(),
+        "{:?} is not an absolute path to a directory",
+        target
+    );
+
+    for layer in layers {
+        let mut archive = Archive::new(GzDecoder::new(layer.as_slice()));
+        archive.set_preserve_permissions(true);
+        archive.unpack(target).context("Unpacking layer")?;
+
+        let mut archive = Archive::new(GzDecoder::new(layer.as_slice()));
+        for entry in archive.entries().context("Reading layer")? {
+            let entry = entry.context("Reading layer entry")?;
+            let path = entry.path()?;
+            let name = match path.file_name().and_then(|name| name.to_str()) {
+                Some(name) if name != ".wh..wh..opq" => name,
+                _ => continue,
+            };
+            if let Some(hidden) = name.strip_prefix(".wh.") {
+                let parent = target.join(path.parent().unwrap_or_else(|| Path::new("")));
+                remove_path(&parent.join(hidden))?;
+                remove_path(&parent.join(name))?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Remove a file or a directory and its content, if it exists.
+fn remove_path(path: &Path) -> Fallible<()> {
+    let result = if path.is_dir() {
+        std::fs::remove_dir_all(path)
+    } else {
+        std::fs::remove_file(path)
+    };
+    match result {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+            Err(e).context(format!("Removing {:?}", path))
+        }
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn layer(files: &[(&str, &str)]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(flate2::write::GzEncoder::new(
+            vec![],
+            flate2::Compression::default(),
+        ));
+        for (path, content) in files {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(content.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder
+                .append_data(&mut header, path, content.as_bytes())
+                .unwrap();
+        }
+        builder.into_inner().unwrap().finish().unwrap()
+    }
+
+    #[test]
+    fn unpack_layers_with_whiteouts() -> Fallible<()> {
+        let target = tempfile::tempdir()?;
+        let layers = vec![
+            layer(&[
+                ("version", "1.0.0"),
+                ("channels/stable-4.1.yaml", "name: stable-4.1"),
+                ("blocked-edges/4.1.0.yaml", "to: 4.1.0"),
+            ]),
+            layer(&[
+                ("version", "1.1.0"),
+                ("blocked-edges/.wh.4.1.0.yaml", ""),
+                (".wh.channels", ""),
+            ]),
+        ];
+
+        unpack(&layers, target.path())?;
+        assert_eq!(
+            std::fs::read_to_string(target.path().join("version"))?,
+            "1.1.0"
+        );
+        assert!(target.path().join("blocked-edges").is_dir());
+        assert!(!target.path().join("blocked-edges/4.1.0.yaml").exists());
+        assert!(!target.path().join("blocked-edges/.wh.4.1.0.yaml").exists());
+        assert!(!target.path().join("channels").exists());
+        assert!(!target.path().join(".wh.channels").exists());
+
+        assert!(unpack(&layers, Path::new("relative")).is_err());
+
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 #[cfg(feature = "test-net")]
 mod network_tests {
@@ -463,14 +584,11 @@ mod network_tests {
                 .collect::<Vec<regex::Regex>>();
             assert!(!regexes.is_empty(), "no regexes compiled");
 
-            let data_dir = if let cincinnati::plugins::PluginIO::InternalIO(iio) = io {
-                iio.parameters
-                    .get(GRAPH_DATA_DIR_PARAM_KEY)
-                    .map(PathBuf::from)
-                    .unwrap()
-            } else {
-                bail!("expected plugin to return InternalIO");
-            };
+            let data_dir = cincinnati::testing::internal_io(io)?
+                .parameters
+                .get(GRAPH_DATA_DIR_PARAM_KEY)
+                .map(PathBuf::from)
+                .unwrap();
 
             assert!(
                 data_dir.starts_with(tmpdir.path()),
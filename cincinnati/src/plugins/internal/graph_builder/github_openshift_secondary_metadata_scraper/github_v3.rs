@@ -4,6 +4,9 @@
 
 use serde::Deserialize;
 
+/// Base URL of the GitHub API.
+pub static DEFAULT_API_URL: &str = "https://api.github.com";
+
 /// Commit structure.
 #[derive(Default, Clone, Debug, Deserialize, PartialEq, Eq)]
 pub(crate) struct Commit {
@@ -11,27 +14,19 @@ pub(crate) struct Commit {
     pub(crate) url: String,
 }
 
-/// Branch structure.
-#[derive(Debug, Deserialize, PartialEq, Eq)]
-pub(crate) struct Branch {
-    pub(crate) name: String,
-    pub(crate) commit: Commit,
-    pub(crate) protected: bool,
-}
+/// Media type of the commits API answering with the bare commit SHA.
+pub(crate) static SHA_MEDIA_TYPE: &str = "application/vnd.github.sha";
 
-/// Format the URL to request branch information.
-pub(crate) fn branches_url(org: &str, repo: &str) -> String {
-    format!(
-        "https://api.github.com/repos/{org}/{repo}/branches",
-        org = &org,
-        repo = &repo,
-    )
+/// Whether `revision` is a full commit SHA, rather than a branch, a tag or an abbreviated SHA.
+pub(crate) fn is_full_sha(revision: &str) -> bool {
+    revision.len() == 40 && revision.chars().all(|c| c.is_ascii_hexdigit())
 }
 
 /// Format the URL to request a tarball URL.
-pub(crate) fn tarball_url(org: &str, repo: &str, commit: &Commit) -> String {
+pub(crate) fn tarball_url(api_url: &str, org: &str, repo: &str, commit: &Commit) -> String {
     format!(
-        "https://api.github.com/repos/{org}/{repo}/tarball/{sha}",
+        "{api_url}/repos/{org}/{repo}/tarball/{sha}",
+        api_url = api_url.trim_end_matches('/'),
         org = org,
         repo = repo,
         sha = commit.sha,
@@ -44,10 +39,13 @@ pub(crate) fn archive_entry_directory_name(org: &str, repo: &str, commit: &Commi
 }
 
 /// Format a commit URL
-pub(crate) fn commit_url(org: &str, repo: &str, sha: &str) -> String {
+pub(crate) fn commit_url(api_url: &str, org: &str, repo: &str, sha: &str) -> String {
     format!(
-        "https://api.github.com/repos/{}/{}/commits/{}",
-        org, repo, sha
+        "{}/repos/{}/{}/commits/{}",
+        api_url.trim_end_matches('/'),
+        org,
+        repo,
+        sha
     )
 }
 
@@ -56,31 +54,25 @@ mod tests {
     use super::*;
 
     #[test]
-    fn de_serialize_branch() {
-        let json = r#"
-            [
-                {
-                    "name": "master",
-                    "commit": {
-                        "sha": "fef06adb57b9d965bfc9ae0959bd038f3044207e",
-                        "url": "https://api.github.com/repos/openshift/cincinnati-graph-data/commits/fef06adb57b9d965bfc9ae0959bd038f3044207e"
-                    },
-                    "protected": true
-                }
-            ]
-            "#;
-
-        let branches = serde_json::from_str::<Vec<Branch>>(json).unwrap();
-
-        let branches_expected = vec![Branch {
-                name: "master".to_string(),
-                commit: Commit {
-                        sha: "fef06adb57b9d965bfc9ae0959bd038f3044207e".to_string(),
-                        url: "https://api.github.com/repos/openshift/cincinnati-graph-data/commits/fef06adb57b9d965bfc9ae0959bd038f3044207e".to_string()
-                },
-                protected: true
-            }];
+    fn full_sha() {
+        assert!(is_full_sha("fef06adb57b9d965bfc9ae0959bd038f3044207e"));
+        assert!(!is_full_sha("fef06ad"));
+        assert!(!is_full_sha("master"));
+        assert!(!is_full_sha(
+            "release-4.16-fef06adb57b9d965bfc9ae0959bd038f30442"
+        ));
+    }
 
-        assert_eq!(branches_expected, branches);
+    #[test]
+    fn format_commit_url() {
+        assert_eq!(
+            commit_url(
+                "https://github.example.com/api/v3/",
+                "openshift",
+                "graph-data",
+                "v1.0"
+            ),
+            "https://github.example.com/api/v3/repos/openshift/graph-data/commits/v1.0"
+        );
     }
 }
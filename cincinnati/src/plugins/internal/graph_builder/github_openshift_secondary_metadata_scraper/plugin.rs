@@ -6,6 +6,8 @@ use crate as cincinnati;
 use self::cincinnati::plugins::prelude::*;
 use self::cincinnati::plugins::prelude_plugin_impl::*;
 
+use prometheus::{IntCounterVec, Opts};
+use reqwest::{header, StatusCode};
 use tokio::sync::Mutex as FuturesMutex;
 
 pub static DEFAULT_OUTPUT_WHITELIST: &[&str] = &[
@@ -25,13 +27,12 @@ lazy_static::lazy_static! {
 /// Environment variable name for the Oauth token path
 pub static GITHUB_SCRAPER_TOKEN_PATH_ENV: &str = "CINCINNATI_GITHUB_SCRAPER_OAUTH_TOKEN_PATH";
 
-static USER_AGENT: &str = "openshift/cincinnati";
-
 /// Models the scrape mode
 #[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(rename_all = "lowercase")]
 pub enum Reference {
     Branch(String),
+    Tag(String),
     Revision(String),
 }
 
@@ -39,26 +40,41 @@ impl Reference {
     fn get_inner(&self) -> &String {
         match self {
             Self::Branch(s) => s,
+            Self::Tag(s) => s,
             Self::Revision(s) => s,
         }
     }
+
+    /// Return the reference as understood by the commits API.
+    ///
+    /// Branches and tags are fully qualified, so that a tag and a branch of
+    /// the same name are not mistaken for each other.
+    fn commitish(&self) -> String {
+        match self {
+            Self::Branch(branch) => format!("refs/heads/{}", branch),
+            Self::Tag(tag) => format!("refs/tags/{}", tag),
+            Self::Revision(revision) => revision.to_string(),
+        }
+    }
 }
 
-impl TryFrom<(Option<&String>, Option<&String>)> for Reference {
+impl TryFrom<(Option<&String>, Option<&String>, Option<&String>)> for Reference {
     type Error = Error;
 
-    fn try_from(options: (Option<&String>, Option<&String>)) -> Fallible<Self> {
-        let reference = match (options.0, options.1) {
-            (Some(branch), Some(revision)) => {
+    fn try_from(options: (Option<&String>, Option<&String>, Option<&String>)) -> Fallible<Self> {
+        let reference = match options {
+            (None, None, None) => Reference::Branch(DEFAULT_REFERENCE_BRANCH.clone().unwrap()),
+            (Some(branch), None, None) => Reference::Branch(branch.to_string()),
+            (None, Some(tag), None) => Reference::Tag(tag.to_string()),
+            (None, None, Some(revision)) => Reference::Revision(revision.to_string()),
+            (branch, tag, revision) => {
                 bail!(
-                    "only one of reference_branch or reference_revision can be set. got {:?} and {:?}",
+                    "only one of reference_branch, reference_tag or reference_revision can be set. got {:?}, {:?} and {:?}",
                     branch,
+                    tag,
                     revision,
                 );
             }
-            (None, None) => Reference::Branch(DEFAULT_REFERENCE_BRANCH.clone().unwrap()),
-            (Some(branch), None) => Reference::Branch(branch.to_string()),
-            (None, Some(revision)) => Reference::Revision(revision.to_string()),
         };
 
         Ok(reference)
@@ -69,6 +85,10 @@ impl TryFrom<(Option<&String>, Option<&String>)> for Reference {
 #[derive(Debug, SmartDefault, Clone, Deserialize)]
 #[serde(default)]
 pub struct GithubOpenshiftSecondaryMetadataScraperSettings {
+    /// Base URL of the GitHub API.
+    #[default(github_v3::DEFAULT_API_URL.to_string())]
+    api_url: String,
+
     github_org: String,
     github_repo: String,
     output_directory: PathBuf,
@@ -76,6 +96,9 @@ pub struct GithubOpenshiftSecondaryMetadataScraperSettings {
     /// Defines the reference branch to be scraped.
     reference_branch: Option<String>,
 
+    /// Defines the reference tag to be scraped.
+    reference_tag: Option<String>,
+
     /// Defines the reference revision to be scraped.
     reference_revision: Option<String>,
 
@@ -90,6 +113,8 @@ pub struct GithubOpenshiftSecondaryMetadataScraperSettings {
     /// An empty vector is regarded as a configuration error.
     #[default(DEFAULT_OUTPUT_WHITELIST.iter().map(|s| (*s).to_string()).collect())]
     output_allowlist: Vec<String>,
+
+    /// File containing the GitHub token, for private repositories and higher rate limits.
     oauth_token_path: Option<PathBuf>,
 }
 
@@ -101,11 +126,13 @@ impl GithubOpenshiftSecondaryMetadataScraperSettings {
             .try_into()
             .context(format!("Deserializing {:#?}", &cfg))?;
 
+        ensure!(!settings.api_url.is_empty(), "empty api_url");
         ensure!(!settings.github_org.is_empty(), "empty github_org");
         ensure!(!settings.github_repo.is_empty(), "empty github_repo");
 
         let reference: Reference = (
             settings.reference_branch.as_ref(),
+            settings.reference_tag.as_ref(),
             settings.reference_revision.as_ref(),
         )
             .try_into()?;
@@ -133,8 +160,51 @@ impl GithubOpenshiftSecondaryMetadataScraperSettings {
 pub struct State {
     commit_wanted: Option<github_v3::Commit>,
     commit_completed: Option<github_v3::Commit>,
+
+    /// Validators of the last commit response, along with the commit it contained.
+    commit_cached: Option<(Validators, github_v3::Commit)>,
+}
+
+/// Validators of a response, for conditional requests.
+#[derive(Clone, Debug, Default)]
+struct Validators {
+    etag: Option<String>,
+    last_modified: Option<String>,
+}
+
+impl Validators {
+    fn of(response: &reqwest::Response) -> Self {
+        let value = |name: header::HeaderName| {
+            response
+                .headers()
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string)
+        };
+        Self {
+            etag: value(header::ETAG),
+            last_modified: value(header::LAST_MODIFIED),
+        }
+    }
+
+    /// Make `request` conditional on the validators.
+    fn apply(&self, mut request: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        if let Some(etag) = &self.etag {
+            request = request.header(header::IF_NONE_MATCH, etag);
+        }
+        if let Some(last_modified) = &self.last_modified {
+            request = request.header(header::IF_MODIFIED_SINCE, last_modified);
+        }
+        request
+    }
 }
 
+/// Label of the cache hits of the commit requests.
+static CACHE_HIT_COMMITS: &str = "commits";
+
+/// Label of the cache hits of the tarball downloads.
+static CACHE_HIT_TARBALL: &str = "tarball";
+
 /// Plugin.
 #[derive(Debug)]
 pub struct GithubOpenshiftSecondaryMetadataScraperPlugin {
@@ -148,13 +218,19 @@ pub struct GithubOpenshiftSecondaryMetadataScraperPlugin {
 
     client: reqwest::Client,
     data_dir: tempfile::TempDir,
+
+    /// Requests answered from the cache, by request.
+    cache_hits: IntCounterVec,
 }
 
 impl GithubOpenshiftSecondaryMetadataScraperPlugin {
     pub(crate) const PLUGIN_NAME: &'static str = "github-secondary-metadata-scrape";
 
     /// Instantiate a new instance of `Self`.
-    pub fn try_new(settings: GithubOpenshiftSecondaryMetadataScraperSettings) -> Fallible<Self> {
+    pub fn try_new(
+        settings: GithubOpenshiftSecondaryMetadataScraperSettings,
+        prometheus_registry: Option<&prometheus::Registry>,
+    ) -> Fallible<Self> {
         let output_allowlist: Vec<regex::Regex> = settings
             .output_allowlist
             .iter()
@@ -168,20 +244,20 @@ impl GithubOpenshiftSecondaryMetadataScraperPlugin {
             )
             .context("Parsing output allowlist strings as regex")?;
 
-        let oauth_token = (&settings.oauth_token_path)
+        let oauth_token = settings
+            .oauth_token_path
             .clone()
             .map(|path| {
                 std::fs::read_to_string(&path)
                     .context(format!("Reading Oauth token from {:?}", &path))
             })
             .transpose()?
-            .map(|token| {
+            .and_then(|token| {
                 token
                     .lines()
                     .next()
                     .map(|first_line| first_line.trim().to_owned())
-            })
-            .flatten();
+            });
 
         // Create the output directory if it doesn't exist
         std::fs::create_dir_all(&settings.output_directory).context(format!(
@@ -191,6 +267,17 @@ impl GithubOpenshiftSecondaryMetadataScraperPlugin {
 
         let data_dir = tempfile::tempdir_in(&settings.output_directory)?;
 
+        let cache_hits = IntCounterVec::new(
+            Opts::new(
+                "graph_data_cache_hits_total",
+                "Total number of graph-data fetches skipped as the upstream was unchanged",
+            ),
+            &["request"],
+        )?;
+        if let Some(registry) = &prometheus_registry {
+            registry.register(Box::new(cache_hits.clone()))?;
+        }
+
         Ok(Self {
             reference: settings
                 .reference
@@ -200,77 +287,114 @@ impl GithubOpenshiftSecondaryMetadataScraperPlugin {
             output_allowlist,
             oauth_token,
             data_dir,
+            cache_hits,
 
             state: FuturesMutex::new(State::default()),
-            client: reqwest::Client::default(),
+            client: commons::outbound::client_builder()
+                .build()
+                .context("Building reqwest client")?,
         })
     }
 
-    /// Lookup the latest commit on the given branch.
-    async fn get_commit_wanted_branch(&self, branch_wanted: &str) -> Fallible<github_v3::Commit> {
-        let url = github_v3::branches_url(&self.settings.github_org, &self.settings.github_repo);
+    /// Add the GitHub token to `request`, if any.
+    fn authorize(&self, request: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        match &self.oauth_token {
+            Some(token) => {
+                request.header(reqwest::header::AUTHORIZATION, format!("token {}", token))
+            }
+            None => request,
+        }
+    }
+
+    /// Lookup the commit the given branch, tag or revision points to, through the commits API.
+    ///
+    /// The commit is requested conditionally on the validators of the
+    /// previous response, so that unchanged references are served from the
+    /// cache without counting against the GitHub rate limit.
+    async fn get_commit_wanted_ref(&self, reference: &str) -> Fallible<github_v3::Commit> {
+        let url = github_v3::commit_url(
+            &self.settings.api_url,
+            &self.settings.github_org,
+            &self.settings.github_repo,
+            reference,
+        );
+        let cached = self.state.lock().await.commit_cached.clone();
 
-        trace!("Getting branches from {}", &url);
+        trace!("Getting commit from {}", &url);
 
         let request = {
             let request = self
                 .client
                 .get(&url)
-                .header(reqwest::header::USER_AGENT, USER_AGENT)
-                .header(reqwest::header::ACCEPT, "application/vnd.github.v3+json");
-            if let Some(token) = &self.oauth_token {
-                request.header(reqwest::header::AUTHORIZATION, format!("token {}", token))
-            } else {
-                request
+                .header(reqwest::header::ACCEPT, github_v3::SHA_MEDIA_TYPE);
+            let request = self.authorize(request);
+            match &cached {
+                Some((validators, _)) => validators.apply(request),
+                None => request,
             }
         };
 
-        let bytes = request
+        let response = request
             .send()
             .await
-            .context(format!("Getting branches from {}", &url))?
-            .bytes()
-            .await
-            .context(format!("Getting bytes from request to {}", &url))?;
+            .context(format!("Getting commit from {}", &url))?;
 
-        let json = std::str::from_utf8(&bytes).context("Parsing body as string")?;
-
-        let branches = serde_json::from_str::<Vec<github_v3::Branch>>(json)
-            .context(format!("Parsing {} to Vec<Branch>", &json))?;
-
-        let latest_commit = branches
-            .iter()
-            .filter_map(|branch| {
-                if branch.name == branch_wanted {
-                    Some(branch.commit.clone())
-                } else {
-                    None
-                }
-            })
-            .next()
-            .ok_or_else(|| {
-                format_err!(format!(
-                    "{}/{} does not have branch {}: {:#?}",
-                    &self.settings.github_org,
-                    &self.settings.github_repo,
-                    &branch_wanted,
-                    &branches
-                ))
+        if response.status() == StatusCode::NOT_MODIFIED {
+            let (_, commit) = cached.ok_or_else(|| {
+                format_err!(
+                    "{} answered 304 Not Modified to an unconditional request",
+                    &url
+                )
             })?;
+            trace!("Reference {} unchanged, commit: {:?}", &reference, &commit);
+            self.cache_hits
+                .with_label_values(&[CACHE_HIT_COMMITS])
+                .inc();
+            return Ok(commit);
+        }
+
+        // GitHub answers 404 Not Found for private repositories without a valid token too.
+        if response.status() == StatusCode::NOT_FOUND
+            || response.status() == StatusCode::UNPROCESSABLE_ENTITY
+        {
+            bail!(
+                "{}/{} does not have {}, or is private and requires oauth_token_path",
+                &self.settings.github_org,
+                &self.settings.github_repo,
+                &reference
+            );
+        }
 
-        trace!(
-            "Latest commit on branch {}: {:?}",
-            &branch_wanted,
-            &latest_commit
+        let response = response
+            .error_for_status()
+            .context(format!("Getting commit from {}", &url))?;
+        let validators = Validators::of(&response);
+        let sha = response
+            .text()
+            .await
+            .context(format!("Getting body from request to {}", &url))?
+            .trim()
+            .to_string();
+        ensure!(
+            github_v3::is_full_sha(&sha),
+            "{} answered an invalid commit SHA {:?}",
+            &url,
+            &sha
         );
+        let commit = self.get_commit_wanted_revision(&sha);
+
+        trace!("Commit of reference {}: {:?}", &reference, &commit);
 
-        Ok(latest_commit)
+        self.state.lock().await.commit_cached = Some((validators, commit.clone()));
+
+        Ok(commit)
     }
 
     /// Construct a github_v3::Commit from the given revision
-    async fn get_commit_wanted_revision(&self, revision: &str) -> github_v3::Commit {
+    fn get_commit_wanted_revision(&self, revision: &str) -> github_v3::Commit {
         github_v3::Commit {
             url: github_v3::commit_url(
+                &self.settings.api_url,
                 &self.settings.github_org,
                 &self.settings.github_repo,
                 revision,
@@ -280,10 +404,15 @@ impl GithubOpenshiftSecondaryMetadataScraperPlugin {
     }
 
     /// Refresh `self.state.commit_wanted` and determine if an update is required.
+    ///
+    /// Full commit SHAs are used as they are, while branches, tags and
+    /// abbreviated SHAs are resolved through the commits API.
     async fn refresh_commit_wanted(&self) -> Fallible<bool> {
         let commit_wanted = match &self.reference {
-            Reference::Revision(revision) => self.get_commit_wanted_revision(revision).await,
-            Reference::Branch(branch) => self.get_commit_wanted_branch(branch).await?,
+            Reference::Revision(revision) if github_v3::is_full_sha(revision) => {
+                self.get_commit_wanted_revision(revision)
+            }
+            reference => self.get_commit_wanted_ref(&reference.commitish()).await?,
         };
 
         let mut state = self.state.lock().await;
@@ -293,7 +422,7 @@ impl GithubOpenshiftSecondaryMetadataScraperPlugin {
             None => true,
         };
 
-        (*state).commit_wanted = Some(commit_wanted);
+        state.commit_wanted = Some(commit_wanted);
 
         Ok(should_update)
     }
@@ -309,26 +438,29 @@ impl GithubOpenshiftSecondaryMetadataScraperPlugin {
         };
 
         let url = github_v3::tarball_url(
+            &self.settings.api_url,
             &self.settings.github_org,
             &self.settings.github_repo,
             &commit_wanted,
         );
 
         trace!("Downloading {:?} from {}", &commit_wanted, &url);
-        reqwest::Client::new()
+        let request = self
+            .client
             .get(&url)
-            .header(reqwest::header::USER_AGENT, USER_AGENT)
-            .header(reqwest::header::ACCEPT, "application/vnd.github.v3.raw")
+            .header(reqwest::header::ACCEPT, "application/vnd.github.v3.raw");
+        self.authorize(request)
             .send()
             .await
             .context(format!("Updating from tarball at {}", &url))?
+            .error_for_status()
+            .context(format!("Updating from tarball at {}", &url))?
             .bytes()
             .await
             .context(format!(
                 "Getting bytes from the request response to {}",
                 &url,
             ))
-            .map_err(Into::into)
             .map(|bytes| (commit_wanted, bytes.to_vec().into_boxed_slice()))
     }
 
@@ -437,8 +569,9 @@ impl GithubOpenshiftSecondaryMetadataScraperPlugin {
 }
 
 impl PluginSettings for GithubOpenshiftSecondaryMetadataScraperSettings {
-    fn build_plugin(&self, _: Option<&prometheus::Registry>) -> Fallible<BoxedPlugin> {
-        let plugin = GithubOpenshiftSecondaryMetadataScraperPlugin::try_new(self.clone())?;
+    fn build_plugin(&self, registry: Option<&prometheus::Registry>) -> Fallible<BoxedPlugin> {
+        let plugin =
+            GithubOpenshiftSecondaryMetadataScraperPlugin::try_new(self.clone(), registry)?;
         Ok(new_plugin!(InternalPluginWrapper(plugin)))
     }
 }
@@ -470,6 +603,12 @@ impl InternalPlugin for GithubOpenshiftSecondaryMetadataScraperPlugin {
             self.extract(commit, blob)
                 .await
                 .context("Extracting tarball")?;
+        } else {
+            // The tarball of a commit never changes, so its SHA validates the extracted one.
+            trace!("Graph-data unchanged, skipping the tarball download");
+            self.cache_hits
+                .with_label_values(&[CACHE_HIT_TARBALL])
+                .inc();
         };
 
         Ok(io)
@@ -561,3 +700,187 @@ mod network_tests {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn archive(files: &[&str]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(flate2::write::GzEncoder::new(
+            vec![],
+            flate2::Compression::default(),
+        ));
+        for path in files {
+            let mut header = tar::Header::new_gnu();
+            header.set_entry_type(tar::EntryType::Regular);
+            header.set_size(0);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder.append_data(&mut header, path, &[][..]).unwrap();
+        }
+        builder.into_inner().unwrap().finish().unwrap()
+    }
+
+    #[test]
+    fn skip_unchanged_graph_data() -> Fallible<()> {
+        let runtime = commons::testing::init_runtime()?;
+        let tmpdir = tempfile::tempdir()?;
+        let sha = "fef06adb57b9d965bfc9ae0959bd038f3044207e";
+
+        let _unchanged = mockito::mock(
+            "GET",
+            "/repos/openshift/graph-data/commits/refs/heads/master",
+        )
+        .match_header("if-none-match", "\"v1\"")
+        .with_status(304)
+        .create();
+        let _commit = mockito::mock(
+            "GET",
+            "/repos/openshift/graph-data/commits/refs/heads/master",
+        )
+        .match_header("accept", "application/vnd.github.sha")
+        .match_header("if-none-match", mockito::Matcher::Missing)
+        .with_header("etag", "\"v1\"")
+        .with_body(sha)
+        .create();
+        let tarball = mockito::mock(
+            "GET",
+            format!("/repos/openshift/graph-data/tarball/{}", sha).as_str(),
+        )
+        .with_body(archive(&[
+            "openshift-graph-data-fef06ad/version",
+            "openshift-graph-data-fef06ad/channels/stable-4.10.yaml",
+        ]))
+        .expect(1)
+        .create();
+
+        let mut settings: GithubOpenshiftSecondaryMetadataScraperSettings =
+            toml::from_str(&format!(
+                r#"
+                    api_url = "{}"
+                    github_org = "openshift"
+                    github_repo = "graph-data"
+                    output_directory = {:?}
+                "#,
+                mockito::server_url(),
+                tmpdir.path(),
+            ))?;
+        settings.reference = Some(Reference::Branch("master".to_string()));
+        let plugin = GithubOpenshiftSecondaryMetadataScraperPlugin::try_new(settings, None)?;
+
+        for _ in 0..2 {
+            let io = runtime.block_on(plugin.run_internal(InternalIO {
+                graph: Default::default(),
+                parameters: Default::default(),
+            }))?;
+            let data_dir = PathBuf::from(&io.parameters[GRAPH_DATA_DIR_PARAM_KEY]);
+            assert!(data_dir.join("version").is_file());
+            assert!(data_dir.join("channels/stable-4.10.yaml").is_file());
+        }
+
+        tarball.assert();
+        let hits = |request: &str| plugin.cache_hits.with_label_values(&[request]).get();
+        assert_eq!(hits(CACHE_HIT_COMMITS), 1);
+        assert_eq!(hits(CACHE_HIT_TARBALL), 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn private_repository_tag() -> Fallible<()> {
+        let runtime = commons::testing::init_runtime()?;
+        let tmpdir = tempfile::tempdir()?;
+        let token_file = tmpdir.path().join("token");
+        std::fs::write(&token_file, "s3cr3t\n")?;
+        let sha = "0123456789abcdef0123456789abcdef01234567";
+
+        let _missing = mockito::mock(
+            "GET",
+            "/repos/openshift/private-data/commits/refs/tags/v0.9",
+        )
+        .with_status(404)
+        .create();
+        let _commit = mockito::mock(
+            "GET",
+            "/repos/openshift/private-data/commits/refs/tags/v1.0",
+        )
+        .match_header("authorization", "token s3cr3t")
+        .with_body(sha)
+        .create();
+        let tarball = mockito::mock(
+            "GET",
+            format!("/repos/openshift/private-data/tarball/{}", sha).as_str(),
+        )
+        .match_header("authorization", "token s3cr3t")
+        .with_body(archive(&["openshift-private-data-0123456/version"]))
+        .expect(1)
+        .create();
+
+        let plugin = |tag: &str| -> Fallible<GithubOpenshiftSecondaryMetadataScraperPlugin> {
+            let mut settings: GithubOpenshiftSecondaryMetadataScraperSettings =
+                toml::from_str(&format!(
+                    r#"
+                        api_url = "{}"
+                        github_org = "openshift"
+                        github_repo = "private-data"
+                        output_directory = {:?}
+                        oauth_token_path = {:?}
+                    "#,
+                    mockito::server_url(),
+                    tmpdir.path(),
+                    token_file,
+                ))?;
+            settings.reference = Some(Reference::Tag(tag.to_string()));
+            GithubOpenshiftSecondaryMetadataScraperPlugin::try_new(settings, None)
+        };
+        let io = || InternalIO {
+            graph: Default::default(),
+            parameters: Default::default(),
+        };
+
+        // The plugin owns the data directory, keep it alive for the assertions.
+        let tagged = plugin("v1.0")?;
+        let output = runtime.block_on(tagged.run_internal(io()))?;
+        let data_dir = PathBuf::from(&output.parameters[GRAPH_DATA_DIR_PARAM_KEY]);
+        assert!(data_dir.join("version").is_file());
+        tarball.assert();
+
+        let error = runtime
+            .block_on(plugin("v0.9")?.run_internal(io()))
+            .unwrap_err();
+        assert!(format!("{:#}", error).contains("does not have refs/tags/v0.9"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn deserialize_reference() -> Fallible<()> {
+        let reference = |branch: Option<&str>, tag: Option<&str>, revision: Option<&str>| {
+            let (branch, tag, revision) = (
+                branch.map(str::to_string),
+                tag.map(str::to_string),
+                revision.map(str::to_string),
+            );
+            Reference::try_from((branch.as_ref(), tag.as_ref(), revision.as_ref()))
+        };
+
+        assert!(matches!(reference(None, None, None)?, Reference::Branch(b) if b == "master"));
+        assert!(matches!(reference(None, Some("v1.0"), None)?, Reference::Tag(t) if t == "v1.0"));
+        assert_eq!(
+            Reference::Branch("v1.0".to_string()).commitish(),
+            "refs/heads/v1.0"
+        );
+        assert_eq!(
+            Reference::Tag("v1.0".to_string()).commitish(),
+            "refs/tags/v1.0"
+        );
+        assert_eq!(
+            Reference::Revision("fef06ad".to_string()).commitish(),
+            "fef06ad"
+        );
+        assert!(reference(Some("main"), Some("v1.0"), None).is_err());
+        assert!(reference(None, Some("v1.0"), Some("fef06ad")).is_err());
+
+        Ok(())
+    }
+}
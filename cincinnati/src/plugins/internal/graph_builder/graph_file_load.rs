@@ -0,0 +1,265 @@
+//! This plugin loads a pre-built graph from a local file.
+//!
+//! It replaces the scraping plugins in air-gapped deployments, where the graph
+//! is built elsewhere and shipped as a file in the Cincinnati JSON format. The
+//! file is optionally watched, so that a new graph is published as soon as the
+//! file is replaced.
+
+use crate as cincinnati;
+
+use self::cincinnati::plugins::internal::local_openshift_secondary_metadata_scraper::plugin::{
+    watch, WatchGuard,
+};
+use self::cincinnati::plugins::prelude::*;
+use self::cincinnati::plugins::prelude_plugin_impl::*;
+use self::cincinnati::Graph;
+
+use notify::{Event, EventKind, RecursiveMode};
+use std::path::Path;
+
+/// Plugin settings.
+#[derive(Clone, Debug, Deserialize, SmartDefault)]
+#[serde(default)]
+pub struct GraphFileLoadSettings {
+    /// File containing the graph, in the Cincinnati JSON format.
+    pub path: PathBuf,
+
+    /// Watch the file and report changes to the change hook.
+    #[default(true)]
+    pub watch: bool,
+}
+
+impl PluginSettings for GraphFileLoadSettings {
+    fn build_plugin(&self, _: Option<&prometheus::Registry>) -> Fallible<BoxedPlugin> {
+        let plugin = GraphFileLoadPlugin::try_new(self.clone())?;
+        Ok(new_plugin!(InternalPluginWrapper(plugin)))
+    }
+}
+
+impl GraphFileLoadSettings {
+    /// Validate plugin configuration and fill in defaults.
+    pub fn deserialize_config(cfg: toml::Value) -> Fallible<Box<dyn PluginSettings>> {
+        let settings: Self = cfg.try_into()?;
+
+        ensure!(!settings.path.as_os_str().is_empty(), "empty path");
+
+        Ok(Box::new(settings))
+    }
+}
+
+/// Returns whether an event may change the file at `path`.
+///
+/// The parent directory is watched, as files are usually replaced by renaming
+/// a new one over them. Kubernetes updates volumes by swapping the `..data`
+/// symlink the files link through, so changes to the entries of the directory
+/// starting with `..` are relevant too.
+fn is_relevant(event: &Event, path: &Path) -> bool {
+    if let EventKind::Access(_) = event.kind {
+        return false;
+    }
+
+    event.paths.iter().any(|changed| {
+        changed == path
+            || (changed.parent() == path.parent()
+                && changed
+                    .file_name()
+                    .and_then(|name| name.to_str())
+                    .is_some_and(|name| name.starts_with("..")))
+    })
+}
+
+/// Loads the graph from a local file.
+#[derive(Debug)]
+pub struct GraphFileLoadPlugin {
+    path: PathBuf,
+
+    _watch: Option<WatchGuard>,
+}
+
+impl GraphFileLoadPlugin {
+    /// Plugin name, for configuration.
+    pub const PLUGIN_NAME: &'static str = "graph-file-load";
+
+    /// Instantiate a new instance of `Self`.
+    ///
+    /// The configured path is kept as is, without resolving symlinks, so that
+    /// the file is found again once they are swapped.
+    pub fn try_new(settings: GraphFileLoadSettings) -> Fallible<Self> {
+        let path = settings.path;
+        ensure!(path.is_file(), "{:?} is not a file", &path);
+
+        let watch = if settings.watch {
+            let directory = match path.parent() {
+                Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+                _ => PathBuf::from("."),
+            };
+            let watched = directory.join(path.file_name().unwrap_or_default());
+            Some(watch(
+                &directory,
+                RecursiveMode::NonRecursive,
+                move |event| is_relevant(event, &watched),
+            )?)
+        } else {
+            None
+        };
+
+        Ok(Self {
+            path,
+            _watch: watch,
+        })
+    }
+}
+
+#[async_trait]
+impl InternalPlugin for GraphFileLoadPlugin {
+    const PLUGIN_NAME: &'static str = Self::PLUGIN_NAME;
+
+    async fn run_internal(&self, io: InternalIO) -> Fallible<InternalIO> {
+        let json = tokio::fs::read(&self.path)
+            .await
+            .context(format!("Reading graph file {:?}", &self.path))?;
+        let graph: Graph = serde_json::from_slice(&json)
+            .context(format!("Parsing graph file {:?}", &self.path))?;
+        debug!(
+            "loaded {} releases from {:?}",
+            graph.releases_count(),
+            &self.path
+        );
+
+        Ok(InternalIO {
+            graph,
+            parameters: io.parameters,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use self::cincinnati::plugins::PluginIO;
+    use self::cincinnati::testing::generate_graph;
+    use super::*;
+    use std::convert::TryInto;
+
+    #[test]
+    fn load_graph() -> Fallible<()> {
+        let runtime = commons::testing::init_runtime()?;
+        let tmpdir = tempfile::tempdir()?;
+        let path = tmpdir.path().join("graph.json");
+
+        let graph = generate_graph(false, false);
+        std::fs::write(&path, serde_json::to_string(&graph)?)?;
+
+        let settings = GraphFileLoadSettings::deserialize_config(toml::from_str(&format!(
+            "path = {:?}\nwatch = false",
+            &path
+        ))?)?;
+        let plugin = settings.build_plugin(None)?;
+        let run = || {
+            runtime.block_on(
+                plugin.run(PluginIO::InternalIO(InternalIO {
+                    graph: Default::default(),
+                    parameters: [("channel".to_string(), "stable-4.11".to_string())]
+                        .iter()
+                        .cloned()
+                        .collect(),
+                })),
+            )
+        };
+
+        let io: InternalIO = run()?.try_into()?;
+        assert_eq!(io.graph, graph);
+        assert_eq!(
+            io.parameters.get("channel").map(String::as_str),
+            Some("stable-4.11")
+        );
+
+        // An invalid file fails the scrape.
+        std::fs::write(&path, "{")?;
+        assert!(run().is_err());
+
+        let missing = GraphFileLoadSettings::deserialize_config(toml::from_str(&format!(
+            "path = {:?}",
+            tmpdir.path().join("missing.json")
+        ))?)?;
+        assert!(missing.build_plugin(None).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn relevant_events() {
+        let path = Path::new("/srv/graph/graph.json");
+        let event = |kind: EventKind, path: &str| Event::new(kind).add_path(PathBuf::from(path));
+
+        assert!(is_relevant(
+            &event(
+                EventKind::Create(notify::event::CreateKind::Any),
+                "/srv/graph/graph.json"
+            ),
+            path
+        ));
+        assert!(!is_relevant(
+            &event(
+                EventKind::Access(notify::event::AccessKind::Any),
+                "/srv/graph/graph.json"
+            ),
+            path
+        ));
+        assert!(!is_relevant(
+            &event(
+                EventKind::Create(notify::event::CreateKind::Any),
+                "/srv/graph/graph.json.tmp"
+            ),
+            path
+        ));
+        // Kubernetes swaps the symlink the file links through.
+        assert!(is_relevant(
+            &event(
+                EventKind::Create(notify::event::CreateKind::Any),
+                "/srv/graph/..data"
+            ),
+            path
+        ));
+    }
+
+    #[test]
+    fn keeps_symlinked_path() -> Fallible<()> {
+        let runtime = commons::testing::init_runtime()?;
+        let tmpdir = tempfile::tempdir()?;
+        let first = tmpdir.path().join("..2022_10_05");
+        std::fs::create_dir(&first)?;
+        std::fs::write(
+            first.join("graph.json"),
+            serde_json::to_string(&Graph::default())?,
+        )?;
+        std::os::unix::fs::symlink(&first, tmpdir.path().join("..data"))?;
+        let path = tmpdir.path().join("graph.json");
+        std::os::unix::fs::symlink("..data/graph.json", &path)?;
+
+        let plugin = GraphFileLoadPlugin::try_new(GraphFileLoadSettings {
+            path: path.clone(),
+            watch: false,
+        })?;
+        assert_eq!(plugin.path, path);
+
+        // The volume is updated by swapping the `..data` symlink.
+        let graph = generate_graph(false, false);
+        let second = tmpdir.path().join("..2022_10_06");
+        std::fs::create_dir(&second)?;
+        std::fs::write(second.join("graph.json"), serde_json::to_string(&graph)?)?;
+        std::os::unix::fs::symlink(&second, tmpdir.path().join("..data_tmp"))?;
+        std::fs::rename(
+            tmpdir.path().join("..data_tmp"),
+            tmpdir.path().join("..data"),
+        )?;
+        std::fs::remove_dir_all(&first)?;
+
+        let io = runtime.block_on(plugin.run_internal(InternalIO {
+            graph: Default::default(),
+            parameters: Default::default(),
+        }))?;
+        assert_eq!(io.graph, graph);
+
+        Ok(())
+    }
+}
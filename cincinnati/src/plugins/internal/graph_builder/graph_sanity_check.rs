@@ -0,0 +1,463 @@
+//! This plugin checks the sanity of the graph before it gets published.
+//!
+//! It is meant to be the last plugin of the graph-builder, and reports graphs
+//! with releases without any edge, conditional edges from or to versions which
+//! are not part of the graph, or which shrank too much compared to the last
+//! graph which passed the checks. Abstract releases are placeholders, so they
+//! are neither orphaned nor counted, and edges to them are not dangling.
+//!
+//! Violations are only logged unless `enforce` is set, in which case the
+//! plugin fails the scrape, so the previously published graph keeps being
+//! served. The change thresholds are those of the graph-builder validation,
+//! and are bypassed along with them by its override.
+
+use crate as cincinnati;
+
+use self::cincinnati::change_thresholds::{
+    GraphSummary, Threshold, Thresholds, OVERRIDE_PARAM_KEY,
+};
+use self::cincinnati::plugins::prelude::*;
+use self::cincinnati::plugins::prelude_plugin_impl::*;
+use self::cincinnati::Release;
+
+use prometheus::{IntCounterVec, Opts};
+use std::collections::HashSet;
+use tokio::sync::Mutex;
+
+/// Plugin settings.
+#[derive(Clone, Debug, Deserialize, SmartDefault)]
+#[serde(default)]
+pub struct GraphSanityCheckSettings {
+    /// Whether violations fail the scrape. If disabled they are only reported.
+    pub enforce: bool,
+
+    /// Whether releases without any edge are a violation.
+    pub reject_orphaned_releases: bool,
+
+    /// Maximum shrinkage between two graphs.
+    #[serde(flatten)]
+    pub thresholds: Thresholds,
+
+    /// File in which the summary of the last graph which passed the checks
+    /// is kept, so that the change thresholds apply across restarts.
+    pub state_path: Option<PathBuf>,
+}
+
+impl PluginSettings for GraphSanityCheckSettings {
+    fn build_plugin(&self, registry: Option<&prometheus::Registry>) -> Fallible<BoxedPlugin> {
+        let plugin = GraphSanityCheckPlugin::try_new(self.clone(), registry)?;
+        Ok(new_plugin!(InternalPluginWrapper(plugin)))
+    }
+}
+
+impl GraphSanityCheckSettings {
+    /// Validate plugin configuration and fill in defaults.
+    pub fn deserialize_config(cfg: toml::Value) -> Fallible<Box<dyn PluginSettings>> {
+        let settings: Self = cfg.try_into()?;
+        settings.thresholds.validate("")?;
+
+        Ok(Box::new(settings))
+    }
+}
+
+/// Checks whether graphs are fit for publication.
+#[derive(CustomDebug)]
+pub struct GraphSanityCheckPlugin {
+    settings: GraphSanityCheckSettings,
+
+    /// Summary of the last graph which passed the checks.
+    #[debug(skip)]
+    previous: Mutex<Option<GraphSummary>>,
+
+    #[debug(skip)]
+    failures: IntCounterVec,
+}
+
+impl GraphSanityCheckPlugin {
+    /// Plugin name, for configuration.
+    pub const PLUGIN_NAME: &'static str = "graph-sanity-check";
+
+    pub fn try_new(
+        settings: GraphSanityCheckSettings,
+        prometheus_registry: Option<&prometheus::Registry>,
+    ) -> Fallible<Self> {
+        let failures = IntCounterVec::new(
+            Opts::new(
+                "graph_sanity_check_failures_total",
+                "Total number of graphs which failed a sanity check",
+            ),
+            &["check"],
+        )?;
+        if let Some(prometheus_registry) = &prometheus_registry {
+            prometheus_registry.register(Box::new(failures.clone()))?;
+        }
+
+        let previous = match &settings.state_path {
+            Some(path) => read_state(path)?,
+            None => None,
+        };
+
+        Ok(Self {
+            settings,
+            previous: Mutex::new(previous),
+            failures,
+        })
+    }
+
+    /// Run all checks, returning the violations along with the name of the
+    /// failed check.
+    fn check(
+        &self,
+        graph: &cincinnati::Graph,
+        previous: Option<&GraphSummary>,
+        override_thresholds: bool,
+    ) -> Vec<(&'static str, String)> {
+        let mut violations = vec![];
+
+        let versions: HashSet<&str> = graph.iter_releases().map(Release::version).collect();
+
+        // Versions taking part in at least one edge.
+        let mut connected: HashSet<&str> = HashSet::new();
+        for (from, to) in graph.iter_edges() {
+            connected.insert(from.version());
+            connected.insert(to.version());
+        }
+        for (from, to, _) in graph.conditional_edges() {
+            for version in &[from, to] {
+                if !versions.contains(version) {
+                    violations.push((
+                        "dangling_edges",
+                        format!(
+                            "conditional edge {} -> {} involves {}, which is not part of the graph",
+                            from, to, version
+                        ),
+                    ));
+                }
+            }
+            connected.insert(from);
+            connected.insert(to);
+        }
+
+        let concrete: Vec<&str> = graph
+            .iter_releases()
+            .filter(|release| matches!(release, Release::Concrete(_)))
+            .map(Release::version)
+            .collect();
+        if self.settings.reject_orphaned_releases && concrete.len() > 1 {
+            let mut orphaned: Vec<&str> = concrete
+                .into_iter()
+                .filter(|version| !connected.contains(version))
+                .collect();
+            if !orphaned.is_empty() {
+                orphaned.sort_unstable();
+                violations.push((
+                    "orphaned_releases",
+                    format!(
+                        "{} release(s) without any edge: {}",
+                        orphaned.len(),
+                        orphaned.join(", ")
+                    ),
+                ));
+            }
+        }
+
+        if let Some(previous) = previous {
+            for (threshold, violation) in self
+                .settings
+                .thresholds
+                .check(previous, &GraphSummary::of(graph))
+            {
+                if override_thresholds {
+                    warn!("overriding change threshold: {}", violation);
+                    continue;
+                }
+                let check = match threshold {
+                    Threshold::Releases => "releases_shrink",
+                    Threshold::Edges => "edges_shrink",
+                };
+                violations.push((check, violation));
+            }
+        }
+
+        violations
+    }
+}
+
+/// Read the summary of the last graph which passed the checks, if any.
+fn read_state(path: &std::path::Path) -> Fallible<Option<GraphSummary>> {
+    match std::fs::read(path) {
+        Ok(content) => serde_json::from_slice(&content)
+            .map(Some)
+            .context(format!("parsing {}", path.display())),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e).context(format!("reading {}", path.display())),
+    }
+}
+
+/// Atomically replace the summary of the last graph which passed the checks.
+fn write_state(path: &std::path::Path, summary: &GraphSummary) -> Fallible<()> {
+    let tmp_path = path.with_extension("tmp");
+    std::fs::write(&tmp_path, serde_json::to_vec(summary)?)
+        .context(format!("writing {}", tmp_path.display()))?;
+    std::fs::rename(&tmp_path, path).context(format!("replacing {}", path.display()))?;
+    Ok(())
+}
+
+#[async_trait]
+impl InternalPlugin for GraphSanityCheckPlugin {
+    const PLUGIN_NAME: &'static str = Self::PLUGIN_NAME;
+
+    async fn run_internal(&self, io: InternalIO) -> Fallible<InternalIO> {
+        let mut previous = self.previous.lock().await;
+
+        let override_thresholds = io.parameters.contains_key(OVERRIDE_PARAM_KEY);
+        let violations = self.check(&io.graph, previous.as_ref(), override_thresholds);
+        if !violations.is_empty() {
+            let mut checks: Vec<&str> = violations.iter().map(|(check, _)| *check).collect();
+            checks.dedup();
+            for check in checks {
+                self.failures.with_label_values(&[check]).inc();
+            }
+            let violations = violations
+                .into_iter()
+                .map(|(_, violation)| violation)
+                .collect::<Vec<_>>()
+                .join("\n  - ");
+            if self.settings.enforce {
+                bail!("graph failed the sanity checks:\n  - {}", violations);
+            }
+            warn!("graph failed the sanity checks:\n  - {}", violations);
+        }
+
+        let summary = GraphSummary::of(&io.graph);
+        if let Some(path) = &self.settings.state_path {
+            if let Err(e) = write_state(path, &summary) {
+                warn!("could not persist the graph summary: {:#}", e);
+            }
+        }
+        *previous = Some(summary);
+
+        Ok(io)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cincinnati::testing::{generate_custom_graph, generate_graph};
+    use commons::testing::init_runtime;
+
+    fn graph(releases: usize, edges: Vec<(usize, usize)>) -> cincinnati::Graph {
+        generate_custom_graph(
+            "image",
+            (0..releases).map(|i| (i, Default::default())).collect(),
+            Some(edges),
+        )
+    }
+
+    fn enforcing() -> GraphSanityCheckSettings {
+        GraphSanityCheckSettings {
+            enforce: true,
+            reject_orphaned_releases: true,
+            ..Default::default()
+        }
+    }
+
+    fn run_with(
+        plugin: &GraphSanityCheckPlugin,
+        graph: cincinnati::Graph,
+        parameters: std::collections::HashMap<String, String>,
+    ) -> Fallible<InternalIO> {
+        let runtime = init_runtime()?;
+        runtime.block_on(plugin.run_internal(InternalIO { graph, parameters }))
+    }
+
+    fn run(plugin: &GraphSanityCheckPlugin, graph: cincinnati::Graph) -> Fallible<InternalIO> {
+        run_with(plugin, graph, Default::default())
+    }
+
+    #[test]
+    fn rejects_orphaned_releases() -> Fallible<()> {
+        let plugin = GraphSanityCheckPlugin::try_new(enforcing(), None)?;
+
+        let err = run(&plugin, graph(3, vec![(0, 1)])).unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("1 release(s) without any edge: 2.0.0"),
+            "{}",
+            err
+        );
+        assert_eq!(
+            plugin
+                .failures
+                .with_label_values(&["orphaned_releases"])
+                .get(),
+            1
+        );
+
+        // A lone release is not orphaned.
+        run(&plugin, graph(1, vec![]))?;
+
+        let plugin = GraphSanityCheckPlugin::try_new(
+            GraphSanityCheckSettings {
+                reject_orphaned_releases: false,
+                ..enforcing()
+            },
+            None,
+        )?;
+        run(&plugin, graph(3, vec![(0, 1)]))?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn only_reports_by_default() -> Fallible<()> {
+        let plugin = GraphSanityCheckPlugin::try_new(Default::default(), None)?;
+
+        run(&plugin, graph(5, vec![(0, 1), (1, 2), (2, 3), (3, 4)]))?;
+        run(&plugin, graph(3, vec![(0, 1)]))?;
+        assert_eq!(
+            plugin
+                .failures
+                .with_label_values(&["releases_shrink"])
+                .get(),
+            1
+        );
+        assert_eq!(
+            plugin
+                .failures
+                .with_label_values(&["orphaned_releases"])
+                .get(),
+            0
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_dangling_conditional_edges() -> Fallible<()> {
+        let plugin = GraphSanityCheckPlugin::try_new(enforcing(), None)?;
+
+        run(&plugin, generate_graph(true, false))?;
+
+        let mut graph = generate_graph(true, false);
+        let removed = graph.find_by_version("2.0.0").unwrap();
+        graph.remove_releases(vec![removed]);
+        let err = run(&plugin, graph).unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("conditional edge 1.0.0 -> 2.0.0 involves 2.0.0"),
+            "{}",
+            err
+        );
+        assert_eq!(
+            plugin.failures.with_label_values(&["dangling_edges"]).get(),
+            1
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn accepts_abstract_releases() -> Fallible<()> {
+        let plugin = GraphSanityCheckPlugin::try_new(enforcing(), None)?;
+
+        let mut graph = graph(2, vec![(0, 1)]);
+        let abstract_release =
+            graph.add_release(Release::Abstract(cincinnati::AbstractRelease {
+                version: "2.0.0".into(),
+            }))?;
+        let last = graph.find_by_version("1.0.0").unwrap();
+        graph.add_edge(&last, &abstract_release)?;
+        graph.add_release(Release::Abstract(cincinnati::AbstractRelease {
+            version: "3.0.0".into(),
+        }))?;
+        run(&plugin, graph)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_shrinking_graph() -> Fallible<()> {
+        let plugin = GraphSanityCheckPlugin::try_new(enforcing(), None)?;
+
+        run(&plugin, graph(5, vec![(0, 1), (1, 2), (2, 3), (3, 4)]))?;
+        // Small changes are tolerated.
+        run(
+            &plugin,
+            graph(5, vec![(0, 1), (1, 2), (2, 3), (3, 4), (0, 4)]),
+        )?;
+
+        let err = run(&plugin, graph(2, vec![(0, 1)])).unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("60.0% of releases disappeared (5 -> 2)"),
+            "{}",
+            err
+        );
+        assert!(err
+            .to_string()
+            .contains("80.0% of edges disappeared (5 -> 1)"));
+        assert_eq!(
+            plugin
+                .failures
+                .with_label_values(&["releases_shrink"])
+                .get(),
+            1
+        );
+        assert_eq!(
+            plugin.failures.with_label_values(&["edges_shrink"]).get(),
+            1
+        );
+
+        // The rejected graph doesn't become the reference.
+        run(&plugin, graph(2, vec![(0, 1)])).unwrap_err();
+        run(&plugin, graph(4, vec![(0, 1), (1, 2), (2, 3), (0, 3)]))?;
+
+        // An override lets the shrink through, and makes it the reference.
+        let parameters = [(OVERRIDE_PARAM_KEY.to_string(), "true".to_string())]
+            .iter()
+            .cloned()
+            .collect();
+        run_with(&plugin, graph(2, vec![(0, 1)]), parameters)?;
+        run(&plugin, graph(2, vec![(0, 1)]))?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn keeps_reference_across_restarts() -> Fallible<()> {
+        let tmpdir = tempfile::tempdir()?;
+        let settings = GraphSanityCheckSettings {
+            state_path: Some(tmpdir.path().join("sanity-check.json")),
+            ..enforcing()
+        };
+
+        let plugin = GraphSanityCheckPlugin::try_new(settings.clone(), None)?;
+        run(&plugin, graph(5, vec![(0, 1), (1, 2), (2, 3), (3, 4)]))?;
+
+        let restarted = GraphSanityCheckPlugin::try_new(settings, None)?;
+        run(&restarted, graph(2, vec![(0, 1)])).unwrap_err();
+
+        Ok(())
+    }
+
+    #[test]
+    fn deserialize_settings() -> Fallible<()> {
+        let cfg: toml::Value = toml::from_str("max_edges_removed_percent = 150.0")?;
+        assert!(GraphSanityCheckSettings::deserialize_config(cfg).is_err());
+
+        let settings: GraphSanityCheckSettings = toml::from_str(
+            r#"
+            enforce = true
+            max_releases_removed_percent = 5.0
+            "#,
+        )?;
+        assert!(settings.enforce);
+        assert!(!settings.reject_orphaned_releases);
+        assert_eq!(settings.thresholds.max_releases_removed_percent, 5.0);
+        assert_eq!(settings.thresholds.max_edges_removed_percent, 20.0);
+
+        Ok(())
+    }
+}
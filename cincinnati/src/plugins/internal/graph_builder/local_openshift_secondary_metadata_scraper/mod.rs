@@ -0,0 +1,13 @@
+//! This plugin points the following plugins to a local graph-data directory.
+//!
+//! It is meant to be included in the plugin chain, preceding other plugins who
+//! rely on the data being in the output directory. The directory is used as
+//! is, e.g. a checkout of the graph-data repository, and is optionally watched
+//! for changes so that the graph can be rebuilt right away.
+
+pub mod plugin;
+
+pub use plugin::{
+    set_change_hook, LocalOpenshiftSecondaryMetadataScraperPlugin,
+    LocalOpenshiftSecondaryMetadataScraperSettings,
+};
@@ -0,0 +1,311 @@
+use crate as cincinnati;
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use std::collections::HashMap;
+use std::path::{Component, Path};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+
+use self::cincinnati::plugins::prelude::*;
+use self::cincinnati::plugins::prelude_plugin_impl::*;
+
+// Defines the key for placing the data directory path in the IO parameters
+pub static GRAPH_DATA_DIR_PARAM_KEY: &str = "io.openshift.upgrades.secondary_metadata.directory";
+
+type ChangeHook = Arc<dyn Fn() + Send + Sync>;
+
+lazy_static::lazy_static! {
+    static ref CHANGE_HOOK: RwLock<Option<ChangeHook>> = RwLock::new(None);
+    /// Filesystem watchers by watched path, along with the ID of their guard.
+    static ref WATCHERS: Mutex<HashMap<PathBuf, (u64, RecommendedWatcher)>> =
+        Mutex::new(HashMap::new());
+}
+
+static NEXT_WATCH_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Set the function called whenever a watched graph-data directory or graph file changes.
+///
+/// The graph-builder uses it to start a new scrape right away.
+pub fn set_change_hook<F>(hook: F)
+where
+    F: Fn() + Send + Sync + 'static,
+{
+    *CHANGE_HOOK
+        .write()
+        .unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(Arc::new(hook));
+}
+
+pub(crate) fn run_change_hook() {
+    let hook = CHANGE_HOOK
+        .read()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .clone();
+    if let Some(hook) = hook {
+        hook();
+    }
+}
+
+/// Keeps a path watched until dropped, unless its watcher was replaced since.
+#[derive(Debug)]
+pub(crate) struct WatchGuard {
+    path: PathBuf,
+    id: u64,
+}
+
+impl Drop for WatchGuard {
+    fn drop(&mut self) {
+        let mut watchers = WATCHERS
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        if watchers.get(&self.path).map(|(id, _)| *id) == Some(self.id) {
+            watchers.remove(&self.path);
+        }
+    }
+}
+
+/// Watch `path`, running the change hook on the events accepted by `is_relevant`.
+///
+/// A single watcher is kept per path: plugins rebuilt on reload replace the
+/// watcher of the plugins they supersede instead of adding one.
+pub(crate) fn watch<F>(path: &Path, mode: RecursiveMode, is_relevant: F) -> Fallible<WatchGuard>
+where
+    F: Fn(&Event) -> bool + Send + 'static,
+{
+    let watched = path.to_path_buf();
+    let mut watcher =
+        notify::recommended_watcher(move |event: notify::Result<Event>| match event {
+            Ok(event) if is_relevant(&event) => {
+                debug!("{:?} changed: {:?}", &watched, &event.paths);
+                run_change_hook();
+            }
+            Ok(_) => {}
+            Err(e) => warn!("Watching {:?}: {}", &watched, e),
+        })
+        .context("Creating filesystem watcher")?;
+    watcher
+        .watch(path, mode)
+        .context(format!("Watching {:?}", path))?;
+
+    let id = NEXT_WATCH_ID.fetch_add(1, Ordering::SeqCst);
+    WATCHERS
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .insert(path.to_path_buf(), (id, watcher));
+
+    Ok(WatchGuard {
+        path: path.to_path_buf(),
+        id,
+    })
+}
+
+/// Plugin settings.
+#[derive(Debug, SmartDefault, Clone, Deserialize)]
+#[serde(default)]
+pub struct LocalOpenshiftSecondaryMetadataScraperSettings {
+    /// Graph-data directory, e.g. a checkout of the graph-data repository.
+    directory: PathBuf,
+
+    /// Watch the directory and report changes to the change hook.
+    #[default(true)]
+    watch: bool,
+}
+
+impl LocalOpenshiftSecondaryMetadataScraperSettings {
+    /// Validate plugin configuration and fill in defaults.
+    pub fn deserialize_config(cfg: toml::Value) -> Fallible<Box<dyn PluginSettings>> {
+        let settings: Self = cfg
+            .clone()
+            .try_into()
+            .context(format!("Deserializing {:#?}", &cfg))?;
+
+        ensure!(
+            !settings.directory.to_str().unwrap_or_default().is_empty(),
+            "empty directory"
+        );
+
+        Ok(Box::new(settings))
+    }
+}
+
+/// Returns whether an event may change the graph-data.
+///
+/// Accesses and changes to the git metadata of a checkout are ignored.
+fn is_relevant(event: &Event, directory: &Path) -> bool {
+    if let EventKind::Access(_) = event.kind {
+        return false;
+    }
+
+    event.paths.iter().any(|path| {
+        !path
+            .strip_prefix(directory)
+            .unwrap_or(path)
+            .components()
+            .any(|component| component == Component::Normal(".git".as_ref()))
+    })
+}
+
+/// This plugin implements reading the secondary metadata from a local directory.
+#[derive(Debug)]
+pub struct LocalOpenshiftSecondaryMetadataScraperPlugin {
+    directory: PathBuf,
+
+    _watch: Option<WatchGuard>,
+}
+
+impl LocalOpenshiftSecondaryMetadataScraperPlugin {
+    pub(crate) const PLUGIN_NAME: &'static str = "local-secondary-metadata-scrape";
+
+    /// Instantiate a new instance of `Self`.
+    ///
+    /// The configured directory is kept as is, without resolving symlinks, so
+    /// that the graph-data is found again once they are swapped, e.g. when
+    /// Kubernetes updates a volume.
+    pub fn try_new(settings: LocalOpenshiftSecondaryMetadataScraperSettings) -> Fallible<Self> {
+        let directory = settings.directory;
+        ensure!(directory.is_dir(), "{:?} is not a directory", &directory);
+
+        let watch = if settings.watch {
+            let watched = directory.clone();
+            Some(watch(&directory, RecursiveMode::Recursive, move |event| {
+                is_relevant(event, &watched)
+            })?)
+        } else {
+            None
+        };
+
+        Ok(Self {
+            directory,
+            _watch: watch,
+        })
+    }
+}
+
+impl PluginSettings for LocalOpenshiftSecondaryMetadataScraperSettings {
+    fn build_plugin(&self, _: Option<&prometheus::Registry>) -> Fallible<BoxedPlugin> {
+        let plugin = LocalOpenshiftSecondaryMetadataScraperPlugin::try_new(self.clone())?;
+        Ok(new_plugin!(InternalPluginWrapper(plugin)))
+    }
+}
+
+#[async_trait]
+impl InternalPlugin for LocalOpenshiftSecondaryMetadataScraperPlugin {
+    const PLUGIN_NAME: &'static str = Self::PLUGIN_NAME;
+
+    async fn run_internal(&self, mut io: InternalIO) -> Fallible<InternalIO> {
+        ensure!(
+            self.directory.is_dir(),
+            "graph-data directory {:?} is not available",
+            &self.directory
+        );
+
+        io.parameters.insert(
+            GRAPH_DATA_DIR_PARAM_KEY.to_string(),
+            self.directory
+                .to_str()
+                .ok_or_else(|| format_err!("directory cannot be converted to str"))?
+                .to_string(),
+        );
+
+        Ok(io)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    #[test]
+    fn relevant_events() {
+        let directory = Path::new("/srv/graph-data");
+        let event = |kind: EventKind, path: &str| Event::new(kind).add_path(PathBuf::from(path));
+
+        assert!(is_relevant(
+            &event(
+                EventKind::Modify(notify::event::ModifyKind::Any),
+                "/srv/graph-data/channels/stable-4.10.yaml"
+            ),
+            directory
+        ));
+        assert!(!is_relevant(
+            &event(
+                EventKind::Access(notify::event::AccessKind::Any),
+                "/srv/graph-data/channels/stable-4.10.yaml"
+            ),
+            directory
+        ));
+        assert!(!is_relevant(
+            &event(
+                EventKind::Create(notify::event::CreateKind::Any),
+                "/srv/graph-data/.git/index.lock"
+            ),
+            directory
+        ));
+    }
+
+    #[test]
+    fn watchers_are_replaced() -> Fallible<()> {
+        let tmpdir = tempfile::tempdir()?;
+        let watched_id = || {
+            WATCHERS
+                .lock()
+                .unwrap()
+                .get(tmpdir.path())
+                .map(|(id, _)| *id)
+        };
+
+        let first = watch(tmpdir.path(), RecursiveMode::NonRecursive, |_| false)?;
+        let second = watch(tmpdir.path(), RecursiveMode::NonRecursive, |_| false)?;
+        assert_eq!(watched_id(), Some(second.id));
+
+        // The superseded guard leaves the new watcher alone.
+        drop(first);
+        assert_eq!(watched_id(), Some(second.id));
+        drop(second);
+        assert_eq!(watched_id(), None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn watch_directory() -> Fallible<()> {
+        let runtime = commons::testing::init_runtime()?;
+        let tmpdir = tempfile::tempdir()?;
+        std::fs::write(tmpdir.path().join("version"), "1.0.0")?;
+
+        let (tx, rx) = mpsc::channel();
+        let tx = std::sync::Mutex::new(tx);
+        set_change_hook(move || {
+            let _ = tx.lock().unwrap().send(());
+        });
+
+        let settings = LocalOpenshiftSecondaryMetadataScraperSettings::deserialize_config(
+            toml::from_str(&format!("directory = {:?}", tmpdir.path()))?,
+        )?;
+        let plugin = settings.build_plugin(None)?;
+
+        let io = runtime.block_on(plugin.run(cincinnati::plugins::PluginIO::InternalIO(
+            InternalIO {
+                graph: Default::default(),
+                parameters: Default::default(),
+            },
+        )))?;
+        let data_dir = cincinnati::testing::internal_io(io)?
+            .parameters
+            .get(GRAPH_DATA_DIR_PARAM_KEY)
+            .map(PathBuf::from)
+            .unwrap();
+        assert_eq!(data_dir, tmpdir.path());
+
+        std::fs::create_dir(tmpdir.path().join("channels"))?;
+        std::fs::write(tmpdir.path().join("channels/stable-4.10.yaml"), "")?;
+        assert!(rx.recv_timeout(Duration::from_secs(10)).is_ok());
+
+        let missing = LocalOpenshiftSecondaryMetadataScraperSettings::deserialize_config(
+            toml::from_str(&format!("directory = {:?}", tmpdir.path().join("missing")))?,
+        )?;
+        assert!(missing.build_plugin(None).is_err());
+
+        Ok(())
+    }
+}
@@ -1,9 +1,21 @@
 //! Plugins specific to the graph-builder
 
+pub mod candidate_channel_derive;
+pub mod conditional_risk_evaluator;
 pub mod dkrv2_openshift_secondary_metadata_scraper;
 pub mod github_openshift_secondary_metadata_scraper;
+pub mod graph_file_load;
+pub mod graph_sanity_check;
+pub mod local_openshift_secondary_metadata_scraper;
+pub mod multiarch_publication_gate;
 pub mod openshift_secondary_metadata_parser;
+pub mod release_cosign_verify;
+pub mod release_enrichment_hook;
+pub mod release_notes_enrich;
 pub mod release_scrape_dockerv2;
+pub mod s3_openshift_secondary_metadata_scraper;
+pub mod synthetic_releases;
+pub mod version_filter;
 
 pub mod commons;
 pub mod release;
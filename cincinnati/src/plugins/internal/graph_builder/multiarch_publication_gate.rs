@@ -0,0 +1,400 @@
+//! This plugin holds new versions out of channels until all required architectures are available.
+//!
+//! A version is published once releases for all of the required architectures
+//! are part of the graph, either as single-arch payloads or as a multi-arch
+//! image index covering them. Until then its releases are stripped of their
+//! channels, so that clusters of one architecture are not offered a version
+//! whose payload for another architecture hasn't been mirrored yet. Once
+//! published, a version is never held back again, also across restarts if a
+//! state file is configured.
+//!
+//! It is meant to run after the graph-data parser, which assigns channels, and
+//! after the `release-cosign-verify` plugin, so that only verified payloads are
+//! taken into account.
+
+use crate as cincinnati;
+
+use self::cincinnati::plugins::internal::arch_filter::{ArchKeys, MULTI_ARCH};
+use self::cincinnati::plugins::prelude::*;
+use self::cincinnati::plugins::prelude_plugin_impl::*;
+use self::cincinnati::Release;
+
+use prometheus::IntGauge;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::convert::TryInto;
+use std::path::{Path, PathBuf};
+use tokio::sync::RwLock;
+
+/// Default prefix of the metadata keys.
+pub static DEFAULT_KEY_PREFIX: &str = "io.openshift.upgrades.graph";
+
+/// Plugin settings.
+#[derive(Clone, Debug, Deserialize, SmartDefault)]
+#[serde(default)]
+pub struct MultiArchPublicationGateSettings {
+    /// Prefix of the metadata keys.
+    #[default(DEFAULT_KEY_PREFIX.to_string())]
+    pub key_prefix: String,
+
+    /// Architectures which must all be available before a version is published.
+    pub required_architectures: Vec<String>,
+
+    /// Lowest version which is gated, older ones are published as they are.
+    pub min_version: Option<String>,
+
+    /// File in which the published versions are kept, so that they are not
+    /// held back again after a restart.
+    pub state_path: Option<PathBuf>,
+}
+
+impl PluginSettings for MultiArchPublicationGateSettings {
+    fn build_plugin(&self, registry: Option<&prometheus::Registry>) -> Fallible<BoxedPlugin> {
+        let plugin = MultiArchPublicationGatePlugin::try_new(self.clone(), registry)?;
+        Ok(new_plugin!(InternalPluginWrapper(plugin)))
+    }
+
+    fn key_prefix(&self) -> Option<&str> {
+        Some(&self.key_prefix)
+    }
+}
+
+impl MultiArchPublicationGateSettings {
+    /// Validate plugin configuration and fill in defaults.
+    pub fn deserialize_config(cfg: toml::Value) -> Fallible<Box<dyn PluginSettings>> {
+        let settings: Self = cfg.try_into()?;
+
+        ensure!(!settings.key_prefix.is_empty(), "empty key_prefix");
+        ensure!(
+            !settings.required_architectures.is_empty(),
+            "no required_architectures configured"
+        );
+        ensure!(
+            settings
+                .required_architectures
+                .iter()
+                .all(|arch| !arch.is_empty() && arch != MULTI_ARCH),
+            "required_architectures must be non-empty and not '{}'",
+            MULTI_ARCH
+        );
+        if let Some(min_version) = &settings.min_version {
+            cincinnati::semver_cache::parse_version(min_version)
+                .context(format!("invalid min_version '{}'", min_version))?;
+        }
+
+        Ok(Box::new(settings))
+    }
+}
+
+/// Holds versions out of channels until all required architectures are available.
+#[derive(CustomDebug)]
+pub struct MultiArchPublicationGatePlugin {
+    settings: MultiArchPublicationGateSettings,
+
+    min_version: Option<semver::Version>,
+
+    /// Versions, without build metadata, which have been published.
+    #[debug(skip)]
+    published: RwLock<HashSet<String>>,
+
+    #[debug(skip)]
+    held_versions: IntGauge,
+}
+
+impl MultiArchPublicationGatePlugin {
+    /// Plugin name, for configuration.
+    pub const PLUGIN_NAME: &'static str = "multiarch-publication-gate";
+
+    pub fn try_new(
+        settings: MultiArchPublicationGateSettings,
+        prometheus_registry: Option<&prometheus::Registry>,
+    ) -> Fallible<Self> {
+        let held_versions = IntGauge::new(
+            "graph_multiarch_held_versions",
+            "Number of versions held out of channels for missing architectures in the last run",
+        )?;
+        if let Some(prometheus_registry) = &prometheus_registry {
+            prometheus_registry.register(Box::new(held_versions.clone()))?;
+        }
+
+        let min_version = settings
+            .min_version
+            .as_deref()
+            .map(cincinnati::semver_cache::parse_version)
+            .transpose()?;
+
+        let published = match &settings.state_path {
+            Some(path) => read_state(path)?,
+            None => Default::default(),
+        };
+
+        Ok(Self {
+            settings,
+            min_version,
+            published: RwLock::new(published),
+            held_versions,
+        })
+    }
+}
+
+/// Read the versions published before the last restart, if any.
+fn read_state(path: &Path) -> Fallible<HashSet<String>> {
+    match std::fs::read(path) {
+        Ok(content) => {
+            serde_json::from_slice(&content).context(format!("parsing {}", path.display()))
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Default::default()),
+        Err(e) => Err(e).context(format!("reading {}", path.display())),
+    }
+}
+
+/// Atomically replace the published versions, sorted for readability.
+fn write_state(path: &Path, published: &HashSet<String>) -> Fallible<()> {
+    let sorted: BTreeSet<&String> = published.iter().collect();
+    let tmp_path = path.with_extension("tmp");
+    std::fs::write(&tmp_path, serde_json::to_vec(&sorted)?)
+        .context(format!("writing {}", tmp_path.display()))?;
+    std::fs::rename(&tmp_path, path).context(format!("replacing {}", path.display()))?;
+    Ok(())
+}
+
+#[async_trait]
+impl InternalPlugin for MultiArchPublicationGatePlugin {
+    const PLUGIN_NAME: &'static str = Self::PLUGIN_NAME;
+
+    async fn run_internal(&self, io: InternalIO) -> Fallible<InternalIO> {
+        let mut graph = io.graph;
+        let arch_keys = ArchKeys::with_prefix(&self.settings.key_prefix);
+        let channels_key = format!("{}.release.channels", self.settings.key_prefix);
+
+        // Architectures available for each gated version, without build metadata.
+        let mut available: BTreeMap<String, HashSet<String>> = BTreeMap::new();
+        graph
+            .iter_releases()
+            .try_for_each(|release| -> Fallible<()> {
+                if let Release::Concrete(concrete) = release {
+                    let mut version =
+                        match cincinnati::semver_cache::parse_version(&concrete.version) {
+                            Ok(version) => version,
+                            Err(e) => {
+                                warn!("Parsing {} as SemVer: {}", concrete.version, e);
+                                return Ok(());
+                            }
+                        };
+                    version.build.clear();
+                    if self
+                        .min_version
+                        .as_ref()
+                        .map_or(false, |min| version < *min)
+                    {
+                        return Ok(());
+                    }
+
+                    available.entry(version.to_string()).or_default().extend(
+                        arch_keys
+                            .covered_archs(release)
+                            .into_iter()
+                            .map(str::to_string),
+                    );
+                }
+                Ok(())
+            })?;
+
+        let held: BTreeSet<String> = {
+            let mut published = self.published.write().await;
+            let published_before = published.len();
+            let mut held = BTreeSet::new();
+            for (version, archs) in available {
+                if published.contains(&version) {
+                    continue;
+                }
+
+                let missing: Vec<&str> = self
+                    .settings
+                    .required_architectures
+                    .iter()
+                    .filter(|arch| !archs.contains(*arch))
+                    .map(String::as_str)
+                    .collect();
+                if missing.is_empty() {
+                    debug!(
+                        "publishing {}, all required architectures available",
+                        version
+                    );
+                    published.insert(version);
+                } else {
+                    info!(
+                        "holding {} out of channels, missing architectures: {}",
+                        version,
+                        missing.join(", ")
+                    );
+                    held.insert(version);
+                }
+            }
+
+            if published.len() != published_before {
+                if let Some(path) = &self.settings.state_path {
+                    if let Err(e) = write_state(path, &published) {
+                        warn!("could not persist the published versions: {:#}", e);
+                    }
+                }
+            }
+            held
+        };
+
+        self.held_versions.set(held.len().try_into()?);
+
+        if !held.is_empty() {
+            graph.iter_releases_mut(|release| {
+                if let Release::Concrete(release) = release {
+                    let base_version = release.version.split('+').next().unwrap_or_default();
+                    if held.contains(base_version) {
+                        release.metadata.remove(channels_key.as_str());
+                    }
+                }
+                Ok(())
+            })?;
+        }
+
+        Ok(InternalIO {
+            graph,
+            parameters: io.parameters,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use commons::testing::init_runtime;
+
+    fn release(version: &str, arch: &str, architectures: Option<&str>) -> Release {
+        let mut metadata = cincinnati::MapImpl::new();
+        metadata.insert(
+            "io.openshift.upgrades.graph.release.channels".into(),
+            "stable-4.10".into(),
+        );
+        metadata.insert(
+            "io.openshift.upgrades.graph.release.arch".into(),
+            arch.into(),
+        );
+        if let Some(architectures) = architectures {
+            metadata.insert(
+                "io.openshift.upgrades.graph.release.architectures".into(),
+                architectures.into(),
+            );
+        }
+        Release::Concrete(cincinnati::ConcreteRelease {
+            version: format!("{}+{}", version, arch).into(),
+            payload: format!("quay.io/ocp/release@sha256:{}-{}", version, arch),
+            metadata,
+        })
+    }
+
+    fn run(
+        runtime: &tokio::runtime::Runtime,
+        plugin: &BoxedPlugin,
+        releases: Vec<Release>,
+    ) -> Fallible<InternalIO> {
+        let mut graph = cincinnati::Graph::default();
+        for release in releases {
+            graph.add_release(release)?;
+        }
+
+        let io = runtime.block_on(plugin.run(cincinnati::plugins::PluginIO::InternalIO(
+            InternalIO {
+                graph,
+                parameters: Default::default(),
+            },
+        )))?;
+        cincinnati::testing::internal_io(io)
+    }
+
+    fn has_channels(io: &InternalIO, version: &str) -> bool {
+        io.graph
+            .find_by_version(version)
+            .and_then(|id| io.graph.find_by_releaseid(&id).ok())
+            .is_some_and(|release| match release {
+                Release::Concrete(release) => release
+                    .metadata
+                    .contains_key("io.openshift.upgrades.graph.release.channels"),
+                _ => false,
+            })
+    }
+
+    #[test]
+    fn hold_versions_with_missing_architectures() -> Fallible<()> {
+        let runtime = init_runtime()?;
+
+        let settings = MultiArchPublicationGateSettings::deserialize_config(toml::from_str(
+            r#"
+                name = "multiarch-publication-gate"
+                required_architectures = ["amd64", "arm64"]
+                min_version = "4.10.0"
+            "#,
+        )?)?;
+        let plugin = settings.build_plugin(None)?;
+
+        let io = run(
+            &runtime,
+            &plugin,
+            vec![
+                release("4.9.9", "amd64", None),
+                release("4.10.1", "amd64", None),
+                release("4.10.1", "arm64", None),
+                release("4.10.2", "amd64", None),
+                release("4.10.3", "multi", Some("amd64,arm64,s390x")),
+            ],
+        )?;
+        assert!(has_channels(&io, "4.9.9+amd64"));
+        assert!(has_channels(&io, "4.10.1+amd64"));
+        assert!(has_channels(&io, "4.10.1+arm64"));
+        assert!(!has_channels(&io, "4.10.2+amd64"));
+        assert!(has_channels(&io, "4.10.3+multi"));
+
+        // Published versions are not held back again.
+        let io = run(
+            &runtime,
+            &plugin,
+            vec![
+                release("4.10.1", "amd64", None),
+                release("4.10.2", "amd64", None),
+                release("4.10.2", "arm64", None),
+            ],
+        )?;
+        assert!(has_channels(&io, "4.10.1+amd64"));
+        assert!(has_channels(&io, "4.10.2+amd64"));
+        assert!(has_channels(&io, "4.10.2+arm64"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn keeps_published_versions_across_restarts() -> Fallible<()> {
+        let runtime = init_runtime()?;
+        let tmpdir = tempfile::tempdir()?;
+        let settings = MultiArchPublicationGateSettings {
+            required_architectures: vec!["amd64".to_string(), "arm64".to_string()],
+            state_path: Some(tmpdir.path().join("multiarch-gate.json")),
+            ..Default::default()
+        };
+
+        let plugin = settings.build_plugin(None)?;
+        let io = run(
+            &runtime,
+            &plugin,
+            vec![
+                release("4.10.1", "amd64", None),
+                release("4.10.1", "arm64", None),
+            ],
+        )?;
+        assert!(has_channels(&io, "4.10.1+amd64"));
+
+        // The arm64 release disappearing after a restart doesn't hold the
+        // version back again.
+        let restarted = settings.build_plugin(None)?;
+        let io = run(&runtime, &restarted, vec![release("4.10.1", "amd64", None)])?;
+        assert!(has_channels(&io, "4.10.1+amd64"));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,329 @@
+//! Lints of graph-data, on top of its schema.
+//!
+//! Graph-data can match the schema and still not do what its author meant,
+//! e.g. block edges to a version which isn't in any channel. These lints
+//! catch such mistakes from a checkout, without any scraped releases, so that
+//! graph-data changes can be checked before they are deployed.
+
+use super::plugin::graph_data_model::{BlockedEdge, Channel, ConditionalEdgeYaml, RawMetadata};
+use super::plugin::{BLOCKED_EDGES_DIR, CHANNELS_DIR, SUPPORTED_VERSIONS};
+use super::schema;
+use commons::prelude_errors::*;
+use std::collections::HashSet;
+use std::path::Path;
+
+/// Condition types of risk matching rules.
+static CONDITION_TYPES: &[&str] = &["Always", "PromQL", "ClusterProfile"];
+
+lazy_static::lazy_static! {
+    /// Risk names are reported as reasons in the cluster version status,
+    /// which must be CamelCase.
+    static ref RISK_NAME: regex::Regex = regex::Regex::new("^[A-Z][A-Za-z0-9]*$").unwrap();
+}
+
+/// A blocked edge or conditional edge declaration.
+struct Declaration {
+    file: String,
+    edge: BlockedEdge,
+    conditional: bool,
+}
+
+/// Lint a graph-data directory, returning the findings as `<file>: <problem>`.
+///
+/// Schema violations are included. Files violating the schema aren't linted
+/// any further.
+pub async fn lint(data_dir: &Path) -> Fallible<Vec<String>> {
+    let mut findings = vec![];
+
+    match tokio::fs::read_to_string(data_dir.join("version")).await {
+        Ok(version) if SUPPORTED_VERSIONS.contains(&version.trim()) => {}
+        Ok(version) => findings.push(format!(
+            "version: unsupported graph-data version '{}', expected one of {:?}",
+            version.trim(),
+            SUPPORTED_VERSIONS
+        )),
+        Err(e) => findings.push(format!("version: {}", e)),
+    }
+
+    let violations = schema::violations(data_dir).await?;
+    let invalid: HashSet<String> = violations
+        .iter()
+        .filter_map(|violation| violation.split(&[':', ' '][..]).next())
+        .map(str::to_string)
+        .collect();
+    findings.extend(violations);
+
+    let mut versions = vec![];
+    for (file, path) in schema::yaml_files(data_dir, CHANNELS_DIR).await? {
+        if invalid.contains(&file) {
+            continue;
+        }
+        let content = tokio::fs::read(&path)
+            .await
+            .context(format!("Reading {:?}", &path))?;
+        match serde_yaml::from_slice::<Channel>(&content) {
+            Ok(channel) => versions.extend(channel.versions),
+            Err(e) => findings.push(format!("{}: {}", file, e)),
+        }
+    }
+    let in_channels = |version: &semver::Version| versions.contains(version);
+
+    let mut declarations = vec![];
+    for (file, path) in schema::yaml_files(data_dir, BLOCKED_EDGES_DIR).await? {
+        if invalid.contains(&file) {
+            continue;
+        }
+        let content = tokio::fs::read(&path)
+            .await
+            .context(format!("Reading {:?}", &path))?;
+        let value: serde_yaml::Value = match serde_yaml::from_slice(&content) {
+            Ok(value) => value,
+            Err(e) => {
+                findings.push(format!("{}: {}", file, e));
+                continue;
+            }
+        };
+
+        let conditional = value.get("matchingRules").is_some();
+        if conditional {
+            match serde_yaml::from_value::<ConditionalEdgeYaml>(value.clone()) {
+                Ok(risk) => findings.extend(
+                    lint_risk(&risk)
+                        .into_iter()
+                        .map(|problem| format!("{}: {}", file, problem)),
+                ),
+                Err(e) => {
+                    findings.push(format!("{}: malformed risk declaration: {}", file, e));
+                    continue;
+                }
+            }
+        }
+        match serde_yaml::from_value::<BlockedEdge>(value) {
+            Ok(edge) => declarations.push(Declaration {
+                file,
+                edge,
+                conditional,
+            }),
+            Err(e) => findings.push(format!("{}: {}", file, e)),
+        }
+    }
+
+    // Dangling version references.
+    for declaration in &declarations {
+        if !in_channels(&declaration.edge.to) {
+            findings.push(format!(
+                "{}: version {} is not in any channel",
+                declaration.file, declaration.edge.to
+            ));
+        }
+        if !versions
+            .iter()
+            .any(|version| declaration.edge.from.is_match(&version.to_string()))
+        {
+            findings.push(format!(
+                "{}: 'from' {} matches no version in any channel",
+                declaration.file,
+                declaration.edge.from.as_str()
+            ));
+        }
+    }
+
+    let raw_metadata = data_dir.join("raw/metadata.json");
+    if raw_metadata.is_file() && !invalid.contains("raw/metadata.json") {
+        let content = tokio::fs::read(&raw_metadata)
+            .await
+            .context(format!("Reading {:?}", &raw_metadata))?;
+        let metadata: RawMetadata = serde_json::from_slice(&content)
+            .context(format!("Deserializing {:?}", &raw_metadata))?;
+        let mut listed: Vec<&String> = metadata.keys().collect();
+        listed.sort();
+        for version in listed {
+            match semver::Version::parse(version) {
+                Ok(parsed) if in_channels(&parsed) => {}
+                Ok(_) => findings.push(format!(
+                    "raw/metadata.json: version {} is not in any channel",
+                    version
+                )),
+                Err(e) => findings.push(format!(
+                    "raw/metadata.json: invalid version '{}': {}",
+                    version, e
+                )),
+            }
+        }
+    }
+
+    // Overlapping declarations for the same target. Several risks may apply
+    // to an edge, but a blocked edge hides the risks of the edges it blocks,
+    // and only one blocked edge declaration per target version is applied.
+    for (i, first) in declarations.iter().enumerate() {
+        for second in &declarations[i + 1..] {
+            if first.edge.to != second.edge.to || (first.conditional && second.conditional) {
+                continue;
+            }
+            let overlap = versions.iter().find(|version| {
+                let version = version.to_string();
+                first.edge.from.is_match(&version) && second.edge.from.is_match(&version)
+            });
+            if let Some(version) = overlap {
+                findings.push(format!(
+                    "{}: 'from' {} overlaps with 'from' {} of {}, both match {} -> {}",
+                    first.file,
+                    first.edge.from.as_str(),
+                    second.edge.from.as_str(),
+                    second.file,
+                    version,
+                    first.edge.to
+                ));
+            }
+        }
+    }
+
+    Ok(findings)
+}
+
+/// Lint a risk declaration, returning its problems.
+fn lint_risk(risk: &ConditionalEdgeYaml) -> Vec<String> {
+    let mut problems = vec![];
+
+    if let Err(e) = url::Url::parse(&risk.url) {
+        problems.push(format!("invalid url '{}': {}", risk.url, e));
+    }
+    if !RISK_NAME.is_match(&risk.name) {
+        problems.push(format!(
+            "risk name '{}' must be CamelCase, without spaces or punctuation",
+            risk.name
+        ));
+    }
+    if risk.message.trim().is_empty() {
+        problems.push("empty risk message".to_string());
+    }
+
+    for (i, rule) in risk.matching_rules.iter().enumerate() {
+        match rule.condition_type.as_str() {
+            "PromQL" if rule.promql.is_empty() => {
+                problems.push(format!("matching rule {} has no PromQL query", i))
+            }
+            "Always" if !rule.promql.is_empty() => problems.push(format!(
+                "matching rule {} of type Always has a PromQL query",
+                i
+            )),
+            "ClusterProfile" if rule.cluster_profile.is_empty() => problems.push(format!(
+                "matching rule {} of type ClusterProfile has no properties nor versions",
+                i
+            )),
+            "ClusterProfile" => {
+                for (name, requirement) in &rule.cluster_profile.versions {
+                    if let Err(e) = semver::VersionReq::parse(requirement) {
+                        problems.push(format!(
+                            "matching rule {} has invalid version requirement '{}' for '{}': {}",
+                            i, requirement, name, e
+                        ));
+                    }
+                }
+            }
+            other if !CONDITION_TYPES.contains(&other) => problems.push(format!(
+                "matching rule {} has unknown type '{}', expected one of {:?}",
+                i, other, CONDITION_TYPES
+            )),
+            _ => {}
+        }
+    }
+
+    problems
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn lint_graph_data() -> Fallible<()> {
+        let runtime = commons::testing::init_runtime()?;
+        let tmpdir = tempfile::tempdir()?;
+        let data_dir = tmpdir.path();
+        fs::create_dir_all(data_dir.join(CHANNELS_DIR))?;
+        fs::create_dir_all(data_dir.join(BLOCKED_EDGES_DIR))?;
+        fs::create_dir_all(data_dir.join("raw"))?;
+
+        fs::write(data_dir.join("version"), "1.1.0\n")?;
+        fs::write(
+            data_dir.join(CHANNELS_DIR).join("stable-4.10.yaml"),
+            "name: stable-4.10\nversions:\n- 4.10.1\n- 4.10.2\n- 4.10.3\n",
+        )?;
+        fs::write(
+            data_dir.join(BLOCKED_EDGES_DIR).join("4.10.3.yaml"),
+            "to: 4.10.3\nfrom: 4\\.10\\.1\n",
+        )?;
+        fs::write(
+            data_dir.join(BLOCKED_EDGES_DIR).join("4.10.3-risk.yaml"),
+            concat!(
+                "to: 4.10.3\nfrom: 4\\.10\\.2\nurl: https://example.com\n",
+                "name: Risk\nmessage: Risky\nmatchingRules:\n- type: Always\n",
+            ),
+        )?;
+        fs::write(data_dir.join("raw/metadata.json"), r#"{"4.10.1": {}}"#)?;
+        assert_eq!(runtime.block_on(lint(data_dir))?, Vec::<String>::new());
+
+        fs::write(
+            data_dir.join(BLOCKED_EDGES_DIR).join("4.10.3-overlap.yaml"),
+            "to: 4.10.3\nfrom: 4\\.10\\..*\n",
+        )?;
+        fs::write(
+            data_dir.join(BLOCKED_EDGES_DIR).join("4.11.0.yaml"),
+            "to: 4.11.0\nfrom: 4\\.9\\..*\n",
+        )?;
+        fs::write(
+            data_dir.join(BLOCKED_EDGES_DIR).join("4.10.2-risk.yaml"),
+            concat!(
+                "to: 4.10.2\nfrom: 4\\.10\\.1\nurl: not a url\n",
+                "name: Broken risk\nmessage: Risky\nmatchingRules:\n",
+                "- type: PromQL\n- type: Sometimes\n- type: ClusterProfile\n",
+                "- type: ClusterProfile\n  clusterProfile:\n    versions:\n      etcd_version: three\n",
+            ),
+        )?;
+        fs::write(
+            data_dir.join(BLOCKED_EDGES_DIR).join("4.10.1.yaml"),
+            "to: 4.10.1\n",
+        )?;
+        fs::write(
+            data_dir.join("raw/metadata.json"),
+            r#"{"4.10.1": {}, "4.12.0": {}}"#,
+        )?;
+
+        let findings = runtime.block_on(lint(data_dir))?;
+        let expected = [
+            // Schema violation.
+            "blocked-edges/4.10.1.yaml:",
+            // Malformed risk.
+            "blocked-edges/4.10.2-risk.yaml: invalid url 'not a url'",
+            "blocked-edges/4.10.2-risk.yaml: risk name 'Broken risk'",
+            "blocked-edges/4.10.2-risk.yaml: matching rule 0 has no PromQL query",
+            "blocked-edges/4.10.2-risk.yaml: matching rule 1 has unknown type 'Sometimes'",
+            "blocked-edges/4.10.2-risk.yaml: matching rule 2 of type ClusterProfile has no properties",
+            "blocked-edges/4.10.2-risk.yaml: matching rule 3 has invalid version requirement 'three'",
+            // Dangling references.
+            "blocked-edges/4.11.0.yaml: version 4.11.0 is not in any channel",
+            "blocked-edges/4.11.0.yaml: 'from' 4\\.9\\..* matches no version in any channel",
+            "raw/metadata.json: version 4.12.0 is not in any channel",
+            // Overlaps, except between risks.
+            "blocked-edges/4.10.3-overlap.yaml: 'from' 4\\.10\\..* overlaps with 'from' 4\\.10\\.2 of blocked-edges/4.10.3-risk.yaml, both match 4.10.2 -> 4.10.3",
+            "blocked-edges/4.10.3-overlap.yaml: 'from' 4\\.10\\..* overlaps with 'from' 4\\.10\\.1 of blocked-edges/4.10.3.yaml, both match 4.10.1 -> 4.10.3",
+        ];
+        for prefix in &expected {
+            assert!(
+                findings.iter().any(|finding| finding.starts_with(prefix)),
+                "missing {:?} in {:#?}",
+                prefix,
+                findings
+            );
+        }
+        assert_eq!(findings.len(), expected.len(), "{:#?}", findings);
+
+        fs::write(data_dir.join("version"), "2.0.0\n")?;
+        let findings = runtime.block_on(lint(data_dir))?;
+        assert!(findings[0].starts_with("version: unsupported graph-data version '2.0.0'"));
+
+        Ok(())
+    }
+}
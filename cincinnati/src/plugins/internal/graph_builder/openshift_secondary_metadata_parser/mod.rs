@@ -2,7 +2,9 @@
 //! as used in https://github.com/openshift/cincinnati-graph-data.
 //! There is currently no formal specification for this format.
 
+pub mod lint;
 pub mod plugin;
+pub mod schema;
 
 pub use plugin::{
     OpenshiftSecondaryMetadataParserPlugin, OpenshiftSecondaryMetadataParserSettings,
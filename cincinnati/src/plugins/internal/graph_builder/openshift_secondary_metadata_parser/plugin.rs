@@ -3,18 +3,21 @@ use crate as cincinnati;
 use self::cincinnati::plugins::internal::graph_builder::github_openshift_secondary_metadata_scraper::plugin::GRAPH_DATA_DIR_PARAM_KEY;
 use self::cincinnati::plugins::prelude::*;
 use self::cincinnati::plugins::prelude_plugin_impl::*;
+use self::cincinnati::IStr;
 
+use crate::channel_lifecycle::Lifecycle;
 use crate::conditional_edges::{ConditionalEdge, ConditionalUpdateEdge, ConditionalUpdateRisk};
 use std::collections::HashSet;
 use std::path::Path;
 
 pub static DEFAULT_KEY_FILTER: &str = "io.openshift.upgrades.graph";
-static SUPPORTED_VERSIONS: &[&str] = &["1.0.0", "1.1.0"];
+pub static SUPPORTED_VERSIONS: &[&str] = &["1.0.0", "1.1.0"];
 
 pub mod graph_data_model {
     //! This module contains the data types corresponding to the graph data files.
 
-    use super::cincinnati::ClusterCondition;
+    use super::cincinnati::channel_lifecycle::Status;
+    use super::cincinnati::{ClusterCondition, PromQLClusterCondition};
     use serde::de::Visitor;
     use serde::Deserialize;
     use serde::Deserializer;
@@ -36,6 +39,8 @@ pub mod graph_data_model {
         pub message: String,
         #[serde(rename = "matchingRules")]
         pub matching_rules: Vec<ClusterCondition>,
+        #[serde(default, rename = "fleetEvaluation")]
+        pub fleet_evaluation: Option<PromQLClusterCondition>,
     }
 
     /// New type used to implement Deserialize for regex::Regex so we can use it in the `BlockedEdge` struct
@@ -88,6 +93,12 @@ pub mod graph_data_model {
     pub struct Channel {
         pub name: String,
         pub versions: Vec<semver::Version>,
+        /// Lifecycle of a channel being retired, unset for active channels.
+        #[serde(default)]
+        pub lifecycle: Option<Status>,
+        /// Date the channel stops receiving updates, as `YYYY-MM-DD`.
+        #[serde(default)]
+        pub sunset: Option<String>,
     }
 
     /// Represents the raw metadata file in the data repository.
@@ -132,6 +143,10 @@ pub struct OpenshiftSecondaryMetadataParserSettings {
     /// This field is used to define errors which are not tolerated while processing the files.
     /// See the `DeserializeDirectoryFilesError` enum for possible options.
     disallowed_errors: std::collections::HashSet<DeserializeDirectoryFilesErrorDiscriminants>,
+
+    /// Reject graph-data which doesn't match the schema, see the `schema` module.
+    #[default(true)]
+    validate_schema: bool,
 }
 
 impl OpenshiftSecondaryMetadataParserSettings {
@@ -170,6 +185,10 @@ impl PluginSettings for OpenshiftSecondaryMetadataParserSettings {
         let plugin = OpenshiftSecondaryMetadataParserPlugin::new(self.clone());
         Ok(new_plugin!(InternalPluginWrapper(plugin)))
     }
+
+    fn key_prefix(&self) -> Option<&str> {
+        Some(&self.key_prefix)
+    }
 }
 
 #[derive(Debug, Fail, strum_macros::EnumDiscriminants)]
@@ -213,7 +232,7 @@ where
             if disallowed_errors.contains(&variant) {
                 match $error_var.lock() {
                     Ok(mut guard) => {
-                        let inner = if let Some(previous_error) = std::mem::replace(&mut *guard, None) {
+                        let inner = if let Some(previous_error) = guard.take() {
                             previous_error.context(e)
                         } else {
                             Error::from(e)
@@ -306,7 +325,7 @@ pub static BLOCKED_EDGES_DIR: &str = "blocked-edges";
 pub static CHANNELS_DIR: &str = "channels";
 
 impl OpenshiftSecondaryMetadataParserPlugin {
-    pub(crate) const PLUGIN_NAME: &'static str = "openshift-secondary-metadata-parse";
+    pub const PLUGIN_NAME: &'static str = "openshift-secondary-metadata-parse";
 
     fn get_data_directory(&self, io: &InternalIO) -> PathBuf {
         if let Some(data_dir) = io.parameters.get(GRAPH_DATA_DIR_PARAM_KEY) {
@@ -350,36 +369,35 @@ impl OpenshiftSecondaryMetadataParserPlugin {
 
         raw_metadata.iter().for_each(|(version, metadata)| {
             metadata.iter().for_each(|(key, value)| {
-                graph.find_by_fn_mut(|release| {
-                    let release_semver = semver::Version::from_str(release.version())
+                let releases = graph.find_by_fn(|release| {
+                    let release_semver = cincinnati::semver_cache::parse_version(release.version())
                         .context(format!("Parsing {} as SemVer", release.version()));
                     if let Err(e) = &release_semver {
                         warn!("{}", e);
                     }
 
-                    let version_semver = semver::Version::from_str(version)
+                    let version_semver = cincinnati::semver_cache::parse_version(version)
                         .context(format!("Parsing {} as SemVer", &version));
                     if let Err(e) = &version_semver {
                         warn!("{}", e);
                     }
 
-                    match (release_semver, version_semver) {
-                        (Ok(release_semver), Ok(version_semver))
-                            if release_semver == version_semver =>
-                        {
-                            release.get_metadata_mut().map(|metadata| {
-                                metadata
-                                    .entry((*key).to_string())
-                                    .and_modify(|previous_add| {
-                                        *previous_add += &format!(",{}", &value)
-                                    })
-                                    .or_insert_with(|| (*value).to_string())
-                            });
-                            true
-                        }
-                        _ => false,
-                    }
+                    matches!(
+                        (release_semver, version_semver),
+                        (Ok(release_semver), Ok(version_semver)) if release_semver == version_semver
+                    )
                 });
+
+                for (release_id, _) in releases {
+                    if let Ok(metadata) = graph.get_metadata_as_ref_mut(&release_id) {
+                        metadata
+                            .entry(key.into())
+                            .and_modify(|previous_add| {
+                                *previous_add = format!("{},{}", previous_add, value).into()
+                            })
+                            .or_insert_with(|| value.into());
+                    }
+                }
             })
         });
 
@@ -411,8 +429,8 @@ impl OpenshiftSecondaryMetadataParserPlugin {
         let architectures = {
             let mut collection = std::collections::BTreeSet::<Vec<semver::Identifier>>::new();
 
-            let _ = graph.find_by_fn_mut(|release| {
-                match semver::Version::from_str(release.version()) {
+            let _ = graph.find_by_fn(|release| {
+                match cincinnati::semver_cache::parse_version(release.version()) {
                     Ok(version_semver) => {
                         collection.insert(version_semver.build);
                     }
@@ -476,8 +494,9 @@ impl OpenshiftSecondaryMetadataParserPlugin {
                                         format!(
                                             "{}.{}",
                                             self.settings.key_prefix, "previous.remove_regex"
-                                        ),
-                                        blocked_edge.from.to_string(),
+                                        )
+                                        .into(),
+                                        blocked_edge.from.to_string().into(),
                                     );
                                 }
                                 Err(e) => warn!("{}", e),
@@ -532,6 +551,7 @@ impl OpenshiftSecondaryMetadataParserPlugin {
                         name: cey.name,
                         message: cey.message,
                         matching_rules: cey.matching_rules,
+                        fleet_evaluation: cey.fleet_evaluation,
                     }],
                 };
                 graph.conditional_edges.as_mut().unwrap().push(ce);
@@ -556,17 +576,34 @@ impl OpenshiftSecondaryMetadataParserPlugin {
         .context(format!("Reading channels from {:?}", channels_dir))?;
         debug!("Found {} valid channel declarations.", channels.len());
 
-        let channels_key = format!("{}.release.channels", self.settings.key_prefix);
+        let channels_key: IStr = format!("{}.release.channels", self.settings.key_prefix).into();
         channels.into_iter().for_each(|channel|
         // Find out for each channel
         {
+            // The lifecycle is recorded once for the channel, on the graph
+            match (channel.lifecycle, channel.sunset.clone()) {
+                (Some(status), sunset) => match Lifecycle::new(status, sunset)
+                    .context(format!("[channels] Lifecycle of {}", &channel.name))
+                {
+                    Ok(lifecycle) => graph.set_channel_lifecycle(&channel.name, lifecycle),
+                    Err(e) => warn!("{:#}", e),
+                },
+                (None, Some(_)) => {
+                    warn!(
+                        "[channels] Ignoring the sunset of {} which has no lifecycle",
+                        &channel.name
+                    );
+                }
+                (None, None) => {}
+            };
+
             let versions_in_channel = channel
                 .versions
                 .iter()
                 .collect::<Vec<&semver::Version>>();
 
-            let releases_in_channel = graph.find_by_fn_mut(|release| {
-                let release_semver = match semver::Version::from_str(release.version())
+            let releases_in_channel = graph.find_by_fn(|release| {
+                let release_semver = match cincinnati::semver_cache::parse_version(release.version())
                     .context(format!("Parsing {} as SemVer", release.version()))
                 {
                     Ok(semver) => semver,
@@ -605,9 +642,9 @@ impl OpenshiftSecondaryMetadataParserPlugin {
                 metadata
                     .entry(channels_key.clone())
                     .and_modify(|channels_value| {
-                        channels_value.extend(format!(",{}", &channel.name).chars());
+                        *channels_value = format!("{},{}", channels_value, &channel.name).into();
                     })
-                    .or_insert_with(|| channel.name.clone());
+                    .or_insert_with(|| channel.name.as_str().into());
             }
         });
 
@@ -627,7 +664,7 @@ impl OpenshiftSecondaryMetadataParserPlugin {
                             let b_split: Vec<&str> = b.splitn(2, '-').collect();
                             a_split[1].cmp(b_split[1])
                         });
-                        *channels = channels_split.join(",")
+                        *channels = channels_split.join(",").into()
                     })
                 })
                 .is_some()
@@ -649,6 +686,9 @@ impl InternalPlugin for OpenshiftSecondaryMetadataParserPlugin {
         let data_dir = self.get_data_directory(&io);
 
         self.process_version(&data_dir).await?;
+        if self.settings.validate_schema {
+            super::schema::validate(&data_dir).await?;
+        }
         self.process_raw_metadata(&mut io.graph, &data_dir).await?;
         self.process_blocked_edges(&mut io.graph, &data_dir).await?;
         self.process_conditional_edges(&mut io.graph, &data_dir)
@@ -692,9 +732,7 @@ mod tests {
             let path = fixture_directory.join(filename);
             let string =
                 std::fs::read_to_string(&path).context(format!("Reading {:?} to string", &path))?;
-            serde_json::from_str(&string)
-                .context(format!("Deserializing {:?} to Graph", &path))
-                .map_err(Into::into)
+            serde_json::from_str(&string).context(format!("Deserializing {:?} to Graph", &path))
         };
 
         // Get the fixture data
@@ -809,4 +847,42 @@ mod tests {
             .context("Running plugin")
             .unwrap_err();
     }
+
+    #[test]
+    fn channel_lifecycle() -> Fallible<()> {
+        use self::cincinnati::channel_lifecycle::{Lifecycle, Status};
+
+        let runtime = commons::testing::init_runtime()?;
+        let data_dir = tempfile::tempdir()?;
+        let channels_dir = data_dir.path().join(super::CHANNELS_DIR);
+        std::fs::create_dir(&channels_dir)?;
+        std::fs::write(
+            channels_dir.join("stable-1.yaml"),
+            "name: stable-1\nversions: ['1.0.0', '2.0.0']\nlifecycle: deprecated\nsunset: 2024-06-30\n",
+        )?;
+        std::fs::write(
+            channels_dir.join("fast-1.yaml"),
+            "name: fast-1\nversions: ['1.0.0', '2.0.0', '3.0.0']\n",
+        )?;
+
+        let plugin = OpenshiftSecondaryMetadataParserPlugin::new(Default::default());
+        let mut graph = cincinnati::testing::generate_graph(false, false);
+        runtime.block_on(plugin.process_channels(&mut graph, data_dir.path()))?;
+
+        assert_eq!(
+            graph.channel_lifecycle("stable-1"),
+            Some(&Lifecycle {
+                status: Status::Deprecated,
+                sunset: Some("2024-06-30".to_string()),
+            })
+        );
+        assert_eq!(graph.channel_lifecycle("fast-1"), None);
+
+        // Releases only carry the channels.
+        let id = graph.find_by_version("1.0.0").unwrap();
+        let metadata = graph.get_metadata_as_ref_mut(&id)?;
+        assert!(metadata.keys().all(|key| !key.contains("lifecycle")));
+
+        Ok(())
+    }
 }
@@ -0,0 +1,212 @@
+//! JSON schema of the graph-data files.
+//!
+//! Graph-data is checked against the schema before it is applied to the
+//! graph, so that a malformed archive is rejected as a whole with a list of
+//! the offending files, instead of being partially applied.
+
+use super::plugin::{BLOCKED_EDGES_DIR, CHANNELS_DIR, SUPPORTED_VERSIONS};
+use commons::prelude_errors::*;
+use jsonschema::{Draft, JSONSchema};
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+
+/// Version of the graph-data format described by the schema.
+pub static SCHEMA_VERSION: &str = "1.1.0";
+
+/// Maximum number of violations listed in a validation error.
+pub static MAX_REPORTED_VIOLATIONS: usize = 20;
+
+static SCHEMA: &str = include_str!("graph_data_schema.json");
+
+lazy_static::lazy_static! {
+    static ref CHANNEL: JSONSchema = compile("channel");
+    static ref BLOCKED_EDGE: JSONSchema = compile("blockedEdge");
+    static ref RAW_METADATA: JSONSchema = compile("rawMetadata");
+}
+
+/// Returns the schema document, including the graph-data versions it applies to.
+pub fn schema() -> Value {
+    let mut schema: Value = serde_json::from_str(SCHEMA).expect("invalid graph-data schema");
+    if let Some(object) = schema.as_object_mut() {
+        object.insert("version".to_string(), SCHEMA_VERSION.into());
+        object.insert("supportedVersions".to_string(), SUPPORTED_VERSIONS.into());
+    }
+    schema
+}
+
+/// Compile the schema of one of the definitions.
+fn compile(definition: &str) -> JSONSchema {
+    let mut schema = schema();
+    schema["$ref"] = format!("#/definitions/{}", definition).into();
+
+    JSONSchema::options()
+        .with_draft(Draft::Draft7)
+        .compile(&schema)
+        .unwrap_or_else(|e| panic!("invalid graph-data schema for {}: {}", definition, e))
+}
+
+/// Check a parsed file against a schema, appending its violations.
+fn check(schema: &JSONSchema, file: &str, instance: &Value, violations: &mut Vec<String>) {
+    if let Err(errors) = schema.validate(instance) {
+        violations.extend(errors.map(|e| {
+            let path = e.instance_path.to_string();
+            if path.is_empty() {
+                format!("{}: {}", file, e)
+            } else {
+                format!("{} at {}: {}", file, path, e)
+            }
+        }));
+    }
+}
+
+/// List the YAML files of a graph-data directory, sorted, along with their
+/// path relative to the graph-data root.
+pub(super) async fn yaml_files(data_dir: &Path, dir: &str) -> Fallible<Vec<(String, PathBuf)>> {
+    let path = data_dir.join(dir);
+    if !path.is_dir() {
+        return Ok(vec![]);
+    }
+
+    let mut entries = tokio::fs::read_dir(&path)
+        .await
+        .context(format!("Reading directory {:?}", &path))?;
+    let mut files = vec![];
+    while let Some(entry) = entries.next_entry().await? {
+        let path = entry.path();
+        let is_yaml = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .is_some_and(|extension| extension == "yaml" || extension == "yml");
+        if is_yaml {
+            files.push(path);
+        }
+    }
+    files.sort();
+
+    Ok(files
+        .into_iter()
+        .map(|path| {
+            let file = format!(
+                "{}/{}",
+                dir,
+                path.file_name().unwrap_or_default().to_string_lossy()
+            );
+            (file, path)
+        })
+        .collect())
+}
+
+/// Check the YAML files of a graph-data directory.
+async fn check_yaml_dir(
+    schema: &JSONSchema,
+    data_dir: &Path,
+    dir: &str,
+    violations: &mut Vec<String>,
+) -> Fallible<()> {
+    for (file, path) in yaml_files(data_dir, dir).await? {
+        let content = tokio::fs::read(&path)
+            .await
+            .context(format!("Reading {:?}", &path))?;
+        match serde_yaml::from_slice::<Value>(&content) {
+            Ok(instance) => check(schema, &file, &instance, violations),
+            Err(e) => violations.push(format!("{}: invalid YAML: {}", file, e)),
+        }
+    }
+
+    Ok(())
+}
+
+/// List the violations of the schema by the files of a graph-data directory.
+pub async fn violations(data_dir: &Path) -> Fallible<Vec<String>> {
+    let mut violations = vec![];
+
+    check_yaml_dir(&CHANNEL, data_dir, CHANNELS_DIR, &mut violations).await?;
+    check_yaml_dir(&BLOCKED_EDGE, data_dir, BLOCKED_EDGES_DIR, &mut violations).await?;
+
+    let raw_metadata = data_dir.join("raw/metadata.json");
+    if raw_metadata.is_file() {
+        let content = tokio::fs::read(&raw_metadata)
+            .await
+            .context(format!("Reading {:?}", &raw_metadata))?;
+        match serde_json::from_slice::<Value>(&content) {
+            Ok(instance) => check(
+                &RAW_METADATA,
+                "raw/metadata.json",
+                &instance,
+                &mut violations,
+            ),
+            Err(e) => violations.push(format!("raw/metadata.json: invalid JSON: {}", e)),
+        }
+    }
+
+    Ok(violations)
+}
+
+/// Validate the files of a graph-data directory against the schema.
+///
+/// All violations are reported at once, up to `MAX_REPORTED_VIOLATIONS`.
+pub async fn validate(data_dir: &Path) -> Fallible<()> {
+    let mut violations = violations(data_dir).await?;
+    if violations.is_empty() {
+        return Ok(());
+    }
+
+    let count = violations.len();
+    violations.truncate(MAX_REPORTED_VIOLATIONS);
+    bail!(
+        "graph-data doesn't match schema version {}, {} violations:\n{}",
+        SCHEMA_VERSION,
+        count,
+        violations.join("\n")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn validate_graph_data() -> Fallible<()> {
+        let runtime = commons::testing::init_runtime()?;
+        let tmpdir = tempfile::tempdir()?;
+        let data_dir = tmpdir.path();
+        fs::create_dir_all(data_dir.join(CHANNELS_DIR))?;
+        fs::create_dir_all(data_dir.join(BLOCKED_EDGES_DIR))?;
+        fs::create_dir_all(data_dir.join("raw"))?;
+
+        fs::write(
+            data_dir.join(CHANNELS_DIR).join("stable-4.10.yaml"),
+            "name: stable-4.10\nversions:\n- 4.10.1\n- 4.10.3\n",
+        )?;
+        fs::write(
+            data_dir.join(BLOCKED_EDGES_DIR).join("4.10.3-risk.yaml"),
+            concat!(
+                "to: 4.10.3\nfrom: 4\\.9\\..*\nurl: https://example.com\n",
+                "name: Risk\nmessage: Risky\nmatchingRules:\n- type: Always\n",
+            ),
+        )?;
+        fs::write(data_dir.join("raw/metadata.json"), "{}")?;
+        runtime.block_on(validate(data_dir))?;
+
+        fs::write(
+            data_dir.join(CHANNELS_DIR).join("fast-4.10.yaml"),
+            "name: fast-4.10\nversions:\n- '4.10'\n",
+        )?;
+        fs::write(
+            data_dir.join(BLOCKED_EDGES_DIR).join("4.10.1.yaml"),
+            "to: 4.10.1\n",
+        )?;
+        let error = runtime
+            .block_on(validate(data_dir))
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("2 violations"), "{}", error);
+        assert!(error.contains("channels/fast-4.10.yaml at /versions/0"));
+        assert!(error.contains("blocked-edges/4.10.1.yaml"));
+
+        assert_eq!(schema()["version"], SCHEMA_VERSION);
+
+        Ok(())
+    }
+}
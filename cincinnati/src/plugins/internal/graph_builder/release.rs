@@ -32,9 +32,14 @@ pub struct Release {
 impl From<Release> for cincinnati::Release {
     fn from(r: Release) -> cincinnati::Release {
         cincinnati::Release::Concrete(cincinnati::ConcreteRelease {
-            version: r.metadata.version.to_string(),
+            version: r.metadata.version.to_string().into(),
             payload: r.source,
-            metadata: r.metadata.metadata,
+            metadata: r
+                .metadata
+                .metadata
+                .into_iter()
+                .map(|(key, value)| (key.into(), value.into()))
+                .collect(),
         })
     }
 }
@@ -107,7 +112,7 @@ pub fn create_graph(releases: Vec<Release>) -> Result<cincinnati::Graph, Error>
                             warn!("Adding abstract release for {}", version.to_string());
                             graph.add_release(cincinnati::Release::Abstract(
                                 cincinnati::AbstractRelease {
-                                    version: version.to_string(),
+                                    version: version.to_string().into(),
                                 },
                             ))?
                         }
@@ -137,7 +142,7 @@ pub fn create_graph(releases: Vec<Release>) -> Result<cincinnati::Graph, Error>
                             warn!("Adding abstract release for {}", version.to_string());
                             graph.add_release(cincinnati::Release::Abstract(
                                 cincinnati::AbstractRelease {
-                                    version: version.to_string(),
+                                    version: version.to_string().into(),
                                 },
                             ))?
                         }
@@ -0,0 +1,14 @@
+//! This plugin verifies cosign signatures of release images.
+//!
+//! Signatures are looked up in the release repository under the conventional
+//! `sha256-<digest>.sig` tag and checked against a configured public key.
+//! Releases without a valid signature are removed from the graph, unless the
+//! plugin runs in report-only mode. The payloads of removed releases are
+//! passed on to the following plugins and the caller as parameter.
+
+mod oci;
+pub mod plugin;
+
+pub use plugin::{
+    ReleaseCosignVerifyPlugin, ReleaseCosignVerifySettings, QUARANTINED_RELEASES_PARAM_KEY,
+};
@@ -0,0 +1,190 @@
+//! Minimal OCI distribution client for fetching cosign signature artifacts.
+//!
+//! The dkregistry client doesn't expose layer annotations, which is where
+//! cosign stores its signatures, so manifests are fetched and decoded here.
+
+use crate as cincinnati;
+
+use self::cincinnati::plugins::internal::release_scrape_dockerv2::registry::auth::{
+    self, Authorization,
+};
+use commons::prelude_errors::*;
+use custom_debug_derive::Debug as CustomDebug;
+use reqwest::header::ACCEPT;
+use reqwest::StatusCode;
+use serde::Deserialize;
+use std::collections::HashMap;
+use tokio::sync::RwLock;
+
+/// Media types accepted for signature manifests.
+static MANIFEST_ACCEPT: &str =
+    "application/vnd.oci.image.manifest.v1+json, application/vnd.docker.distribution.manifest.v2+json";
+
+/// A reference to an image by digest, as found in a release payload.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DigestReference {
+    pub host: String,
+    pub repository: String,
+    pub digest: String,
+}
+
+impl DigestReference {
+    /// Parse a `host/repository@sha256:<hex>` reference.
+    pub fn parse(payload: &str) -> Fallible<Self> {
+        let (name, digest) = payload
+            .split_once('@')
+            .ok_or_else(|| format_err!("'{}' is not a digest reference", payload))?;
+        let (host, repository) = name
+            .split_once('/')
+            .ok_or_else(|| format_err!("'{}' has no registry host", payload))?;
+        ensure!(
+            digest.starts_with("sha256:") && digest.len() > "sha256:".len(),
+            "'{}' has an unsupported digest",
+            payload
+        );
+
+        Ok(Self {
+            host: host.to_string(),
+            repository: repository.to_string(),
+            digest: digest.to_string(),
+        })
+    }
+
+    /// Tag under which cosign stores the signatures for this image.
+    pub fn signature_tag(&self) -> String {
+        format!("{}.sig", self.digest.replacen(':', "-", 1))
+    }
+}
+
+/// A layer descriptor of an OCI manifest.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Descriptor {
+    pub media_type: String,
+    pub digest: String,
+    #[serde(default)]
+    pub annotations: HashMap<String, String>,
+}
+
+/// The subset of an OCI manifest needed for signature verification.
+#[derive(Debug, Deserialize)]
+pub struct Manifest {
+    #[serde(default)]
+    pub layers: Vec<Descriptor>,
+}
+
+/// Client for reading manifests and blobs, authorizing as challenged by the registries.
+#[derive(CustomDebug)]
+pub struct OciClient {
+    client: reqwest::Client,
+    scheme: &'static str,
+    #[debug(skip)]
+    credentials: HashMap<String, (String, String)>,
+    /// Authorization of the requests, by repository.
+    #[debug(skip)]
+    authorizations: RwLock<HashMap<String, Authorization>>,
+}
+
+impl OciClient {
+    pub fn new(
+        client: reqwest::Client,
+        insecure: bool,
+        credentials: HashMap<String, (String, String)>,
+    ) -> Self {
+        Self {
+            client,
+            scheme: if insecure { "http" } else { "https" },
+            credentials,
+            authorizations: Default::default(),
+        }
+    }
+
+    /// Fetch a manifest by tag or digest. Returns `None` if it doesn't exist.
+    pub async fn get_manifest(
+        &self,
+        host: &str,
+        repository: &str,
+        reference: &str,
+    ) -> Fallible<Option<Manifest>> {
+        let url = format!(
+            "{}://{}/v2/{}/manifests/{}",
+            self.scheme, host, repository, reference
+        );
+        match self.get(host, repository, &url, MANIFEST_ACCEPT).await? {
+            Some(body) => Ok(Some(serde_json::from_slice(&body)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Fetch a blob by digest.
+    pub async fn get_blob(&self, host: &str, repository: &str, digest: &str) -> Fallible<Vec<u8>> {
+        let url = format!(
+            "{}://{}/v2/{}/blobs/{}",
+            self.scheme, host, repository, digest
+        );
+        self.get(host, repository, &url, "*/*")
+            .await?
+            .ok_or_else(|| format_err!("blob {} not found in {}/{}", digest, host, repository))
+    }
+
+    async fn get(
+        &self,
+        host: &str,
+        repository: &str,
+        url: &str,
+        accept: &str,
+    ) -> Fallible<Option<Vec<u8>>> {
+        let key = format!("{}/{}", host, repository);
+
+        for attempt in 0..2 {
+            let mut request = self.client.get(url).header(ACCEPT, accept);
+            if let Some(authorization) = self.authorizations.read().await.get(&key) {
+                request = authorization.apply(request);
+            }
+            let response = request.send().await?;
+
+            match response.status() {
+                StatusCode::NOT_FOUND => return Ok(None),
+                StatusCode::UNAUTHORIZED if attempt == 0 => {
+                    let challenge = auth::challenge(&response)?;
+                    let authorization = auth::authorize(
+                        &self.client,
+                        &challenge,
+                        &format!("repository:{}:pull", repository),
+                        self.credentials.get(host),
+                    )
+                    .await?;
+                    self.authorizations
+                        .write()
+                        .await
+                        .insert(key.clone(), authorization);
+                }
+                status if status.is_success() => {
+                    return Ok(Some(response.bytes().await?.to_vec()));
+                }
+                status => bail!("fetching {}: {}", url, status),
+            }
+        }
+
+        bail!("fetching {}: authorization failed", url)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_digest_reference() {
+        let reference =
+            DigestReference::parse("quay.io/openshift-release-dev/ocp-release@sha256:0123abcd")
+                .unwrap();
+        assert_eq!(reference.host, "quay.io");
+        assert_eq!(reference.repository, "openshift-release-dev/ocp-release");
+        assert_eq!(reference.signature_tag(), "sha256-0123abcd.sig");
+
+        assert!(DigestReference::parse("quay.io/openshift:4.1.0").is_err());
+        assert!(DigestReference::parse("ocp-release@sha256:0123abcd").is_err());
+        assert!(DigestReference::parse("quay.io/ocp-release@md5:0123").is_err());
+    }
+}
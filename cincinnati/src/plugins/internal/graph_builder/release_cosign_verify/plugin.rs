@@ -0,0 +1,496 @@
+use super::oci::{DigestReference, OciClient};
+
+use crate as cincinnati;
+
+use self::cincinnati::plugins::internal::release_scrape_dockerv2::registry;
+use self::cincinnati::plugins::prelude::*;
+use self::cincinnati::plugins::prelude_plugin_impl::*;
+use self::cincinnati::Release;
+
+use futures::stream::{self, StreamExt};
+use p256::ecdsa::signature::Verifier;
+use p256::ecdsa::{Signature, VerifyingKey};
+use p256::pkcs8::DecodePublicKey;
+use prometheus::IntGauge;
+use std::collections::{HashMap, HashSet};
+use std::convert::TryInto;
+use std::time::Duration;
+use tokio::sync::RwLock;
+
+/// Media type of cosign simple-signing payloads.
+pub static SIMPLESIGNING_MEDIA_TYPE: &str = "application/vnd.dev.cosign.simplesigning.v1+json";
+
+/// Layer annotation carrying the base64-encoded signature.
+pub static SIGNATURE_ANNOTATION: &str = "dev.cosignproject.cosign/signature";
+
+/// Parameter key under which the payloads of removed releases are passed on,
+/// comma-separated and sorted.
+pub static QUARANTINED_RELEASES_PARAM_KEY: &str =
+    "io.openshift.upgrades.graph.cosign.quarantined_releases";
+
+/// Default number of concurrent verifications.
+pub static DEFAULT_VERIFY_CONCURRENCY: usize = 16;
+
+/// Default timeout for registry requests, in seconds.
+pub static DEFAULT_VERIFY_TIMEOUT_SECS: u64 = 30;
+
+/// Plugin settings.
+#[derive(Clone, Debug, Deserialize, SmartDefault)]
+#[serde(default)]
+pub struct ReleaseCosignVerifySettings {
+    /// Path to the PEM-encoded ECDSA P-256 public key which signs the releases.
+    pub public_key_path: PathBuf,
+
+    /// Whether releases without a valid signature are removed from the graph.
+    /// If disabled they are only reported. Releases whose signatures can't be
+    /// fetched, e.g. while the registry is unavailable, are never removed;
+    /// the run fails instead so that the last published graph is kept.
+    #[default(true)]
+    pub enforce: bool,
+
+    /// Maximum number of releases verified concurrently.
+    #[default(DEFAULT_VERIFY_CONCURRENCY)]
+    pub concurrency: usize,
+
+    /// Timeout for a single registry request, in seconds.
+    #[default(DEFAULT_VERIFY_TIMEOUT_SECS)]
+    pub timeout_secs: u64,
+
+    /// Access the registries over plain HTTP.
+    pub insecure: bool,
+
+    /// File containing the credentials (in "dockercfg" format) for the registries.
+    pub credentials_path: Option<PathBuf>,
+
+    /// File containing PEM-encoded CA certificates to trust for the registries,
+    /// in addition to the system ones.
+    pub ca_bundle_path: Option<PathBuf>,
+
+    /// File containing the PEM-encoded client certificate presented to the registries.
+    pub client_cert_path: Option<PathBuf>,
+
+    /// File containing the PKCS#8 PEM-encoded key of the client certificate.
+    pub client_key_path: Option<PathBuf>,
+}
+
+impl PluginSettings for ReleaseCosignVerifySettings {
+    fn build_plugin(&self, registry: Option<&prometheus::Registry>) -> Fallible<BoxedPlugin> {
+        let plugin = ReleaseCosignVerifyPlugin::try_new(self.clone(), registry)?;
+        Ok(new_plugin!(InternalPluginWrapper(plugin)))
+    }
+}
+
+impl ReleaseCosignVerifySettings {
+    /// Validate plugin configuration and fill in defaults.
+    pub fn deserialize_config(cfg: toml::Value) -> Fallible<Box<dyn PluginSettings>> {
+        let settings: Self = cfg.try_into()?;
+
+        ensure!(
+            settings.public_key_path != PathBuf::new(),
+            "empty public_key_path; keyless verification is not supported"
+        );
+        ensure!(settings.concurrency > 0, "concurrency must be positive");
+        ensure!(settings.timeout_secs > 0, "timeout_secs must be positive");
+        ensure!(
+            settings.client_cert_path.is_some() == settings.client_key_path.is_some(),
+            "client_cert_path and client_key_path must be set together"
+        );
+
+        Ok(Box::new(settings))
+    }
+}
+
+/// The part of a cosign simple-signing payload which binds it to an image.
+#[derive(Debug, Deserialize)]
+struct SimpleSigning {
+    critical: SimpleSigningCritical,
+}
+
+#[derive(Debug, Deserialize)]
+struct SimpleSigningCritical {
+    image: SimpleSigningImage,
+}
+
+#[derive(Debug, Deserialize)]
+struct SimpleSigningImage {
+    #[serde(rename = "docker-manifest-digest")]
+    docker_manifest_digest: String,
+}
+
+/// Verify a cosign signature over `payload` and check that it refers to `expected_digest`.
+pub fn verify_signature(
+    key: &VerifyingKey,
+    payload: &[u8],
+    signature: &str,
+    expected_digest: &str,
+) -> Fallible<()> {
+    let signature = base64::decode(signature.trim()).context("decoding signature")?;
+    let signature = Signature::from_der(&signature).map_err(|e| format_err!("{}", e))?;
+    key.verify(payload, &signature)
+        .map_err(|e| format_err!("signature mismatch: {}", e))?;
+
+    let payload: SimpleSigning =
+        serde_json::from_slice(payload).context("parsing simple-signing payload")?;
+    ensure!(
+        payload.critical.image.docker_manifest_digest == expected_digest,
+        "signature is for {}, not {}",
+        payload.critical.image.docker_manifest_digest,
+        expected_digest
+    );
+
+    Ok(())
+}
+
+/// Outcome of a verification which completed.
+#[derive(Debug)]
+enum Verification {
+    /// The release carries a valid signature.
+    Verified,
+    /// The release definitely has no valid signature.
+    Rejected(Error),
+}
+
+/// Removes releases whose payload doesn't carry a valid cosign signature.
+#[derive(CustomDebug)]
+pub struct ReleaseCosignVerifyPlugin {
+    settings: ReleaseCosignVerifySettings,
+
+    #[debug(skip)]
+    key: VerifyingKey,
+
+    #[debug(skip)]
+    client: OciClient,
+
+    /// Payloads which have been verified successfully.
+    #[debug(skip)]
+    verified: RwLock<HashSet<String>>,
+
+    #[debug(skip)]
+    rejected_releases: IntGauge,
+}
+
+impl ReleaseCosignVerifyPlugin {
+    /// Plugin name, for configuration.
+    pub const PLUGIN_NAME: &'static str = "release-cosign-verify";
+
+    pub fn try_new(
+        settings: ReleaseCosignVerifySettings,
+        prometheus_registry: Option<&prometheus::Registry>,
+    ) -> Fallible<Self> {
+        let rejected_releases = IntGauge::new(
+            "graph_cosign_rejected_releases",
+            "Number of releases without a valid cosign signature in the last run",
+        )?;
+        if let Some(prometheus_registry) = &prometheus_registry {
+            prometheus_registry.register(Box::new(rejected_releases.clone()))?;
+        }
+
+        let pem = std::fs::read_to_string(&settings.public_key_path).context(format!(
+            "reading public key from {:?}",
+            &settings.public_key_path
+        ))?;
+        let key = VerifyingKey::from_public_key_pem(&pem)
+            .map_err(|e| format_err!("parsing public key: {}", e))?;
+
+        let credentials = match &settings.credentials_path {
+            Some(path) => read_dockercfg(path)?,
+            None => HashMap::new(),
+        };
+
+        let client = registry::ClientTls::try_new(
+            settings.ca_bundle_path.as_deref(),
+            settings.client_cert_path.as_deref(),
+            settings.client_key_path.as_deref(),
+        )?
+        .configure(commons::outbound::client_builder())
+        .timeout(Duration::from_secs(settings.timeout_secs))
+        .build()
+        .context("Building reqwest client")?;
+
+        Ok(Self {
+            client: OciClient::new(client, settings.insecure, credentials),
+            settings,
+            key,
+            verified: Default::default(),
+            rejected_releases,
+        })
+    }
+
+    /// Verify the signatures of a single release payload.
+    ///
+    /// Errors are failures to fetch the signatures, which say nothing about
+    /// the release and may be transient.
+    async fn verify(&self, payload: &str) -> Fallible<Verification> {
+        let reference = match DigestReference::parse(payload) {
+            Ok(reference) => reference,
+            Err(e) => return Ok(Verification::Rejected(e)),
+        };
+
+        let manifest = match self
+            .client
+            .get_manifest(
+                &reference.host,
+                &reference.repository,
+                &reference.signature_tag(),
+            )
+            .await?
+        {
+            Some(manifest) => manifest,
+            None => return Ok(Verification::Rejected(format_err!("no signatures found"))),
+        };
+
+        let mut last_error = format_err!("no cosign signature layers found");
+        for layer in manifest
+            .layers
+            .iter()
+            .filter(|layer| layer.media_type == SIMPLESIGNING_MEDIA_TYPE)
+        {
+            let signature = match layer.annotations.get(SIGNATURE_ANNOTATION) {
+                Some(signature) => signature,
+                None => continue,
+            };
+            let blob = self
+                .client
+                .get_blob(&reference.host, &reference.repository, &layer.digest)
+                .await?;
+
+            match verify_signature(&self.key, &blob, signature, &reference.digest) {
+                Ok(()) => return Ok(Verification::Verified),
+                Err(e) => last_error = e,
+            }
+        }
+
+        Ok(Verification::Rejected(last_error))
+    }
+}
+
+/// Read all credentials from a dockercfg file, keyed by registry host.
+fn read_dockercfg(path: &std::path::Path) -> Fallible<HashMap<String, (String, String)>> {
+    #[derive(Deserialize)]
+    struct DockerConfig {
+        auths: HashMap<String, serde_json::Value>,
+    }
+
+    let file = std::fs::File::open(path).context(format!("could not open '{:?}'", path))?;
+    let config: DockerConfig = serde_json::from_reader(file)?;
+
+    let mut credentials = HashMap::new();
+    for host in config.auths.keys() {
+        if let (Some(username), Some(password)) =
+            registry::read_credentials(Some(&path.to_path_buf()), host)?
+        {
+            credentials.insert(host.clone(), (username, password));
+        }
+    }
+
+    Ok(credentials)
+}
+
+#[async_trait]
+impl InternalPlugin for ReleaseCosignVerifyPlugin {
+    const PLUGIN_NAME: &'static str = Self::PLUGIN_NAME;
+
+    async fn run_internal(&self, io: InternalIO) -> Fallible<InternalIO> {
+        let mut graph = io.graph;
+
+        let unverified: Vec<String> = {
+            let verified = self.verified.read().await;
+            graph
+                .iter_releases()
+                .filter_map(|release| match release {
+                    Release::Concrete(release) if !verified.contains(&release.payload) => {
+                        Some(release.payload.clone())
+                    }
+                    _ => None,
+                })
+                .collect()
+        };
+
+        let results: Vec<(String, Fallible<Verification>)> = stream::iter(unverified)
+            .map(|payload| async move {
+                let result = self.verify(&payload).await;
+                (payload, result)
+            })
+            .buffer_unordered(self.settings.concurrency)
+            .collect()
+            .await;
+
+        let mut rejected = HashSet::new();
+        let mut unavailable = vec![];
+        {
+            let mut verified = self.verified.write().await;
+            for (payload, result) in results {
+                match result {
+                    Ok(Verification::Verified) => {
+                        verified.insert(payload);
+                    }
+                    Ok(Verification::Rejected(e)) => {
+                        warn!("rejecting release {}: {:#}", payload, e);
+                        rejected.insert(payload);
+                    }
+                    Err(e) => {
+                        warn!("could not verify release {}: {:#}", payload, e);
+                        unavailable.push(payload);
+                    }
+                }
+            }
+        }
+
+        self.rejected_releases.set(rejected.len().try_into()?);
+
+        // Removing releases which couldn't be verified would unpublish them on
+        // every registry hiccup, and keeping them would publish unverified
+        // releases. Fail the run instead, which keeps the last published graph.
+        if self.settings.enforce && !unavailable.is_empty() {
+            unavailable.sort();
+            bail!(
+                "could not verify the signatures of {} releases: {}",
+                unavailable.len(),
+                unavailable.join(", ")
+            );
+        }
+
+        let mut parameters = io.parameters;
+        if self.settings.enforce && !rejected.is_empty() {
+            let to_remove = graph
+                .find_by_fn(|release| match release {
+                    Release::Concrete(release) => rejected.contains(&release.payload),
+                    Release::Abstract(_) => false,
+                })
+                .into_iter()
+                .map(|(release_id, _)| release_id)
+                .collect();
+            let removed = graph.remove_releases(to_remove);
+            info!("removed {} releases without a valid signature", removed);
+
+            let mut quarantined: Vec<String> = rejected.into_iter().collect();
+            quarantined.sort();
+            parameters.insert(
+                QUARANTINED_RELEASES_PARAM_KEY.to_string(),
+                quarantined.join(","),
+            );
+        }
+
+        Ok(InternalIO { graph, parameters })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use p256::ecdsa::signature::Signer;
+    use p256::ecdsa::SigningKey;
+
+    fn signing_key() -> SigningKey {
+        SigningKey::from_slice(&[7u8; 32]).unwrap()
+    }
+
+    fn sign(key: &SigningKey, payload: &[u8]) -> String {
+        let signature: Signature = key.sign(payload);
+        base64::encode(signature.to_der().as_bytes())
+    }
+
+    fn payload(digest: &str) -> Vec<u8> {
+        format!(
+            r#"{{"critical":{{"identity":{{"docker-reference":"quay.io/test"}},"image":{{"docker-manifest-digest":"{}"}},"type":"cosign container image signature"}},"optional":null}}"#,
+            digest
+        )
+        .into_bytes()
+    }
+
+    #[test]
+    fn verify_valid_signature() {
+        let key = signing_key();
+        let payload = payload("sha256:abcd");
+        let signature = sign(&key, &payload);
+
+        verify_signature(key.verifying_key(), &payload, &signature, "sha256:abcd").unwrap();
+    }
+
+    #[test]
+    fn reject_signature_for_other_digest() {
+        let key = signing_key();
+        let payload = payload("sha256:abcd");
+        let signature = sign(&key, &payload);
+
+        assert!(
+            verify_signature(key.verifying_key(), &payload, &signature, "sha256:ffff").is_err()
+        );
+    }
+
+    #[test]
+    fn reject_tampered_payload() {
+        let key = signing_key();
+        let signature = sign(&key, &payload("sha256:abcd"));
+
+        assert!(verify_signature(
+            key.verifying_key(),
+            &payload("sha256:ffff"),
+            &signature,
+            "sha256:ffff"
+        )
+        .is_err());
+    }
+
+    #[tokio::test]
+    async fn only_definitive_failures_remove_releases() -> Fallible<()> {
+        use p256::pkcs8::{EncodePublicKey, LineEnding};
+
+        let tmpdir = tempfile::tempdir()?;
+        let public_key_path = tmpdir.path().join("cosign.pub");
+        let pem = signing_key()
+            .verifying_key()
+            .to_public_key_pem(LineEnding::LF)
+            .map_err(|e| format_err!("{}", e))?;
+        std::fs::write(&public_key_path, pem)?;
+
+        let plugin = ReleaseCosignVerifyPlugin::try_new(
+            ReleaseCosignVerifySettings {
+                public_key_path,
+                insecure: true,
+                ..Default::default()
+            },
+            None,
+        )?;
+        let input = |repository: &str| -> Fallible<InternalIO> {
+            let mut graph = cincinnati::Graph::default();
+            graph.add_release(Release::Concrete(cincinnati::ConcreteRelease {
+                version: "1.0.0".into(),
+                payload: format!("{}/{}@sha256:abcd", mockito::server_address(), repository),
+                metadata: Default::default(),
+            }))?;
+            Ok(InternalIO {
+                graph,
+                parameters: Default::default(),
+            })
+        };
+
+        let _unavailable = mockito::mock("GET", "/v2/cosign-unavailable/manifests/sha256-abcd.sig")
+            .with_status(503)
+            .create();
+        assert!(plugin
+            .run_internal(input("cosign-unavailable")?)
+            .await
+            .is_err());
+
+        let _unsigned = mockito::mock("GET", "/v2/cosign-unsigned/manifests/sha256-abcd.sig")
+            .with_status(404)
+            .create();
+        let output = plugin.run_internal(input("cosign-unsigned")?).await?;
+        assert_eq!(output.graph.releases_count(), 0);
+        assert!(output
+            .parameters
+            .contains_key(QUARANTINED_RELEASES_PARAM_KEY));
+
+        Ok(())
+    }
+
+    #[test]
+    fn deserialize_config_requires_key() {
+        let cfg: toml::Value = toml::from_str("enforce = true").unwrap();
+        assert!(ReleaseCosignVerifySettings::deserialize_config(cfg).is_err());
+
+        let cfg: toml::Value = toml::from_str(r#"public_key_path = "/etc/cosign.pub""#).unwrap();
+        assert!(ReleaseCosignVerifySettings::deserialize_config(cfg).is_ok());
+    }
+}
@@ -0,0 +1,416 @@
+//! This plugin POSTs newly discovered releases to external enrichment services
+//! and merges the returned metadata into the graph.
+//!
+//! Each configured hook receives the release descriptor as JSON (`version`,
+//! `payload` and `metadata`) and must answer with a JSON object of the form
+//! `{"metadata": {"<key>": "<value>"}}`. All returned keys must start with the
+//! configured `key_prefix`. Responses are cached by payload as long as the
+//! release is part of the graph, so every release is only sent once unless a
+//! hook fails, and the cache doesn't outgrow the graph.
+
+use crate as cincinnati;
+
+use self::cincinnati::plugins::prelude::*;
+use self::cincinnati::plugins::prelude_plugin_impl::*;
+use self::cincinnati::{ConcreteRelease, IStr, MapImpl, Release};
+
+use futures::stream::{self, StreamExt};
+use prometheus::IntCounter;
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+use tokio::sync::RwLock;
+
+/// Default timeout for a single hook request, in seconds.
+pub static DEFAULT_HOOK_TIMEOUT_SECS: u64 = 10;
+
+/// Default number of concurrent hook requests.
+pub static DEFAULT_HOOK_CONCURRENCY: usize = 8;
+
+/// Default prefix which all returned metadata keys must carry.
+pub static DEFAULT_HOOK_KEY_PREFIX: &str = "io.openshift.upgrades.graph.enrichment.";
+
+/// Plugin settings.
+#[derive(Clone, Debug, Deserialize, SmartDefault)]
+#[serde(default)]
+pub struct ReleaseEnrichmentHookSettings {
+    /// URLs of the enrichment services.
+    pub urls: Vec<String>,
+
+    /// Timeout for a single hook request, in seconds.
+    #[default(DEFAULT_HOOK_TIMEOUT_SECS)]
+    pub timeout_secs: u64,
+
+    /// Maximum number of hook requests in flight.
+    #[default(DEFAULT_HOOK_CONCURRENCY)]
+    pub concurrency: usize,
+
+    /// Prefix which all returned metadata keys must carry.
+    #[default(DEFAULT_HOOK_KEY_PREFIX.to_string())]
+    pub key_prefix: String,
+
+    /// Whether a failing hook should fail the whole plugin chain.
+    pub fail_on_error: bool,
+}
+
+impl PluginSettings for ReleaseEnrichmentHookSettings {
+    fn build_plugin(&self, registry: Option<&prometheus::Registry>) -> Fallible<BoxedPlugin> {
+        let plugin = ReleaseEnrichmentHookPlugin::try_new(self.clone(), registry)?;
+        Ok(new_plugin!(InternalPluginWrapper(plugin)))
+    }
+}
+
+impl ReleaseEnrichmentHookSettings {
+    /// Validate plugin configuration and fill in defaults.
+    pub fn deserialize_config(cfg: toml::Value) -> Fallible<Box<dyn PluginSettings>> {
+        let settings: Self = cfg.try_into()?;
+
+        ensure!(!settings.urls.is_empty(), "no hook urls configured");
+        for url in &settings.urls {
+            url::Url::parse(url).context(format!("parsing hook url '{}'", url))?;
+        }
+        ensure!(settings.timeout_secs > 0, "timeout_secs must be positive");
+        ensure!(settings.concurrency > 0, "concurrency must be positive");
+        ensure!(!settings.key_prefix.is_empty(), "empty key_prefix");
+
+        Ok(Box::new(settings))
+    }
+}
+
+/// Expected response of an enrichment hook.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct HookResponse {
+    metadata: HashMap<String, String>,
+}
+
+/// Enriches release metadata through external services.
+#[derive(CustomDebug)]
+pub struct ReleaseEnrichmentHookPlugin {
+    settings: ReleaseEnrichmentHookSettings,
+
+    #[debug(skip)]
+    client: reqwest::Client,
+
+    /// Merged hook results, keyed by payload of the releases of the last graph.
+    #[debug(skip)]
+    enrichments: RwLock<HashMap<String, MapImpl<IStr, IStr>>>,
+
+    #[debug(skip)]
+    hook_requests: IntCounter,
+
+    #[debug(skip)]
+    hook_errors: IntCounter,
+}
+
+impl ReleaseEnrichmentHookPlugin {
+    /// Plugin name, for configuration.
+    pub const PLUGIN_NAME: &'static str = "release-enrichment-hook";
+
+    pub fn try_new(
+        settings: ReleaseEnrichmentHookSettings,
+        prometheus_registry: Option<&prometheus::Registry>,
+    ) -> Fallible<Self> {
+        let hook_requests = IntCounter::new(
+            "release_enrichment_hook_requests_total",
+            "Total number of requests sent to release enrichment hooks",
+        )?;
+        let hook_errors = IntCounter::new(
+            "release_enrichment_hook_errors_total",
+            "Total number of failed or invalid release enrichment hook responses",
+        )?;
+
+        if let Some(registry) = &prometheus_registry {
+            registry.register(Box::new(hook_requests.clone()))?;
+            registry.register(Box::new(hook_errors.clone()))?;
+        }
+
+        let client = commons::outbound::client_builder()
+            .timeout(Duration::from_secs(settings.timeout_secs))
+            .build()
+            .context("Building reqwest client")?;
+
+        Ok(Self {
+            settings,
+            client,
+            enrichments: Default::default(),
+            hook_requests,
+            hook_errors,
+        })
+    }
+
+    /// Query a single hook for a release and validate its response.
+    async fn query_hook(
+        &self,
+        url: &str,
+        release: &ConcreteRelease,
+    ) -> Fallible<HashMap<String, String>> {
+        self.hook_requests.inc();
+
+        let response = self
+            .client
+            .post(url)
+            .json(release)
+            .send()
+            .await
+            .context(format!("sending release {} to {}", release.version, url))?;
+
+        ensure!(
+            response.status().is_success(),
+            "hook {} returned status {}",
+            url,
+            response.status()
+        );
+
+        let body = response.bytes().await?;
+        let response: HookResponse = serde_json::from_slice(&body)
+            .context(format!("hook {} returned an invalid response", url))?;
+
+        if let Some(key) = response
+            .metadata
+            .keys()
+            .find(|key| !key.starts_with(&self.settings.key_prefix))
+        {
+            bail!(
+                "hook {} returned key '{}' outside of prefix '{}'",
+                url,
+                key,
+                self.settings.key_prefix
+            );
+        }
+
+        Ok(response.metadata)
+    }
+
+    /// Query all hooks for a release, merging the results in configuration order.
+    async fn enrich(&self, release: ConcreteRelease) -> Fallible<(String, MapImpl<IStr, IStr>)> {
+        let mut merged = MapImpl::new();
+
+        for url in &self.settings.urls {
+            let metadata = self.query_hook(url, &release).await.inspect_err(|_e| {
+                self.hook_errors.inc();
+            })?;
+            merged.extend(
+                metadata
+                    .into_iter()
+                    .map(|(key, value)| (key.into(), value.into())),
+            );
+        }
+
+        Ok((release.payload, merged))
+    }
+}
+
+#[async_trait]
+impl InternalPlugin for ReleaseEnrichmentHookPlugin {
+    const PLUGIN_NAME: &'static str = Self::PLUGIN_NAME;
+
+    async fn run_internal(&self, io: InternalIO) -> Fallible<InternalIO> {
+        let mut graph = io.graph;
+
+        let new_releases: Vec<ConcreteRelease> = {
+            let enrichments = self.enrichments.read().await;
+            graph
+                .iter_releases()
+                .filter_map(|release| match release {
+                    Release::Concrete(release) if !enrichments.contains_key(&release.payload) => {
+                        Some(release.clone())
+                    }
+                    _ => None,
+                })
+                .collect()
+        };
+
+        if !new_releases.is_empty() {
+            debug!(
+                "sending {} new releases to enrichment hooks",
+                new_releases.len()
+            );
+        }
+
+        let results: Vec<_> = stream::iter(new_releases)
+            .map(|release| self.enrich(release))
+            .buffer_unordered(self.settings.concurrency)
+            .collect()
+            .await;
+
+        {
+            let payloads: HashSet<&str> = graph
+                .iter_releases()
+                .filter_map(|release| match release {
+                    Release::Concrete(release) => Some(release.payload.as_str()),
+                    Release::Abstract(_) => None,
+                })
+                .collect();
+            let mut enrichments = self.enrichments.write().await;
+            enrichments.retain(|payload, _| payloads.contains(payload.as_str()));
+            for result in results {
+                match result {
+                    Ok((payload, metadata)) => {
+                        enrichments.insert(payload, metadata);
+                    }
+                    Err(e) if self.settings.fail_on_error => return Err(e),
+                    Err(e) => warn!("release enrichment failed: {:#}", e),
+                }
+            }
+        }
+
+        let enrichments = self.enrichments.read().await;
+        graph.iter_releases_mut(|release| {
+            if let Release::Concrete(release) = release {
+                if let Some(metadata) = enrichments.get(&release.payload) {
+                    release
+                        .metadata
+                        .extend(metadata.iter().map(|(k, v)| (k.clone(), v.clone())));
+                }
+            }
+            Ok(())
+        })?;
+
+        Ok(InternalIO {
+            graph,
+            parameters: io.parameters,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cincinnati::testing::generate_custom_graph;
+    use commons::testing::init_runtime;
+
+    fn settings(path: &str) -> ReleaseEnrichmentHookSettings {
+        ReleaseEnrichmentHookSettings {
+            urls: vec![format!("{}{}", mockito::server_url(), path)],
+            key_prefix: "test.".to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn input_graph() -> cincinnati::Graph {
+        generate_custom_graph(
+            "image",
+            (0..2).map(|i| (i, Default::default())).collect(),
+            Some(vec![(0, 1)]),
+        )
+    }
+
+    #[test]
+    fn merges_hook_metadata_once() -> Fallible<()> {
+        let runtime = init_runtime()?;
+
+        let _m = mockito::mock("POST", "/enrich-ok")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(r#"{"metadata": {"test.key": "value"}}"#)
+            .expect(2)
+            .create();
+
+        let plugin = ReleaseEnrichmentHookPlugin::try_new(settings("/enrich-ok"), None)?;
+
+        for _ in 0..2 {
+            let graph = runtime
+                .block_on(plugin.run_internal(InternalIO {
+                    graph: input_graph(),
+                    parameters: Default::default(),
+                }))?
+                .graph;
+
+            for release in graph.iter_releases() {
+                match release {
+                    Release::Concrete(release) => {
+                        assert_eq!(release.metadata.get("test.key"), Some(&"value".into()))
+                    }
+                    _ => bail!("expected concrete release"),
+                }
+            }
+        }
+
+        _m.assert();
+        assert_eq!(plugin.hook_requests.get(), 2);
+        assert_eq!(plugin.hook_errors.get(), 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn forgets_removed_releases() -> Fallible<()> {
+        let runtime = init_runtime()?;
+
+        let _m = mockito::mock("POST", "/enrich-removed")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(r#"{"metadata": {"test.key": "value"}}"#)
+            .create();
+
+        let plugin = ReleaseEnrichmentHookPlugin::try_new(settings("/enrich-removed"), None)?;
+        runtime.block_on(plugin.run_internal(InternalIO {
+            graph: input_graph(),
+            parameters: Default::default(),
+        }))?;
+        assert_eq!(runtime.block_on(plugin.enrichments.read()).len(), 2);
+
+        let smaller = generate_custom_graph("image", vec![(0, Default::default())], None);
+        runtime.block_on(plugin.run_internal(InternalIO {
+            graph: smaller,
+            parameters: Default::default(),
+        }))?;
+        assert_eq!(runtime.block_on(plugin.enrichments.read()).len(), 1);
+        assert_eq!(plugin.hook_requests.get(), 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_invalid_hook_response() -> Fallible<()> {
+        let runtime = init_runtime()?;
+
+        let _m = mockito::mock("POST", "/enrich-bad")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(r#"{"metadata": {"other.key": "value"}}"#)
+            .create();
+
+        let plugin = ReleaseEnrichmentHookPlugin::try_new(settings("/enrich-bad"), None)?;
+        let graph = runtime
+            .block_on(plugin.run_internal(InternalIO {
+                graph: input_graph(),
+                parameters: Default::default(),
+            }))?
+            .graph;
+
+        assert_eq!(graph, input_graph());
+        assert_eq!(plugin.hook_errors.get(), 2);
+
+        let plugin = ReleaseEnrichmentHookPlugin::try_new(
+            ReleaseEnrichmentHookSettings {
+                fail_on_error: true,
+                ..settings("/enrich-bad")
+            },
+            None,
+        )?;
+        assert!(runtime
+            .block_on(plugin.run_internal(InternalIO {
+                graph: input_graph(),
+                parameters: Default::default(),
+            }))
+            .is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn deserialize_config_validation() {
+        let cfg = |s: &str| toml::from_str::<toml::Value>(s).unwrap();
+
+        assert!(ReleaseEnrichmentHookSettings::deserialize_config(cfg(
+            r#"urls = ["http://localhost:1234/enrich"]"#
+        ))
+        .is_ok());
+        assert!(ReleaseEnrichmentHookSettings::deserialize_config(cfg("urls = []")).is_err());
+        assert!(
+            ReleaseEnrichmentHookSettings::deserialize_config(cfg(r#"urls = ["not a url"]"#))
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,326 @@
+//! This plugin adds release-notes URLs, errata identifiers and release dates to the release metadata.
+//!
+//! All are looked up in a JSON or YAML mapping file keyed by version, which is
+//! read again on every run. URLs and errata identifiers of versions missing
+//! from the mapping are expanded from templates instead. The `{version}`,
+//! `{major}`, `{minor}` and `{patch}` placeholders of a template are replaced
+//! with the parts of the release version, without build metadata.
+
+use crate as cincinnati;
+
+use self::cincinnati::plugins::prelude::*;
+use self::cincinnati::plugins::prelude_plugin_impl::*;
+use self::cincinnati::{IStr, Release};
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+/// Default prefix of the metadata keys.
+pub static DEFAULT_KEY_PREFIX: &str = "io.openshift.upgrades.graph";
+
+/// Release notes of a single version, as listed in the mapping file.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ReleaseNotes {
+    /// URL of the human-readable release notes.
+    #[serde(default)]
+    pub url: Option<String>,
+
+    /// Identifier of the errata, e.g. `RHSA-2022:1234`.
+    #[serde(default)]
+    pub errata: Option<String>,
+
+    /// Date the release was published at, e.g. `2022-03-01`.
+    #[serde(default)]
+    pub date: Option<String>,
+}
+
+/// Plugin settings.
+#[derive(Clone, Debug, Deserialize, SmartDefault)]
+#[serde(default)]
+pub struct ReleaseNotesEnrichSettings {
+    /// Prefix of the metadata keys.
+    #[default(DEFAULT_KEY_PREFIX.to_string())]
+    pub key_prefix: String,
+
+    /// JSON or YAML file mapping versions to their release notes.
+    pub mapping_path: Option<PathBuf>,
+
+    /// Template of the release-notes URL of versions missing from the mapping.
+    pub url_template: Option<String>,
+
+    /// Template of the errata identifier of versions missing from the mapping.
+    pub errata_template: Option<String>,
+
+    /// Whether to replace release notes already present in the metadata.
+    pub overwrite: bool,
+}
+
+impl PluginSettings for ReleaseNotesEnrichSettings {
+    fn build_plugin(&self, _: Option<&prometheus::Registry>) -> Fallible<BoxedPlugin> {
+        let plugin = ReleaseNotesEnrichPlugin {
+            settings: self.clone(),
+        };
+        Ok(new_plugin!(InternalPluginWrapper(plugin)))
+    }
+
+    fn key_prefix(&self) -> Option<&str> {
+        Some(&self.key_prefix)
+    }
+}
+
+impl ReleaseNotesEnrichSettings {
+    /// Validate plugin configuration and fill in defaults.
+    pub fn deserialize_config(cfg: toml::Value) -> Fallible<Box<dyn PluginSettings>> {
+        let settings: Self = cfg.try_into()?;
+
+        ensure!(!settings.key_prefix.is_empty(), "empty key_prefix");
+        ensure!(
+            settings.mapping_path.is_some()
+                || settings.url_template.is_some()
+                || settings.errata_template.is_some(),
+            "one of mapping_path, url_template or errata_template must be configured"
+        );
+        ensure!(
+            settings.mapping_path != Some(PathBuf::new()),
+            "empty mapping_path"
+        );
+        for template in settings
+            .url_template
+            .iter()
+            .chain(settings.errata_template.iter())
+        {
+            ensure!(!template.is_empty(), "empty template");
+        }
+
+        Ok(Box::new(settings))
+    }
+}
+
+/// Adds release notes to the release metadata.
+#[derive(Debug)]
+pub struct ReleaseNotesEnrichPlugin {
+    settings: ReleaseNotesEnrichSettings,
+}
+
+impl ReleaseNotesEnrichPlugin {
+    /// Plugin name, for configuration.
+    pub const PLUGIN_NAME: &'static str = "release-notes-enrich";
+
+    /// Read the mapping file, if configured.
+    async fn read_mapping(&self) -> Fallible<HashMap<String, ReleaseNotes>> {
+        let path = match &self.settings.mapping_path {
+            Some(path) => path,
+            None => return Ok(HashMap::new()),
+        };
+
+        let content = tokio::fs::read(path)
+            .await
+            .context(format!("reading release notes mapping from {:?}", path))?;
+        // YAML is a superset of JSON, so both are parsed the same way.
+        let mapping = serde_yaml::from_slice(&content)
+            .context(format!("parsing release notes mapping from {:?}", path))?;
+
+        Ok(mapping)
+    }
+}
+
+/// Expand the version placeholders of a template.
+fn expand(template: &str, version: &semver::Version) -> String {
+    template
+        .replace("{version}", &version.to_string())
+        .replace("{major}", &version.major.to_string())
+        .replace("{minor}", &version.minor.to_string())
+        .replace("{patch}", &version.patch.to_string())
+}
+
+#[async_trait]
+impl InternalPlugin for ReleaseNotesEnrichPlugin {
+    const PLUGIN_NAME: &'static str = Self::PLUGIN_NAME;
+
+    async fn run_internal(&self, io: InternalIO) -> Fallible<InternalIO> {
+        let mut graph = io.graph;
+        let mapping = self.read_mapping().await?;
+        let url_key: IStr = format!("{}.release.notes_url", self.settings.key_prefix).into();
+        let errata_key: IStr = format!("{}.release.errata", self.settings.key_prefix).into();
+        let date_key: IStr = format!("{}.release.date", self.settings.key_prefix).into();
+
+        let mut enriched = 0;
+        graph.iter_releases_mut(|release| {
+            if let Release::Concrete(release) = release {
+                let mut version = match cincinnati::semver_cache::parse_version(&release.version) {
+                    Ok(version) => version,
+                    Err(e) => {
+                        warn!("Parsing {} as SemVer: {}", release.version, e);
+                        return Ok(());
+                    }
+                };
+                version.build.clear();
+
+                let mapped = mapping
+                    .get(release.version.as_str())
+                    .or_else(|| mapping.get(&version.to_string()))
+                    .cloned()
+                    .unwrap_or_default();
+                let url = mapped.url.or_else(|| {
+                    self.settings
+                        .url_template
+                        .as_ref()
+                        .map(|template| expand(template, &version))
+                });
+                let errata = mapped.errata.or_else(|| {
+                    self.settings
+                        .errata_template
+                        .as_ref()
+                        .map(|template| expand(template, &version))
+                });
+
+                let mut changed = false;
+                for (key, value) in [
+                    (&url_key, url),
+                    (&errata_key, errata),
+                    (&date_key, mapped.date),
+                ] {
+                    if let Some(value) = value {
+                        if self.settings.overwrite || !release.metadata.contains_key(key) {
+                            release.metadata.insert(key.clone(), value.into());
+                            changed = true;
+                        }
+                    }
+                }
+                if changed {
+                    enriched += 1;
+                }
+            }
+            Ok(())
+        })?;
+        debug!("Added release notes to {} releases.", enriched);
+
+        Ok(InternalIO {
+            graph,
+            parameters: io.parameters,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use commons::testing::init_runtime;
+    use std::io::Write;
+
+    fn release(version: &str, metadata: &[(&str, &str)]) -> Release {
+        Release::Concrete(cincinnati::ConcreteRelease {
+            version: version.into(),
+            payload: format!("quay.io/ocp/release:{}", version),
+            metadata: metadata
+                .iter()
+                .map(|(key, value)| ((*key).into(), (*value).into()))
+                .collect(),
+        })
+    }
+
+    #[test]
+    fn enrich_release_notes() -> Fallible<()> {
+        let runtime = init_runtime()?;
+
+        let mut mapping = tempfile::NamedTempFile::new()?;
+        mapping.write_all(
+            br#"
+                4.10.1:
+                  url: https://example.com/errata/1
+                  errata: RHBA-2022:0001
+                  date: "2022-03-01"
+                4.10.2+arm64:
+                  errata: RHBA-2022:0002
+            "#,
+        )?;
+
+        let mut graph = cincinnati::Graph::default();
+        for release in [
+            release("4.10.1+amd64", &[]),
+            release("4.10.2+arm64", &[]),
+            release(
+                "4.10.3+amd64",
+                &[("io.openshift.upgrades.graph.release.errata", "RHSA-2022:9")],
+            ),
+        ] {
+            graph.add_release(release)?;
+        }
+
+        let settings = ReleaseNotesEnrichSettings::deserialize_config(toml::from_str(&format!(
+            r#"
+                name = "release-notes-enrich"
+                mapping_path = {:?}
+                url_template = "https://example.com/{{major}}.{{minor}}/notes#{{version}}"
+                errata_template = "unknown-{{patch}}"
+            "#,
+            mapping.path()
+        ))?)?;
+        let plugin = settings.build_plugin(None)?;
+
+        let io = runtime.block_on(plugin.run(cincinnati::plugins::PluginIO::InternalIO(
+            InternalIO {
+                graph,
+                parameters: Default::default(),
+            },
+        )))?;
+        let graph = cincinnati::testing::internal_io(io)?.graph;
+
+        let notes = |version: &str| -> (Option<String>, Option<String>) {
+            let release = graph
+                .find_by_version(version)
+                .and_then(|id| graph.find_by_releaseid(&id).ok());
+            match release {
+                Some(Release::Concrete(release)) => (
+                    release
+                        .metadata
+                        .get("io.openshift.upgrades.graph.release.notes_url")
+                        .map(IStr::to_string),
+                    release
+                        .metadata
+                        .get("io.openshift.upgrades.graph.release.errata")
+                        .map(IStr::to_string),
+                ),
+                _ => (None, None),
+            }
+        };
+        assert_eq!(
+            notes("4.10.1+amd64"),
+            (
+                Some("https://example.com/errata/1".to_string()),
+                Some("RHBA-2022:0001".to_string())
+            )
+        );
+        assert_eq!(
+            notes("4.10.2+arm64"),
+            (
+                Some("https://example.com/4.10/notes#4.10.2".to_string()),
+                Some("RHBA-2022:0002".to_string())
+            )
+        );
+        let date = |version: &str| match graph
+            .find_by_version(version)
+            .and_then(|id| graph.find_by_releaseid(&id).ok())
+        {
+            Some(Release::Concrete(release)) => release
+                .metadata
+                .get("io.openshift.upgrades.graph.release.date")
+                .cloned(),
+            _ => None,
+        };
+        assert_eq!(date("4.10.1+amd64").as_deref(), Some("2022-03-01"));
+        assert_eq!(date("4.10.2+arm64"), None);
+
+        // Existing metadata is kept.
+        assert_eq!(
+            notes("4.10.3+amd64"),
+            (
+                Some("https://example.com/4.10/notes#4.10.3".to_string()),
+                Some("RHSA-2022:9".to_string())
+            )
+        );
+
+        Ok(())
+    }
+}
@@ -3,7 +3,13 @@
 pub mod plugin;
 pub mod registry;
 
+pub use registry::artifacts::MetadataSource;
+pub use registry::retry::{RetryClass, RetryPolicy};
+pub use registry::throttle::DEFAULT_THROTTLE_MIN_RATE;
+
 pub use plugin::{
-    ReleaseScrapeDockerv2Plugin, ReleaseScrapeDockerv2Settings, DEFAULT_FETCH_CONCURRENCY,
-    DEFAULT_MANIFESTREF_KEY, DEFAULT_SCRAPE_REGISTRY, DEFAULT_SCRAPE_REPOSITORY,
+    MetadataLimitAction, ReleaseScrapeDockerv2Plugin, ReleaseScrapeDockerv2Settings, ScrapeSource,
+    DEFAULT_CACHE_MAX_ENTRIES, DEFAULT_DISCOVERY_MAX_REPOSITORIES, DEFAULT_FETCH_CONCURRENCY,
+    DEFAULT_MANIFESTREF_KEY, DEFAULT_METADATA_MAX_KEYS, DEFAULT_METADATA_MAX_KEY_LENGTH,
+    DEFAULT_METADATA_MAX_VALUE_SIZE, DEFAULT_SCRAPE_REGISTRY, DEFAULT_SCRAPE_REPOSITORY,
 };
@@ -4,8 +4,15 @@ use crate as cincinnati;
 
 use self::cincinnati::plugins::prelude::*;
 use self::cincinnati::plugins::prelude_plugin_impl::*;
+use self::cincinnati::MapImpl;
 
+use futures::{StreamExt, TryStreamExt};
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
 use std::convert::TryInto;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 /// Default registry to scrape.
 pub static DEFAULT_SCRAPE_REGISTRY: &str = "quay.io";
@@ -16,9 +23,45 @@ pub static DEFAULT_SCRAPE_REPOSITORY: &str = "openshift-release-dev/ocp-release"
 /// Default key for storing and retrieving the manifest reference from the metadata.
 pub static DEFAULT_MANIFESTREF_KEY: &str = "io.openshift.upgrades.graph.release.manifestref";
 
+/// Parameter key under which the repository which served the main repository is passed on.
+pub static SERVING_SOURCE_PARAM_KEY: &str = "io.openshift.upgrades.graph.scrape.serving_source";
+
 /// Default fetch concurrency.
 pub static DEFAULT_FETCH_CONCURRENCY: usize = 16;
 
+/// Default maximum number of entries in the persistent metadata cache.
+pub static DEFAULT_CACHE_MAX_ENTRIES: usize = 10_000;
+
+/// Default maximum number of metadata entries per release.
+pub static DEFAULT_METADATA_MAX_KEYS: usize = 64;
+
+/// Default maximum length of a metadata key, in bytes.
+pub static DEFAULT_METADATA_MAX_KEY_LENGTH: usize = 256;
+
+/// Default maximum size of a metadata value, in bytes.
+pub static DEFAULT_METADATA_MAX_VALUE_SIZE: usize = 16 * 1024;
+
+/// Default maximum number of repositories scraped in discovery mode.
+pub static DEFAULT_DISCOVERY_MAX_REPOSITORIES: usize = 100;
+
+/// Number of discovered repositories fetched concurrently, each one with up to
+/// `fetch_concurrency` requests in flight.
+static DISCOVERY_CONCURRENCY: usize = 4;
+
+/// Default maximum number of tags scraped per repository.
+pub static DEFAULT_MAX_TAGS: usize = 50_000;
+
+/// Handling of releases whose metadata exceeds the limits.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, SmartDefault)]
+#[serde(rename_all = "lowercase")]
+pub enum MetadataLimitAction {
+    /// Drop the offending entries and truncate oversized values.
+    #[default]
+    Truncate,
+    /// Leave the release out of the graph.
+    Reject,
+}
+
 /// Plugin settings.
 #[derive(Clone, Debug, Deserialize, SmartDefault)]
 #[serde(default)]
@@ -33,6 +76,7 @@ pub struct ReleaseScrapeDockerv2Settings {
     #[default(DEFAULT_MANIFESTREF_KEY.to_string())]
     pub manifestref_key: String,
 
+    /// Maximum number of tags whose manifests and metadata are fetched at once.
     #[default(DEFAULT_FETCH_CONCURRENCY)]
     pub fetch_concurrency: usize,
 
@@ -44,10 +88,157 @@ pub struct ReleaseScrapeDockerv2Settings {
     #[default(Option::None)]
     pub password: Option<String>,
 
-    /// File containing the credentials for authenticating with the registry.
+    /// File containing the credentials for authenticating with the registry,
+    /// in the docker config format. Read again before every scrape.
     /// Takes precedence over username and password
     #[default(Option::None)]
     pub credentials_path: Option<PathBuf>,
+
+    /// Authenticate with AWS ECR tokens, requested with the IAM credentials of
+    /// the environment and refreshed before they expire.
+    /// Can't be combined with credentials_path.
+    pub ecr_auth: bool,
+
+    /// File containing PEM-encoded CA certificates to trust for the registry,
+    /// in addition to the system ones.
+    #[default(Option::None)]
+    pub ca_bundle_path: Option<PathBuf>,
+
+    /// File containing the PEM-encoded client certificate presented to the registry.
+    #[default(Option::None)]
+    pub client_cert_path: Option<PathBuf>,
+
+    /// File containing the PKCS#8 PEM-encoded key of the client certificate.
+    #[default(Option::None)]
+    pub client_key_path: Option<PathBuf>,
+
+    /// Directory for the persistent release metadata cache.
+    /// If unset, metadata is only cached in memory.
+    #[default(Option::None)]
+    pub cache_path: Option<PathBuf>,
+
+    /// Maximum number of entries kept in the persistent metadata cache.
+    #[default(DEFAULT_CACHE_MAX_ENTRIES)]
+    pub cache_max_entries: usize,
+
+    /// Further repositories to scrape, e.g. mirrors.
+    /// Their releases are merged into the graph, de-duplicated by payload digest.
+    pub additional_sources: Vec<ScrapeSource>,
+
+    /// Mirrors of the main repository, tried in order if it can't be scraped.
+    /// Releases served by a mirror are attributed to the main repository.
+    pub mirrors: Vec<ScrapeSource>,
+
+    /// Timeout for scraping the main repository or one of its mirrors, in seconds.
+    /// Only applies if mirrors are configured.
+    #[default(Option::None)]
+    pub mirror_timeout_secs: Option<u64>,
+
+    /// Maximum number of metadata entries per release.
+    #[default(DEFAULT_METADATA_MAX_KEYS)]
+    pub metadata_max_keys: usize,
+
+    /// Maximum length of a metadata key, in bytes.
+    #[default(DEFAULT_METADATA_MAX_KEY_LENGTH)]
+    pub metadata_max_key_length: usize,
+
+    /// Maximum size of a metadata value, in bytes.
+    #[default(DEFAULT_METADATA_MAX_VALUE_SIZE)]
+    pub metadata_max_value_size: usize,
+
+    /// Handling of releases whose metadata exceeds the limits.
+    pub metadata_limit_action: MetadataLimitAction,
+
+    /// Where to find the release metadata, falling back to the payload layers.
+    pub metadata_source: registry::artifacts::MetadataSource,
+
+    /// Sources of the release metadata, tried in order. Replaces metadata_source
+    /// if not empty, the payload layers then only being used if listed.
+    pub metadata_sources: Vec<registry::artifacts::MetadataSource>,
+
+    /// Artifact type of the release metadata artifacts, for the referrers source.
+    #[default(registry::artifacts::DEFAULT_ARTIFACT_TYPE.to_string())]
+    pub metadata_artifact_type: String,
+
+    /// Manifest annotation carrying the release metadata, for the annotations source.
+    #[default(registry::artifacts::DEFAULT_ANNOTATION.to_string())]
+    pub metadata_annotation: String,
+
+    /// Label carrying the release metadata, for the label sources.
+    #[default(registry::artifacts::DEFAULT_LABEL.to_string())]
+    pub metadata_label: String,
+
+    /// File containing the Quay API token, for the Quay labels source.
+    #[default(Option::None)]
+    pub metadata_quay_api_token_path: Option<PathBuf>,
+
+    /// Regular expression matching the repositories to scrape, listed through the
+    /// registry catalog API on every scrape. Replaces `repository` if set.
+    #[default(Option::None)]
+    pub repository_pattern: Option<String>,
+
+    /// Maximum number of repositories scraped in discovery mode.
+    #[default(DEFAULT_DISCOVERY_MAX_REPOSITORIES)]
+    pub discovery_max_repositories: usize,
+
+    /// Maximum number of tags matching the filters scraped per repository.
+    #[default(DEFAULT_MAX_TAGS)]
+    pub max_tags: usize,
+
+    /// Regular expression matching the whole tags to scrape.
+    #[default(Option::None)]
+    pub tag_pattern: Option<String>,
+
+    /// Range of the versions at the start of the tags to scrape, e.g. "4.16.*".
+    #[default(Option::None)]
+    pub tag_version_range: Option<String>,
+
+    /// Architectures of the releases to scrape, "multi" standing for all image
+    /// indexes. Image indexes with an image of a listed architecture are
+    /// scraped too. All of them if empty.
+    pub architectures: Vec<String>,
+
+    /// Retry policy of the failed registry requests.
+    pub retry: registry::retry::RetryPolicy,
+
+    /// Whether to slow down the requests once the registry throttles them.
+    pub adaptive_throttling: bool,
+
+    /// Minimum rate of the throttled requests, per second.
+    #[default(registry::throttle::DEFAULT_THROTTLE_MIN_RATE)]
+    pub throttle_min_rate: f64,
+}
+
+/// A registry repository to scrape in addition to the main one.
+#[derive(Clone, Debug, Deserialize, Serialize, SmartDefault)]
+#[serde(default)]
+pub struct ScrapeSource {
+    pub registry: String,
+
+    pub repository: String,
+
+    /// File containing the credentials for authenticating with the registry.
+    #[default(Option::None)]
+    pub credentials_path: Option<PathBuf>,
+
+    /// Authenticate with AWS ECR tokens.
+    pub ecr_auth: bool,
+
+    /// File containing PEM-encoded CA certificates to trust for the registry.
+    #[default(Option::None)]
+    pub ca_bundle_path: Option<PathBuf>,
+
+    /// File containing the PEM-encoded client certificate presented to the registry.
+    #[default(Option::None)]
+    pub client_cert_path: Option<PathBuf>,
+
+    /// File containing the PKCS#8 PEM-encoded key of the client certificate.
+    #[default(Option::None)]
+    pub client_key_path: Option<PathBuf>,
+
+    /// Sources of the release metadata in this repository, replacing the ones
+    /// of the main repository if not empty.
+    pub metadata_sources: Vec<registry::artifacts::MetadataSource>,
 }
 
 impl PluginSettings for ReleaseScrapeDockerv2Settings {
@@ -74,20 +265,417 @@ impl ReleaseScrapeDockerv2Settings {
                 settings.credentials_path = None;
             }
         }
+        if settings.ca_bundle_path == Some(PathBuf::from("")) {
+            settings.ca_bundle_path = None;
+        }
+        ensure!(
+            settings.client_cert_path.is_some() == settings.client_key_path.is_some(),
+            "client_cert_path and client_key_path must be set together"
+        );
+        if let Some(cache_path) = &settings.cache_path {
+            if cache_path == &std::path::PathBuf::from("") {
+                warn!("Settings contain an empty cache path, setting to None");
+                settings.cache_path = None;
+            }
+        }
+        ensure!(
+            settings.fetch_concurrency > 0,
+            "fetch_concurrency must be greater than 0"
+        );
+        ensure!(
+            settings.cache_max_entries > 0,
+            "cache_max_entries must be greater than 0"
+        );
+        ensure!(
+            settings.metadata_max_keys > 0,
+            "metadata_max_keys must be greater than 0"
+        );
+        ensure!(
+            settings.metadata_max_key_length >= settings.manifestref_key.len(),
+            "metadata_max_key_length must fit the manifestref_key"
+        );
+        ensure!(
+            settings.metadata_max_value_size > 0,
+            "metadata_max_value_size must be greater than 0"
+        );
+        ensure!(
+            !settings.metadata_artifact_type.is_empty(),
+            "empty metadata_artifact_type"
+        );
+        ensure!(
+            !settings.metadata_annotation.is_empty(),
+            "empty metadata_annotation"
+        );
+        ensure!(!settings.metadata_label.is_empty(), "empty metadata_label");
+        if settings.metadata_quay_api_token_path == Some(PathBuf::from("")) {
+            settings.metadata_quay_api_token_path = None;
+        }
+        registry::artifacts::validate_sources(&settings.metadata_sources)
+            .context("invalid metadata_sources")?;
+        for source in &mut settings.additional_sources {
+            ensure!(!source.repository.is_empty(), "empty additional repository");
+            ensure!(!source.registry.is_empty(), "empty additional registry");
+            registry::artifacts::validate_sources(&source.metadata_sources)
+                .context("invalid metadata_sources")?;
+            if source.credentials_path == Some(PathBuf::from("")) {
+                source.credentials_path = None;
+            }
+            if source.ca_bundle_path == Some(PathBuf::from("")) {
+                source.ca_bundle_path = None;
+            }
+            ensure!(
+                source.client_cert_path.is_some() == source.client_key_path.is_some(),
+                "client_cert_path and client_key_path must be set together"
+            );
+        }
+        for mirror in &mut settings.mirrors {
+            ensure!(!mirror.repository.is_empty(), "empty mirror repository");
+            ensure!(!mirror.registry.is_empty(), "empty mirror registry");
+            registry::artifacts::validate_sources(&mirror.metadata_sources)
+                .context("invalid metadata_sources")?;
+            if mirror.credentials_path == Some(PathBuf::from("")) {
+                mirror.credentials_path = None;
+            }
+            if mirror.ca_bundle_path == Some(PathBuf::from("")) {
+                mirror.ca_bundle_path = None;
+            }
+            ensure!(
+                mirror.client_cert_path.is_some() == mirror.client_key_path.is_some(),
+                "client_cert_path and client_key_path must be set together"
+            );
+        }
+        settings
+            .retry
+            .validate()
+            .context("invalid retry settings")?;
+        ensure!(
+            settings.throttle_min_rate.is_finite() && settings.throttle_min_rate > 0.0,
+            "throttle_min_rate must be greater than 0"
+        );
+        ensure!(
+            settings.mirror_timeout_secs != Some(0),
+            "mirror_timeout_secs must be greater than 0"
+        );
+        ensure!(settings.max_tags > 0, "max_tags must be greater than 0");
+        settings.scrape_filter()?;
+        if let Some(pattern) = &settings.repository_pattern {
+            discovery_regex(pattern)?;
+            ensure!(
+                settings.mirrors.is_empty(),
+                "mirrors can't be combined with repository_pattern"
+            );
+            ensure!(
+                settings.discovery_max_repositories > 0,
+                "discovery_max_repositories must be greater than 0"
+            );
+        }
 
         Ok(Box::new(settings))
     }
+
+    /// The tags and architectures to scrape.
+    fn scrape_filter(&self) -> Fallible<registry::filter::ScrapeFilter> {
+        registry::filter::ScrapeFilter::try_new(
+            self.tag_pattern.as_deref(),
+            self.tag_version_range.as_deref(),
+            &self.architectures,
+        )
+    }
+
+    /// The lookup of the release metadata of a repository, in `sources` if not
+    /// empty, or else in the sources of the main repository.
+    fn metadata_lookup(
+        &self,
+        sources: &[registry::artifacts::MetadataSource],
+    ) -> Fallible<registry::artifacts::MetadataLookup> {
+        use registry::artifacts::MetadataSource;
+
+        let sources = if !sources.is_empty() {
+            sources.to_vec()
+        } else if !self.metadata_sources.is_empty() {
+            self.metadata_sources.clone()
+        } else {
+            let mut sources = vec![self.metadata_source, MetadataSource::Layers];
+            sources.dedup();
+            sources
+        };
+        let quay_api_token = match &self.metadata_quay_api_token_path {
+            Some(path) if sources.contains(&MetadataSource::QuayLabels) => {
+                Some(quay::read_credentials(path).context("reading metadata_quay_api_token_path")?)
+            }
+            _ => None,
+        };
+
+        Ok(registry::artifacts::MetadataLookup {
+            sources,
+            artifact_type: self.metadata_artifact_type.clone(),
+            annotation: self.metadata_annotation.clone(),
+            label: self.metadata_label.clone(),
+            quay_api_token,
+        })
+    }
+}
+
+/// Compile a discovery pattern, which must match whole repository names.
+fn discovery_regex(pattern: &str) -> Fallible<regex::Regex> {
+    regex::Regex::new(&format!("^(?:{})$", pattern))
+        .context(format!("invalid repository_pattern '{}'", pattern))
+}
+
+/// A resolved repository to scrape.
+#[derive(CustomDebug)]
+struct Source {
+    registry: registry::Registry,
+    repository: String,
+    credentials: Arc<registry::credentials::Credentials>,
+    /// TLS settings of the clients of the registry.
+    #[debug(skip)]
+    tls: registry::ClientTls,
+    /// Tag digests observed by the last scrape of this repository.
+    tag_digests: registry::cache::TagDigests,
+    /// Lookup of the release metadata of this repository.
+    #[debug(skip)]
+    metadata_lookup: registry::artifacts::MetadataLookup,
+}
+
+impl Source {
+    fn try_new(
+        registry: &str,
+        repository: &str,
+        credentials_path: Option<&PathBuf>,
+        ecr_auth: bool,
+        tls: registry::ClientTls,
+        username: Option<String>,
+        password: Option<String>,
+    ) -> Fallible<Self> {
+        let registry = registry::Registry::try_from_str(registry)
+            .context(format!("Parsing {} as Registry", registry))?;
+
+        ensure!(
+            !(ecr_auth && credentials_path.is_some()),
+            "ecr_auth can't be combined with credentials_path for {}",
+            registry.host_port_string()
+        );
+        let credentials = match credentials_path {
+            _ if ecr_auth => registry::credentials::Credentials::Ecr(Box::new(
+                registry::ecr::EcrAuth::try_new(&registry.host_port_string())?,
+            )),
+            Some(credentials_path) => registry::credentials::Credentials::File(
+                registry::credentials::CredentialsFile::new(
+                    credentials_path,
+                    &registry.host_port_string(),
+                ),
+            ),
+            None => registry::credentials::Credentials::Static((username, password)),
+        };
+
+        if tls.identity.is_some() {
+            warn!(
+                "The client certificate for {} is only presented by the metadata artifact requests, the registry client can't present one",
+                registry.host_port_string()
+            );
+        }
+
+        Ok(Self {
+            registry,
+            repository: repository.to_string(),
+            credentials: Arc::new(credentials),
+            tls,
+            tag_digests: registry::cache::new_tag_digests(),
+            metadata_lookup: Default::default(),
+        })
+    }
+
+    fn name(&self) -> String {
+        format!("{}/{}", self.registry.host_port_string(), self.repository)
+    }
+
+    /// Returns another repository of the same registry, with the same credentials.
+    fn sibling(&self, repository: &str, tag_digests: registry::cache::TagDigests) -> Self {
+        Self {
+            registry: self.registry.clone(),
+            repository: repository.to_string(),
+            credentials: self.credentials.clone(),
+            tls: self.tls.clone(),
+            tag_digests,
+            metadata_lookup: self.metadata_lookup.clone(),
+        }
+    }
+
+    fn from_settings(
+        source: &ScrapeSource,
+        settings: &ReleaseScrapeDockerv2Settings,
+    ) -> Fallible<Self> {
+        let mut scrape_source = Self::try_new(
+            &source.registry,
+            &source.repository,
+            source.credentials_path.as_ref(),
+            source.ecr_auth,
+            registry::ClientTls::try_new(
+                source.ca_bundle_path.as_deref(),
+                source.client_cert_path.as_deref(),
+                source.client_key_path.as_deref(),
+            )?,
+            None,
+            None,
+        )?;
+        scrape_source.metadata_lookup = settings.metadata_lookup(&source.metadata_sources)?;
+        Ok(scrape_source)
+    }
+}
+
+/// Attribute releases served by a mirror to the repository it mirrors.
+///
+/// Mirrors carry the same payload digests, so only the repository part of
+/// the source changes.
+fn attribute_to(
+    releases: Vec<cincinnati::plugins::internal::graph_builder::release::Release>,
+    repository: &str,
+) -> Vec<cincinnati::plugins::internal::graph_builder::release::Release> {
+    releases
+        .into_iter()
+        .map(|mut release| {
+            if let Some((_, digest)) = release.source.rsplit_once('@') {
+                release.source = format!("{}@{}", repository, digest);
+            }
+            release
+        })
+        .collect()
+}
+
+/// Merge the releases of multiple sources, keeping only the first release for
+/// each payload digest.
+fn merge_releases(
+    sources: Vec<Vec<cincinnati::plugins::internal::graph_builder::release::Release>>,
+) -> Vec<cincinnati::plugins::internal::graph_builder::release::Release> {
+    let mut digests = HashSet::new();
+
+    sources
+        .into_iter()
+        .flatten()
+        .filter(|release| {
+            let digest = release
+                .source
+                .rsplit_once('@')
+                .map(|(_, digest)| digest)
+                .unwrap_or(&release.source)
+                .to_string();
+            if digests.insert(digest) {
+                true
+            } else {
+                debug!("skipping duplicate release {}", release.source);
+                false
+            }
+        })
+        .collect()
+}
+
+/// Outcome of enforcing the metadata limits on a release.
+#[derive(Debug, PartialEq)]
+enum LimitOutcome {
+    Unchanged,
+    Truncated,
+    Rejected,
+}
+
+/// Enforce the metadata limits of `settings` on `metadata`.
+///
+/// Entries with empty or oversized keys, or keys containing control
+/// characters, are dropped. Values are truncated to the maximum size. Excess
+/// entries are dropped by reverse key order, except for the manifest reference.
+/// Nothing is changed if the release is rejected.
+fn enforce_metadata_limits(
+    metadata: &mut MapImpl<String, String>,
+    settings: &ReleaseScrapeDockerv2Settings,
+) -> LimitOutcome {
+    let invalid_keys: Vec<String> = metadata
+        .keys()
+        .filter(|key| {
+            key.is_empty()
+                || key.len() > settings.metadata_max_key_length
+                || key.chars().any(char::is_control)
+        })
+        .cloned()
+        .collect();
+    let oversized_values = metadata
+        .values()
+        .any(|value| value.len() > settings.metadata_max_value_size);
+    let excess_keys =
+        (metadata.len() - invalid_keys.len()).saturating_sub(settings.metadata_max_keys);
+
+    if invalid_keys.is_empty() && !oversized_values && excess_keys == 0 {
+        return LimitOutcome::Unchanged;
+    }
+    if settings.metadata_limit_action == MetadataLimitAction::Reject {
+        return LimitOutcome::Rejected;
+    }
+
+    for key in invalid_keys {
+        metadata.remove(&key);
+    }
+
+    for value in metadata.values_mut() {
+        if value.len() > settings.metadata_max_value_size {
+            let mut end = settings.metadata_max_value_size;
+            while !value.is_char_boundary(end) {
+                end -= 1;
+            }
+            value.truncate(end);
+        }
+    }
+
+    let mut droppable: Vec<String> = metadata
+        .keys()
+        .filter(|key| **key != settings.manifestref_key)
+        .cloned()
+        .collect();
+    droppable.sort();
+    for key in droppable.into_iter().rev().take(excess_keys) {
+        metadata.remove(&key);
+    }
+
+    LimitOutcome::Truncated
 }
 
 /// Metadata fetcher for quay.io API.
 #[derive(CustomDebug)]
 pub struct ReleaseScrapeDockerv2Plugin {
     settings: ReleaseScrapeDockerv2Settings,
-    registry: registry::Registry,
+    sources: Vec<Source>,
+    mirrors: Vec<Source>,
     cache: registry::cache::Cache,
+    store: Option<Arc<dyn registry::persistence::MetadataStore>>,
+    store_loaded: AtomicBool,
+    /// Pattern of the repositories to scrape, in discovery mode.
+    discovery: Option<regex::Regex>,
+    /// Tag digests observed by the last scrape of each discovered repository.
+    discovered_tag_digests: Mutex<HashMap<String, registry::cache::TagDigests>>,
+    /// Tags and architectures to scrape.
+    filter: registry::filter::ScrapeFilter,
 
     #[debug(skip)]
     graph_upstream_raw_releases: prometheus::IntGauge,
+
+    #[debug(skip)]
+    upstream_tags_fetched: prometheus::IntCounter,
+
+    #[debug(skip)]
+    upstream_tags_skipped: prometheus::IntCounter,
+
+    #[debug(skip)]
+    upstream_metadata_truncated: prometheus::IntCounter,
+
+    #[debug(skip)]
+    upstream_metadata_rejected: prometheus::IntCounter,
+
+    #[debug(skip)]
+    upstream_mirror_fallbacks: prometheus::IntCounter,
+
+    #[debug(skip)]
+    upstream_serving_source: prometheus::IntGaugeVec,
+
+    #[debug(skip)]
+    upstream_discovered_repositories: prometheus::IntGauge,
 }
 
 impl ReleaseScrapeDockerv2Plugin {
@@ -95,90 +683,664 @@ impl ReleaseScrapeDockerv2Plugin {
     pub const PLUGIN_NAME: &'static str = "release-scrape-dockerv2";
 
     pub fn try_new(
-        mut settings: ReleaseScrapeDockerv2Settings,
+        settings: ReleaseScrapeDockerv2Settings,
         cache: Option<registry::cache::Cache>,
         prometheus_registry: Option<&prometheus::Registry>,
     ) -> Fallible<Self> {
-        use prometheus::IntGauge;
+        use prometheus::{IntCounter, IntGauge, IntGaugeVec, Opts};
         let graph_upstream_raw_releases: IntGauge = IntGauge::new(
             "graph_upstream_raw_releases",
             "Number of releases fetched from upstream, before processing",
         )?;
+        let upstream_tags_fetched = IntCounter::new(
+            "graph_upstream_tags_fetched_total",
+            "Total number of new or changed tags whose release metadata was fetched from upstream",
+        )?;
+        let upstream_tags_skipped = IntCounter::new(
+            "graph_upstream_tags_skipped_total",
+            "Total number of unchanged tags whose release metadata was served from the cache",
+        )?;
+        let upstream_metadata_truncated = IntCounter::new(
+            "graph_upstream_metadata_truncated_total",
+            "Total number of scraped releases whose metadata was truncated to the limits",
+        )?;
+        let upstream_metadata_rejected = IntCounter::new(
+            "graph_upstream_metadata_rejected_total",
+            "Total number of scraped releases left out because their metadata exceeds the limits",
+        )?;
+        let upstream_mirror_fallbacks = IntCounter::new(
+            "graph_upstream_mirror_fallbacks_total",
+            "Total number of scrapes of the main repository which were served by a mirror",
+        )?;
+        let upstream_serving_source = IntGaugeVec::new(
+            Opts::new(
+                "graph_upstream_serving_source",
+                "Repository which served the main repository in the last scrape, set to 1",
+            ),
+            &["source"],
+        )?;
+        let upstream_discovered_repositories = IntGauge::new(
+            "graph_upstream_discovered_repositories",
+            "Number of repositories matching the discovery pattern in the last scrape",
+        )?;
 
         if let Some(prometheus_registry) = &prometheus_registry {
             prometheus_registry.register(Box::new(graph_upstream_raw_releases.clone()))?;
+            prometheus_registry.register(Box::new(upstream_tags_fetched.clone()))?;
+            prometheus_registry.register(Box::new(upstream_tags_skipped.clone()))?;
+            prometheus_registry.register(Box::new(upstream_metadata_truncated.clone()))?;
+            prometheus_registry.register(Box::new(upstream_metadata_rejected.clone()))?;
+            prometheus_registry.register(Box::new(upstream_mirror_fallbacks.clone()))?;
+            prometheus_registry.register(Box::new(upstream_serving_source.clone()))?;
+            prometheus_registry.register(Box::new(upstream_discovered_repositories.clone()))?;
         }
 
-        let registry = registry::Registry::try_from_str(&settings.registry)
-            .context(format!("Parsing {} as Registry", &settings.registry))?;
-
-        if let Some(credentials_path) = &settings.credentials_path {
-            let (username, password) = registry::read_credentials(
-                Some(credentials_path),
-                &registry.host_port_string(),
-            )
-            .unwrap_or_else(|err| {
-                warn!(
-                    "Error reading registry credentials from {:?}. Access to {:?} will be unauthenticated: {} ",
-                    credentials_path, &registry.host_port_string() ,err
-                );
-                (None, None)
-            });
-
-            settings.username = username;
-            settings.password = password;
+        let mut main = Source::try_new(
+            &settings.registry,
+            &settings.repository,
+            settings.credentials_path.as_ref(),
+            settings.ecr_auth,
+            registry::ClientTls::try_new(
+                settings.ca_bundle_path.as_deref(),
+                settings.client_cert_path.as_deref(),
+                settings.client_key_path.as_deref(),
+            )?,
+            settings.username.clone(),
+            settings.password.clone(),
+        )?;
+        main.metadata_lookup = settings.metadata_lookup(&[])?;
+        let mut sources = vec![main];
+        for source in &settings.additional_sources {
+            sources.push(Source::from_settings(source, &settings)?);
         }
+        let mirrors = settings
+            .mirrors
+            .iter()
+            .map(|mirror| Source::from_settings(mirror, &settings))
+            .collect::<Fallible<Vec<_>>>()?;
+
+        let store = match &settings.cache_path {
+            Some(cache_path) => {
+                let store = registry::persistence::SledMetadataStore::open(
+                    cache_path,
+                    settings.cache_max_entries,
+                )?;
+                Some(Arc::new(store) as Arc<dyn registry::persistence::MetadataStore>)
+            }
+            None => None,
+        };
+
+        let discovery = settings
+            .repository_pattern
+            .as_deref()
+            .map(discovery_regex)
+            .transpose()?;
+        let filter = settings.scrape_filter()?;
 
         Ok(Self {
             settings,
-            registry,
-            cache: cache.unwrap_or_else(registry::cache::new),
+            sources,
+            mirrors,
+            cache: cache.unwrap_or_default(),
+            store,
+            store_loaded: AtomicBool::new(false),
+            discovery,
+            discovered_tag_digests: Default::default(),
+            filter,
             graph_upstream_raw_releases,
+            upstream_tags_fetched,
+            upstream_tags_skipped,
+            upstream_metadata_truncated,
+            upstream_metadata_rejected,
+            upstream_mirror_fallbacks,
+            upstream_serving_source,
+            upstream_discovered_repositories,
         })
     }
-}
 
-#[async_trait]
-impl InternalPlugin for ReleaseScrapeDockerv2Plugin {
-    const PLUGIN_NAME: &'static str = Self::PLUGIN_NAME;
+    /// List the repositories of the main registry matching the discovery pattern.
+    async fn discover(&self, pattern: &regex::Regex) -> Fallible<Vec<Source>> {
+        let main = &self.sources[0];
+        let (username, password) = main.credentials.get().await?;
+        let mut repositories: Vec<String> = registry::list_repositories(
+            &main.registry,
+            username.as_deref(),
+            password.as_deref(),
+            &main.tls,
+        )
+        .await
+        .context(format!(
+            "failed to list the repositories of {}",
+            main.registry.host_port_string()
+        ))?
+        .into_iter()
+        .filter(|repository| pattern.is_match(repository))
+        .collect();
+        repositories.sort();
 
-    async fn run_internal(&self, io: InternalIO) -> Fallible<InternalIO> {
-        let releases = registry::fetch_releases(
-            &self.registry,
-            &self.settings.repository,
-            self.settings.username.as_ref().map(String::as_ref),
-            self.settings.password.as_ref().map(String::as_ref),
+        self.upstream_discovered_repositories
+            .set(repositories.len().try_into()?);
+        ensure!(
+            repositories.len() <= self.settings.discovery_max_repositories,
+            "{} repositories of {} match '{}', more than discovery_max_repositories ({})",
+            repositories.len(),
+            main.registry.host_port_string(),
+            pattern.as_str(),
+            self.settings.discovery_max_repositories
+        );
+        if repositories.is_empty() {
+            warn!(
+                "no repository of {} matches '{}'",
+                main.registry.host_port_string(),
+                pattern.as_str()
+            );
+        }
+
+        let mut tag_digests = self
+            .discovered_tag_digests
+            .lock()
+            .map_err(|e| format_err!("{}", e))?;
+        // Repositories which disappeared from the catalog are forgotten.
+        tag_digests.retain(|repository, _| repositories.contains(repository));
+        Ok(repositories
+            .iter()
+            .map(|repository| {
+                let digests = tag_digests
+                    .entry(repository.clone())
+                    .or_insert_with(registry::cache::new_tag_digests)
+                    .clone();
+                main.sibling(repository, digests)
+            })
+            .collect())
+    }
+
+    /// Enforce the metadata limits, leaving out rejected releases.
+    fn limit_metadata(
+        &self,
+        releases: Vec<cincinnati::plugins::internal::graph_builder::release::Release>,
+    ) -> Vec<cincinnati::plugins::internal::graph_builder::release::Release> {
+        releases
+            .into_iter()
+            .filter_map(|mut release| {
+                match enforce_metadata_limits(&mut release.metadata.metadata, &self.settings) {
+                    LimitOutcome::Unchanged => Some(release),
+                    LimitOutcome::Truncated => {
+                        warn!("truncated metadata of {} to the limits", release.source);
+                        self.upstream_metadata_truncated.inc();
+                        Some(release)
+                    }
+                    LimitOutcome::Rejected => {
+                        warn!(
+                            "leaving out {}, its metadata exceeds the limits",
+                            release.source
+                        );
+                        self.upstream_metadata_rejected.inc();
+                        None
+                    }
+                }
+            })
+            .collect()
+    }
+
+    /// Fetch the releases of a single repository.
+    async fn fetch_source(
+        &self,
+        source: &Source,
+    ) -> Fallible<Vec<cincinnati::plugins::internal::graph_builder::release::Release>> {
+        let (username, password) = source.credentials.get().await?;
+        let (releases, stats) = registry::fetch_releases(
+            &source.registry,
+            &source.repository,
+            username.as_deref(),
+            password.as_deref(),
+            &source.tls,
             self.cache.clone(),
+            source.tag_digests.clone(),
+            self.store.clone(),
             &self.settings.manifestref_key,
+            &source.metadata_lookup,
+            &self.filter,
             self.settings.fetch_concurrency,
+            self.settings.max_tags,
+            &self.settings.retry,
+            self.settings
+                .adaptive_throttling
+                .then_some(self.settings.throttle_min_rate),
         )
         .await
         .context(format!(
-            "failed to fetch all release metadata from {}/{}",
-            &self.registry.host_port_string(),
-            &self.settings.repository
+            "failed to fetch all release metadata from {}",
+            source.name()
         ))?;
 
         if releases.is_empty() {
-            warn!(
-                "could not find any releases in {}/{}",
-                &self.registry.host_port_string(),
-                &self.settings.repository
-            );
+            warn!("could not find any releases in {}", source.name());
+        };
+
+        self.upstream_tags_fetched
+            .inc_by(stats.fetched_tags.try_into()?);
+        self.upstream_tags_skipped
+            .inc_by(stats.skipped_tags.try_into()?);
+
+        Ok(releases)
+    }
+
+    /// Fetch the releases of the main repository, falling back to its mirrors in order.
+    ///
+    /// Returns the releases along with the name of the repository which served them.
+    async fn fetch_with_mirrors(
+        &self,
+        main: &Source,
+    ) -> Fallible<(
+        Vec<cincinnati::plugins::internal::graph_builder::release::Release>,
+        String,
+    )> {
+        if self.mirrors.is_empty() {
+            return Ok((self.fetch_source(main).await?, main.name()));
+        }
+
+        let timeout = self.settings.mirror_timeout_secs.map(Duration::from_secs);
+        let mut errors = vec![];
+        for (index, source) in std::iter::once(main).chain(&self.mirrors).enumerate() {
+            let result = match timeout {
+                Some(timeout) => tokio::time::timeout(timeout, self.fetch_source(source))
+                    .await
+                    .unwrap_or_else(|_| {
+                        Err(format_err!(
+                            "timed out fetching release metadata from {} after {}s",
+                            source.name(),
+                            timeout.as_secs()
+                        ))
+                    }),
+                None => self.fetch_source(source).await,
+            };
+
+            match result {
+                Ok(releases) => {
+                    for candidate in std::iter::once(main).chain(&self.mirrors) {
+                        self.upstream_serving_source
+                            .with_label_values(&[&candidate.name()])
+                            .set((candidate.name() == source.name()) as i64);
+                    }
+                    if index == 0 {
+                        return Ok((releases, main.name()));
+                    }
+
+                    warn!("{} was served by mirror {}", main.name(), source.name());
+                    self.upstream_mirror_fallbacks.inc();
+                    return Ok((attribute_to(releases, &main.name()), source.name()));
+                }
+                Err(e) => {
+                    warn!("{:#}", e);
+                    errors.push(format!("{:#}", e));
+                }
+            }
+        }
+
+        bail!(
+            "{} and all its mirrors failed:\n{}",
+            main.name(),
+            errors.join("\n")
+        )
+    }
+
+    /// Keep only the cached metadata of the manifests the tags pointed to in the last scrapes.
+    ///
+    /// These are marked as recently used in the persistent store, which is
+    /// flushed once per scrape.
+    async fn prune_cache(&self) -> Fallible<()> {
+        let mut all_tag_digests: Vec<registry::cache::TagDigests> = self
+            .sources
+            .iter()
+            .chain(&self.mirrors)
+            .map(|source| source.tag_digests.clone())
+            .collect();
+        all_tag_digests.extend(
+            self.discovered_tag_digests
+                .lock()
+                .map_err(|e| format_err!("{}", e))?
+                .values()
+                .cloned(),
+        );
+
+        let mut used = HashSet::new();
+        for tag_digests in all_tag_digests {
+            used.extend(tag_digests.read().await.values().cloned());
+        }
+        self.cache
+            .write()
+            .await
+            .retain(|manifestref, _| used.contains(manifestref));
+
+        if let Some(store) = &self.store {
+            store.touch(&used)?;
+            store.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Populate the in-memory cache from the persistent store, once.
+    async fn load_persisted_cache(&self) {
+        let store = match &self.store {
+            Some(store) if !self.store_loaded.swap(true, Ordering::SeqCst) => store,
+            _ => return,
         };
 
+        match registry::persistence::warm_cache(store.as_ref(), &self.cache).await {
+            Ok(count) => info!("loaded {} entries from persistent metadata cache", count),
+            Err(e) => warn!("failed to load persistent metadata cache: {}", e),
+        }
+    }
+}
+
+#[async_trait]
+impl InternalPlugin for ReleaseScrapeDockerv2Plugin {
+    const PLUGIN_NAME: &'static str = Self::PLUGIN_NAME;
+
+    async fn run_internal(&self, io: InternalIO) -> Fallible<InternalIO> {
+        self.load_persisted_cache().await;
+
+        let mut parameters = io.parameters;
+        let mut scraped = Vec::with_capacity(self.sources.len());
+        for (index, source) in self.sources.iter().enumerate() {
+            let releases = if index == 0 && self.discovery.is_some() {
+                // The main repository is replaced by the discovered ones.
+                continue;
+            } else if index == 0 {
+                let (releases, serving_source) = self.fetch_with_mirrors(source).await?;
+                parameters.insert(SERVING_SOURCE_PARAM_KEY.to_string(), serving_source);
+                releases
+            } else {
+                self.fetch_source(source).await?
+            };
+            scraped.push(releases);
+        }
+        if let Some(pattern) = &self.discovery {
+            let sources = self.discover(pattern).await?;
+            // The order of the repositories is kept, as it decides which one a
+            // release found in several of them is attributed to.
+            let fetches: Vec<_> = sources
+                .iter()
+                .map(|source| self.fetch_source(source))
+                .collect();
+            let discovered: Vec<_> = futures::stream::iter(fetches)
+                .buffered(DISCOVERY_CONCURRENCY)
+                .try_collect()
+                .await?;
+            scraped.extend(discovered);
+        }
+
+        if let Err(e) = self.prune_cache().await {
+            warn!("failed to prune the release metadata cache: {}", e);
+        }
+
+        let releases = self.limit_metadata(merge_releases(scraped));
         self.graph_upstream_raw_releases
             .set(releases.len().try_into()?);
 
         let graph = cincinnati::plugins::internal::graph_builder::release::create_graph(releases)?;
 
-        Ok(InternalIO {
-            graph,
-            parameters: io.parameters,
-        })
+        Ok(InternalIO { graph, parameters })
     }
 }
 
 #[cfg(test)]
 #[cfg(feature = "test-net")]
 mod network_tests;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cincinnati::plugins::internal::graph_builder::release::{Metadata, MetadataKind, Release};
+
+    fn release(source: &str, version: (u64, u64, u64)) -> Release {
+        Release {
+            source: source.to_string(),
+            metadata: Metadata {
+                kind: MetadataKind::V0,
+                version: semver::Version::from(version),
+                next: Default::default(),
+                previous: Default::default(),
+                metadata: Default::default(),
+            },
+        }
+    }
+
+    #[test]
+    fn merge_releases_deduplicates_by_digest() {
+        let primary = vec![
+            release("quay.io/ocp/release@sha256:aaaa", (4, 1, 0)),
+            release("quay.io/ocp/release@sha256:bbbb", (4, 1, 1)),
+        ];
+        let mirror = vec![
+            release("mirror.local:5000/ocp/release@sha256:bbbb", (4, 1, 1)),
+            release("mirror.local:5000/ocp/release@sha256:cccc", (4, 1, 2)),
+        ];
+
+        let sources: Vec<String> = merge_releases(vec![primary, mirror])
+            .into_iter()
+            .map(|release| release.source)
+            .collect();
+
+        assert_eq!(
+            sources,
+            vec![
+                "quay.io/ocp/release@sha256:aaaa",
+                "quay.io/ocp/release@sha256:bbbb",
+                "mirror.local:5000/ocp/release@sha256:cccc",
+            ]
+        );
+    }
+
+    #[test]
+    fn metadata_limits() {
+        let settings = ReleaseScrapeDockerv2Settings {
+            manifestref_key: "ref".to_string(),
+            metadata_max_keys: 2,
+            metadata_max_key_length: 8,
+            metadata_max_value_size: 4,
+            ..Default::default()
+        };
+        let metadata = |entries: &[(&str, &str)]| -> MapImpl<String, String> {
+            entries
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect()
+        };
+
+        let mut within = metadata(&[("ref", "sha"), ("url", "u")]);
+        assert_eq!(
+            enforce_metadata_limits(&mut within, &settings),
+            LimitOutcome::Unchanged
+        );
+
+        let mut exceeding = metadata(&[
+            ("ref", "sha"),
+            ("a", "abcdé"),
+            ("z", "z"),
+            ("too-long-key", "x"),
+            ("bad\nkey", "x"),
+        ]);
+        assert_eq!(
+            enforce_metadata_limits(&mut exceeding, &settings),
+            LimitOutcome::Truncated
+        );
+        assert_eq!(exceeding, metadata(&[("ref", "sha"), ("a", "abcd")]));
+
+        let settings = ReleaseScrapeDockerv2Settings {
+            metadata_limit_action: MetadataLimitAction::Reject,
+            ..settings
+        };
+        let mut oversized = metadata(&[("ref", "sha256:abc")]);
+        assert_eq!(
+            enforce_metadata_limits(&mut oversized, &settings),
+            LimitOutcome::Rejected
+        );
+        assert_eq!(oversized, metadata(&[("ref", "sha256:abc")]));
+    }
+
+    #[test]
+    fn deserialize_additional_sources() -> Fallible<()> {
+        let settings: ReleaseScrapeDockerv2Settings = toml::from_str(
+            r#"
+                repository = "ocp/release"
+
+                [[additional_sources]]
+                registry = "mirror.local:5000"
+                repository = "ocp/release"
+            "#,
+        )?;
+        assert_eq!(settings.additional_sources.len(), 1);
+        assert!(settings.additional_sources[0].credentials_path.is_none());
+
+        let plugin = ReleaseScrapeDockerv2Plugin::try_new(settings, None, None)?;
+        assert_eq!(plugin.sources.len(), 2);
+        assert_eq!(plugin.sources[1].name(), "mirror.local:5000/ocp/release");
+
+        Ok(())
+    }
+
+    #[test]
+    fn deserialize_metadata_sources() -> Fallible<()> {
+        use registry::artifacts::MetadataSource::*;
+
+        let settings: ReleaseScrapeDockerv2Settings = toml::from_str(
+            r#"
+                registry = "registry.local"
+                repository = "ocp/release"
+                metadata_source = "annotations"
+
+                [[additional_sources]]
+                registry = "quay.io"
+                repository = "ocp/release"
+                metadata_sources = ["quay_labels", "config_labels"]
+            "#,
+        )?;
+        let plugin = ReleaseScrapeDockerv2Plugin::try_new(settings.clone(), None, None)?;
+        assert_eq!(
+            plugin.sources[0].metadata_lookup.sources,
+            vec![Annotations, Layers]
+        );
+        assert_eq!(
+            plugin.sources[1].metadata_lookup.sources,
+            vec![QuayLabels, ConfigLabels]
+        );
+        assert!(!plugin.sources[1].metadata_lookup.falls_back_to_layers());
+
+        let settings = ReleaseScrapeDockerv2Settings {
+            metadata_sources: vec![ConfigLabels, Referrers, Layers],
+            ..settings
+        };
+        assert_eq!(
+            settings.metadata_lookup(&[])?.sources,
+            vec![ConfigLabels, Referrers, Layers]
+        );
+
+        let cfg = |sources: &str| -> Fallible<toml::Value> {
+            Ok(toml::from_str(&format!(
+                "name = 'release-scrape-dockerv2'\nmetadata_sources = {}",
+                sources
+            ))?)
+        };
+        ReleaseScrapeDockerv2Settings::deserialize_config(cfg("['config_labels', 'layers']")?)?;
+        ReleaseScrapeDockerv2Settings::deserialize_config(cfg("['layers', 'config_labels']")?)
+            .unwrap_err();
+        ReleaseScrapeDockerv2Settings::deserialize_config(cfg("['labels']")?).unwrap_err();
+
+        Ok(())
+    }
+
+    #[test]
+    fn deserialize_scrape_filter() -> Fallible<()> {
+        let cfg = |extra: &str| -> Fallible<toml::Value> {
+            Ok(toml::from_str(&format!(
+                "name = 'release-scrape-dockerv2'\n{}",
+                extra
+            ))?)
+        };
+
+        ReleaseScrapeDockerv2Settings::deserialize_config(cfg(
+            "tag_pattern = '.*-x86_64'\ntag_version_range = '4.16.*'\narchitectures = ['amd64']",
+        )?)?;
+        ReleaseScrapeDockerv2Settings::deserialize_config(cfg("tag_pattern = '['")?).unwrap_err();
+        ReleaseScrapeDockerv2Settings::deserialize_config(cfg(
+            "tag_version_range = 'not a range'",
+        )?)
+        .unwrap_err();
+        ReleaseScrapeDockerv2Settings::deserialize_config(cfg("architectures = ['']")?)
+            .unwrap_err();
+
+        Ok(())
+    }
+
+    #[test]
+    fn deserialize_fetch_concurrency() -> Fallible<()> {
+        let cfg = |concurrency: usize| -> Fallible<toml::Value> {
+            Ok(toml::from_str(&format!(
+                "name = 'release-scrape-dockerv2'\nfetch_concurrency = {}",
+                concurrency
+            ))?)
+        };
+
+        ReleaseScrapeDockerv2Settings::deserialize_config(cfg(4)?)?;
+        ReleaseScrapeDockerv2Settings::deserialize_config(cfg(0)?).unwrap_err();
+
+        Ok(())
+    }
+
+    #[test]
+    fn deserialize_discovery() -> Fallible<()> {
+        let cfg = |extra: &str| -> Fallible<toml::Value> {
+            Ok(toml::from_str(&format!(
+                "name = 'release-scrape-dockerv2'\nregistry = 'registry.local'\n{}",
+                extra
+            ))?)
+        };
+
+        ReleaseScrapeDockerv2Settings::deserialize_config(cfg(
+            "repository_pattern = 'product/release-[0-9.]+'",
+        )?)?;
+        ReleaseScrapeDockerv2Settings::deserialize_config(cfg("repository_pattern = '('")?)
+            .unwrap_err();
+        ReleaseScrapeDockerv2Settings::deserialize_config(cfg(
+            "repository_pattern = 'product/.*'\n[[mirrors]]\nregistry = 'mirror.local'\nrepository = 'product/release'",
+        )?)
+        .unwrap_err();
+
+        // Patterns match whole repository names.
+        let pattern = discovery_regex("product/release-[0-9.]+")?;
+        assert!(pattern.is_match("product/release-4.12"));
+        assert!(!pattern.is_match("product/release-4.12-dev"));
+        assert!(!pattern.is_match("other/product/release-4.12"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn attribute_mirrored_releases() -> Fallible<()> {
+        let settings: ReleaseScrapeDockerv2Settings = toml::from_str(
+            r#"
+                repository = "ocp/release"
+                mirror_timeout_secs = 30
+
+                [[mirrors]]
+                registry = "mirror.local:5000"
+                repository = "ocp/release"
+            "#,
+        )?;
+        let plugin = ReleaseScrapeDockerv2Plugin::try_new(settings, None, None)?;
+        assert_eq!(plugin.sources.len(), 1);
+        assert_eq!(plugin.mirrors[0].name(), "mirror.local:5000/ocp/release");
+
+        let releases = attribute_to(
+            vec![release(
+                "mirror.local:5000/ocp/release@sha256:abc",
+                (4, 10, 1),
+            )],
+            &plugin.sources[0].name(),
+        );
+        assert_eq!(releases[0].source, "quay.io/ocp/release@sha256:abc");
+
+        Ok(())
+    }
+}
@@ -0,0 +1,744 @@
+//! Release metadata published alongside the release images.
+//!
+//! Finding the release metadata in the payload layers takes downloading and
+//! unpacking them, which amounts to hundreds of megabytes per release. Registries
+//! implementing OCI 1.1 can instead serve the metadata as an artifact referring
+//! to the release image, listed by the referrers API, while release images can
+//! also carry it in an annotation of their manifest or a label of their image
+//! configuration, and Quay serves the labels of a manifest through its API.
+//! Either only takes a few small requests per release.
+
+use crate as cincinnati;
+
+use self::cincinnati::plugins::internal::graph_builder::release::Metadata;
+use self::cincinnati::plugins::prelude_plugin_impl::*;
+
+use super::http::RepositoryClient;
+use super::{ClientTls, Registry};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use url::Url;
+
+/// Default artifact type of the release metadata artifacts.
+pub static DEFAULT_ARTIFACT_TYPE: &str = "application/vnd.openshift.release.metadata.v1+json";
+
+/// Default manifest annotation carrying the release metadata.
+pub static DEFAULT_ANNOTATION: &str = "io.openshift.upgrades.graph.release.metadata";
+
+/// Default label carrying the release metadata.
+pub static DEFAULT_LABEL: &str = "io.openshift.upgrades.graph.release.metadata";
+
+/// Maximum size of the manifests and blobs fetched to find the release metadata.
+pub static MAX_ARTIFACT_SIZE: u64 = 1024 * 1024;
+
+static OCI_INDEX: &str = "application/vnd.oci.image.index.v1+json";
+static OCI_MANIFEST: &str = "application/vnd.oci.image.manifest.v1+json";
+static DOCKER_MANIFEST_LIST: &str = "application/vnd.docker.distribution.manifest.list.v2+json";
+static DOCKER_MANIFEST: &str = "application/vnd.docker.distribution.manifest.v2+json";
+
+/// Where to find the release metadata.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq, SmartDefault)]
+#[serde(rename_all = "snake_case")]
+pub enum MetadataSource {
+    /// In the payload layers.
+    #[default]
+    Layers,
+    /// In an artifact listed by the OCI referrers API.
+    Referrers,
+    /// In an annotation of the image manifest.
+    Annotations,
+    /// In a label of the image configuration.
+    ConfigLabels,
+    /// In a label of the manifest, as served by the Quay API.
+    QuayLabels,
+}
+
+/// Check an ordered list of metadata sources.
+///
+/// The payload layers are the last resort, so no source may follow them.
+pub fn validate_sources(sources: &[MetadataSource]) -> Fallible<()> {
+    for (i, source) in sources.iter().enumerate() {
+        ensure!(
+            !sources[..i].contains(source),
+            "metadata source {:?} is listed twice",
+            source
+        );
+    }
+    ensure!(
+        sources
+            .iter()
+            .position(|source| *source == MetadataSource::Layers)
+            .is_none_or(|position| position + 1 == sources.len()),
+        "no metadata source can follow the payload layers"
+    );
+    Ok(())
+}
+
+/// Lookup of the release metadata outside of the payload layers.
+#[derive(Clone, Debug, PartialEq, SmartDefault)]
+pub struct MetadataLookup {
+    /// Sources to try in order, until one has the metadata of a release.
+    #[default(vec![MetadataSource::Layers])]
+    pub sources: Vec<MetadataSource>,
+
+    /// Artifact type of the metadata artifacts, for the referrers source.
+    #[default(DEFAULT_ARTIFACT_TYPE.to_string())]
+    pub artifact_type: String,
+
+    /// Annotation carrying the metadata, for the annotations source.
+    #[default(DEFAULT_ANNOTATION.to_string())]
+    pub annotation: String,
+
+    /// Label carrying the metadata, for the label sources.
+    #[default(DEFAULT_LABEL.to_string())]
+    pub label: String,
+
+    /// Token for the Quay API, for the Quay labels source.
+    #[default(Option::None)]
+    pub quay_api_token: Option<String>,
+}
+
+impl MetadataLookup {
+    /// Whether releases without published metadata fall back to the payload layers.
+    pub fn falls_back_to_layers(&self) -> bool {
+        self.sources.contains(&MetadataSource::Layers)
+    }
+}
+
+/// OCI content descriptor.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Descriptor {
+    #[serde(default)]
+    media_type: String,
+    digest: String,
+    size: u64,
+    #[serde(default)]
+    artifact_type: Option<String>,
+}
+
+/// OCI image index, as returned by the referrers API.
+#[derive(Debug, Deserialize)]
+struct Index {
+    #[serde(default)]
+    manifests: Vec<Descriptor>,
+}
+
+/// The parts of an OCI image manifest or index relevant to the metadata lookup.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Manifest {
+    #[serde(default)]
+    artifact_type: Option<String>,
+    #[serde(default)]
+    config: Option<Descriptor>,
+    #[serde(default)]
+    layers: Vec<Descriptor>,
+    #[serde(default)]
+    annotations: HashMap<String, String>,
+}
+
+/// The labels of an image configuration.
+#[derive(Debug, Deserialize)]
+struct ImageConfig {
+    #[serde(default)]
+    config: Option<ContainerConfig>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ContainerConfig {
+    #[serde(default, rename = "Labels")]
+    labels: Option<HashMap<String, String>>,
+}
+
+/// Client looking up the release metadata of a repository outside of the payload layers.
+#[derive(CustomDebug)]
+pub struct ArtifactClient {
+    lookup: MetadataLookup,
+    http: RepositoryClient,
+    /// Client of the Quay API of the registry, for the Quay labels source.
+    #[debug(skip)]
+    quay_client: Option<quay::v1::Client>,
+}
+
+impl ArtifactClient {
+    /// Create a client for the repository `repo` of `registry`.
+    ///
+    /// Returns `None` if the metadata is only looked up in the payload layers.
+    pub fn try_new(
+        lookup: &MetadataLookup,
+        registry: &Registry,
+        repo: &str,
+        username: Option<&str>,
+        password: Option<&str>,
+        tls: &ClientTls,
+    ) -> Fallible<Option<Self>> {
+        if lookup
+            .sources
+            .first()
+            .is_none_or(|source| *source == MetadataSource::Layers)
+        {
+            return Ok(None);
+        }
+
+        let http = RepositoryClient::try_new(registry, repo, username, password, tls)?;
+        let quay_client = if lookup.sources.contains(&MetadataSource::QuayLabels) {
+            let client = quay::v1::Client::builder()
+                .http_client(Some(http.http_client().clone()))
+                .access_token(lookup.quay_api_token.clone())
+                .api_base(Some(format!("{}/api/v1/", registry.base_url())))
+                .build()
+                .context("Building Quay API client")?;
+            Some(client)
+        } else {
+            None
+        };
+
+        Ok(Some(Self {
+            lookup: lookup.clone(),
+            http,
+            quay_client,
+        }))
+    }
+
+    /// The sources publishing the metadata to try in order, up to the payload layers.
+    pub fn sources(&self) -> impl Iterator<Item = MetadataSource> + '_ {
+        self.lookup
+            .sources
+            .iter()
+            .copied()
+            .take_while(|source| *source != MetadataSource::Layers)
+    }
+
+    /// Whether releases without published metadata fall back to the payload layers.
+    pub fn falls_back_to_layers(&self) -> bool {
+        self.lookup.falls_back_to_layers()
+    }
+
+    /// Find the release metadata of the manifest `manifestref` in `source`.
+    ///
+    /// Returns `None` if it isn't published there.
+    pub async fn find_metadata(
+        &self,
+        source: MetadataSource,
+        manifestref: &str,
+    ) -> Fallible<Option<Metadata>> {
+        match source {
+            MetadataSource::Layers => Ok(None),
+            MetadataSource::Referrers => self.find_referrer(manifestref).await,
+            MetadataSource::Annotations => self.find_annotation(manifestref).await,
+            MetadataSource::ConfigLabels => self.find_config_label(manifestref).await,
+            MetadataSource::QuayLabels => self.find_quay_label(manifestref).await,
+        }
+    }
+
+    /// Read the metadata from the first metadata artifact referring to `manifestref`.
+    async fn find_referrer(&self, manifestref: &str) -> Fallible<Option<Metadata>> {
+        let mut url = self
+            .http
+            .base()
+            .join(&format!("referrers/{}", manifestref))?;
+        url.query_pairs_mut()
+            .append_pair("artifactType", &self.lookup.artifact_type);
+        let index: Index = match self.get(url, &[OCI_INDEX]).await? {
+            Some(body) => serde_json::from_slice(&body).context("parsing referrers")?,
+            None => {
+                trace!("referrers API not supported for {}", self.http.repo());
+                return Ok(None);
+            }
+        };
+
+        // Registries may ignore the artifact type filter.
+        let referrer = match index.manifests.into_iter().find(|descriptor| {
+            descriptor.artifact_type.as_deref() == Some(self.lookup.artifact_type.as_str())
+        }) {
+            Some(referrer) => referrer,
+            None => return Ok(None),
+        };
+
+        let manifest = self
+            .get_verified(
+                &format!("manifests/{}", referrer.digest),
+                &referrer,
+                &[OCI_MANIFEST],
+            )
+            .await?;
+        let manifest: Manifest = serde_json::from_slice(&manifest)
+            .context(format!("parsing artifact manifest {}", referrer.digest))?;
+        ensure!(
+            manifest
+                .artifact_type
+                .as_deref()
+                .is_none_or(|artifact_type| { artifact_type == self.lookup.artifact_type }),
+            "artifact {} is not of type {}",
+            referrer.digest,
+            self.lookup.artifact_type
+        );
+        let layer = manifest
+            .layers
+            .first()
+            .ok_or_else(|| format_err!("artifact {} has no layers", referrer.digest))?;
+
+        let blob = self
+            .get_verified(
+                &format!("blobs/{}", layer.digest),
+                layer,
+                &[&layer.media_type],
+            )
+            .await?;
+        let metadata = serde_json::from_slice(&blob).context(format!(
+            "parsing release metadata of artifact {}",
+            referrer.digest
+        ))?;
+        Ok(Some(metadata))
+    }
+
+    /// Read the metadata from the annotation of the manifest `manifestref`.
+    async fn find_annotation(&self, manifestref: &str) -> Fallible<Option<Metadata>> {
+        let url = self
+            .http
+            .base()
+            .join(&format!("manifests/{}", manifestref))?;
+        let accept = [
+            OCI_INDEX,
+            OCI_MANIFEST,
+            DOCKER_MANIFEST_LIST,
+            DOCKER_MANIFEST,
+        ];
+        let manifest = self
+            .get(url, &accept)
+            .await?
+            .ok_or_else(|| format_err!("manifest {} not found", manifestref))?;
+        let manifest: Manifest = serde_json::from_slice(&manifest)
+            .context(format!("parsing manifest {}", manifestref))?;
+
+        match manifest.annotations.get(&self.lookup.annotation) {
+            Some(value) => {
+                let metadata = serde_json::from_str(value).context(format!(
+                    "parsing annotation {} of manifest {}",
+                    self.lookup.annotation, manifestref
+                ))?;
+                Ok(Some(metadata))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Read the metadata from a label of the image configuration of the manifest `manifestref`.
+    ///
+    /// Image indexes have no configuration, so multi-arch releases never carry such a label.
+    async fn find_config_label(&self, manifestref: &str) -> Fallible<Option<Metadata>> {
+        let url = self
+            .http
+            .base()
+            .join(&format!("manifests/{}", manifestref))?;
+        let manifest = self
+            .get(url, &[OCI_MANIFEST, DOCKER_MANIFEST])
+            .await?
+            .ok_or_else(|| format_err!("manifest {} not found", manifestref))?;
+        let manifest: Manifest = serde_json::from_slice(&manifest)
+            .context(format!("parsing manifest {}", manifestref))?;
+        let config = match manifest.config {
+            Some(config) => config,
+            None => return Ok(None),
+        };
+
+        let blob = self
+            .get_verified(
+                &format!("blobs/{}", config.digest),
+                &config,
+                &[&config.media_type],
+            )
+            .await?;
+        let image_config: ImageConfig = serde_json::from_slice(&blob)
+            .context(format!("parsing image configuration {}", config.digest))?;
+
+        match image_config
+            .config
+            .and_then(|config| config.labels)
+            .and_then(|mut labels| labels.remove(&self.lookup.label))
+        {
+            Some(value) => {
+                let metadata = serde_json::from_str(&value).context(format!(
+                    "parsing label {} of image configuration {}",
+                    self.lookup.label, config.digest
+                ))?;
+                Ok(Some(metadata))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Read the metadata from a label of the manifest `manifestref`, through the Quay API.
+    async fn find_quay_label(&self, manifestref: &str) -> Fallible<Option<Metadata>> {
+        let quay_client = self
+            .quay_client
+            .as_ref()
+            .ok_or_else(|| format_err!("no Quay API client"))?;
+        let labels = quay_client
+            .get_labels(
+                self.http.repo(),
+                manifestref,
+                Some(self.lookup.label.as_str()),
+            )
+            .await
+            .context(format!("fetching the Quay labels of {}", manifestref))?;
+
+        // Only keep exact matches, whatever the filter semantics of the Quay instance.
+        match labels
+            .into_iter()
+            .find(|label| label.key == self.lookup.label)
+        {
+            Some(label) => {
+                let metadata = serde_json::from_str(&label.value).context(format!(
+                    "parsing Quay label {} of manifest {}",
+                    self.lookup.label, manifestref
+                ))?;
+                Ok(Some(metadata))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Fetch the content of `descriptor`, checking its size and digest.
+    async fn get_verified(
+        &self,
+        path: &str,
+        descriptor: &Descriptor,
+        accept: &[&str],
+    ) -> Fallible<Vec<u8>> {
+        ensure!(
+            descriptor.size <= MAX_ARTIFACT_SIZE,
+            "{} exceeds the maximum size of {} bytes",
+            descriptor.digest,
+            MAX_ARTIFACT_SIZE
+        );
+        let body = self
+            .get(self.http.base().join(path)?, accept)
+            .await?
+            .ok_or_else(|| format_err!("{} not found", descriptor.digest))?;
+        let digest = format!("sha256:{}", hex::encode(Sha256::digest(&body)));
+        ensure!(
+            digest == descriptor.digest,
+            "digest mismatch for {}, got {}",
+            descriptor.digest,
+            digest
+        );
+        Ok(body)
+    }
+
+    /// Fetch `url`, within the size limit of the artifacts.
+    ///
+    /// Returns `None` if the registry answers 404 Not Found.
+    async fn get(&self, url: Url, accept: &[&str]) -> Fallible<Option<Vec<u8>>> {
+        let response = match self.http.get(url.clone(), accept).await? {
+            Some(response) => response,
+            None => return Ok(None),
+        };
+        ensure!(
+            response
+                .content_length()
+                .is_none_or(|length| length <= MAX_ARTIFACT_SIZE),
+            "{} exceeds the maximum size of {} bytes",
+            url,
+            MAX_ARTIFACT_SIZE
+        );
+        let body = response.bytes().await?;
+        ensure!(
+            body.len() as u64 <= MAX_ARTIFACT_SIZE,
+            "{} exceeds the maximum size of {} bytes",
+            url,
+            MAX_ARTIFACT_SIZE
+        );
+        Ok(Some(body.to_vec()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn digest(content: &str) -> String {
+        format!("sha256:{}", hex::encode(Sha256::digest(content.as_bytes())))
+    }
+
+    fn client(source: MetadataSource, repo: &str) -> ArtifactClient {
+        let lookup = MetadataLookup {
+            sources: vec![source, MetadataSource::Layers],
+            ..Default::default()
+        };
+        let registry = Registry::try_from_str(&mockito::server_url()).unwrap();
+        ArtifactClient::try_new(
+            &lookup,
+            &registry,
+            repo,
+            Some("user"),
+            Some("pass"),
+            &Default::default(),
+        )
+        .unwrap()
+        .unwrap()
+    }
+
+    static METADATA: &str = r#"{"kind":"cincinnati-metadata-v0","version":"4.11.1","previous":["4.11.0"],"metadata":{"url":"https://example.com/4.11.1"}}"#;
+
+    #[tokio::test]
+    async fn find_referrers() -> Fallible<()> {
+        let manifestref = digest("release");
+        let manifest = format!(
+            r#"{{"schemaVersion":2,"mediaType":"{}","artifactType":"{}","layers":[{{"mediaType":"application/json","digest":"{}","size":{}}}]}}"#,
+            OCI_MANIFEST,
+            DEFAULT_ARTIFACT_TYPE,
+            digest(METADATA),
+            METADATA.len()
+        );
+        let index = format!(
+            r#"{{"schemaVersion":2,"manifests":[{{"mediaType":"{}","digest":"{}","size":10,"artifactType":"application/vnd.example.sbom"}},{{"mediaType":"{}","digest":"{}","size":{},"artifactType":"{}"}}]}}"#,
+            OCI_MANIFEST,
+            digest("sbom"),
+            OCI_MANIFEST,
+            digest(&manifest),
+            manifest.len(),
+            DEFAULT_ARTIFACT_TYPE
+        );
+
+        let _token = mockito::mock("GET", "/token")
+            .match_query(mockito::Matcher::UrlEncoded(
+                "scope".into(),
+                "repository:referrers/release:pull".into(),
+            ))
+            .match_header("authorization", "Basic dXNlcjpwYXNz")
+            .with_body(r#"{"token":"t0k3n"}"#)
+            .create();
+        let _unauthorized = mockito::mock(
+            "GET",
+            mockito::Matcher::Regex("^/v2/referrers/release/referrers/".to_string()),
+        )
+        .match_header("authorization", mockito::Matcher::Missing)
+        .with_status(401)
+        .with_header(
+            "www-authenticate",
+            &format!(r#"Bearer realm="{}/token""#, mockito::server_url()),
+        )
+        .create();
+        let _referrers = mockito::mock(
+            "GET",
+            mockito::Matcher::Regex(format!("^/v2/referrers/release/referrers/{}", manifestref)),
+        )
+        .match_header("authorization", "Bearer t0k3n")
+        .with_body(&index)
+        .create();
+        let _manifest = mockito::mock(
+            "GET",
+            format!("/v2/referrers/release/manifests/{}", digest(&manifest)).as_str(),
+        )
+        .match_header("authorization", "Bearer t0k3n")
+        .with_body(&manifest)
+        .create();
+        let _blob = mockito::mock(
+            "GET",
+            format!("/v2/referrers/release/blobs/{}", digest(METADATA)).as_str(),
+        )
+        .match_header("authorization", "Bearer t0k3n")
+        .with_body(METADATA)
+        .create();
+
+        let referrers = client(MetadataSource::Referrers, "referrers/release");
+        let metadata = referrers
+            .find_metadata(MetadataSource::Referrers, &manifestref)
+            .await?
+            .unwrap();
+        assert_eq!(metadata.version.to_string(), "4.11.1");
+        assert_eq!(metadata.previous.len(), 1);
+        assert_eq!(metadata.metadata["url"], "https://example.com/4.11.1");
+
+        // Releases without metadata artifacts.
+        let _none = mockito::mock(
+            "GET",
+            mockito::Matcher::Regex(format!(
+                "^/v2/referrers/release/referrers/{}",
+                digest("old")
+            )),
+        )
+        .with_body(r#"{"schemaVersion":2,"manifests":[]}"#)
+        .create();
+        assert!(referrers
+            .find_metadata(MetadataSource::Referrers, &digest("old"))
+            .await?
+            .is_none());
+
+        // Registries without the referrers API.
+        let unsupported = client(MetadataSource::Referrers, "referrers/unsupported");
+        let _not_found = mockito::mock(
+            "GET",
+            mockito::Matcher::Regex("^/v2/referrers/unsupported/".to_string()),
+        )
+        .with_status(404)
+        .create();
+        assert!(unsupported
+            .find_metadata(MetadataSource::Referrers, &manifestref)
+            .await?
+            .is_none());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn find_annotations() -> Fallible<()> {
+        let manifest = serde_json::json!({
+            "schemaVersion": 2,
+            "mediaType": OCI_MANIFEST,
+            "layers": [],
+            "annotations": { DEFAULT_ANNOTATION: METADATA },
+        })
+        .to_string();
+        let _manifest = mockito::mock(
+            "GET",
+            format!("/v2/annotations/release/manifests/{}", digest("annotated")).as_str(),
+        )
+        .with_body(&manifest)
+        .create();
+        let _plain = mockito::mock(
+            "GET",
+            format!("/v2/annotations/release/manifests/{}", digest("plain")).as_str(),
+        )
+        .with_body(r#"{"schemaVersion":2,"layers":[]}"#)
+        .create();
+
+        let client = client(MetadataSource::Annotations, "annotations/release");
+        let metadata = client
+            .find_metadata(MetadataSource::Annotations, &digest("annotated"))
+            .await?
+            .unwrap();
+        assert_eq!(metadata.version.to_string(), "4.11.1");
+        assert!(client
+            .find_metadata(MetadataSource::Annotations, &digest("plain"))
+            .await?
+            .is_none());
+        assert!(client
+            .find_metadata(MetadataSource::Annotations, &digest("missing"))
+            .await
+            .is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn find_config_labels() -> Fallible<()> {
+        let config = serde_json::json!({
+            "architecture": "amd64",
+            "config": { "Labels": { DEFAULT_LABEL: METADATA } },
+        })
+        .to_string();
+        let manifest = format!(
+            r#"{{"schemaVersion":2,"mediaType":"{}","config":{{"mediaType":"application/vnd.oci.image.config.v1+json","digest":"{}","size":{}}},"layers":[]}}"#,
+            OCI_MANIFEST,
+            digest(&config),
+            config.len()
+        );
+        let _manifest = mockito::mock(
+            "GET",
+            format!("/v2/labels/release/manifests/{}", digest("labeled")).as_str(),
+        )
+        .with_body(&manifest)
+        .create();
+        let _config = mockito::mock(
+            "GET",
+            format!("/v2/labels/release/blobs/{}", digest(&config)).as_str(),
+        )
+        .with_body(&config)
+        .create();
+        let _index = mockito::mock(
+            "GET",
+            format!("/v2/labels/release/manifests/{}", digest("index")).as_str(),
+        )
+        .with_body(r#"{"schemaVersion":2,"manifests":[]}"#)
+        .create();
+
+        let client = client(MetadataSource::ConfigLabels, "labels/release");
+        let metadata = client
+            .find_metadata(MetadataSource::ConfigLabels, &digest("labeled"))
+            .await?
+            .unwrap();
+        assert_eq!(metadata.version.to_string(), "4.11.1");
+        assert!(client
+            .find_metadata(MetadataSource::ConfigLabels, &digest("index"))
+            .await?
+            .is_none());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn find_quay_labels() -> Fallible<()> {
+        let labels = serde_json::json!({
+            "labels": [
+                {
+                    "key": format!("{}.extra", DEFAULT_LABEL),
+                    "value": "{}",
+                    "media_type": "text/plain",
+                    "id": "1",
+                    "source_type": "api",
+                },
+                {
+                    "key": DEFAULT_LABEL,
+                    "value": METADATA,
+                    "media_type": "application/json",
+                    "id": "2",
+                    "source_type": "api",
+                },
+            ]
+        })
+        .to_string();
+        let _labels = mockito::mock(
+            "GET",
+            format!(
+                "/api/v1/repository/quay/release/manifest/{}/labels",
+                digest("labeled")
+            )
+            .as_str(),
+        )
+        .match_query(mockito::Matcher::UrlEncoded(
+            "filter".into(),
+            DEFAULT_LABEL.into(),
+        ))
+        .with_body(&labels)
+        .create();
+        let _unlabeled = mockito::mock(
+            "GET",
+            format!(
+                "/api/v1/repository/quay/release/manifest/{}/labels",
+                digest("plain")
+            )
+            .as_str(),
+        )
+        .match_query(mockito::Matcher::Any)
+        .with_body(r#"{"labels":[]}"#)
+        .create();
+
+        let client = client(MetadataSource::QuayLabels, "quay/release");
+        let metadata = client
+            .find_metadata(MetadataSource::QuayLabels, &digest("labeled"))
+            .await?
+            .unwrap();
+        assert_eq!(metadata.version.to_string(), "4.11.1");
+        assert!(client
+            .find_metadata(MetadataSource::QuayLabels, &digest("plain"))
+            .await?
+            .is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn validate_metadata_sources() {
+        use MetadataSource::*;
+
+        validate_sources(&[QuayLabels, ConfigLabels, Layers]).unwrap();
+        validate_sources(&[Annotations, Referrers]).unwrap();
+        validate_sources(&[Layers]).unwrap();
+        validate_sources(&[ConfigLabels, ConfigLabels]).unwrap_err();
+        validate_sources(&[Layers, Annotations]).unwrap_err();
+    }
+}
@@ -0,0 +1,165 @@
+//! Authorization of requests to the repository API of a registry.
+//!
+//! Registries answer unauthorized requests with a `WWW-Authenticate`
+//! challenge: either `Basic`, answered with the credentials, or `Bearer`,
+//! answered with a token for the requested scope, e.g. pulling from a
+//! repository, from the authorization service named in the challenge. The
+//! clients keep the resulting authorization and send it with their further
+//! requests.
+
+use commons::prelude_errors::*;
+use reqwest::header::WWW_AUTHENTICATE;
+use serde::Deserialize;
+use std::collections::HashMap;
+
+/// Authorization of the requests to a repository.
+#[derive(Clone)]
+pub enum Authorization {
+    Basic(String, String),
+    Bearer(String),
+}
+
+impl Authorization {
+    /// Authorize a request.
+    pub fn apply(&self, request: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        match self {
+            Authorization::Basic(username, password) => {
+                request.basic_auth(username, Some(password))
+            }
+            Authorization::Bearer(token) => request.bearer_auth(token),
+        }
+    }
+}
+
+/// Returns the challenge of an unauthorized response.
+pub fn challenge(response: &reqwest::Response) -> Fallible<String> {
+    response
+        .headers()
+        .get(WWW_AUTHENTICATE)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string)
+        .ok_or_else(|| format_err!("unauthorized without challenge for {}", response.url()))
+}
+
+/// Answer the authentication `challenge` of a registry for `scope`, e.g.
+/// `repository:<repo>:pull`.
+///
+/// Bearer challenges are answered with a token for the scope of the challenge,
+/// or `scope` if it has none, requested with the credentials if any, or anonymously.
+pub async fn authorize(
+    client: &reqwest::Client,
+    challenge: &str,
+    scope: &str,
+    credentials: Option<&(String, String)>,
+) -> Fallible<Authorization> {
+    let (scheme, params) = parse_challenge(challenge)?;
+
+    if scheme.eq_ignore_ascii_case("basic") {
+        let (username, password) =
+            credentials.ok_or_else(|| format_err!("registry requires credentials"))?;
+        return Ok(Authorization::Basic(username.clone(), password.clone()));
+    }
+    ensure!(
+        scheme.eq_ignore_ascii_case("bearer"),
+        "unsupported authentication scheme '{}'",
+        scheme
+    );
+
+    let realm = params
+        .get("realm")
+        .ok_or_else(|| format_err!("no realm in challenge '{}'", challenge))?;
+    let mut url = url::Url::parse(realm).context(format!("invalid realm '{}'", realm))?;
+    {
+        let mut query = url.query_pairs_mut();
+        if let Some(service) = params.get("service") {
+            query.append_pair("service", service);
+        }
+        query.append_pair(
+            "scope",
+            params.get("scope").map(String::as_str).unwrap_or(scope),
+        );
+    }
+    let request = client.get(url);
+    let request = match credentials {
+        Some((username, password)) => request.basic_auth(username, Some(password)),
+        None => request,
+    };
+
+    #[derive(Deserialize)]
+    struct Token {
+        token: Option<String>,
+        access_token: Option<String>,
+    }
+    let token: Token = request
+        .send()
+        .await?
+        .error_for_status()
+        .context(format!("requesting a token from {}", realm))?
+        .json()
+        .await?;
+    token
+        .token
+        .or(token.access_token)
+        .map(Authorization::Bearer)
+        .ok_or_else(|| format_err!("no token in the authentication response from {}", realm))
+}
+
+/// Parse a `WWW-Authenticate` challenge into its scheme and parameters.
+pub fn parse_challenge(challenge: &str) -> Fallible<(String, HashMap<String, String>)> {
+    let challenge = challenge.trim();
+    let (scheme, rest) = challenge.split_once(' ').unwrap_or((challenge, ""));
+    ensure!(!scheme.is_empty(), "empty challenge");
+
+    let mut params = HashMap::new();
+    let mut chars = rest.chars().peekable();
+    loop {
+        while chars.peek().is_some_and(|c| *c == ',' || c.is_whitespace()) {
+            chars.next();
+        }
+        let name: String = chars.by_ref().take_while(|c| *c != '=').collect();
+        if name.is_empty() {
+            break;
+        }
+        let value: String = if chars.peek() == Some(&'"') {
+            chars.next();
+            let mut value = String::new();
+            while let Some(c) = chars.next() {
+                match c {
+                    '"' => break,
+                    '\\' => value.extend(chars.next()),
+                    c => value.push(c),
+                }
+            }
+            value
+        } else {
+            chars.by_ref().take_while(|c| *c != ',').collect()
+        };
+        params.insert(name.trim().to_lowercase(), value.trim().to_string());
+    }
+
+    Ok((scheme.to_string(), params))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_challenges() {
+        let (scheme, params) = parse_challenge(
+            r#"Bearer realm="https://auth.example.com/token",service="registry.example.com",scope="repository:ocp/release:pull""#,
+        )
+        .unwrap();
+        assert_eq!(scheme, "Bearer");
+        assert_eq!(params["realm"], "https://auth.example.com/token");
+        assert_eq!(params["service"], "registry.example.com");
+        assert_eq!(params["scope"], "repository:ocp/release:pull");
+
+        let (scheme, params) = parse_challenge(r#"Basic realm=Registry, charset="UTF-8""#).unwrap();
+        assert_eq!(scheme, "Basic");
+        assert_eq!(params["realm"], "Registry");
+        assert_eq!(params["charset"], "UTF-8");
+
+        assert!(parse_challenge("").is_err());
+    }
+}
@@ -0,0 +1,265 @@
+//! Registry credentials from docker config files.
+//!
+//! Credentials are read from a `config.json` or `.dockerconfigjson` file, as
+//! mounted from a Kubernetes pull secret, picking the `auths` entry of the
+//! registry host. Files are read again before every scrape, so that rotated
+//! secrets are picked up without restarting.
+
+use crate as cincinnati;
+
+use self::cincinnati::plugins::prelude_plugin_impl::*;
+
+use super::ecr::EcrAuth;
+use log::{info, warn};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Username and password for a registry.
+pub type UserPass = (Option<String>, Option<String>);
+
+#[derive(Debug, Deserialize)]
+struct DockerConfig {
+    #[serde(default)]
+    auths: HashMap<String, AuthEntry>,
+}
+
+#[derive(Deserialize)]
+struct AuthEntry {
+    #[serde(default)]
+    auth: Option<String>,
+    #[serde(default)]
+    username: Option<String>,
+    #[serde(default)]
+    password: Option<String>,
+}
+
+impl std::fmt::Debug for AuthEntry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("AuthEntry")
+    }
+}
+
+impl AuthEntry {
+    /// Return the credentials of the entry, preferring the encoded `auth` field.
+    fn credentials(&self) -> Fallible<UserPass> {
+        if let Some(auth) = self.auth.as_deref().filter(|auth| !auth.is_empty()) {
+            let decoded = String::from_utf8(base64::decode(auth).context("invalid base64")?)
+                .context("invalid UTF-8")?;
+            let (username, password) = decoded
+                .split_once(':')
+                .ok_or_else(|| format_err!("auth is not in the 'username:password' format"))?;
+            return Ok((Some(username.to_string()), Some(password.to_string())));
+        }
+
+        Ok((self.username.clone(), self.password.clone()))
+    }
+}
+
+/// Normalize a registry host or an `auths` key to its host and port.
+///
+/// Keys written by `docker login` may carry a scheme and a path, and Docker Hub
+/// is known under several names.
+fn normalize_host(key: &str) -> String {
+    let key = key
+        .strip_prefix("https://")
+        .or_else(|| key.strip_prefix("http://"))
+        .unwrap_or(key);
+    let host = key.split('/').next().unwrap_or_default().to_lowercase();
+
+    match host.as_str() {
+        "index.docker.io" | "registry-1.docker.io" | "registry.hub.docker.com" => {
+            "docker.io".to_string()
+        }
+        _ => host,
+    }
+}
+
+/// Return the credentials of `registry_host` in a docker config.
+///
+/// An exact `auths` key takes precedence, otherwise keys are matched on their
+/// normalized host and port.
+pub fn lookup(config: &[u8], registry_host: &str) -> Fallible<UserPass> {
+    let config: DockerConfig = serde_json::from_slice(config).context("invalid docker config")?;
+
+    let host = normalize_host(registry_host);
+    let entry = config.auths.get(registry_host).or_else(|| {
+        config
+            .auths
+            .iter()
+            .filter(|(key, _)| normalize_host(key) == host)
+            .min_by_key(|(key, _)| key.as_str())
+            .map(|(_, entry)| entry)
+    });
+
+    match entry {
+        Some(entry) => entry
+            .credentials()
+            .context(format!("invalid credentials for {}", registry_host)),
+        None => bail!("no credentials for {}", registry_host),
+    }
+}
+
+/// Credentials of a registry, read from a docker config file.
+pub struct CredentialsFile {
+    path: PathBuf,
+    registry_host: String,
+    /// Last content of the file, and the credentials read from it.
+    loaded: Mutex<(Option<Vec<u8>>, UserPass)>,
+}
+
+impl CredentialsFile {
+    /// Create the credentials of `registry_host`, read on first use.
+    pub fn new(path: &Path, registry_host: &str) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            registry_host: registry_host.to_string(),
+            loaded: Mutex::new((None, (None, None))),
+        }
+    }
+
+    /// Return the current credentials, reading the file again if it changed.
+    ///
+    /// If the file can't be read or has no valid entry for the registry, the
+    /// last valid credentials are kept, and access is unauthenticated if
+    /// there are none.
+    pub fn get(&self) -> UserPass {
+        let mut loaded = self.loaded.lock().expect("credentials lock poisoned");
+
+        match std::fs::read(&self.path) {
+            Ok(content) if loaded.0.as_ref() == Some(&content) => {}
+            Ok(content) => {
+                match lookup(&content, &self.registry_host) {
+                    Ok(credentials) => {
+                        if loaded.0.is_some() {
+                            info!(
+                                "Reloaded registry credentials for {} from {:?}",
+                                self.registry_host, self.path
+                            );
+                        }
+                        loaded.1 = credentials;
+                    }
+                    Err(e) => warn!(
+                        "Error reading registry credentials for {} from {:?}, keeping the previous ones: {:#}",
+                        self.registry_host, self.path, e
+                    ),
+                }
+                loaded.0 = Some(content);
+            }
+            Err(e) => warn!(
+                "Error reading registry credentials for {} from {:?}, keeping the previous ones: {}",
+                self.registry_host, self.path, e
+            ),
+        }
+
+        loaded.1.clone()
+    }
+}
+
+/// Credentials of a registry.
+pub enum Credentials {
+    /// Fixed username and password.
+    Static(UserPass),
+    /// Credentials from a docker config file, reloaded when it changes.
+    File(CredentialsFile),
+    /// ECR tokens, refreshed before they expire.
+    Ecr(Box<EcrAuth>),
+}
+
+impl std::fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Credentials::Static(_) => f.write_str("Static"),
+            Credentials::File(file) => write!(f, "File({:?})", file.path),
+            Credentials::Ecr(ecr) => write!(f, "Ecr({:?})", ecr),
+        }
+    }
+}
+
+impl Credentials {
+    /// Return the current username and password.
+    pub async fn get(&self) -> Fallible<UserPass> {
+        match self {
+            Credentials::Static(credentials) => Ok(credentials.clone()),
+            Credentials::File(file) => Ok(file.get()),
+            Credentials::Ecr(ecr) => ecr.get().await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn auth(username: &str, password: &str) -> String {
+        base64::encode(format!("{}:{}", username, password))
+    }
+
+    fn userpass(username: &str, password: &str) -> UserPass {
+        (Some(username.to_string()), Some(password.to_string()))
+    }
+
+    #[test]
+    fn lookup_by_host() -> Fallible<()> {
+        let config = serde_json::json!({
+            "auths": {
+                "https://quay.io/v1/": { "auth": auth("quay", "secret") },
+                "registry.example.com:5000": { "username": "local", "password": "pass" },
+                "https://index.docker.io/v1/": { "auth": auth("hub", "token") },
+                "broken.example.com": { "auth": "bm90LWEtcGFpcg==" },
+            }
+        })
+        .to_string();
+
+        assert_eq!(
+            lookup(config.as_bytes(), "quay.io")?,
+            userpass("quay", "secret")
+        );
+        assert_eq!(
+            lookup(config.as_bytes(), "registry.example.com:5000")?,
+            userpass("local", "pass")
+        );
+        assert_eq!(
+            lookup(config.as_bytes(), "registry-1.docker.io")?,
+            userpass("hub", "token")
+        );
+        assert!(lookup(config.as_bytes(), "registry.example.com").is_err());
+        assert!(lookup(config.as_bytes(), "broken.example.com").is_err());
+        assert!(lookup(b"{", "quay.io").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn reload_on_change() -> Fallible<()> {
+        let tmpdir = tempfile::tempdir()?;
+        let path = tmpdir.path().join(".dockerconfigjson");
+        let write = |username: &str, password: &str| {
+            std::fs::write(
+                &path,
+                serde_json::json!({
+                    "auths": { "quay.io": { "auth": auth(username, password) } }
+                })
+                .to_string(),
+            )
+        };
+
+        let credentials = CredentialsFile::new(&path, "quay.io");
+        assert_eq!(credentials.get(), (None, None));
+
+        write("robot", "first")?;
+        assert_eq!(credentials.get(), userpass("robot", "first"));
+
+        // A rotated secret is picked up on the next use.
+        write("robot", "second")?;
+        assert_eq!(credentials.get(), userpass("robot", "second"));
+
+        // Invalid or missing files keep the last valid credentials.
+        std::fs::write(&path, "{")?;
+        assert_eq!(credentials.get(), userpass("robot", "second"));
+        std::fs::remove_file(&path)?;
+        assert_eq!(credentials.get(), userpass("robot", "second"));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,171 @@
+//! Restriction of a scrape to some of the tags and architectures of a repository.
+//!
+//! Tags not matching the filter are dropped from the tag list, before any of
+//! their manifests is fetched. Releases of other architectures are left out
+//! once their manifest is known, before their metadata is looked up, which is
+//! the expensive part of a scrape.
+
+use commons::prelude_errors::*;
+use regex::Regex;
+use semver::{Version, VersionReq};
+
+pub use crate::plugins::internal::arch_filter::MULTI_ARCH;
+
+/// Tags and architectures to scrape, all of them if unset.
+#[derive(Clone, Debug, Default)]
+pub struct ScrapeFilter {
+    /// Pattern matching whole tags.
+    tag_pattern: Option<Regex>,
+    /// Range of the versions at the start of the tags.
+    version_range: Option<VersionReq>,
+    /// Architectures of the releases.
+    architectures: Vec<String>,
+}
+
+impl ScrapeFilter {
+    pub fn try_new(
+        tag_pattern: Option<&str>,
+        version_range: Option<&str>,
+        architectures: &[String],
+    ) -> Fallible<Self> {
+        let tag_pattern = tag_pattern
+            .map(|pattern| {
+                Regex::new(&format!("^(?:{})$", pattern))
+                    .context(format!("invalid tag_pattern '{}'", pattern))
+            })
+            .transpose()?;
+        let version_range = version_range
+            .map(|range| {
+                VersionReq::parse(range).context(format!("invalid tag_version_range '{}'", range))
+            })
+            .transpose()?;
+        ensure!(
+            architectures.iter().all(|arch| !arch.is_empty()),
+            "empty architecture"
+        );
+
+        Ok(Self {
+            tag_pattern,
+            version_range,
+            architectures: architectures.to_vec(),
+        })
+    }
+
+    /// Whether the tag is to be scraped.
+    ///
+    /// With a version range, tags not starting with a version are left out.
+    pub fn matches_tag(&self, tag: &str) -> bool {
+        if let Some(pattern) = &self.tag_pattern {
+            if !pattern.is_match(tag) {
+                return false;
+            }
+        }
+        match &self.version_range {
+            Some(range) => tag_version(tag).is_some_and(|version| range.matches(&version)),
+            None => true,
+        }
+    }
+
+    /// Whether releases of architecture `arch` are to be scraped.
+    pub fn matches_arch(&self, arch: &str) -> bool {
+        self.architectures.is_empty() || self.architectures.iter().any(|wanted| wanted == arch)
+    }
+
+    /// Whether a release of architecture `arch` is to be scraped, where
+    /// `index_archs` are the architectures of the images of an image index.
+    ///
+    /// Image indexes are scraped if `multi` or any of their architectures is listed.
+    pub fn matches_release(&self, arch: &str, index_archs: &[String]) -> bool {
+        self.matches_arch(arch)
+            || (arch == MULTI_ARCH && index_archs.iter().any(|arch| self.matches_arch(arch)))
+    }
+
+    /// Whether the metadata of an image index covering `index_archs` may be
+    /// read from its image of architecture `arch`.
+    ///
+    /// Images of the listed architectures are preferred, other images are only
+    /// used if the index covers none of them.
+    pub fn matches_index_image(&self, index_archs: &[String], arch: &str) -> bool {
+        self.matches_arch(arch) || !index_archs.iter().any(|arch| self.matches_arch(arch))
+    }
+}
+
+/// The version at the start of a tag, e.g. 4.16.3 for `4.16.3-x86_64`.
+///
+/// Pre-release parts are ignored, so that the release candidates of a version
+/// fall in the ranges of the version itself.
+fn tag_version(tag: &str) -> Option<Version> {
+    let mut numbers = tag
+        .trim_start_matches('v')
+        .splitn(3, '.')
+        .enumerate()
+        .map(|(i, part)| {
+            // The patch number ends the version.
+            let part = if i == 2 {
+                let end = part
+                    .find(|c: char| !c.is_ascii_digit())
+                    .unwrap_or(part.len());
+                &part[..end]
+            } else {
+                part
+            };
+            part.parse::<u64>().ok()
+        });
+    let major = numbers.next()??;
+    let minor = numbers.next()??;
+    let patch = numbers.next()??;
+    Some(Version::new(major, minor, patch))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn filter_tags() -> Fallible<()> {
+        assert!(ScrapeFilter::default().matches_tag("anything"));
+
+        let filter = ScrapeFilter::try_new(Some(".*-x86_64"), Some("4.16.*"), &[])?;
+        assert!(filter.matches_tag("4.16.3-x86_64"));
+        assert!(filter.matches_tag("4.16.0-rc.1-x86_64"));
+        assert!(!filter.matches_tag("4.16.3-aarch64"));
+        assert!(!filter.matches_tag("4.15.20-x86_64"));
+        assert!(!filter.matches_tag("latest-x86_64"));
+
+        let filter = ScrapeFilter::try_new(None, Some(">=4.15.2, <4.16.0"), &[])?;
+        assert!(filter.matches_tag("v4.15.2"));
+        assert!(!filter.matches_tag("4.15.1-multi"));
+        assert!(!filter.matches_tag("4.16"));
+
+        assert!(ScrapeFilter::try_new(Some("("), None, &[]).is_err());
+        assert!(ScrapeFilter::try_new(None, Some("not a range"), &[]).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn filter_architectures() -> Fallible<()> {
+        let index = vec!["amd64".to_string(), "arm64".to_string()];
+        let all = ScrapeFilter::default();
+        assert!(all.matches_arch("s390x"));
+        assert!(all.matches_arch(MULTI_ARCH));
+        assert!(all.matches_index_image(&index, "arm64"));
+
+        let filter = ScrapeFilter::try_new(None, None, &["amd64".to_string()])?;
+        assert!(filter.matches_arch("amd64"));
+        assert!(!filter.matches_arch("arm64"));
+        assert!(!filter.matches_arch(MULTI_ARCH));
+        assert!(filter.matches_release(MULTI_ARCH, &index));
+        assert!(!filter.matches_release(MULTI_ARCH, &["s390x".to_string()]));
+        assert!(!filter.matches_release("arm64", &[]));
+
+        let multi =
+            ScrapeFilter::try_new(None, None, &[MULTI_ARCH.to_string(), "amd64".to_string()])?;
+        assert!(multi.matches_arch(MULTI_ARCH));
+        assert!(multi.matches_index_image(&index, "amd64"));
+        assert!(!multi.matches_index_image(&index, "arm64"));
+        assert!(multi.matches_index_image(&["s390x".to_string()], "s390x"));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,171 @@
+//! Requests to the repository API of a registry.
+//!
+//! The requests are made with the outbound clients, which identify the
+//! deployment and present the client certificate, if any, and are authorized
+//! as challenged by the registry.
+
+use crate as cincinnati;
+
+use self::cincinnati::plugins::prelude_plugin_impl::*;
+
+use super::auth::{self, Authorization};
+use super::{retry, throttle, ClientTls, Registry};
+use reqwest::header::ACCEPT;
+use reqwest::StatusCode;
+use tokio::sync::Mutex;
+use url::Url;
+
+/// Client of the repository API of a registry.
+#[derive(CustomDebug)]
+pub struct RepositoryClient {
+    repo: String,
+    /// Scope of the authorization requested from the registry.
+    scope: String,
+    /// Registry host, as known to its throttle.
+    host: String,
+    /// Base URL of the repository API, `<registry>/v2/<repo>/`.
+    base: Url,
+    #[debug(skip)]
+    client: reqwest::Client,
+    #[debug(skip)]
+    credentials: Option<(String, String)>,
+    #[debug(skip)]
+    authorization: Mutex<Option<Authorization>>,
+}
+
+impl RepositoryClient {
+    /// Create a client for the repository `repo` of `registry`.
+    pub fn try_new(
+        registry: &Registry,
+        repo: &str,
+        username: Option<&str>,
+        password: Option<&str>,
+        tls: &ClientTls,
+    ) -> Fallible<Self> {
+        let base =
+            Url::parse(&format!("{}/v2/{}/", registry.base_url(), repo)).context(format!(
+                "invalid repository {}/{}",
+                registry.host_port_string(),
+                repo
+            ))?;
+        let scope = format!("repository:{}:pull", repo);
+        Self::with_base(registry, repo, base, scope, username, password, tls)
+    }
+
+    /// Create a client for the catalog of `registry`, whose base URL is `<registry>/v2/`.
+    pub fn catalog(
+        registry: &Registry,
+        username: Option<&str>,
+        password: Option<&str>,
+        tls: &ClientTls,
+    ) -> Fallible<Self> {
+        let base = Url::parse(&format!("{}/v2/", registry.base_url()))
+            .context(format!("invalid registry {}", registry.host_port_string()))?;
+        let scope = "registry:catalog:*".to_string();
+        Self::with_base(registry, "_catalog", base, scope, username, password, tls)
+    }
+
+    fn with_base(
+        registry: &Registry,
+        repo: &str,
+        base: Url,
+        scope: String,
+        username: Option<&str>,
+        password: Option<&str>,
+        tls: &ClientTls,
+    ) -> Fallible<Self> {
+        let client = tls
+            .configure(commons::outbound::client_builder())
+            .build()
+            .context("Building reqwest client")?;
+        let credentials = match (username, password) {
+            (Some(username), Some(password)) => Some((username.to_string(), password.to_string())),
+            _ => None,
+        };
+
+        Ok(Self {
+            repo: repo.to_string(),
+            scope,
+            host: registry.host_port_string(),
+            base,
+            client,
+            credentials,
+            authorization: Mutex::new(None),
+        })
+    }
+
+    /// The repository name.
+    pub fn repo(&self) -> &str {
+        &self.repo
+    }
+
+    /// Base URL of the repository API, `<registry>/v2/<repo>/`.
+    pub fn base(&self) -> &Url {
+        &self.base
+    }
+
+    /// The underlying HTTP client.
+    pub fn http_client(&self) -> &reqwest::Client {
+        &self.client
+    }
+
+    /// Fetch `url`, authorizing as challenged by the registry.
+    ///
+    /// Returns `None` if the registry answers 404 Not Found, and fails with a
+    /// `retry::HttpStatusError` on other unsuccessful statuses.
+    pub async fn get(&self, url: Url, accept: &[&str]) -> Fallible<Option<reqwest::Response>> {
+        let authorization = self.authorization.lock().await.clone();
+        let mut response = self.send(&url, accept, authorization.as_ref()).await?;
+
+        if response.status() == StatusCode::UNAUTHORIZED {
+            let challenge = auth::challenge(&response)?;
+            let authorization = auth::authorize(
+                &self.client,
+                &challenge,
+                &self.scope,
+                self.credentials.as_ref(),
+            )
+            .await?;
+            *self.authorization.lock().await = Some(authorization.clone());
+            response = self.send(&url, accept, Some(&authorization)).await?;
+        }
+
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        if !response.status().is_success() {
+            let error = retry::HttpStatusError::of(&response);
+            return Err(Error::new(error).context(format!("fetching {}", url)));
+        }
+        Ok(Some(response))
+    }
+
+    /// Fetch the blob `digest` of the repository.
+    pub async fn get_blob(&self, digest: &str) -> Fallible<Vec<u8>> {
+        let url = self.base.join(&format!("blobs/{}", digest))?;
+        let response = self
+            .get(url, &["*/*"])
+            .await?
+            .ok_or_else(|| format_err!("blob {} not found in {}", digest, self.repo))?;
+        Ok(response.bytes().await?.to_vec())
+    }
+
+    async fn send(
+        &self,
+        url: &Url,
+        accept: &[&str],
+        authorization: Option<&Authorization>,
+    ) -> Fallible<reqwest::Response> {
+        let request = self
+            .client
+            .get(url.clone())
+            .header(ACCEPT, accept.join(", "));
+        let request = match authorization {
+            Some(authorization) => authorization.apply(request),
+            None => request,
+        };
+        let response = request.send().await?;
+        throttle::observe_response(&self.host, response.headers());
+        Ok(response)
+    }
+}
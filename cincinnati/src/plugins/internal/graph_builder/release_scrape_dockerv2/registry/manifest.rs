@@ -0,0 +1,215 @@
+//! Image manifests, as served by the repository API of a registry.
+//!
+//! Docker schema 2 manifests and manifest lists, OCI image manifests and
+//! indexes, and signed Docker schema 1 manifests are supported. A manifest is
+//! either a single image, whose architecture is read from its configuration,
+//! or a list of images, one per platform.
+
+use super::http::RepositoryClient;
+use commons::prelude_errors::*;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+/// Media types of the manifests, by decreasing preference.
+pub static ACCEPTED_TYPES: &[&str] = &[
+    "application/vnd.docker.distribution.manifest.v2+json",
+    "application/vnd.oci.image.manifest.v1+json",
+    "application/vnd.docker.distribution.manifest.v1+prettyjws; q=0.8",
+    "application/vnd.docker.distribution.manifest.list.v2+json; q=0.5",
+    "application/vnd.oci.image.index.v1+json; q=0.5",
+];
+
+/// Header carrying the digest of a manifest.
+static DOCKER_CONTENT_DIGEST: &str = "Docker-Content-Digest";
+
+/// An image manifest.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Manifest {
+    /// A single image.
+    Image {
+        /// Architecture of the image.
+        architecture: String,
+        /// Digests of the layers, from the base layer up.
+        layers: Vec<String>,
+    },
+    /// A manifest list or image index.
+    List {
+        /// Digest and architecture of each image of the list.
+        images: Vec<(String, String)>,
+    },
+}
+
+impl Manifest {
+    /// Returns the architectures of the images of the manifest.
+    pub fn architectures(&self) -> Vec<String> {
+        match self {
+            Manifest::Image { architecture, .. } => vec![architecture.clone()],
+            Manifest::List { images } => images
+                .iter()
+                .map(|(_, architecture)| architecture.clone())
+                .collect(),
+        }
+    }
+
+    /// Returns the digests of the layers of an image, or of the images of a list.
+    ///
+    /// Fails if the image doesn't have the given `architecture`.
+    pub fn layers_digests(&self, architecture: Option<&str>) -> Fallible<Vec<String>> {
+        match (self, architecture) {
+            (Manifest::Image { architecture, .. }, Some(wanted)) if architecture != wanted => {
+                bail!(
+                    "architecture mismatch: wanted {}, found {}",
+                    wanted,
+                    architecture
+                )
+            }
+            (Manifest::Image { layers, .. }, _) => Ok(layers.clone()),
+            (Manifest::List { images }, _) => {
+                Ok(images.iter().map(|(digest, _)| digest.clone()).collect())
+            }
+        }
+    }
+}
+
+/// Descriptor of a blob or manifest referenced by a manifest.
+#[derive(Debug, Deserialize)]
+struct Descriptor {
+    digest: String,
+    #[serde(default)]
+    platform: Option<Platform>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Platform {
+    architecture: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct FsLayer {
+    #[serde(rename = "blobSum")]
+    blob_sum: String,
+}
+
+/// Any of the supported manifests, as served.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RawManifest {
+    schema_version: u32,
+    #[serde(default)]
+    config: Option<Descriptor>,
+    #[serde(default)]
+    layers: Vec<Descriptor>,
+    #[serde(default)]
+    manifests: Option<Vec<Descriptor>>,
+    /// Architecture of a schema 1 manifest.
+    #[serde(default)]
+    architecture: Option<String>,
+    /// Layers of a schema 1 manifest, from the top-most layer down.
+    #[serde(default)]
+    fs_layers: Vec<FsLayer>,
+}
+
+/// Configuration of an image, only as far as needed.
+#[derive(Debug, Deserialize)]
+struct ImageConfig {
+    architecture: String,
+}
+
+/// Fetch the manifest `reference`, a tag or a digest, along with its digest.
+///
+/// The architecture of a single image is read from its configuration blob.
+pub async fn fetch(client: &RepositoryClient, reference: &str) -> Fallible<(Manifest, String)> {
+    let url = client.base().join(&format!("manifests/{}", reference))?;
+    let response = client
+        .get(url, ACCEPTED_TYPES)
+        .await?
+        .ok_or_else(|| format_err!("manifest {}:{} not found", client.repo(), reference))?;
+    let digest = response
+        .headers()
+        .get(DOCKER_CONTENT_DIGEST)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string);
+    let body = response.bytes().await?;
+    let digest = digest.unwrap_or_else(|| format!("sha256:{:x}", Sha256::digest(&body)));
+
+    let raw: RawManifest = serde_json::from_slice(&body).context(format!(
+        "parsing manifest {}:{}",
+        client.repo(),
+        reference
+    ))?;
+    let manifest = match raw {
+        RawManifest {
+            schema_version: 1,
+            architecture,
+            fs_layers,
+            ..
+        } => Manifest::Image {
+            architecture: architecture
+                .ok_or_else(|| format_err!("no architecture in manifest {}", digest))?,
+            layers: fs_layers
+                .into_iter()
+                .rev()
+                .map(|layer| layer.blob_sum)
+                .collect(),
+        },
+        RawManifest {
+            manifests: Some(manifests),
+            ..
+        } => Manifest::List {
+            images: manifests
+                .into_iter()
+                .filter_map(|image| {
+                    let platform = image.platform?;
+                    Some((image.digest, platform.architecture))
+                })
+                .collect(),
+        },
+        RawManifest {
+            config: Some(config),
+            layers,
+            ..
+        } => {
+            let config: ImageConfig =
+                serde_json::from_slice(&client.get_blob(&config.digest).await?)
+                    .context(format!("parsing the configuration of manifest {}", digest))?;
+            Manifest::Image {
+                architecture: config.architecture,
+                layers: layers.into_iter().map(|layer| layer.digest).collect(),
+            }
+        }
+        _ => bail!("unsupported manifest {}", digest),
+    };
+
+    Ok((manifest, digest))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn layers_digests() {
+        let image = Manifest::Image {
+            architecture: "amd64".to_string(),
+            layers: vec!["sha256:base".to_string(), "sha256:top".to_string()],
+        };
+        assert_eq!(image.architectures(), vec!["amd64"]);
+        assert_eq!(
+            image.layers_digests(Some("amd64")).unwrap(),
+            vec!["sha256:base", "sha256:top"]
+        );
+        assert!(image.layers_digests(Some("arm64")).is_err());
+
+        let list = Manifest::List {
+            images: vec![
+                ("sha256:amd64".to_string(), "amd64".to_string()),
+                ("sha256:arm64".to_string(), "arm64".to_string()),
+            ],
+        };
+        assert_eq!(list.architectures(), vec!["amd64", "arm64"]);
+        assert_eq!(
+            list.layers_digests(Some("multi")).unwrap(),
+            vec!["sha256:amd64", "sha256:arm64"]
+        );
+    }
+}
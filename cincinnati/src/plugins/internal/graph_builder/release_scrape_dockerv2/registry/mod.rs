@@ -14,28 +14,153 @@
 
 use crate as cincinnati;
 
+use self::cincinnati::plugins::internal::arch_filter;
 use self::cincinnati::plugins::internal::graph_builder::release::Metadata;
 use self::cincinnati::plugins::internal::graph_builder::release::MetadataKind;
 use self::cincinnati::plugins::prelude_plugin_impl::*;
 
 use flate2::read::GzDecoder;
-use futures::lock::Mutex as FuturesMutex;
 use futures::prelude::*;
 use futures::TryStreamExt;
-use log::{debug, error, trace, warn};
+use log::{debug, trace, warn};
+use opentelemetry::trace::get_active_span;
+use opentelemetry::Key;
+use prometheus::{histogram_opts, HistogramVec};
 use semver::Version;
 use serde::Deserialize;
 use serde_json;
-use std::fs::File;
+use std::collections::HashMap;
 use std::io::Read;
 use std::iter::Iterator;
 use std::path::{Path, PathBuf};
 use std::string::String;
 use std::sync::Arc;
+use std::time::Instant;
 use tar::Archive;
 
-use dkregistry::mediatypes::MediaTypes::{ManifestList, ManifestV2S1Signed, ManifestV2S2};
-use dkregistry::v2::Client;
+pub mod artifacts;
+pub mod auth;
+pub mod credentials;
+pub mod ecr;
+pub mod filter;
+pub mod http;
+pub mod manifest;
+pub mod persistence;
+pub mod retry;
+pub mod throttle;
+
+lazy_static::lazy_static! {
+    /// Buckets from 50ms to about 50s, registries being anywhere from local to overloaded.
+    static ref REQUEST_BUCKETS: Vec<f64> = prometheus::exponential_buckets(0.05, 2.0, 11).unwrap();
+    static ref MANIFEST_FETCH_DURATION: HistogramVec = HistogramVec::new(
+        histogram_opts!(
+            "graph_upstream_manifest_fetch_duration_seconds",
+            "Duration of the manifest fetches from upstream registries",
+            REQUEST_BUCKETS.clone()
+        ),
+        &["registry", "outcome"]
+    )
+    .unwrap();
+    static ref TAG_LIST_PAGE_DURATION: HistogramVec = HistogramVec::new(
+        histogram_opts!(
+            "graph_upstream_tag_list_page_duration_seconds",
+            "Duration of the fetches of single pages of the tag lists from upstream registries",
+            REQUEST_BUCKETS.clone()
+        ),
+        &["registry", "outcome"]
+    )
+    .unwrap();
+    static ref LABEL_FETCH_DURATION: HistogramVec = HistogramVec::new(
+        histogram_opts!(
+            "graph_upstream_label_fetch_duration_seconds",
+            "Duration of the fetches of the layers holding the release metadata from upstream registries",
+            REQUEST_BUCKETS.clone()
+        ),
+        &["registry", "outcome"]
+    )
+    .unwrap();
+}
+
+/// Outcome label of successful registry requests.
+static OUTCOME_SUCCESS: &str = "success";
+
+/// Outcome label of failed registry requests.
+static OUTCOME_ERROR: &str = "error";
+
+/// Register relevant metrics to a prometheus registry.
+pub fn register_metrics(registry: &prometheus::Registry) -> Fallible<()> {
+    registry.register(Box::new(MANIFEST_FETCH_DURATION.clone()))?;
+    registry.register(Box::new(TAG_LIST_PAGE_DURATION.clone()))?;
+    registry.register(Box::new(LABEL_FETCH_DURATION.clone()))?;
+    retry::register_metrics(registry)?;
+    throttle::register_metrics(registry)?;
+    Ok(())
+}
+
+fn outcome<T, E>(result: &Result<T, E>) -> &'static str {
+    match result {
+        Ok(_) => OUTCOME_SUCCESS,
+        Err(_) => OUTCOME_ERROR,
+    }
+}
+
+/// Await a registry request, observing its duration by registry host and outcome.
+///
+/// Requests aren't traced individually, as a scrape makes thousands of them:
+/// the span of the scrape is annotated with the number of fetched tags instead.
+async fn timed<T, F>(histogram: &HistogramVec, host: &str, request: F) -> Fallible<T>
+where
+    F: Future<Output = Fallible<T>>,
+{
+    let started = Instant::now();
+    let result = request.await;
+    histogram
+        .with_label_values(&[host, outcome(&result)])
+        .observe(started.elapsed().as_secs_f64());
+    result
+}
+
+/// Policy of the requests of a single scrape of a registry.
+struct Requests<'a> {
+    host: String,
+    retry: &'a retry::RetryPolicy,
+    /// Adaptive throttle of the registry, if enabled.
+    throttle: Option<Arc<throttle::Throttle>>,
+}
+
+impl Requests<'_> {
+    /// Run a request, within the throttle and with retries.
+    async fn run<T, F, Fut>(&self, mut request: F) -> Fallible<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Fallible<T>>,
+    {
+        let throttle = self.throttle.as_ref();
+        self.retry
+            .run(&self.host, move || {
+                let request = request();
+                async move {
+                    let throttle = match throttle {
+                        Some(throttle) => throttle,
+                        None => return request.await,
+                    };
+                    throttle.acquire().await;
+                    let result = request.await;
+                    match &result {
+                        Ok(_) => throttle.succeeded(),
+                        Err(e)
+                            if retry::RetryClass::of(e) == Some(retry::RetryClass::Throttled) =>
+                        {
+                            throttle.throttled()
+                        }
+                        Err(_) => {}
+                    }
+                    result
+                }
+            })
+            .await
+    }
+}
 
 /// Module for the release cache
 pub mod cache {
@@ -63,6 +188,25 @@ pub mod cache {
     pub fn new() -> Cache {
         Arc::new(CacheAsync::new(CacheSync::new()))
     }
+
+    /// The tag to manifest-reference mapping observed during the last scrape
+    pub type TagDigests = Arc<CacheAsync<HashMap<String, String>>>;
+
+    /// Instantiate a new, empty tag to manifest-reference mapping
+    pub fn new_tag_digests() -> TagDigests {
+        Arc::new(CacheAsync::new(HashMap::new()))
+    }
+}
+
+/// Statistics about a single `fetch_releases` run.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct FetchStats {
+    /// Tags whose manifests had to be fetched.
+    pub fetched_tags: usize,
+
+    /// Tags which still point to the same manifest as in the previous run and were
+    /// served from the cache.
+    pub skipped_tags: usize,
 }
 
 #[derive(Debug, Clone, PartialEq, Default, Deserialize)]
@@ -183,6 +327,15 @@ impl Registry {
         )
     }
 
+    /// Base URL of the registry API, e.g. `https://quay.io`.
+    pub fn base_url(&self) -> String {
+        let scheme = if self.insecure { "http" } else { "https" };
+        match self.port {
+            Some(port) => format!("{}://{}:{}", scheme, self.host, port),
+            None => format!("{}://{}", scheme, self.host),
+        }
+    }
+
     fn insecure_scheme(scheme: &str) -> Fallible<bool> {
         match scheme {
             "https" => Ok(false),
@@ -197,86 +350,115 @@ pub fn read_credentials(
     registry_host: &str,
 ) -> Result<(Option<String>, Option<String>), Error> {
     credentials_path.map_or(Ok((None, None)), |path| {
-        let file = File::open(&path).context(format!("could not open '{:?}'", path))?;
+        let config = std::fs::read(path).context(format!("could not read '{:?}'", path))?;
 
-        dkregistry::get_credentials(file, registry_host).map_err(|e| format_err!("{}", e))
+        credentials::lookup(&config, registry_host)
     })
 }
 
-pub async fn new_registry_client(
-    registry: &Registry,
-    repo: &str,
-    username: Option<&str>,
-    password: Option<&str>,
-) -> Result<dkregistry::v2::Client, Error> {
-    let client = {
-        let client_builder = dkregistry::v2::Client::configure()
-            .registry(&registry.host_port_string())
-            .insecure_registry(registry.insecure)
-            .accepted_types(Some(vec![
-                (ManifestV2S2, None),
-                (ManifestV2S1Signed, Some(0.8)),
-                (ManifestList, Some(0.5)),
-            ]));
-        let scope = format!("repository:{}:pull", &repo);
-
-        if username.is_some() && password.is_some() {
-            client_builder
-                .username(username.map(ToString::to_string))
-                .password(password.map(ToString::to_string))
-                .build()?
-                .authenticate(&[&scope])
-                .await?
-        } else {
-            let client = client_builder.build()?;
+/// Read the PEM-encoded CA certificates to trust in addition to the system ones.
+pub fn read_ca_bundle(ca_bundle_path: &Path) -> Fallible<Vec<reqwest::Certificate>> {
+    let pem =
+        std::fs::read(ca_bundle_path).context(format!("could not read '{:?}'", ca_bundle_path))?;
+    let certificates = rustls_pemfile::certs(&mut pem.as_slice())
+        .context(format!("invalid PEM in '{:?}'", ca_bundle_path))?
+        .iter()
+        .map(|der| {
+            reqwest::Certificate::from_der(der)
+                .context(format!("invalid certificate in '{:?}'", ca_bundle_path))
+        })
+        .collect::<Fallible<Vec<_>>>()?;
+    ensure!(
+        !certificates.is_empty(),
+        "no certificate found in '{:?}'",
+        ca_bundle_path
+    );
+
+    Ok(certificates)
+}
 
-            if client
-                .is_v2_supported_and_authorized()
-                .await
-                .map(|(_, authorized)| authorized)?
-            {
-                client
-            } else {
-                debug!("registry not authorized, attempting anonymous authorization");
-                client.authenticate(&[&scope]).await?
-            }
-        }
-    };
+/// Read a PEM-encoded client certificate and its PKCS#8 key.
+pub fn read_identity(cert_path: &Path, key_path: &Path) -> Fallible<reqwest::Identity> {
+    let cert = std::fs::read(cert_path)
+        .context(format!("Reading client certificate from {:?}", cert_path))?;
+    let key = std::fs::read(key_path).context(format!("Reading client key from {:?}", key_path))?;
 
-    Ok(client)
+    reqwest::Identity::from_pkcs8_pem(&cert, &key).context(format!(
+        "Loading client certificate from {:?} and {:?}",
+        cert_path, key_path
+    ))
 }
 
-// get the architecture, manifestref and layers_digest for images with tag/digest
+/// TLS settings of the outbound clients of a registry.
+#[derive(Clone, Debug, Default)]
+pub struct ClientTls {
+    /// CA certificates to trust in addition to the system ones.
+    pub root_certificates: Vec<reqwest::Certificate>,
+    /// Client certificate presented to the registry.
+    pub identity: Option<reqwest::Identity>,
+}
+
+impl ClientTls {
+    /// Read the CA bundle and the client certificate, if set.
+    pub fn try_new(
+        ca_bundle_path: Option<&Path>,
+        client_cert_path: Option<&Path>,
+        client_key_path: Option<&Path>,
+    ) -> Fallible<Self> {
+        let root_certificates = match ca_bundle_path {
+            Some(ca_bundle_path) => read_ca_bundle(ca_bundle_path)?,
+            None => vec![],
+        };
+        let identity = match (client_cert_path, client_key_path) {
+            (Some(cert_path), Some(key_path)) => Some(read_identity(cert_path, key_path)?),
+            (None, None) => None,
+            _ => bail!("client_cert_path and client_key_path must be set together"),
+        };
+
+        Ok(Self {
+            root_certificates,
+            identity,
+        })
+    }
+
+    /// Apply the settings to a client.
+    pub fn configure(&self, builder: reqwest::ClientBuilder) -> reqwest::ClientBuilder {
+        let builder = self
+            .root_certificates
+            .iter()
+            .cloned()
+            .fold(builder, reqwest::ClientBuilder::add_root_certificate);
+        match &self.identity {
+            Some(identity) => builder.identity(identity.clone()),
+            None => builder,
+        }
+    }
+}
+
+// get the architecture, the architectures of the image index, manifestref and layers_digest for images with tag/digest
 async fn get_manifest_layers(
+    registry: &Registry,
+    requests: &Requests<'_>,
     tag: String,
-    repo: &str,
-    registry_client: &Client,
-) -> Result<(Option<String>, String, Vec<String>), Error> {
+    client: &http::RepositoryClient,
+) -> Result<(Option<String>, Vec<String>, String, Vec<String>), Error> {
     trace!("[{}] Fetching release", tag);
     let (tag, manifest, manifestref) =
-        get_manifest_and_ref(tag, repo.to_owned(), &registry_client).await?;
-
-    // Try to read the architecture from the manifest
-    let arch = match manifest.architectures() {
-        Ok(archs) => {
-            if archs.len() == 1 {
-                archs.first().map(std::string::ToString::to_string)
-            } else {
-                Some(String::from("multi"))
-            }
-        }
-        Err(e) => {
-            error!(
-                "could not get architecture from manifest for tag {}: {}",
-                tag, e
-            );
-            None
+        get_manifest_and_ref(registry, requests, tag, client).await?;
+
+    // Read the architecture from the manifest
+    let (arch, index_archs) = match &manifest {
+        manifest::Manifest::Image { architecture, .. } => (Some(architecture.clone()), vec![]),
+        manifest::Manifest::List { .. } => {
+            let mut index_archs = manifest.architectures();
+            index_archs.sort();
+            index_archs.dedup();
+            (Some(filter::MULTI_ARCH.to_string()), index_archs)
         }
     };
 
     let layers_digests = manifest
         .layers_digests(arch.as_deref())
-        .map_err(|e| format_err!("{}", e))
         .context(format!(
             "[{}] could not get layers_digests from manifest",
             tag
@@ -286,95 +468,263 @@ async fn get_manifest_layers(
         .rev()
         .collect();
 
-    Ok((arch, manifestref, layers_digests))
+    Ok((arch, index_archs, manifestref, layers_digests))
 }
 
 /// Fetches a vector of all release metadata from the given repository, hosted on the given
 /// registry.
+///
+/// Newly fetched metadata is also written to `store`, if given.
+///
+/// Tags which resolve to the same manifest reference as recorded in `tag_digests`
+/// by the previous run are served from the cache without looking up their metadata.
+/// On success `tag_digests` is replaced with the mapping observed in this run.
+///
+/// The release metadata is looked up in the sources configured by `metadata_lookup`,
+/// in order. Releases without published metadata fall back to the payload layers
+/// if they are listed, and are left out otherwise.
+///
+/// Only the tags and architectures matching `filter` are scraped. The scrape
+/// fails as soon as more than `max_tags` of them are listed, before their
+/// releases are fetched, so that a flooded repository can't exhaust memory.
+///
+/// Authentication, manifest and layer requests are retried according to `retry`.
+/// If `throttle_min_rate` is set, they go through the adaptive throttle of the
+/// registry, which slows down to no less than that rate once the registry
+/// answers 429 Too Many Requests.
+#[allow(clippy::too_many_arguments)]
 pub async fn fetch_releases(
     registry: &Registry,
     repo: &str,
     username: Option<&str>,
     password: Option<&str>,
+    tls: &ClientTls,
     cache: cache::Cache,
+    tag_digests: cache::TagDigests,
+    store: Option<Arc<dyn persistence::MetadataStore>>,
     manifestref_key: &str,
+    metadata_lookup: &artifacts::MetadataLookup,
+    filter: &filter::ScrapeFilter,
     concurrency: usize,
-) -> Result<Vec<cincinnati::plugins::internal::graph_builder::release::Release>, Error> {
-    let registry_client = new_registry_client(registry, repo, username, password).await?;
-
-    let registry_client_get_tags = registry_client.clone();
-    let tags = Box::pin(get_tags(repo, &registry_client_get_tags).await);
-
-    let releases = {
-        let estimated_releases = match tags.size_hint() {
-            (_, Some(upper)) => upper,
-            (lower, None) => lower,
-        };
-        Arc::new(FuturesMutex::new(Vec::with_capacity(estimated_releases)))
+    max_tags: usize,
+    retry: &retry::RetryPolicy,
+    throttle_min_rate: Option<f64>,
+) -> Result<
+    (
+        Vec<cincinnati::plugins::internal::graph_builder::release::Release>,
+        FetchStats,
+    ),
+    Error,
+> {
+    let host = registry.host_port_string();
+    let requests = Requests {
+        throttle: throttle_min_rate.map(|min_rate| throttle::Throttle::shared(&host, min_rate)),
+        host,
+        retry,
     };
-
-    tags.try_for_each_concurrent(concurrency, |tag| {
-        let registry_client = registry_client.clone();
-        let cache = cache.clone();
-        let releases = releases.clone();
-
-        async move {
-            let (arch, manifestref, mut layers_digests) =
-                get_manifest_layers(tag.to_owned(), &repo, &registry_client).await?;
-
-            // if the image is multi arch, we will have to get one image from the manifest list and
-            // use its metadata, because manifest lists are just collections of manifests and don't
-            // have their own layers with metadata files.
-            if arch.as_ref().unwrap() == "multi" {
-                let digest = layers_digests
-                    .first()
-                    .map(std::string::ToString::to_string)
-                    .expect(
-                        format!("no images referenced in ManifestList ref:{}", manifestref)
-                            .as_str(),
-                    );
-                // TODO: destructured assignments are unstable in current rust, after updating rust
-                // change this to (_,_,layers_digests) and remove separate assignment from below.
-                let (_ml_arch, _ml_manifestref, ml_layers_digests) =
-                    get_manifest_layers(digest, &repo, &registry_client).await?;
-                layers_digests = ml_layers_digests;
-            }
-
-            let release = match lookup_or_fetch(
-                layers_digests,
-                registry_client.to_owned(),
-                registry.to_owned(),
-                repo.to_owned(),
-                tag.to_owned(),
+    let artifact_client = artifacts::ArtifactClient::try_new(
+        metadata_lookup,
+        registry,
+        repo,
+        username,
+        password,
+        tls,
+    )?;
+
+    let client = http::RepositoryClient::try_new(registry, repo, username, password, tls)?;
+    let mut listed_tags: usize = 0;
+    let tags = Box::pin(
+        get_tags(&requests, &client)?
+            .try_filter(|tag| future::ready(filter.matches_tag(tag)))
+            .and_then(move |tag| {
+                listed_tags += 1;
+                future::ready(if listed_tags > max_tags {
+                    Err(format_err!(
+                        "{} has more than max_tags ({}) tags to scrape",
+                        repo,
+                        max_tags
+                    ))
+                } else {
+                    Ok(tag)
+                })
+            }),
+    );
+
+    let estimated_releases = match tags.size_hint() {
+        (_, Some(upper)) => upper,
+        (lower, None) => lower,
+    }
+    .min(max_tags);
+    let previous_tag_digests = tag_digests.read().await.clone();
+    let store = store.as_deref();
+
+    // At most `concurrency` tags are in flight at any time, their outcomes
+    // are collected as they complete.
+    let outcomes = tags
+        .map_ok(|tag| {
+            let previous_manifestref = previous_tag_digests.get(&tag).cloned();
+            fetch_tag(
+                registry,
+                &requests,
+                repo,
+                &client,
+                artifact_client.as_ref(),
+                tag,
+                previous_manifestref,
                 &cache,
-                manifestref.clone(),
-                manifestref_key.to_string(),
-                arch,
+                store,
+                manifestref_key,
+                filter,
             )
-            .await?
-            {
-                Some(release) => release,
-                None => {
-                    // Reminder: this means the layer_digests point to layers
-                    // without any release and we've cached this before
-                    return Ok(());
+        })
+        .try_buffer_unordered(concurrency);
+    futures::pin_mut!(outcomes);
+
+    let mut releases = Vec::with_capacity(estimated_releases);
+    let mut current_tag_digests = HashMap::with_capacity(estimated_releases);
+    let mut stats = FetchStats::default();
+    while let Some(outcome) = outcomes.try_next().await? {
+        if outcome.skipped {
+            stats.skipped_tags += 1;
+        } else {
+            stats.fetched_tags += 1;
+        }
+        current_tag_digests.insert(outcome.tag, outcome.manifestref);
+        releases.extend(outcome.release);
+    }
+    *tag_digests.write().await = current_tag_digests;
+
+    get_active_span(|span| {
+        span.set_attribute(Key::new("fetched_tags").i64(stats.fetched_tags as i64));
+        span.set_attribute(Key::new("skipped_tags").i64(stats.skipped_tags as i64));
+    });
+    debug!(
+        "fetched {} tags, skipped {} unchanged tags",
+        stats.fetched_tags, stats.skipped_tags
+    );
+
+    Ok((releases, stats))
+}
+
+/// Outcome of the processing of a single tag.
+struct TagOutcome {
+    tag: String,
+    manifestref: String,
+    release: Option<cincinnati::plugins::internal::graph_builder::release::Release>,
+    /// Whether the tag was unchanged and served from the cache.
+    skipped: bool,
+}
+
+/// Fetch the release of a single tag.
+///
+/// If the tag still points to `previous_manifestref`, the release is served
+/// from the cache without looking up its metadata. The manifest reference is
+/// the one of the manifest fetched as usual, so that it's the digest of the
+/// manifest list or image index for multi-arch releases, and not of the
+/// single-arch manifest a registry may resolve the tag to otherwise.
+///
+/// Releases of architectures not matching `filter` are left out without
+/// looking up their metadata, and without caching them.
+#[allow(clippy::too_many_arguments)]
+async fn fetch_tag(
+    registry: &Registry,
+    requests: &Requests<'_>,
+    repo: &str,
+    client: &http::RepositoryClient,
+    artifact_client: Option<&artifacts::ArtifactClient>,
+    tag: String,
+    previous_manifestref: Option<String>,
+    cache: &cache::Cache,
+    store: Option<&dyn persistence::MetadataStore>,
+    manifestref_key: &str,
+    filter: &filter::ScrapeFilter,
+) -> Fallible<TagOutcome> {
+    let (arch, index_archs, manifestref, mut layers_digests) =
+        get_manifest_layers(registry, requests, tag.to_owned(), client).await?;
+
+    if previous_manifestref.as_deref() == Some(manifestref.as_str()) {
+        let cached = { cache.read().await.get(&manifestref).cloned() };
+        if let Some(cached) = cached {
+            trace!("[{}] Tag unchanged since last scrape, skipping", &tag);
+            let release = cached.map(|metadata| {
+                cincinnati::plugins::internal::graph_builder::release::Release {
+                    source: format_release_source(registry, repo, &manifestref),
+                    metadata,
                 }
-            };
+            });
+            return Ok(TagOutcome {
+                tag,
+                manifestref,
+                release,
+                skipped: true,
+            });
+        }
+    }
 
-            releases.lock().await.push(release);
+    if !arch
+        .as_deref()
+        .is_none_or(|arch| filter.matches_release(arch, &index_archs))
+    {
+        trace!("[{}] Architecture {:?} filtered out, skipping", &tag, arch);
+        return Ok(TagOutcome {
+            tag,
+            manifestref,
+            release: None,
+            skipped: false,
+        });
+    }
 
-            Ok(())
+    // if the image is multi arch, we will have to get one image from the manifest list and
+    // use its metadata, because manifest lists are just collections of manifests and don't
+    // have their own layers with metadata files.
+    if arch.as_deref() == Some(filter::MULTI_ARCH) {
+        ensure!(
+            !layers_digests.is_empty(),
+            "no images referenced in ManifestList ref:{}",
+            manifestref
+        );
+        let mut image_layers_digests = None;
+        for digest in &layers_digests {
+            let (ml_arch, _ml_index_archs, _ml_manifestref, ml_layers_digests) =
+                get_manifest_layers(registry, requests, digest.clone(), client).await?;
+            if ml_arch.is_none_or(|ml_arch| filter.matches_index_image(&index_archs, &ml_arch)) {
+                image_layers_digests = Some(ml_layers_digests);
+                break;
+            }
         }
-    })
-    .await?;
+        layers_digests = image_layers_digests.ok_or_else(|| {
+            format_err!(
+                "no image of a wanted architecture in ManifestList ref:{}",
+                manifestref
+            )
+        })?;
+    }
 
-    let releases = Arc::<
-        FuturesMutex<Vec<cincinnati::plugins::internal::graph_builder::release::Release>>,
-    >::try_unwrap(releases)
-    .map_err(|_| format_err!("Unwrapping the shared Releases vector. This must not fail."))?
-    .into_inner();
+    // `None` means the layer_digests point to layers without any release,
+    // which is cached as well.
+    let release = lookup_or_fetch(
+        layers_digests,
+        client,
+        artifact_client,
+        registry.to_owned(),
+        requests,
+        repo.to_owned(),
+        tag.to_owned(),
+        cache,
+        store,
+        manifestref.clone(),
+        manifestref_key.to_string(),
+        arch,
+        index_archs,
+    )
+    .await?;
 
-    Ok(releases)
+    Ok(TagOutcome {
+        tag,
+        manifestref,
+        release,
+        skipped: false,
+    })
 }
 
 /// Look up release metadata for a specific tag, and cache it.
@@ -389,18 +739,22 @@ pub async fn fetch_releases(
 #[allow(clippy::too_many_arguments)]
 async fn lookup_or_fetch(
     layer_digests: Vec<String>,
-    registry_client: dkregistry::v2::Client,
+    client: &http::RepositoryClient,
+    artifact_client: Option<&artifacts::ArtifactClient>,
     registry: Registry,
+    requests: &Requests<'_>,
     repo: String,
     tag: String,
     cache: &cache::Cache,
+    store: Option<&dyn persistence::MetadataStore>,
     manifestref: String,
     manifestref_key: String,
     arch: Option<String>,
+    index_archs: Vec<String>,
 ) -> Fallible<Option<cincinnati::plugins::internal::graph_builder::release::Release>> {
     let cached_metadata = {
         // Nest the guard in a scope to guarantee that the cache isn't locked when trying to write to it later
-        cache.read().await.get(&manifestref).map(Clone::clone)
+        cache.read().await.get(&manifestref).cloned()
     };
 
     let metadata = match cached_metadata {
@@ -422,15 +776,45 @@ async fn lookup_or_fetch(
             });
             cache.write().await.insert(manifestref.clone(), placeholder);
 
-            let metadata = find_first_release_metadata(
-                layer_digests,
-                registry_client,
-                repo.clone(),
-                tag.clone(),
-            )
-            .await
-            .context("failed to find first release")?
-            .map(|mut metadata| {
+            let published = match artifact_client {
+                Some(artifact_client) => {
+                    find_published_metadata(
+                        &registry,
+                        requests,
+                        artifact_client,
+                        &tag,
+                        &manifestref,
+                    )
+                    .await
+                }
+                None => None,
+            };
+            let metadata = match published {
+                Some(metadata) => Some(metadata),
+                None if !artifact_client
+                    .is_none_or(artifacts::ArtifactClient::falls_back_to_layers) =>
+                {
+                    debug!(
+                        "[{}] No published release metadata, leaving the release out",
+                        tag
+                    );
+                    // Not cached, the metadata may still be published later.
+                    cache.write().await.remove(&manifestref);
+                    return Ok(None);
+                }
+                None => find_first_release_metadata(
+                    &registry,
+                    requests,
+                    layer_digests,
+                    client,
+                    repo.clone(),
+                    tag.clone(),
+                )
+                .await
+                .context("failed to find first release")?,
+            };
+            let arch_keys = arch_filter::ArchKeys::with_prefix(arch_filter::DEFAULT_KEY_FILTER);
+            let metadata = metadata.map(|mut metadata| {
                 // Attach the manifestref this release was found in for further processing
                 metadata
                     .metadata
@@ -442,10 +826,15 @@ async fn lookup_or_fetch(
                     metadata.version.build = vec![semver::Identifier::AlphaNumeric(arch.clone())];
 
                     // Attach the architecture for later processing
+                    metadata.metadata.insert(arch_keys.arch.clone(), arch);
+                };
+
+                // Record the architectures covered by a multi-arch image index
+                if !index_archs.is_empty() {
                     metadata
                         .metadata
-                        .insert("io.openshift.upgrades.graph.release.arch".to_owned(), arch);
-                };
+                        .insert(arch_keys.architectures, index_archs.join(","));
+                }
 
                 metadata
             });
@@ -456,6 +845,15 @@ async fn lookup_or_fetch(
                 .await
                 .insert(manifestref.clone(), metadata.clone());
 
+            if let Some(store) = store {
+                if let Err(e) = store.store(&manifestref, &metadata) {
+                    warn!(
+                        "[{}] Failed to persist release metadata for {}: {}",
+                        &tag, &manifestref, e
+                    );
+                }
+            }
+
             metadata
         }
     };
@@ -466,39 +864,152 @@ async fn lookup_or_fetch(
     }))
 }
 
-// Get a stream of tags
-async fn get_tags<'a, 'b: 'a>(
-    repo: &'b str,
-    registry_client: &'b dkregistry::v2::Client,
-) -> impl TryStreamExt<Item = Fallible<String>> + 'a {
-    registry_client
-        // According to https://docs.docker.com/registry/spec/api/#listing-image-tags
-        // the tags should be ordered lexically but they aren't
-        .get_tags(repo, Some(20))
-        .map_err(|e| format_err!("{}", e))
+/// List the repositories of a registry, through its catalog API.
+pub async fn list_repositories(
+    registry: &Registry,
+    username: Option<&str>,
+    password: Option<&str>,
+    tls: &ClientTls,
+) -> Fallible<Vec<String>> {
+    let client = http::RepositoryClient::catalog(registry, username, password, tls)?;
+    let mut url = client.base().join("_catalog")?;
+    url.query_pairs_mut()
+        .append_pair("n", &CATALOG_PAGE_SIZE.to_string());
+
+    let mut repositories = vec![];
+    let mut next = Some(url);
+    while let Some(url) = next {
+        let response = client
+            .get(url.clone(), &["application/json"])
+            .await?
+            .ok_or_else(|| format_err!("{} has no catalog", registry.host_port_string()))?;
+        next =
+            next_page(&response, &url).context("invalid link to the next page of the catalog")?;
+        let catalog: Catalog = response.json().await.context("parsing the catalog")?;
+        repositories.extend(catalog.repositories.unwrap_or_default());
+    }
+    Ok(repositories)
+}
+
+/// Number of repositories requested per page of the catalog.
+static CATALOG_PAGE_SIZE: usize = 100;
+
+/// A page of the catalog.
+#[derive(Deserialize)]
+struct Catalog {
+    #[serde(default)]
+    repositories: Option<Vec<String>>,
+}
+
+/// Number of tags requested per page of the tag listing.
+static TAGS_PAGE_SIZE: usize = 20;
+
+/// A page of the tag listing.
+#[derive(Deserialize)]
+struct TagList {
+    #[serde(default)]
+    tags: Option<Vec<String>>,
+}
+
+/// Returns the target of the `rel="next"` link of a `Link` header, if any.
+fn next_page_link(header: &str) -> Option<&str> {
+    header.split(',').find_map(|link| {
+        let (target, params) = link.split_once(';')?;
+        params
+            .split(';')
+            .map(|param| param.trim().replace('"', ""))
+            .any(|param| param == "rel=next")
+            .then(|| target.trim().trim_start_matches('<').trim_end_matches('>'))
+    })
+}
+
+/// Returns the URL of the page following the one at `url`, if any.
+fn next_page(response: &reqwest::Response, url: &url::Url) -> Fallible<Option<url::Url>> {
+    Ok(response
+        .headers()
+        .get(reqwest::header::LINK)
+        .and_then(|value| value.to_str().ok())
+        .and_then(next_page_link)
+        .map(|link| url.join(link))
+        .transpose()?)
+}
+
+/// Fetch the page of the tag listing at `url`, returning its tags and the URL of the next page.
+async fn get_tags_page(
+    client: &http::RepositoryClient,
+    url: url::Url,
+) -> Fallible<(Vec<String>, Option<url::Url>)> {
+    let response = client
+        .get(url.clone(), &["application/json"])
+        .await?
+        .ok_or_else(|| format_err!("repository {} not found", client.repo()))?;
+    let next = next_page(&response, &url).context("invalid link to the next page of tags")?;
+    let list: TagList = response.json().await.context("parsing the tag list")?;
+    Ok((list.tags.unwrap_or_default(), next))
+}
+
+/// Stream of the tags of the repository of `client`, fetched page by page.
+///
+/// Each page request is throttled and retried according to `requests`, and
+/// timed and traced on its own.
+fn get_tags<'a>(
+    requests: &'a Requests<'a>,
+    client: &'a http::RepositoryClient,
+) -> Fallible<impl Stream<Item = Fallible<String>> + 'a> {
+    // According to https://docs.docker.com/registry/spec/api/#listing-image-tags
+    // the tags should be ordered lexically but they aren't
+    let mut first = client.base().join("tags/list")?;
+    first
+        .query_pairs_mut()
+        .append_pair("n", &TAGS_PAGE_SIZE.to_string());
+
+    let pages = stream::try_unfold(Some(first), move |url| async move {
+        let url = match url {
+            Some(url) => url,
+            None => return Ok::<_, Error>(None),
+        };
+        let (tags, next) = requests
+            .run(|| {
+                timed(
+                    &TAG_LIST_PAGE_DURATION,
+                    &requests.host,
+                    get_tags_page(client, url.clone()),
+                )
+            })
+            .await?;
+        Ok(Some((
+            stream::iter(tags.into_iter().map(Ok::<_, Error>)),
+            next,
+        )))
+    });
+    Ok(pages.try_flatten())
 }
 
 async fn get_manifest_and_ref(
+    registry: &Registry,
+    requests: &Requests<'_>,
     tag: String,
-    repo: String,
-    registry_client: &dkregistry::v2::Client,
-) -> Result<(String, dkregistry::v2::manifest::Manifest, String), Error> {
-    trace!("[{}] Processing {}", &tag, &repo);
-    let (manifest, manifestref) = registry_client
-        .get_manifest_and_ref(&repo, &tag)
-        .map_err(|e| {
-            format_err!(
-                "fetching manifest and manifestref for {}:{}: {}",
-                &repo,
-                &tag,
-                e
+    client: &http::RepositoryClient,
+) -> Result<(String, manifest::Manifest, String), Error> {
+    trace!("[{}] Processing {}", &tag, client.repo());
+    let host = registry.host_port_string();
+    let (manifest, manifestref) = requests
+        .run(|| {
+            timed(
+                &MANIFEST_FETCH_DURATION,
+                &host,
+                manifest::fetch(client, &tag).map_err(|e| {
+                    let message = format!(
+                        "fetching manifest and manifestref for {}:{}",
+                        client.repo(),
+                        &tag
+                    );
+                    e.context(message)
+                }),
             )
         })
         .await?;
 
-    let manifestref =
-        manifestref.ok_or_else(|| format_err!("no manifestref found for {}:{}", &repo, &tag))?;
-
     Ok((tag, manifest, manifestref))
 }
 
@@ -506,24 +1017,74 @@ fn format_release_source(registry: &Registry, repo: &str, manifestref: &str) ->
     format!("{}/{}@{}", registry.host_port_string(), repo, manifestref)
 }
 
+/// Look up the release metadata published alongside the image `manifestref`,
+/// trying the configured sources in order.
+///
+/// Failures are logged and treated as missing metadata, so that the caller
+/// falls back to the next source.
+async fn find_published_metadata(
+    registry: &Registry,
+    requests: &Requests<'_>,
+    artifact_client: &artifacts::ArtifactClient,
+    tag: &str,
+    manifestref: &str,
+) -> Option<Metadata> {
+    let host = registry.host_port_string();
+    for source in artifact_client.sources() {
+        let result = requests
+            .run(|| {
+                timed(
+                    &LABEL_FETCH_DURATION,
+                    &host,
+                    artifact_client.find_metadata(source, manifestref),
+                )
+            })
+            .await;
+
+        match result {
+            Ok(Some(metadata)) => {
+                trace!("[{}] Found release metadata in {:?}", tag, source);
+                return Some(metadata);
+            }
+            Ok(None) => {
+                debug!("[{}] No release metadata in {:?}", tag, source);
+            }
+            Err(e) => {
+                warn!(
+                    "[{}] Could not look up release metadata in {:?}, falling back to the next source: {:#}",
+                    tag, source, e
+                );
+            }
+        }
+    }
+    None
+}
+
 async fn find_first_release_metadata(
+    registry: &Registry,
+    requests: &Requests<'_>,
     layer_digests: Vec<String>,
-    registry_client: dkregistry::v2::Client,
+    client: &http::RepositoryClient,
     repo: String,
     tag: String,
 ) -> Fallible<Option<Metadata>> {
+    let host = registry.host_port_string();
     for layer_digest in layer_digests {
         trace!("[{}] Downloading layer {}", &tag, &layer_digest);
         let (repo, tag) = (repo.clone(), tag.clone());
 
-        let blob = registry_client
-            .get_blob(&repo, &layer_digest)
-            .map_err(|e| {
-                format_err!(
-                    "fetching blob for repo {} with layer_digest {}: {}",
-                    &repo,
-                    &layer_digest,
-                    e
+        let blob = requests
+            .run(|| {
+                timed(
+                    &LABEL_FETCH_DURATION,
+                    &host,
+                    client.get_blob(&layer_digest).map_err(|e| {
+                        let message = format!(
+                            "fetching blob for repo {} with layer_digest {}",
+                            &repo, &layer_digest
+                        );
+                        e.context(message)
+                    }),
                 )
             })
             .await?;
@@ -665,4 +1226,137 @@ mod tests {
             assert_eq!(input, registry.host_port_string());
         }
     }
+
+    #[test]
+    fn read_ca_bundle_certificates() -> Fallible<()> {
+        static CERT: &str = "-----BEGIN CERTIFICATE-----
+MIIBfzCCASWgAwIBAgIUERsGxBAy1CMXp7EIyoDP2gxob2QwCgYIKoZIzj0EAwIw
+FDESMBAGA1UEAwwJbG9jYWxob3N0MCAXDTI2MTAxNTA0MDgxNloYDzIxMjYwOTIx
+MDQwODE2WjAUMRIwEAYDVQQDDAlsb2NhbGhvc3QwWTATBgcqhkjOPQIBBggqhkjO
+PQMBBwNCAASmSIuY8ZOh+z8oZSQcb13W0cIJzS+t+LA5esMlazX11OMYrpAs/Ong
+NRw8ViFO+p5lRBhK3CRJ8Rcx+88KigxBo1MwUTAdBgNVHQ4EFgQUTA8d7WG5wSG1
+rPSMsyYnpIac5wEwHwYDVR0jBBgwFoAUTA8d7WG5wSG1rPSMsyYnpIac5wEwDwYD
+VR0TAQH/BAUwAwEB/zAKBggqhkjOPQQDAgNIADBFAiAy4BLv3U3/+MVb/bPR55pX
+z0oTCVDEkFYHR1siepf8DQIhAOi9+wBW7a9neBMHOugKm2eSwKsCW01jGrbdZHeD
+DrpD
+-----END CERTIFICATE-----
+";
+
+        let tmpdir = tempfile::tempdir()?;
+        let path = tmpdir.path().join("ca.pem");
+
+        std::fs::write(&path, format!("{}\n# comment\n{}", CERT, CERT))?;
+        assert_eq!(read_ca_bundle(&path)?.len(), 2);
+
+        std::fs::write(&path, "")?;
+        assert!(read_ca_bundle(&path).is_err());
+        assert!(read_ca_bundle(&tmpdir.path().join("missing.pem")).is_err());
+
+        std::fs::write(&path, CERT)?;
+        let tls = ClientTls::try_new(Some(&path), None, None)?;
+        assert_eq!(tls.root_certificates.len(), 1);
+        assert!(tls.identity.is_none());
+        assert!(ClientTls::try_new(None, Some(&path), None).is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn request_durations() -> Fallible<()> {
+        let host = "durations.example.com:5000";
+        let count = |histogram: &HistogramVec, outcome: &str| {
+            histogram
+                .with_label_values(&[host, outcome])
+                .get_sample_count()
+        };
+
+        assert_eq!(
+            timed(&MANIFEST_FETCH_DURATION, host, async { Ok(42) }).await?,
+            42
+        );
+        assert!(timed(&MANIFEST_FETCH_DURATION, host, async {
+            Err::<(), _>(format_err!("unauthorized"))
+        })
+        .await
+        .is_err());
+        assert_eq!(count(&MANIFEST_FETCH_DURATION, OUTCOME_SUCCESS), 1);
+        assert_eq!(count(&MANIFEST_FETCH_DURATION, OUTCOME_ERROR), 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn next_page_links() {
+        assert_eq!(
+            next_page_link(r#"</v2/ocp/release/tags/list?n=20&last=4.11.1>; rel="next""#),
+            Some("/v2/ocp/release/tags/list?n=20&last=4.11.1")
+        );
+        assert_eq!(
+            next_page_link(
+                r#"<https://example.com/prev>; rel="prev", <https://example.com/next>; rel=next"#
+            ),
+            Some("https://example.com/next")
+        );
+        assert_eq!(next_page_link(r#"</prev>; rel="prev""#), None);
+    }
+
+    #[tokio::test]
+    async fn list_tags_by_page() -> Fallible<()> {
+        let registry = Registry::try_from_str(&mockito::server_url())?;
+        let retry = retry::RetryPolicy {
+            max_attempts: 2,
+            base_delay_secs: 0.0,
+            max_delay_secs: 0.0,
+            ..Default::default()
+        };
+        let requests = Requests {
+            host: registry.host_port_string(),
+            retry: &retry,
+            throttle: None,
+        };
+
+        let _first = mockito::mock("GET", "/v2/paged/release/tags/list?n=20")
+            .with_header(
+                "link",
+                r#"</v2/paged/release/tags/list?n=20&last=4.11.0>; rel="next""#,
+            )
+            .with_body(r#"{"name":"paged/release","tags":["4.11.0"]}"#)
+            .create();
+        let _second = mockito::mock("GET", "/v2/paged/release/tags/list?n=20&last=4.11.0")
+            .with_body(r#"{"name":"paged/release","tags":["4.11.1"]}"#)
+            .create();
+        let client = http::RepositoryClient::try_new(
+            &registry,
+            "paged/release",
+            None,
+            None,
+            &Default::default(),
+        )?;
+        let tags: Vec<String> = get_tags(&requests, &client)?.try_collect().await?;
+        assert_eq!(tags, vec!["4.11.0", "4.11.1"]);
+        assert_eq!(
+            TAG_LIST_PAGE_DURATION
+                .with_label_values(&[&requests.host, OUTCOME_SUCCESS])
+                .get_sample_count(),
+            2
+        );
+
+        // Failed pages are retried.
+        let failing = mockito::mock("GET", "/v2/failing/release/tags/list?n=20")
+            .with_status(503)
+            .expect(2)
+            .create();
+        let client = http::RepositoryClient::try_new(
+            &registry,
+            "failing/release",
+            None,
+            None,
+            &Default::default(),
+        )?;
+        let result: Fallible<Vec<String>> = get_tags(&requests, &client)?.try_collect().await;
+        assert!(result.is_err());
+        failing.assert();
+
+        Ok(())
+    }
 }
@@ -0,0 +1,265 @@
+//! Persistent storage for the release metadata cache.
+//!
+//! Entries are keyed by manifest digest, which makes them immutable and safe to
+//! reuse across process restarts. The on-disk store is only a backing layer for
+//! the in-memory `cache::Cache`: it is loaded once on startup, written to
+//! whenever a new entry has been fetched from the registry, and flushed once
+//! per scrape. Once full, the least recently used entries are evicted, entries
+//! being used when stored and when a scrape finds their manifest again.
+
+use super::cache;
+use crate as cincinnati;
+
+use self::cincinnati::plugins::internal::graph_builder::release::Metadata;
+use commons::prelude_errors::*;
+use log::{debug, trace, warn};
+use std::collections::HashSet;
+use std::path::Path;
+
+/// A persistence backend for cached release metadata.
+pub trait MetadataStore: std::fmt::Debug + Send + Sync {
+    /// Return all persisted entries.
+    fn load(&self) -> Fallible<Vec<(String, Option<Metadata>)>>;
+
+    /// Persist a single entry, keyed by its manifest digest.
+    ///
+    /// The entry may only be written to disk by the next `flush`.
+    fn store(&self, manifestref: &str, metadata: &Option<Metadata>) -> Fallible<()>;
+
+    /// Mark the entries of the given manifest digests as recently used.
+    fn touch(&self, manifestrefs: &HashSet<String>) -> Fallible<()>;
+
+    /// Write the pending changes to disk.
+    fn flush(&self) -> Fallible<()>;
+}
+
+/// A `MetadataStore` backed by a sled database.
+///
+/// The entries are kept in the default tree. The order of use is kept in two
+/// further trees: `used`, mapping each manifest digest to the sequence number
+/// of its last use, and `lru`, mapping these sequence numbers back to the
+/// digests, in the order of eviction.
+#[derive(Debug)]
+pub struct SledMetadataStore {
+    db: sled::Db,
+    used: sled::Tree,
+    lru: sled::Tree,
+    max_entries: usize,
+}
+
+impl SledMetadataStore {
+    /// Open (or create) a store at the given path, holding at most `max_entries` entries.
+    pub fn open<P: AsRef<Path>>(path: P, max_entries: usize) -> Fallible<Self> {
+        ensure!(max_entries > 0, "cache max entries must be greater than 0");
+
+        // Changes are flushed explicitly once per scrape, hence the periodic
+        // flusher is disabled. It also would keep the database locked for a
+        // while after the store has been dropped.
+        let db = sled::Config::new()
+            .path(path.as_ref())
+            .flush_every_ms(None)
+            .open()
+            .context(format!(
+                "opening metadata cache at {}",
+                path.as_ref().display()
+            ))?;
+        let store = Self {
+            used: db.open_tree("used")?,
+            lru: db.open_tree("lru")?,
+            db,
+            max_entries,
+        };
+
+        // Entries stored before their use was tracked are the first to go.
+        for key in store.db.iter().keys() {
+            let key = key?;
+            if !store.used.contains_key(&key)? {
+                store.mark_used(&key)?;
+            }
+        }
+
+        Ok(store)
+    }
+
+    /// Make `key` the most recently used entry.
+    fn mark_used(&self, key: &[u8]) -> Fallible<()> {
+        let sequence = self.db.generate_id()?.to_be_bytes();
+        if let Some(previous) = self.used.insert(key, &sequence[..])? {
+            self.lru.remove(previous)?;
+        }
+        self.lru.insert(&sequence[..], key)?;
+        Ok(())
+    }
+
+    /// Evict the least recently used entries until there is room for a new one.
+    fn make_room(&self) -> Fallible<()> {
+        while self.db.len() >= self.max_entries {
+            let key = match self.lru.pop_min()? {
+                Some((_, key)) => key,
+                None => match self.db.first()? {
+                    Some((key, _)) => key,
+                    None => break,
+                },
+            };
+            debug!(
+                "evicting {} from persistent metadata cache",
+                String::from_utf8_lossy(&key)
+            );
+            self.used.remove(&key)?;
+            self.db.remove(&key)?;
+        }
+        Ok(())
+    }
+}
+
+impl MetadataStore for SledMetadataStore {
+    fn load(&self) -> Fallible<Vec<(String, Option<Metadata>)>> {
+        let mut entries = Vec::with_capacity(self.db.len());
+
+        for entry in self.db.iter() {
+            let (key, value) = entry?;
+            let manifestref = String::from_utf8(key.to_vec())?;
+            match serde_json::from_slice::<Option<Metadata>>(&value) {
+                Ok(metadata) => entries.push((manifestref, metadata)),
+                Err(e) => {
+                    warn!(
+                        "dropping unreadable cache entry for {}: {}",
+                        &manifestref, e
+                    );
+                    self.db.remove(&key)?;
+                    if let Some(sequence) = self.used.remove(&key)? {
+                        self.lru.remove(sequence)?;
+                    }
+                }
+            }
+        }
+
+        Ok(entries)
+    }
+
+    fn store(&self, manifestref: &str, metadata: &Option<Metadata>) -> Fallible<()> {
+        let value = serde_json::to_vec(metadata)?;
+
+        if !self.db.contains_key(manifestref)? {
+            self.make_room()?;
+        }
+
+        trace!("persisting release metadata for {}", manifestref);
+        self.db.insert(manifestref, value)?;
+        self.mark_used(manifestref.as_bytes())?;
+
+        Ok(())
+    }
+
+    fn touch(&self, manifestrefs: &HashSet<String>) -> Fallible<()> {
+        for manifestref in manifestrefs {
+            if self.db.contains_key(manifestref)? {
+                self.mark_used(manifestref.as_bytes())?;
+            }
+        }
+        Ok(())
+    }
+
+    fn flush(&self) -> Fallible<()> {
+        self.db.flush()?;
+        Ok(())
+    }
+}
+
+/// Populate the in-memory cache with all entries from the given store.
+///
+/// Returns the number of loaded entries.
+pub async fn warm_cache(store: &dyn MetadataStore, cache: &cache::Cache) -> Fallible<usize> {
+    let entries = store.load()?;
+    let count = entries.len();
+
+    let mut cache = cache.write().await;
+    cache.extend(entries);
+
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use self::cincinnati::plugins::internal::graph_builder::release::MetadataKind;
+    use super::*;
+    use commons::testing::init_runtime;
+    use semver::Version;
+
+    fn metadata(version: &str) -> Option<Metadata> {
+        Some(Metadata {
+            kind: MetadataKind::V0,
+            version: Version::parse(version).unwrap(),
+            previous: vec![],
+            next: vec![],
+            metadata: Default::default(),
+        })
+    }
+
+    #[test]
+    fn store_survives_reopen() -> Fallible<()> {
+        let runtime = init_runtime()?;
+        let tmpdir = tempfile::tempdir()?;
+
+        {
+            let store = SledMetadataStore::open(tmpdir.path(), 10)?;
+            store.store("sha256:a", &metadata("1.0.0"))?;
+            store.store("sha256:b", &None)?;
+        }
+
+        let store = SledMetadataStore::open(tmpdir.path(), 10)?;
+        let cache = cache::new();
+        let loaded = runtime.block_on(warm_cache(&store, &cache))?;
+        assert_eq!(loaded, 2);
+
+        let cache = runtime.block_on(cache.read());
+        assert_eq!(cache.get("sha256:a"), Some(&metadata("1.0.0")));
+        assert_eq!(cache.get("sha256:b"), Some(&None));
+
+        Ok(())
+    }
+
+    #[test]
+    fn store_respects_max_entries() -> Fallible<()> {
+        let tmpdir = tempfile::tempdir()?;
+        let store = SledMetadataStore::open(tmpdir.path(), 2)?;
+
+        store.store("sha256:a", &metadata("1.0.0"))?;
+        store.store("sha256:b", &metadata("1.0.1"))?;
+        store.store("sha256:b", &metadata("1.0.1"))?;
+        assert_eq!(store.load()?.len(), 2);
+
+        store.store("sha256:c", &metadata("1.0.2"))?;
+        let keys: Vec<String> = store.load()?.into_iter().map(|(k, _)| k).collect();
+        assert_eq!(keys, vec!["sha256:b".to_string(), "sha256:c".to_string()]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn store_evicts_least_recently_used() -> Fallible<()> {
+        let tmpdir = tempfile::tempdir()?;
+
+        {
+            let store = SledMetadataStore::open(tmpdir.path(), 2)?;
+            store.store("sha256:a", &metadata("1.0.0"))?;
+            store.store("sha256:b", &metadata("1.0.1"))?;
+            store.touch(&vec!["sha256:a".to_string()].into_iter().collect())?;
+            store.flush()?;
+        }
+
+        // The order of use survives a reopen.
+        let store = SledMetadataStore::open(tmpdir.path(), 2)?;
+        store.store("sha256:c", &metadata("1.0.2"))?;
+        let keys: Vec<String> = store.load()?.into_iter().map(|(k, _)| k).collect();
+        assert_eq!(keys, vec!["sha256:a".to_string(), "sha256:c".to_string()]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn store_rejects_zero_entries() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        assert!(SledMetadataStore::open(tmpdir.path(), 0).is_err());
+    }
+}
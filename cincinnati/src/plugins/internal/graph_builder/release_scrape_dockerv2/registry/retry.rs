@@ -0,0 +1,381 @@
+//! Retries of failed registry requests.
+//!
+//! Registries throttle scrapers which hammer them, so failed requests are
+//! retried with an exponential backoff. The delays are randomized, so that the
+//! concurrent fetches of a scrape don't hit the registry again all at once.
+//! A registry asking for a longer delay with `Retry-After` gets it, as far as
+//! the response is known, i.e. for the requests made outside of the registry
+//! client.
+
+use super::throttle::ResponseLimits;
+use commons::prelude_errors::*;
+use log::debug;
+use prometheus::{IntCounterVec, Opts};
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use smart_default::SmartDefault;
+use std::fmt;
+use std::future::Future;
+use std::time::Duration;
+
+/// Default number of attempts of a request, including the first one.
+pub static DEFAULT_MAX_ATTEMPTS: usize = 4;
+
+/// Default delay before the first retry, in seconds.
+pub static DEFAULT_BASE_DELAY_SECS: f64 = 1.0;
+
+/// Default maximum delay between two attempts, in seconds.
+pub static DEFAULT_MAX_DELAY_SECS: f64 = 30.0;
+
+/// Default share of the delay which is randomized.
+pub static DEFAULT_JITTER: f64 = 0.5;
+
+lazy_static::lazy_static! {
+    static ref REQUEST_RETRIES: IntCounterVec = IntCounterVec::new(
+        Opts::new(
+            "graph_upstream_request_retries_total",
+            "Total number of retried requests to upstream registries"
+        ),
+        &["registry", "class"]
+    )
+    .unwrap();
+}
+
+/// Register relevant metrics to a prometheus registry.
+pub(crate) fn register_metrics(registry: &prometheus::Registry) -> Fallible<()> {
+    registry.register(Box::new(REQUEST_RETRIES.clone()))?;
+    Ok(())
+}
+
+/// A registry response with an unsuccessful status.
+#[derive(Debug)]
+pub struct HttpStatusError {
+    pub status: StatusCode,
+    /// Delay asked for by the `Retry-After` header.
+    pub retry_after: Option<Duration>,
+}
+
+impl HttpStatusError {
+    /// Describe the unsuccessful `response`.
+    pub fn of(response: &reqwest::Response) -> Self {
+        Self {
+            status: response.status(),
+            retry_after: ResponseLimits::of(response.headers()).retry_after,
+        }
+    }
+
+    /// Returns the delay asked for by the first response of the error chain, if any.
+    pub fn retry_after(error: &Error) -> Option<Duration> {
+        error
+            .chain()
+            .find_map(|cause| cause.downcast_ref::<HttpStatusError>())
+            .and_then(|error| error.retry_after)
+    }
+}
+
+impl fmt::Display for HttpStatusError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "unexpected HTTP status {}", self.status)
+    }
+}
+
+impl std::error::Error for HttpStatusError {}
+
+/// Class of failures which may be retried.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RetryClass {
+    /// The registry answered 429 Too Many Requests.
+    Throttled,
+    /// The registry answered with a 5xx status.
+    ServerError,
+    /// The request timed out or the connection failed.
+    Timeout,
+}
+
+impl RetryClass {
+    fn as_str(&self) -> &'static str {
+        match self {
+            RetryClass::Throttled => "throttled",
+            RetryClass::ServerError => "server_error",
+            RetryClass::Timeout => "timeout",
+        }
+    }
+
+    fn from_status(status: StatusCode) -> Option<Self> {
+        if status == StatusCode::TOO_MANY_REQUESTS {
+            Some(RetryClass::Throttled)
+        } else if status.is_server_error() {
+            Some(RetryClass::ServerError)
+        } else {
+            None
+        }
+    }
+
+    /// Classify a failed request, `None` meaning that it is not worth retrying.
+    pub fn of(error: &Error) -> Option<Self> {
+        for cause in error.chain() {
+            if let Some(error) = cause.downcast_ref::<HttpStatusError>() {
+                return Self::from_status(error.status);
+            }
+            if let Some(error) = cause.downcast_ref::<reqwest::Error>() {
+                if error.is_timeout() || error.is_connect() {
+                    return Some(RetryClass::Timeout);
+                }
+                return error.status().and_then(Self::from_status);
+            }
+        }
+        None
+    }
+}
+
+/// Retry policy of the registry requests.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, SmartDefault)]
+#[serde(default)]
+pub struct RetryPolicy {
+    /// Number of attempts of a request, including the first one.
+    #[default(DEFAULT_MAX_ATTEMPTS)]
+    pub max_attempts: usize,
+
+    /// Delay before the first retry, doubled on each further retry.
+    #[default(DEFAULT_BASE_DELAY_SECS)]
+    pub base_delay_secs: f64,
+
+    /// Maximum delay between two attempts.
+    #[default(DEFAULT_MAX_DELAY_SECS)]
+    pub max_delay_secs: f64,
+
+    /// Share of the delay which is randomized, from 0 (fixed delays) to 1.
+    #[default(DEFAULT_JITTER)]
+    pub jitter: f64,
+
+    /// Classes of failures which are retried.
+    #[default(vec![RetryClass::Throttled, RetryClass::ServerError, RetryClass::Timeout])]
+    pub retry_on: Vec<RetryClass>,
+}
+
+impl RetryPolicy {
+    /// Validate the policy.
+    pub fn validate(&self) -> Fallible<()> {
+        ensure!(self.max_attempts > 0, "max_attempts must be greater than 0");
+        ensure!(
+            self.base_delay_secs.is_finite() && self.base_delay_secs >= 0.0,
+            "base_delay_secs must not be negative"
+        );
+        ensure!(
+            self.max_delay_secs.is_finite() && self.max_delay_secs >= self.base_delay_secs,
+            "max_delay_secs must not be lower than base_delay_secs"
+        );
+        ensure!(
+            (0.0..=1.0).contains(&self.jitter),
+            "jitter must be between 0 and 1"
+        );
+        Ok(())
+    }
+
+    /// Delay before the given retry, starting at 1.
+    ///
+    /// Up to `jitter` of the exponential delay is cut off at random.
+    fn delay(&self, retry: usize) -> Duration {
+        let exponent = retry.saturating_sub(1).min(63) as i32;
+        let delay = (self.base_delay_secs * 2f64.powi(exponent)).min(self.max_delay_secs);
+        Duration::from_secs_f64(delay * (1.0 - self.jitter * rand::random::<f64>()))
+    }
+
+    /// Run `request` until it succeeds, fails with an error which isn't retried,
+    /// or runs out of attempts.
+    ///
+    /// Retries wait for at least the `Retry-After` delay of the failed response,
+    /// and aren't attempted if it exceeds `max_delay_secs`.
+    ///
+    /// Retries are counted by `registry` host and class of failure.
+    pub async fn run<T, F, Fut>(&self, registry: &str, mut request: F) -> Fallible<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Fallible<T>>,
+    {
+        let mut attempt = 1;
+        loop {
+            let error = match request().await {
+                Ok(value) => return Ok(value),
+                Err(error) => error,
+            };
+            let class = match RetryClass::of(&error) {
+                Some(class) if attempt < self.max_attempts && self.retry_on.contains(&class) => {
+                    class
+                }
+                _ => return Err(error),
+            };
+
+            let delay = match HttpStatusError::retry_after(&error) {
+                Some(retry_after) if retry_after.as_secs_f64() > self.max_delay_secs => {
+                    return Err(error.context(format!(
+                        "{} asked to retry in {:?}, beyond max_delay_secs",
+                        registry, retry_after
+                    )));
+                }
+                Some(retry_after) => retry_after.max(self.delay(attempt)),
+                None => self.delay(attempt),
+            };
+            debug!(
+                "retrying request to {} in {:?} after attempt {}/{}: {}",
+                registry, delay, attempt, self.max_attempts, error
+            );
+            REQUEST_RETRIES
+                .with_label_values(&[registry, class.as_str()])
+                .inc();
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn status_error(status: StatusCode) -> Error {
+        Error::new(HttpStatusError {
+            status,
+            retry_after: None,
+        })
+        .context("fetching manifest")
+    }
+
+    #[test]
+    fn classify_errors() {
+        assert_eq!(
+            RetryClass::of(&status_error(StatusCode::TOO_MANY_REQUESTS)),
+            Some(RetryClass::Throttled)
+        );
+        assert_eq!(
+            RetryClass::of(&status_error(StatusCode::BAD_GATEWAY)),
+            Some(RetryClass::ServerError)
+        );
+        assert_eq!(RetryClass::of(&status_error(StatusCode::NOT_FOUND)), None);
+        let error = Error::new(HttpStatusError {
+            status: StatusCode::TOO_MANY_REQUESTS,
+            retry_after: Some(Duration::from_secs(5)),
+        })
+        .context("listing tags");
+        assert_eq!(RetryClass::of(&error), Some(RetryClass::Throttled));
+        assert_eq!(
+            HttpStatusError::retry_after(&error),
+            Some(Duration::from_secs(5))
+        );
+        assert_eq!(RetryClass::of(&format_err!("invalid manifest")), None);
+    }
+
+    #[test]
+    fn backoff_delays() {
+        let policy = RetryPolicy {
+            base_delay_secs: 1.0,
+            max_delay_secs: 5.0,
+            jitter: 0.0,
+            ..Default::default()
+        };
+        let delays: Vec<u64> = (1..=5).map(|retry| policy.delay(retry).as_secs()).collect();
+        assert_eq!(delays, vec![1, 2, 4, 5, 5]);
+
+        let policy = RetryPolicy {
+            jitter: 0.5,
+            ..policy
+        };
+        for _ in 0..100 {
+            let delay = policy.delay(3);
+            assert!(delay >= Duration::from_secs(2) && delay <= Duration::from_secs(4));
+        }
+    }
+
+    #[test]
+    fn validate_policy() {
+        assert!(RetryPolicy::default().validate().is_ok());
+        for policy in [
+            RetryPolicy {
+                max_attempts: 0,
+                ..Default::default()
+            },
+            RetryPolicy {
+                base_delay_secs: -1.0,
+                ..Default::default()
+            },
+            RetryPolicy {
+                max_delay_secs: 0.5,
+                ..Default::default()
+            },
+            RetryPolicy {
+                jitter: 1.5,
+                ..Default::default()
+            },
+        ] {
+            assert!(policy.validate().is_err(), "{:?}", policy);
+        }
+    }
+
+    #[tokio::test]
+    async fn retry_requests() -> Fallible<()> {
+        let host = "retries.example.com";
+        let policy = RetryPolicy {
+            max_attempts: 3,
+            base_delay_secs: 0.0,
+            max_delay_secs: 0.0,
+            retry_on: vec![RetryClass::Throttled],
+            ..Default::default()
+        };
+        let attempts = &AtomicUsize::new(0);
+
+        // Throttled requests are retried until they succeed.
+        let value = policy
+            .run(host, || async move {
+                match attempts.fetch_add(1, Ordering::SeqCst) {
+                    0 | 1 => Err(status_error(StatusCode::TOO_MANY_REQUESTS)),
+                    _ => Ok(42),
+                }
+            })
+            .await?;
+        assert_eq!(value, 42);
+        assert_eq!(attempts.swap(0, Ordering::SeqCst), 3);
+        assert_eq!(
+            REQUEST_RETRIES
+                .with_label_values(&[host, "throttled"])
+                .get(),
+            2
+        );
+
+        // ... but not beyond the maximum number of attempts.
+        let result: Fallible<()> = policy
+            .run(host, || async move {
+                attempts.fetch_add(1, Ordering::SeqCst);
+                Err(status_error(StatusCode::TOO_MANY_REQUESTS))
+            })
+            .await;
+        assert!(result.is_err());
+        assert_eq!(attempts.swap(0, Ordering::SeqCst), 3);
+
+        // Other failures fail right away.
+        let result: Fallible<()> = policy
+            .run(host, || async move {
+                attempts.fetch_add(1, Ordering::SeqCst);
+                Err(status_error(StatusCode::SERVICE_UNAVAILABLE))
+            })
+            .await;
+        assert!(result.is_err());
+        assert_eq!(attempts.swap(0, Ordering::SeqCst), 1);
+
+        // Retries asked beyond the maximum delay are given up.
+        let result: Fallible<()> = policy
+            .run(host, || async move {
+                attempts.fetch_add(1, Ordering::SeqCst);
+                Err(Error::new(HttpStatusError {
+                    status: StatusCode::TOO_MANY_REQUESTS,
+                    retry_after: Some(Duration::from_secs(60)),
+                }))
+            })
+            .await;
+        assert!(result.unwrap_err().to_string().contains("asked to retry"));
+        assert_eq!(attempts.load(Ordering::SeqCst), 1);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,369 @@
+//! Adaptive throttling of the requests to a registry.
+//!
+//! Once a registry answers 429 Too Many Requests, its requests are spaced out
+//! to half the rate observed over the last second. The rate then grows by about
+//! one request per second every second until the registry throttles again, so
+//! that scrapes settle just below the rate limit instead of failing.
+//!
+//! The registries announcing their limits are followed instead of probed:
+//! requests are paused as long as a `Retry-After` header asks for, or until
+//! the `RateLimit-Reset` of an exhausted `RateLimit-Remaining`, and otherwise
+//! spread so that the remaining requests last until the reset. Only responses
+//! to the requests made outside of the registry client carry these headers.
+//!
+//! The throttle of a registry is shared by all the scrapes and repositories
+//! of that registry, and kept from one scrape to the next.
+
+use commons::prelude_errors::*;
+use log::{debug, warn};
+use prometheus::{GaugeVec, Opts};
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Default minimum rate of the throttled requests, per second.
+pub static DEFAULT_THROTTLE_MIN_RATE: f64 = 1.0;
+
+/// Window over which the request rate is measured.
+static RATE_WINDOW: Duration = Duration::from_secs(1);
+
+/// Longest pause asked for by a registry which is honored.
+static MAX_PAUSE: Duration = Duration::from_secs(300);
+
+static RATELIMIT_REMAINING: &str = "ratelimit-remaining";
+static RATELIMIT_RESET: &str = "ratelimit-reset";
+
+lazy_static::lazy_static! {
+    static ref EFFECTIVE_REQUEST_RATE: GaugeVec = GaugeVec::new(
+        Opts::new(
+            "graph_upstream_effective_request_rate",
+            "Requests per second allowed to upstream registries by the adaptive throttling, 0 if not throttled"
+        ),
+        &["registry"]
+    )
+    .unwrap();
+
+    /// Throttles by registry host.
+    static ref THROTTLES: Mutex<HashMap<String, Arc<Throttle>>> = Default::default();
+}
+
+/// Register relevant metrics to a prometheus registry.
+pub(crate) fn register_metrics(registry: &prometheus::Registry) -> Fallible<()> {
+    registry.register(Box::new(EFFECTIVE_REQUEST_RATE.clone()))?;
+    Ok(())
+}
+
+/// Rate-limit information of a registry response.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ResponseLimits {
+    /// Delay asked for by `Retry-After`.
+    pub retry_after: Option<Duration>,
+    /// Requests left in the current window, from `RateLimit-Remaining`.
+    pub remaining: Option<u64>,
+    /// Time until the window resets, from `RateLimit-Reset`.
+    pub reset: Option<Duration>,
+}
+
+impl ResponseLimits {
+    /// Read the rate-limit headers of a response.
+    ///
+    /// `Retry-After` is either a number of seconds or an HTTP date.
+    pub fn of(headers: &HeaderMap) -> Self {
+        let header = |name: &str| {
+            headers
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(str::trim)
+        };
+        let seconds = |name: &str| {
+            header(name)
+                .and_then(|value| value.parse::<u64>().ok())
+                .map(Duration::from_secs)
+        };
+
+        let retry_after = seconds(RETRY_AFTER.as_str()).or_else(|| {
+            let date = chrono::DateTime::parse_from_rfc2822(header(RETRY_AFTER.as_str())?).ok()?;
+            (date.with_timezone(&chrono::Utc) - chrono::Utc::now())
+                .to_std()
+                .ok()
+        });
+
+        Self {
+            retry_after,
+            remaining: header(RATELIMIT_REMAINING).and_then(|value| value.parse().ok()),
+            reset: seconds(RATELIMIT_RESET),
+        }
+    }
+}
+
+/// Pass the rate-limit headers of a response from `host` to its throttle, if any.
+pub fn observe_response(host: &str, headers: &HeaderMap) {
+    let throttle = THROTTLES
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .get(host)
+        .cloned();
+    if let Some(throttle) = throttle {
+        throttle.observe(&ResponseLimits::of(headers), Instant::now());
+    }
+}
+
+#[derive(Debug)]
+struct State {
+    /// Minimum allowed requests per second.
+    min_rate: f64,
+    /// Allowed requests per second, `None` until the registry throttles.
+    rate: Option<f64>,
+    /// Earliest start of the next request.
+    next_slot: Instant,
+    /// Starts of the requests within the last `RATE_WINDOW`.
+    recent: VecDeque<Instant>,
+}
+
+/// Request throttle of a registry.
+#[derive(Debug)]
+pub struct Throttle {
+    host: String,
+    state: Mutex<State>,
+}
+
+impl Throttle {
+    /// Create an unthrottled throttle for the registry at `host`.
+    fn new(host: &str, min_rate: f64) -> Self {
+        EFFECTIVE_REQUEST_RATE.with_label_values(&[host]).set(0.0);
+        Self {
+            host: host.to_string(),
+            state: Mutex::new(State {
+                min_rate,
+                rate: None,
+                next_slot: Instant::now(),
+                recent: VecDeque::new(),
+            }),
+        }
+    }
+
+    /// Returns the throttle of the registry at `host`, creating it if needed.
+    ///
+    /// The minimum rate is the one of the latest caller.
+    pub fn shared(host: &str, min_rate: f64) -> Arc<Self> {
+        let mut throttles = THROTTLES
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let throttle = throttles
+            .entry(host.to_string())
+            .or_insert_with(|| Arc::new(Self::new(host, min_rate)))
+            .clone();
+        throttle.lock().min_rate = min_rate;
+        throttle
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Current allowed requests per second, `None` if not throttled.
+    pub fn rate(&self) -> Option<f64> {
+        self.lock().rate
+    }
+
+    fn set_rate(&self, state: &mut State, rate: f64) {
+        state.rate = Some(rate);
+        EFFECTIVE_REQUEST_RATE
+            .with_label_values(&[&self.host])
+            .set(rate);
+    }
+
+    /// Reserve a slot for a request, returning when it may be sent.
+    fn reserve(&self, now: Instant) -> Instant {
+        let mut state = self.lock();
+        let slot = state.next_slot.max(now);
+        state.next_slot = match state.rate {
+            Some(rate) => slot + Duration::from_secs_f64(1.0 / rate),
+            None => slot,
+        };
+        while matches!(state.recent.front(), Some(start) if slot.saturating_duration_since(*start) > RATE_WINDOW)
+        {
+            state.recent.pop_front();
+        }
+        state.recent.push_back(slot);
+        slot
+    }
+
+    /// Wait for the next request slot.
+    pub async fn acquire(&self) {
+        let slot = self.reserve(Instant::now());
+        tokio::time::sleep_until(slot.into()).await;
+    }
+
+    /// Slow down after the registry throttled a request.
+    pub fn throttled(&self) {
+        let mut state = self.lock();
+        let observed = state.recent.len() as f64 / RATE_WINDOW.as_secs_f64();
+        let current = state.rate.map_or(observed, |rate| rate.min(observed));
+        let rate = (current / 2.0).max(state.min_rate);
+        if state.rate.is_none() {
+            warn!(
+                "{} is throttling requests, slowing down to {:.1} requests per second",
+                self.host, rate
+            );
+        } else {
+            debug!(
+                "{} is throttling requests, slowing down to {:.1} requests per second",
+                self.host, rate
+            );
+        }
+        self.set_rate(&mut state, rate);
+    }
+
+    /// Speed up again after a successful request.
+    pub fn succeeded(&self) {
+        let mut state = self.lock();
+        if let Some(rate) = state.rate {
+            self.set_rate(&mut state, rate + 1.0 / rate);
+        }
+    }
+
+    /// Follow the rate-limit headers of a response received at `now`.
+    ///
+    /// Requests are paused for the asked delay, bounded by `MAX_PAUSE`, and
+    /// the rate is lowered so that the remaining requests last until the reset.
+    fn observe(&self, limits: &ResponseLimits, now: Instant) {
+        let mut state = self.lock();
+
+        let exhausted = limits.remaining == Some(0);
+        let pause = limits
+            .retry_after
+            .or_else(|| limits.reset.filter(|_| exhausted));
+        if let Some(pause) = pause {
+            let pause = pause.min(MAX_PAUSE);
+            debug!("{} asked to pause requests for {:?}", self.host, pause);
+            state.next_slot = state.next_slot.max(now + pause);
+        }
+
+        if let (Some(remaining), Some(reset)) = (limits.remaining, limits.reset) {
+            if remaining > 0 && !reset.is_zero() {
+                let allowed = (remaining as f64 / reset.as_secs_f64()).max(state.min_rate);
+                if state.rate.is_none_or(|rate| allowed < rate) {
+                    self.set_rate(&mut state, allowed);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::HeaderValue;
+
+    #[test]
+    fn adaptive_rate() {
+        let host = "throttle.example.com";
+        let gauge = || EFFECTIVE_REQUEST_RATE.with_label_values(&[host]).get();
+        let throttle = Throttle::new(host, 2.0);
+
+        // Requests aren't delayed until the registry throttles.
+        let now = Instant::now();
+        for _ in 0..20 {
+            assert_eq!(throttle.reserve(now), now);
+        }
+        throttle.succeeded();
+        assert_eq!(throttle.rate(), None);
+        assert_eq!(gauge(), 0.0);
+
+        // The rate is halved, and requests are spaced out accordingly.
+        throttle.throttled();
+        assert_eq!(throttle.rate(), Some(10.0));
+        assert_eq!(gauge(), 10.0);
+        let first = throttle.reserve(now);
+        let second = throttle.reserve(now);
+        assert_eq!(second - first, Duration::from_millis(100));
+
+        // Successes slowly raise the rate again.
+        throttle.succeeded();
+        assert!((throttle.rate().unwrap() - 10.1).abs() < 1e-9);
+
+        // The rate never falls below the minimum.
+        for _ in 0..10 {
+            throttle.throttled();
+        }
+        assert_eq!(throttle.rate(), Some(2.0));
+        assert_eq!(gauge(), 2.0);
+    }
+
+    #[test]
+    fn shared_by_host() {
+        let a = Throttle::shared("shared.example.com", 1.0);
+        let b = Throttle::shared("shared.example.com", 3.0);
+        assert!(Arc::ptr_eq(&a, &b));
+        assert_eq!(a.lock().min_rate, 3.0);
+        assert!(!Arc::ptr_eq(
+            &a,
+            &Throttle::shared("other.example.com", 1.0)
+        ));
+    }
+
+    #[test]
+    fn parse_limits() {
+        let mut headers = HeaderMap::new();
+        headers.insert(RETRY_AFTER, HeaderValue::from_static("30"));
+        headers.insert(RATELIMIT_REMAINING, HeaderValue::from_static("10"));
+        headers.insert(RATELIMIT_RESET, HeaderValue::from_static("5"));
+        assert_eq!(
+            ResponseLimits::of(&headers),
+            ResponseLimits {
+                retry_after: Some(Duration::from_secs(30)),
+                remaining: Some(10),
+                reset: Some(Duration::from_secs(5)),
+            }
+        );
+
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            RETRY_AFTER,
+            HeaderValue::from_static("Wed, 21 Oct 2015 07:28:00 GMT"),
+        );
+        assert_eq!(ResponseLimits::of(&headers), ResponseLimits::default());
+    }
+
+    #[test]
+    fn follow_limits() {
+        let throttle = Throttle::new("limits.example.com", 1.0);
+        let now = Instant::now();
+
+        // The remaining requests are spread until the reset.
+        throttle.observe(
+            &ResponseLimits {
+                remaining: Some(20),
+                reset: Some(Duration::from_secs(10)),
+                ..Default::default()
+            },
+            now,
+        );
+        assert_eq!(throttle.rate(), Some(2.0));
+
+        // Requests are paused until an exhausted limit resets.
+        throttle.observe(
+            &ResponseLimits {
+                remaining: Some(0),
+                reset: Some(Duration::from_secs(10)),
+                ..Default::default()
+            },
+            now,
+        );
+        assert_eq!(throttle.reserve(now), now + Duration::from_secs(10));
+
+        // Retry-After pauses requests, up to a bound.
+        throttle.observe(
+            &ResponseLimits {
+                retry_after: Some(Duration::from_secs(3600)),
+                ..Default::default()
+            },
+            now,
+        );
+        assert_eq!(throttle.reserve(now), now + MAX_PAUSE);
+    }
+}
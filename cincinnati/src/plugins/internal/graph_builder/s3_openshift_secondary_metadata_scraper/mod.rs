@@ -0,0 +1,13 @@
+//! This plugin downloads a graph-data archive from an S3-compatible bucket and
+//! extracts it to a given output directory.
+//!
+//! It is meant to be included in the plugin chain, preceding other plugins who
+//! rely on the data being in the output directory.
+//! The plugin will only extract the archive if its ETag changed or on first run.
+
+pub mod plugin;
+pub(crate) mod sigv4;
+
+pub use plugin::{
+    S3OpenshiftSecondaryMetadataScraperPlugin, S3OpenshiftSecondaryMetadataScraperSettings,
+};
@@ -0,0 +1,284 @@
+//! This plugin injects configured synthetic releases and edges into the graph.
+//!
+//! It is meant for staging environments, so that client teams can exercise
+//! upgrade flows against versions which don't exist in the real registry.
+//! Synthetic releases are marked with the `<key_prefix>.synthetic` metadata
+//! key, and their payloads default to a non-pullable reference. Releases which
+//! are already part of the graph always win over synthetic ones.
+
+use crate as cincinnati;
+
+use self::cincinnati::plugins::prelude::*;
+use self::cincinnati::plugins::prelude_plugin_impl::*;
+use self::cincinnati::{ConcreteRelease, IStr, MapImpl, Release};
+
+/// Default prefix of the metadata keys.
+pub static DEFAULT_KEY_PREFIX: &str = "io.openshift.upgrades.graph";
+
+/// Registry of the default payloads, which can never be resolved.
+pub static DEFAULT_PAYLOAD_REPOSITORY: &str = "example.invalid/synthetic-release";
+
+/// A synthetic release.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SyntheticRelease {
+    /// Version of the release.
+    pub version: String,
+
+    /// Payload of the release, `<DEFAULT_PAYLOAD_REPOSITORY>:<version>` if unset.
+    #[serde(default)]
+    pub payload: Option<String>,
+
+    /// Channels the release is part of.
+    #[serde(default)]
+    pub channels: Vec<String>,
+
+    /// Further metadata of the release.
+    #[serde(default)]
+    pub metadata: MapImpl<String, String>,
+}
+
+/// A synthetic edge, between releases of the graph or synthetic ones.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SyntheticEdge {
+    /// Version of the source release.
+    pub from: String,
+
+    /// Version of the target release.
+    pub to: String,
+}
+
+/// Plugin settings.
+#[derive(Clone, Debug, Deserialize, SmartDefault)]
+#[serde(default)]
+pub struct SyntheticReleasesSettings {
+    /// Prefix of the metadata keys.
+    #[default(DEFAULT_KEY_PREFIX.to_string())]
+    pub key_prefix: String,
+
+    /// Releases to inject.
+    pub releases: Vec<SyntheticRelease>,
+
+    /// Edges to inject.
+    pub edges: Vec<SyntheticEdge>,
+}
+
+impl PluginSettings for SyntheticReleasesSettings {
+    fn build_plugin(&self, _: Option<&prometheus::Registry>) -> Fallible<BoxedPlugin> {
+        let plugin = SyntheticReleasesPlugin {
+            settings: self.clone(),
+        };
+        Ok(new_plugin!(InternalPluginWrapper(plugin)))
+    }
+}
+
+impl SyntheticReleasesSettings {
+    /// Validate plugin configuration and fill in defaults.
+    pub fn deserialize_config(cfg: toml::Value) -> Fallible<Box<dyn PluginSettings>> {
+        let settings: Self = cfg.try_into()?;
+
+        ensure!(!settings.key_prefix.is_empty(), "empty key_prefix");
+        for release in &settings.releases {
+            cincinnati::semver_cache::parse_version(&release.version)
+                .context(format!("invalid synthetic version '{}'", release.version))?;
+            ensure!(
+                release.payload.as_ref().is_none_or(|p| !p.is_empty()),
+                "empty payload for synthetic release '{}'",
+                release.version
+            );
+        }
+        for edge in &settings.edges {
+            ensure!(
+                edge.from != edge.to,
+                "synthetic edge from '{}' to itself",
+                edge.from
+            );
+        }
+
+        Ok(Box::new(settings))
+    }
+}
+
+/// Injects synthetic releases and edges.
+#[derive(Debug)]
+pub struct SyntheticReleasesPlugin {
+    settings: SyntheticReleasesSettings,
+}
+
+impl SyntheticReleasesPlugin {
+    /// Plugin name, for configuration.
+    pub const PLUGIN_NAME: &'static str = "synthetic-releases";
+}
+
+#[async_trait]
+impl InternalPlugin for SyntheticReleasesPlugin {
+    const PLUGIN_NAME: &'static str = Self::PLUGIN_NAME;
+
+    async fn run_internal(&self, io: InternalIO) -> Fallible<InternalIO> {
+        let mut graph = io.graph;
+        let synthetic_key: IStr = format!("{}.synthetic", self.settings.key_prefix).into();
+        let channels_key: IStr = format!("{}.release.channels", self.settings.key_prefix).into();
+
+        let mut added = 0;
+        for release in &self.settings.releases {
+            if graph.find_by_version(&release.version).is_some() {
+                warn!(
+                    "not injecting synthetic release {}, it is already part of the graph",
+                    release.version
+                );
+                continue;
+            }
+
+            let mut metadata: MapImpl<IStr, IStr> = release
+                .metadata
+                .iter()
+                .map(|(key, value)| (key.into(), value.into()))
+                .collect();
+            metadata.insert(synthetic_key.clone(), "true".into());
+            if !release.channels.is_empty() {
+                metadata.insert(channels_key.clone(), release.channels.join(",").into());
+            }
+            let payload = release
+                .payload
+                .clone()
+                .unwrap_or_else(|| format!("{}:{}", DEFAULT_PAYLOAD_REPOSITORY, release.version));
+
+            graph.add_release(Release::Concrete(ConcreteRelease {
+                version: release.version.as_str().into(),
+                payload,
+                metadata,
+            }))?;
+            added += 1;
+        }
+
+        for edge in &self.settings.edges {
+            let from = graph
+                .find_by_version(&edge.from)
+                .ok_or_else(|| format_err!("synthetic edge source {} not found", edge.from))?;
+            let to = graph
+                .find_by_version(&edge.to)
+                .ok_or_else(|| format_err!("synthetic edge target {} not found", edge.to))?;
+
+            if let Err(e) = graph.add_edge(&from, &to) {
+                if e.downcast_ref::<cincinnati::errors::EdgeAlreadyExists>()
+                    .is_none()
+                {
+                    return Err(e);
+                }
+            }
+        }
+        debug!(
+            "Injected {} synthetic releases and {} synthetic edges.",
+            added,
+            self.settings.edges.len()
+        );
+
+        Ok(InternalIO {
+            graph,
+            parameters: io.parameters,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use commons::testing::init_runtime;
+
+    #[test]
+    fn inject_synthetic_releases() -> Fallible<()> {
+        let runtime = init_runtime()?;
+
+        let mut graph = cincinnati::Graph::default();
+        graph.add_release(Release::Concrete(ConcreteRelease {
+            version: "4.10.1".into(),
+            payload: "quay.io/ocp/release@sha256:1".to_string(),
+            metadata: MapImpl::new(),
+        }))?;
+
+        let settings = SyntheticReleasesSettings::deserialize_config(toml::from_str(
+            r#"
+                name = "synthetic-releases"
+
+                [[releases]]
+                version = "4.10.99"
+                channels = ["stable-4.10", "fast-4.10"]
+                metadata = { "url" = "https://example.com/4.10.99" }
+
+                [[releases]]
+                version = "4.10.1"
+
+                [[edges]]
+                from = "4.10.1"
+                to = "4.10.99"
+            "#,
+        )?)?;
+        let plugin = settings.build_plugin(None)?;
+
+        let io = runtime.block_on(plugin.run(cincinnati::plugins::PluginIO::InternalIO(
+            InternalIO {
+                graph,
+                parameters: Default::default(),
+            },
+        )))?;
+        let graph = cincinnati::testing::internal_io(io)?.graph;
+
+        let metadata = |version: &str| -> Fallible<MapImpl<IStr, IStr>> {
+            let id = graph
+                .find_by_version(version)
+                .ok_or_else(|| format_err!("{} not found", version))?;
+            match graph.find_by_releaseid(&id)? {
+                Release::Concrete(release) => Ok(release.metadata.clone()),
+                _ => bail!("{} is not concrete", version),
+            }
+        };
+        let synthetic = metadata("4.10.99")?;
+        assert_eq!(
+            synthetic
+                .get("io.openshift.upgrades.graph.synthetic")
+                .map(IStr::as_str),
+            Some("true")
+        );
+        assert_eq!(
+            synthetic
+                .get("io.openshift.upgrades.graph.release.channels")
+                .map(IStr::as_str),
+            Some("stable-4.10,fast-4.10")
+        );
+        assert_eq!(
+            synthetic.get("url").map(IStr::as_str),
+            Some("https://example.com/4.10.99")
+        );
+
+        // The real release is kept as is.
+        assert!(metadata("4.10.1")?.is_empty());
+
+        let edges: Vec<(String, String)> = graph
+            .iter_edges()
+            .map(|(from, to)| (from.version().to_string(), to.version().to_string()))
+            .collect();
+        assert_eq!(edges, vec![("4.10.1".to_string(), "4.10.99".to_string())]);
+
+        // Edges need existing endpoints.
+        let settings = SyntheticReleasesSettings::deserialize_config(toml::from_str(
+            r#"
+                name = "synthetic-releases"
+
+                [[edges]]
+                from = "4.10.1"
+                to = "4.10.100"
+            "#,
+        )?)?;
+        let plugin = settings.build_plugin(None)?;
+        let result = runtime.block_on(plugin.run(cincinnati::plugins::PluginIO::InternalIO(
+            InternalIO {
+                graph: cincinnati::Graph::default(),
+                parameters: Default::default(),
+            },
+        )));
+        assert!(result.is_err());
+
+        Ok(())
+    }
+}
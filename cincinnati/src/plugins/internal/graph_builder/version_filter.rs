@@ -0,0 +1,255 @@
+//! This plugin removes releases by version, along with their edges.
+//!
+//! It is meant for emergency pulls of bad releases, without waiting for a
+//! graph-data change. Releases are matched by SemVer requirements, like
+//! `>=4.10.3, <4.10.5`, or by regular expressions which must match the whole
+//! version. Blocked releases are always removed, and if any allow rule is
+//! configured, releases which match none of them are removed too.
+
+use crate as cincinnati;
+
+use self::cincinnati::plugins::prelude::*;
+use self::cincinnati::plugins::prelude_plugin_impl::*;
+
+use regex::Regex;
+
+/// Plugin settings.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct VersionFilterSettings {
+    /// SemVer requirements of the releases to keep.
+    pub allow_ranges: Vec<String>,
+
+    /// Regular expressions of the versions to keep.
+    pub allow_regexes: Vec<String>,
+
+    /// SemVer requirements of the releases to remove.
+    pub block_ranges: Vec<String>,
+
+    /// Regular expressions of the versions to remove.
+    pub block_regexes: Vec<String>,
+}
+
+impl PluginSettings for VersionFilterSettings {
+    fn build_plugin(&self, _: Option<&prometheus::Registry>) -> Fallible<BoxedPlugin> {
+        let plugin = VersionFilterPlugin {
+            allow: VersionRules::new(&self.allow_ranges, &self.allow_regexes)?,
+            block: VersionRules::new(&self.block_ranges, &self.block_regexes)?,
+        };
+        Ok(new_plugin!(InternalPluginWrapper(plugin)))
+    }
+}
+
+impl VersionFilterSettings {
+    /// Validate plugin configuration and fill in defaults.
+    pub fn deserialize_config(cfg: toml::Value) -> Fallible<Box<dyn PluginSettings>> {
+        let settings: Self = cfg.try_into()?;
+
+        let allow = VersionRules::new(&settings.allow_ranges, &settings.allow_regexes)
+            .context("invalid allow rule")?;
+        let block = VersionRules::new(&settings.block_ranges, &settings.block_regexes)
+            .context("invalid block rule")?;
+        ensure!(
+            !allow.is_empty() || !block.is_empty(),
+            "at least one allow or block rule must be configured"
+        );
+
+        Ok(Box::new(settings))
+    }
+}
+
+/// Rules matching release versions.
+#[derive(Debug)]
+struct VersionRules {
+    ranges: Vec<semver::VersionReq>,
+    regexes: Vec<Regex>,
+}
+
+impl VersionRules {
+    fn new(ranges: &[String], regexes: &[String]) -> Fallible<Self> {
+        let ranges = ranges
+            .iter()
+            .map(|range| {
+                cincinnati::semver_cache::parse_version_req(range)
+                    .context(format!("invalid SemVer requirement '{}'", range))
+            })
+            .collect::<Fallible<_>>()?;
+        let regexes = regexes
+            .iter()
+            .map(|regex| {
+                Regex::new(&format!("^(?:{})$", regex))
+                    .context(format!("invalid regular expression '{}'", regex))
+            })
+            .collect::<Fallible<_>>()?;
+
+        Ok(Self { ranges, regexes })
+    }
+
+    fn is_empty(&self) -> bool {
+        self.ranges.is_empty() && self.regexes.is_empty()
+    }
+
+    /// Returns whether a version matches any rule.
+    ///
+    /// Versions which aren't valid SemVer only match regular expressions.
+    fn matches(&self, version: &str) -> bool {
+        if self.regexes.iter().any(|regex| regex.is_match(version)) {
+            return true;
+        }
+        if self.ranges.is_empty() {
+            return false;
+        }
+
+        match cincinnati::semver_cache::parse_version(version) {
+            Ok(version) => self.ranges.iter().any(|range| range.matches(&version)),
+            Err(_) => false,
+        }
+    }
+}
+
+/// Removes releases by version.
+#[derive(Debug)]
+pub struct VersionFilterPlugin {
+    allow: VersionRules,
+    block: VersionRules,
+}
+
+impl VersionFilterPlugin {
+    /// Plugin name, for configuration.
+    pub const PLUGIN_NAME: &'static str = "version-filter";
+
+    /// Returns whether a release is kept.
+    fn keeps(&self, version: &str) -> bool {
+        (self.allow.is_empty() || self.allow.matches(version)) && !self.block.matches(version)
+    }
+}
+
+#[async_trait]
+impl InternalPlugin for VersionFilterPlugin {
+    const PLUGIN_NAME: &'static str = Self::PLUGIN_NAME;
+
+    async fn run_internal(&self, io: InternalIO) -> Fallible<InternalIO> {
+        let mut graph = io.graph;
+
+        let to_remove = graph
+            .find_by_fn(|release| !self.keeps(release.version()))
+            .into_iter()
+            .map(|(release_id, version)| {
+                info!("removing release {} by version filter", version);
+                release_id
+            })
+            .collect();
+        let removed = graph.remove_releases(to_remove);
+        debug!("Removed {} releases by version.", removed);
+
+        Ok(InternalIO {
+            graph,
+            parameters: io.parameters,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cincinnati::testing::{generate_custom_graph, TestMetadata};
+    use commons::testing::init_runtime;
+
+    fn filter(cfg: &str, graph: cincinnati::Graph) -> Fallible<cincinnati::Graph> {
+        let runtime = init_runtime()?;
+        let plugin =
+            VersionFilterSettings::deserialize_config(toml::from_str(cfg)?)?.build_plugin(None)?;
+
+        let io = runtime.block_on(plugin.run(cincinnati::plugins::PluginIO::InternalIO(
+            InternalIO {
+                graph,
+                parameters: Default::default(),
+            },
+        )))?;
+        Ok(cincinnati::testing::internal_io(io)?.graph)
+    }
+
+    /// Returns the sorted versions of a graph, as removals reorder the releases.
+    fn versions(graph: &cincinnati::Graph) -> Vec<&str> {
+        let mut versions: Vec<&str> = graph
+            .iter_releases()
+            .map(|release| release.version())
+            .collect();
+        versions.sort_unstable();
+        versions
+    }
+
+    #[test]
+    fn filter_versions() -> Fallible<()> {
+        let graph = || {
+            let metadata: TestMetadata = (0..5)
+                .map(|i| {
+                    let mut metadata = cincinnati::MapImpl::new();
+                    if i == 4 {
+                        metadata.insert("version_suffix".to_string(), "-rc.1".to_string());
+                    }
+                    (i, metadata)
+                })
+                .collect();
+            cincinnati::testing::TestGraphBuilder::new()
+                .with_metadata(metadata)
+                .with_version_template("4.10.{{i}}")
+                .build()
+        };
+
+        let filtered = filter(
+            r#"
+                name = "version-filter"
+                block_ranges = [">=4.10.1, <4.10.3"]
+            "#,
+            graph(),
+        )?;
+        assert_eq!(versions(&filtered), vec!["4.10.0", "4.10.3", "4.10.4-rc.1"]);
+
+        // Regular expressions match whole versions, blocks win over allows.
+        let filtered = filter(
+            r#"
+                name = "version-filter"
+                allow_regexes = ['4\.10\.[0-3]', '4\.10\.4-rc\..*']
+                block_regexes = ['4\.10\.2', '4\.10\.4']
+            "#,
+            graph(),
+        )?;
+        assert_eq!(
+            versions(&filtered),
+            vec!["4.10.0", "4.10.1", "4.10.3", "4.10.4-rc.1"]
+        );
+
+        // Edges of removed releases are removed with them.
+        let graph = generate_custom_graph(
+            "image",
+            (0..3).map(|i| (i, Default::default())).collect(),
+            Some(vec![(0, 1), (1, 2), (0, 2)]),
+        );
+        let filtered = filter(
+            r#"
+                name = "version-filter"
+                allow_ranges = ["<2.0.0"]
+            "#,
+            graph,
+        )?;
+        assert_eq!(versions(&filtered), vec!["0.0.0", "1.0.0"]);
+        assert_eq!(filtered.edges_count(), 1);
+
+        for invalid in &[
+            r#"name = "version-filter""#,
+            r#"
+                name = "version-filter"
+                block_ranges = ["not a range"]
+            "#,
+            r#"
+                name = "version-filter"
+                allow_regexes = ["4.10.("]
+            "#,
+        ] {
+            assert!(VersionFilterSettings::deserialize_config(toml::from_str(invalid)?).is_err());
+        }
+
+        Ok(())
+    }
+}
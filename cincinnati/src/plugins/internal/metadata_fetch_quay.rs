@@ -85,6 +85,11 @@ impl QuayMetadataFetchPlugin {
         let client: quay::v1::Client = quay::v1::Client::builder()
             .access_token(api_token)
             .api_base(Some(api_base))
+            .http_client(Some(
+                commons::outbound::client_builder()
+                    .build()
+                    .context("Building reqwest client")?,
+            ))
             .build()?;
 
         Ok(Self {
@@ -142,7 +147,7 @@ impl InternalPlugin for QuayMetadataFetchPlugin {
                 .get_metadata_as_ref_mut(&release_id)
                 .context("trying to find metadata for release")?;
             for (key, value) in labels {
-                let warn_msg = if metadata.contains_key(&key) {
+                let warn_msg = if metadata.contains_key(key.as_str()) {
                     Some(format!(
                         "[{}] key '{}' already exists. overwriting with value '{}'. ",
                         &release_version, &key, &value
@@ -158,7 +163,7 @@ impl InternalPlugin for QuayMetadataFetchPlugin {
                     &value
                 );
 
-                if let Some(previous_value) = metadata.insert(key, value) {
+                if let Some(previous_value) = metadata.insert(key.into(), value.into()) {
                     warn!(
                         "{}previous value: '{}'",
                         warn_msg.unwrap_or_default(),
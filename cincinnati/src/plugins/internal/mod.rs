@@ -3,14 +3,23 @@
 pub mod arch_filter;
 pub mod channel_filter;
 pub mod cincinnati_graph_fetch;
+pub mod cluster_profile_filter;
 pub mod edge_add_remove;
 pub mod metadata_fetch_quay;
 pub mod node_remove;
+pub mod phased_rollout;
+pub mod time_window;
 pub mod versioned_graph;
+#[cfg(feature = "wasm")]
+pub mod wasm_transform;
 
 mod graph_builder;
 
 pub use graph_builder::{
+    candidate_channel_derive, conditional_risk_evaluator,
     dkrv2_openshift_secondary_metadata_scraper, github_openshift_secondary_metadata_scraper,
-    openshift_secondary_metadata_parser, release_scrape_dockerv2,
+    graph_file_load, graph_sanity_check, local_openshift_secondary_metadata_scraper,
+    multiarch_publication_gate, openshift_secondary_metadata_parser, release_cosign_verify,
+    release_enrichment_hook, release_notes_enrich, release_scrape_dockerv2,
+    s3_openshift_secondary_metadata_scraper, synthetic_releases, version_filter,
 };
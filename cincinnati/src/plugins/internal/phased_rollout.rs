@@ -0,0 +1,457 @@
+//! This plugin exposes the edges to a new release to a growing share of the clusters.
+//!
+//! Each rollout targets the edges to a release, optionally from a single
+//! release, and follows a schedule of percentages starting at given times.
+//! Rollouts are configured in the plugin settings, or in graph-data as release
+//! metadata, e.g. `2022-10-03T00:00:00Z=10,2022-10-05T00:00:00Z=50`.
+//!
+//! A cluster is exposed to the edges if its bucket, hashed from the cluster
+//! `id` parameter and the target version, is below the current percentage.
+//! The same cluster thus keeps seeing the edges as the percentage grows.
+//! Clusters without `id` are only exposed once the rollout reached 100%.
+//!
+//! Invalid schedules fail closed: the edges they target are held back from all
+//! clusters until the schedule is fixed. Graph-data schedules are parsed once,
+//! when first seen.
+
+use crate as cincinnati;
+
+use self::cincinnati::plugins::prelude::*;
+use self::cincinnati::plugins::prelude_plugin_impl::*;
+use self::cincinnati::Release;
+
+use chrono::{DateTime, Utc};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// Default prefix of the metadata keys.
+pub static DEFAULT_KEY_PREFIX: &str = "io.openshift.upgrades.graph";
+
+/// Default suffix of the release metadata key carrying the rollout schedule.
+pub static DEFAULT_SCHEDULE_KEY: &str = "release.rollout";
+
+/// Default query parameter identifying the cluster.
+pub static DEFAULT_ID_PARAM: &str = "id";
+
+/// Maximum number of parsed graph-data schedules kept, beyond which they are parsed again.
+static MAX_PARSED_SCHEDULES: usize = 1024;
+
+/// A step of a rollout schedule, as configured.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct RolloutStepSettings {
+    /// Time from which the percentage applies, in RFC 3339 format.
+    pub start: String,
+    /// Percentage of clusters exposed to the edges.
+    pub percentage: u8,
+}
+
+/// A rollout, as configured.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct RolloutSettings {
+    /// Version the edges lead to.
+    pub to: String,
+    /// Version the edges come from, all of them if unset.
+    #[serde(default)]
+    pub from: Option<String>,
+    /// Percentage schedule.
+    pub schedule: Vec<RolloutStepSettings>,
+}
+
+/// Plugin settings.
+#[derive(Clone, Debug, Deserialize, SmartDefault)]
+#[serde(default)]
+pub struct PhasedRolloutSettings {
+    /// Prefix of the metadata keys.
+    #[default(DEFAULT_KEY_PREFIX.to_string())]
+    pub key_prefix: String,
+
+    /// Suffix of the release metadata key carrying the rollout schedule.
+    #[default(DEFAULT_SCHEDULE_KEY.to_string())]
+    pub key_suffix: String,
+
+    /// Query parameter identifying the cluster.
+    #[default(DEFAULT_ID_PARAM.to_string())]
+    pub id_param: String,
+
+    /// Rollouts, in addition to the ones of the graph-data.
+    pub rollouts: Vec<RolloutSettings>,
+}
+
+impl PluginSettings for PhasedRolloutSettings {
+    fn build_plugin(&self, _: Option<&prometheus::Registry>) -> Fallible<BoxedPlugin> {
+        let rollouts = self
+            .rollouts
+            .iter()
+            .map(|rollout| {
+                let steps = rollout
+                    .schedule
+                    .iter()
+                    .map(|step| parse_step(&step.start, step.percentage))
+                    .collect::<Fallible<Vec<_>>>()?;
+                Ok(Rollout {
+                    to: rollout.to.clone(),
+                    from: rollout.from.clone(),
+                    schedule: Schedule::new(steps)?,
+                })
+            })
+            .collect::<Fallible<_>>()?;
+
+        let plugin = PhasedRolloutPlugin {
+            schedule_key: format!("{}.{}", self.key_prefix, self.key_suffix),
+            id_param: self.id_param.clone(),
+            rollouts,
+            parsed: Default::default(),
+        };
+        Ok(new_plugin!(InternalPluginWrapper(plugin)))
+    }
+}
+
+impl PhasedRolloutSettings {
+    /// Validate plugin configuration and fill in defaults.
+    pub fn deserialize_config(cfg: toml::Value) -> Fallible<Box<dyn PluginSettings>> {
+        let settings: Self = cfg.try_into()?;
+
+        ensure!(!settings.key_prefix.is_empty(), "empty key_prefix");
+        ensure!(!settings.key_suffix.is_empty(), "empty key_suffix");
+        ensure!(!settings.id_param.is_empty(), "empty id_param");
+        for rollout in &settings.rollouts {
+            ensure!(!rollout.to.is_empty(), "empty rollout target version");
+            ensure!(
+                !rollout.schedule.is_empty(),
+                "empty schedule for the rollout to {}",
+                rollout.to
+            );
+        }
+        // Catch invalid schedules at startup.
+        settings.build_plugin(None)?;
+
+        Ok(Box::new(settings))
+    }
+}
+
+/// Percentages of a rollout over time, sorted by start time.
+#[derive(Clone, Debug, PartialEq)]
+struct Schedule(Vec<(DateTime<Utc>, u8)>);
+
+impl Schedule {
+    /// A schedule which never exposes the edges.
+    fn closed() -> Self {
+        Self(vec![])
+    }
+
+    fn new(mut steps: Vec<(DateTime<Utc>, u8)>) -> Fallible<Self> {
+        steps.sort_by_key(|(start, _)| *start);
+        for (start, percentage) in &steps {
+            ensure!(
+                *percentage <= 100,
+                "percentage {} at {} above 100",
+                percentage,
+                start
+            );
+        }
+        Ok(Self(steps))
+    }
+
+    /// Returns the percentage at the given time, 0 before the first step.
+    fn percentage(&self, now: DateTime<Utc>) -> u8 {
+        self.0
+            .iter()
+            .take_while(|(start, _)| *start <= now)
+            .last()
+            .map_or(0, |(_, percentage)| *percentage)
+    }
+}
+
+impl FromStr for Schedule {
+    type Err = Error;
+
+    /// Parse a schedule from `<start>=<percentage>` pairs, separated by commas.
+    fn from_str(schedule: &str) -> Fallible<Self> {
+        let steps = schedule
+            .split(',')
+            .map(str::trim)
+            .filter(|step| !step.is_empty())
+            .map(|step| {
+                let (start, percentage) = step
+                    .rsplit_once('=')
+                    .ok_or_else(|| format_err!("missing '=' in rollout step '{}'", step))?;
+                let percentage = percentage
+                    .trim()
+                    .parse()
+                    .context(format!("invalid percentage in rollout step '{}'", step))?;
+                parse_step(start.trim(), percentage)
+            })
+            .collect::<Fallible<Vec<_>>>()?;
+        ensure!(!steps.is_empty(), "empty rollout schedule");
+        Self::new(steps)
+    }
+}
+
+fn parse_step(start: &str, percentage: u8) -> Fallible<(DateTime<Utc>, u8)> {
+    let start = DateTime::parse_from_rfc3339(start)
+        .context(format!("invalid rollout start time '{}'", start))?;
+    Ok((start.with_timezone(&Utc), percentage))
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Rollout {
+    to: String,
+    from: Option<String>,
+    schedule: Schedule,
+}
+
+/// Returns the bucket of a cluster for the rollout to a version, between 0 and 99.
+fn bucket(id: &str, to: &str) -> u8 {
+    let digest = Sha256::digest(format!("{}/{}", id, to).as_bytes());
+    let mut prefix = [0u8; 8];
+    prefix.copy_from_slice(&digest[..8]);
+    (u64::from_be_bytes(prefix) % 100) as u8
+}
+
+/// Removes the edges of ongoing rollouts which the cluster isn't part of yet.
+#[derive(Debug)]
+pub struct PhasedRolloutPlugin {
+    schedule_key: String,
+    id_param: String,
+    rollouts: Vec<Rollout>,
+    /// Graph-data schedules by their raw value, closed if invalid.
+    parsed: Mutex<HashMap<String, Schedule>>,
+}
+
+impl PhasedRolloutPlugin {
+    /// Plugin name, for configuration.
+    pub const PLUGIN_NAME: &'static str = "phased-rollout";
+
+    /// Returns the configured rollouts along with the ones of the graph-data.
+    fn rollouts(&self, graph: &cincinnati::Graph) -> Vec<Rollout> {
+        let mut rollouts = self.rollouts.clone();
+        for release in graph.iter_releases() {
+            let release = match release {
+                Release::Concrete(release) => release,
+                Release::Abstract(_) => continue,
+            };
+            if let Some(schedule) = release.metadata.get(self.schedule_key.as_str()) {
+                rollouts.push(Rollout {
+                    to: release.version.to_string(),
+                    from: None,
+                    schedule: self.parse_schedule(&release.version, schedule),
+                });
+            }
+        }
+        rollouts
+    }
+
+    /// Returns the parsed graph-data schedule of the rollout to a version.
+    ///
+    /// Invalid schedules are logged once and hold back the edges.
+    fn parse_schedule(&self, to: &str, raw: &str) -> Schedule {
+        let mut parsed = self.parsed.lock().unwrap();
+        if let Some(schedule) = parsed.get(raw) {
+            return schedule.clone();
+        }
+
+        let schedule = raw.parse().unwrap_or_else(|e| {
+            error!(
+                "Holding back the rollout to {} with an invalid schedule: {}",
+                to, e
+            );
+            Schedule::closed()
+        });
+        if parsed.len() >= MAX_PARSED_SCHEDULES {
+            parsed.clear();
+        }
+        parsed.insert(raw.to_string(), schedule.clone());
+        schedule
+    }
+}
+
+#[async_trait]
+impl InternalPlugin for PhasedRolloutPlugin {
+    const PLUGIN_NAME: &'static str = Self::PLUGIN_NAME;
+
+    async fn run_internal(&self, io: InternalIO) -> Fallible<InternalIO> {
+        let mut graph = io.graph;
+        let id = io.parameters.get(&self.id_param);
+        let now = Utc::now();
+
+        let mut held_back: Vec<(ReleaseId, ReleaseId)> = vec![];
+        let mut held_back_conditional: Vec<(Option<String>, String)> = vec![];
+        for rollout in self.rollouts(&graph) {
+            let percentage = rollout.schedule.percentage(now);
+            let exposed = match id {
+                _ if percentage >= 100 => true,
+                Some(id) => bucket(id, &rollout.to) < percentage,
+                None => false,
+            };
+            if exposed {
+                continue;
+            }
+
+            if let Some(to) = graph.find_by_version(&rollout.to) {
+                held_back.extend(
+                    graph
+                        .previous_releases(&to)
+                        .filter(|(_, _, from)| {
+                            rollout
+                                .from
+                                .as_ref()
+                                .is_none_or(|version| from.version() == version)
+                        })
+                        .map(|(_, from, _)| (ReleaseId(from), to.clone())),
+                );
+            }
+            held_back_conditional.push((rollout.from, rollout.to));
+        }
+
+        for (from, to) in &held_back {
+            graph.remove_edge(from, to)?;
+        }
+        let mut removed_conditional = 0;
+        if let Some(conditional_edges) = graph.conditional_edges.as_mut() {
+            for conditional_edge in conditional_edges.iter_mut() {
+                let total_edges = conditional_edge.edges.len();
+                conditional_edge.edges.retain(|edge| {
+                    !held_back_conditional.iter().any(|(from, to)| {
+                        edge.to == *to && from.as_ref().is_none_or(|from| edge.from == *from)
+                    })
+                });
+                removed_conditional += total_edges - conditional_edge.edges.len();
+            }
+            conditional_edges.retain(|conditional_edge| !conditional_edge.edges.is_empty());
+        }
+        trace!(
+            "held back {} edges and {} conditional edges",
+            held_back.len(),
+            removed_conditional
+        );
+
+        Ok(InternalIO {
+            graph,
+            parameters: io.parameters,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use commons::testing::init_runtime;
+
+    #[test]
+    fn rollout_schedule() -> Fallible<()> {
+        let schedule: Schedule = "2022-10-05T00:00:00Z=50, 2022-10-03T00:00:00Z=10".parse()?;
+        let at = |time: &str| -> Fallible<u8> {
+            Ok(schedule.percentage(DateTime::parse_from_rfc3339(time)?.with_timezone(&Utc)))
+        };
+        assert_eq!(at("2022-10-01T00:00:00Z")?, 0);
+        assert_eq!(at("2022-10-03T00:00:00Z")?, 10);
+        assert_eq!(at("2022-10-04T12:00:00+02:00")?, 10);
+        assert_eq!(at("2022-10-06T00:00:00Z")?, 50);
+
+        assert!("".parse::<Schedule>().is_err());
+        assert!("2022-10-03=10".parse::<Schedule>().is_err());
+        assert!("2022-10-03T00:00:00Z=101".parse::<Schedule>().is_err());
+
+        // Buckets are stable and spread over all percentages.
+        assert_eq!(bucket("cluster-a", "2.0.0"), bucket("cluster-a", "2.0.0"));
+        let exposed = (0..1000)
+            .filter(|i| bucket(&format!("cluster-{}", i), "2.0.0") < 10)
+            .count();
+        assert!(
+            exposed > 50 && exposed < 150,
+            "{} clusters exposed",
+            exposed
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn hold_back_edges() -> Fallible<()> {
+        let runtime = init_runtime()?;
+
+        // 0.0.0 -> 1.0.0 -> 2.0.0 and 0.0.0 -> 2.0.0
+        let mut metadata = HashMap::new();
+        metadata.insert(
+            "io.openshift.upgrades.graph.release.rollout".to_string(),
+            "2000-01-01T00:00:00Z=0".to_string(),
+        );
+        let graph = cincinnati::testing::generate_custom_graph(
+            "image",
+            vec![
+                (0, Default::default()),
+                (1, Default::default()),
+                (2, metadata.into_iter().collect()),
+            ],
+            Some(vec![(0, 1), (1, 2), (0, 2)]),
+        );
+
+        let settings = PhasedRolloutSettings::deserialize_config(toml::from_str(
+            r#"
+                name = "phased-rollout"
+
+                [[rollouts]]
+                to = "1.0.0"
+                schedule = [{ start = "2000-01-01T00:00:00Z", percentage = 100 }]
+            "#,
+        )?)?;
+        let plugin = settings.build_plugin(None)?;
+
+        let mut parameters = HashMap::new();
+        parameters.insert("id".to_string(), "cluster-a".to_string());
+        let io = runtime.block_on(plugin.run(cincinnati::plugins::PluginIO::InternalIO(
+            InternalIO { graph, parameters },
+        )))?;
+        let graph = cincinnati::testing::internal_io(io)?.graph;
+
+        // The rollout to 2.0.0 didn't start yet.
+        let edges: Vec<(&str, &str)> = graph
+            .iter_edges()
+            .map(|(from, to)| (from.version(), to.version()))
+            .collect();
+        assert_eq!(edges, vec![("0.0.0", "1.0.0")]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_schedules_hold_back_edges() -> Fallible<()> {
+        let runtime = init_runtime()?;
+
+        let mut metadata = HashMap::new();
+        metadata.insert(
+            "io.openshift.upgrades.graph.release.rollout".to_string(),
+            "tomorrow=100".to_string(),
+        );
+        let graph = cincinnati::testing::generate_custom_graph(
+            "image",
+            vec![(0, Default::default()), (1, metadata.into_iter().collect())],
+            Some(vec![(0, 1)]),
+        );
+
+        let plugin = PhasedRolloutPlugin {
+            schedule_key: "io.openshift.upgrades.graph.release.rollout".to_string(),
+            id_param: DEFAULT_ID_PARAM.to_string(),
+            rollouts: vec![],
+            parsed: Default::default(),
+        };
+        let mut parameters = HashMap::new();
+        parameters.insert("id".to_string(), "cluster-a".to_string());
+        for _ in 0..2 {
+            let io = runtime.block_on(plugin.run_internal(InternalIO {
+                graph: graph.clone(),
+                parameters: parameters.clone(),
+            }))?;
+            assert_eq!(io.graph.edges_count(), 0);
+        }
+        assert_eq!(
+            plugin.parsed.lock().unwrap().get("tomorrow=100"),
+            Some(&Schedule::closed())
+        );
+
+        Ok(())
+    }
+}
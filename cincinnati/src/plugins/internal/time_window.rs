@@ -0,0 +1,558 @@
+//! This plugin only exposes the edges to a release during time windows.
+//!
+//! It is meant for promoting releases during business hours only, or for
+//! holding them back until an embargo lifts. Windows are either one-off,
+//! between two points in time of which one may be left open, or recurring,
+//! starting on a cron schedule and lasting for a fixed duration. An edge
+//! targeted by several windows is exposed during any of them.
+//!
+//! Windows are configured in the plugin settings, or in graph-data as release
+//! metadata, separated by semicolons, e.g.
+//! `2022-10-03T00:00:00Z/;stable-4.11@0 9 * * 1-5 28800`. Each window may be
+//! restricted to the requests for some channels.
+//!
+//! Invalid windows fail closed: the edges they target are held back until the
+//! windows are fixed. Graph-data windows are parsed once, when first seen.
+
+use crate as cincinnati;
+
+use self::cincinnati::plugins::prelude::*;
+use self::cincinnati::plugins::prelude_plugin_impl::*;
+use self::cincinnati::Release;
+
+use chrono::{DateTime, Duration, Utc};
+use commons::cron;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// Default prefix of the metadata keys.
+pub static DEFAULT_KEY_PREFIX: &str = "io.openshift.upgrades.graph";
+
+/// Default suffix of the release metadata key carrying the exposure windows.
+pub static DEFAULT_WINDOWS_KEY: &str = "release.exposure-windows";
+
+/// Maximum duration of recurring windows, bounding the search for their start.
+pub static MAX_RECURRING_DURATION_SECS: u64 = 31 * 24 * 60 * 60;
+
+/// Maximum number of parsed graph-data windows kept, beyond which they are parsed again.
+static MAX_PARSED_WINDOWS: usize = 1024;
+
+/// An exposure window, as configured.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ExposureWindowSettings {
+    /// Version the edges lead to.
+    pub to: String,
+
+    /// Version the edges come from, all of them if unset.
+    #[serde(default)]
+    pub from: Option<String>,
+
+    /// Channels of the requests the window applies to, all of them if empty.
+    #[serde(default)]
+    pub channels: Vec<String>,
+
+    /// Start of a one-off window, in RFC 3339 format, open if unset.
+    #[serde(default)]
+    pub start: Option<String>,
+
+    /// End of a one-off window, in RFC 3339 format, open if unset.
+    #[serde(default)]
+    pub end: Option<String>,
+
+    /// Cron schedule of the start of recurring windows, in UTC.
+    #[serde(default)]
+    pub schedule: Option<String>,
+
+    /// Duration of recurring windows, in seconds.
+    #[serde(default)]
+    pub duration_secs: Option<u64>,
+}
+
+/// Plugin settings.
+#[derive(Clone, Debug, Deserialize, SmartDefault)]
+#[serde(default)]
+pub struct TimeWindowSettings {
+    /// Prefix of the metadata keys.
+    #[default(DEFAULT_KEY_PREFIX.to_string())]
+    pub key_prefix: String,
+
+    /// Suffix of the release metadata key carrying the exposure windows.
+    #[default(DEFAULT_WINDOWS_KEY.to_string())]
+    pub key_suffix: String,
+
+    /// Windows, in addition to the ones of the graph-data.
+    pub windows: Vec<ExposureWindowSettings>,
+}
+
+impl PluginSettings for TimeWindowSettings {
+    fn build_plugin(&self, _: Option<&prometheus::Registry>) -> Fallible<BoxedPlugin> {
+        let gates = self
+            .windows
+            .iter()
+            .map(|settings| {
+                let window = match (
+                    &settings.start,
+                    &settings.end,
+                    &settings.schedule,
+                    settings.duration_secs,
+                ) {
+                    (start, end, None, None) if start.is_some() || end.is_some() => {
+                        Window::between(
+                            start.as_deref().map(parse_time).transpose()?,
+                            end.as_deref().map(parse_time).transpose()?,
+                        )?
+                    }
+                    (None, None, Some(schedule), Some(duration_secs)) => {
+                        Window::recurring(schedule, duration_secs)?
+                    }
+                    _ => bail!(
+                        "the window of the edges to {} needs either start or end, or schedule and duration_secs",
+                        settings.to
+                    ),
+                };
+                Ok(Gate {
+                    to: settings.to.clone(),
+                    from: settings.from.clone(),
+                    channels: settings.channels.clone(),
+                    window,
+                })
+            })
+            .collect::<Fallible<_>>()?;
+
+        let plugin = TimeWindowPlugin {
+            windows_key: format!("{}.{}", self.key_prefix, self.key_suffix),
+            gates,
+            parsed: Default::default(),
+        };
+        Ok(new_plugin!(InternalPluginWrapper(plugin)))
+    }
+}
+
+impl TimeWindowSettings {
+    /// Validate plugin configuration and fill in defaults.
+    pub fn deserialize_config(cfg: toml::Value) -> Fallible<Box<dyn PluginSettings>> {
+        let settings: Self = cfg.try_into()?;
+
+        ensure!(!settings.key_prefix.is_empty(), "empty key_prefix");
+        ensure!(!settings.key_suffix.is_empty(), "empty key_suffix");
+        for window in &settings.windows {
+            ensure!(!window.to.is_empty(), "empty window target version");
+        }
+        // Catch invalid windows at startup.
+        settings.build_plugin(None)?;
+
+        Ok(Box::new(settings))
+    }
+}
+
+/// A time window during which edges are exposed.
+#[derive(Clone, Debug, PartialEq)]
+enum Window {
+    /// Between two points in time, either of them open.
+    Between(Option<DateTime<Utc>>, Option<DateTime<Utc>>),
+    /// Starting on a cron schedule, for a fixed duration.
+    Recurring(cron::Schedule, Duration),
+    /// Never open, in place of invalid windows.
+    Never,
+}
+
+impl Window {
+    fn between(start: Option<DateTime<Utc>>, end: Option<DateTime<Utc>>) -> Fallible<Self> {
+        if let (Some(start), Some(end)) = (start, end) {
+            ensure!(start < end, "window ends before it starts");
+        }
+        Ok(Window::Between(start, end))
+    }
+
+    fn recurring(schedule: &str, duration_secs: u64) -> Fallible<Self> {
+        ensure!(
+            duration_secs > 0 && duration_secs <= MAX_RECURRING_DURATION_SECS,
+            "window duration_secs must be between 1 and {}",
+            MAX_RECURRING_DURATION_SECS
+        );
+        Ok(Window::Recurring(
+            schedule.parse()?,
+            Duration::seconds(duration_secs as i64),
+        ))
+    }
+
+    /// Returns whether the window contains the given time.
+    fn contains(&self, now: DateTime<Utc>) -> bool {
+        match self {
+            Window::Between(start, end) => {
+                start.is_none_or(|start| start <= now) && end.is_none_or(|end| now < end)
+            }
+            Window::Recurring(schedule, duration) => schedule.window_end(now, *duration).is_some(),
+            Window::Never => false,
+        }
+    }
+}
+
+impl FromStr for Window {
+    type Err = Error;
+
+    /// Parse a window, either `<start>/<end>` in RFC 3339 format with one of
+    /// them possibly empty, or `<cron schedule> <duration in seconds>`.
+    fn from_str(window: &str) -> Fallible<Self> {
+        let fields: Vec<&str> = window.split_whitespace().collect();
+        match fields.len() {
+            1 => {
+                let (start, end) = window
+                    .split_once('/')
+                    .ok_or_else(|| format_err!("missing '/' in window '{}'", window))?;
+                let parse =
+                    |time: &str| Some(time.trim()).filter(|t| !t.is_empty()).map(parse_time);
+                ensure!(
+                    !start.trim().is_empty() || !end.trim().is_empty(),
+                    "window '{}' has neither start nor end",
+                    window
+                );
+                Window::between(parse(start).transpose()?, parse(end).transpose()?)
+            }
+            6 => {
+                let duration_secs = fields[5]
+                    .parse()
+                    .context(format!("invalid duration in window '{}'", window))?;
+                Window::recurring(&fields[..5].join(" "), duration_secs)
+            }
+            _ => bail!(
+                "invalid window '{}', expected '<start>/<end>' or '<cron schedule> <duration>'",
+                window
+            ),
+        }
+    }
+}
+
+fn parse_time(time: &str) -> Fallible<DateTime<Utc>> {
+    Ok(DateTime::parse_from_rfc3339(time)
+        .context(format!("invalid window time '{}'", time))?
+        .with_timezone(&Utc))
+}
+
+/// A window gating the edges to a release.
+#[derive(Clone, Debug, PartialEq)]
+struct Gate {
+    to: String,
+    from: Option<String>,
+    channels: Vec<String>,
+    window: Window,
+}
+
+impl Gate {
+    /// Returns whether the gate targets the edge between the given versions.
+    fn targets(&self, from: &str, to: &str) -> bool {
+        self.to == to && self.from.as_ref().is_none_or(|version| version == from)
+    }
+}
+
+/// Parse the windows of a release, optionally prefixed by `<channel>@`.
+fn parse_gates(to: &str, windows: &str) -> Fallible<Vec<Gate>> {
+    windows
+        .split(';')
+        .map(str::trim)
+        .filter(|window| !window.is_empty())
+        .map(|window| {
+            let (channels, window) = match window.split_once('@') {
+                Some((channel, window)) => (vec![channel.trim().to_string()], window),
+                None => (vec![], window),
+            };
+            Ok(Gate {
+                to: to.to_string(),
+                from: None,
+                channels,
+                window: window.parse()?,
+            })
+        })
+        .collect()
+}
+
+/// Removes the edges gated by windows which are currently closed.
+#[derive(Debug)]
+pub struct TimeWindowPlugin {
+    windows_key: String,
+    gates: Vec<Gate>,
+    /// Gates of the graph-data windows by release version and raw value.
+    parsed: Mutex<HashMap<(String, String), Vec<Gate>>>,
+}
+
+impl TimeWindowPlugin {
+    /// Plugin name, for configuration.
+    pub const PLUGIN_NAME: &'static str = "time-window";
+
+    /// Returns the configured gates along with the ones of the graph-data,
+    /// restricted to the requested channel.
+    fn gates(&self, graph: &cincinnati::Graph, channel: Option<&String>) -> Vec<Gate> {
+        let mut gates = self.gates.clone();
+        for release in graph.iter_releases() {
+            let release = match release {
+                Release::Concrete(release) => release,
+                Release::Abstract(_) => continue,
+            };
+            if let Some(windows) = release.metadata.get(self.windows_key.as_str()) {
+                gates.extend(self.release_gates(&release.version, windows));
+            }
+        }
+        gates.retain(|gate| {
+            gate.channels.is_empty()
+                || channel.is_some_and(|channel| gate.channels.contains(channel))
+        });
+        gates
+    }
+
+    /// Returns the gates of the graph-data windows of a release.
+    ///
+    /// Invalid windows are logged once and hold back the edges in all channels.
+    fn release_gates(&self, to: &str, windows: &str) -> Vec<Gate> {
+        let key = (to.to_string(), windows.to_string());
+        let mut parsed = self.parsed.lock().unwrap();
+        if let Some(gates) = parsed.get(&key) {
+            return gates.clone();
+        }
+
+        let gates = parse_gates(to, windows).unwrap_or_else(|e| {
+            error!(
+                "Holding back the edges to {} with invalid windows: {}",
+                to, e
+            );
+            vec![Gate {
+                to: to.to_string(),
+                from: None,
+                channels: vec![],
+                window: Window::Never,
+            }]
+        });
+        if parsed.len() >= MAX_PARSED_WINDOWS {
+            parsed.clear();
+        }
+        parsed.insert(key, gates.clone());
+        gates
+    }
+}
+
+/// Returns whether an edge is exposed at the given time: either no gate
+/// targets it, or one of the windows of the gates targeting it is open.
+fn is_exposed(gates: &[Gate], from: &str, to: &str, now: DateTime<Utc>) -> bool {
+    let mut targeting = gates
+        .iter()
+        .filter(|gate| gate.targets(from, to))
+        .peekable();
+    targeting.peek().is_none() || targeting.any(|gate| gate.window.contains(now))
+}
+
+#[async_trait]
+impl InternalPlugin for TimeWindowPlugin {
+    const PLUGIN_NAME: &'static str = Self::PLUGIN_NAME;
+
+    async fn run_internal(&self, io: InternalIO) -> Fallible<InternalIO> {
+        let mut graph = io.graph;
+        let gates = self.gates(&graph, io.parameters.get("channel"));
+        let now = Utc::now();
+
+        let mut held_back: Vec<(ReleaseId, ReleaseId)> = vec![];
+        for to_version in gates.iter().map(|gate| gate.to.as_str()) {
+            let to = match graph.find_by_version(to_version) {
+                Some(to) => to,
+                None => continue,
+            };
+            for (_, from, from_release) in graph.previous_releases(&to) {
+                let edge = (ReleaseId(from), to.clone());
+                if !held_back.contains(&edge)
+                    && !is_exposed(&gates, from_release.version(), to_version, now)
+                {
+                    held_back.push(edge);
+                }
+            }
+        }
+
+        for (from, to) in &held_back {
+            graph.remove_edge(from, to)?;
+        }
+        let mut removed_conditional = 0;
+        if let Some(conditional_edges) = graph.conditional_edges.as_mut() {
+            for conditional_edge in conditional_edges.iter_mut() {
+                let total_edges = conditional_edge.edges.len();
+                conditional_edge
+                    .edges
+                    .retain(|edge| is_exposed(&gates, &edge.from, &edge.to, now));
+                removed_conditional += total_edges - conditional_edge.edges.len();
+            }
+            conditional_edges.retain(|conditional_edge| !conditional_edge.edges.is_empty());
+        }
+        trace!(
+            "held back {} edges and {} conditional edges outside of their windows",
+            held_back.len(),
+            removed_conditional
+        );
+
+        Ok(InternalIO {
+            graph,
+            parameters: io.parameters,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use commons::testing::init_runtime;
+
+    fn time(time: &str) -> DateTime<Utc> {
+        parse_time(time).unwrap()
+    }
+
+    #[test]
+    fn parse_windows() -> Fallible<()> {
+        let gates = parse_gates(
+            "2.0.0",
+            "2022-10-03T00:00:00Z/; stable-2@0 9 * * 1-5 28800; /2022-10-01T00:00:00Z",
+        )?;
+        assert_eq!(gates.len(), 3);
+        assert_eq!(
+            gates[0].window,
+            Window::Between(Some(time("2022-10-03T00:00:00Z")), None)
+        );
+        assert_eq!(gates[1].channels, vec!["stable-2".to_string()]);
+
+        // Embargo until a point in time.
+        assert!(!gates[0].window.contains(time("2022-10-02T23:59:59Z")));
+        assert!(gates[0].window.contains(time("2022-10-03T00:00:00Z")));
+        // Weekdays from 09:00 to 17:00, 2022-10-07 is a Friday.
+        assert!(gates[1].window.contains(time("2022-10-07T16:59:00Z")));
+        assert!(!gates[1].window.contains(time("2022-10-07T17:00:00Z")));
+        assert!(!gates[1].window.contains(time("2022-10-08T12:00:00Z")));
+        // Until a point in time.
+        assert!(gates[2].window.contains(time("2022-09-30T00:00:00Z")));
+        assert!(!gates[2].window.contains(time("2022-10-01T00:00:00Z")));
+
+        // An edge is exposed during any of its windows.
+        assert!(is_exposed(
+            &gates,
+            "1.0.0",
+            "2.0.0",
+            time("2022-09-30T00:00:00Z")
+        ));
+        assert!(!is_exposed(
+            &gates,
+            "1.0.0",
+            "2.0.0",
+            time("2022-10-02T00:00:00Z")
+        ));
+        assert!(is_exposed(
+            &gates,
+            "1.0.0",
+            "3.0.0",
+            time("2022-10-02T00:00:00Z")
+        ));
+
+        for invalid in &[
+            "2022-10-03T00:00:00Z",
+            "/",
+            "2022-10-03T00:00:00Z/2022-10-01T00:00:00Z",
+            "0 9 * * 1-5",
+            "0 9 * * 1-5 0",
+            "0 25 * * * 3600",
+        ] {
+            assert!(invalid.parse::<Window>().is_err(), "{}", invalid);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn hold_back_edges() -> Fallible<()> {
+        let runtime = init_runtime()?;
+
+        // 0.0.0 -> 1.0.0 -> 2.0.0 and 0.0.0 -> 2.0.0
+        let mut metadata = HashMap::new();
+        metadata.insert(
+            "io.openshift.upgrades.graph.release.exposure-windows".to_string(),
+            "2999-01-01T00:00:00Z/".to_string(),
+        );
+        let graph = cincinnati::testing::generate_custom_graph(
+            "image",
+            vec![
+                (0, Default::default()),
+                (1, Default::default()),
+                (2, metadata.into_iter().collect()),
+            ],
+            Some(vec![(0, 1), (1, 2), (0, 2)]),
+        );
+
+        let settings = TimeWindowSettings::deserialize_config(toml::from_str(
+            r#"
+                name = "time-window"
+
+                [[windows]]
+                to = "1.0.0"
+                channels = ["fast-1"]
+                end = "2000-01-01T00:00:00Z"
+            "#,
+        )?)?;
+        let plugin = settings.build_plugin(None)?;
+
+        let edges = |channel: &str| -> Fallible<Vec<(String, String)>> {
+            let mut parameters = HashMap::new();
+            parameters.insert("channel".to_string(), channel.to_string());
+            let io = runtime.block_on(plugin.run(cincinnati::plugins::PluginIO::InternalIO(
+                InternalIO {
+                    graph: graph.clone(),
+                    parameters,
+                },
+            )))?;
+            let graph = cincinnati::testing::internal_io(io)?.graph;
+            Ok(graph
+                .iter_edges()
+                .map(|(from, to)| (from.version().to_string(), to.version().to_string()))
+                .collect())
+        };
+
+        // The embargo on 2.0.0 didn't lift yet, the window of 1.0.0 only
+        // applies to fast-1.
+        assert_eq!(
+            edges("stable-1")?,
+            vec![("0.0.0".to_string(), "1.0.0".to_string())]
+        );
+        assert!(edges("fast-1")?.is_empty());
+
+        for invalid in &[
+            r#"
+                name = "time-window"
+                [[windows]]
+                to = "1.0.0"
+            "#,
+            r#"
+                name = "time-window"
+                [[windows]]
+                to = "1.0.0"
+                start = "2000-01-01T00:00:00Z"
+                schedule = "0 9 * * *"
+                duration_secs = 3600
+            "#,
+        ] {
+            assert!(TimeWindowSettings::deserialize_config(toml::from_str(invalid)?).is_err());
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_windows_hold_back_edges() {
+        let plugin = TimeWindowPlugin {
+            windows_key: "io.openshift.upgrades.graph.release.exposure-windows".to_string(),
+            gates: vec![],
+            parsed: Default::default(),
+        };
+        let now = time("2022-10-02T00:00:00Z");
+
+        for _ in 0..2 {
+            let gates = plugin.release_gates("2.0.0", "2022-10-01T00:00:00Z/; tomorrow/");
+            assert_eq!(gates.len(), 1);
+            assert!(!is_exposed(&gates, "1.0.0", "2.0.0", now));
+        }
+        assert_eq!(plugin.parsed.lock().unwrap().len(), 1);
+
+        let gates = plugin.release_gates("2.0.0", "2022-10-01T00:00:00Z/");
+        assert!(is_exposed(&gates, "1.0.0", "2.0.0", now));
+    }
+}
@@ -0,0 +1,500 @@
+//! This plugin runs a WebAssembly module transforming the graph, so that
+//! graph-mutation policies can be shipped without recompiling Cincinnati.
+//!
+//! The module exports its `memory` and a `transform` function without
+//! parameters, returning 0 on success. It accesses the graph through the host
+//! functions of the `cincinnati` import module:
+//!
+//!  * `input_len() -> i32`: length of the input, in bytes.
+//!  * `input_read(ptr: i32)`: copy the input to `ptr` in the module memory.
+//!  * `output_write(ptr: i32, len: i32)`: set the output to `len` bytes at `ptr`.
+//!  * `fail(ptr: i32, len: i32)`: set the error message of a failed transform.
+//!  * `log(level: i32, ptr: i32, len: i32)`: log a message, from 0 (error) to 3 (debug).
+//!
+//! Input and output are JSON objects holding the Cincinnati JSON `graph` and
+//! the plugin `parameters`. Each run gets a fresh instance, bounded in memory,
+//! in fuel, i.e. in executed instructions, and in time.
+//!
+//! The plugin is only available with the `wasm` feature, as the WebAssembly
+//! runtime is a heavy dependency.
+
+use crate as cincinnati;
+
+use self::cincinnati::plugins::prelude::*;
+use self::cincinnati::plugins::prelude_plugin_impl::*;
+
+use log::log;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+use wasmtime::{
+    Caller, Config, Engine, Extern, ExternType, Linker, Memory, Module, Store, StoreLimits,
+    StoreLimitsBuilder, Trap,
+};
+
+/// Default fuel of a single run, roughly a few seconds of CPU time.
+pub static DEFAULT_FUEL: u64 = 10_000_000_000;
+
+/// Default maximum size of the module memory.
+pub static DEFAULT_MAX_MEMORY_BYTES: usize = 256 * 1024 * 1024;
+
+/// Default maximum duration of a single run, in seconds.
+pub static DEFAULT_TIMEOUT_SECS: u64 = 10;
+
+/// Interval of the epochs the run durations are measured in.
+static EPOCH_TICK: Duration = Duration::from_millis(100);
+
+/// Name of the import module of the host functions.
+static HOST_MODULE: &str = "cincinnati";
+
+/// Name of the exported transform function.
+static TRANSFORM_EXPORT: &str = "transform";
+
+/// Name of the exported memory.
+static MEMORY_EXPORT: &str = "memory";
+
+/// Maximum length of error and log messages, longer ones are truncated.
+static MAX_MESSAGE_LENGTH: usize = 1024;
+
+/// Plugin settings.
+#[derive(Clone, Debug, Deserialize, SmartDefault)]
+#[serde(default)]
+pub struct WasmTransformSettings {
+    /// Path of the WebAssembly module, either binary or text.
+    pub module_path: PathBuf,
+
+    /// Fuel of a single run, the run fails once it is consumed.
+    #[default(DEFAULT_FUEL)]
+    pub fuel: u64,
+
+    /// Maximum size of the module memory, in bytes.
+    #[default(DEFAULT_MAX_MEMORY_BYTES)]
+    pub max_memory_bytes: usize,
+
+    /// Maximum duration of a single run, in seconds, the run fails once it is reached.
+    #[default(DEFAULT_TIMEOUT_SECS)]
+    pub timeout_secs: u64,
+}
+
+impl PluginSettings for WasmTransformSettings {
+    fn build_plugin(&self, registry: Option<&prometheus::Registry>) -> Fallible<BoxedPlugin> {
+        let plugin = WasmTransformPlugin::try_new(self.clone(), registry)?;
+        Ok(new_plugin!(InternalPluginWrapper(plugin)))
+    }
+}
+
+impl WasmTransformSettings {
+    /// Validate plugin configuration and fill in defaults.
+    pub fn deserialize_config(cfg: toml::Value) -> Fallible<Box<dyn PluginSettings>> {
+        let settings: Self = cfg.try_into()?;
+
+        ensure!(
+            !settings.module_path.as_os_str().is_empty(),
+            "empty module_path"
+        );
+        ensure!(settings.fuel > 0, "fuel must be positive");
+        ensure!(
+            settings.max_memory_bytes > 0,
+            "max_memory_bytes must be positive"
+        );
+        ensure!(settings.timeout_secs > 0, "timeout_secs must be positive");
+
+        Ok(Box::new(settings))
+    }
+}
+
+/// Input and output of the module.
+#[derive(Debug, Serialize, Deserialize)]
+struct TransformIO {
+    graph: cincinnati::Graph,
+    #[serde(default)]
+    parameters: HashMap<String, String>,
+}
+
+/// State of a single run, available to the host functions.
+struct HostState {
+    input: Vec<u8>,
+    output: Option<Vec<u8>>,
+    error: Option<String>,
+    limits: StoreLimits,
+}
+
+/// Compiled module along with its host functions.
+struct Runtime {
+    engine: Engine,
+    module: Module,
+    linker: Linker<HostState>,
+    fuel: u64,
+    max_memory_bytes: usize,
+    /// Maximum duration of a run, in epochs.
+    deadline_epochs: u64,
+}
+
+/// Returns the exported memory of the calling module.
+fn memory(caller: &mut Caller<'_, HostState>) -> Result<Memory, Trap> {
+    caller
+        .get_export(MEMORY_EXPORT)
+        .and_then(Extern::into_memory)
+        .ok_or_else(|| Trap::new("module doesn't export its memory"))
+}
+
+/// Read `len` bytes at `ptr` from the memory of the calling module.
+fn read_memory(caller: &mut Caller<'_, HostState>, ptr: i32, len: i32) -> Result<Vec<u8>, Trap> {
+    let memory = memory(caller)?;
+    let (ptr, len) = (ptr as u32 as usize, len as u32 as usize);
+    if ptr.saturating_add(len) > memory.data_size(&caller) {
+        return Err(Trap::new("read out of the module memory"));
+    }
+
+    let mut buffer = vec![0; len];
+    memory
+        .read(&caller, ptr, &mut buffer)
+        .map_err(|e| Trap::new(e.to_string()))?;
+    Ok(buffer)
+}
+
+/// Read a message from the memory of the calling module.
+fn read_message(caller: &mut Caller<'_, HostState>, ptr: i32, len: i32) -> Result<String, Trap> {
+    let len = len.min(MAX_MESSAGE_LENGTH as i32);
+    let message = read_memory(caller, ptr, len)?;
+    Ok(String::from_utf8_lossy(&message).into_owned())
+}
+
+impl Runtime {
+    fn try_new(settings: &WasmTransformSettings) -> Fallible<Self> {
+        let mut config = Config::new();
+        config.consume_fuel(true);
+        config.epoch_interruption(true);
+        let engine = Engine::new(&config)?;
+
+        let module = Module::from_file(&engine, &settings.module_path).context(format!(
+            "loading WebAssembly module {}",
+            settings.module_path.display()
+        ))?;
+        ensure!(
+            matches!(
+                module.get_export(TRANSFORM_EXPORT),
+                Some(ExternType::Func(_))
+            ),
+            "module doesn't export a '{}' function",
+            TRANSFORM_EXPORT
+        );
+        ensure!(
+            matches!(
+                module.get_export(MEMORY_EXPORT),
+                Some(ExternType::Memory(_))
+            ),
+            "module doesn't export its '{}'",
+            MEMORY_EXPORT
+        );
+
+        let mut linker = Linker::new(&engine);
+        linker.func_wrap(
+            HOST_MODULE,
+            "input_len",
+            |caller: Caller<'_, HostState>| -> i32 { caller.data().input.len() as i32 },
+        )?;
+        linker.func_wrap(
+            HOST_MODULE,
+            "input_read",
+            |mut caller: Caller<'_, HostState>, ptr: i32| -> Result<(), Trap> {
+                let memory = memory(&mut caller)?;
+                let input = std::mem::take(&mut caller.data_mut().input);
+                let written = memory.write(&mut caller, ptr as u32 as usize, &input);
+                caller.data_mut().input = input;
+                written.map_err(|e| Trap::new(e.to_string()))
+            },
+        )?;
+        linker.func_wrap(
+            HOST_MODULE,
+            "output_write",
+            |mut caller: Caller<'_, HostState>, ptr: i32, len: i32| -> Result<(), Trap> {
+                let output = read_memory(&mut caller, ptr, len)?;
+                caller.data_mut().output = Some(output);
+                Ok(())
+            },
+        )?;
+        linker.func_wrap(
+            HOST_MODULE,
+            "fail",
+            |mut caller: Caller<'_, HostState>, ptr: i32, len: i32| -> Result<(), Trap> {
+                let message = read_message(&mut caller, ptr, len)?;
+                caller.data_mut().error = Some(message);
+                Ok(())
+            },
+        )?;
+        linker.func_wrap(
+            HOST_MODULE,
+            "log",
+            |mut caller: Caller<'_, HostState>,
+             level: i32,
+             ptr: i32,
+             len: i32|
+             -> Result<(), Trap> {
+                let message = read_message(&mut caller, ptr, len)?;
+                let level = match level {
+                    0 => log::Level::Error,
+                    1 => log::Level::Warn,
+                    2 => log::Level::Info,
+                    _ => log::Level::Debug,
+                };
+                log!(level, "[{}] {}", WasmTransformPlugin::PLUGIN_NAME, message);
+                Ok(())
+            },
+        )?;
+
+        Ok(Self {
+            engine,
+            module,
+            linker,
+            fuel: settings.fuel,
+            max_memory_bytes: settings.max_memory_bytes,
+            deadline_epochs: (Duration::from_secs(settings.timeout_secs).as_millis()
+                / EPOCH_TICK.as_millis()) as u64,
+        })
+    }
+
+    /// Advance the epoch of the engine until the runtime is dropped.
+    fn tick(runtime: Weak<Self>) {
+        std::thread::spawn(move || loop {
+            std::thread::sleep(EPOCH_TICK);
+            match runtime.upgrade() {
+                Some(runtime) => runtime.engine.increment_epoch(),
+                None => return,
+            }
+        });
+    }
+
+    /// Run the transform of a fresh instance on the given input.
+    fn transform(&self, input: Vec<u8>) -> Fallible<Vec<u8>> {
+        let limits = StoreLimitsBuilder::new()
+            .memory_size(self.max_memory_bytes)
+            .instances(1)
+            .build();
+        let mut store = Store::new(
+            &self.engine,
+            HostState {
+                input,
+                output: None,
+                error: None,
+                limits,
+            },
+        );
+        store.limiter(|state| &mut state.limits);
+        store.add_fuel(self.fuel)?;
+        store.set_epoch_deadline(self.deadline_epochs);
+
+        let instance = self
+            .linker
+            .instantiate(&mut store, &self.module)
+            .context("instantiating the module")?;
+        let transform = instance.get_typed_func::<(), i32, _>(&mut store, TRANSFORM_EXPORT)?;
+        let status = transform
+            .call(&mut store, ())
+            .context("running the transform")?;
+        trace!(
+            "[{}] consumed {} fuel",
+            WasmTransformPlugin::PLUGIN_NAME,
+            store.fuel_consumed().unwrap_or_default()
+        );
+
+        let state = store.into_data();
+        if status != 0 {
+            bail!(
+                "transform failed with status {}: {}",
+                status,
+                state.error.as_deref().unwrap_or("no error message")
+            );
+        }
+        state
+            .output
+            .ok_or_else(|| format_err!("transform didn't write any output"))
+    }
+}
+
+/// Transforms the graph with a WebAssembly module.
+#[derive(CustomDebug)]
+pub struct WasmTransformPlugin {
+    module_path: PathBuf,
+
+    #[debug(skip)]
+    runtime: Arc<Runtime>,
+}
+
+impl WasmTransformPlugin {
+    /// Plugin name, for configuration.
+    pub const PLUGIN_NAME: &'static str = "wasm-transform";
+
+    pub fn try_new(
+        settings: WasmTransformSettings,
+        _prometheus_registry: Option<&prometheus::Registry>,
+    ) -> Fallible<Self> {
+        let runtime = Arc::new(Runtime::try_new(&settings)?);
+        Runtime::tick(Arc::downgrade(&runtime));
+
+        Ok(Self {
+            module_path: settings.module_path,
+            runtime,
+        })
+    }
+}
+
+#[async_trait]
+impl InternalPlugin for WasmTransformPlugin {
+    const PLUGIN_NAME: &'static str = Self::PLUGIN_NAME;
+
+    async fn run_internal(&self, io: InternalIO) -> Fallible<InternalIO> {
+        let input = serde_json::to_vec(&TransformIO {
+            graph: io.graph,
+            parameters: io.parameters,
+        })?;
+
+        let runtime = self.runtime.clone();
+        let output = tokio::task::spawn_blocking(move || runtime.transform(input))
+            .await?
+            .context(format!(
+                "running WebAssembly module {}",
+                self.module_path.display()
+            ))?;
+        let output: TransformIO = serde_json::from_slice(&output).context(format!(
+            "WebAssembly module {} returned an invalid output",
+            self.module_path.display()
+        ))?;
+
+        Ok(InternalIO {
+            graph: output.graph,
+            parameters: output.parameters,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cincinnati::testing::generate_custom_graph;
+    use commons::testing::init_runtime;
+
+    /// Returns the output of the plugin running the given module in text format.
+    fn run(module: &str, fuel: u64) -> Fallible<InternalIO> {
+        run_with(
+            module,
+            WasmTransformSettings {
+                fuel,
+                ..Default::default()
+            },
+        )
+    }
+
+    /// Returns the output of the plugin running the given module with the given settings.
+    fn run_with(module: &str, settings: WasmTransformSettings) -> Fallible<InternalIO> {
+        let tmpdir = tempfile::tempdir()?;
+        let module_path = tmpdir.path().join("transform.wat");
+        std::fs::write(&module_path, module)?;
+
+        let plugin = WasmTransformPlugin::try_new(
+            WasmTransformSettings {
+                module_path,
+                ..settings
+            },
+            None,
+        )?;
+        init_runtime()?.block_on(
+            plugin.run_internal(InternalIO {
+                graph: input_graph(),
+                parameters: [("channel".to_string(), "stable-4.11".to_string())]
+                    .iter()
+                    .cloned()
+                    .collect(),
+            }),
+        )
+    }
+
+    fn input_graph() -> cincinnati::Graph {
+        generate_custom_graph(
+            "image",
+            (0..3).map(|i| (i, Default::default())).collect(),
+            Some(vec![(0, 1), (1, 2)]),
+        )
+    }
+
+    #[test]
+    fn identity_transform() -> Fallible<()> {
+        let output = run(
+            r#"(module
+                (import "cincinnati" "input_len" (func $input_len (result i32)))
+                (import "cincinnati" "input_read" (func $input_read (param i32)))
+                (import "cincinnati" "output_write" (func $output_write (param i32 i32)))
+                (memory (export "memory") 1)
+                (func (export "transform") (result i32)
+                    (call $input_read (i32.const 0))
+                    (call $output_write (i32.const 0) (call $input_len))
+                    (i32.const 0)))"#,
+            DEFAULT_FUEL,
+        )?;
+
+        assert_eq!(output.graph, input_graph());
+        assert_eq!(
+            output.parameters.get("channel"),
+            Some(&"stable-4.11".to_string())
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn failing_transforms() {
+        let failed = run(
+            r#"(module
+                (import "cincinnati" "fail" (func $fail (param i32 i32)))
+                (memory (export "memory") 1)
+                (data (i32.const 0) "no graph for you")
+                (func (export "transform") (result i32)
+                    (call $fail (i32.const 0) (i32.const 16))
+                    (i32.const 1)))"#,
+            DEFAULT_FUEL,
+        )
+        .unwrap_err();
+        assert!(format!("{:#}", failed).contains("no graph for you"));
+
+        // Endless loops run out of fuel.
+        assert!(run(
+            r#"(module
+                (memory (export "memory") 1)
+                (func (export "transform") (result i32)
+                    (loop $forever (br $forever))
+                    (i32.const 0)))"#,
+            1_000_000,
+        )
+        .is_err());
+
+        // Endless loops with enough fuel run out of time.
+        let started = std::time::Instant::now();
+        assert!(run_with(
+            r#"(module
+                (memory (export "memory") 1)
+                (func (export "transform") (result i32)
+                    (loop $forever (br $forever))
+                    (i32.const 0)))"#,
+            WasmTransformSettings {
+                fuel: i64::MAX as u64 / 2,
+                timeout_secs: 1,
+                ..Default::default()
+            },
+        )
+        .is_err());
+        assert!(started.elapsed() < Duration::from_secs(30));
+
+        // Memory beyond the limit can't be allocated.
+        assert!(run(
+            r#"(module
+                (memory (export "memory") 8192)
+                (func (export "transform") (result i32) (i32.const 0)))"#,
+            DEFAULT_FUEL,
+        )
+        .is_err());
+
+        // Modules without a transform are refused right away.
+        assert!(run(r#"(module (memory (export "memory") 1))"#, DEFAULT_FUEL).is_err());
+    }
+}
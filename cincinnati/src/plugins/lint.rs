@@ -0,0 +1,299 @@
+//! Plugin chain linter.
+//!
+//! Plugins rely on the work of the plugins before them in the chain, e.g. the
+//! secondary metadata parser reads the files downloaded by a scraper. A
+//! misordered chain doesn't fail, it silently produces a wrong graph, so the
+//! chain is checked against the dependencies of its plugins when it is built.
+//! A dependency configured after the plugin needing it is an error, while a
+//! missing one is only a warning as the plugin may not need it in every setup.
+
+use crate as cincinnati;
+
+use self::cincinnati::plugins::BoxedPlugin;
+
+use super::internal::arch_filter::ArchFilterPlugin;
+use super::internal::candidate_channel_derive::CandidateChannelDerivePlugin;
+use super::internal::channel_filter::ChannelFilterPlugin;
+use super::internal::cincinnati_graph_fetch::CincinnatiGraphFetchPlugin;
+use super::internal::cluster_profile_filter::ClusterProfileFilterPlugin;
+use super::internal::conditional_risk_evaluator::ConditionalRiskEvaluatorPlugin;
+use super::internal::dkrv2_openshift_secondary_metadata_scraper::DkrV2OpenshiftSecondaryMetadataScraperPlugin;
+use super::internal::edge_add_remove::EdgeAddRemovePlugin;
+use super::internal::github_openshift_secondary_metadata_scraper::GithubOpenshiftSecondaryMetadataScraperPlugin;
+use super::internal::graph_file_load::GraphFileLoadPlugin;
+use super::internal::local_openshift_secondary_metadata_scraper::LocalOpenshiftSecondaryMetadataScraperPlugin;
+use super::internal::metadata_fetch_quay::QuayMetadataFetchPlugin;
+use super::internal::multiarch_publication_gate::MultiArchPublicationGatePlugin;
+use super::internal::node_remove::NodeRemovePlugin;
+use super::internal::openshift_secondary_metadata_parser::OpenshiftSecondaryMetadataParserPlugin;
+use super::internal::phased_rollout::PhasedRolloutPlugin;
+use super::internal::release_cosign_verify::ReleaseCosignVerifyPlugin;
+use super::internal::release_enrichment_hook::ReleaseEnrichmentHookPlugin;
+use super::internal::release_notes_enrich::ReleaseNotesEnrichPlugin;
+use super::internal::release_scrape_dockerv2::ReleaseScrapeDockerv2Plugin;
+use super::internal::s3_openshift_secondary_metadata_scraper::S3OpenshiftSecondaryMetadataScraperPlugin;
+use super::internal::synthetic_releases::SyntheticReleasesPlugin;
+use super::internal::time_window::TimeWindowPlugin;
+use super::internal::version_filter::VersionFilterPlugin;
+use commons::prelude_errors::*;
+use log::warn;
+use std::fmt;
+
+/// Plugins building the graph from scratch.
+static GRAPH_SOURCES: &[&str] = &[
+    ReleaseScrapeDockerv2Plugin::PLUGIN_NAME,
+    CincinnatiGraphFetchPlugin::PLUGIN_NAME,
+    GraphFileLoadPlugin::PLUGIN_NAME,
+];
+
+/// Plugins downloading the secondary metadata.
+static SECONDARY_METADATA_SCRAPERS: &[&str] = &[
+    GithubOpenshiftSecondaryMetadataScraperPlugin::PLUGIN_NAME,
+    DkrV2OpenshiftSecondaryMetadataScraperPlugin::PLUGIN_NAME,
+    LocalOpenshiftSecondaryMetadataScraperPlugin::PLUGIN_NAME,
+    S3OpenshiftSecondaryMetadataScraperPlugin::PLUGIN_NAME,
+];
+
+/// Plugins adding the edge and node metadata consumed by the graph-editing plugins.
+static METADATA_PROVIDERS: &[&str] = &[
+    QuayMetadataFetchPlugin::PLUGIN_NAME,
+    OpenshiftSecondaryMetadataParserPlugin::PLUGIN_NAME,
+];
+
+/// Plugins adding the conditional edges and channel metadata.
+static SECONDARY_METADATA_PARSERS: &[&str] = &[OpenshiftSecondaryMetadataParserPlugin::PLUGIN_NAME];
+
+/// A plugin which must come after one of the given plugins.
+struct Dependency {
+    plugin: &'static str,
+    after: &'static [&'static str],
+    /// What the plugin needs from them, for messages.
+    needs: &'static str,
+}
+
+static DEPENDENCIES: &[Dependency] = &[
+    Dependency {
+        plugin: OpenshiftSecondaryMetadataParserPlugin::PLUGIN_NAME,
+        after: SECONDARY_METADATA_SCRAPERS,
+        needs: "the secondary metadata files",
+    },
+    Dependency {
+        plugin: EdgeAddRemovePlugin::PLUGIN_NAME,
+        after: METADATA_PROVIDERS,
+        needs: "the edge metadata",
+    },
+    Dependency {
+        plugin: NodeRemovePlugin::PLUGIN_NAME,
+        after: METADATA_PROVIDERS,
+        needs: "the node metadata",
+    },
+    Dependency {
+        plugin: ConditionalRiskEvaluatorPlugin::PLUGIN_NAME,
+        after: SECONDARY_METADATA_PARSERS,
+        needs: "the conditional edges",
+    },
+    Dependency {
+        plugin: CandidateChannelDerivePlugin::PLUGIN_NAME,
+        after: SECONDARY_METADATA_PARSERS,
+        needs: "the channel metadata",
+    },
+    Dependency {
+        plugin: ChannelFilterPlugin::PLUGIN_NAME,
+        after: GRAPH_SOURCES,
+        needs: "the graph",
+    },
+    Dependency {
+        plugin: ArchFilterPlugin::PLUGIN_NAME,
+        after: GRAPH_SOURCES,
+        needs: "the graph",
+    },
+    Dependency {
+        plugin: PhasedRolloutPlugin::PLUGIN_NAME,
+        after: GRAPH_SOURCES,
+        needs: "the graph",
+    },
+    Dependency {
+        plugin: TimeWindowPlugin::PLUGIN_NAME,
+        after: GRAPH_SOURCES,
+        needs: "the graph",
+    },
+    Dependency {
+        plugin: ClusterProfileFilterPlugin::PLUGIN_NAME,
+        after: GRAPH_SOURCES,
+        needs: "the graph",
+    },
+    Dependency {
+        plugin: ReleaseCosignVerifyPlugin::PLUGIN_NAME,
+        after: GRAPH_SOURCES,
+        needs: "the graph",
+    },
+    Dependency {
+        plugin: MultiArchPublicationGatePlugin::PLUGIN_NAME,
+        after: GRAPH_SOURCES,
+        needs: "the graph",
+    },
+    Dependency {
+        plugin: ReleaseEnrichmentHookPlugin::PLUGIN_NAME,
+        after: GRAPH_SOURCES,
+        needs: "the graph",
+    },
+    Dependency {
+        plugin: ReleaseNotesEnrichPlugin::PLUGIN_NAME,
+        after: GRAPH_SOURCES,
+        needs: "the graph",
+    },
+    Dependency {
+        plugin: SyntheticReleasesPlugin::PLUGIN_NAME,
+        after: GRAPH_SOURCES,
+        needs: "the graph",
+    },
+    Dependency {
+        plugin: VersionFilterPlugin::PLUGIN_NAME,
+        after: GRAPH_SOURCES,
+        needs: "the graph",
+    },
+];
+
+/// Severity of a chain issue.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Severity {
+    /// The chain may be intended, e.g. a dependency provided out of band.
+    Warning,
+    /// The chain produces a wrong graph.
+    Error,
+}
+
+/// An issue found in a plugin chain.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ChainIssue {
+    pub severity: Severity,
+    /// Position of the offending plugin in the chain.
+    pub position: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ChainIssue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "plugin #{}: {}", self.position, self.message)
+    }
+}
+
+/// Check the order of a chain of plugins, given by name.
+pub fn lint(chain: &[&str]) -> Vec<ChainIssue> {
+    let mut issues = vec![];
+
+    for (position, plugin) in chain.iter().enumerate() {
+        let (before, after) = (&chain[..position], &chain[position + 1..]);
+
+        if GRAPH_SOURCES.contains(plugin) {
+            if let Some(source) = before.iter().find(|name| GRAPH_SOURCES.contains(*name)) {
+                issues.push(ChainIssue {
+                    severity: Severity::Error,
+                    position,
+                    message: format!("'{}' replaces the graph built by '{}'", plugin, source),
+                });
+            }
+        }
+
+        for dependency in DEPENDENCIES.iter().filter(|dep| dep.plugin == *plugin) {
+            if before.iter().any(|name| dependency.after.contains(name)) {
+                continue;
+            }
+
+            let (severity, message) =
+                match after.iter().find(|name| dependency.after.contains(*name)) {
+                    Some(later) => (
+                        Severity::Error,
+                        format!(
+                            "'{}' needs {} from '{}', which comes later in the chain",
+                            plugin, dependency.needs, later
+                        ),
+                    ),
+                    None => (
+                        Severity::Warning,
+                        format!(
+                            "'{}' needs {} from one of '{}', none of which is configured before it",
+                            plugin,
+                            dependency.needs,
+                            dependency.after.join("', '")
+                        ),
+                    ),
+                };
+            issues.push(ChainIssue {
+                severity,
+                position,
+                message,
+            });
+        }
+    }
+
+    issues
+}
+
+/// Check the order of a chain of plugins, logging warnings and failing on errors.
+pub fn check(plugins: &[BoxedPlugin]) -> Fallible<()> {
+    let chain: Vec<&str> = plugins.iter().map(|plugin| plugin.get_name()).collect();
+    let issues = lint(&chain);
+
+    for issue in issues.iter().filter(|i| i.severity == Severity::Warning) {
+        warn!("plugin chain: {}", issue);
+    }
+
+    let errors: Vec<String> = issues
+        .iter()
+        .filter(|issue| issue.severity == Severity::Error)
+        .map(ToString::to_string)
+        .collect();
+    ensure!(
+        errors.is_empty(),
+        "misordered plugin chain: {}",
+        errors.join("; ")
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lint_chains() {
+        // Default chains.
+        assert!(lint(&[
+            "release-scrape-dockerv2",
+            "github-secondary-metadata-scrape",
+            "openshift-secondary-metadata-parse",
+            "edge-add-remove",
+        ])
+        .is_empty());
+        assert!(lint(&["cincinnati-graph-fetch", "channel-filter", "arch-filter"]).is_empty());
+
+        let issues = lint(&[
+            "release-scrape-dockerv2",
+            "openshift-secondary-metadata-parse",
+            "github-secondary-metadata-scrape",
+            "edge-add-remove",
+        ]);
+        assert_eq!(issues.len(), 1);
+        assert_eq!(issues[0].severity, Severity::Error);
+        assert_eq!(issues[0].position, 1);
+
+        let issues = lint(&[
+            "channel-filter",
+            "cincinnati-graph-fetch",
+            "release-scrape-dockerv2",
+        ]);
+        assert_eq!(
+            issues
+                .iter()
+                .map(|issue| (issue.position, issue.severity))
+                .collect::<Vec<_>>(),
+            vec![(0, Severity::Error), (2, Severity::Error)]
+        );
+
+        let issues = lint(&["channel-filter"]);
+        assert_eq!(issues.len(), 1);
+        assert_eq!(issues[0].severity, Severity::Warning);
+    }
+}
@@ -6,8 +6,11 @@ pub mod macros;
 
 pub mod catalog;
 pub mod external;
+pub mod guard;
+#[allow(mismatched_lifetime_syntaxes, renamed_and_removed_lints, unused_parens)]
 pub mod interface;
 pub mod internal;
+pub mod lint;
 
 use crate as cincinnati;
 
@@ -15,15 +18,49 @@ use self::cincinnati::plugins::interface::{PluginError, PluginExchange};
 
 use async_trait::async_trait;
 pub use commons::prelude_errors::*;
-use commons::tracing::get_tracer;
+use commons::tracing::{child_context, record_result};
+use prometheus::{HistogramOpts, HistogramVec, IntCounterVec, Opts, Registry};
 use std::collections::HashMap;
 use std::convert::{TryFrom, TryInto};
 use std::fmt::Debug;
+use std::ops::Deref;
+
+use opentelemetry::trace::{FutureExt, TraceContextExt};
+use opentelemetry::Key;
+
+lazy_static::lazy_static! {
+    static ref PLUGIN_DURATION: HistogramVec = HistogramVec::new(
+        HistogramOpts::new(
+            "plugin_duration_seconds",
+            "Execution time of plugin runs, failed ones included"
+        )
+        .buckets(vec![0.001, 0.01, 0.1, 0.5, 1.0, 5.0, 10.0, 30.0, 60.0, 300.0]),
+        &["plugin"]
+    )
+    .unwrap();
+    static ref PLUGIN_ERRORS: IntCounterVec = IntCounterVec::new(
+        Opts::new("plugin_errors_total", "Total number of plugin runs which returned an error"),
+        &["plugin"]
+    )
+    .unwrap();
+}
 
-use opentelemetry::{
-    trace::{mark_span_as_active, FutureExt, Tracer},
-    Context as ot_context,
-};
+/// Register the metrics of the plugin runs to a prometheus registry.
+///
+/// The metrics are shared by all plugins, so registering them again is not an error.
+pub fn register_metrics(registry: &Registry) -> Fallible<()> {
+    let collectors: Vec<Box<dyn prometheus::core::Collector>> = vec![
+        Box::new(PLUGIN_DURATION.clone()),
+        Box::new(PLUGIN_ERRORS.clone()),
+    ];
+    for collector in collectors {
+        match registry.register(collector) {
+            Ok(()) | Err(prometheus::Error::AlreadyReg) => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(())
+}
 
 pub mod prelude {
     use crate as cincinnati;
@@ -36,16 +73,27 @@ pub mod prelude {
     pub use plugins::internal::arch_filter::ArchFilterPlugin;
     pub use plugins::internal::channel_filter::ChannelFilterPlugin;
     pub use plugins::internal::cincinnati_graph_fetch::CincinnatiGraphFetchPlugin;
+    pub use plugins::internal::cluster_profile_filter::ClusterProfileFilterPlugin;
+    pub use plugins::internal::conditional_risk_evaluator::{
+        ConditionalRiskEvaluatorPlugin, ConditionalRiskEvaluatorSettings,
+    };
     pub use plugins::internal::edge_add_remove::EdgeAddRemovePlugin;
     pub use plugins::internal::github_openshift_secondary_metadata_scraper::{
         GithubOpenshiftSecondaryMetadataScraperPlugin,
         GithubOpenshiftSecondaryMetadataScraperSettings,
     };
+    pub use plugins::internal::graph_file_load::{GraphFileLoadPlugin, GraphFileLoadSettings};
     pub use plugins::internal::metadata_fetch_quay::QuayMetadataFetchPlugin;
     pub use plugins::internal::node_remove::NodeRemovePlugin;
     pub use plugins::internal::openshift_secondary_metadata_parser::{
         OpenshiftSecondaryMetadataParserPlugin, OpenshiftSecondaryMetadataParserSettings,
     };
+    pub use plugins::internal::release_cosign_verify::{
+        ReleaseCosignVerifyPlugin, ReleaseCosignVerifySettings,
+    };
+    pub use plugins::internal::release_enrichment_hook::{
+        ReleaseEnrichmentHookPlugin, ReleaseEnrichmentHookSettings,
+    };
     pub use plugins::internal::release_scrape_dockerv2::{
         ReleaseScrapeDockerv2Plugin, ReleaseScrapeDockerv2Settings,
     };
@@ -87,8 +135,7 @@ impl PartialEq<BoxedPlugin> for BoxedPlugin {
 }
 
 /// Enum for the two IO variants used by InternalPlugin and ExternalPlugin respectively
-#[derive(Debug)]
-#[cfg_attr(test, derive(Clone))]
+#[derive(Debug, Clone)]
 pub enum PluginIO {
     InternalIO(InternalIO),
     ExternalIO(ExternalIO),
@@ -117,8 +164,7 @@ pub struct InternalIO {
 }
 
 /// Struct used by the InternalPlugin trait impl's
-#[derive(Debug, PartialEq)]
-#[cfg_attr(test, derive(Clone))]
+#[derive(Debug, Clone, PartialEq)]
 pub struct ExternalIO {
     pub bytes: Vec<u8>,
 }
@@ -194,7 +240,6 @@ impl TryFrom<ExternalIO> for PluginExchange {
     fn try_from(external_io: ExternalIO) -> Fallible<Self> {
         protobuf::Message::parse_from_bytes(&external_io.bytes)
             .context("could not parse ExternalIO to PluginExchange")
-            .map_err(Into::into)
     }
 }
 
@@ -208,7 +253,6 @@ impl TryFrom<ExternalIO> for PluginError {
     fn try_from(external_io: ExternalIO) -> Fallible<Self> {
         protobuf::Message::parse_from_bytes(&external_io.bytes)
             .context("could not parse ExternalIO to PluginError")
-            .map_err(Into::into)
     }
 }
 
@@ -377,30 +421,55 @@ where
 
 /// Processes all given Plugins sequentially.
 ///
+/// The plugins are either borrowed for the whole process lifetime or shared,
+/// e.g. behind an `Arc`, so that replaced plugins can be dropped.
+///
 /// This function automatically converts between the different IO representations
-/// if necessary.
-pub async fn process<T>(plugins: T, initial_io: PluginIO) -> Fallible<InternalIO>
+/// if necessary. The execution time and the failure of each plugin run are
+/// recorded in the metrics labeled by plugin name.
+pub async fn process<T, P>(plugins: T, initial_io: PluginIO) -> Fallible<InternalIO>
 where
-    T: Iterator<Item = &'static BoxedPlugin>,
+    T: Iterator<Item = P>,
     T: Sync + Send,
     T: 'static,
+    P: Deref<Target = BoxedPlugin>,
+    P: Sync + Send,
+    P: 'static,
 {
-    let mut io = initial_io;
-
-    let span = get_tracer().start("plugins");
-    let _active_span = mark_span_as_active(span);
-
-    for next_plugin in plugins {
-        let plugin_name = next_plugin.get_name();
-        log::trace!("Running next plugin '{}'", plugin_name);
+    let cx = child_context("plugins", vec![]);
+    async move {
+        let mut io = initial_io;
+
+        // Each plugin gets its own span, annotated with the size of the graph
+        // it returned.
+        for next_plugin in plugins {
+            let plugin_name = next_plugin.get_name();
+            log::trace!("Running next plugin '{}'", plugin_name);
+
+            let plugin_cx = child_context(plugin_name, vec![]);
+            let timer = PLUGIN_DURATION
+                .with_label_values(&[plugin_name])
+                .start_timer();
+            let result = next_plugin.run(io).with_context(plugin_cx.clone()).await;
+            timer.observe_duration();
+            if result.is_err() {
+                PLUGIN_ERRORS.with_label_values(&[plugin_name]).inc();
+            }
+            record_result(&plugin_cx, &result);
+            io = result?;
+            if let PluginIO::InternalIO(internal_io) = &io {
+                let span = plugin_cx.span();
+                span.set_attribute(
+                    Key::new("releases").i64(internal_io.graph.releases_count() as i64),
+                );
+                span.set_attribute(Key::new("edges").i64(internal_io.graph.edges_count() as i64));
+            }
+        }
 
-        let plugin_span = get_tracer().start(plugin_name);
-        let _active_plugin_span = mark_span_as_active(plugin_span);
-        let cx = ot_context::current();
-        io = next_plugin.run(io).with_context(cx).await?;
+        io.try_into()
     }
-
-    io.try_into()
+    .with_context(cx)
+    .await
 }
 
 /// Wrapper around `process` with an optional timeout.
@@ -413,20 +482,26 @@ where
 /// 1. Use the runtime's internal timeout implementation which works for proper async tasks.
 /// 2. Spawn a separate sleeper thread to enforce a deadline of 101% of the timeout
 ///    in case the async timeout is not effective.
-pub fn process_blocking<T>(
+pub fn process_blocking<T, P>(
     plugins: T,
     initial_io: PluginIO,
     timeout: Option<std::time::Duration>,
 ) -> Fallible<InternalIO>
 where
-    T: Iterator<Item = &'static BoxedPlugin>,
+    T: Iterator<Item = P>,
     T: Sync + Send,
     T: 'static,
+    P: Deref<Target = BoxedPlugin>,
+    P: Sync + Send,
+    P: 'static,
 {
     let runtime = tokio::runtime::Runtime::new()?;
 
+    // The plugins may run on another thread, so the tracing context is passed on.
+    let cx = opentelemetry::Context::current();
+
     let timeout = match timeout {
-        None => return runtime.block_on(process(plugins, initial_io)),
+        None => return runtime.block_on(process(plugins, initial_io).with_context(cx)),
         Some(timeout) => timeout,
     };
     let deadline = timeout + (timeout / 100);
@@ -437,15 +512,15 @@ where
         let tx = tx.clone();
 
         std::thread::spawn(move || {
-            let io_future =
-                async { tokio::time::timeout(timeout, process(plugins, initial_io)).await };
+            let io_future = async {
+                tokio::time::timeout(timeout, process(plugins, initial_io).with_context(cx)).await
+            };
             let io_result = runtime
                 .block_on(io_future)
                 .context(format!(
                     "Processing all plugins with a timeout of {:?}",
                     timeout
                 ))
-                .map_err(Error::from)
                 .unwrap_or_else(Err);
 
             // This may fail if it's attempted after the timeout is exceeded.
@@ -596,6 +671,64 @@ mod tests {
         Ok(())
     }
 
+    #[derive(Debug)]
+    struct TestFailingPlugin {}
+    #[async_trait]
+    impl InternalPlugin for TestFailingPlugin {
+        const PLUGIN_NAME: &'static str = "test_failing_plugin";
+
+        async fn run_internal(&self, _: InternalIO) -> Fallible<InternalIO> {
+            bail!("failing on purpose")
+        }
+    }
+
+    #[test]
+    fn process_plugins_records_metrics() -> Fallible<()> {
+        let runtime = commons::testing::init_runtime()?;
+
+        lazy_static! {
+            static ref PLUGINS: Vec<BoxedPlugin> = new_plugins!(
+                ExternalPluginWrapper(TestExternalPlugin {}),
+                InternalPluginWrapper(TestFailingPlugin {})
+            );
+        }
+
+        let registry = Registry::new();
+        register_metrics(&registry)?;
+        register_metrics(&registry)?;
+
+        let initial_internalio = InternalIO {
+            graph: generate_graph(false, false),
+            parameters: Default::default(),
+        };
+        let result = runtime.block_on(process(
+            PLUGINS.iter(),
+            PluginIO::InternalIO(initial_internalio),
+        ));
+        assert!(result.is_err());
+
+        let duration = |plugin: &str| {
+            PLUGIN_DURATION
+                .with_label_values(&[plugin])
+                .get_sample_count()
+        };
+        assert!(duration(TestExternalPlugin::PLUGIN_NAME) >= 1);
+        assert_eq!(duration(TestFailingPlugin::PLUGIN_NAME), 1);
+        assert_eq!(
+            PLUGIN_ERRORS
+                .with_label_values(&[TestFailingPlugin::PLUGIN_NAME])
+                .get(),
+            1
+        );
+
+        let families = registry.gather();
+        let names: Vec<&str> = families.iter().map(|family| family.get_name()).collect();
+        assert!(names.contains(&"plugin_duration_seconds"));
+        assert!(names.contains(&"plugin_errors_total"));
+
+        Ok(())
+    }
+
     #[test]
     fn process_plugins_loop() -> Fallible<()> {
         let runtime = commons::testing::init_runtime()?;
@@ -0,0 +1,165 @@
+//! Provenance of a published graph.
+//!
+//! Graph responses carry headers identifying the graph they were computed
+//! from: its revision, the hash of its content, the time it was generated at
+//! and the upstream it was scraped from. Policy-engine passes on the
+//! provenance of the graph it fetched, so that a bad recommendation reported
+//! by a client can be traced back to the graph-builder artifact behind it.
+
+use reqwest::header::HeaderMap;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+
+/// Header carrying the revision of the graph.
+pub static REVISION_HEADER: &str = "cincinnati-graph-revision";
+
+/// Header carrying the hash of the graph content.
+pub static HASH_HEADER: &str = "cincinnati-graph-hash";
+
+/// Header carrying the time the graph was generated at, in RFC 3339 format.
+pub static GENERATED_HEADER: &str = "cincinnati-graph-generated";
+
+/// Header carrying the identifier of the upstream the graph was scraped from.
+pub static SOURCE_HEADER: &str = "cincinnati-graph-source";
+
+/// Header carrying the time of the last successful scrape of the graph, in
+/// RFC 3339 format.
+///
+/// Unlike the generation time, it advances even if the graph doesn't change,
+/// and isn't part of the provenance of the graph.
+pub static REFRESHED_HEADER: &str = "cincinnati-graph-refreshed";
+
+/// Prefix of the plugin parameters under which the provenance is passed on.
+static PARAM_KEY_PREFIX: &str = "io.openshift.upgrades.graph.provenance.";
+
+/// Provenance of a published graph.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct Provenance {
+    /// Revision of the graph.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub revision: Option<u64>,
+    /// Hash of the graph content, as `sha256:<hex>`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hash: Option<String>,
+    /// Time the graph was generated at, in RFC 3339 format.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub generated: Option<String>,
+    /// Identifier of the upstream the graph was scraped from.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+}
+
+/// Returns the hash of a graph content, as `sha256:<hex>`.
+pub fn content_hash(content: &[u8]) -> String {
+    format!("sha256:{}", hex::encode(Sha256::digest(content)))
+}
+
+impl Provenance {
+    /// Returns the name and value of each known provenance header.
+    pub fn headers(&self) -> Vec<(&'static str, String)> {
+        vec![
+            (REVISION_HEADER, self.revision.map(|r| r.to_string())),
+            (HASH_HEADER, self.hash.clone()),
+            (GENERATED_HEADER, self.generated.clone()),
+            (SOURCE_HEADER, self.source.clone()),
+        ]
+        .into_iter()
+        .filter_map(|(name, value)| value.map(|value| (name, value)))
+        .collect()
+    }
+
+    /// Read the provenance from response headers, if any.
+    pub fn from_headers(headers: &HeaderMap) -> Option<Self> {
+        let get = |name: &str| {
+            headers
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string)
+        };
+        let provenance = Self {
+            revision: get(REVISION_HEADER).and_then(|revision| revision.parse().ok()),
+            hash: get(HASH_HEADER),
+            generated: get(GENERATED_HEADER),
+            source: get(SOURCE_HEADER),
+        };
+
+        if provenance == Self::default() {
+            None
+        } else {
+            Some(provenance)
+        }
+    }
+
+    /// Store the provenance in plugin parameters.
+    pub fn insert_into(&self, parameters: &mut HashMap<String, String>) {
+        for (name, value) in self.headers() {
+            parameters.insert(param_key(name), value);
+        }
+    }
+
+    /// Read the provenance from plugin parameters, if any.
+    pub fn from_parameters(parameters: &HashMap<String, String>) -> Option<Self> {
+        let mut headers = HeaderMap::new();
+        for name in &[
+            REVISION_HEADER,
+            HASH_HEADER,
+            GENERATED_HEADER,
+            SOURCE_HEADER,
+        ] {
+            if let Some(value) = parameters
+                .get(&param_key(name))
+                .and_then(|value| value.parse().ok())
+            {
+                headers.insert(*name, value);
+            }
+        }
+        Self::from_headers(&headers)
+    }
+}
+
+/// Returns the plugin parameter key of a provenance header.
+fn param_key(header: &str) -> String {
+    format!(
+        "{}{}",
+        PARAM_KEY_PREFIX,
+        header.trim_start_matches("cincinnati-graph-")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn provenance_roundtrip() {
+        let provenance = Provenance {
+            revision: Some(42),
+            hash: Some(content_hash(b"{}")),
+            generated: Some("2022-10-05T12:00:00+00:00".to_string()),
+            source: Some("quay.io/openshift-release-dev/ocp-release".to_string()),
+        };
+        assert_eq!(
+            provenance.hash.as_deref(),
+            Some("sha256:44136fa355b3678a1146ad16f7e8649e94fb4fc21fe77e8310c060f61caaff8a")
+        );
+
+        let mut headers = HeaderMap::new();
+        for (name, value) in provenance.headers() {
+            headers.insert(name, value.parse().unwrap());
+        }
+        assert_eq!(Provenance::from_headers(&headers), Some(provenance.clone()));
+
+        let mut parameters = HashMap::new();
+        provenance.insert_into(&mut parameters);
+        assert_eq!(
+            parameters
+                .get("io.openshift.upgrades.graph.provenance.revision")
+                .map(String::as_str),
+            Some("42")
+        );
+        assert_eq!(Provenance::from_parameters(&parameters), Some(provenance));
+
+        assert_eq!(Provenance::from_headers(&HeaderMap::new()), None);
+        assert_eq!(Provenance::from_parameters(&HashMap::new()), None);
+    }
+}
@@ -0,0 +1,171 @@
+//! Process-wide cache of parsed semantic versions.
+//!
+//! Every scrape parses the same version strings over and over, in the
+//! graph-data parser and in the filtering plugins. Successfully parsed values
+//! are kept in a bounded LRU cache, parse errors are not cached.
+//!
+//! The cache is split in shards locked independently, so that plugins parsing
+//! versions concurrently, e.g. the scrape loops of several tenants and the
+//! policy-engine requests, rarely wait for each other.
+
+use cached::{Cached, SizedCache};
+use commons::prelude_errors::*;
+use prometheus::{IntCounterVec, Opts, Registry};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::sync::{Mutex, MutexGuard};
+
+/// Maximum number of cached values of each type.
+pub static CACHE_SIZE: usize = 16 * 1024;
+
+/// Number of shards of each cache.
+static SHARDS: usize = 16;
+
+/// LRU cache split in independently locked shards, by hash of the key.
+struct ShardedCache<T> {
+    shards: Vec<Mutex<SizedCache<String, T>>>,
+}
+
+impl<T> ShardedCache<T> {
+    fn new() -> Self {
+        Self {
+            shards: (0..SHARDS)
+                .map(|_| Mutex::new(SizedCache::with_size(CACHE_SIZE / SHARDS)))
+                .collect(),
+        }
+    }
+
+    /// Returns the locked shard holding `key`.
+    fn shard(&self, key: &str) -> MutexGuard<'_, SizedCache<String, T>> {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        self.shards[hasher.finish() as usize % SHARDS]
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn len(&self) -> usize {
+        self.shards
+            .iter()
+            .map(|shard| {
+                shard
+                    .lock()
+                    .unwrap_or_else(|poisoned| poisoned.into_inner())
+                    .cache_size()
+            })
+            .sum()
+    }
+
+    fn clear(&self) {
+        for shard in &self.shards {
+            shard
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+                .cache_clear();
+        }
+    }
+}
+
+lazy_static::lazy_static! {
+    static ref VERSIONS: ShardedCache<semver::Version> = ShardedCache::new();
+    static ref VERSION_REQS: ShardedCache<semver::VersionReq> = ShardedCache::new();
+    static ref CACHE_HITS: IntCounterVec = IntCounterVec::new(
+        Opts::new(
+            "semver_parse_cache_hits_total",
+            "Total number of semantic versions found in the parse cache"
+        ),
+        &["type"]
+    )
+    .unwrap();
+    static ref CACHE_MISSES: IntCounterVec = IntCounterVec::new(
+        Opts::new(
+            "semver_parse_cache_misses_total",
+            "Total number of semantic versions which had to be parsed"
+        ),
+        &["type"]
+    )
+    .unwrap();
+}
+
+/// Register relevant metrics to a prometheus registry.
+pub fn register_metrics(registry: &Registry) -> Fallible<()> {
+    registry.register(Box::new(CACHE_HITS.clone()))?;
+    registry.register(Box::new(CACHE_MISSES.clone()))?;
+    Ok(())
+}
+
+fn cached_parse<T, E, F>(cache: &ShardedCache<T>, kind: &str, input: &str, parse: F) -> Result<T, E>
+where
+    T: Clone,
+    F: FnOnce(&str) -> Result<T, E>,
+{
+    let key = input.to_string();
+    if let Some(value) = cache.shard(input).cache_get(&key) {
+        CACHE_HITS.with_label_values(&[kind]).inc();
+        return Ok(value.clone());
+    }
+
+    CACHE_MISSES.with_label_values(&[kind]).inc();
+    let value = parse(input)?;
+    cache.shard(input).cache_set(key, value.clone());
+
+    Ok(value)
+}
+
+/// Parse a semantic version, like `semver::Version::parse`.
+pub fn parse_version(input: &str) -> Result<semver::Version, semver::SemVerError> {
+    cached_parse(&VERSIONS, "version", input, semver::Version::parse)
+}
+
+/// Parse a semantic version requirement, like `semver::VersionReq::parse`.
+pub fn parse_version_req(input: &str) -> Result<semver::VersionReq, semver::ReqParseError> {
+    cached_parse(
+        &VERSION_REQS,
+        "requirement",
+        input,
+        semver::VersionReq::parse,
+    )
+}
+
+/// Returns the number of cached values, versions and requirements together.
+pub fn len() -> usize {
+    VERSIONS.len() + VERSION_REQS.len()
+}
+
+/// Drop all cached values, e.g. to release memory.
+pub fn clear() {
+    VERSIONS.clear();
+    VERSION_REQS.clear();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_cached() {
+        let hits = || CACHE_HITS.with_label_values(&["version"]).get();
+        let misses = || CACHE_MISSES.with_label_values(&["version"]).get();
+
+        let version = "4.10.3-0.nightly+amd64.cache-test";
+        let (hits_before, misses_before) = (hits(), misses());
+        assert_eq!(
+            parse_version(version).unwrap(),
+            semver::Version::parse(version).unwrap()
+        );
+        assert_eq!(
+            parse_version(version).unwrap(),
+            semver::Version::parse(version).unwrap()
+        );
+        assert!(misses() > misses_before);
+        assert!(hits() > hits_before);
+
+        // Errors are not cached.
+        assert!(parse_version("4.10").is_err());
+        assert!(parse_version("4.10").is_err());
+
+        assert!(parse_version_req(">= 4.9.0, < 4.11.0")
+            .unwrap()
+            .matches(&parse_version("4.10.3").unwrap()));
+    }
+}
@@ -0,0 +1,258 @@
+//! Version 2 of the graph format.
+//!
+//! Version 1 lists the edges as pairs of node indices, and the conditional
+//! edges separately. Version 2 lists every edge once, as an object referring to
+//! its nodes by version, so that edges keep their identity across responses
+//! and graph revisions. Edges carry the risks of conditional updates and the
+//! channels they are part of. Nodes carry the well-known release metadata as
+//! structured fields, along with the raw metadata.
+
+use crate::metadata_keys::MetadataKeys;
+use crate::provenance::Provenance;
+use crate::{ConditionalUpdateRisk, Graph, IStr, MapImpl, Release};
+use std::collections::HashMap;
+
+/// Media type of version 2 graphs.
+pub const CONTENT_TYPE: &str = "application/vnd.redhat.cincinnati.v2+json";
+
+/// Version of the format, in the `version` field of the graphs.
+pub const VERSION: i32 = 2;
+
+/// A release.
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Node<'a> {
+    pub version: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub payload: Option<&'a str>,
+    /// Architecture of the release payload.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub architecture: Option<&'a str>,
+    /// Date the release was published at.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub release_date: Option<&'a str>,
+    /// Identifier of the errata of the release.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub errata: Option<&'a str>,
+    /// URL of the release notes.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub release_notes: Option<&'a str>,
+    /// Channels the release is part of.
+    pub channels: Vec<&'a str>,
+    /// All metadata of the release, including the structured fields.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<&'a MapImpl<IStr, IStr>>,
+}
+
+impl<'a> Node<'a> {
+    fn new(release: &'a Release, keys: &MetadataKeys) -> Self {
+        let metadata = match release {
+            Release::Concrete(release) => Some(&release.metadata),
+            Release::Abstract(_) => None,
+        };
+        let get = |key: &str| metadata.and_then(|metadata| metadata.get(key).map(IStr::as_str));
+
+        Self {
+            version: release.version(),
+            payload: match release {
+                Release::Concrete(release) => Some(release.payload.as_str()),
+                Release::Abstract(_) => None,
+            },
+            architecture: get(&keys.arch),
+            release_date: get(&keys.date),
+            errata: get(&keys.errata),
+            release_notes: get(&keys.notes_url),
+            channels: get(&keys.channels)
+                .map(|channels| {
+                    channels
+                        .split(',')
+                        .map(str::trim)
+                        .filter(|channel| !channel.is_empty())
+                        .collect()
+                })
+                .unwrap_or_default(),
+            metadata,
+        }
+    }
+}
+
+/// An update from one release to another.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Edge<'a> {
+    pub from: &'a str,
+    pub to: &'a str,
+    /// Channels both releases are part of.
+    pub channels: Vec<&'a str>,
+    /// Risks of the update, empty if it is recommended unconditionally.
+    pub risks: Vec<&'a ConditionalUpdateRisk>,
+}
+
+/// A graph in the version 2 format.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct GraphV2<'a> {
+    pub version: i32,
+    pub nodes: Vec<Node<'a>>,
+    pub edges: Vec<Edge<'a>>,
+    /// Provenance of the graph, if known.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub provenance: Option<Provenance>,
+}
+
+impl<'a> GraphV2<'a> {
+    /// Convert a graph to the version 2 format.
+    ///
+    /// Conditional edges which are also regular edges are listed once, with
+    /// their risks. The well-known metadata is read from the given keys.
+    pub fn new(graph: &'a Graph, provenance: Option<Provenance>, keys: &MetadataKeys) -> Self {
+        let nodes: Vec<Node<'a>> = graph
+            .dag
+            .raw_nodes()
+            .iter()
+            .map(|node| Node::new(node.weight.as_ref(), keys))
+            .collect();
+        let channels: HashMap<&str, &[&str]> = nodes
+            .iter()
+            .map(|node| (node.version, node.channels.as_slice()))
+            .collect();
+        let edge = |from: &'a str, to: &'a str| Edge {
+            from,
+            to,
+            channels: match (channels.get(from), channels.get(to)) {
+                (Some(from), Some(to)) => from
+                    .iter()
+                    .filter(|channel| to.contains(*channel))
+                    .cloned()
+                    .collect(),
+                _ => vec![],
+            },
+            risks: vec![],
+        };
+
+        let mut edges: Vec<Edge<'a>> = graph
+            .dag
+            .raw_edges()
+            .iter()
+            .map(|raw| {
+                let version = |index| {
+                    graph
+                        .dag
+                        .node_weight(index)
+                        .expect(crate::EXPECT_NODE_WEIGHT)
+                        .version()
+                };
+                edge(version(raw.source()), version(raw.target()))
+            })
+            .collect();
+        let mut positions: HashMap<(&str, &str), usize> = edges
+            .iter()
+            .enumerate()
+            .map(|(position, edge)| ((edge.from, edge.to), position))
+            .collect();
+        for conditional_edge in graph.conditional_edges.iter().flatten() {
+            for update in &conditional_edge.edges {
+                let key = (update.from.as_str(), update.to.as_str());
+                let position = *positions.entry(key).or_insert_with(|| {
+                    edges.push(edge(&update.from, &update.to));
+                    edges.len() - 1
+                });
+                edges[position].risks.extend(conditional_edge.risks.iter());
+            }
+        }
+
+        Self {
+            version: VERSION,
+            nodes,
+            edges,
+            provenance,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::generate_graph;
+    use crate::ConcreteRelease;
+
+    #[test]
+    fn serialize_v2() {
+        let keys = MetadataKeys::with_prefix("com.example.graph");
+        let mut graph = generate_graph(true, false);
+        for (version, channels) in &[
+            ("1.0.0", "stable-1,fast-1"),
+            ("2.0.0", "fast-1"),
+            ("3.0.0", "stable-1, fast-1"),
+        ] {
+            let id = graph.find_by_version(version).unwrap();
+            let metadata = graph.get_metadata_as_ref_mut(&id).unwrap();
+            metadata.insert(keys.channels.as_str().into(), (*channels).into());
+        }
+        let id = graph.find_by_version("3.0.0").unwrap();
+        let metadata = graph.get_metadata_as_ref_mut(&id).unwrap();
+        metadata.insert(keys.arch.as_str().into(), "amd64".into());
+        metadata.insert(keys.errata.as_str().into(), "RHBA-2024:0001".into());
+        metadata.insert(keys.date.as_str().into(), "2024-01-15".into());
+
+        let provenance = Provenance {
+            revision: Some(7),
+            ..Default::default()
+        };
+        let v2 = GraphV2::new(&graph, Some(provenance), &keys);
+
+        assert_eq!(v2.nodes[0].channels, vec!["stable-1", "fast-1"]);
+        assert_eq!(v2.nodes[2].architecture, Some("amd64"));
+        assert_eq!(v2.nodes[2].release_date, Some("2024-01-15"));
+        assert_eq!(v2.nodes[2].errata, Some("RHBA-2024:0001"));
+        assert_eq!(v2.nodes[2].release_notes, None);
+
+        let edges: Vec<(&str, &str, Vec<&str>, usize)> = v2
+            .edges
+            .iter()
+            .map(|edge| (edge.from, edge.to, edge.channels.clone(), edge.risks.len()))
+            .collect();
+        assert_eq!(
+            edges,
+            vec![
+                ("1.0.0", "2.0.0", vec!["fast-1"], 1),
+                ("2.0.0", "3.0.0", vec!["fast-1"], 0),
+                ("1.0.0", "3.0.0", vec!["stable-1", "fast-1"], 0),
+            ]
+        );
+        assert_eq!(v2.edges[0].risks[0].name, "BrokenUpdates");
+
+        let json: serde_json::Value = serde_json::to_value(&v2).unwrap();
+        assert_eq!(json["version"], 2);
+        assert_eq!(json["provenance"]["revision"], 7);
+        assert_eq!(json["nodes"][2]["releaseDate"], "2024-01-15");
+        assert_eq!(
+            json["edges"][0]["risks"][0]["matchingRules"][0]["type"],
+            "PromQL"
+        );
+        assert!(json["nodes"][0].get("architecture").is_none());
+    }
+
+    #[test]
+    fn conditional_only_edges() {
+        let mut graph = Graph::default();
+        for version in &["1.0.0", "2.0.0"] {
+            graph
+                .add_release(Release::Concrete(ConcreteRelease {
+                    version: (*version).into(),
+                    payload: format!("image/{}", version),
+                    metadata: Default::default(),
+                }))
+                .unwrap();
+        }
+        let mut conditional = generate_graph(true, true);
+        graph.conditional_edges = conditional.conditional_edges.take();
+
+        let v2 = GraphV2::new(&graph, None, &MetadataKeys::default());
+        assert_eq!(v2.edges.len(), 1);
+        assert_eq!((v2.edges[0].from, v2.edges[0].to), ("1.0.0", "2.0.0"));
+        assert_eq!(v2.edges[0].risks[0].name, "AllBrokenUpdates");
+        assert!(serde_json::to_value(&v2)
+            .unwrap()
+            .get("provenance")
+            .is_none());
+    }
+}
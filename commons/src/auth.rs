@@ -0,0 +1,300 @@
+//! Authentication of the status services.
+//!
+//! Some deployments must not expose metrics and health endpoints
+//! unauthenticated, even on the status port. The status services can then
+//! require a bearer token, basic authentication credentials, or either.
+
+use crate::prelude_errors::*;
+use crate::GraphError;
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse};
+use actix_web::http::header::{self, HeaderMap};
+use futures::future::{self, Either, Ready};
+use std::path::Path;
+
+/// Credentials accepted by a status service.
+#[derive(Clone, Default)]
+pub struct Credentials {
+    token: Option<String>,
+    users: Vec<(String, String)>,
+}
+
+impl std::fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Credentials")
+            .field("token", &self.token.as_ref().map(|_| "<redacted>"))
+            .field(
+                "users",
+                &self.users.iter().map(|(user, _)| user).collect::<Vec<_>>(),
+            )
+            .finish()
+    }
+}
+
+impl Credentials {
+    /// Read the bearer token and the basic authentication credentials, if configured.
+    ///
+    /// The token file holds the token, surrounding whitespace excluded. The
+    /// credentials file holds one `user:password` pair per line, empty lines
+    /// and lines starting with `#` being ignored.
+    pub fn read(token_path: Option<&Path>, users_path: Option<&Path>) -> Fallible<Option<Self>> {
+        if token_path.is_none() && users_path.is_none() {
+            return Ok(None);
+        }
+
+        let token = match token_path {
+            Some(path) => {
+                let token = std::fs::read_to_string(path)
+                    .context(format!("reading status token from {}", path.display()))?
+                    .trim()
+                    .to_string();
+                ensure!(
+                    !token.is_empty(),
+                    "empty status token in {}",
+                    path.display()
+                );
+                Some(token)
+            }
+            None => None,
+        };
+        let users = match users_path {
+            Some(path) => {
+                let content = std::fs::read_to_string(path).context(format!(
+                    "reading status credentials from {}",
+                    path.display()
+                ))?;
+                let users = Self::parse_users(&content)
+                    .context(format!("invalid status credentials in {}", path.display()))?;
+                ensure!(
+                    !users.is_empty(),
+                    "no status credentials in {}",
+                    path.display()
+                );
+                users
+            }
+            None => vec![],
+        };
+
+        Ok(Some(Self { token, users }))
+    }
+
+    /// Parse `user:password` lines.
+    fn parse_users(content: &str) -> Fallible<Vec<(String, String)>> {
+        content
+            .lines()
+            .map(str::trim)
+            .enumerate()
+            .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+            .map(|(index, line)| match line.split_once(':') {
+                Some((user, password)) if !user.is_empty() && !password.is_empty() => {
+                    Ok((user.to_string(), password.to_string()))
+                }
+                _ => bail!("line {} is not of the form 'user:password'", index + 1),
+            })
+            .collect()
+    }
+
+    /// Check the `Authorization` header of a request.
+    pub fn check(&self, req: &ServiceRequest) -> Result<(), GraphError> {
+        let authorization = req
+            .headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default();
+
+        let authorized = if authorization.starts_with("Bearer ") {
+            self.token
+                .as_deref()
+                .is_some_and(|expected| has_bearer_token(req.headers(), expected))
+        } else if let Some(encoded) = authorization.strip_prefix("Basic ") {
+            base64::decode(encoded.trim())
+                .ok()
+                .and_then(|decoded| String::from_utf8(decoded).ok())
+                .and_then(|decoded| {
+                    decoded
+                        .split_once(':')
+                        .map(|(user, password)| (user.to_string(), password.to_string()))
+                })
+                .is_some_and(|(user, password)| {
+                    // Check every entry, so that the timing doesn't reveal users.
+                    self.users.iter().fold(false, |found, (u, p)| {
+                        (constant_time_eq(&user, u) & constant_time_eq(&password, p)) | found
+                    })
+                })
+        } else {
+            false
+        };
+
+        if authorized {
+            Ok(())
+        } else {
+            Err(GraphError::Unauthorized(
+                "missing or invalid status credentials".to_string(),
+            ))
+        }
+    }
+}
+
+/// Check that the `Authorization` header carries the bearer token `expected`.
+///
+/// The token is compared in constant time.
+pub fn has_bearer_token(headers: &HeaderMap, expected: &str) -> bool {
+    headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .is_some_and(|token| constant_time_eq(token, expected))
+}
+
+/// Compare two strings in time independent of their content.
+fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |acc, (x, y)| acc | (x ^ y))
+            == 0
+}
+
+/// Middleware requiring the credentials on all endpoints but the `exempt` ones.
+///
+/// Endpoints are exempted with their path, which also exempts the paths below
+/// it. This is meant for endpoints with their own authentication, such as the
+/// admin endpoints.
+pub fn authenticate<S, B>(
+    credentials: Option<&Credentials>,
+    exempt: &[&str],
+    req: ServiceRequest,
+    srv: &S,
+) -> Either<Ready<Result<ServiceResponse<B>, actix_web::Error>>, S::Future>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+{
+    let path = req.path();
+    let is_exempt = exempt.iter().any(|prefix| {
+        path.strip_prefix(prefix)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+    });
+    if is_exempt {
+        return Either::Right(srv.call(req));
+    }
+
+    match credentials.map(|credentials| credentials.check(&req)) {
+        Some(Err(e)) => Either::Left(future::err(e.into())),
+        _ => Either::Right(srv.call(req)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+    use std::io::Write;
+
+    fn credentials() -> Credentials {
+        Credentials {
+            token: Some("secret".to_string()),
+            users: vec![("prometheus".to_string(), "hunter2".to_string())],
+        }
+    }
+
+    fn request(authorization: Option<&str>) -> ServiceRequest {
+        let mut request = TestRequest::get().uri("/metrics");
+        if let Some(authorization) = authorization {
+            request = request.insert_header((header::AUTHORIZATION, authorization.to_string()));
+        }
+        request.to_srv_request()
+    }
+
+    #[test]
+    fn check_credentials() {
+        let credentials = credentials();
+        let basic = |credentials: &str| format!("Basic {}", base64::encode(credentials));
+
+        assert!(credentials.check(&request(Some("Bearer secret"))).is_ok());
+        assert!(credentials
+            .check(&request(Some(&basic("prometheus:hunter2"))))
+            .is_ok());
+
+        assert!(credentials.check(&request(None)).is_err());
+        assert!(credentials.check(&request(Some("Bearer other"))).is_err());
+        assert!(credentials
+            .check(&request(Some(&basic("prometheus:other"))))
+            .is_err());
+        assert!(credentials
+            .check(&request(Some(&basic("other:hunter2"))))
+            .is_err());
+        assert!(credentials.check(&request(Some("Basic %%%"))).is_err());
+
+        let token_only = Credentials {
+            users: vec![],
+            ..credentials
+        };
+        assert!(token_only
+            .check(&request(Some(&basic("prometheus:hunter2"))))
+            .is_err());
+    }
+
+    #[test]
+    fn bearer_token() {
+        let headers = |authorization: Option<&str>| request(authorization).headers().clone();
+
+        assert!(has_bearer_token(&headers(Some("Bearer secret")), "secret"));
+        assert!(!has_bearer_token(&headers(Some("Bearer secre")), "secret"));
+        assert!(!has_bearer_token(
+            &headers(Some("Bearer secrets")),
+            "secret"
+        ));
+        assert!(!has_bearer_token(&headers(Some("secret")), "secret"));
+        assert!(!has_bearer_token(&headers(None), "secret"));
+    }
+
+    #[test]
+    fn read_credentials() {
+        assert!(Credentials::read(None, None).unwrap().is_none());
+
+        let mut users = tempfile::NamedTempFile::new().unwrap();
+        writeln!(users, "# Prometheus\nprometheus:hunter2\n\nadmin:a:b").unwrap();
+        let credentials = Credentials::read(None, Some(users.path()))
+            .unwrap()
+            .unwrap();
+        assert_eq!(credentials.token, None);
+        assert_eq!(
+            credentials.users,
+            vec![
+                ("prometheus".to_string(), "hunter2".to_string()),
+                ("admin".to_string(), "a:b".to_string())
+            ]
+        );
+
+        let mut invalid = tempfile::NamedTempFile::new().unwrap();
+        writeln!(invalid, "prometheus").unwrap();
+        assert!(Credentials::read(None, Some(invalid.path())).is_err());
+
+        let mut token = tempfile::NamedTempFile::new().unwrap();
+        writeln!(token, "  secret  ").unwrap();
+        let credentials = Credentials::read(Some(token.path()), None)
+            .unwrap()
+            .unwrap();
+        assert_eq!(credentials.token.as_deref(), Some("secret"));
+    }
+
+    #[test]
+    fn exempt_paths() {
+        let credentials = credentials();
+        let exempt = ["/debug"];
+        let is_exempt = |path: &str| {
+            let req = TestRequest::get().uri(path).to_srv_request();
+            let srv = actix_web::dev::fn_service(|req: ServiceRequest| async {
+                Ok::<_, actix_web::Error>(req.into_response(actix_web::HttpResponse::Ok().finish()))
+            });
+            matches!(
+                authenticate(Some(&credentials), &exempt, req, &srv),
+                Either::Right(_)
+            )
+        };
+
+        assert!(is_exempt("/debug"));
+        assert!(is_exempt("/debug/plugins"));
+        assert!(!is_exempt("/debugger"));
+        assert!(!is_exempt("/metrics"));
+    }
+}
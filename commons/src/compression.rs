@@ -0,0 +1,618 @@
+//! Response compression.
+//!
+//! actix-web's `Compress` middleware only uses fixed compression levels, while
+//! graph payloads of large products weigh multiple megabytes and are served
+//! over and over: a higher level is well worth its CPU time. This middleware
+//! negotiates zstd, brotli, gzip or deflate with the client and compresses
+//! response bodies as they are streamed, with configurable zstd and brotli
+//! levels.
+
+use actix_web::body::{BodySize, BoxBody, MessageBody};
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse};
+use actix_web::http::header::{self, HeaderMap, HeaderValue};
+use actix_web::web::Bytes;
+use futures::future::LocalBoxFuture;
+use futures::ready;
+use std::future::Future;
+use std::io::Write;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use tokio::task::JoinHandle;
+
+/// Default zstd compression level.
+pub static DEFAULT_ZSTD_LEVEL: i32 = 3;
+
+/// Default brotli compression level.
+pub static DEFAULT_BROTLI_LEVEL: u32 = 5;
+
+/// Valid zstd compression levels.
+pub static ZSTD_LEVELS: std::ops::RangeInclusive<i32> = 1..=22;
+
+/// Valid brotli compression levels.
+pub static BROTLI_LEVELS: std::ops::RangeInclusive<u32> = 0..=11;
+
+/// Size under which responses are sent uncompressed, as compressing them doesn't pay off.
+pub static MIN_COMPRESSED_SIZE: usize = 1024;
+
+/// Size of the chunks compressed on the event loop, larger ones are compressed
+/// on the blocking thread pool.
+static MAX_INLINE_CHUNK_SIZE: usize = 16 * 1024;
+
+/// Content type of server-sent events, whose responses are never compressed
+/// as the encoders would hold the events back.
+static EVENT_STREAM_CONTENT_TYPE: &str = "text/event-stream";
+
+/// Size of the brotli compressor buffer.
+static BROTLI_BUFFER_SIZE: usize = 4096;
+
+/// Base-2 logarithm of the brotli window size.
+static BROTLI_WINDOW_LOG: u32 = 22;
+
+/// Compression levels.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CompressionSettings {
+    /// zstd compression level, see `ZSTD_LEVELS`.
+    pub zstd_level: i32,
+    /// brotli compression level, see `BROTLI_LEVELS`.
+    pub brotli_level: u32,
+}
+
+impl Default for CompressionSettings {
+    fn default() -> Self {
+        Self {
+            zstd_level: DEFAULT_ZSTD_LEVEL,
+            brotli_level: DEFAULT_BROTLI_LEVEL,
+        }
+    }
+}
+
+/// Content encodings, in order of preference.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Encoding {
+    Zstd,
+    Brotli,
+    Gzip,
+    Deflate,
+}
+
+impl Encoding {
+    const PREFERENCE: [Encoding; 4] = [
+        Encoding::Zstd,
+        Encoding::Brotli,
+        Encoding::Gzip,
+        Encoding::Deflate,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            Encoding::Zstd => "zstd",
+            Encoding::Brotli => "br",
+            Encoding::Gzip => "gzip",
+            Encoding::Deflate => "deflate",
+        }
+    }
+
+    /// Returns the encoding with the highest quality in an `Accept-Encoding` header,
+    /// ties being broken by our order of preference.
+    fn negotiate(accept_encoding: &str) -> Option<Self> {
+        let qualities: Vec<(&str, f32)> = accept_encoding
+            .split(',')
+            .filter_map(|coding| {
+                let mut parts = coding.split(';').map(str::trim);
+                let name = parts.next().filter(|name| !name.is_empty())?;
+                let quality = parts
+                    .find_map(|param| param.strip_prefix("q="))
+                    .map(|quality| quality.parse().unwrap_or(0.0))
+                    .unwrap_or(1.0);
+                Some((name, quality))
+            })
+            .collect();
+        let quality = |name: &str| {
+            qualities
+                .iter()
+                .find(|(coding, _)| coding.eq_ignore_ascii_case(name))
+                .or_else(|| qualities.iter().find(|(coding, _)| *coding == "*"))
+                .map(|(_, quality)| *quality)
+                .unwrap_or(0.0)
+        };
+
+        Self::PREFERENCE
+            .iter()
+            .map(|encoding| (*encoding, quality(encoding.name())))
+            .filter(|(_, quality)| *quality > 0.0)
+            .fold(
+                None,
+                |best: Option<(Self, f32)>, (encoding, quality)| match best {
+                    Some((_, best_quality)) if best_quality >= quality => best,
+                    _ => Some((encoding, quality)),
+                },
+            )
+            .map(|(encoding, _)| encoding)
+    }
+
+    fn encode(self, settings: CompressionSettings, body: &[u8]) -> std::io::Result<Vec<u8>> {
+        let output = Output::default();
+        let mut encoder = Encoder::new(self, settings, output.clone())?;
+        encoder.write(body)?;
+        encoder.finish()?;
+        Ok(output.take())
+    }
+}
+
+/// Compressed output shared with an encoder, taken as it is produced.
+#[derive(Clone, Debug, Default)]
+struct Output(Arc<Mutex<Vec<u8>>>);
+
+impl Output {
+    fn take(&self) -> Vec<u8> {
+        std::mem::take(&mut *self.0.lock().unwrap_or_else(|e| e.into_inner()))
+    }
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Streaming encoder writing to an `Output`.
+enum Encoder {
+    Zstd(zstd::stream::write::Encoder<'static, Output>),
+    Brotli(Box<brotli::CompressorWriter<Output>>),
+    Gzip(flate2::write::GzEncoder<Output>),
+    Deflate(flate2::write::ZlibEncoder<Output>),
+}
+
+impl Encoder {
+    fn new(
+        encoding: Encoding,
+        settings: CompressionSettings,
+        output: Output,
+    ) -> std::io::Result<Self> {
+        Ok(match encoding {
+            Encoding::Zstd => Encoder::Zstd(zstd::stream::write::Encoder::new(
+                output,
+                settings.zstd_level,
+            )?),
+            Encoding::Brotli => Encoder::Brotli(Box::new(brotli::CompressorWriter::new(
+                output,
+                BROTLI_BUFFER_SIZE,
+                settings.brotli_level,
+                BROTLI_WINDOW_LOG,
+            ))),
+            Encoding::Gzip => Encoder::Gzip(flate2::write::GzEncoder::new(
+                output,
+                flate2::Compression::default(),
+            )),
+            Encoding::Deflate => Encoder::Deflate(flate2::write::ZlibEncoder::new(
+                output,
+                flate2::Compression::default(),
+            )),
+        })
+    }
+
+    fn write(&mut self, data: &[u8]) -> std::io::Result<()> {
+        match self {
+            Encoder::Zstd(encoder) => encoder.write_all(data),
+            Encoder::Brotli(encoder) => encoder.write_all(data),
+            Encoder::Gzip(encoder) => encoder.write_all(data),
+            Encoder::Deflate(encoder) => encoder.write_all(data),
+        }
+    }
+
+    /// Write the end of the compressed stream.
+    fn finish(self) -> std::io::Result<()> {
+        match self {
+            Encoder::Zstd(encoder) => encoder.finish().map(drop),
+            Encoder::Brotli(encoder) => {
+                encoder.into_inner();
+                Ok(())
+            }
+            Encoder::Gzip(encoder) => encoder.finish().map(drop),
+            Encoder::Deflate(encoder) => encoder.finish().map(drop),
+        }
+    }
+}
+
+/// A response body compressed as it is streamed.
+///
+/// Small chunks are compressed right away, larger ones and the end of the
+/// stream on the blocking thread pool, which hands the encoder back.
+struct CompressedBody {
+    body: BoxBody,
+    output: Output,
+    encoder: Option<Encoder>,
+    pending: Option<JoinHandle<std::io::Result<Option<Encoder>>>>,
+    eof: bool,
+}
+
+impl CompressedBody {
+    fn new(
+        encoding: Encoding,
+        settings: CompressionSettings,
+        body: BoxBody,
+    ) -> std::io::Result<Self> {
+        let output = Output::default();
+        let encoder = Encoder::new(encoding, settings, output.clone())?;
+        Ok(Self {
+            body,
+            output,
+            encoder: Some(encoder),
+            pending: None,
+            eof: false,
+        })
+    }
+
+    /// Returns the output produced so far, if any.
+    fn take_chunk(&self) -> Option<Bytes> {
+        let chunk = self.output.take();
+        if chunk.is_empty() {
+            None
+        } else {
+            Some(chunk.into())
+        }
+    }
+}
+
+impl MessageBody for CompressedBody {
+    type Error = Box<dyn std::error::Error>;
+
+    fn size(&self) -> BodySize {
+        BodySize::Stream
+    }
+
+    fn poll_next(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Bytes, Self::Error>>> {
+        let this = self.get_mut();
+        loop {
+            if let Some(pending) = &mut this.pending {
+                let encoder = ready!(Pin::new(pending).poll(cx));
+                this.pending = None;
+                this.encoder = encoder??;
+                if let Some(chunk) = this.take_chunk() {
+                    return Poll::Ready(Some(Ok(chunk)));
+                }
+            }
+            if this.eof {
+                return Poll::Ready(None);
+            }
+
+            let mut encoder = match this.encoder.take() {
+                Some(encoder) => encoder,
+                None => return Poll::Ready(None),
+            };
+            match ready!(Pin::new(&mut this.body).poll_next(cx)) {
+                Some(Ok(chunk)) if chunk.len() <= MAX_INLINE_CHUNK_SIZE => {
+                    encoder.write(&chunk)?;
+                    this.encoder = Some(encoder);
+                    if let Some(chunk) = this.take_chunk() {
+                        return Poll::Ready(Some(Ok(chunk)));
+                    }
+                }
+                Some(Ok(chunk)) => {
+                    this.pending = Some(tokio::task::spawn_blocking(move || {
+                        encoder.write(&chunk).map(|()| Some(encoder))
+                    }));
+                }
+                Some(Err(e)) => {
+                    this.encoder = Some(encoder);
+                    return Poll::Ready(Some(Err(e)));
+                }
+                None => {
+                    this.eof = true;
+                    this.pending = Some(tokio::task::spawn_blocking(move || {
+                        encoder.finish().map(|()| None)
+                    }));
+                }
+            }
+        }
+    }
+}
+
+/// Compress the response of the service as accepted by the client, for use with `App::wrap_fn`.
+///
+/// Responses which are already encoded, server-sent events and bodies known
+/// to be smaller than `MIN_COMPRESSED_SIZE` are left alone.
+pub fn compress<S, B>(
+    settings: CompressionSettings,
+    req: ServiceRequest,
+    srv: &S,
+) -> LocalBoxFuture<'static, Result<ServiceResponse<BoxBody>, actix_web::Error>>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    S::Future: 'static,
+    B: MessageBody + 'static,
+{
+    let encoding = req
+        .headers()
+        .get(header::ACCEPT_ENCODING)
+        .and_then(|value| value.to_str().ok())
+        .and_then(Encoding::negotiate);
+    let response = srv.call(req);
+
+    Box::pin(async move {
+        let mut response = response.await?;
+        let is_event_stream = response
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|content_type| content_type.starts_with(EVENT_STREAM_CONTENT_TYPE));
+        let encoding = match encoding {
+            Some(encoding)
+                if !is_event_stream
+                    && !response.headers().contains_key(header::CONTENT_ENCODING) =>
+            {
+                encoding
+            }
+            _ => return Ok(response.map_into_boxed_body()),
+        };
+
+        response
+            .headers_mut()
+            .append(header::VARY, HeaderValue::from_static("accept-encoding"));
+        match response.response().body().size() {
+            BodySize::None => return Ok(response.map_into_boxed_body()),
+            BodySize::Sized(size) if size < MIN_COMPRESSED_SIZE as u64 => {
+                return Ok(response.map_into_boxed_body())
+            }
+            _ => {}
+        }
+
+        let (req, response) = response.into_parts();
+        let (mut response, body) = response.into_parts();
+        let body = CompressedBody::new(encoding, settings, body.boxed())
+            .map_err(actix_web::error::ErrorInternalServerError)?;
+        response.headers_mut().remove(header::CONTENT_LENGTH);
+        response.headers_mut().insert(
+            header::CONTENT_ENCODING,
+            HeaderValue::from_static(encoding.name()),
+        );
+        Ok(ServiceResponse::new(
+            req,
+            response.set_body(body).map_into_boxed_body(),
+        ))
+    })
+}
+
+/// A body compressed ahead of time with every supported encoding, for
+/// payloads which are served many times between changes.
+///
+/// Responses carrying a `Content-Encoding` are left alone by `compress`, so
+/// serving the negotiated variant skips compression per request.
+#[derive(Clone, Debug, Default)]
+pub struct PrecompressedBody {
+    identity: Bytes,
+    encoded: Vec<(Encoding, Bytes)>,
+}
+
+impl PrecompressedBody {
+    /// Compress a body with every supported encoding.
+    ///
+    /// Bodies smaller than `MIN_COMPRESSED_SIZE` are kept uncompressed.
+    pub fn new(settings: CompressionSettings, body: Bytes) -> std::io::Result<Self> {
+        let encoded = if body.len() < MIN_COMPRESSED_SIZE {
+            vec![]
+        } else {
+            Encoding::PREFERENCE
+                .iter()
+                .map(|encoding| Ok((*encoding, Bytes::from(encoding.encode(settings, &body)?))))
+                .collect::<std::io::Result<_>>()?
+        };
+
+        Ok(Self {
+            identity: body,
+            encoded,
+        })
+    }
+
+    /// Returns the uncompressed body.
+    pub fn identity(&self) -> &Bytes {
+        &self.identity
+    }
+
+    /// Returns the total size of the compressed variants.
+    pub fn encoded_len(&self) -> usize {
+        self.encoded.iter().map(|(_, body)| body.len()).sum()
+    }
+
+    /// Returns the body in the encoding negotiated with a request, along with
+    /// the name of the encoding if compressed.
+    pub fn negotiate(&self, headers: &HeaderMap) -> (Option<&'static str>, Bytes) {
+        headers
+            .get(header::ACCEPT_ENCODING)
+            .and_then(|value| value.to_str().ok())
+            .and_then(Encoding::negotiate)
+            .and_then(|encoding| {
+                self.encoded
+                    .iter()
+                    .find(|(candidate, _)| *candidate == encoding)
+            })
+            .map(|(encoding, body)| (Some(encoding.name()), body.clone()))
+            .unwrap_or_else(|| (None, self.identity.clone()))
+    }
+}
+
+/// An uncompressed body.
+impl From<Bytes> for PrecompressedBody {
+    fn from(body: Bytes) -> Self {
+        Self {
+            identity: body,
+            encoded: vec![],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::{call_service, init_service, read_body, TestRequest};
+    use actix_web::{web, App, HttpResponse};
+    use std::io::Read;
+
+    #[test]
+    fn negotiate() {
+        assert_eq!(Encoding::negotiate(""), None);
+        assert_eq!(Encoding::negotiate("identity"), None);
+        assert_eq!(
+            Encoding::negotiate("gzip, deflate, br, zstd"),
+            Some(Encoding::Zstd)
+        );
+        assert_eq!(Encoding::negotiate("gzip, br"), Some(Encoding::Brotli));
+        assert_eq!(
+            Encoding::negotiate("zstd;q=0.5, gzip;q=0.8"),
+            Some(Encoding::Gzip)
+        );
+        assert_eq!(Encoding::negotiate("*, zstd;q=0"), Some(Encoding::Brotli));
+        assert_eq!(Encoding::negotiate("GZIP"), Some(Encoding::Gzip));
+    }
+
+    #[actix_web::test]
+    async fn compress_responses() {
+        let body = "{\"nodes\":[]}".repeat(1000);
+        let app = {
+            let body = body.clone();
+            init_service(
+                App::new()
+                    .wrap_fn(|req, srv| compress(CompressionSettings::default(), req, srv))
+                    .route(
+                        "/large",
+                        web::get().to(move || {
+                            let body = body.clone();
+                            async move { HttpResponse::Ok().body(body) }
+                        }),
+                    )
+                    .route(
+                        "/small",
+                        web::get().to(|| async { HttpResponse::Ok().body("{}") }),
+                    )
+                    .route(
+                        "/streamed",
+                        web::get().to(|| async {
+                            // Chunks both smaller and larger than compressed inline.
+                            let chunks = vec![
+                                Bytes::from("[".repeat(100)),
+                                Bytes::from("{}".repeat(MAX_INLINE_CHUNK_SIZE)),
+                                Bytes::from("]".repeat(100)),
+                            ];
+                            HttpResponse::Ok().streaming(futures::stream::iter(
+                                chunks.into_iter().map(Ok::<_, actix_web::Error>),
+                            ))
+                        }),
+                    )
+                    .route(
+                        "/events",
+                        web::get().to(|| async {
+                            HttpResponse::Ok()
+                                .content_type(EVENT_STREAM_CONTENT_TYPE)
+                                .streaming(futures::stream::iter(vec![Ok::<_, actix_web::Error>(
+                                    Bytes::from("data: {}\n\n".repeat(1000)),
+                                )]))
+                        }),
+                    ),
+            )
+            .await
+        };
+        let request = |path: &str, accept_encoding: &str| {
+            TestRequest::get()
+                .uri(path)
+                .insert_header((header::ACCEPT_ENCODING, accept_encoding.to_string()))
+                .to_request()
+        };
+
+        let response = call_service(&app, request("/large", "gzip, br, zstd")).await;
+        assert_eq!(
+            response.headers().get(header::CONTENT_ENCODING).unwrap(),
+            "zstd"
+        );
+        let compressed = read_body(response).await;
+        assert!(compressed.len() < body.len());
+        assert_eq!(zstd::decode_all(&compressed[..]).unwrap(), body.as_bytes());
+
+        let response = call_service(&app, request("/large", "br")).await;
+        assert_eq!(
+            response.headers().get(header::CONTENT_ENCODING).unwrap(),
+            "br"
+        );
+        let mut decompressed = String::new();
+        brotli::Decompressor::new(&read_body(response).await[..], BROTLI_BUFFER_SIZE)
+            .read_to_string(&mut decompressed)
+            .unwrap();
+        assert_eq!(decompressed, body);
+
+        let response = call_service(&app, request("/streamed", "gzip")).await;
+        assert_eq!(
+            response.headers().get(header::CONTENT_ENCODING).unwrap(),
+            "gzip"
+        );
+        let mut decompressed = String::new();
+        flate2::read::GzDecoder::new(&read_body(response).await[..])
+            .read_to_string(&mut decompressed)
+            .unwrap();
+        assert_eq!(
+            decompressed,
+            format!(
+                "{}{}{}",
+                "[".repeat(100),
+                "{}".repeat(MAX_INLINE_CHUNK_SIZE),
+                "]".repeat(100)
+            )
+        );
+
+        // Server-sent events are never held back by an encoder.
+        let response = call_service(&app, request("/events", "zstd")).await;
+        assert!(response.headers().get(header::CONTENT_ENCODING).is_none());
+        assert_eq!(read_body(response).await, "data: {}\n\n".repeat(1000));
+
+        let response = call_service(&app, request("/small", "zstd")).await;
+        assert!(response.headers().get(header::CONTENT_ENCODING).is_none());
+        assert_eq!(read_body(response).await, "{}");
+
+        let response = call_service(&app, request("/large", "identity")).await;
+        assert!(response.headers().get(header::CONTENT_ENCODING).is_none());
+        assert_eq!(read_body(response).await, body);
+    }
+
+    #[test]
+    fn precompressed_body() {
+        let body = "{\"nodes\":[]}".repeat(1000);
+        let precompressed =
+            PrecompressedBody::new(CompressionSettings::default(), body.clone().into()).unwrap();
+        assert!(precompressed.encoded_len() > 0);
+        let headers = |accept_encoding: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(
+                header::ACCEPT_ENCODING,
+                HeaderValue::from_str(accept_encoding).unwrap(),
+            );
+            headers
+        };
+
+        let (encoding, compressed) = precompressed.negotiate(&headers("gzip, zstd"));
+        assert_eq!(encoding, Some("zstd"));
+        assert_eq!(zstd::decode_all(&compressed[..]).unwrap(), body.as_bytes());
+
+        assert_eq!(
+            precompressed.negotiate(&headers("identity")),
+            (None, Bytes::from(body.clone()))
+        );
+        assert_eq!(
+            precompressed.negotiate(&HeaderMap::new()),
+            (None, Bytes::from(body))
+        );
+
+        let small = PrecompressedBody::new(CompressionSettings::default(), "{}".into()).unwrap();
+        assert_eq!(small.encoded_len(), 0);
+        assert_eq!(small.negotiate(&headers("zstd")), (None, "{}".into()));
+    }
+}
@@ -0,0 +1,283 @@
+//! Layered TOML configuration files.
+//!
+//! The configuration can be split between a main file and a directory of
+//! fragments, merged in lexical order of their file names, so that secrets and
+//! per-environment overrides can be mounted as separate files. String values
+//! can reference environment variables as `${VAR}`.
+//!
+//! As the files may hold secrets, errors point at the file and line only and
+//! never quote the content.
+
+use crate::prelude_errors::*;
+use serde::de::DeserializeOwned;
+use std::path::{Path, PathBuf};
+
+/// Extension of the configuration fragments.
+pub static FRAGMENT_EXTENSION: &str = "toml";
+
+/// Read and merge the configuration file and the fragments of the configuration directory.
+///
+/// Returns `None` if neither is given.
+pub fn read_layered<T>(path: Option<&Path>, dir: Option<&Path>) -> Fallible<Option<T>>
+where
+    T: DeserializeOwned,
+{
+    if path.is_none() && dir.is_none() {
+        return Ok(None);
+    }
+    let mut paths: Vec<PathBuf> = path.map(Path::to_path_buf).into_iter().collect();
+    if let Some(dir) = dir {
+        paths.extend(fragments(dir)?);
+    }
+
+    let mut merged = toml::Value::Table(Default::default());
+    for path in &paths {
+        let content = std::fs::read_to_string(path)
+            .context(format!("failed to open config path {:?}", path))?;
+        let mut value: toml::Value = toml::from_str(&content).map_err(|e| parse_error(path, &e))?;
+        expand_env(&mut value, &|name| std::env::var(name).ok())
+            .context(format!("in config file {}", path.display()))?;
+        merge(&mut merged, value);
+    }
+
+    let paths: Vec<_> = paths
+        .iter()
+        .map(|path| path.display().to_string())
+        .collect();
+    let cfg = merged.try_into().context(format!(
+        "failed to parse configuration from {}",
+        paths.join(", ")
+    ))?;
+    Ok(Some(cfg))
+}
+
+/// Describe a syntax error in a configuration file by its location only.
+fn parse_error(path: &Path, error: &toml::de::Error) -> Error {
+    match error.line_col() {
+        Some((line, _)) => format_err!(
+            "failed to parse config file {} at line {}",
+            path.display(),
+            line + 1
+        ),
+        None => format_err!("failed to parse config file {}", path.display()),
+    }
+}
+
+/// Returns the fragments of a configuration directory, in lexical order.
+fn fragments(dir: &Path) -> Fallible<Vec<PathBuf>> {
+    let mut fragments = vec![];
+    for entry in std::fs::read_dir(dir).context(format!("failed to read config dir {:?}", dir))? {
+        let path = entry?.path();
+        if path.is_file()
+            && path
+                .extension()
+                .is_some_and(|ext| ext == FRAGMENT_EXTENSION)
+        {
+            fragments.push(path);
+        }
+    }
+    fragments.sort();
+    Ok(fragments)
+}
+
+/// Merge `overlay` into `base`.
+///
+/// Tables are merged key by key, while any other value of `overlay`, arrays
+/// included, replaces the one of `base`.
+pub fn merge(base: &mut toml::Value, overlay: toml::Value) {
+    match (base, overlay) {
+        (toml::Value::Table(base), toml::Value::Table(overlay)) => {
+            for (key, value) in overlay {
+                match base.get_mut(&key) {
+                    Some(existing) => merge(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, overlay) => *base = overlay,
+    }
+}
+
+/// Replace `${VAR}` references in string values with the value of the variable.
+///
+/// `$${` stands for a literal `${`. Referencing an unset variable is an error.
+pub fn expand_env(
+    value: &mut toml::Value,
+    lookup: &dyn Fn(&str) -> Option<String>,
+) -> Fallible<()> {
+    match value {
+        toml::Value::String(string) => *string = expand_string(string, lookup)?,
+        toml::Value::Array(values) => {
+            for value in values {
+                expand_env(value, lookup)?;
+            }
+        }
+        toml::Value::Table(table) => {
+            for (_, value) in table.iter_mut() {
+                expand_env(value, lookup)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+fn expand_string(input: &str, lookup: &dyn Fn(&str) -> Option<String>) -> Fallible<String> {
+    let mut output = String::with_capacity(input.len());
+    let mut rest = input;
+    while let Some(start) = rest.find('$') {
+        output.push_str(&rest[..start]);
+        rest = &rest[start..];
+        if let Some(escaped) = rest.strip_prefix("$${") {
+            output.push_str("${");
+            rest = escaped;
+        } else if let Some(reference) = rest.strip_prefix("${") {
+            let end = reference
+                .find('}')
+                .ok_or_else(|| format_err!("unterminated variable reference"))?;
+            let name = &reference[..end];
+            ensure!(
+                !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_'),
+                "invalid variable name '{}'",
+                name
+            );
+            let value = lookup(name)
+                .ok_or_else(|| format_err!("environment variable {} is not set", name))?;
+            output.push_str(&value);
+            rest = &reference[end + 1..];
+        } else {
+            output.push('$');
+            rest = &rest[1..];
+        }
+    }
+    output.push_str(rest);
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn expand_variables() {
+        let env: HashMap<&str, &str> = vec![("TOKEN", "s3cr3t"), ("REGION", "us-east-1")]
+            .into_iter()
+            .collect();
+        let lookup = |name: &str| env.get(name).map(|value| value.to_string());
+
+        let mut value: toml::Value = toml::from_str(
+            r#"
+                token = "${TOKEN}"
+                url = "https://${REGION}.example.com/$path/$${literal}"
+                port = 8080
+                [nested]
+                list = ["a-${REGION}", "b"]
+            "#,
+        )
+        .unwrap();
+        expand_env(&mut value, &lookup).unwrap();
+        assert_eq!(value["token"].as_str(), Some("s3cr3t"));
+        assert_eq!(
+            value["url"].as_str(),
+            Some("https://us-east-1.example.com/$path/${literal}")
+        );
+        assert_eq!(value["port"].as_integer(), Some(8080));
+        assert_eq!(value["nested"]["list"][0].as_str(), Some("a-us-east-1"));
+
+        for invalid in &["${UNSET}", "${TOKEN", "${}", "${A-B}"] {
+            let mut value = toml::Value::String(invalid.to_string());
+            assert!(expand_env(&mut value, &lookup).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn merge_layers() {
+        let mut base: toml::Value = toml::from_str(
+            r#"
+                verbosity = "info"
+                [service]
+                port = 8080
+                address = "0.0.0.0"
+                mandatory_client_parameters = ["channel"]
+            "#,
+        )
+        .unwrap();
+        let overlay: toml::Value = toml::from_str(
+            r#"
+                [service]
+                port = 9090
+                mandatory_client_parameters = ["channel", "arch"]
+                [status]
+                port = 9091
+            "#,
+        )
+        .unwrap();
+
+        merge(&mut base, overlay);
+        let expected: toml::Value = toml::from_str(
+            r#"
+                verbosity = "info"
+                [service]
+                port = 9090
+                address = "0.0.0.0"
+                mandatory_client_parameters = ["channel", "arch"]
+                [status]
+                port = 9091
+            "#,
+        )
+        .unwrap();
+        assert_eq!(base, expected);
+    }
+
+    #[test]
+    fn read_config_dir() {
+        #[derive(Debug, Deserialize, PartialEq)]
+        struct Config {
+            name: String,
+            port: u16,
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+        let main = dir.path().join("main.toml");
+        std::fs::write(&main, "name = 'main'\nport = 1").unwrap();
+        let fragments = dir.path().join("conf.d");
+        std::fs::create_dir(&fragments).unwrap();
+        std::fs::write(fragments.join("20-port.toml"), "port = 3").unwrap();
+        std::fs::write(fragments.join("10-port.toml"), "port = 2").unwrap();
+        std::fs::write(fragments.join("README"), "not a fragment").unwrap();
+
+        assert_eq!(read_layered::<Config>(None, None).unwrap(), None);
+        assert_eq!(
+            read_layered::<Config>(Some(&main), None).unwrap(),
+            Some(Config {
+                name: "main".to_string(),
+                port: 1
+            })
+        );
+        assert_eq!(
+            read_layered::<Config>(Some(&main), Some(&fragments)).unwrap(),
+            Some(Config {
+                name: "main".to_string(),
+                port: 3
+            })
+        );
+        assert!(read_layered::<Config>(None, Some(&fragments)).is_err());
+    }
+
+    #[test]
+    fn parse_error_without_content() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("secret.toml");
+        std::fs::write(&path, "[auth]\ntoken = \"s3cr3t\"\nbroken\n").unwrap();
+
+        let error = read_layered::<toml::Value>(Some(&path), None).unwrap_err();
+        let message = format!("{:#}", error);
+        assert_eq!(
+            message,
+            format!("failed to parse config file {} at line 3", path.display())
+        );
+    }
+}
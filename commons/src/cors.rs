@@ -0,0 +1,186 @@
+//! Cross-origin resource sharing.
+//!
+//! Web consoles hosted on other origins than the services can only call them
+//! if the responses allow their origin. Allowed origins, methods and request
+//! headers are configurable, while the permissive mode allows everything for
+//! development setups.
+
+use crate::prelude_errors::*;
+use actix_cors::Cors;
+use actix_web::http::header::HeaderName;
+use actix_web::http::Method;
+
+/// Wildcard allowing any origin, method or header.
+pub static ANY: &str = "*";
+
+/// CORS settings.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct CorsSettings {
+    /// Allow any origin, method and header, for development only.
+    pub permissive: bool,
+
+    /// Allowed origins, such as `https://console.example.com`, or `*` for any.
+    pub allowed_origins: Vec<String>,
+
+    /// Allowed methods, or `*` for any.
+    pub allowed_methods: Vec<String>,
+
+    /// Allowed request headers, or `*` for any.
+    pub allowed_headers: Vec<String>,
+
+    /// Duration for which browsers may cache preflight responses, in seconds.
+    pub max_age_secs: Option<usize>,
+}
+
+impl Default for CorsSettings {
+    fn default() -> Self {
+        Self {
+            permissive: false,
+            allowed_origins: vec![ANY.to_string()],
+            allowed_methods: vec!["HEAD".to_string(), "GET".to_string(), "POST".to_string()],
+            allowed_headers: vec![],
+            max_age_secs: None,
+        }
+    }
+}
+
+impl CorsSettings {
+    /// Validate the allowed origins, methods and headers.
+    pub fn validate(&self) -> Fallible<()> {
+        for origin in self.allowed_origins.iter().filter(|origin| *origin != ANY) {
+            let url = url::Url::parse(origin)
+                .map_err(|e| format_err!("invalid CORS origin '{}': {}", origin, e))?;
+            ensure!(
+                origin.trim_end_matches('/') == url.origin().ascii_serialization(),
+                "invalid CORS origin '{}', expected a scheme, a host and an optional port",
+                origin
+            );
+        }
+        for method in self.allowed_methods.iter().filter(|method| *method != ANY) {
+            Method::from_bytes(method.as_bytes())
+                .map_err(|_| format_err!("invalid CORS method '{}'", method))?;
+        }
+        for header in self.allowed_headers.iter().filter(|header| *header != ANY) {
+            HeaderName::from_bytes(header.as_bytes())
+                .map_err(|_| format_err!("invalid CORS header '{}'", header))?;
+        }
+        Ok(())
+    }
+
+    /// Build the CORS middleware.
+    pub fn middleware(&self) -> Cors {
+        if self.permissive {
+            return Cors::permissive();
+        }
+
+        let mut cors = Cors::default();
+        for origin in &self.allowed_origins {
+            cors = if origin == ANY {
+                cors.allow_any_origin()
+            } else {
+                cors.allowed_origin(origin.trim_end_matches('/'))
+            };
+        }
+        cors = if self.allowed_methods.iter().any(|method| method == ANY) {
+            cors.allow_any_method()
+        } else {
+            cors.allowed_methods(self.allowed_methods.iter().map(String::as_str))
+        };
+        if self.allowed_headers.iter().any(|header| header == ANY) {
+            cors = cors.allow_any_header();
+        } else if !self.allowed_headers.is_empty() {
+            cors = cors.allowed_headers(self.allowed_headers.iter().map(String::as_str));
+        }
+        cors.max_age(self.max_age_secs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::header;
+    use actix_web::test::{call_service, init_service, TestRequest};
+    use actix_web::{web, App, HttpResponse};
+
+    #[test]
+    fn validate_settings() {
+        let settings = |origin: &str, method: &str, header: &str| CorsSettings {
+            allowed_origins: vec![origin.to_string()],
+            allowed_methods: vec![method.to_string()],
+            allowed_headers: vec![header.to_string()],
+            ..CorsSettings::default()
+        };
+
+        CorsSettings::default().validate().unwrap();
+        settings("https://console.example.com", "GET", "authorization")
+            .validate()
+            .unwrap();
+        settings("http://localhost:3000/", "*", "*")
+            .validate()
+            .unwrap();
+
+        assert!(settings("console.example.com", "GET", "accept")
+            .validate()
+            .is_err());
+        assert!(settings("https://example.com/console", "GET", "accept")
+            .validate()
+            .is_err());
+        assert!(settings("*", "GET POST", "accept").validate().is_err());
+        assert!(settings("*", "GET", "bad header").validate().is_err());
+    }
+
+    #[actix_web::test]
+    async fn allowed_origins() {
+        let settings = CorsSettings {
+            allowed_origins: vec!["https://console.example.com".to_string()],
+            allowed_methods: vec!["GET".to_string()],
+            allowed_headers: vec!["authorization".to_string()],
+            max_age_secs: Some(600),
+            ..CorsSettings::default()
+        };
+        let app = init_service(
+            App::new()
+                .wrap(settings.middleware())
+                .route("/", web::get().to(|| async { HttpResponse::Ok().finish() })),
+        )
+        .await;
+        let request = |origin: &str| {
+            TestRequest::get()
+                .uri("/")
+                .insert_header((header::ORIGIN, origin.to_string()))
+        };
+
+        let response =
+            call_service(&app, request("https://console.example.com").to_request()).await;
+        assert!(response.status().is_success());
+        assert_eq!(
+            response
+                .headers()
+                .get(header::ACCESS_CONTROL_ALLOW_ORIGIN)
+                .unwrap(),
+            "https://console.example.com"
+        );
+
+        let preflight = request("https://console.example.com")
+            .method(Method::OPTIONS)
+            .insert_header((header::ACCESS_CONTROL_REQUEST_METHOD, "GET"))
+            .insert_header((header::ACCESS_CONTROL_REQUEST_HEADERS, "authorization"))
+            .to_request();
+        let response = call_service(&app, preflight).await;
+        assert!(response.status().is_success());
+        assert_eq!(
+            response
+                .headers()
+                .get(header::ACCESS_CONTROL_MAX_AGE)
+                .unwrap(),
+            "600"
+        );
+
+        let response = call_service(&app, request("https://other.example.com").to_request()).await;
+        assert!(response
+            .headers()
+            .get(header::ACCESS_CONTROL_ALLOW_ORIGIN)
+            .is_none());
+    }
+}
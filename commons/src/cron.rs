@@ -0,0 +1,201 @@
+//! Cron schedules, for recurring time windows.
+
+use crate::prelude_errors::*;
+use chrono::{DateTime, Datelike, Duration, Timelike, Utc};
+
+/// Cron schedule, with the minute, hour, day of month, month and day of week fields.
+///
+/// Schedules are evaluated in UTC.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Schedule {
+    minutes: Field,
+    hours: Field,
+    days_of_month: Field,
+    months: Field,
+    days_of_week: Field,
+}
+
+/// Allowed values of a cron field.
+#[derive(Clone, Debug, PartialEq)]
+struct Field {
+    allowed: Vec<bool>,
+    /// Whether the field is `*`, for the day matching rules.
+    any: bool,
+}
+
+impl Field {
+    /// Parse a field of comma-separated `*`, values or ranges, with an optional `/step`.
+    fn parse(spec: &str, min: u32, max: u32) -> Fallible<Self> {
+        let mut allowed = vec![false; max as usize + 1];
+        for part in spec.split(',') {
+            let (range, step) = match part.split_once('/') {
+                Some((range, step)) => (
+                    range,
+                    step.parse::<u32>()
+                        .ok()
+                        .filter(|step| *step > 0)
+                        .ok_or_else(|| format_err!("invalid step in cron field '{}'", spec))?,
+                ),
+                None => (part, 1),
+            };
+            let (first, last) = match range {
+                "*" => (min, max),
+                _ => {
+                    let parse = |value: &str| {
+                        value
+                            .parse::<u32>()
+                            .ok()
+                            .filter(|value| (min..=max).contains(value))
+                            .ok_or_else(|| {
+                                format_err!(
+                                    "invalid value '{}' in cron field '{}', expected {}-{}",
+                                    value,
+                                    spec,
+                                    min,
+                                    max
+                                )
+                            })
+                    };
+                    match range.split_once('-') {
+                        Some((first, last)) => (parse(first)?, parse(last)?),
+                        None => (parse(range)?, parse(range)?),
+                    }
+                }
+            };
+            ensure!(first <= last, "invalid range in cron field '{}'", spec);
+            for value in (first..=last).step_by(step as usize) {
+                allowed[value as usize] = true;
+            }
+        }
+
+        Ok(Self {
+            allowed,
+            any: spec == "*",
+        })
+    }
+
+    fn contains(&self, value: u32) -> bool {
+        self.allowed.get(value as usize).copied().unwrap_or(false)
+    }
+}
+
+impl std::str::FromStr for Schedule {
+    type Err = Error;
+
+    fn from_str(schedule: &str) -> Fallible<Self> {
+        let fields: Vec<&str> = schedule.split_whitespace().collect();
+        ensure!(
+            fields.len() == 5,
+            "cron schedule '{}' must have 5 fields",
+            schedule
+        );
+
+        let mut days_of_week = Field::parse(fields[4], 0, 7)?;
+        // Both 0 and 7 are Sunday.
+        if days_of_week.allowed[7] {
+            days_of_week.allowed[0] = true;
+        }
+
+        Ok(Self {
+            minutes: Field::parse(fields[0], 0, 59)?,
+            hours: Field::parse(fields[1], 0, 23)?,
+            days_of_month: Field::parse(fields[2], 1, 31)?,
+            months: Field::parse(fields[3], 1, 12)?,
+            days_of_week,
+        })
+    }
+}
+
+impl Schedule {
+    /// Returns whether the schedule fires at the minute of the given time.
+    pub fn matches(&self, time: DateTime<Utc>) -> bool {
+        let day_of_month = self.days_of_month.contains(time.day());
+        let day_of_week = self
+            .days_of_week
+            .contains(time.weekday().num_days_from_sunday());
+        // As in cron, a day matches either field if both are restricted.
+        let day = if self.days_of_month.any || self.days_of_week.any {
+            day_of_month && day_of_week
+        } else {
+            day_of_month || day_of_week
+        };
+
+        day && self.minutes.contains(time.minute())
+            && self.hours.contains(time.hour())
+            && self.months.contains(time.month())
+    }
+
+    /// Returns the end of the window of the given duration, starting when the
+    /// schedule fires, which contains the given time.
+    ///
+    /// The search goes back minute by minute, so the duration should be bounded.
+    pub fn window_end(&self, now: DateTime<Utc>, duration: Duration) -> Option<DateTime<Utc>> {
+        // Look for the latest start within the duration before now.
+        let mut candidate = now
+            - Duration::nanoseconds(now.nanosecond() as i64)
+            - Duration::seconds(now.second() as i64);
+        while candidate + duration > now {
+            if self.matches(candidate) {
+                return Some(candidate + duration);
+            }
+            candidate = candidate - Duration::minutes(1);
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn time(time: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(time)
+            .unwrap()
+            .with_timezone(&Utc)
+    }
+
+    #[test]
+    fn cron_schedule() {
+        let schedule: Schedule = "30 18 * * 5".parse().unwrap();
+        // 2022-10-07 is a Friday.
+        assert!(schedule.matches(time("2022-10-07T18:30:00Z")));
+        assert!(!schedule.matches(time("2022-10-07T18:31:00Z")));
+        assert!(!schedule.matches(time("2022-10-08T18:30:00Z")));
+
+        let schedule: Schedule = "*/15 0-6 1,15 * 0".parse().unwrap();
+        assert!(schedule.matches(time("2022-10-15T06:45:00Z")));
+        // 2022-10-09 is a Sunday, either day field matches.
+        assert!(schedule.matches(time("2022-10-09T00:00:00Z")));
+        assert!(!schedule.matches(time("2022-10-10T00:00:00Z")));
+        assert!(!schedule.matches(time("2022-10-15T07:00:00Z")));
+
+        assert!("* * * *".parse::<Schedule>().is_err());
+        assert!("60 * * * *".parse::<Schedule>().is_err());
+        assert!("*/0 * * * *".parse::<Schedule>().is_err());
+        assert!("5-1 * * * *".parse::<Schedule>().is_err());
+    }
+
+    #[test]
+    fn schedule_window_end() {
+        // Weekdays from 09:00, for 8 hours.
+        let schedule: Schedule = "0 9 * * 1-5".parse().unwrap();
+        let hours = Duration::hours(8);
+        // 2022-10-07 is a Friday.
+        assert_eq!(
+            schedule.window_end(time("2022-10-07T12:34:56Z"), hours),
+            Some(time("2022-10-07T17:00:00Z"))
+        );
+        assert_eq!(
+            schedule.window_end(time("2022-10-07T09:00:00Z"), hours),
+            Some(time("2022-10-07T17:00:00Z"))
+        );
+        assert_eq!(
+            schedule.window_end(time("2022-10-07T17:00:00Z"), hours),
+            None
+        );
+        assert_eq!(
+            schedule.window_end(time("2022-10-08T12:00:00Z"), hours),
+            None
+        );
+    }
+}
@@ -1,4 +1,6 @@
+use actix_web::dev::ServiceResponse;
 use actix_web::http;
+use actix_web::middleware::{ErrorHandlerResponse, ErrorHandlers};
 use actix_web::web;
 use actix_web::HttpResponse;
 use prometheus::{IntCounterVec, Opts, Registry};
@@ -33,13 +35,20 @@ lazy_static! {
 pub static MISSING_APPSTATE_PANIC_MSG: &str =
     "the request has no app_data attached. this is a bug.";
 
+/// Media type of RFC 7807 problem details responses.
+pub static PROBLEM_JSON_CONTENT_TYPE: &str = "application/problem+json";
+
+/// Prefix of the stable problem type URIs.
+pub static PROBLEM_TYPE_PREFIX: &str = "urn:cincinnati:error:";
+
 /// Register relevant metrics to a prometheus registry.
 pub fn register_metrics(registry: &Registry) -> Fallible<()> {
     registry.register(Box::new(GRAPH_ERRORS.clone()))?;
+    crate::ratelimit::register_metrics(registry)?;
     Ok(())
 }
 
-#[derive(Debug, Error, Eq, PartialEq)]
+#[derive(Clone, Debug, Error, Eq, PartialEq)]
 /// Error that can be returned by graph endpoint.
 pub enum GraphError {
     /// Failed to deserialize JSON.
@@ -77,6 +86,34 @@ pub enum GraphError {
     /// Failed to parse as Semantic Version
     #[error("failed to process version: {}", _0)]
     ArchVersionError(String),
+
+    /// Client exceeded its request rate, may retry after the given number of seconds.
+    #[error("too many requests, retry after {} seconds", _0)]
+    TooManyRequests(u64),
+
+    /// No update path between the requested versions.
+    #[error("no update path: {}", _0)]
+    NoUpdatePath(String),
+
+    /// Missing or invalid credentials.
+    #[error("unauthorized: {}", _0)]
+    Unauthorized(String),
+
+    /// No resource at the requested path.
+    #[error("not found: {}", _0)]
+    NotFound(String),
+
+    /// The resource doesn't support the requested method.
+    #[error("method not allowed: {}", _0)]
+    MethodNotAllowed(String),
+
+    /// The request body exceeds the size limit.
+    #[error("payload too large: {}", _0)]
+    PayloadTooLarge(String),
+
+    /// The request could not be extracted, e.g. a malformed query or body.
+    #[error("invalid request: {}", _0)]
+    InvalidRequest(String),
 }
 
 impl actix_web::error::ResponseError for GraphError {
@@ -90,21 +127,99 @@ impl actix_web::error::ResponseError for GraphError {
     }
 }
 
-#[derive(Serialize, Deserialize)]
-struct ErrorMessage {
-    kind: String,
-    value: String,
+/// RFC 7807 problem details body.
+///
+/// Besides the standard members, `kind` and `value` are kept as extension
+/// members for clients relying on the previous error format.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ProblemDetails {
+    /// Stable URI identifying the problem type.
+    #[serde(rename = "type")]
+    pub problem_type: String,
+    /// Short, human-readable summary of the problem type.
+    pub title: String,
+    /// HTTP status code.
+    pub status: u16,
+    /// Human-readable explanation specific to this occurrence.
+    pub detail: String,
+    /// Identifier correlating this response with traces and logs.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub correlation_id: Option<String>,
+    /// Machine-readable error kind.
+    pub kind: String,
+    /// Same as `detail`.
+    pub value: String,
+}
+
+impl ProblemDetails {
+    /// Build problem details for the current request.
+    pub fn new(code: http::StatusCode, kind: &str, title: &str, detail: String) -> Self {
+        Self {
+            problem_type: format!("{}{}", PROBLEM_TYPE_PREFIX, kind.replace('_', "-")),
+            title: title.to_string(),
+            status: code.as_u16(),
+            value: detail.clone(),
+            detail,
+            correlation_id: Some(crate::tracing::correlation_id()),
+            kind: kind.to_string(),
+        }
+    }
+
+    /// Return the HTTP response carrying these problem details.
+    pub fn into_response(self) -> HttpResponse {
+        let code = http::StatusCode::from_u16(self.status)
+            .unwrap_or(http::StatusCode::INTERNAL_SERVER_ERROR);
+        let mut builder = HttpResponse::build(code);
+        if let Some(correlation_id) = &self.correlation_id {
+            builder.insert_header((CORRELATION_ID_HEADER, correlation_id.as_str()));
+        }
+        builder
+            .content_type(PROBLEM_JSON_CONTENT_TYPE)
+            .json(web::Json(self))
+    }
 }
 
+/// Response header carrying the correlation ID of an error response.
+pub static CORRELATION_ID_HEADER: &str = "x-correlation-id";
+
 impl GraphError {
-    /// Return the HTTP JSON error response.
+    /// Return the HTTP problem+json error response.
     pub fn as_json_error(&self) -> HttpResponse {
-        let code = self.status_code();
-        let json_body = web::Json(ErrorMessage {
-            kind: self.kind(),
-            value: self.value(),
-        });
-        HttpResponse::build(code).json(json_body)
+        let mut resp = self.problem_details().into_response();
+        if let GraphError::TooManyRequests(retry_after) = self {
+            resp.headers_mut().insert(
+                http::header::RETRY_AFTER,
+                http::header::HeaderValue::from(*retry_after),
+            );
+        }
+        resp
+    }
+
+    /// Return the problem details describing this error.
+    pub fn problem_details(&self) -> ProblemDetails {
+        ProblemDetails::new(self.status_code(), &self.kind(), self.title(), self.value())
+    }
+
+    /// Return a short, occurrence-independent summary for the error.
+    pub fn title(&self) -> &'static str {
+        match *self {
+            GraphError::FailedJsonIn(_) => "Failed to deserialize JSON",
+            GraphError::FailedJsonOut(_) => "Failed to serialize JSON",
+            GraphError::FailedUpstreamFetch(_) => "Failed to fetch upstream graph",
+            GraphError::FailedPluginExecution(_) => "Failed to execute plugins",
+            GraphError::FailedUpstreamRequest(_) => "Failed to assemble upstream request",
+            GraphError::InvalidContentType => "Invalid Content-Type requested",
+            GraphError::MissingParams(_) => "Mandatory client parameters missing",
+            GraphError::InvalidParams(_) => "Invalid client parameters",
+            GraphError::ArchVersionError(_) => "Failed to process version",
+            GraphError::TooManyRequests(_) => "Too many requests",
+            GraphError::NoUpdatePath(_) => "No update path",
+            GraphError::Unauthorized(_) => "Unauthorized",
+            GraphError::NotFound(_) => "Resource not found",
+            GraphError::MethodNotAllowed(_) => "Method not allowed",
+            GraphError::PayloadTooLarge(_) => "Payload too large",
+            GraphError::InvalidRequest(_) => "Invalid request",
+        }
     }
 
     /// Return the HTTP status code for the error.
@@ -119,6 +234,13 @@ impl GraphError {
             GraphError::MissingParams(_) => http::StatusCode::BAD_REQUEST,
             GraphError::InvalidParams(_) => http::StatusCode::BAD_REQUEST,
             GraphError::ArchVersionError(_) => http::StatusCode::INTERNAL_SERVER_ERROR,
+            GraphError::TooManyRequests(_) => http::StatusCode::TOO_MANY_REQUESTS,
+            GraphError::NoUpdatePath(_) => http::StatusCode::NOT_FOUND,
+            GraphError::Unauthorized(_) => http::StatusCode::UNAUTHORIZED,
+            GraphError::NotFound(_) => http::StatusCode::NOT_FOUND,
+            GraphError::MethodNotAllowed(_) => http::StatusCode::METHOD_NOT_ALLOWED,
+            GraphError::PayloadTooLarge(_) => http::StatusCode::PAYLOAD_TOO_LARGE,
+            GraphError::InvalidRequest(_) => http::StatusCode::BAD_REQUEST,
         }
     }
 
@@ -134,6 +256,13 @@ impl GraphError {
             GraphError::MissingParams(_) => "missing_params",
             GraphError::InvalidParams(_) => "invalid_params",
             GraphError::ArchVersionError(_) => "arch_version_error",
+            GraphError::TooManyRequests(_) => "too_many_requests",
+            GraphError::NoUpdatePath(_) => "no_update_path",
+            GraphError::Unauthorized(_) => "unauthorized",
+            GraphError::NotFound(_) => "not_found",
+            GraphError::MethodNotAllowed(_) => "method_not_allowed",
+            GraphError::PayloadTooLarge(_) => "payload_too_large",
+            GraphError::InvalidRequest(_) => "invalid_request",
         };
         kind.to_string()
     }
@@ -148,9 +277,130 @@ impl GraphError {
     }
 }
 
+/// Statuses of the plain error responses of actix-web, for unmatched routes
+/// and failing extractors.
+static FRAMEWORK_ERROR_STATUSES: &[http::StatusCode] = &[
+    http::StatusCode::BAD_REQUEST,
+    http::StatusCode::NOT_FOUND,
+    http::StatusCode::METHOD_NOT_ALLOWED,
+    http::StatusCode::PAYLOAD_TOO_LARGE,
+];
+
+/// Middleware turning the plain error responses of actix-web, such as the ones
+/// of unmatched routes and failing extractors, into `GraphError`s.
+pub fn problem_json_handlers<B: 'static>() -> ErrorHandlers<B> {
+    FRAMEWORK_ERROR_STATUSES
+        .iter()
+        .fold(ErrorHandlers::new(), |handlers, status| {
+            handlers.handler(*status, into_problem_json)
+        })
+}
+
+fn into_problem_json<B>(res: ServiceResponse<B>) -> actix_web::Result<ErrorHandlerResponse<B>> {
+    let is_problem_json = res
+        .headers()
+        .get(http::header::CONTENT_TYPE)
+        .map(|content_type| content_type == PROBLEM_JSON_CONTENT_TYPE)
+        .unwrap_or(false);
+    if is_problem_json {
+        return Ok(ErrorHandlerResponse::Response(res.map_into_left_body()));
+    }
+
+    let resource = format!("{} {}", res.request().method(), res.request().path());
+    let detail = res
+        .response()
+        .error()
+        .map(|e| e.to_string())
+        .unwrap_or_else(|| resource.clone());
+    let error = match res.status() {
+        http::StatusCode::NOT_FOUND => GraphError::NotFound(resource),
+        http::StatusCode::METHOD_NOT_ALLOWED => GraphError::MethodNotAllowed(resource),
+        http::StatusCode::PAYLOAD_TOO_LARGE => GraphError::PayloadTooLarge(detail),
+        _ => GraphError::InvalidRequest(detail),
+    };
+
+    let (req, _) = res.into_parts();
+    let res = actix_web::ResponseError::error_response(&error);
+    Ok(ErrorHandlerResponse::Response(
+        ServiceResponse::new(req, res).map_into_right_body(),
+    ))
+}
+
 #[cfg(test)]
 mod tests {
+    use super::*;
     use crate::ensure_query_params;
+    use actix_web::body::MessageBody;
+
+    #[test]
+    fn error_response_is_problem_json() {
+        let resp = GraphError::InvalidParams("bad channel".to_string()).as_json_error();
+
+        assert_eq!(resp.status(), http::StatusCode::BAD_REQUEST);
+        assert_eq!(
+            resp.headers().get(http::header::CONTENT_TYPE).unwrap(),
+            PROBLEM_JSON_CONTENT_TYPE
+        );
+        let correlation_id = resp
+            .headers()
+            .get(CORRELATION_ID_HEADER)
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .to_string();
+
+        let body = resp.into_body().try_into_bytes().unwrap();
+        let problem: ProblemDetails = serde_json::from_slice(&body).unwrap();
+        assert_eq!(problem.problem_type, "urn:cincinnati:error:invalid-params");
+        assert_eq!(problem.title, "Invalid client parameters");
+        assert_eq!(problem.status, 400);
+        assert_eq!(problem.detail, "invalid client parameters: bad channel");
+        assert_eq!(problem.kind, "invalid_params");
+        assert_eq!(problem.value, problem.detail);
+        assert_eq!(problem.correlation_id, Some(correlation_id));
+    }
+
+    #[test]
+    fn framework_errors_are_problem_json() -> Fallible<()> {
+        #[derive(Deserialize)]
+        struct Limit {
+            #[allow(dead_code)]
+            limit: u32,
+        }
+
+        let rt = crate::testing::init_runtime()?;
+        let app = actix_web::App::new()
+            .wrap(problem_json_handlers())
+            .service(web::resource("/limit").route(
+                web::get().to(|_: web::Query<Limit>| async { HttpResponse::Ok().finish() }),
+            ));
+
+        rt.block_on(async {
+            let svc = actix_web::test::init_service(app).await;
+            for (method, uri, kind) in &[
+                (http::Method::GET, "/limit?limit=many", "invalid_request"),
+                (http::Method::GET, "/unknown", "not_found"),
+                (http::Method::POST, "/limit?limit=1", "method_not_allowed"),
+            ] {
+                let req = actix_web::test::TestRequest::default()
+                    .method(method.clone())
+                    .uri(uri)
+                    .to_request();
+                let resp = actix_web::test::call_service(&svc, req).await;
+                assert_eq!(
+                    resp.headers().get(http::header::CONTENT_TYPE).unwrap(),
+                    PROBLEM_JSON_CONTENT_TYPE,
+                    "{} {}",
+                    method,
+                    uri
+                );
+                let problem: ProblemDetails = actix_web::test::read_body_json(resp).await;
+                assert_eq!(&problem.kind, kind);
+            }
+        });
+
+        Ok(())
+    }
 
     #[test]
     fn error_msg_missing_params() {
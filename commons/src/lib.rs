@@ -11,13 +11,31 @@ extern crate serde_derive;
 mod config;
 pub use crate::config::MergeOptions;
 
+pub mod auth;
+pub mod compression;
+pub mod config_files;
+pub mod cors;
+pub mod cron;
 pub mod de;
+pub mod listen;
+pub mod logging;
 pub mod metrics;
+pub mod openapi;
+pub mod otlp;
+pub mod outbound;
+pub mod ratelimit;
+pub mod request_id;
+pub mod selftest;
+pub mod server;
 pub mod testing;
+pub mod tls;
 pub mod tracing;
 
 mod errors;
-pub use errors::{register_metrics, Fallible, GraphError, MISSING_APPSTATE_PANIC_MSG};
+pub use errors::{
+    problem_json_handlers, register_metrics, Fallible, GraphError, ProblemDetails,
+    CORRELATION_ID_HEADER, MISSING_APPSTATE_PANIC_MSG, PROBLEM_JSON_CONTENT_TYPE,
+};
 
 /// Commonly used imports for error handling.
 pub mod prelude_errors {
@@ -141,7 +159,7 @@ pub fn validate_content_type(
 
     // FIXME: this is not a full-blown Accept parser
     if acceptable_content_types.iter().any(|c| c == header_value) {
-        return if header_value
+        if header_value
             .to_str()
             .unwrap_or("")
             .split('/')
@@ -156,7 +174,7 @@ pub fn validate_content_type(
                 Ok(a) => Ok(a.parse().unwrap()),
                 Err(_e) => Ok(MIN_CINCINNATI_VERSION.to_string()),
             }
-        };
+        }
     } else {
         Err(GraphError::InvalidContentType)
     }
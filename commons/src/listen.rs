@@ -0,0 +1,368 @@
+//! Listening addresses of the served endpoints.
+//!
+//! Services listen either on TCP addresses, or on a Unix domain socket for
+//! sidecar deployments which don't expose any TCP port. Under systemd socket
+//! activation, they listen on the sockets passed by the service manager instead.
+
+use crate::errors::prelude::*;
+use socket2::{Domain, Protocol, Socket, Type};
+use std::fmt;
+use std::net::{IpAddr, SocketAddr, TcpListener};
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::io::{FromRawFd, RawFd};
+use std::os::unix::net::UnixListener;
+use std::path::Path;
+
+/// First file descriptor passed by the service manager, see sd_listen_fds(3).
+static LISTEN_FDS_START: RawFd = 3;
+
+/// Name of the passed sockets, in `LISTEN_FDNAMES`, which the status service listens on.
+pub static STATUS_FD_NAME: &str = "status";
+
+/// Return the socket addresses of a server.
+///
+/// `addresses` overrides the single `address` if not empty.
+pub fn socket_addrs(address: IpAddr, addresses: &[IpAddr], port: u16) -> Vec<SocketAddr> {
+    if addresses.is_empty() {
+        vec![SocketAddr::new(address, port)]
+    } else {
+        addresses
+            .iter()
+            .map(|address| SocketAddr::new(*address, port))
+            .collect()
+    }
+}
+
+/// Maximum number of pending connections of a TCP listener, as in actix-web.
+static LISTEN_BACKLOG: i32 = 2048;
+
+/// Bind a TCP listener to `addr`.
+///
+/// IPv6 listeners only accept IPv6 connections, whatever the
+/// `net.ipv6.bindv6only` setting of the host, so that `0.0.0.0` and `::` can
+/// be listened on side by side.
+pub fn bind_tcp(addr: SocketAddr) -> Fallible<TcpListener> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+    if addr.is_ipv6() {
+        socket.set_only_v6(true)?;
+    }
+    socket.set_reuse_address(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(LISTEN_BACKLOG)?;
+
+    Ok(socket.into())
+}
+
+/// Returns whether two listeners would compete for the same connections.
+///
+/// Unspecified addresses cover their own address family only, see `bind_tcp`.
+fn overlaps(a: &SocketAddr, b: &SocketAddr) -> bool {
+    if a.port() != b.port() {
+        return false;
+    }
+
+    let covers =
+        |wide: &IpAddr, other: &IpAddr| wide.is_unspecified() && wide.is_ipv4() == other.is_ipv4();
+
+    a.ip() == b.ip() || covers(&a.ip(), &b.ip()) || covers(&b.ip(), &a.ip())
+}
+
+/// A listening socket of a service.
+#[derive(Debug)]
+pub enum Listener {
+    /// TCP socket.
+    Tcp(TcpListener),
+    /// Unix domain socket.
+    Unix(UnixListener),
+}
+
+impl fmt::Display for Listener {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Listener::Tcp(listener) => match listener.local_addr() {
+                Ok(addr) => write!(f, "{}", addr),
+                Err(_) => f.write_str("TCP socket"),
+            },
+            Listener::Unix(listener) => match listener
+                .local_addr()
+                .ok()
+                .as_ref()
+                .and_then(|addr| addr.as_pathname())
+            {
+                Some(path) => write!(f, "{}", path.display()),
+                None => f.write_str("Unix socket"),
+            },
+        }
+    }
+}
+
+/// Bind the listeners of a service, on its Unix socket if any and its TCP addresses.
+pub fn bind(socket_path: Option<&Path>, addrs: &[SocketAddr]) -> Fallible<Vec<Listener>> {
+    let mut listeners = Vec::with_capacity(addrs.len() + 1);
+    if let Some(path) = socket_path {
+        remove_stale_socket(path)?;
+        listeners.push(Listener::Unix(
+            UnixListener::bind(path).context(format!("binding {}", path.display()))?,
+        ));
+    }
+    for addr in addrs {
+        listeners.push(Listener::Tcp(
+            bind_tcp(*addr).context(format!("binding {}", addr))?,
+        ));
+    }
+
+    Ok(listeners)
+}
+
+/// Listening sockets passed by the service manager with socket activation.
+#[derive(Debug, Default)]
+pub struct ActivatedListeners {
+    /// Sockets of the main service.
+    pub main: Vec<Listener>,
+    /// Sockets of the status service.
+    pub status: Vec<Listener>,
+}
+
+impl ActivatedListeners {
+    /// Take the sockets passed through `LISTEN_FDS`, following sd_listen_fds(3).
+    ///
+    /// Sockets named [`STATUS_FD_NAME`] in `LISTEN_FDNAMES` are for the status
+    /// service, all others for the main service. This must only be called once,
+    /// as it takes ownership of the passed file descriptors.
+    pub fn from_env() -> Fallible<Self> {
+        let var = |name: &str| std::env::var(name).ok();
+        let fds = parse_listen_fds(
+            var("LISTEN_PID").as_deref(),
+            var("LISTEN_FDS").as_deref(),
+            var("LISTEN_FDNAMES").as_deref(),
+            std::process::id(),
+        )?;
+
+        let mut activated = Self::default();
+        for (fd, is_status) in fds {
+            // The file descriptor was passed to this process, and isn't owned
+            // by anything else.
+            let socket = unsafe { Socket::from_raw_fd(fd) };
+            socket.set_cloexec(true)?;
+            let listener = match socket
+                .local_addr()
+                .context(format!("inspecting passed file descriptor {}", fd))?
+                .as_socket()
+            {
+                Some(_) => Listener::Tcp(socket.into()),
+                None => Listener::Unix(socket.into()),
+            };
+            if is_status {
+                activated.status.push(listener);
+            } else {
+                activated.main.push(listener);
+            }
+        }
+
+        Ok(activated)
+    }
+}
+
+/// Return the passed file descriptors, and whether each is for the status service.
+///
+/// The variables are ignored if `LISTEN_PID` is not the current process, as
+/// they were then meant for a parent process.
+fn parse_listen_fds(
+    pid: Option<&str>,
+    fds: Option<&str>,
+    names: Option<&str>,
+    own_pid: u32,
+) -> Fallible<Vec<(RawFd, bool)>> {
+    let (pid, fds) = match (pid, fds) {
+        (Some(pid), Some(fds)) => (pid, fds),
+        _ => return Ok(vec![]),
+    };
+    if pid.trim().parse::<u32>().ok() != Some(own_pid) {
+        return Ok(vec![]);
+    }
+
+    let count: RawFd = fds
+        .trim()
+        .parse()
+        .context(format!("invalid LISTEN_FDS '{}'", fds))?;
+    let names: Vec<&str> = names.map_or_else(Vec::new, |names| names.split(':').collect());
+    ensure!(
+        names.is_empty() || names.len() == count as usize,
+        "LISTEN_FDNAMES names {} sockets, but LISTEN_FDS is {}",
+        names.len(),
+        count
+    );
+
+    Ok((0..count)
+        .map(|i| {
+            let is_status = names.get(i as usize) == Some(&STATUS_FD_NAME);
+            (LISTEN_FDS_START + i, is_status)
+        })
+        .collect())
+}
+
+/// Validate the listeners of the main and status services.
+pub fn validate_listeners(main: &[SocketAddr], status: &[SocketAddr]) -> Fallible<()> {
+    for (name, addrs) in &[("main", main), ("status", status)] {
+        for (i, addr) in addrs.iter().enumerate() {
+            ensure!(
+                !addrs[..i].contains(addr),
+                "{} service configured with duplicate address {}",
+                name,
+                addr
+            );
+        }
+    }
+
+    for addr in main {
+        if let Some(other) = status.iter().find(|other| overlaps(addr, other)) {
+            bail!(
+                "main and status service configured with overlapping addresses {} and {}",
+                addr,
+                other
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Validate the Unix sockets of the main and status services, if any, given
+/// whether each service terminates TLS.
+pub fn validate_sockets(
+    main: Option<&Path>,
+    status: Option<&Path>,
+    main_tls: bool,
+    status_tls: bool,
+) -> Fallible<()> {
+    if let (Some(main), Some(status)) = (main, status) {
+        ensure!(
+            main != status,
+            "main and status service configured with the same socket {}",
+            main.display()
+        );
+    }
+    ensure!(
+        !(main_tls && main.is_some() || status_tls && status.is_some()),
+        "TLS termination is not supported on Unix sockets"
+    );
+
+    Ok(())
+}
+
+/// Prepare binding a Unix socket, removing the socket left behind by a previous run.
+pub fn remove_stale_socket(path: &Path) -> Fallible<()> {
+    match std::fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => std::fs::remove_file(path)
+            .context(format!("removing stale socket {}", path.display()))?,
+        Ok(_) => bail!("{} already exists and is not a socket", path.display()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e).context(format!("checking socket {}", path.display())),
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    #[test]
+    fn listeners() -> Fallible<()> {
+        let localhost = IpAddr::V4(Ipv4Addr::LOCALHOST);
+        let any_v4 = IpAddr::V4(Ipv4Addr::UNSPECIFIED);
+        let any_v6 = IpAddr::V6(Ipv6Addr::UNSPECIFIED);
+
+        assert_eq!(
+            socket_addrs(localhost, &[], 8080),
+            vec![SocketAddr::new(localhost, 8080)]
+        );
+        let several = socket_addrs(localhost, &[any_v4, any_v6], 8080);
+        assert_eq!(
+            several,
+            vec![SocketAddr::new(any_v4, 8080), SocketAddr::new(any_v6, 8080)]
+        );
+
+        validate_listeners(&several, &socket_addrs(localhost, &[], 9080))?;
+        validate_listeners(
+            &socket_addrs(localhost, &[], 8080),
+            &socket_addrs(IpAddr::V6(Ipv6Addr::LOCALHOST), &[], 8080),
+        )?;
+        assert!(validate_listeners(&several, &socket_addrs(localhost, &[], 8080)).is_err());
+        assert!(validate_listeners(
+            &socket_addrs(any_v6, &[], 8080),
+            &socket_addrs(IpAddr::V6(Ipv6Addr::LOCALHOST), &[], 8080)
+        )
+        .is_err());
+        validate_listeners(
+            &socket_addrs(any_v6, &[], 8080),
+            &socket_addrs(localhost, &[], 8080),
+        )?;
+        assert!(validate_listeners(
+            &socket_addrs(localhost, &[localhost, localhost], 8080),
+            &socket_addrs(localhost, &[], 9080)
+        )
+        .is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn bind_both_unspecified_addresses() -> Fallible<()> {
+        let v4 = bind_tcp(SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0))?;
+        let port = v4.local_addr()?.port();
+        let v6 = bind_tcp(SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), port))?;
+        assert_eq!(v6.local_addr()?.port(), port);
+
+        // The port is still taken for each family.
+        assert!(bind_tcp(SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), port)).is_err());
+        assert!(bind_tcp(SocketAddr::new(IpAddr::V6(Ipv6Addr::LOCALHOST), port)).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn listen_fds() -> Fallible<()> {
+        assert_eq!(parse_listen_fds(None, None, None, 42)?, vec![]);
+        assert_eq!(parse_listen_fds(Some("41"), Some("2"), None, 42)?, vec![]);
+        assert_eq!(
+            parse_listen_fds(Some("42"), Some("2"), None, 42)?,
+            vec![(3, false), (4, false)]
+        );
+        assert_eq!(
+            parse_listen_fds(Some("42"), Some("3"), Some("main:status:main"), 42)?,
+            vec![(3, false), (4, true), (5, false)]
+        );
+        assert!(parse_listen_fds(Some("42"), Some("2"), Some("status"), 42).is_err());
+        assert!(parse_listen_fds(Some("42"), Some("two"), None, 42).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn sockets() -> Fallible<()> {
+        let main = Path::new("/run/cincinnati/main.sock");
+        let status = Path::new("/run/cincinnati/status.sock");
+        validate_sockets(Some(main), Some(status), false, false)?;
+        validate_sockets(None, None, true, true)?;
+        validate_sockets(None, Some(status), true, false)?;
+        assert!(validate_sockets(Some(main), Some(main), false, false).is_err());
+        assert!(validate_sockets(None, Some(status), true, true).is_err());
+        assert!(validate_sockets(Some(main), None, true, false).is_err());
+
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("main.sock");
+        remove_stale_socket(&path)?;
+        drop(std::os::unix::net::UnixListener::bind(&path)?);
+        assert!(path.exists());
+        remove_stale_socket(&path)?;
+        assert!(!path.exists());
+
+        std::fs::write(&path, "not a socket")?;
+        assert!(remove_stale_socket(&path).is_err());
+        assert!(path.exists());
+
+        Ok(())
+    }
+}
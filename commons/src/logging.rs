@@ -0,0 +1,147 @@
+//! Log output setup.
+
+use crate::request_id;
+use crate::tracing::current_trace_id;
+use log::kv::{self, Key, Value, Visitor};
+use log::{LevelFilter, Record};
+use std::io::Write;
+use std::str::FromStr;
+
+/// Format of the log output.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// Human-readable text, as formatted by `env_logger`.
+    #[default]
+    Text,
+    /// One JSON object per line.
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            x => Err(format!(
+                "unknown log format '{}', expected 'text' or 'json'",
+                x
+            )),
+        }
+    }
+}
+
+/// Initialize the global logger.
+///
+/// `verbosity` applies to the given modules, further filters are read from
+/// the `RUST_LOG` environment variable.
+pub fn init(modules: &[&str], verbosity: LevelFilter, format: LogFormat) {
+    let mut builder = env_logger::Builder::from_default_env();
+    for module in modules {
+        builder.filter(Some(module), verbosity);
+    }
+    match format {
+        LogFormat::Json => {
+            builder.format(|buf, record| {
+                let entry = json_entry(buf.timestamp_millis().to_string(), record);
+                writeln!(buf, "{}", entry)
+            });
+        }
+        LogFormat::Text => {
+            builder.format(|buf, record| {
+                let request_id = request_id::current()
+                    .map(|id| format!(" request_id={}", id))
+                    .unwrap_or_default();
+                writeln!(
+                    buf,
+                    "[{} {:<5} {}{}] {}",
+                    buf.timestamp(),
+                    buf.default_styled_level(record.level()),
+                    record.module_path().unwrap_or_else(|| record.target()),
+                    request_id,
+                    record.args()
+                )
+            });
+        }
+    }
+    builder.init();
+}
+
+/// Collects the key/value fields of a record.
+struct Fields(serde_json::Map<String, serde_json::Value>);
+
+impl<'kvs> Visitor<'kvs> for Fields {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), kv::Error> {
+        self.0.insert(
+            key.to_string(),
+            serde_json::Value::String(value.to_string()),
+        );
+        Ok(())
+    }
+}
+
+/// Build the JSON representation of a record.
+///
+/// The trace id of the active span and the ID of the request being served are
+/// included, so that logs can be correlated with traces and responses.
+fn json_entry(timestamp: String, record: &Record) -> serde_json::Value {
+    let mut entry = serde_json::Map::new();
+    entry.insert("timestamp".to_string(), timestamp.into());
+    entry.insert("level".to_string(), record.level().to_string().into());
+    entry.insert(
+        "module".to_string(),
+        record
+            .module_path()
+            .unwrap_or_else(|| record.target())
+            .into(),
+    );
+    if let Some(trace_id) = current_trace_id() {
+        entry.insert("trace_id".to_string(), trace_id.into());
+    }
+    if let Some(request_id) = request_id::current() {
+        entry.insert("request_id".to_string(), request_id.into());
+    }
+    entry.insert("message".to_string(), record.args().to_string().into());
+
+    let mut fields = Fields(serde_json::Map::new());
+    if record.key_values().visit(&mut fields).is_ok() && !fields.0.is_empty() {
+        entry.insert("fields".to_string(), fields.0.into());
+    }
+
+    entry.into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_record() {
+        let fields = ("cluster", "east-1");
+        let entry = json_entry(
+            "2022-06-01T12:00:00.000Z".to_string(),
+            &Record::builder()
+                .args(format_args!("graph published"))
+                .level(log::Level::Info)
+                .module_path(Some("graph_builder::graph"))
+                .key_values(&fields)
+                .build(),
+        );
+
+        assert_eq!(
+            entry,
+            serde_json::json!({
+                "timestamp": "2022-06-01T12:00:00.000Z",
+                "level": "INFO",
+                "module": "graph_builder::graph",
+                "message": "graph published",
+                "fields": { "cluster": "east-1" },
+            })
+        );
+
+        assert_eq!("json".parse::<LogFormat>(), Ok(LogFormat::Json));
+        assert!("yaml".parse::<LogFormat>().is_err());
+    }
+}
@@ -31,7 +31,13 @@ where
     let mut buf = vec![];
     match tenc.encode(&metrics, &mut buf) {
         Ok(()) => HttpResponse::Ok().body(buf),
-        Err(e) => HttpResponse::InternalServerError().body(format!("{}", e)),
+        Err(e) => crate::ProblemDetails::new(
+            actix_web::http::StatusCode::INTERNAL_SERVER_ERROR,
+            "metrics_encoding_failed",
+            "Failed to encode metrics",
+            e.to_string(),
+        )
+        .into_response(),
     }
 }
 
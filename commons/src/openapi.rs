@@ -0,0 +1,406 @@
+//! OpenAPI 3 documents of the HTTP services.
+//!
+//! Services register their routes through [`ApiRoutes`], along with an
+//! [`Endpoint`] describing each of them, and serve the resulting document.
+//! The paths and methods in the document are the ones actually routed, and
+//! query parameters are read by the handlers through the [`QueryParam`]
+//! definitions they are documented with, so that it can't drift from the
+//! service.
+
+use actix_web::http::Method;
+use actix_web::{web, HttpResponse};
+use serde_json::{json, Map, Value};
+
+/// Location of a parameter.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ParamLocation {
+    /// Query string parameter.
+    Query,
+    /// Request header.
+    Header,
+    /// Templated path segment.
+    Path,
+}
+
+impl ParamLocation {
+    fn name(self) -> &'static str {
+        match self {
+            ParamLocation::Query => "query",
+            ParamLocation::Header => "header",
+            ParamLocation::Path => "path",
+        }
+    }
+}
+
+/// A parameter of an endpoint.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Param {
+    /// Name of the parameter.
+    pub name: String,
+    /// Where the parameter is carried.
+    pub location: ParamLocation,
+    /// Whether requests must carry the parameter.
+    pub required: bool,
+    /// Description of the parameter.
+    pub description: String,
+}
+
+impl Param {
+    fn new(name: &str, location: ParamLocation, description: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            location,
+            required: location == ParamLocation::Path,
+            description: description.to_string(),
+        }
+    }
+
+    /// Optional query string parameter.
+    pub fn query(name: &str, description: &str) -> Self {
+        Self::new(name, ParamLocation::Query, description)
+    }
+
+    /// Optional request header.
+    pub fn header(name: &str, description: &str) -> Self {
+        Self::new(name, ParamLocation::Header, description)
+    }
+
+    /// Path parameter, matching a `{name}` segment of the path.
+    pub fn path(name: &str, description: &str) -> Self {
+        Self::new(name, ParamLocation::Path, description)
+    }
+
+    /// Mark the parameter as required.
+    pub fn required(mut self) -> Self {
+        self.required = true;
+        self
+    }
+
+    fn to_json(&self) -> Value {
+        json!({
+            "name": self.name,
+            "in": self.location.name(),
+            "required": self.required,
+            "description": self.description,
+            "schema": { "type": "string" },
+        })
+    }
+}
+
+/// A query string parameter, read by the handlers and documented from the same definition.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct QueryParam {
+    /// Name of the parameter.
+    pub name: &'static str,
+    /// Description of the parameter.
+    pub description: &'static str,
+}
+
+impl QueryParam {
+    /// Query string parameter `name`.
+    pub const fn new(name: &'static str, description: &'static str) -> Self {
+        Self { name, description }
+    }
+
+    /// Return the value of the parameter in `query`, if present.
+    pub fn get(&self, query: &str) -> Option<String> {
+        url::form_urlencoded::parse(query.as_bytes())
+            .find(|(key, _)| key == self.name)
+            .map(|(_, value)| value.into_owned())
+    }
+}
+
+impl From<&QueryParam> for Param {
+    fn from(param: &QueryParam) -> Self {
+        Param::query(param.name, param.description)
+    }
+}
+
+/// An endpoint of a service.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Endpoint {
+    /// HTTP method of the endpoint.
+    pub method: Method,
+    /// Path of the endpoint, with `{name}` segments for path parameters.
+    pub path: String,
+    /// Short description of the endpoint.
+    pub summary: String,
+    /// Parameters of the requests.
+    pub params: Vec<Param>,
+    /// Possible responses, by status code.
+    pub responses: Vec<(u16, String)>,
+}
+
+impl Endpoint {
+    fn new(method: Method, path: &str, summary: &str) -> Self {
+        Self {
+            method,
+            path: path.to_string(),
+            summary: summary.to_string(),
+            params: vec![],
+            responses: vec![],
+        }
+    }
+
+    /// `GET` endpoint at `path`.
+    pub fn get(path: &str, summary: &str) -> Self {
+        Self::new(Method::GET, path, summary)
+    }
+
+    /// `POST` endpoint at `path`.
+    pub fn post(path: &str, summary: &str) -> Self {
+        Self::new(Method::POST, path, summary)
+    }
+
+    /// Add a parameter.
+    pub fn param(mut self, param: Param) -> Self {
+        self.params.push(param);
+        self
+    }
+
+    /// Add optional query string parameters, as read by the handler.
+    pub fn query(self, params: &[&QueryParam]) -> Self {
+        params
+            .iter()
+            .fold(self, |endpoint, param| endpoint.param((*param).into()))
+    }
+
+    /// Add a possible response.
+    pub fn response(mut self, status: u16, description: &str) -> Self {
+        self.responses.push((status, description.to_string()));
+        self
+    }
+
+    fn operation(&self) -> Value {
+        let mut responses: Map<String, Value> = self
+            .responses
+            .iter()
+            .map(|(status, description)| {
+                (status.to_string(), json!({ "description": description }))
+            })
+            .collect();
+        if responses.is_empty() {
+            responses.insert("default".to_string(), json!({ "description": "" }));
+        }
+
+        json!({
+            "summary": self.summary,
+            "parameters": self.params.iter().map(Param::to_json).collect::<Vec<_>>(),
+            "responses": responses,
+        })
+    }
+}
+
+/// Render the OpenAPI document describing `endpoints`.
+pub fn document(title: &str, version: &str, endpoints: &[Endpoint]) -> Value {
+    let mut paths = Map::new();
+    for endpoint in endpoints {
+        let item = paths
+            .entry(endpoint.path.clone())
+            .or_insert_with(|| json!({}));
+        item[endpoint.method.as_str().to_lowercase()] = endpoint.operation();
+    }
+
+    json!({
+        "openapi": "3.0.3",
+        "info": { "title": title, "version": version },
+        "paths": paths,
+    })
+}
+
+/// Routes of a service, along with the endpoints they serve.
+#[derive(Default)]
+pub struct ApiRoutes {
+    endpoints: Vec<Endpoint>,
+    resources: Vec<actix_web::Resource>,
+}
+
+impl ApiRoutes {
+    /// Serve `endpoint` with `handler`.
+    pub fn route<F, Args>(self, endpoint: Endpoint, handler: F) -> Self
+    where
+        F: actix_web::Handler<Args>,
+        Args: actix_web::FromRequest + 'static,
+        F::Output: actix_web::Responder + 'static,
+    {
+        self.route_with(endpoint, handler, |resource| resource)
+    }
+
+    /// Serve `endpoint` with `handler`, on a resource customized by `configure`,
+    /// e.g. to set a payload limit.
+    pub fn route_with<F, Args, C>(mut self, endpoint: Endpoint, handler: F, configure: C) -> Self
+    where
+        F: actix_web::Handler<Args>,
+        Args: actix_web::FromRequest + 'static,
+        F::Output: actix_web::Responder + 'static,
+        C: FnOnce(actix_web::Resource) -> actix_web::Resource,
+    {
+        let resource = configure(web::resource(&endpoint.path))
+            .route(web::method(endpoint.method.clone()).to(handler));
+        self.endpoints.push(endpoint);
+        self.resources.push(resource);
+        self
+    }
+
+    /// Attach `data` to the routes added so far, taking precedence over the
+    /// application data of the same type.
+    pub fn app_data<U: Clone + 'static>(mut self, data: U) -> Self {
+        self.resources = self
+            .resources
+            .into_iter()
+            .map(|resource| resource.app_data(data.clone()))
+            .collect();
+        self
+    }
+
+    /// Return the endpoints served so far.
+    pub fn endpoints(&self) -> &[Endpoint] {
+        &self.endpoints
+    }
+
+    /// Register the routes, and serve their OpenAPI document at `document_path`.
+    pub fn register(
+        self,
+        cfg: &mut web::ServiceConfig,
+        document_path: &str,
+        title: &str,
+        version: &str,
+    ) {
+        let mut endpoints = self.endpoints;
+        endpoints.push(
+            Endpoint::get(document_path, "OpenAPI document of the service")
+                .response(200, "OpenAPI 3 document"),
+        );
+        let document = document(title, version, &endpoints);
+
+        for resource in self.resources {
+            cfg.service(resource);
+        }
+        cfg.service(web::resource(document_path).route(web::get().to(move || {
+            let document = document.clone();
+            async move { HttpResponse::Ok().json(document) }
+        })));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::init_runtime;
+
+    async fn ok() -> HttpResponse {
+        HttpResponse::Ok().finish()
+    }
+
+    #[test]
+    fn serve_document() -> crate::Fallible<()> {
+        let rt = init_runtime()?;
+
+        let routes = ApiRoutes::default()
+            .route(
+                Endpoint::get("/graph", "Get the update graph")
+                    .param(Param::query("channel", "Channel of the graph").required())
+                    .param(Param::header("Accept", "Media type of the graph"))
+                    .response(200, "The update graph"),
+                ok,
+            )
+            .route(
+                Endpoint::get("/debug/{section}", "Debug information")
+                    .param(Param::path("section", "Section to return")),
+                ok,
+            )
+            .route(Endpoint::post("/rescrape", "Trigger a rescrape"), ok);
+        assert_eq!(routes.endpoints().len(), 3);
+
+        let app = actix_web::App::new()
+            .configure(|cfg| routes.register(cfg, "/openapi.json", "test", "1.0.0"));
+        let document: Value = rt.block_on(async {
+            let svc = actix_web::test::init_service(app).await;
+            for (method, path) in &[
+                (Method::GET, "/graph"),
+                (Method::GET, "/debug/graph"),
+                (Method::POST, "/rescrape"),
+            ] {
+                let req = actix_web::test::TestRequest::default()
+                    .method(method.clone())
+                    .uri(path)
+                    .to_request();
+                let resp = actix_web::test::call_service(&svc, req).await;
+                assert!(resp.status().is_success(), "{} {}", method, path);
+            }
+            let req = actix_web::test::TestRequest::get()
+                .uri("/openapi.json")
+                .to_request();
+            actix_web::test::call_and_read_body_json(&svc, req).await
+        });
+
+        assert_eq!(document["openapi"], "3.0.3");
+        assert_eq!(document["info"]["title"], "test");
+        let mut paths: Vec<&String> = document["paths"].as_object().unwrap().keys().collect();
+        paths.sort();
+        assert_eq!(
+            paths,
+            vec!["/debug/{section}", "/graph", "/openapi.json", "/rescrape"]
+        );
+
+        let graph = &document["paths"]["/graph"]["get"];
+        assert_eq!(graph["parameters"][0]["name"], "channel");
+        assert_eq!(graph["parameters"][0]["in"], "query");
+        assert_eq!(graph["parameters"][0]["required"], true);
+        assert_eq!(graph["parameters"][1]["in"], "header");
+        assert_eq!(graph["parameters"][1]["required"], false);
+        assert_eq!(graph["responses"]["200"]["description"], "The update graph");
+        assert_eq!(
+            document["paths"]["/debug/{section}"]["get"]["parameters"][0]["required"],
+            true
+        );
+        assert!(document["paths"]["/rescrape"]["post"].is_object());
+
+        Ok(())
+    }
+
+    #[test]
+    fn query_param() {
+        static CHANNEL: QueryParam = QueryParam::new("channel", "Channel of the graph");
+
+        assert_eq!(
+            CHANNEL.get("arch=amd64&channel=stable%2D4.9"),
+            Some("stable-4.9".to_string())
+        );
+        assert_eq!(CHANNEL.get("channels=stable-4.9"), None);
+        assert_eq!(CHANNEL.get(""), None);
+
+        let endpoint = Endpoint::get("/graph", "Get the update graph").query(&[&CHANNEL]);
+        assert_eq!(
+            endpoint.params,
+            vec![Param::query("channel", "Channel of the graph")]
+        );
+    }
+
+    #[test]
+    fn route_app_data() -> crate::Fallible<()> {
+        let rt = init_runtime()?;
+
+        async fn name(name: web::Data<&'static str>) -> HttpResponse {
+            HttpResponse::Ok().body(*name.get_ref())
+        }
+        let routes = ApiRoutes::default()
+            .route(Endpoint::get("/okd/graph", "Get the update graph"), name)
+            .app_data(web::Data::new("okd"));
+
+        let app = actix_web::App::new()
+            .app_data(web::Data::new("default"))
+            .route("/graph", web::get().to(name))
+            .configure(|cfg| routes.register(cfg, "/okd/openapi.json", "test", "1.0.0"));
+        rt.block_on(async {
+            let svc = actix_web::test::init_service(app).await;
+            for (path, expected) in &[("/graph", "default"), ("/okd/graph", "okd")] {
+                let req = actix_web::test::TestRequest::get().uri(path).to_request();
+                let body = actix_web::test::call_and_read_body(&svc, req).await;
+                assert_eq!(body, *expected, "{}", path);
+            }
+        });
+
+        Ok(())
+    }
+}
@@ -0,0 +1,377 @@
+//! OTLP push export of metrics.
+//!
+//! The metrics of a Prometheus registry are periodically converted to the
+//! OpenTelemetry data model and sent to an OTLP/HTTP endpoint, e.g. an
+//! OpenTelemetry collector, using the protobuf encoding.
+
+use crate::prelude_errors::*;
+use opentelemetry_proto::tonic::collector::metrics::v1::ExportMetricsServiceRequest;
+use opentelemetry_proto::tonic::common::v1::{any_value, AnyValue, InstrumentationScope, KeyValue};
+use opentelemetry_proto::tonic::metrics::v1::{
+    metric, number_data_point, summary_data_point, AggregationTemporality, Gauge, Histogram,
+    HistogramDataPoint, Metric, NumberDataPoint, ResourceMetrics, ScopeMetrics, Sum, Summary,
+    SummaryDataPoint,
+};
+use opentelemetry_proto::tonic::resource::v1::Resource;
+use prometheus::proto::{MetricFamily, MetricType};
+use prometheus::Registry;
+use prost::Message;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Default interval between exports.
+pub static DEFAULT_EXPORT_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Maximum duration of an export, which is also bounded by half the interval.
+pub static EXPORT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Name of the instrumentation scope of all metrics.
+static SCOPE_NAME: &str = "cincinnati";
+
+/// Settings of the OTLP metrics export.
+#[derive(Clone, Debug, PartialEq)]
+pub struct OtlpSettings {
+    /// URL of the OTLP/HTTP metrics endpoint, e.g. `http://collector:4318/v1/metrics`.
+    pub endpoint: String,
+    /// Interval between exports.
+    pub interval: Duration,
+}
+
+/// Pushes the metrics of a registry to an OTLP endpoint.
+pub struct OtlpExporter {
+    settings: OtlpSettings,
+    service_name: String,
+    registry: &'static Registry,
+    client: reqwest::Client,
+    start: SystemTime,
+}
+
+impl OtlpExporter {
+    /// Create an exporter for the metrics of `registry`, reported as `service_name`.
+    pub fn try_new(
+        settings: OtlpSettings,
+        service_name: &str,
+        registry: &'static Registry,
+    ) -> Fallible<Self> {
+        url::Url::parse(&settings.endpoint)
+            .context(format!("parsing OTLP endpoint '{}'", settings.endpoint))?;
+        ensure!(
+            settings.interval > Duration::from_secs(0),
+            "OTLP export interval must be positive"
+        );
+
+        let client = crate::outbound::client_builder()
+            .timeout(EXPORT_TIMEOUT.min(settings.interval / 2))
+            .build()
+            .context("Building reqwest client")?;
+
+        Ok(Self {
+            settings,
+            service_name: service_name.to_string(),
+            registry,
+            client,
+            start: SystemTime::now(),
+        })
+    }
+
+    /// Export the metrics once.
+    pub async fn export(&self) -> Fallible<()> {
+        let request = export_request(
+            &self.registry.gather(),
+            &self.service_name,
+            self.start,
+            SystemTime::now(),
+        );
+
+        let response = self
+            .client
+            .post(&self.settings.endpoint)
+            .header(reqwest::header::CONTENT_TYPE, "application/x-protobuf")
+            .body(request.encode_to_vec())
+            .send()
+            .await
+            .context(format!("sending metrics to {}", self.settings.endpoint))?;
+        ensure!(
+            response.status().is_success(),
+            "OTLP endpoint {} returned status {}",
+            self.settings.endpoint,
+            response.status()
+        );
+
+        Ok(())
+    }
+
+    /// Export the metrics periodically, forever.
+    pub async fn run(self) {
+        let mut interval = tokio::time::interval(self.settings.interval);
+        // A slow export delays the next ones instead of causing a burst.
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            interval.tick().await;
+            if let Err(e) = self.export().await {
+                log::warn!("failed to export metrics: {:#}", e);
+            }
+        }
+    }
+}
+
+fn unix_nanos(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos() as u64
+}
+
+fn string_attribute(key: &str, value: &str) -> KeyValue {
+    KeyValue {
+        key: key.to_string(),
+        value: Some(AnyValue {
+            value: Some(any_value::Value::StringValue(value.to_string())),
+        }),
+    }
+}
+
+/// Build the OTLP `ExportMetricsServiceRequest` for the given metric families.
+pub fn export_request(
+    families: &[MetricFamily],
+    service_name: &str,
+    start: SystemTime,
+    now: SystemTime,
+) -> ExportMetricsServiceRequest {
+    let start_time_unix_nano = unix_nanos(start);
+    let time_unix_nano = unix_nanos(now);
+
+    let metrics = families
+        .iter()
+        .map(|family| {
+            let attributes = |metric: &prometheus::proto::Metric| -> Vec<KeyValue> {
+                metric
+                    .get_label()
+                    .iter()
+                    .map(|label| string_attribute(label.get_name(), label.get_value()))
+                    .collect()
+            };
+            let number_points =
+                |value: fn(&prometheus::proto::Metric) -> f64| -> Vec<NumberDataPoint> {
+                    family
+                        .get_metric()
+                        .iter()
+                        .map(|metric| NumberDataPoint {
+                            attributes: attributes(metric),
+                            start_time_unix_nano,
+                            time_unix_nano,
+                            value: Some(number_data_point::Value::AsDouble(value(metric))),
+                            ..Default::default()
+                        })
+                        .collect()
+                };
+
+            let data = match family.get_field_type() {
+                MetricType::COUNTER => metric::Data::Sum(Sum {
+                    data_points: number_points(|metric| metric.get_counter().get_value()),
+                    aggregation_temporality: AggregationTemporality::Cumulative as i32,
+                    is_monotonic: true,
+                }),
+                MetricType::GAUGE => metric::Data::Gauge(Gauge {
+                    data_points: number_points(|metric| metric.get_gauge().get_value()),
+                }),
+                MetricType::UNTYPED => metric::Data::Gauge(Gauge {
+                    data_points: number_points(|metric| metric.get_untyped().get_value()),
+                }),
+                MetricType::HISTOGRAM => metric::Data::Histogram(Histogram {
+                    data_points: family
+                        .get_metric()
+                        .iter()
+                        .map(|metric| {
+                            let histogram = metric.get_histogram();
+                            // Prometheus buckets are cumulative, OTLP ones are
+                            // not and end with the implicit +Inf bucket.
+                            let mut previous = 0;
+                            let mut bucket_counts: Vec<u64> = histogram
+                                .get_bucket()
+                                .iter()
+                                .map(|bucket| {
+                                    let count = bucket.get_cumulative_count() - previous;
+                                    previous = bucket.get_cumulative_count();
+                                    count
+                                })
+                                .collect();
+                            bucket_counts.push(histogram.get_sample_count() - previous);
+
+                            HistogramDataPoint {
+                                attributes: attributes(metric),
+                                start_time_unix_nano,
+                                time_unix_nano,
+                                count: histogram.get_sample_count(),
+                                sum: Some(histogram.get_sample_sum()),
+                                bucket_counts,
+                                explicit_bounds: histogram
+                                    .get_bucket()
+                                    .iter()
+                                    .map(|bucket| bucket.get_upper_bound())
+                                    .collect(),
+                                ..Default::default()
+                            }
+                        })
+                        .collect(),
+                    aggregation_temporality: AggregationTemporality::Cumulative as i32,
+                }),
+                MetricType::SUMMARY => metric::Data::Summary(Summary {
+                    data_points: family
+                        .get_metric()
+                        .iter()
+                        .map(|metric| {
+                            let summary = metric.get_summary();
+                            SummaryDataPoint {
+                                attributes: attributes(metric),
+                                start_time_unix_nano,
+                                time_unix_nano,
+                                count: summary.get_sample_count(),
+                                sum: summary.get_sample_sum(),
+                                quantile_values: summary
+                                    .get_quantile()
+                                    .iter()
+                                    .map(|quantile| summary_data_point::ValueAtQuantile {
+                                        quantile: quantile.get_quantile(),
+                                        value: quantile.get_value(),
+                                    })
+                                    .collect(),
+                                ..Default::default()
+                            }
+                        })
+                        .collect(),
+                }),
+            };
+
+            Metric {
+                name: family.get_name().to_string(),
+                description: family.get_help().to_string(),
+                data: Some(data),
+                ..Default::default()
+            }
+        })
+        .collect();
+
+    ExportMetricsServiceRequest {
+        resource_metrics: vec![ResourceMetrics {
+            resource: Some(Resource {
+                attributes: vec![string_attribute("service.name", service_name)],
+                ..Default::default()
+            }),
+            scope_metrics: vec![ScopeMetrics {
+                scope: Some(InstrumentationScope {
+                    name: SCOPE_NAME.to_string(),
+                    ..Default::default()
+                }),
+                metrics,
+                ..Default::default()
+            }],
+            ..Default::default()
+        }],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+    use prometheus::{Histogram, HistogramOpts, IntCounterVec, Opts};
+
+    #[test]
+    fn convert_metrics() -> Fallible<()> {
+        let registry = Registry::new_custom(Some("cincinnati".to_string()), None)?;
+        testing::dummy_gauge(&registry, 42.0)?;
+        let counter = IntCounterVec::new(Opts::new("requests_total", "Requests"), &["code"])?;
+        registry.register(Box::new(counter.clone()))?;
+        counter.with_label_values(&["200"]).inc_by(3);
+        let histogram = Histogram::with_opts(
+            HistogramOpts::new("duration_seconds", "Durations").buckets(vec![1.0, 10.0]),
+        )?;
+        registry.register(Box::new(histogram.clone()))?;
+        histogram.observe(0.5);
+        histogram.observe(5.0);
+        histogram.observe(50.0);
+
+        let request = export_request(
+            &registry.gather(),
+            "graph-builder",
+            UNIX_EPOCH,
+            UNIX_EPOCH + Duration::from_secs(1),
+        );
+        // The request survives the wire encoding.
+        let request = ExportMetricsServiceRequest::decode(&*request.encode_to_vec())?;
+        let resource = &request.resource_metrics[0];
+        assert_eq!(
+            resource.resource.as_ref().unwrap().attributes,
+            vec![string_attribute("service.name", "graph-builder")]
+        );
+        let metrics = &resource.scope_metrics[0].metrics;
+        let data = |name: &str| {
+            metrics
+                .iter()
+                .find(|metric| metric.name == name)
+                .and_then(|metric| metric.data.clone())
+                .unwrap()
+        };
+
+        match data("cincinnati_dummy_gauge") {
+            metric::Data::Gauge(gauge) => {
+                assert_eq!(
+                    gauge.data_points[0].value,
+                    Some(number_data_point::Value::AsDouble(42.0))
+                );
+                assert_eq!(gauge.data_points[0].time_unix_nano, 1_000_000_000);
+            }
+            data => panic!("unexpected gauge data {:?}", data),
+        }
+
+        match data("cincinnati_requests_total") {
+            metric::Data::Sum(sum) => {
+                assert!(sum.is_monotonic);
+                assert_eq!(
+                    sum.aggregation_temporality,
+                    AggregationTemporality::Cumulative as i32
+                );
+                let point = &sum.data_points[0];
+                assert_eq!(point.value, Some(number_data_point::Value::AsDouble(3.0)));
+                assert_eq!(point.attributes, vec![string_attribute("code", "200")]);
+            }
+            data => panic!("unexpected counter data {:?}", data),
+        }
+
+        match data("cincinnati_duration_seconds") {
+            metric::Data::Histogram(histogram) => {
+                let point = &histogram.data_points[0];
+                assert_eq!(point.count, 3);
+                assert_eq!(point.explicit_bounds, vec![1.0, 10.0]);
+                assert_eq!(point.bucket_counts, vec![1, 1, 1]);
+            }
+            data => panic!("unexpected histogram data {:?}", data),
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn export_to_endpoint() -> Fallible<()> {
+        let rt = testing::init_runtime()?;
+        let registry: &'static Registry = Box::leak(Box::new(Registry::new()));
+        testing::dummy_gauge(registry, 1.0)?;
+
+        let mock = mockito::mock("POST", "/v1/metrics")
+            .match_header("content-type", "application/x-protobuf")
+            .with_status(200)
+            .create();
+
+        let exporter = OtlpExporter::try_new(
+            OtlpSettings {
+                endpoint: format!("{}/v1/metrics", mockito::server_url()),
+                interval: DEFAULT_EXPORT_INTERVAL,
+            },
+            "policy-engine",
+            registry,
+        )?;
+        rt.block_on(exporter.export())?;
+        mock.assert();
+
+        Ok(())
+    }
+}
@@ -0,0 +1,282 @@
+//! Token-bucket rate limiting of client requests.
+//!
+//! Every client IP, and optionally every value of a cluster-ID query
+//! parameter, gets a bucket of `burst` tokens which refills at
+//! `requests_per_second`. A request takes one token from each of its buckets
+//! and is refused with `429 Too Many Requests` if one of them is empty.
+
+use crate::errors::{Fallible, GraphError};
+use actix_service::Service;
+use actix_web::body::EitherBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use futures::future::{self, Either, Future, TryFutureExt};
+use prometheus::{IntCounterVec, Opts, Registry};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use url::form_urlencoded;
+
+/// Interval after which idle buckets are dropped.
+static PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
+lazy_static! {
+    static ref RATE_LIMITED_REQUESTS: IntCounterVec = IntCounterVec::new(
+        Opts::new(
+            "rate_limited_requests_total",
+            "Total number of requests refused because of rate limiting"
+        ),
+        &["limit"]
+    )
+    .unwrap();
+}
+
+/// Register relevant metrics to a prometheus registry.
+pub fn register_metrics(registry: &Registry) -> Fallible<()> {
+    registry.register(Box::new(RATE_LIMITED_REQUESTS.clone()))?;
+    Ok(())
+}
+
+/// Rate limiting settings.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RateLimitSettings {
+    /// Sustained number of requests per second and client.
+    pub requests_per_second: f64,
+    /// Number of requests a client may send at once.
+    pub burst: u32,
+    /// Query parameter identifying the cluster, limited on its own if set.
+    pub cluster_id_param: Option<String>,
+    /// Identify clients by the `Forwarded` or `X-Forwarded-For` headers
+    /// instead of the peer address. Only enable this behind a trusted proxy.
+    pub trust_forwarded_for: bool,
+}
+
+/// Tokens of a single client.
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    /// Refill the bucket up to `now`.
+    fn refill(&mut self, now: Instant, settings: &RateLimitSettings) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens =
+            (self.tokens + elapsed * settings.requests_per_second).min(settings.burst as f64);
+        self.updated = now;
+    }
+
+    fn is_full(&self, settings: &RateLimitSettings) -> bool {
+        self.tokens >= settings.burst as f64
+    }
+}
+
+#[derive(Debug)]
+struct Buckets {
+    by_key: HashMap<String, Bucket>,
+    last_prune: Instant,
+}
+
+/// Shared rate limiter.
+#[derive(Clone, Debug)]
+pub struct RateLimiter {
+    settings: Arc<RateLimitSettings>,
+    buckets: Arc<Mutex<Buckets>>,
+}
+
+impl RateLimiter {
+    /// Create a rate limiter with the given settings.
+    pub fn new(settings: RateLimitSettings) -> Self {
+        Self {
+            settings: Arc::new(settings),
+            buckets: Arc::new(Mutex::new(Buckets {
+                by_key: HashMap::new(),
+                last_prune: Instant::now(),
+            })),
+        }
+    }
+
+    /// Take a token for the request, or return the error to respond with.
+    pub fn check(&self, req: &ServiceRequest) -> Result<(), GraphError> {
+        let now = Instant::now();
+
+        if let Some(ip) = self.client_ip(req) {
+            self.acquire(&format!("ip:{}", ip), now)
+                .map_err(|retry_after| rejected("client_ip", retry_after))?;
+        }
+
+        if let Some(cluster_id) = self.cluster_id(req) {
+            self.acquire(&format!("cluster:{}", cluster_id), now)
+                .map_err(|retry_after| rejected("cluster_id", retry_after))?;
+        }
+
+        Ok(())
+    }
+
+    fn client_ip(&self, req: &ServiceRequest) -> Option<String> {
+        if self.settings.trust_forwarded_for {
+            req.connection_info()
+                .realip_remote_addr()
+                .map(str::to_string)
+        } else {
+            req.peer_addr().map(|addr| addr.ip().to_string())
+        }
+    }
+
+    fn cluster_id(&self, req: &ServiceRequest) -> Option<String> {
+        let param = self.settings.cluster_id_param.as_ref()?;
+        form_urlencoded::parse(req.query_string().as_bytes())
+            .find(|(key, _)| key == param)
+            .map(|(_, value)| value.into_owned())
+    }
+
+    /// Take a token from the bucket of `key`, or return when the next one is available.
+    fn acquire(&self, key: &str, now: Instant) -> Result<(), Duration> {
+        let settings = &self.settings;
+        let mut buckets = self
+            .buckets
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+
+        if now.saturating_duration_since(buckets.last_prune) > PRUNE_INTERVAL {
+            buckets.by_key.retain(|_, bucket| {
+                bucket.refill(now, settings);
+                !bucket.is_full(settings)
+            });
+            buckets.last_prune = now;
+        }
+
+        let bucket = buckets
+            .by_key
+            .entry(key.to_string())
+            .or_insert_with(|| Bucket {
+                tokens: settings.burst as f64,
+                updated: now,
+            });
+        bucket.refill(now, settings);
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64(
+                (1.0 - bucket.tokens) / settings.requests_per_second,
+            ))
+        }
+    }
+}
+
+fn rejected(limit: &str, retry_after: Duration) -> GraphError {
+    RATE_LIMITED_REQUESTS.with_label_values(&[limit]).inc();
+    GraphError::TooManyRequests(retry_after.as_secs_f64().ceil() as u64)
+}
+
+/// Call the service unless the request is rate limited, for use with `App::wrap_fn`.
+///
+/// Requests pass through if no rate limiter is given. Rate limited requests
+/// get their error response right away rather than an error, so that the
+/// middlewares wrapping the limiter, e.g. CORS or request IDs, add their
+/// headers to it.
+pub fn limit<S, B>(
+    limiter: Option<&RateLimiter>,
+    req: ServiceRequest,
+    srv: &S,
+) -> impl Future<Output = Result<ServiceResponse<EitherBody<B>>, actix_web::Error>>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+{
+    match limiter.map(|limiter| limiter.check(&req)) {
+        Some(Err(e)) => Either::Left(future::ok(req.error_response(e).map_into_right_body())),
+        _ => Either::Right(srv.call(req).map_ok(ServiceResponse::map_into_left_body)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    fn limiter(cluster_id_param: Option<&str>) -> RateLimiter {
+        RateLimiter::new(RateLimitSettings {
+            requests_per_second: 1.0,
+            burst: 2,
+            cluster_id_param: cluster_id_param.map(str::to_string),
+            trust_forwarded_for: false,
+        })
+    }
+
+    #[test]
+    fn token_bucket() {
+        let limiter = limiter(None);
+        let now = Instant::now();
+
+        assert!(limiter.acquire("a", now).is_ok());
+        assert!(limiter.acquire("a", now).is_ok());
+        assert_eq!(limiter.acquire("a", now), Err(Duration::from_secs(1)));
+        assert!(limiter.acquire("b", now).is_ok());
+
+        let later = now + Duration::from_millis(1500);
+        assert!(limiter.acquire("a", later).is_ok());
+        assert!(limiter.acquire("a", later).is_err());
+    }
+
+    #[test]
+    fn check_by_ip_and_cluster_id() {
+        let limiter = limiter(Some("id"));
+        let request = |ip: &str, query: &str| {
+            TestRequest::get()
+                .uri(&format!("/graph?{}", query))
+                .peer_addr(format!("{}:12345", ip).parse().unwrap())
+                .to_srv_request()
+        };
+
+        assert!(limiter.check(&request("10.0.0.1", "id=a")).is_ok());
+        assert!(limiter.check(&request("10.0.0.2", "id=a")).is_ok());
+        assert_eq!(
+            limiter.check(&request("10.0.0.3", "id=a")),
+            Err(GraphError::TooManyRequests(1))
+        );
+        assert!(limiter.check(&request("10.0.0.3", "id=b")).is_ok());
+        assert!(limiter.check(&request("10.0.0.3", "id=c")).is_err());
+    }
+
+    #[actix_web::test]
+    async fn rejected_through_outer_middlewares() {
+        use crate::request_id::{self, REQUEST_ID_HEADER};
+        use actix_web::test::{call_service, init_service};
+        use actix_web::{web, App, HttpResponse};
+
+        let limiter = limiter(Some("id"));
+        let app = init_service(
+            App::new()
+                .wrap_fn(move |req, srv| limit(Some(&limiter), req, srv))
+                .wrap_fn(|req, srv| request_id::propagate(req, srv))
+                .route("/graph", web::get().to(HttpResponse::Ok)),
+        )
+        .await;
+
+        let mut statuses = vec![];
+        for _ in 0..3 {
+            let request = TestRequest::get().uri("/graph?id=a").to_request();
+            let response = call_service(&app, request).await;
+            assert!(response.headers().contains_key(REQUEST_ID_HEADER));
+            statuses.push(response.status().as_u16());
+        }
+        assert_eq!(statuses, vec![200, 200, 429]);
+    }
+
+    #[test]
+    fn too_many_requests_response() {
+        let resp = GraphError::TooManyRequests(3).as_json_error();
+        assert_eq!(
+            resp.status(),
+            actix_web::http::StatusCode::TOO_MANY_REQUESTS
+        );
+        assert_eq!(
+            resp.headers()
+                .get(actix_web::http::header::RETRY_AFTER)
+                .unwrap(),
+            "3"
+        );
+    }
+}
@@ -0,0 +1,131 @@
+//! Request IDs.
+//!
+//! Every request gets an ID, taken from its `X-Request-Id` header if the
+//! client sent a valid one, or generated otherwise. The ID is returned in the
+//! response, recorded on the request span and included in the log lines
+//! emitted while serving the request, so that a failed update check reported
+//! by a cluster can be found in the server logs.
+
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse};
+use actix_web::http::header::{HeaderName, HeaderValue};
+use futures::future::LocalBoxFuture;
+use opentelemetry::trace::get_active_span;
+use opentelemetry::Key;
+
+/// Header carrying the request ID, in requests and responses.
+pub static REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Maximum length of request IDs sent by clients.
+pub static MAX_REQUEST_ID_LEN: usize = 128;
+
+tokio::task_local! {
+    /// ID of the request being served by the current task.
+    static REQUEST_ID: String;
+}
+
+/// Returns the ID of the request being served, if any.
+pub fn current() -> Option<String> {
+    REQUEST_ID.try_with(Clone::clone).ok()
+}
+
+/// Returns whether a client-provided request ID is safe to log and echo.
+fn is_valid(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= MAX_REQUEST_ID_LEN
+        && id
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"-_.:".contains(&b))
+}
+
+/// Returns the request ID sent by the client, or a new random one.
+fn request_id(req: &ServiceRequest) -> String {
+    req.headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .filter(|id| is_valid(id))
+        .map(str::to_string)
+        .unwrap_or_else(|| format!("{:032x}", rand::random::<u128>()))
+}
+
+/// Middleware assigning an ID to each request.
+///
+/// It must be wrapped within the tracing middleware, so that the ID is
+/// recorded on the request span.
+pub fn propagate<S, B>(
+    req: ServiceRequest,
+    srv: &S,
+) -> LocalBoxFuture<'static, Result<ServiceResponse<B>, actix_web::Error>>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    let id = request_id(&req);
+    get_active_span(|span| span.set_attribute(Key::new("request_id").string(id.clone())));
+    let response = REQUEST_ID.scope(id.clone(), srv.call(req));
+
+    Box::pin(async move {
+        let mut response = response.await?;
+        // Valid by construction, as checked or generated above.
+        if let Ok(value) = HeaderValue::from_str(&id) {
+            response
+                .headers_mut()
+                .insert(HeaderName::from_static(REQUEST_ID_HEADER), value);
+        }
+        Ok(response)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::{call_service, init_service, read_body, TestRequest};
+    use actix_web::{web, App, HttpResponse};
+
+    #[test]
+    fn validate_request_ids() {
+        assert!(is_valid("7d1c9b7e-2f4a-4c55-9a3e-0c1d2e3f4a5b"));
+        assert!(is_valid("cluster.east-1:42"));
+        assert!(!is_valid(""));
+        assert!(!is_valid("two words"));
+        assert!(!is_valid("line\nbreak"));
+        assert!(!is_valid(&"a".repeat(MAX_REQUEST_ID_LEN + 1)));
+    }
+
+    #[actix_web::test]
+    async fn propagate_request_ids() {
+        let app = init_service(App::new().wrap_fn(propagate).route(
+            "/",
+            web::get().to(|| async { HttpResponse::Ok().body(current().unwrap()) }),
+        ))
+        .await;
+
+        let request = TestRequest::get()
+            .uri("/")
+            .insert_header((REQUEST_ID_HEADER, "support-case-1234"))
+            .to_request();
+        let response = call_service(&app, request).await;
+        assert_eq!(
+            response.headers().get(REQUEST_ID_HEADER).unwrap(),
+            "support-case-1234"
+        );
+        assert_eq!(read_body(response).await, "support-case-1234");
+
+        let request = TestRequest::get()
+            .uri("/")
+            .insert_header((REQUEST_ID_HEADER, "not a valid id"))
+            .to_request();
+        let response = call_service(&app, request).await;
+        let id = response
+            .headers()
+            .get(REQUEST_ID_HEADER)
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .to_string();
+        assert_eq!(id.len(), 32);
+        assert_eq!(read_body(response).await, id);
+
+        assert_eq!(current(), None);
+    }
+}
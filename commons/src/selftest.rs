@@ -0,0 +1,513 @@
+//! Client protocol conformance self-test.
+//!
+//! Exercises the graph endpoint of the local main service the way clients do:
+//! it fetches the graph, validates it against the Cincinnati graph format and
+//! checks the content negotiation, compression and headers of the responses.
+//! Served by the status service, it lets CD pipelines smoke test a deployment
+//! without external tooling.
+//!
+//! Each run sends several requests to the main service, so runs are spaced by
+//! at least [`MIN_RUN_INTERVAL`].
+
+use crate::compression::MIN_COMPRESSED_SIZE;
+use crate::errors::prelude::*;
+use crate::MIN_CINCINNATI_VERSION;
+use reqwest::header::{self, HeaderMap};
+use reqwest::StatusCode;
+use std::collections::{HashMap, HashSet};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use url::Url;
+
+/// Timeout of each request of the self-test.
+pub static REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Minimum interval between the starts of two runs.
+pub static MIN_RUN_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Encodings the self-test accepts, as sent by clients.
+static ACCEPT_ENCODING: &str = "zstd, br, gzip";
+
+/// Main service under test.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SelfTestTarget {
+    /// URL of the graph endpoint, along with the query parameters.
+    pub graph_url: Url,
+    /// Address the main service is reached on.
+    pub addr: SocketAddr,
+}
+
+impl SelfTestTarget {
+    /// Target the graph endpoint of a main service listening on the given address.
+    ///
+    /// Unspecified addresses are reached through the loopback interface.
+    pub fn new(addr: SocketAddr, tls: bool, path_prefix: &str, query: &str) -> Fallible<Self> {
+        let ip = match addr.ip() {
+            IpAddr::V4(ip) if ip.is_unspecified() => IpAddr::V4(Ipv4Addr::LOCALHOST),
+            IpAddr::V6(ip) if ip.is_unspecified() => IpAddr::V6(Ipv6Addr::LOCALHOST),
+            ip => ip,
+        };
+        let addr = SocketAddr::new(ip, addr.port());
+        let mut graph_url = Url::parse(&format!(
+            "{}://{}{}/graph",
+            if tls { "https" } else { "http" },
+            addr,
+            path_prefix.trim_end_matches('/')
+        ))?;
+        if !query.is_empty() {
+            graph_url.set_query(Some(query.trim_start_matches('?')));
+        }
+
+        Ok(Self { graph_url, addr })
+    }
+
+    /// Name the main service by a host name, such as one its certificate is
+    /// valid for, while still reaching it on the same address.
+    pub fn with_server_name(mut self, name: &str) -> Fallible<Self> {
+        self.graph_url
+            .set_host(Some(name))
+            .context(format!("invalid self-test server name '{}'", name))?;
+        Ok(self)
+    }
+}
+
+/// The self-test of a main service, along with the client it is run with.
+#[derive(Debug)]
+pub struct SelfTest {
+    target: SelfTestTarget,
+    client: reqwest::Client,
+    last_run: Mutex<Option<Instant>>,
+}
+
+impl SelfTest {
+    /// Prepare the self-test of the given main service.
+    ///
+    /// The certificate of the main service is verified against the system
+    /// roots and the CA certificates of the optional PEM file.
+    pub fn new(target: SelfTestTarget, ca_path: Option<&Path>) -> Fallible<Self> {
+        let mut builder = crate::outbound::client_builder().timeout(REQUEST_TIMEOUT);
+        if let Some(url::Host::Domain(name)) = target.graph_url.host() {
+            builder = builder.resolve(name, target.addr);
+        }
+        if let Some(path) = ca_path {
+            let file = std::fs::File::open(path).context(format!("opening {}", path.display()))?;
+            let certs = rustls_pemfile::certs(&mut std::io::BufReader::new(file))
+                .context(format!("reading certificates from {}", path.display()))?;
+            ensure!(!certs.is_empty(), "no certificates in {}", path.display());
+            for cert in certs {
+                builder = builder.add_root_certificate(reqwest::Certificate::from_der(&cert)?);
+            }
+        }
+
+        Ok(Self {
+            client: builder.build()?,
+            target,
+            last_run: Mutex::new(None),
+        })
+    }
+
+    /// Start a run, unless one started less than [`MIN_RUN_INTERVAL`] ago.
+    ///
+    /// Returns the time to wait before the next run otherwise.
+    pub fn try_start(&self) -> Result<(), Duration> {
+        let now = Instant::now();
+        let mut last_run = self
+            .last_run
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        match *last_run {
+            Some(last) if now.duration_since(last) < MIN_RUN_INTERVAL => {
+                Err(MIN_RUN_INTERVAL - now.duration_since(last))
+            }
+            _ => {
+                *last_run = Some(now);
+                Ok(())
+            }
+        }
+    }
+
+    /// Run all checks against the main service.
+    pub async fn run(&self) -> SelfTestReport {
+        run(&self.client, &self.target).await
+    }
+}
+
+/// Outcome of a check.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct SelfTestCheck {
+    /// Name of the check.
+    pub name: &'static str,
+    /// Whether the check passed.
+    pub passed: bool,
+    /// What was observed.
+    pub detail: String,
+}
+
+impl SelfTestCheck {
+    fn new(name: &'static str, outcome: Fallible<String>) -> Self {
+        match outcome {
+            Ok(detail) => Self {
+                name,
+                passed: true,
+                detail,
+            },
+            Err(e) => Self {
+                name,
+                passed: false,
+                detail: format!("{:#}", e),
+            },
+        }
+    }
+}
+
+/// Outcome of all checks, as served by the status service.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct SelfTestReport {
+    /// Whether all checks passed.
+    pub passed: bool,
+    /// Outcome of each check.
+    pub checks: Vec<SelfTestCheck>,
+}
+
+/// A response of the main service.
+struct Fetched {
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Vec<u8>,
+}
+
+async fn fetch(
+    client: &reqwest::Client,
+    target: &SelfTestTarget,
+    accept: &str,
+    accept_encoding: &str,
+) -> Fallible<Fetched> {
+    let response = client
+        .get(target.graph_url.clone())
+        .header(header::ACCEPT, accept)
+        .header(header::ACCEPT_ENCODING, accept_encoding)
+        .send()
+        .await
+        .context(format!("requesting {}", target.graph_url))?;
+
+    Ok(Fetched {
+        status: response.status(),
+        headers: response.headers().clone(),
+        body: response.bytes().await?.to_vec(),
+    })
+}
+
+fn header_value(headers: &HeaderMap, name: header::HeaderName) -> &str {
+    headers
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default()
+}
+
+async fn run(client: &reqwest::Client, target: &SelfTestTarget) -> SelfTestReport {
+    let graph = fetch(client, target, "application/json", "identity")
+        .await
+        .and_then(|fetched| {
+            ensure!(
+                fetched.status == StatusCode::OK,
+                "expected 200 OK, got {}",
+                fetched.status
+            );
+            Ok(fetched)
+        });
+    let mut checks = vec![SelfTestCheck::new(
+        "graph",
+        graph
+            .as_ref()
+            .map(|fetched| format!("{} bytes", fetched.body.len()))
+            .map_err(|e| format_err!("{:#}", e)),
+    )];
+    let body = graph.map(|fetched| fetched.body).ok();
+
+    checks.push(SelfTestCheck::new(
+        "schema",
+        match &body {
+            Some(body) => validate_graph(body),
+            None => Err(format_err!("no graph to validate")),
+        },
+    ));
+
+    checks.push(SelfTestCheck::new(
+        "content_type",
+        fetch(client, target, *MIN_CINCINNATI_VERSION, "identity")
+            .await
+            .and_then(|fetched| {
+                let content_type = header_value(&fetched.headers, header::CONTENT_TYPE);
+                ensure!(
+                    fetched.status == StatusCode::OK && content_type == *MIN_CINCINNATI_VERSION,
+                    "expected 200 OK with Content-Type {}, got {} with Content-Type '{}'",
+                    *MIN_CINCINNATI_VERSION,
+                    fetched.status,
+                    content_type
+                );
+                Ok(format!("served as {}", content_type))
+            }),
+    ));
+
+    checks.push(SelfTestCheck::new(
+        "content_negotiation",
+        fetch(client, target, "image/png", "identity")
+            .await
+            .and_then(|fetched| {
+                ensure!(
+                    fetched.status == StatusCode::NOT_ACCEPTABLE,
+                    "expected 406 Not Acceptable for an unsupported media type, got {}",
+                    fetched.status
+                );
+                Ok("unsupported media types are rejected".to_string())
+            }),
+    ));
+
+    checks.push(SelfTestCheck::new(
+        "compression",
+        match &body {
+            Some(body) => fetch(client, target, "application/json", ACCEPT_ENCODING)
+                .await
+                .and_then(|fetched| check_compression(body.len(), &fetched)),
+            None => Err(format_err!("no graph to compare with")),
+        },
+    ));
+
+    SelfTestReport {
+        passed: checks.iter().all(|check| check.passed),
+        checks,
+    }
+}
+
+fn check_compression(identity_size: usize, fetched: &Fetched) -> Fallible<String> {
+    ensure!(
+        fetched.status == StatusCode::OK,
+        "expected 200 OK, got {}",
+        fetched.status
+    );
+    ensure!(
+        fetched
+            .headers
+            .get_all(header::VARY)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .any(|name| name.trim().eq_ignore_ascii_case("accept-encoding")),
+        "missing 'Vary: Accept-Encoding'"
+    );
+
+    let encoding = header_value(&fetched.headers, header::CONTENT_ENCODING);
+    if identity_size < MIN_COMPRESSED_SIZE {
+        return Ok(format!(
+            "graph of {} bytes is too small to be compressed",
+            identity_size
+        ));
+    }
+    ensure!(
+        ACCEPT_ENCODING
+            .split(", ")
+            .any(|accepted| accepted == encoding),
+        "expected one of '{}', got Content-Encoding '{}'",
+        ACCEPT_ENCODING,
+        encoding
+    );
+    ensure!(
+        fetched.body.len() < identity_size,
+        "{} response of {} bytes is not smaller than the {} bytes graph",
+        encoding,
+        fetched.body.len(),
+        identity_size
+    );
+
+    Ok(format!(
+        "{} bytes compressed to {} bytes with {}",
+        identity_size,
+        fetched.body.len(),
+        encoding
+    ))
+}
+
+/// A graph, as sent to clients.
+#[derive(Deserialize)]
+struct Graph {
+    nodes: Vec<Node>,
+    edges: Vec<(usize, usize)>,
+    #[serde(default, rename = "conditionalEdges")]
+    conditional_edges: Vec<ConditionalEdges>,
+}
+
+#[derive(Deserialize)]
+struct Node {
+    version: String,
+    payload: String,
+    #[allow(dead_code)]
+    metadata: HashMap<String, String>,
+}
+
+#[derive(Deserialize)]
+struct ConditionalEdges {
+    edges: Vec<ConditionalEdge>,
+    risks: Vec<Risk>,
+}
+
+#[derive(Deserialize)]
+struct ConditionalEdge {
+    from: String,
+    to: String,
+}
+
+#[derive(Deserialize)]
+struct Risk {
+    name: String,
+    #[serde(rename = "matchingRules")]
+    matching_rules: Vec<serde_json::Value>,
+}
+
+/// Validate a serialized graph against the Cincinnati graph format.
+fn validate_graph(body: &[u8]) -> Fallible<String> {
+    let graph: Graph = serde_json::from_slice(body).context("invalid graph document")?;
+
+    let mut versions = HashSet::with_capacity(graph.nodes.len());
+    for node in &graph.nodes {
+        ensure!(
+            !node.version.is_empty() && !node.payload.is_empty(),
+            "node with empty version or payload"
+        );
+        ensure!(
+            versions.insert(node.version.as_str()),
+            "duplicate node {}",
+            node.version
+        );
+    }
+    for (from, to) in &graph.edges {
+        ensure!(
+            *from < graph.nodes.len() && *to < graph.nodes.len(),
+            "edge ({}, {}) out of the {} nodes",
+            from,
+            to,
+            graph.nodes.len()
+        );
+    }
+    let mut conditional_edges = 0;
+    for conditional in &graph.conditional_edges {
+        for edge in &conditional.edges {
+            ensure!(
+                versions.contains(edge.from.as_str()) && versions.contains(edge.to.as_str()),
+                "conditional edge {} -> {} between unknown nodes",
+                edge.from,
+                edge.to
+            );
+        }
+        for risk in &conditional.risks {
+            ensure!(
+                !risk.matching_rules.is_empty(),
+                "risk {} without matching rules",
+                risk.name
+            );
+        }
+        conditional_edges += conditional.edges.len();
+    }
+
+    Ok(format!(
+        "{} nodes, {} edges, {} conditional edges",
+        graph.nodes.len(),
+        graph.edges.len(),
+        conditional_edges
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn targets() -> Fallible<()> {
+        let target = SelfTestTarget::new("0.0.0.0:8080".parse()?, false, "", "")?;
+        assert_eq!(target.graph_url.as_str(), "http://127.0.0.1:8080/graph");
+
+        let target = SelfTestTarget::new(
+            "[::]:8443".parse()?,
+            true,
+            "/api/upgrades_info/",
+            "channel=stable-4.11",
+        )?;
+        assert_eq!(
+            target.graph_url.as_str(),
+            "https://[::1]:8443/api/upgrades_info/graph?channel=stable-4.11"
+        );
+
+        let target = target.with_server_name("cincinnati.example.com")?;
+        assert_eq!(
+            target.graph_url.as_str(),
+            "https://cincinnati.example.com:8443/api/upgrades_info/graph?channel=stable-4.11"
+        );
+        assert_eq!(target.addr, "[::1]:8443".parse()?);
+        Ok(())
+    }
+
+    #[test]
+    fn runs_are_spaced() -> Fallible<()> {
+        let target = SelfTestTarget::new("127.0.0.1:8080".parse()?, false, "", "")?;
+        let selftest = SelfTest::new(target, None)?;
+
+        assert!(selftest.try_start().is_ok());
+        let wait = selftest.try_start().unwrap_err();
+        assert!(wait > Duration::from_secs(0) && wait <= MIN_RUN_INTERVAL);
+
+        *selftest.last_run.lock().unwrap() = Some(Instant::now() - MIN_RUN_INTERVAL);
+        assert!(selftest.try_start().is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn graph_format() {
+        let node = |version: &str| {
+            format!(
+                r#"{{"version":"{}","payload":"quay.io/ocp/release@sha256:{}","metadata":{{}}}}"#,
+                version, version
+            )
+        };
+        let graph = |edges: &str, conditional_edges: &str| {
+            format!(
+                r#"{{"nodes":[{},{}],"edges":{},"conditionalEdges":{}}}"#,
+                node("4.11.0"),
+                node("4.11.1"),
+                edges,
+                conditional_edges
+            )
+        };
+        let risk = r#"{"url":"https://example.com","name":"A","message":"m","matchingRules":[{"type":"Always"}]}"#;
+
+        assert_eq!(
+            validate_graph(graph("[[0,1]]", "[]").as_bytes()).unwrap(),
+            "2 nodes, 1 edges, 0 conditional edges"
+        );
+        assert!(validate_graph(
+            graph(
+                "[]",
+                &format!(
+                    r#"[{{"edges":[{{"from":"4.11.0","to":"4.11.1"}}],"risks":[{}]}}]"#,
+                    risk
+                )
+            )
+            .as_bytes()
+        )
+        .is_ok());
+
+        assert!(validate_graph(graph("[[0,2]]", "[]").as_bytes()).is_err());
+        assert!(validate_graph(
+            graph(
+                "[]",
+                &format!(
+                    r#"[{{"edges":[{{"from":"4.11.0","to":"4.12.0"}}],"risks":[{}]}}]"#,
+                    risk
+                )
+            )
+            .as_bytes()
+        )
+        .is_err());
+        assert!(validate_graph(b"{\"nodes\":[]}").is_err());
+    }
+}
@@ -0,0 +1,110 @@
+//! Tuning of the HTTP servers.
+//!
+//! The main and status services each run their own server, tuned separately:
+//! high-concurrency deployments mostly need to tune the main service, while
+//! the status service keeps the defaults.
+
+use crate::errors::prelude::*;
+use actix_web::web::PayloadConfig;
+use std::time::Duration;
+
+/// Default keep-alive of idle connections, as in actix-web.
+pub static DEFAULT_KEEP_ALIVE: Duration = Duration::from_secs(5);
+
+/// Default time a client has to send the headers of its first request, as in actix-web.
+pub static DEFAULT_CLIENT_REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Default maximum size of request bodies, as in actix-web.
+pub static DEFAULT_MAX_PAYLOAD_BYTES: usize = 262_144;
+
+/// Settings of an HTTP server.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HttpServerSettings {
+    /// Keep-alive of idle connections, disabled if unset.
+    pub keep_alive: Option<Duration>,
+    /// Time a client has to send the headers of its first request.
+    pub client_request_timeout: Duration,
+    /// Maximum size of request bodies, in bytes.
+    pub max_payload_bytes: usize,
+    /// Number of worker threads, one per physical CPU if unset.
+    pub workers: Option<usize>,
+    /// Accept HTTP/2 without TLS (h2c) on TCP listeners, in addition to HTTP/1.1.
+    /// TLS listeners always negotiate HTTP/2 through ALPN.
+    pub http2: bool,
+}
+
+impl Default for HttpServerSettings {
+    fn default() -> Self {
+        Self {
+            keep_alive: Some(DEFAULT_KEEP_ALIVE),
+            client_request_timeout: DEFAULT_CLIENT_REQUEST_TIMEOUT,
+            max_payload_bytes: DEFAULT_MAX_PAYLOAD_BYTES,
+            workers: None,
+            http2: false,
+        }
+    }
+}
+
+impl HttpServerSettings {
+    /// Set the keep-alive, in seconds, 0 disabling it.
+    pub fn set_keep_alive_secs(&mut self, secs: u64) {
+        self.keep_alive = if secs == 0 {
+            None
+        } else {
+            Some(Duration::from_secs(secs))
+        };
+    }
+
+    /// Validate the settings of the server of the `service` section.
+    pub fn validate(&self, service: &str) -> Fallible<()> {
+        ensure!(
+            self.workers != Some(0),
+            "{}.workers must be positive",
+            service
+        );
+        ensure!(
+            self.max_payload_bytes > 0,
+            "{}.max_payload_bytes must be positive",
+            service
+        );
+        Ok(())
+    }
+
+    /// Size limit of the request bodies, to be registered as app data.
+    ///
+    /// Resources with their own limit keep it.
+    pub fn payload_config(&self) -> PayloadConfig {
+        PayloadConfig::new(self.max_payload_bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validate_server_settings() {
+        let mut settings = HttpServerSettings::default();
+        assert!(settings.validate("service").is_ok());
+
+        settings.set_keep_alive_secs(0);
+        assert_eq!(settings.keep_alive, None);
+        settings.set_keep_alive_secs(75);
+        assert_eq!(settings.keep_alive, Some(Duration::from_secs(75)));
+
+        let error = HttpServerSettings {
+            workers: Some(0),
+            ..Default::default()
+        }
+        .validate("status")
+        .unwrap_err();
+        assert_eq!(error.to_string(), "status.workers must be positive");
+
+        assert!(HttpServerSettings {
+            max_payload_bytes: 0,
+            ..Default::default()
+        }
+        .validate("service")
+        .is_err());
+    }
+}
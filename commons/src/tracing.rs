@@ -7,11 +7,12 @@ use opentelemetry::{
         propagation::TraceContextPropagator,
         trace::{Config, Sampler, TracerProvider as sdk_tracerprovider},
     },
-    trace::{Span, TracerProvider},
-    Context, Key,
+    trace::{get_active_span, Span, StatusCode, TraceContextExt, Tracer, TracerProvider},
+    Context, Key, KeyValue,
 };
 
 use std::collections::HashMap;
+use std::str::FromStr;
 
 use actix_web::dev::ServiceRequest;
 use actix_web::http::header::HeaderMap as HttpHeaderMap;
@@ -19,8 +20,88 @@ use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
 
 use crate::prelude_errors::*;
 
+/// Default ratio of sampled traces.
+pub static DEFAULT_SAMPLING_RATIO: f64 = 1.0;
+
+/// Sampling strategy of the traces.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SamplerKind {
+    /// Sample every trace.
+    AlwaysOn,
+    /// Sample a ratio of the traces, by trace ID.
+    Ratio,
+    /// Follow the sampling decision of the caller, sampling a ratio of the
+    /// traces started locally.
+    #[default]
+    ParentBased,
+}
+
+impl FromStr for SamplerKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "always_on" => Ok(SamplerKind::AlwaysOn),
+            "ratio" => Ok(SamplerKind::Ratio),
+            "parent_based" => Ok(SamplerKind::ParentBased),
+            x => Err(format!(
+                "unknown sampler '{}', expected 'always_on', 'ratio' or 'parent_based'",
+                x
+            )),
+        }
+    }
+}
+
+/// Sampling settings of the tracer.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SamplingSettings {
+    /// Sampling strategy.
+    pub sampler: SamplerKind,
+    /// Ratio of sampled traces, between 0 and 1, for the ratio based strategies.
+    pub ratio: f64,
+}
+
+impl Default for SamplingSettings {
+    fn default() -> Self {
+        Self {
+            sampler: SamplerKind::default(),
+            ratio: DEFAULT_SAMPLING_RATIO,
+        }
+    }
+}
+
+impl SamplingSettings {
+    /// Check that the ratio is valid.
+    pub fn validate(&self) -> Fallible<()> {
+        ensure!(
+            (0.0..=1.0).contains(&self.ratio),
+            "sampling ratio must be between 0 and 1, got {}",
+            self.ratio
+        );
+        Ok(())
+    }
+
+    fn sampler(&self) -> Sampler {
+        match self.sampler {
+            SamplerKind::AlwaysOn => Sampler::AlwaysOn,
+            SamplerKind::Ratio => Sampler::TraceIdRatioBased(self.ratio),
+            SamplerKind::ParentBased => {
+                Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(self.ratio)))
+            }
+        }
+    }
+}
+
 /// init_tracer sets up Jaeger tracer
-pub fn init_tracer(name: &'static str, maybe_agent_endpoint: Option<String>) -> Fallible<()> {
+///
+/// Spans are exported in batches from a background task, so that ending a span
+/// never waits for the agent. It must be called within a Tokio runtime.
+pub fn init_tracer(
+    name: &'static str,
+    maybe_agent_endpoint: Option<String>,
+    sampling: SamplingSettings,
+) -> Fallible<()> {
     // Skip provider config if agent endpoint is not set
     let agent_endpoint = match maybe_agent_endpoint {
         None => return Ok(()),
@@ -34,9 +115,9 @@ pub fn init_tracer(name: &'static str, maybe_agent_endpoint: Option<String>) ->
         .init_exporter()?;
 
     let provider = sdk_tracerprovider::builder()
-        .with_simple_exporter(exporter)
+        .with_batch_exporter(exporter, opentelemetry::runtime::Tokio)
         .with_config(Config {
-            sampler: Box::new(Sampler::AlwaysOn),
+            sampler: Box::new(sampling.sampler()),
             ..Default::default()
         })
         .build();
@@ -45,11 +126,45 @@ pub fn init_tracer(name: &'static str, maybe_agent_endpoint: Option<String>) ->
     Ok(())
 }
 
+/// Returns the trace id of the active span, if any, as hex string.
+pub fn current_trace_id() -> Option<String> {
+    get_active_span(|span| {
+        let span_context = span.span_context();
+        if span_context.is_valid() {
+            Some(span_context.trace_id().to_hex())
+        } else {
+            None
+        }
+    })
+}
+
 /// get_tracer returns an instance of global tracer
 pub fn get_tracer() -> global::BoxedTracer {
     global::tracer_provider().get_tracer("", None)
 }
 
+/// Return a context holding a new span, child of the current one.
+///
+/// Futures run with `FutureExt::with_context` on the returned context are
+/// traced within the span, which ends once the context is dropped.
+pub fn child_context(name: &'static str, attributes: Vec<KeyValue>) -> Context {
+    let mut span = get_tracer().start(name);
+    for attribute in attributes {
+        span.set_attribute(attribute);
+    }
+    Context::current_with_span(span)
+}
+
+/// Mark the span of `cx` as failed if `result` is an error.
+pub fn record_result<T, E>(cx: &Context, result: &Result<T, E>)
+where
+    E: std::fmt::Display,
+{
+    if let Err(e) = result {
+        cx.span().set_status(StatusCode::Error, e.to_string());
+    }
+}
+
 struct HttpHeaderMapCarrier<'a>(&'a HttpHeaderMap);
 impl<'a> Extractor for HttpHeaderMapCarrier<'a> {
     fn get(&self, key: &str) -> Option<&str> {
@@ -113,6 +228,23 @@ pub fn set_context(context: Context, headers: &mut HeaderMap) -> crate::errors::
     Ok(())
 }
 
+/// Return an identifier correlating the current request with its trace.
+///
+/// This is the trace ID of the active span if there is one, or a random
+/// identifier of the same format otherwise.
+pub fn correlation_id() -> String {
+    let trace_id = get_active_span(|span| {
+        let span_context = span.span_context();
+        if span_context.is_valid() {
+            Some(span_context.trace_id().to_hex())
+        } else {
+            None
+        }
+    });
+
+    trace_id.unwrap_or_else(|| format!("{:032x}", rand::random::<u128>()))
+}
+
 /// Add span attributes from servicerequest
 pub fn set_span_tags(req_path: &str, headers: &HttpHeaderMap, span: &mut dyn Span) {
     span.set_attribute(Key::new("path").string(req_path.to_string()));
@@ -121,3 +253,32 @@ pub fn set_span_tags(req_path: &str, headers: &HttpHeaderMap, span: &mut dyn Spa
         span.set_attribute(Key::new(format!("header.{}", k)).string(value))
     });
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sampling_settings() {
+        assert_eq!("parent_based".parse(), Ok(SamplerKind::ParentBased));
+        assert!("always_off".parse::<SamplerKind>().is_err());
+
+        let settings = SamplingSettings {
+            sampler: SamplerKind::Ratio,
+            ratio: 0.01,
+        };
+        settings.validate().unwrap();
+        assert!(matches!(
+            settings.sampler(),
+            Sampler::TraceIdRatioBased(ratio) if ratio == 0.01
+        ));
+
+        for ratio in &[-0.1, 1.5, f64::NAN] {
+            let settings = SamplingSettings {
+                ratio: *ratio,
+                ..Default::default()
+            };
+            assert!(settings.validate().is_err());
+        }
+    }
+}
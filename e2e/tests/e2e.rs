@@ -115,7 +115,7 @@ fn run_graph_query(
         .append_pair("channel", channel)
         .append_pair("arch", arch);
 
-    println!("Querying {}", graph_url.to_string());
+    println!("Querying {}", graph_url);
 
     runtime
         .block_on(
@@ -123,7 +123,7 @@ fn run_graph_query(
                 .danger_accept_invalid_certs(true)
                 .build()
                 .unwrap()
-                .get(&graph_url.to_string())
+                .get(graph_url.to_string())
                 .header(ACCEPT, HeaderValue::from_static(header))
                 .header(ORIGIN, ORIGIN_HEADER_VALUE.clone())
                 .send(),
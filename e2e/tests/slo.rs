@@ -38,7 +38,7 @@ fn get_query_result_string(query: &'static str) -> VectorResult {
         _ => panic!("expected vector"),
     };
     assert_ne!(vector_data.len(), 0, "the vector contains 0 elements");
-    return vector_data.last().unwrap().clone();
+    vector_data.last().unwrap().clone()
 }
 
 #[test_case("sum(kube_pod_container_status_restarts_total{container=~'(graph-builder|policy-engine)'}) or vector(0)" => "0"; "No GB/PE restarts")]
@@ -91,6 +91,6 @@ fn check_slo_parameter(query: &'static str, parameter: &'static str) -> String {
     };
     match param_value {
         Value::String(v) => v.to_string(),
-        _ => panic!("Expected {} to be a string", param_value.to_string()),
+        _ => panic!("Expected {} to be a string", param_value),
     }
 }
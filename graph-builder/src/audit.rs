@@ -0,0 +1,375 @@
+//! Audit log of the published graph transitions.
+//!
+//! Every published graph is recorded as a structured event, listing the
+//! releases and edges it added and removed, the conditional edges whose risks
+//! changed, and the scrape which produced it. Events are appended to a JSON
+//! lines file, if configured, which is never rewritten but rotated once it
+//! reaches a size limit, so that post-incident reviews can tell when an edge
+//! appeared or disappeared across restarts. The last recorded graph is kept
+//! next to the file, so that the first transition after a restart is taken
+//! from it rather than from an empty graph. The latest events are also kept in
+//! memory and served on the `/audit` admin endpoint.
+
+use crate::freeze;
+use cincinnati::diff::GraphDiff;
+use cincinnati::provenance::{self, Provenance};
+use cincinnati::Graph;
+use commons::openapi::QueryParam;
+use commons::prelude_errors::*;
+use commons::GraphError;
+use parking_lot::{Mutex, RwLock};
+use prometheus::Counter;
+use std::collections::VecDeque;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Default number of events kept in memory.
+pub static DEFAULT_MAX_ENTRIES: usize = 1000;
+
+/// Default size of the audit log file at which it is rotated, in bytes.
+pub static DEFAULT_MAX_FILE_BYTES: u64 = 64 * 1024 * 1024;
+
+/// Default number of events returned per request.
+pub static DEFAULT_LIMIT: usize = 100;
+
+lazy_static! {
+    pub(crate) static ref AUDIT_LOG_WRITE_FAILURES: Counter = Counter::new(
+        "audit_log_write_failures_total",
+        "Total number of audit events which could not be appended to the audit log file"
+    )
+    .unwrap();
+}
+
+/// A published graph transition.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct AuditEvent {
+    /// Hash of the event content, as `sha256:<hex>`, set when recorded.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub id: String,
+    /// Revision of the published graph, local to the graph-builder process.
+    pub revision: u64,
+    /// Publication time, in RFC 3339 format.
+    pub published: String,
+    /// Identifier of the scrape which produced the graph.
+    pub scrape_id: String,
+    /// Hash of the published graph, as `sha256:<hex>`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash: Option<String>,
+    /// Hash of the graph recorded before, set when recorded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub previous_hash: Option<String>,
+    /// Identifier of the upstream the graph was scraped from.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+    /// Releases, edges and risks added, removed and changed.
+    #[serde(flatten)]
+    pub diff: GraphDiff,
+}
+
+impl AuditEvent {
+    /// Describe the transition from `old` to `new`, published with `provenance`.
+    pub fn new(scrape_id: &str, provenance: &Provenance, old: &Graph, new: &Graph) -> Self {
+        Self {
+            id: String::new(),
+            revision: provenance.revision.unwrap_or_default(),
+            published: provenance
+                .generated
+                .clone()
+                .unwrap_or_else(|| chrono::Utc::now().to_rfc3339()),
+            scrape_id: scrape_id.to_string(),
+            hash: provenance.hash.clone(),
+            previous_hash: None,
+            source: provenance.source.clone(),
+            diff: GraphDiff::between(old, new),
+        }
+    }
+
+    /// Returns the hash of the event content, its `id` aside.
+    fn content_id(&self) -> Fallible<String> {
+        let content = serde_json::to_vec(&Self {
+            id: String::new(),
+            ..self.clone()
+        })?;
+        Ok(provenance::content_hash(&content))
+    }
+}
+
+/// Audit log file, along with its rotation limit.
+#[derive(Debug)]
+struct LogFile {
+    path: PathBuf,
+    file: File,
+    max_bytes: u64,
+}
+
+impl LogFile {
+    fn open(path: &Path, max_bytes: u64) -> Fallible<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .context(format!("opening audit log {} for writing", path.display()))?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            file,
+            max_bytes,
+        })
+    }
+
+    /// Append a line durably, rotating the file first if it reached its limit,
+    /// so that the file always holds the latest event.
+    fn append(&mut self, line: &str) -> Fallible<()> {
+        if self.file.metadata()?.len() >= self.max_bytes {
+            let mut rotated = self.path.clone().into_os_string();
+            rotated.push(format!(".{}", chrono::Utc::now().timestamp()));
+            std::fs::rename(&self.path, &rotated)
+                .context(format!("rotating audit log {}", self.path.display()))?;
+            *self = Self::open(&self.path, self.max_bytes)?;
+        }
+
+        writeln!(self.file, "{}", line)?;
+        self.file.flush()?;
+        self.file.sync_data()?;
+        Ok(())
+    }
+
+    /// Path of the last recorded graph.
+    fn graph_path(&self) -> PathBuf {
+        graph_path(&self.path)
+    }
+}
+
+fn graph_path(path: &Path) -> PathBuf {
+    path.with_extension("graph")
+}
+
+/// Append-only log of the published graph transitions.
+#[derive(Clone, Debug)]
+pub struct AuditLog {
+    recent: Arc<RwLock<VecDeque<AuditEvent>>>,
+    last_hash: Arc<Mutex<Option<String>>>,
+    max_entries: usize,
+    file: Option<Arc<Mutex<LogFile>>>,
+}
+
+impl Default for AuditLog {
+    fn default() -> Self {
+        Self {
+            recent: Default::default(),
+            last_hash: Default::default(),
+            max_entries: DEFAULT_MAX_ENTRIES,
+            file: None,
+        }
+    }
+}
+
+impl AuditLog {
+    /// Open the audit log, keeping the latest `max_entries` events in memory.
+    ///
+    /// Events are appended to the file at `path`, if any, whose latest events
+    /// are loaded so that they remain available after a restart. The file is
+    /// rotated once it reaches `max_file_bytes`, which bounds the loading.
+    pub fn open(path: Option<&Path>, max_entries: usize, max_file_bytes: u64) -> Fallible<Self> {
+        let mut recent: VecDeque<AuditEvent> = VecDeque::new();
+        let file = match path {
+            Some(path) => {
+                if path.exists() {
+                    let existing = File::open(path)
+                        .context(format!("opening audit log {}", path.display()))?;
+                    for (index, line) in BufReader::new(existing).lines().enumerate() {
+                        let line = line.context(format!("reading audit log {}", path.display()))?;
+                        if line.trim().is_empty() {
+                            continue;
+                        }
+                        match serde_json::from_str(&line) {
+                            Ok(event) => recent.push_back(event),
+                            Err(e) => warn!(
+                                "skipping invalid event on line {} of audit log {}: {}",
+                                index + 1,
+                                path.display(),
+                                e
+                            ),
+                        }
+                        if recent.len() > max_entries {
+                            recent.pop_front();
+                        }
+                    }
+                }
+                Some(Arc::new(Mutex::new(LogFile::open(path, max_file_bytes)?)))
+            }
+            None => None,
+        };
+        let last_hash = recent.back().and_then(|event| event.hash.clone());
+
+        Ok(Self {
+            recent: Arc::new(RwLock::new(recent)),
+            last_hash: Arc::new(Mutex::new(last_hash)),
+            max_entries,
+            file,
+        })
+    }
+
+    /// Returns the last graph recorded before a restart, if any.
+    pub fn last_graph(&self) -> Option<Graph> {
+        let path = self.file.as_ref()?.lock().graph_path();
+        freeze::read_state(&path).unwrap_or_else(|e| {
+            error!("failed to read the last audited graph: {:#}", e);
+            None
+        })
+    }
+
+    /// Record a published graph transition to `graph`.
+    ///
+    /// Transitions to the graph recorded last, as after a restart, are
+    /// skipped. Failures to append the event to the file are logged, as they
+    /// must not prevent the publication.
+    pub fn record(&self, mut event: AuditEvent, graph: &Graph) {
+        {
+            let mut last_hash = self.last_hash.lock();
+            if event.hash.is_some() && event.hash == *last_hash {
+                return;
+            }
+            event.previous_hash = std::mem::replace(&mut *last_hash, event.hash.clone());
+        }
+
+        let appended = event.content_id().and_then(|id| {
+            event.id = id;
+            let file = match &self.file {
+                Some(file) => file,
+                None => return Ok(()),
+            };
+            let line = serde_json::to_string(&event)?;
+            let mut file = file.lock();
+            file.append(&line)?;
+            freeze::write_state(&file.graph_path(), event.revision, graph)
+        });
+        if let Err(e) = appended {
+            AUDIT_LOG_WRITE_FAILURES.inc();
+            error!(
+                "failed to append revision {} to the audit log: {:#}",
+                event.revision, e
+            );
+        }
+
+        let mut recent = self.recent.write();
+        recent.push_back(event);
+        while recent.len() > self.max_entries {
+            recent.pop_front();
+        }
+    }
+
+    /// Returns up to `limit` of the latest events, newest first.
+    pub fn recent(&self, limit: usize) -> Vec<AuditEvent> {
+        self.recent
+            .read()
+            .iter()
+            .rev()
+            .take(limit)
+            .cloned()
+            .collect()
+    }
+}
+
+/// Query parameter bounding the number of transitions returned.
+pub static LIMIT_PARAM: QueryParam =
+    QueryParam::new("limit", "Maximum number of transitions to return");
+
+/// Parse the [`LIMIT_PARAM`] of the audit endpoint.
+pub fn parse_limit(query: &str) -> Result<usize, GraphError> {
+    match LIMIT_PARAM.get(query) {
+        Some(limit) => match limit.parse::<usize>() {
+            Ok(limit) if limit > 0 => Ok(limit),
+            _ => Err(GraphError::InvalidParams(format!(
+                "{} must be a positive integer, got '{}'",
+                LIMIT_PARAM.name, limit
+            ))),
+        },
+        None => Ok(DEFAULT_LIMIT),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cincinnati::testing::generate_graph;
+
+    fn event(revision: u64) -> AuditEvent {
+        AuditEvent {
+            id: String::new(),
+            revision,
+            published: "2021-06-01T00:00:00+00:00".to_string(),
+            scrape_id: format!("1622505600-{}", revision),
+            hash: Some(format!("sha256:{}", revision)),
+            previous_hash: None,
+            source: None,
+            diff: Default::default(),
+        }
+    }
+
+    fn revisions(events: Vec<AuditEvent>) -> Vec<u64> {
+        events.iter().map(|event| event.revision).collect()
+    }
+
+    #[test]
+    fn record_and_reload() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("audit.jsonl");
+        let graph = generate_graph(true, false);
+
+        let log = AuditLog::open(Some(&path), 2, DEFAULT_MAX_FILE_BYTES).unwrap();
+        assert_eq!(log.last_graph(), None);
+        for revision in 1..=3 {
+            log.record(event(revision), &graph);
+        }
+        assert_eq!(revisions(log.recent(10)), vec![3, 2]);
+        assert_eq!(revisions(log.recent(1)), vec![3]);
+        assert_eq!(std::fs::read_to_string(&path).unwrap().lines().count(), 3);
+
+        let latest = &log.recent(1)[0];
+        assert_eq!(latest.previous_hash.as_deref(), Some("sha256:2"));
+        assert_eq!(latest.id, latest.content_id().unwrap());
+        assert_ne!(latest.id, log.recent(2)[1].id);
+
+        let reopened = AuditLog::open(Some(&path), 2, DEFAULT_MAX_FILE_BYTES).unwrap();
+        assert_eq!(reopened.recent(10), log.recent(10));
+        assert_eq!(reopened.last_graph(), Some(graph.clone()));
+
+        // The graph published again after the restart is no transition.
+        reopened.record(event(3), &graph);
+        assert_eq!(std::fs::read_to_string(&path).unwrap().lines().count(), 3);
+        reopened.record(event(4), &graph);
+        assert_eq!(std::fs::read_to_string(&path).unwrap().lines().count(), 4);
+    }
+
+    #[test]
+    fn rotation() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("audit.jsonl");
+        let graph = generate_graph(true, false);
+
+        let log = AuditLog::open(Some(&path), 10, 1).unwrap();
+        log.record(event(1), &graph);
+        log.record(event(2), &graph);
+
+        let files = std::fs::read_dir(dir.path())
+            .unwrap()
+            .filter(|entry| {
+                let name = entry.as_ref().unwrap().file_name();
+                name.to_string_lossy().starts_with("audit.jsonl.")
+            })
+            .count();
+        assert_eq!(files, 1);
+        let reopened = AuditLog::open(Some(&path), 10, 1).unwrap();
+        assert_eq!(revisions(reopened.recent(10)), vec![2]);
+    }
+
+    #[test]
+    fn parse_limits() {
+        assert_eq!(parse_limit("").unwrap(), DEFAULT_LIMIT);
+        assert_eq!(parse_limit("limit=5").unwrap(), 5);
+        assert!(parse_limit("limit=0").is_err());
+        assert!(parse_limit("limit=x").is_err());
+    }
+}
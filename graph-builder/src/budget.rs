@@ -0,0 +1,248 @@
+//! Resource budget of the process.
+//!
+//! The resident memory and CPU time of the process, the size of the published
+//! graph representations and the size of the in-memory caches are exported as
+//! gauges, and logged as a budget report on startup and after each scrape.
+//! When a soft limit is exceeded, the parsed-version cache is cleared. The
+//! changelog isn't a cache, mirrors rely on its revisions to catch up, so it
+//! is reported as over the limit rather than truncated.
+
+use crate::config::AppSettings;
+use crate::graph::State;
+use commons::Fallible;
+use prometheus::{Gauge, IntCounter, IntGauge, IntGaugeVec, Opts};
+
+lazy_static! {
+    static ref RESIDENT_MEMORY: IntGauge = IntGauge::new(
+        "budget_resident_memory_bytes",
+        "Resident memory of the process, in bytes"
+    )
+    .unwrap();
+    static ref CPU_SECONDS: Gauge = Gauge::new(
+        "budget_cpu_seconds",
+        "CPU time consumed by the process, in seconds"
+    )
+    .unwrap();
+    static ref GRAPH_SIZE: IntGaugeVec = IntGaugeVec::new(
+        Opts::new(
+            "budget_graph_bytes",
+            "Size of each representation of the published graph, in bytes"
+        ),
+        &["representation", "tenant"]
+    )
+    .unwrap();
+    static ref CACHE_ENTRIES: IntGaugeVec = IntGaugeVec::new(
+        Opts::new(
+            "budget_cache_entries",
+            "Number of entries of each in-memory cache"
+        ),
+        &["cache", "tenant"]
+    )
+    .unwrap();
+    static ref CACHE_EVICTIONS: IntCounter = IntCounter::new(
+        "budget_cache_evictions_total",
+        "Total number of cache evictions triggered by a soft limit"
+    )
+    .unwrap();
+}
+
+/// Register relevant metrics to a prometheus registry.
+pub fn register_metrics(registry: &prometheus::Registry) -> Fallible<()> {
+    registry.register(Box::new(RESIDENT_MEMORY.clone()))?;
+    registry.register(Box::new(CPU_SECONDS.clone()))?;
+    registry.register(Box::new(GRAPH_SIZE.clone()))?;
+    registry.register(Box::new(CACHE_ENTRIES.clone()))?;
+    registry.register(Box::new(CACHE_EVICTIONS.clone()))?;
+    Ok(())
+}
+
+/// Resource usage at a point in time.
+#[derive(Debug, Default, PartialEq)]
+pub struct Usage {
+    /// Resident memory in bytes, if available on this platform.
+    pub resident_memory_bytes: Option<u64>,
+    /// CPU time in seconds, if available on this platform.
+    pub cpu_seconds: Option<f64>,
+    /// Size in bytes of each representation of the published graph.
+    pub graph_bytes: Vec<(&'static str, usize)>,
+    /// Number of entries of each in-memory cache.
+    pub cache_entries: Vec<(&'static str, usize)>,
+}
+
+impl Usage {
+    /// Collect the current usage of the process.
+    pub fn collect(state: &State) -> Self {
+        let (resident_memory_bytes, cpu_seconds) = process_usage();
+        Self {
+            resident_memory_bytes,
+            cpu_seconds,
+            graph_bytes: state.published_sizes(),
+            cache_entries: vec![
+                ("semver", cincinnati::semver_cache::len()),
+                ("changelog", state.changelog().len()),
+            ],
+        }
+    }
+
+    /// Export the usage as gauges, those of the graph labeled with its tenant.
+    fn record(&self, tenant: &str) {
+        if let Some(bytes) = self.resident_memory_bytes {
+            RESIDENT_MEMORY.set(bytes as i64);
+        }
+        if let Some(seconds) = self.cpu_seconds {
+            CPU_SECONDS.set(seconds);
+        }
+        for (representation, bytes) in &self.graph_bytes {
+            GRAPH_SIZE
+                .with_label_values(&[representation, tenant])
+                .set(*bytes as i64);
+        }
+        for (cache, entries) in &self.cache_entries {
+            CACHE_ENTRIES
+                .with_label_values(&[cache, tenant])
+                .set(*entries as i64);
+        }
+    }
+
+    /// Returns the soft limits exceeded by this usage.
+    fn exceeded_limits(&self, settings: &AppSettings) -> Vec<String> {
+        let mut exceeded = vec![];
+
+        if let (Some(limit), Some(bytes)) = (
+            settings.budget_memory_soft_limit_bytes,
+            self.resident_memory_bytes,
+        ) {
+            if bytes > limit {
+                exceeded.push(format!(
+                    "resident memory {} bytes above {} bytes",
+                    bytes, limit
+                ));
+            }
+        }
+
+        if let Some(limit) = settings.budget_cache_soft_limit_entries {
+            let entries: usize = self.cache_entries.iter().map(|(_, entries)| entries).sum();
+            if entries > limit {
+                exceeded.push(format!("{} cache entries above {}", entries, limit));
+            }
+        }
+
+        exceeded
+    }
+
+    /// Format the usage as a single log line.
+    fn report(&self) -> String {
+        let optional = |value: Option<String>| value.unwrap_or_else(|| "n/a".to_string());
+        let list = |values: &[(&str, usize)]| {
+            values
+                .iter()
+                .map(|(name, value)| format!("{}={}", name, value))
+                .collect::<Vec<_>>()
+                .join(" ")
+        };
+
+        format!(
+            "resident_memory_bytes={} cpu_seconds={} graph_bytes[{}] cache_entries[{}]",
+            optional(self.resident_memory_bytes.map(|bytes| bytes.to_string())),
+            optional(self.cpu_seconds.map(|seconds| format!("{:.2}", seconds))),
+            list(&self.graph_bytes),
+            list(&self.cache_entries),
+        )
+    }
+}
+
+/// Record and log the budget report, evicting the caches if a soft limit is exceeded.
+pub fn check(state: &State, settings: &AppSettings) {
+    let usage = Usage::collect(state);
+    usage.record(state.tenant_label());
+    info!("budget report: {}", usage.report());
+
+    let exceeded = usage.exceeded_limits(settings);
+    if exceeded.is_empty() {
+        return;
+    }
+
+    warn!(
+        "soft limits exceeded, evicting caches: {}",
+        exceeded.join(", ")
+    );
+    cincinnati::semver_cache::clear();
+    CACHE_EVICTIONS.inc();
+    let usage = Usage::collect(state);
+    usage.record(state.tenant_label());
+
+    if !usage.exceeded_limits(settings).is_empty() {
+        warn!(
+            "soft limits still exceeded after eviction, the changelog keeps its {} revisions: \
+             lower service.changelog_max_revisions to bound it",
+            state.changelog().len()
+        );
+    }
+}
+
+/// Returns the resident memory in bytes and the CPU time in seconds of the process.
+#[cfg(target_os = "linux")]
+fn process_usage() -> (Option<u64>, Option<f64>) {
+    let process = match procfs::process::Process::myself() {
+        Ok(process) => process,
+        Err(e) => {
+            debug!("failed to read the process usage: {}", e);
+            return (None, None);
+        }
+    };
+
+    let resident_memory_bytes = process
+        .status()
+        .ok()
+        .and_then(|status| status.vmrss)
+        .map(|kilobytes| kilobytes * 1024);
+    let cpu_seconds = match (process.stat(), procfs::ticks_per_second()) {
+        (Ok(stat), Ok(ticks)) if ticks > 0 => Some((stat.utime + stat.stime) as f64 / ticks as f64),
+        _ => None,
+    };
+    (resident_memory_bytes, cpu_seconds)
+}
+
+/// Returns the resident memory in bytes and the CPU time in seconds of the process.
+#[cfg(not(target_os = "linux"))]
+fn process_usage() -> (Option<u64>, Option<f64>) {
+    (None, None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn process_usage_on_linux() {
+        let (resident_memory_bytes, cpu_seconds) = process_usage();
+        assert!(resident_memory_bytes.unwrap() > 0);
+        assert!(cpu_seconds.unwrap() >= 0.0);
+    }
+
+    #[test]
+    fn exceeded_soft_limits() {
+        let usage = Usage {
+            resident_memory_bytes: Some(2048),
+            cpu_seconds: None,
+            graph_bytes: vec![("json", 10)],
+            cache_entries: vec![("semver", 30), ("changelog", 20)],
+        };
+
+        let mut settings = AppSettings::default();
+        assert!(usage.exceeded_limits(&settings).is_empty());
+
+        settings.budget_memory_soft_limit_bytes = Some(1024);
+        settings.budget_cache_soft_limit_entries = Some(100);
+        assert_eq!(usage.exceeded_limits(&settings).len(), 1);
+
+        settings.budget_cache_soft_limit_entries = Some(40);
+        assert_eq!(usage.exceeded_limits(&settings).len(), 2);
+
+        assert_eq!(
+            usage.report(),
+            "resident_memory_bytes=2048 cpu_seconds=n/a graph_bytes[json=10] cache_entries[semver=30 changelog=20]"
+        );
+    }
+}
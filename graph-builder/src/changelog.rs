@@ -0,0 +1,294 @@
+//! History of the published graph revisions.
+//!
+//! Every revision of the published graph is recorded along with its
+//! publication time and its differences with the previous revision, so that
+//! downstream mirrors can catch up on the changes they missed. Only the latest
+//! revisions are kept, in memory. Revisions are numbered locally to a
+//! graph-builder process, so clients identify the graph they know by the hash
+//! of its content, which is the same on every replica and across restarts.
+//! Clients whose graph isn't covered anymore are told to fetch the full graph
+//! again.
+
+use cincinnati::diff::GraphDiff;
+use commons::openapi::QueryParam;
+use commons::GraphError;
+use parking_lot::RwLock;
+use std::collections::VecDeque;
+use std::sync::Arc;
+
+/// Default number of revisions kept.
+pub static DEFAULT_MAX_REVISIONS: usize = 100;
+
+/// Default number of revisions returned per request.
+pub static DEFAULT_LIMIT: usize = 100;
+
+/// Maximum number of revisions returned per request.
+pub static MAX_LIMIT: usize = 1000;
+
+/// A revision of the published graph.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct RevisionEntry {
+    /// Revision number, local to the graph-builder process.
+    pub revision: u64,
+    /// Hash of the graph content, as sent in the `ETag` of graph responses.
+    pub hash: String,
+    /// Publication time, in seconds since the epoch.
+    pub published: i64,
+    /// Changes compared to the previous revision.
+    pub diff: GraphDiff,
+}
+
+/// Revisions following the one a client knows about.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct ChangelogPage {
+    /// Latest recorded revision.
+    pub revision: u64,
+    /// Hash of the latest recorded graph, if any.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hash: Option<String>,
+    /// Whether `revisions` covers all changes since the requested graph.
+    /// If not, the full graph must be fetched again.
+    pub complete: bool,
+    /// Revisions after the requested graph, oldest first.
+    pub revisions: Vec<RevisionEntry>,
+    /// Hash to request next, if more revisions are available.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next: Option<String>,
+}
+
+#[derive(Debug, Default)]
+struct Entries {
+    latest: u64,
+    latest_hash: Option<String>,
+    entries: VecDeque<RevisionEntry>,
+    /// Hash of the graph preceding the oldest entry, if it was evicted.
+    evicted_hash: Option<String>,
+}
+
+impl Entries {
+    /// Drop the oldest entries, keeping at most `max_revisions` of them.
+    fn truncate(&mut self, max_revisions: usize) {
+        while self.entries.len() > max_revisions {
+            self.evicted_hash = self.entries.pop_front().map(|entry| entry.hash);
+        }
+    }
+}
+
+/// Bounded history of the published graph revisions.
+#[derive(Clone, Debug)]
+pub struct Changelog {
+    entries: Arc<RwLock<Entries>>,
+    max_revisions: usize,
+}
+
+impl Default for Changelog {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_REVISIONS)
+    }
+}
+
+impl Changelog {
+    /// Create a changelog keeping the latest `max_revisions` revisions.
+    pub fn new(max_revisions: usize) -> Self {
+        Self {
+            entries: Default::default(),
+            max_revisions,
+        }
+    }
+
+    /// Record a newly published revision, whose graph content hashes to `hash`.
+    pub fn record(&self, revision: u64, hash: String, diff: GraphDiff) {
+        let mut entries = self.entries.write();
+        entries.latest = revision;
+        entries.latest_hash = Some(hash.clone());
+        entries.entries.push_back(RevisionEntry {
+            revision,
+            hash,
+            published: chrono::Utc::now().timestamp(),
+            diff,
+        });
+        entries.truncate(self.max_revisions);
+    }
+
+    /// Returns the number of recorded revisions.
+    pub fn len(&self) -> usize {
+        self.entries.read().entries.len()
+    }
+
+    /// Returns whether no revision was recorded.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns up to `limit` revisions after the graph whose content hashes to
+    /// `since`, or after the first graph if unset.
+    ///
+    /// A graph published several times is known by its latest revision, the
+    /// changes since its former revisions amounting to the same.
+    pub fn since(&self, since: Option<&str>, limit: usize) -> ChangelogPage {
+        let entries = self.entries.read();
+
+        let start = match since {
+            Some(since) if entries.latest_hash.as_deref() == Some(since) => {
+                Some(entries.entries.len())
+            }
+            Some(since) => entries
+                .entries
+                .iter()
+                .rposition(|entry| entry.hash == since)
+                .map(|position| position + 1)
+                .or_else(|| (entries.evicted_hash.as_deref() == Some(since)).then_some(0)),
+            None => match entries.entries.front() {
+                Some(oldest) => (oldest.revision == 1).then_some(0),
+                None => (entries.latest == 0).then_some(0),
+            },
+        };
+        let start = match start {
+            Some(start) => start,
+            None => {
+                return ChangelogPage {
+                    revision: entries.latest,
+                    hash: entries.latest_hash.clone(),
+                    complete: false,
+                    revisions: vec![],
+                    next: None,
+                }
+            }
+        };
+
+        let mut pending = entries.entries.iter().skip(start).peekable();
+        let revisions: Vec<RevisionEntry> = pending.by_ref().take(limit).cloned().collect();
+        let next = match pending.peek() {
+            Some(_) => revisions.last().map(|entry| entry.hash.clone()),
+            None => None,
+        };
+
+        ChangelogPage {
+            revision: entries.latest,
+            hash: entries.latest_hash.clone(),
+            complete: true,
+            revisions,
+            next,
+        }
+    }
+}
+
+/// Query parameter of the last graph known to the client.
+pub static SINCE_PARAM: QueryParam = QueryParam::new(
+    "since",
+    "Hash of the last graph known to the client, as sent in the ETag",
+);
+
+/// Query parameter bounding the number of revisions returned.
+pub static LIMIT_PARAM: QueryParam =
+    QueryParam::new("limit", "Maximum number of revisions to return");
+
+/// Parse the [`SINCE_PARAM`] and [`LIMIT_PARAM`] of a changelog request.
+pub fn parse_params(query: &str) -> Result<(Option<String>, usize), GraphError> {
+    let since = SINCE_PARAM.get(query);
+    // The hash may be passed as sent in the `ETag`, quoted.
+    let since = match since.as_deref().map(|since| since.trim_matches('"')) {
+        Some(since) if since.starts_with("sha256:") => Some(since.to_string()),
+        Some(since) => {
+            return Err(GraphError::InvalidParams(format!(
+                "invalid graph hash '{}'",
+                since
+            )))
+        }
+        None => None,
+    };
+
+    let limit = match LIMIT_PARAM.get(query) {
+        Some(limit) => match limit.parse::<usize>() {
+            Ok(limit) if limit > 0 => std::cmp::min(limit, MAX_LIMIT),
+            _ => {
+                return Err(GraphError::InvalidParams(format!(
+                    "{} must be a positive integer, got '{}'",
+                    LIMIT_PARAM.name, limit
+                )))
+            }
+        },
+        None => DEFAULT_LIMIT,
+    };
+
+    Ok((since, limit))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn diff(release: &str) -> GraphDiff {
+        GraphDiff {
+            releases_added: vec![release.to_string()],
+            ..Default::default()
+        }
+    }
+
+    fn hash(revision: u64) -> String {
+        format!("sha256:{}", revision)
+    }
+
+    fn revisions(page: &ChangelogPage) -> Vec<u64> {
+        page.revisions.iter().map(|entry| entry.revision).collect()
+    }
+
+    #[test]
+    fn changelog_pages() {
+        let changelog = Changelog::new(3);
+        let page = changelog.since(None, 10);
+        assert!(page.complete);
+        assert!(page.revisions.is_empty());
+
+        for revision in 1..=4 {
+            changelog.record(revision, hash(revision), diff(&format!("4.{}.0", revision)));
+        }
+
+        // The first revision was evicted.
+        let page = changelog.since(None, 10);
+        assert!(!page.complete);
+        assert_eq!(page.revision, 4);
+        assert_eq!(page.hash, Some(hash(4)));
+        assert!(page.revisions.is_empty());
+
+        let page = changelog.since(Some(&hash(1)), 2);
+        assert!(page.complete);
+        assert_eq!(revisions(&page), vec![2, 3]);
+        assert_eq!(page.revisions[0].diff, diff("4.2.0"));
+        assert_eq!(page.next, Some(hash(3)));
+
+        let page = changelog.since(page.next.as_deref(), 2);
+        assert_eq!(revisions(&page), vec![4]);
+        assert_eq!(page.next, None);
+
+        let page = changelog.since(Some(&hash(4)), 2);
+        assert!(page.complete);
+        assert!(page.revisions.is_empty());
+
+        // The graph was never published by this process, e.g. by another replica.
+        assert!(!changelog.since(Some(&hash(7)), 2).complete);
+
+        // A graph published again is known by its latest revision.
+        changelog.record(5, hash(2), diff("4.2.0"));
+        let page = changelog.since(Some(&hash(2)), 2);
+        assert!(page.complete);
+        assert!(page.revisions.is_empty());
+    }
+
+    #[test]
+    fn changelog_params() {
+        assert_eq!(parse_params("").unwrap(), (None, DEFAULT_LIMIT));
+        assert_eq!(
+            parse_params("since=sha256:4c6f&limit=10").unwrap(),
+            (Some("sha256:4c6f".to_string()), 10)
+        );
+        assert_eq!(
+            parse_params("since=%22sha256:4c6f%22").unwrap(),
+            (Some("sha256:4c6f".to_string()), DEFAULT_LIMIT)
+        );
+        assert_eq!(parse_params("limit=100000").unwrap(), (None, MAX_LIMIT));
+        assert!(parse_params("since=5").is_err());
+        assert!(parse_params("limit=0").is_err());
+        assert!(parse_params("limit=-1").is_err());
+    }
+}
@@ -1,9 +1,12 @@
 //! Command-line options.
 
 use super::options;
+use super::settings::{Profile, UpstreamMethod};
 use super::AppSettings;
+use commons::logging::LogFormat;
 use commons::prelude_errors::*;
 use commons::MergeOptions;
+use std::path::PathBuf;
 
 /// CLI configuration flags, top-level.
 #[derive(Debug, StructOpt)]
@@ -12,22 +15,61 @@ pub struct CliOptions {
     #[structopt(short = "v", parse(from_occurrences))]
     pub verbosity: u8,
 
+    /// Log output format, 'text' or 'json'
+    #[structopt(long = "log_format")]
+    pub log_format: Option<LogFormat>,
+
+    /// Deployment profile, 'production' or 'staging'
+    #[structopt(long = "profile")]
+    pub profile: Option<Profile>,
+
     /// Path to configuration file
     #[structopt(short = "c")]
     pub config_path: Option<String>,
 
+    /// Directory of configuration fragments, merged over the configuration file in lexical order
+    #[structopt(long = "config-dir", parse(from_os_str))]
+    pub config_dir: Option<PathBuf>,
+
+    /// Build the graph once, write it out and exit, without starting the services
+    #[structopt(long = "once")]
+    pub once: bool,
+
+    /// Output of the graph built with --once, '-' for stdout (default)
+    #[structopt(long = "output", parse(from_os_str))]
+    pub output: Option<PathBuf>,
+
+    /// Lint the graph-data in a directory, or in a gzipped tarball at a URL, and exit
+    #[structopt(long = "validate-graph-data", value_name = "path-or-url")]
+    pub validate_graph_data: Option<String>,
+
     #[structopt(flatten)]
     pub service: options::ServiceOptions,
 
     #[structopt(flatten)]
     pub status: options::StatusOptions,
 
-    /// Fetcher method.
+    /// Fetcher method, 'registry' or 'file'
     #[structopt(long = "upstream.method")]
-    pub upstream_method: Option<String>,
+    pub upstream_method: Option<UpstreamMethod>,
 
     #[structopt(flatten)]
     pub upstream_registry: options::DockerRegistryOptions,
+
+    #[structopt(flatten)]
+    pub upstream_file: options::GraphFileOptions,
+
+    #[structopt(flatten)]
+    pub validation: options::ValidationOptions,
+
+    #[structopt(flatten)]
+    pub budget: options::BudgetOptions,
+
+    #[structopt(flatten)]
+    pub limits: options::LimitsOptions,
+
+    #[structopt(flatten)]
+    pub tls: options::TlsOptions,
 }
 
 impl MergeOptions<CliOptions> for AppSettings {
@@ -38,9 +80,20 @@ impl MergeOptions<CliOptions> for AppSettings {
             2 => log::LevelFilter::Debug,
             _ => log::LevelFilter::Trace,
         };
+        assign_if_some!(self.log_format, opts.log_format);
+        assign_if_some!(self.profile, opts.profile);
+        self.once |= opts.once;
+        assign_if_some!(self.once_output, opts.output);
+        assign_if_some!(self.validate_graph_data, opts.validate_graph_data);
         self.try_merge(Some(opts.service))?;
         self.try_merge(Some(opts.status))?;
+        assign_if_some!(self.upstream_method, opts.upstream_method);
         self.try_merge(Some(opts.upstream_registry))?;
+        self.try_merge(Some(opts.upstream_file))?;
+        self.try_merge(Some(opts.validation))?;
+        self.try_merge(Some(opts.budget))?;
+        self.try_merge(Some(opts.limits))?;
+        self.try_merge(Some(opts.tls))?;
 
         Ok(())
     }
@@ -67,6 +120,26 @@ mod tests {
         let svc_port_args = vec!["argv0", "--service.port", "9999"];
         let svc_port_cli = CliOptions::from_iter_safe(svc_port_args).unwrap();
         assert_eq!(svc_port_cli.service.port, Some(9999));
+
+        let log_format_args = vec!["argv0", "--log_format", "json"];
+        let log_format_cli = CliOptions::from_iter_safe(log_format_args).unwrap();
+        assert_eq!(
+            log_format_cli.log_format,
+            Some(commons::logging::LogFormat::Json)
+        );
+        assert!(CliOptions::from_iter_safe(vec!["argv0", "--log_format", "xml"]).is_err());
+
+        let once_args = vec!["argv0", "--once", "--output", "graph.json"];
+        let once_cli = CliOptions::from_iter_safe(once_args).unwrap();
+        assert!(once_cli.once);
+        assert_eq!(once_cli.output, Some("graph.json".into()));
+
+        let validate_args = vec!["argv0", "--validate-graph-data", "graph-data"];
+        let validate_cli = CliOptions::from_iter_safe(validate_args).unwrap();
+        assert_eq!(
+            validate_cli.validate_graph_data,
+            Some("graph-data".to_string())
+        );
     }
 
     #[test]
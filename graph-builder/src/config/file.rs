@@ -1,12 +1,17 @@
 //! TOML file configuration options.
 
 use super::options;
+use super::settings::{Profile, TenantSettings, UpstreamMethod};
 use super::AppSettings;
+use crate::{coordination, freeze, notify};
+use cincinnati::plugins::catalog::PluginSettings;
+use cincinnati::plugins::internal::synthetic_releases::SyntheticReleasesPlugin;
+use commons::cors::CorsSettings;
 use commons::de::de_loglevel;
+use commons::logging::LogFormat;
 use commons::prelude_errors::*;
 use commons::MergeOptions;
-use std::io::Read;
-use std::{fs, io, path};
+use std::path;
 
 /// TOML configuration, top-level.
 #[derive(Debug, Deserialize)]
@@ -15,6 +20,12 @@ pub struct FileOptions {
     #[serde(default = "Option::default", deserialize_with = "de_loglevel")]
     pub verbosity: Option<log::LevelFilter>,
 
+    /// Log output format.
+    pub log_format: Option<LogFormat>,
+
+    /// Deployment profile.
+    pub profile: Option<Profile>,
+
     /// Upstream options.
     pub upstream: Option<UpstreamOptions>,
 
@@ -24,8 +35,35 @@ pub struct FileOptions {
     /// Status service options.
     pub status: Option<options::StatusOptions>,
 
+    /// Graph validation options.
+    pub validation: Option<options::ValidationOptions>,
+
+    /// Resource budget options.
+    pub budget: Option<options::BudgetOptions>,
+
+    /// Protective limits of the published graph.
+    pub limits: Option<options::LimitsOptions>,
+
+    /// TLS termination options.
+    pub tls: Option<options::TlsOptions>,
+
+    /// Operational event notifications.
+    pub notifications: Option<NotificationOptions>,
+
+    /// Publication freeze windows.
+    pub freeze: Option<FreezeOptions>,
+
+    /// Leader election across replicas.
+    pub coordination: Option<coordination::CoordinationSettings>,
+
+    /// Cross-origin resource sharing.
+    pub cors: Option<CorsSettings>,
+
     /// Plugin settings.
     pub plugin_settings: Option<Vec<toml::Value>>,
+
+    /// Graphs of other products served by the same instance.
+    pub tenants: Option<Vec<TenantOptions>>,
 }
 
 impl FileOptions {
@@ -33,21 +71,17 @@ impl FileOptions {
     where
         P: AsRef<path::Path>,
     {
-        let cfg_file = fs::File::open(&cfg_path).context(format!(
-            "failed to open config path {:?}",
-            cfg_path.as_ref()
-        ))?;
-        let mut bufrd = io::BufReader::new(cfg_file);
-
-        let mut content = vec![];
-        bufrd.read_to_end(&mut content)?;
-        let cfg = toml::from_slice(&content).context(format!(
-            "failed to parse config file {}:\n{}",
-            cfg_path.as_ref().display(),
-            std::str::from_utf8(&content).unwrap_or("file not decodable")
-        ))?;
-
-        Ok(cfg)
+        Self::read_layered(Some(cfg_path.as_ref()), None)?
+            .ok_or_else(|| format_err!("no configuration read"))
+    }
+
+    /// Parse and merge a TOML configuration file and the fragments of a
+    /// configuration directory, if any, expanding environment variables.
+    pub fn read_layered(
+        path: Option<&path::Path>,
+        dir: Option<&path::Path>,
+    ) -> Fallible<Option<Self>> {
+        commons::config_files::read_layered(path, dir)
     }
 }
 
@@ -55,10 +89,21 @@ impl MergeOptions<Option<FileOptions>> for AppSettings {
     fn try_merge(&mut self, opts: Option<FileOptions>) -> Fallible<()> {
         if let Some(file) = opts {
             assign_if_some!(self.verbosity, file.verbosity);
+            assign_if_some!(self.log_format, file.log_format);
+            assign_if_some!(self.profile, file.profile);
             self.try_merge(file.upstream)?;
             self.try_merge(file.service)?;
             self.try_merge(file.status)?;
+            self.try_merge(file.validation)?;
+            self.try_merge(file.budget)?;
+            self.try_merge(file.limits)?;
+            self.try_merge(file.tls)?;
+            self.try_merge(file.notifications)?;
+            self.try_merge(file.freeze)?;
+            assign_if_some!(self.coordination, file.coordination);
+            assign_if_some!(self.cors, file.cors);
             self.try_merge(file.plugin_settings)?;
+            self.try_merge(file.tenants)?;
         }
         Ok(())
     }
@@ -67,9 +112,57 @@ impl MergeOptions<Option<FileOptions>> for AppSettings {
 impl MergeOptions<Option<Vec<toml::Value>>> for AppSettings {
     fn try_merge(&mut self, opts: Option<Vec<toml::Value>>) -> Fallible<()> {
         if let Some(policies) = opts {
-            for conf in policies {
-                let plugin = cincinnati::plugins::catalog::deserialize_config(conf)?;
-                self.plugin_settings.push(plugin);
+            let plugins = deserialize_plugins(self.profile, policies)?;
+            self.plugin_settings.extend(plugins);
+        }
+        Ok(())
+    }
+}
+
+/// Deserialize the settings of a plugin chain.
+fn deserialize_plugins(
+    profile: Profile,
+    policies: Vec<toml::Value>,
+) -> Fallible<Vec<Box<dyn PluginSettings>>> {
+    policies
+        .into_iter()
+        .map(|conf| {
+            let name = conf.get("name").and_then(toml::Value::as_str);
+            ensure!(
+                profile != Profile::Production
+                    || name != Some(SyntheticReleasesPlugin::PLUGIN_NAME),
+                "plugin '{}' is not allowed in the production profile",
+                SyntheticReleasesPlugin::PLUGIN_NAME
+            );
+            cincinnati::plugins::catalog::deserialize_config(conf)
+        })
+        .collect()
+}
+
+/// Graph of a product, served under its own path prefix.
+#[derive(Debug, Deserialize)]
+pub struct TenantOptions {
+    /// Name of the tenant, the last segment of its path prefix.
+    pub name: String,
+
+    /// Plugin settings, including the upstream of the tenant.
+    #[serde(default)]
+    pub plugin_settings: Vec<toml::Value>,
+}
+
+impl MergeOptions<Option<Vec<TenantOptions>>> for AppSettings {
+    fn try_merge(&mut self, opts: Option<Vec<TenantOptions>>) -> Fallible<()> {
+        if let Some(tenants) = opts {
+            for tenant in tenants {
+                let plugin_settings = deserialize_plugins(self.profile, tenant.plugin_settings)
+                    .context(format!(
+                        "invalid plugin settings of tenant '{}'",
+                        tenant.name
+                    ))?;
+                self.tenants.push(TenantSettings {
+                    name: tenant.name,
+                    plugin_settings,
+                });
             }
         }
         Ok(())
@@ -80,19 +173,24 @@ impl MergeOptions<Option<Vec<toml::Value>>> for AppSettings {
 #[derive(Debug, Deserialize)]
 pub struct UpstreamOptions {
     /// Fetcher method.
-    pub method: Option<String>,
+    pub method: Option<UpstreamMethod>,
 
     /// DEPRECATED: Pause between upstream scrapes.
     pub pause_secs: Option<u64>,
 
     /// Docker-registry-v2 upstream options.
     pub registry: Option<options::DockerRegistryOptions>,
+
+    /// Pre-built graph file upstream options.
+    pub file: Option<options::GraphFileOptions>,
 }
 
 impl MergeOptions<Option<UpstreamOptions>> for AppSettings {
     fn try_merge(&mut self, opts: Option<UpstreamOptions>) -> Fallible<()> {
         if let Some(upstream) = opts {
+            assign_if_some!(self.upstream_method, upstream.method);
             self.try_merge(upstream.registry)?;
+            self.try_merge(upstream.file)?;
             if upstream.pause_secs.is_some() {
                 warn!("the upstream option 'pause_secs' has been deprecated and has no effect. please use '--pause-secs' instead");
             };
@@ -101,9 +199,62 @@ impl MergeOptions<Option<UpstreamOptions>> for AppSettings {
     }
 }
 
+/// Options for operational event notifications.
+#[derive(Debug, Deserialize)]
+pub struct NotificationOptions {
+    /// Number of consecutive scrape failures which triggers a notification.
+    pub scrape_failure_threshold: Option<u64>,
+
+    /// Duration in seconds during which repeated events of the same type are not notified again.
+    pub debounce_secs: Option<u64>,
+
+    /// Notification sinks.
+    pub sinks: Option<Vec<notify::SinkConfig>>,
+}
+
+impl MergeOptions<Option<NotificationOptions>> for AppSettings {
+    fn try_merge(&mut self, opts: Option<NotificationOptions>) -> Fallible<()> {
+        if let Some(notifications) = opts {
+            assign_if_some!(
+                self.notification_scrape_failure_threshold,
+                notifications.scrape_failure_threshold
+            );
+            if let Some(secs) = notifications.debounce_secs {
+                self.notification_debounce = std::time::Duration::from_secs(secs);
+            }
+            if let Some(sinks) = notifications.sinks {
+                self.notification_sinks.extend(sinks);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Options for publication freeze windows.
+#[derive(Debug, Deserialize)]
+pub struct FreezeOptions {
+    /// Windows during which new graphs are not published.
+    pub windows: Option<Vec<freeze::FreezeWindow>>,
+
+    /// File keeping the published graph, to serve it again if restarted during a freeze.
+    pub state_path: Option<path::PathBuf>,
+}
+
+impl MergeOptions<Option<FreezeOptions>> for AppSettings {
+    fn try_merge(&mut self, opts: Option<FreezeOptions>) -> Fallible<()> {
+        if let Some(freeze) = opts {
+            if let Some(windows) = freeze.windows {
+                self.freeze_windows.extend(windows);
+            }
+            assign_if_some!(self.freeze_state_path, freeze.state_path);
+        }
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use super::FileOptions;
+    use super::{FileOptions, UpstreamMethod};
     use crate::config::AppSettings;
     use commons::MergeOptions;
 
@@ -131,6 +282,333 @@ mod tests {
         assert_eq!(settings.status_port, 2222);
     }
 
+    #[test]
+    fn toml_listen_addresses() {
+        let mut settings = AppSettings::default();
+
+        let toml_input = r#"
+            [service]
+            addresses = ["0.0.0.0", "::1"]
+
+            [status]
+            address = "::1"
+        "#;
+        let file_opts: FileOptions = toml::from_str(toml_input).unwrap();
+
+        settings.try_merge(Some(file_opts)).unwrap();
+        let service_addrs: Vec<String> = settings
+            .service_addrs()
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(service_addrs, vec!["0.0.0.0:8080", "[::1]:8080"]);
+        assert_eq!(settings.status_addrs()[0].to_string(), "[::1]:9080");
+    }
+
+    #[test]
+    fn toml_server_tuning() {
+        let mut settings = AppSettings::default();
+        assert_eq!(
+            settings.service_server.keep_alive,
+            Some(std::time::Duration::from_secs(10))
+        );
+
+        let toml_input = r#"
+            [service]
+            keep_alive = 0
+            client_timeout = 30
+            max_payload_bytes = 1048576
+            workers = 16
+            http2 = true
+
+            [status]
+            workers = 1
+        "#;
+        let file_opts: FileOptions = toml::from_str(toml_input).unwrap();
+
+        settings.try_merge(Some(file_opts)).unwrap();
+        assert_eq!(settings.service_server.keep_alive, None);
+        assert_eq!(
+            settings.service_server.client_request_timeout,
+            std::time::Duration::from_secs(30)
+        );
+        assert_eq!(settings.service_server.max_payload_bytes, 1_048_576);
+        assert_eq!(settings.service_server.workers, Some(16));
+        assert!(settings.service_server.http2);
+        assert_eq!(settings.status_server.workers, Some(1));
+        assert!(!settings.status_server.http2);
+    }
+
+    #[test]
+    fn toml_additional_sources() {
+        let mut settings = AppSettings::default();
+        assert!(settings.additional_sources.is_empty());
+
+        let toml_input = r#"
+            [[upstream.registry.additional_sources]]
+            registry = "mirror.local:5000"
+            repository = "ocp/release"
+            credentials_path = "/etc/mirror-auth.json"
+        "#;
+        let file_opts: FileOptions = toml::from_str(toml_input).unwrap();
+
+        settings.try_merge(Some(file_opts)).unwrap();
+        assert_eq!(settings.additional_sources.len(), 1);
+        assert_eq!(settings.additional_sources[0].registry, "mirror.local:5000");
+        assert_eq!(
+            settings.upstream(),
+            format!(
+                "registry:{}/{}, registry:mirror.local:5000/ocp/release",
+                settings.registry, settings.repository
+            )
+        );
+        assert!(settings.validate_and_build_plugins(None).is_ok());
+    }
+
+    #[test]
+    fn toml_metadata_sources() {
+        use cincinnati::plugins::internal::release_scrape_dockerv2::MetadataSource;
+
+        let mut settings = AppSettings::default();
+        assert!(settings.metadata_sources.is_empty());
+
+        let toml_input = r#"
+            [upstream.registry]
+            metadata_sources = ["config_labels", "layers"]
+            metadata_label = "com.example.release.metadata"
+
+            [[upstream.registry.additional_sources]]
+            registry = "quay.io"
+            repository = "ocp/release"
+            metadata_sources = ["quay_labels"]
+        "#;
+        let file_opts: FileOptions = toml::from_str(toml_input).unwrap();
+
+        settings.try_merge(Some(file_opts)).unwrap();
+        assert_eq!(
+            settings.metadata_sources,
+            vec![MetadataSource::ConfigLabels, MetadataSource::Layers]
+        );
+        assert_eq!(
+            settings.additional_sources[0].metadata_sources,
+            vec![MetadataSource::QuayLabels]
+        );
+        assert!(settings.validate_and_build_plugins(None).is_ok());
+    }
+
+    #[test]
+    fn toml_scrape_filter() {
+        let mut settings = AppSettings::default();
+        assert!(settings.tag_version_range.is_none());
+        assert!(settings.architectures.is_empty());
+
+        let toml_input = r#"
+            [upstream.registry]
+            tag_version_range = "4.16.*"
+            architectures = ["amd64"]
+        "#;
+        let file_opts: FileOptions = toml::from_str(toml_input).unwrap();
+
+        settings.try_merge(Some(file_opts)).unwrap();
+        assert_eq!(settings.tag_version_range.as_deref(), Some("4.16.*"));
+        assert_eq!(settings.architectures, vec!["amd64".to_string()]);
+        assert!(settings.validate_and_build_plugins(None).is_ok());
+
+        settings.tag_pattern = Some("(".to_string());
+        assert!(settings.validate_and_build_plugins(None).is_err());
+    }
+
+    #[test]
+    fn toml_mirrors() {
+        let mut settings = AppSettings::default();
+        assert!(settings.mirrors.is_empty());
+
+        let toml_input = r#"
+            [upstream.registry]
+            mirror_timeout_secs = 60
+
+            [[upstream.registry.mirrors]]
+            registry = "mirror.local:5000"
+            repository = "ocp/release"
+        "#;
+        let file_opts: FileOptions = toml::from_str(toml_input).unwrap();
+
+        settings.try_merge(Some(file_opts)).unwrap();
+        assert_eq!(settings.mirrors.len(), 1);
+        assert_eq!(
+            settings.mirror_timeout_secs,
+            Some(std::time::Duration::from_secs(60))
+        );
+        assert!(settings.validate_and_build_plugins(None).is_ok());
+    }
+
+    #[test]
+    fn toml_repository_pattern() {
+        let mut settings = AppSettings::default();
+        assert_eq!(settings.repository_pattern, None);
+
+        let toml_input = r#"
+            [upstream.registry]
+            repository_pattern = "ocp/release-4\\.[0-9]+"
+            discovery_max_repositories = 20
+        "#;
+        let file_opts: FileOptions = toml::from_str(toml_input).unwrap();
+
+        settings.try_merge(Some(file_opts)).unwrap();
+        assert_eq!(
+            settings.repository_pattern.as_deref(),
+            Some("ocp/release-4\\.[0-9]+")
+        );
+        assert_eq!(settings.discovery_max_repositories, 20);
+        assert!(settings.validate_and_build_plugins(None).is_ok());
+    }
+
+    #[test]
+    fn toml_retry_policy() {
+        let mut settings = AppSettings::default();
+        assert_eq!(settings.retry_policy.max_attempts, 4);
+
+        let toml_input = r#"
+            [upstream.registry.retry]
+            max_attempts = 6
+            base_delay_secs = 0.5
+            retry_on = ["throttled"]
+        "#;
+        let file_opts: FileOptions = toml::from_str(toml_input).unwrap();
+
+        settings.try_merge(Some(file_opts)).unwrap();
+        assert_eq!(settings.retry_policy.max_attempts, 6);
+        assert_eq!(settings.retry_policy.base_delay_secs, 0.5);
+        assert_eq!(settings.retry_policy.max_delay_secs, 30.0);
+        assert!(settings.validate_and_build_plugins(None).is_ok());
+
+        settings.retry_policy.jitter = 2.0;
+        assert!(settings.validate_and_build_plugins(None).is_err());
+    }
+
+    #[test]
+    fn toml_graph_file() {
+        let mut settings = AppSettings::default();
+        assert_eq!(settings.upstream_method, UpstreamMethod::Registry);
+
+        let tmpdir = tempfile::tempdir().unwrap();
+        let path = tmpdir.path().join("graph.json");
+        std::fs::write(&path, r#"{"nodes":[],"edges":[]}"#).unwrap();
+
+        let toml_input = format!(
+            r#"
+            [upstream]
+            method = "file"
+
+            [upstream.file]
+            path = {:?}
+            watch = false
+        "#,
+            &path
+        );
+        let file_opts: FileOptions = toml::from_str(&toml_input).unwrap();
+
+        settings.try_merge(Some(file_opts)).unwrap();
+        assert_eq!(settings.upstream_method, UpstreamMethod::File);
+        assert_eq!(settings.graph_file_path, Some(path));
+        assert!(!settings.graph_file_watch);
+        let plugins = settings.validate_and_build_plugins(None).unwrap();
+        assert_eq!(plugins.len(), 1);
+
+        let missing_path: FileOptions = toml::from_str(
+            r#"
+            [upstream]
+            method = "file"
+        "#,
+        )
+        .unwrap();
+        let mut settings = AppSettings::default();
+        settings.try_merge(Some(missing_path)).unwrap();
+        assert!(settings.validate_and_build_plugins(None).is_err());
+    }
+
+    #[test]
+    fn toml_notifications() {
+        let mut settings = AppSettings::default();
+        assert!(settings.notification_sinks.is_empty());
+
+        let toml_input = r#"
+            [notifications]
+            scrape_failure_threshold = 5
+            debounce_secs = 600
+
+            [[notifications.sinks]]
+            type = "webhook"
+            url = "https://events.example.com/cincinnati"
+            events = ["validation_rejection"]
+        "#;
+        let file_opts: FileOptions = toml::from_str(toml_input).unwrap();
+
+        settings.try_merge(Some(file_opts)).unwrap();
+        assert_eq!(settings.notification_scrape_failure_threshold, 5);
+        assert_eq!(
+            settings.notification_debounce,
+            std::time::Duration::from_secs(600)
+        );
+        assert_eq!(settings.notification_sinks.len(), 1);
+    }
+
+    #[test]
+    fn toml_freeze_windows() {
+        let mut settings = AppSettings::default();
+        assert!(settings.freeze_windows.is_empty());
+
+        let toml_input = r#"
+            [freeze]
+            state_path = "/var/lib/cincinnati/published-graph.cbor"
+
+            [[freeze.windows]]
+            reason = "year-end holidays"
+            start = "2022-12-20T00:00:00Z"
+            end = "2023-01-03T00:00:00Z"
+
+            [[freeze.windows]]
+            schedule = "0 18 * * 5"
+            duration_secs = 216000
+        "#;
+        let file_opts: FileOptions = toml::from_str(toml_input).unwrap();
+
+        settings.try_merge(Some(file_opts)).unwrap();
+        assert_eq!(settings.freeze_windows.len(), 2);
+        assert_eq!(
+            settings.freeze_state_path,
+            Some("/var/lib/cincinnati/published-graph.cbor".into())
+        );
+        assert_eq!(
+            settings.freeze_windows[1].schedule.as_deref(),
+            Some("0 18 * * 5")
+        );
+        assert!(settings
+            .freeze_windows
+            .iter()
+            .all(|window| window.validate().is_ok()));
+    }
+
+    #[test]
+    fn toml_outbound_identity() {
+        let mut settings = AppSettings::default();
+
+        let toml_input = r#"
+            [service]
+            deployment_name = "prod"
+            outbound_headers = { X-Cluster = "east-1" }
+        "#;
+        let file_opts: FileOptions = toml::from_str(toml_input).unwrap();
+
+        settings.try_merge(Some(file_opts)).unwrap();
+        let identity = settings.client_identity();
+        assert_eq!(identity.deployment.as_deref(), Some("prod"));
+        assert_eq!(identity.headers["X-Cluster"], "east-1");
+        assert!(identity
+            .user_agent()
+            .starts_with("cincinnati-graph-builder/"));
+    }
+
     #[test]
     fn toml_sample_config() {
         use tempfile;
@@ -172,4 +650,68 @@ mod tests {
         let repo = ups_registry.repository.unwrap();
         assert_eq!(repo, "openshift-release-dev/ocp-release");
     }
+
+    #[test]
+    fn toml_synthetic_releases_profile() {
+        let toml_input = r#"
+            [[plugin_settings]]
+            name = "synthetic-releases"
+
+            [[plugin_settings.releases]]
+            version = "4.10.99"
+        "#;
+
+        let mut settings = AppSettings::default();
+        let file_opts: FileOptions = toml::from_str(toml_input).unwrap();
+        settings.try_merge(Some(file_opts)).unwrap_err();
+
+        let mut settings = AppSettings::default();
+        let toml_input = format!("profile = \"staging\"\n{}", toml_input);
+        let file_opts: FileOptions = toml::from_str(&toml_input).unwrap();
+        settings.try_merge(Some(file_opts)).unwrap();
+        assert_eq!(settings.plugin_settings.len(), 1);
+    }
+
+    #[test]
+    fn toml_tenants() {
+        let toml_input = r#"
+            [[tenants]]
+            name = "okd"
+
+            [[tenants.plugin_settings]]
+            name = "release-scrape-dockerv2"
+            registry = "quay.io"
+            repository = "openshift/okd"
+
+            [[tenants]]
+            name = "staging"
+
+            [[tenants.plugin_settings]]
+            name = "synthetic-releases"
+
+            [[tenants.plugin_settings.releases]]
+            version = "4.10.99"
+        "#;
+
+        let mut settings = AppSettings::default();
+        let file_opts: FileOptions = toml::from_str(toml_input).unwrap();
+        let err = settings.try_merge(Some(file_opts)).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "invalid plugin settings of tenant 'staging'"
+        );
+
+        let mut settings = AppSettings::default();
+        let toml_input = format!("profile = \"staging\"\n{}", toml_input);
+        let file_opts: FileOptions = toml::from_str(&toml_input).unwrap();
+        settings.try_merge(Some(file_opts)).unwrap();
+        assert!(settings.plugin_settings.is_empty());
+        let names: Vec<_> = settings.tenants.iter().map(|t| t.name.as_str()).collect();
+        assert_eq!(names, vec!["okd", "staging"]);
+
+        let okd = settings.into_tenant("okd").unwrap();
+        assert_eq!(okd.plugin_settings.len(), 1);
+        assert!(okd.tenants.is_empty());
+        assert!(okd.into_tenant("staging").is_err());
+    }
 }
@@ -1,9 +1,14 @@
 //! Options shared by CLI and TOML.
 
 use super::AppSettings;
+use cincinnati::plugins::internal::release_scrape_dockerv2::{
+    MetadataLimitAction, MetadataSource, RetryPolicy, ScrapeSource,
+};
+use commons::outbound::ProxyUrl;
 use commons::prelude_errors::*;
+use commons::tracing::SamplerKind;
 use commons::{de_path_prefix, parse_params_set, parse_path_prefix, MergeOptions};
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashSet};
 use std::net::IpAddr;
 use std::path::PathBuf;
 use std::time::Duration;
@@ -18,9 +23,90 @@ pub struct StatusOptions {
     #[structopt(name = "status_address", long = "status.address")]
     pub address: Option<IpAddr>,
 
+    /// Comma-separated addresses on which the status service will listen, overriding the address
+    #[structopt(
+        name = "status_addresses",
+        long = "status.addresses",
+        use_delimiter = true,
+        require_delimiter = true
+    )]
+    pub addresses: Option<Vec<IpAddr>>,
+
     /// Port to which the status service will bind
     #[structopt(name = "status_port", long = "status.port")]
     pub port: Option<u16>,
+
+    /// Unix socket on which the status service will listen, instead of TCP
+    #[structopt(name = "status_socket_path", long = "status.socket_path")]
+    pub socket_path: Option<PathBuf>,
+
+    /// OTLP/HTTP endpoint to push metrics to, in addition to serving them
+    #[structopt(long = "status.metrics_otlp_endpoint")]
+    pub metrics_otlp_endpoint: Option<String>,
+
+    /// Interval between OTLP metrics exports, in seconds
+    #[structopt(long = "status.metrics_otlp_interval_secs")]
+    pub metrics_otlp_interval_secs: Option<u64>,
+
+    /// File containing the bearer token for the admin endpoints of the status service
+    #[structopt(long = "status.admin_token_path")]
+    pub admin_token_path: Option<PathBuf>,
+
+    /// File containing the bearer token required on the status endpoints
+    #[structopt(long = "status.auth_token_path")]
+    pub auth_token_path: Option<PathBuf>,
+
+    /// File of "user:password" lines accepted with basic authentication on the status endpoints
+    #[structopt(long = "status.auth_credentials_path")]
+    pub auth_credentials_path: Option<PathBuf>,
+
+    /// File to which the published graph transitions are appended
+    #[structopt(long = "status.audit_log_path")]
+    pub audit_log_path: Option<PathBuf>,
+
+    /// Number of graph transitions kept in memory for the audit endpoint
+    #[structopt(long = "status.audit_max_entries")]
+    pub audit_max_entries: Option<usize>,
+
+    /// Size of the audit log file at which it is rotated, in bytes
+    #[structopt(long = "status.audit_max_file_bytes")]
+    pub audit_max_file_bytes: Option<u64>,
+
+    /// Query parameters of the graph requests of the self-test, e.g. "channel=stable-4.11"
+    #[structopt(long = "status.selftest_query")]
+    pub selftest_query: Option<String>,
+
+    /// Host name the self-test reaches the main service by, e.g. one its certificate is valid for
+    #[structopt(long = "status.selftest_server_name")]
+    pub selftest_server_name: Option<String>,
+
+    /// PEM file with additional CA certificates the self-test trusts
+    #[structopt(long = "status.selftest_ca_path")]
+    pub selftest_ca_path: Option<PathBuf>,
+
+    /// Age of the last successful graph refresh (in seconds) after which the service isn't ready
+    #[structopt(long = "status.max_graph_staleness_secs")]
+    pub max_graph_staleness_secs: Option<u64>,
+
+    /// Keep-alive of idle connections of the status service, in seconds, 0 to disable
+    #[structopt(name = "status_keep_alive", long = "status.keep_alive")]
+    pub keep_alive: Option<u64>,
+
+    /// Time a client of the status service has to send its first request, in seconds
+    #[structopt(name = "status_client_timeout", long = "status.client_timeout")]
+    pub client_timeout: Option<u64>,
+
+    /// Maximum size of request bodies on the status service, in bytes
+    #[structopt(name = "status_max_payload_bytes", long = "status.max_payload_bytes")]
+    pub max_payload_bytes: Option<usize>,
+
+    /// Number of worker threads of the status service, one per physical CPU if unset
+    #[structopt(name = "status_workers", long = "status.workers")]
+    pub workers: Option<usize>,
+
+    /// Accept HTTP/2 without TLS on the status service, in addition to HTTP/1.1
+    #[structopt(name = "status_http2", long = "status.http2")]
+    pub http2: Option<bool>,
 }
 
 /// Options for the main Cincinnati service.
@@ -42,14 +128,43 @@ pub struct ServiceOptions {
     #[serde(default = "Option::default", deserialize_with = "de_duration_secs")]
     pub scrape_timeout_secs: Option<Duration>,
 
+    /// Grace period (in seconds) for in-flight requests and the ongoing scrape on shutdown
+    #[structopt(
+        long = "service.shutdown_grace_secs",
+        parse(try_from_str = duration_from_secs)
+    )]
+    #[serde(default = "Option::default", deserialize_with = "de_duration_secs")]
+    pub shutdown_grace_secs: Option<Duration>,
+
+    /// Delay (in seconds) between stopping to report readiness and stopping the main service on shutdown
+    #[structopt(
+        long = "service.shutdown_drain_secs",
+        parse(try_from_str = duration_from_secs)
+    )]
+    #[serde(default = "Option::default", deserialize_with = "de_duration_secs")]
+    pub shutdown_drain_secs: Option<Duration>,
+
     /// Address on which the server will listen
     #[structopt(name = "service_address", long = "service.address", alias = "address")]
     pub address: Option<IpAddr>,
 
+    /// Comma-separated addresses on which the server will listen, overriding the address
+    #[structopt(
+        name = "service_addresses",
+        long = "service.addresses",
+        use_delimiter = true,
+        require_delimiter = true
+    )]
+    pub addresses: Option<Vec<IpAddr>>,
+
     /// Port to which the server will bind
     #[structopt(name = "service_port", long = "service.port", alias = "port")]
     pub port: Option<u16>,
 
+    /// Unix socket on which the server will listen, instead of TCP
+    #[structopt(name = "service_socket_path", long = "service.socket_path")]
+    pub socket_path: Option<PathBuf>,
+
     /// Namespace prefix for all service endpoints (e.g. '/<prefix>/graph')
     #[structopt(long = "service.path_prefix", parse(from_str = parse_path_prefix))]
     #[serde(default = "Option::default", deserialize_with = "de_path_prefix")]
@@ -65,6 +180,106 @@ pub struct ServiceOptions {
     /// Optional tracing endpoint
     #[structopt(name = "tracing_endpoint", long = "service.tracing_endpoint")]
     pub tracing_endpoint: Option<String>,
+
+    /// Sampling strategy of the traces, 'always_on', 'ratio' or 'parent_based'
+    #[structopt(long = "service.tracing_sampler")]
+    pub tracing_sampler: Option<SamplerKind>,
+
+    /// Ratio of sampled traces, between 0 and 1, for the ratio based samplers
+    #[structopt(long = "service.tracing_sampling_ratio")]
+    pub tracing_sampling_ratio: Option<f64>,
+
+    /// Whether to run preflight checks on startup
+    #[structopt(long = "service.preflight_checks")]
+    pub preflight_checks: Option<bool>,
+
+    /// Sustained requests per second allowed per client, unlimited if unset
+    #[structopt(long = "service.rate_limit_per_second")]
+    pub rate_limit_per_second: Option<f64>,
+
+    /// Number of requests a client may send at once
+    #[structopt(long = "service.rate_limit_burst")]
+    pub rate_limit_burst: Option<u32>,
+
+    /// Query parameter identifying clusters, which are rate limited on their own
+    #[structopt(long = "service.rate_limit_cluster_id_param")]
+    pub rate_limit_cluster_id_param: Option<String>,
+
+    /// Identify clients by the forwarding headers set by a trusted proxy
+    #[structopt(long = "service.rate_limit_trust_forwarded_for")]
+    pub rate_limit_trust_forwarded_for: Option<bool>,
+
+    /// Name of this deployment, sent in the User-Agent of outbound requests
+    #[structopt(long = "service.deployment_name")]
+    pub deployment_name: Option<String>,
+
+    /// Further headers to send along with outbound requests
+    #[structopt(skip)]
+    pub outbound_headers: Option<BTreeMap<String, String>>,
+
+    /// Proxy of outbound plain HTTP requests, overriding the http_proxy environment variable
+    #[structopt(long = "service.http_proxy")]
+    pub http_proxy: Option<String>,
+
+    /// Proxy of outbound HTTPS requests, overriding the https_proxy environment variable
+    #[structopt(long = "service.https_proxy")]
+    pub https_proxy: Option<String>,
+
+    /// Comma-separated hosts and domains reached without proxy, overriding the no_proxy environment variable
+    #[structopt(long = "service.no_proxy")]
+    pub no_proxy: Option<String>,
+
+    /// File containing the 'username:password' credentials for the proxies
+    #[structopt(long = "service.proxy_credentials_path")]
+    pub proxy_credentials_path: Option<PathBuf>,
+
+    /// Number of graph revisions kept for the changelog endpoint
+    #[structopt(long = "service.changelog_max_revisions")]
+    pub changelog_max_revisions: Option<usize>,
+
+    /// Number of recently published graphs kept as snapshots, 0 to disable
+    #[structopt(long = "service.snapshots_max")]
+    pub snapshots_max: Option<usize>,
+
+    /// Lifetime (in seconds) of graph responses in HTTP caches, 0 to have them revalidated
+    #[structopt(
+        long = "service.cache_max_age_secs",
+        parse(try_from_str = duration_from_secs)
+    )]
+    #[serde(default = "Option::default", deserialize_with = "de_duration_secs")]
+    pub cache_max_age_secs: Option<Duration>,
+
+    /// Whether to serve the binary graph artifact to internal consumers, requires client certificates
+    #[structopt(long = "service.internal_graph_api")]
+    pub internal_graph_api: Option<bool>,
+
+    /// zstd compression level of responses, between 1 and 22
+    #[structopt(long = "service.compression_zstd_level")]
+    pub compression_zstd_level: Option<i32>,
+
+    /// brotli compression level of responses, between 0 and 11
+    #[structopt(long = "service.compression_brotli_level")]
+    pub compression_brotli_level: Option<u32>,
+
+    /// Keep-alive of idle connections of the main service, in seconds, 0 to disable
+    #[structopt(name = "service_keep_alive", long = "service.keep_alive")]
+    pub keep_alive: Option<u64>,
+
+    /// Time a client of the main service has to send its first request, in seconds
+    #[structopt(name = "service_client_timeout", long = "service.client_timeout")]
+    pub client_timeout: Option<u64>,
+
+    /// Maximum size of request bodies on the main service, in bytes
+    #[structopt(name = "service_max_payload_bytes", long = "service.max_payload_bytes")]
+    pub max_payload_bytes: Option<usize>,
+
+    /// Number of worker threads of the main service, one per physical CPU if unset
+    #[structopt(name = "service_workers", long = "service.workers")]
+    pub workers: Option<usize>,
+
+    /// Accept HTTP/2 without TLS on the main service, in addition to HTTP/1.1
+    #[structopt(name = "service_http2", long = "service.http2")]
+    pub http2: Option<bool>,
 }
 
 /// Options for the Docker-registry-v2 fetcher.
@@ -85,13 +300,181 @@ pub struct DockerRegistryOptions {
     )]
     pub credentials_path: Option<PathBuf>,
 
+    /// File with PEM-encoded CA certificates to trust for the image registry
+    #[structopt(long = "upstream.registry.ca_bundle_path")]
+    pub ca_bundle_path: Option<PathBuf>,
+
+    /// File with the PEM-encoded client certificate presented to the image registry
+    #[structopt(long = "upstream.registry.client_cert_path")]
+    pub client_cert_path: Option<PathBuf>,
+
+    /// File with the PKCS#8 PEM-encoded key of the client certificate
+    #[structopt(long = "upstream.registry.client_key_path")]
+    pub client_key_path: Option<PathBuf>,
+
     /// Metadata key where to record the manifest-reference
     #[structopt(long = "upstream.registry.manifestref_key")]
     pub manifestref_key: Option<String>,
 
-    /// Concurrency for graph fetching
+    /// Maximum number of tags whose manifests and metadata are fetched at once
     #[structopt(long = "upstream.registry.fetch_concurrency")]
     pub fetch_concurrency: Option<usize>,
+
+    /// Directory for the persistent release metadata cache
+    #[structopt(long = "upstream.registry.cache_path")]
+    pub cache_path: Option<PathBuf>,
+
+    /// Maximum number of entries in the persistent release metadata cache
+    #[structopt(long = "upstream.registry.cache_max_entries")]
+    pub cache_max_entries: Option<usize>,
+
+    /// Further repositories to scrape, merged into the same graph
+    #[structopt(skip)]
+    pub additional_sources: Option<Vec<ScrapeSource>>,
+
+    /// Mirrors of the repository, tried in order if it can't be scraped
+    #[structopt(skip)]
+    pub mirrors: Option<Vec<ScrapeSource>>,
+
+    /// Timeout for scraping the repository or one of its mirrors, in seconds
+    #[structopt(long = "upstream.registry.mirror_timeout_secs")]
+    pub mirror_timeout_secs: Option<u64>,
+
+    /// Maximum number of metadata entries per release
+    #[structopt(long = "upstream.registry.metadata_max_keys")]
+    pub metadata_max_keys: Option<usize>,
+
+    /// Maximum length of a release metadata key, in bytes
+    #[structopt(long = "upstream.registry.metadata_max_key_length")]
+    pub metadata_max_key_length: Option<usize>,
+
+    /// Maximum size of a release metadata value, in bytes
+    #[structopt(long = "upstream.registry.metadata_max_value_size")]
+    pub metadata_max_value_size: Option<usize>,
+
+    /// Handling of releases whose metadata exceeds the limits
+    #[structopt(skip)]
+    pub metadata_limit_action: Option<MetadataLimitAction>,
+
+    /// Where to find the release metadata
+    #[structopt(skip)]
+    pub metadata_source: Option<MetadataSource>,
+
+    /// Sources of the release metadata, tried in order
+    #[structopt(skip)]
+    pub metadata_sources: Option<Vec<MetadataSource>>,
+
+    /// Artifact type of the release metadata artifacts
+    #[structopt(long = "upstream.registry.metadata_artifact_type")]
+    pub metadata_artifact_type: Option<String>,
+
+    /// Manifest annotation carrying the release metadata
+    #[structopt(long = "upstream.registry.metadata_annotation")]
+    pub metadata_annotation: Option<String>,
+
+    /// Label carrying the release metadata
+    #[structopt(long = "upstream.registry.metadata_label")]
+    pub metadata_label: Option<String>,
+
+    /// File containing the Quay API token, for looking up the release metadata in Quay labels
+    #[structopt(long = "upstream.registry.metadata_quay_api_token_path")]
+    pub metadata_quay_api_token_path: Option<PathBuf>,
+
+    /// Pattern of the repositories to discover through the registry catalog, replacing the repository
+    #[structopt(long = "upstream.registry.repository_pattern")]
+    pub repository_pattern: Option<String>,
+
+    /// Maximum number of discovered repositories
+    #[structopt(long = "upstream.registry.discovery_max_repositories")]
+    pub discovery_max_repositories: Option<usize>,
+
+    /// Pattern of the tags to scrape
+    #[structopt(long = "upstream.registry.tag_pattern")]
+    pub tag_pattern: Option<String>,
+
+    /// Range of the versions of the tags to scrape
+    #[structopt(long = "upstream.registry.tag_version_range")]
+    pub tag_version_range: Option<String>,
+
+    /// Comma-separated architectures of the releases to scrape
+    #[structopt(
+        long = "upstream.registry.architectures",
+        use_delimiter = true,
+        require_delimiter = true
+    )]
+    pub architectures: Option<Vec<String>>,
+
+    /// Retry policy of the failed registry requests
+    #[structopt(skip)]
+    pub retry: Option<RetryPolicy>,
+
+    /// Whether to slow down the requests once the registry throttles them
+    #[structopt(long = "upstream.registry.adaptive_throttling")]
+    pub adaptive_throttling: Option<bool>,
+
+    /// Minimum rate of the throttled registry requests, per second
+    #[structopt(long = "upstream.registry.throttle_min_rate")]
+    pub throttle_min_rate: Option<f64>,
+}
+
+/// Options for the `file` upstream method.
+#[derive(Debug, Deserialize, Serialize, StructOpt)]
+pub struct GraphFileOptions {
+    /// File containing the pre-built graph, in the Cincinnati JSON format
+    #[structopt(long = "upstream.file.path", parse(from_os_str))]
+    pub path: Option<PathBuf>,
+
+    /// Whether to reload the graph when the file changes
+    #[structopt(name = "upstream_file_watch", long = "upstream.file.watch")]
+    pub watch: Option<bool>,
+}
+
+/// Options for the pre-publication graph validation.
+#[derive(Debug, Deserialize, Serialize, StructOpt)]
+pub struct ValidationOptions {
+    /// Whether to validate graphs before publishing them
+    #[structopt(name = "validation_enabled", long = "validation.enabled")]
+    pub enabled: Option<bool>,
+
+    /// Maximum percentage of releases which may disappear between two published graphs
+    #[structopt(long = "validation.max_releases_removed_percent")]
+    pub max_releases_removed_percent: Option<f64>,
+
+    /// Maximum percentage of edges which may disappear between two published graphs
+    #[structopt(long = "validation.max_edges_removed_percent")]
+    pub max_edges_removed_percent: Option<f64>,
+}
+
+/// Options for the resource budget reporting.
+#[derive(Debug, Deserialize, Serialize, StructOpt)]
+pub struct BudgetOptions {
+    /// Resident memory in bytes above which the caches are evicted
+    #[structopt(long = "budget.memory_soft_limit_bytes")]
+    pub memory_soft_limit_bytes: Option<u64>,
+
+    /// Number of cache entries above which the caches are evicted
+    #[structopt(long = "budget.cache_soft_limit_entries")]
+    pub cache_soft_limit_entries: Option<usize>,
+}
+
+/// Options for the protective limits of the published graph.
+#[derive(Debug, Deserialize, Serialize, StructOpt)]
+pub struct LimitsOptions {
+    /// Maximum number of releases of a published graph
+    #[structopt(long = "limits.max_releases")]
+    pub max_releases: Option<u64>,
+
+    /// Maximum number of edges of a published graph
+    #[structopt(long = "limits.max_edges")]
+    pub max_edges: Option<u64>,
+
+    /// Maximum size of the serialized graph, in bytes
+    #[structopt(long = "limits.max_graph_bytes")]
+    pub max_graph_bytes: Option<usize>,
+
+    /// Maximum size of the metadata of a single release, in bytes
+    #[structopt(long = "limits.max_metadata_bytes")]
+    pub max_metadata_bytes: Option<usize>,
 }
 
 impl MergeOptions<Option<ServiceOptions>> for AppSettings {
@@ -99,13 +482,64 @@ impl MergeOptions<Option<ServiceOptions>> for AppSettings {
         if let Some(service) = opts {
             assign_if_some!(self.pause_secs, service.pause_secs);
             assign_if_some!(self.scrape_timeout_secs, service.scrape_timeout_secs);
+            assign_if_some!(self.shutdown_grace, service.shutdown_grace_secs);
+            assign_if_some!(self.shutdown_drain, service.shutdown_drain_secs);
             assign_if_some!(self.address, service.address);
+            assign_if_some!(self.addresses, service.addresses);
             assign_if_some!(self.port, service.port);
+            assign_if_some!(self.socket_path, service.socket_path);
             assign_if_some!(self.path_prefix, service.path_prefix);
             assign_if_some!(self.tracing_endpoint, service.tracing_endpoint);
+            assign_if_some!(self.tracing_sampler, service.tracing_sampler);
+            assign_if_some!(self.tracing_sampling_ratio, service.tracing_sampling_ratio);
+            assign_if_some!(self.preflight_checks, service.preflight_checks);
+            assign_if_some!(self.rate_limit_per_second, service.rate_limit_per_second);
+            assign_if_some!(self.rate_limit_burst, service.rate_limit_burst);
+            assign_if_some!(
+                self.rate_limit_cluster_id_param,
+                service.rate_limit_cluster_id_param
+            );
+            assign_if_some!(
+                self.rate_limit_trust_forwarded_for,
+                service.rate_limit_trust_forwarded_for
+            );
+            assign_if_some!(self.deployment_name, service.deployment_name);
+            assign_if_some!(self.http_proxy, service.http_proxy.map(ProxyUrl));
+            assign_if_some!(self.https_proxy, service.https_proxy.map(ProxyUrl));
+            assign_if_some!(self.no_proxy, service.no_proxy);
+            assign_if_some!(self.proxy_credentials_path, service.proxy_credentials_path);
+            if let Some(headers) = service.outbound_headers {
+                self.outbound_headers.extend(headers);
+            }
+            assign_if_some!(
+                self.changelog_max_revisions,
+                service.changelog_max_revisions
+            );
+            assign_if_some!(self.snapshots_max, service.snapshots_max);
+            assign_if_some!(self.cache_max_age, service.cache_max_age_secs);
+            assign_if_some!(self.internal_graph_api, service.internal_graph_api);
+            assign_if_some!(self.compression_zstd_level, service.compression_zstd_level);
+            assign_if_some!(
+                self.compression_brotli_level,
+                service.compression_brotli_level
+            );
             if let Some(params) = service.mandatory_client_parameters {
                 self.mandatory_client_parameters.extend(params);
             }
+            if let Some(secs) = service.keep_alive {
+                self.service_server.set_keep_alive_secs(secs);
+            }
+            if let Some(secs) = service.client_timeout {
+                self.service_server.client_request_timeout = Duration::from_secs(secs);
+            }
+            assign_if_some!(
+                self.service_server.max_payload_bytes,
+                service.max_payload_bytes
+            );
+            if let Some(workers) = service.workers {
+                self.service_server.workers = Some(workers);
+            }
+            assign_if_some!(self.service_server.http2, service.http2);
         }
         Ok(())
     }
@@ -115,7 +549,42 @@ impl MergeOptions<Option<StatusOptions>> for AppSettings {
     fn try_merge(&mut self, opts: Option<StatusOptions>) -> Fallible<()> {
         if let Some(status) = opts {
             assign_if_some!(self.status_address, status.address);
+            assign_if_some!(self.status_addresses, status.addresses);
             assign_if_some!(self.status_port, status.port);
+            assign_if_some!(self.status_socket_path, status.socket_path);
+            assign_if_some!(self.metrics_otlp_endpoint, status.metrics_otlp_endpoint);
+            if let Some(secs) = status.metrics_otlp_interval_secs {
+                self.metrics_otlp_interval = Duration::from_secs(secs);
+            }
+            assign_if_some!(self.admin_token_path, status.admin_token_path);
+            assign_if_some!(self.status_auth_token_path, status.auth_token_path);
+            assign_if_some!(
+                self.status_auth_credentials_path,
+                status.auth_credentials_path
+            );
+            assign_if_some!(self.audit_log_path, status.audit_log_path);
+            assign_if_some!(self.audit_max_entries, status.audit_max_entries);
+            assign_if_some!(self.audit_max_file_bytes, status.audit_max_file_bytes);
+            assign_if_some!(self.selftest_query, status.selftest_query);
+            assign_if_some!(self.selftest_server_name, status.selftest_server_name);
+            assign_if_some!(self.selftest_ca_path, status.selftest_ca_path);
+            if let Some(secs) = status.max_graph_staleness_secs {
+                self.max_graph_staleness = Some(Duration::from_secs(secs));
+            }
+            if let Some(secs) = status.keep_alive {
+                self.status_server.set_keep_alive_secs(secs);
+            }
+            if let Some(secs) = status.client_timeout {
+                self.status_server.client_request_timeout = Duration::from_secs(secs);
+            }
+            assign_if_some!(
+                self.status_server.max_payload_bytes,
+                status.max_payload_bytes
+            );
+            if let Some(workers) = status.workers {
+                self.status_server.workers = Some(workers);
+            }
+            assign_if_some!(self.status_server.http2, status.http2);
         }
         Ok(())
     }
@@ -127,8 +596,140 @@ impl MergeOptions<Option<DockerRegistryOptions>> for AppSettings {
             assign_if_some!(self.registry, registry.url);
             assign_if_some!(self.repository, registry.repository);
             assign_if_some!(self.credentials_path, registry.credentials_path);
+            assign_if_some!(self.ca_bundle_path, registry.ca_bundle_path);
+            assign_if_some!(self.client_cert_path, registry.client_cert_path);
+            assign_if_some!(self.client_key_path, registry.client_key_path);
             assign_if_some!(self.manifestref_key, registry.manifestref_key);
             assign_if_some!(self.fetch_concurrency, registry.fetch_concurrency);
+            assign_if_some!(self.cache_path, registry.cache_path);
+            assign_if_some!(self.cache_max_entries, registry.cache_max_entries);
+            assign_if_some!(self.metadata_max_keys, registry.metadata_max_keys);
+            assign_if_some!(
+                self.metadata_max_key_length,
+                registry.metadata_max_key_length
+            );
+            assign_if_some!(
+                self.metadata_max_value_size,
+                registry.metadata_max_value_size
+            );
+            assign_if_some!(self.metadata_limit_action, registry.metadata_limit_action);
+            assign_if_some!(self.metadata_source, registry.metadata_source);
+            assign_if_some!(self.metadata_artifact_type, registry.metadata_artifact_type);
+            assign_if_some!(self.metadata_annotation, registry.metadata_annotation);
+            assign_if_some!(self.metadata_sources, registry.metadata_sources);
+            assign_if_some!(self.metadata_label, registry.metadata_label);
+            assign_if_some!(
+                self.metadata_quay_api_token_path,
+                registry.metadata_quay_api_token_path
+            );
+            if let Some(sources) = registry.additional_sources {
+                self.additional_sources.extend(sources);
+            }
+            if let Some(mirrors) = registry.mirrors {
+                self.mirrors.extend(mirrors);
+            }
+            assign_if_some!(
+                self.mirror_timeout_secs,
+                registry.mirror_timeout_secs.map(Duration::from_secs)
+            );
+            assign_if_some!(self.repository_pattern, registry.repository_pattern);
+            assign_if_some!(
+                self.discovery_max_repositories,
+                registry.discovery_max_repositories
+            );
+            assign_if_some!(self.tag_pattern, registry.tag_pattern);
+            assign_if_some!(self.tag_version_range, registry.tag_version_range);
+            assign_if_some!(self.architectures, registry.architectures);
+            assign_if_some!(self.retry_policy, registry.retry);
+            assign_if_some!(self.adaptive_throttling, registry.adaptive_throttling);
+            assign_if_some!(self.throttle_min_rate, registry.throttle_min_rate);
+        }
+        Ok(())
+    }
+}
+
+impl MergeOptions<Option<GraphFileOptions>> for AppSettings {
+    fn try_merge(&mut self, opts: Option<GraphFileOptions>) -> Fallible<()> {
+        if let Some(file) = opts {
+            assign_if_some!(self.graph_file_path, file.path);
+            assign_if_some!(self.graph_file_watch, file.watch);
+        }
+        Ok(())
+    }
+}
+
+impl MergeOptions<Option<ValidationOptions>> for AppSettings {
+    fn try_merge(&mut self, opts: Option<ValidationOptions>) -> Fallible<()> {
+        if let Some(validation) = opts {
+            assign_if_some!(self.validation_enabled, validation.enabled);
+            assign_if_some!(
+                self.validation_max_releases_removed_percent,
+                validation.max_releases_removed_percent
+            );
+            assign_if_some!(
+                self.validation_max_edges_removed_percent,
+                validation.max_edges_removed_percent
+            );
+        }
+        Ok(())
+    }
+}
+
+impl MergeOptions<Option<BudgetOptions>> for AppSettings {
+    fn try_merge(&mut self, opts: Option<BudgetOptions>) -> Fallible<()> {
+        if let Some(budget) = opts {
+            assign_if_some!(
+                self.budget_memory_soft_limit_bytes,
+                budget.memory_soft_limit_bytes
+            );
+            assign_if_some!(
+                self.budget_cache_soft_limit_entries,
+                budget.cache_soft_limit_entries
+            );
+        }
+        Ok(())
+    }
+}
+
+impl MergeOptions<Option<LimitsOptions>> for AppSettings {
+    fn try_merge(&mut self, opts: Option<LimitsOptions>) -> Fallible<()> {
+        if let Some(limits) = opts {
+            assign_if_some!(self.limits_max_releases, limits.max_releases);
+            assign_if_some!(self.limits_max_edges, limits.max_edges);
+            assign_if_some!(self.limits_max_graph_bytes, limits.max_graph_bytes);
+            assign_if_some!(self.limits_max_metadata_bytes, limits.max_metadata_bytes);
+        }
+        Ok(())
+    }
+}
+
+/// Options for TLS termination.
+#[derive(Debug, Deserialize, Serialize, StructOpt)]
+pub struct TlsOptions {
+    /// PEM file with the server certificate, TLS is disabled if unset
+    #[structopt(long = "tls.cert_path")]
+    pub cert_path: Option<PathBuf>,
+
+    /// PEM file with the private key of the server certificate
+    #[structopt(long = "tls.key_path")]
+    pub key_path: Option<PathBuf>,
+
+    /// PEM file with the CA certificates of clients of the main service
+    #[structopt(long = "tls.client_ca_path")]
+    pub client_ca_path: Option<PathBuf>,
+
+    /// Whether the status service terminates TLS too
+    #[structopt(long = "tls.status")]
+    pub status: Option<bool>,
+}
+
+impl MergeOptions<Option<TlsOptions>> for AppSettings {
+    fn try_merge(&mut self, opts: Option<TlsOptions>) -> Fallible<()> {
+        if let Some(tls) = opts {
+            assign_if_some!(self.tls_cert_path, tls.cert_path);
+            assign_if_some!(self.tls_key_path, tls.key_path);
+            assign_if_some!(self.tls_client_ca_path, tls.client_ca_path);
+            assign_if_some!(self.tls_status, tls.status);
         }
         Ok(())
     }
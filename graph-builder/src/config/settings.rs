@@ -1,16 +1,102 @@
 //! Application settings for graph-builder.
 
 use super::{cli, file};
+use crate::{audit, changelog, coordination, freeze, limits, notify, snapshots};
+use cincinnati::metadata_keys::MetadataKeys;
 use cincinnati::plugins::catalog::{build_plugins, PluginSettings};
+use cincinnati::plugins::internal::release_scrape_dockerv2::{
+    MetadataLimitAction, MetadataSource, RetryPolicy, ScrapeSource,
+};
 use cincinnati::plugins::BoxedPlugin;
+use commons::auth::Credentials;
+use commons::compression::CompressionSettings;
+use commons::cors::CorsSettings;
+use commons::listen::{socket_addrs, validate_listeners, validate_sockets};
+use commons::logging::LogFormat;
+use commons::otlp::OtlpSettings;
+use commons::outbound::{ClientIdentity, ProxySettings, ProxyUrl};
 use commons::prelude_errors::*;
+use commons::ratelimit::RateLimitSettings;
+use commons::selftest::{SelfTest, SelfTestTarget};
+use commons::server::HttpServerSettings;
+use commons::tls::TlsSettings;
+use commons::tracing::{SamplerKind, SamplingSettings};
 use commons::MergeOptions;
-use std::collections::HashSet;
-use std::net::{IpAddr, Ipv4Addr};
+use std::collections::{BTreeMap, HashSet};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 use std::path::PathBuf;
+use std::str::FromStr;
 use std::time;
 use structopt::StructOpt;
 
+/// Name of this service in the User-Agent of outbound requests.
+static SERVICE_NAME: &str = "cincinnati-graph-builder";
+
+/// Path segments of the endpoints of the default graph, which tenants can't be named after.
+static RESERVED_TENANT_NAMES: &[&str] = &["graph", "graph-data", "internal", "v1", "v2"];
+
+/// Deployment profile, gating features which must not reach production.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Profile {
+    /// Serves real clusters.
+    #[default]
+    Production,
+    /// Serves test clusters, synthetic releases are allowed.
+    Staging,
+}
+
+impl FromStr for Profile {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "production" => Ok(Profile::Production),
+            "staging" => Ok(Profile::Staging),
+            x => Err(format!(
+                "unknown profile '{}', expected 'production' or 'staging'",
+                x
+            )),
+        }
+    }
+}
+
+/// Graph of a product served by the same instance, under its own path prefix.
+#[derive(Debug)]
+pub struct TenantSettings {
+    /// Name of the tenant, the last segment of its path prefix.
+    pub name: String,
+
+    /// Plugin configuration, including the upstream of the tenant.
+    pub plugin_settings: Vec<Box<dyn PluginSettings>>,
+}
+
+/// Source of the published graph.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum UpstreamMethod {
+    /// Scrape releases from a container registry.
+    #[default]
+    Registry,
+    /// Load a pre-built graph from a local file.
+    File,
+}
+
+impl FromStr for UpstreamMethod {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "registry" => Ok(UpstreamMethod::Registry),
+            "file" => Ok(UpstreamMethod::File),
+            x => Err(format!(
+                "unknown upstream method '{}', expected 'registry' or 'file'",
+                x
+            )),
+        }
+    }
+}
+
 /// Runtime application settings (validated config).
 #[derive(Debug, SmartDefault)]
 pub struct AppSettings {
@@ -18,9 +104,21 @@ pub struct AppSettings {
     #[default(IpAddr::V4(Ipv4Addr::LOCALHOST))]
     pub address: IpAddr,
 
+    /// Listening addresses for the main service, overriding `address` if not empty.
+    pub addresses: Vec<IpAddr>,
+
     /// Optional auth secrets for the registry scraper.
     pub credentials_path: Option<PathBuf>,
 
+    /// Optional CA certificates to trust for the registry scraper.
+    pub ca_bundle_path: Option<PathBuf>,
+
+    /// Optional client certificate presented by the registry scraper.
+    pub client_cert_path: Option<PathBuf>,
+
+    /// Optional key of the client certificate of the registry scraper.
+    pub client_key_path: Option<PathBuf>,
+
     /// Required client parameters for the main service.
     pub mandatory_client_parameters: HashSet<String>,
 
@@ -38,10 +136,38 @@ pub struct AppSettings {
     /// Timeout (in seconds) per registry scrape.
     pub scrape_timeout_secs: Option<time::Duration>,
 
+    /// Grace period for in-flight requests and the ongoing scrape on shutdown.
+    #[default(crate::shutdown::DEFAULT_GRACE_PERIOD)]
+    pub shutdown_grace: time::Duration,
+
+    /// Delay between stopping to report readiness and stopping the main service on shutdown.
+    #[default(crate::shutdown::DEFAULT_DRAIN_DELAY)]
+    pub shutdown_drain: time::Duration,
+
     /// Listening port for the main service.
     #[default(8080)]
     pub port: u16,
 
+    /// Unix socket for the main service, replacing the TCP listeners if set.
+    pub socket_path: Option<PathBuf>,
+
+    /// Tuning of the HTTP server of the main service.
+    #[default(HttpServerSettings {
+        keep_alive: Some(time::Duration::from_secs(10)),
+        ..Default::default()
+    })]
+    pub service_server: HttpServerSettings,
+
+    /// Source of the published graph.
+    pub upstream_method: UpstreamMethod,
+
+    /// File containing the pre-built graph, for the `file` upstream method.
+    pub graph_file_path: Option<PathBuf>,
+
+    /// Whether to reload the pre-built graph when its file changes.
+    #[default(true)]
+    pub graph_file_watch: bool,
+
     // TODO(lucab): split this in (TLS, hostname+port).
     /// Target host for the registry scraper.
     #[default(cincinnati::plugins::internal::release_scrape_dockerv2::DEFAULT_SCRAPE_REGISTRY.to_string())]
@@ -51,22 +177,159 @@ pub struct AppSettings {
     #[default(cincinnati::plugins::internal::release_scrape_dockerv2::DEFAULT_SCRAPE_REPOSITORY.to_string())]
     pub repository: String,
 
+    /// Further registry repositories to scrape releases from.
+    pub additional_sources: Vec<ScrapeSource>,
+
+    /// Mirrors of the main registry repository, tried in order if it can't be scraped.
+    pub mirrors: Vec<ScrapeSource>,
+
+    /// Timeout for scraping the main repository or one of its mirrors.
+    pub mirror_timeout_secs: Option<time::Duration>,
+
+    /// Pattern of the repositories to discover through the registry catalog, replacing `repository`.
+    pub repository_pattern: Option<String>,
+
+    /// Maximum number of repositories scraped in discovery mode.
+    #[default(
+        cincinnati::plugins::internal::release_scrape_dockerv2::DEFAULT_DISCOVERY_MAX_REPOSITORIES
+    )]
+    pub discovery_max_repositories: usize,
+
+    /// Pattern of the tags to scrape, if any.
+    pub tag_pattern: Option<String>,
+
+    /// Range of the versions of the tags to scrape, if any.
+    pub tag_version_range: Option<String>,
+
+    /// Architectures of the releases to scrape, all of them if empty.
+    pub architectures: Vec<String>,
+
+    /// Retry policy of the failed requests of the registry scraper.
+    pub retry_policy: RetryPolicy,
+
+    /// Whether the registry scraper slows down once the registry throttles it.
+    pub adaptive_throttling: bool,
+
+    /// Minimum rate of the throttled requests of the registry scraper, per second.
+    #[default(cincinnati::plugins::internal::release_scrape_dockerv2::DEFAULT_THROTTLE_MIN_RATE)]
+    pub throttle_min_rate: f64,
+
     /// Listening address for the status service.
     #[default(IpAddr::V4(Ipv4Addr::LOCALHOST))]
     pub status_address: IpAddr,
 
+    /// Listening addresses for the status service, overriding `status_address` if not empty.
+    pub status_addresses: Vec<IpAddr>,
+
     /// Listening port for the status service.
     #[default(9080)]
     pub status_port: u16,
 
+    /// Unix socket for the status service, replacing the TCP listeners if set.
+    pub status_socket_path: Option<PathBuf>,
+
+    /// Tuning of the HTTP server of the status service.
+    pub status_server: HttpServerSettings,
+
+    /// Optional OTLP/HTTP endpoint to push metrics to.
+    pub metrics_otlp_endpoint: Option<String>,
+
+    /// Interval between OTLP metrics exports.
+    #[default(commons::otlp::DEFAULT_EXPORT_INTERVAL)]
+    pub metrics_otlp_interval: time::Duration,
+
+    /// Query parameters of the graph requests of the self-test.
+    pub selftest_query: String,
+
+    /// Optional host name the self-test reaches the main service by.
+    pub selftest_server_name: Option<String>,
+
+    /// Optional PEM file with additional CA certificates trusted by the self-test.
+    pub selftest_ca_path: Option<PathBuf>,
+
+    /// Optional age of the last successful graph refresh after which the service isn't ready.
+    pub max_graph_staleness: Option<time::Duration>,
+
+    /// Optional file containing the bearer token for the admin endpoints.
+    /// The admin endpoints are disabled if unset.
+    pub admin_token_path: Option<PathBuf>,
+
+    /// Optional file containing the bearer token required on the status endpoints.
+    pub status_auth_token_path: Option<PathBuf>,
+
+    /// Optional file of basic authentication credentials accepted on the status endpoints.
+    /// The status endpoints are unauthenticated if neither this nor the token is set.
+    pub status_auth_credentials_path: Option<PathBuf>,
+
+    /// Optional file to which the published graph transitions are appended.
+    pub audit_log_path: Option<PathBuf>,
+
+    /// Number of graph transitions kept in memory for the audit endpoint.
+    #[default(audit::DEFAULT_MAX_ENTRIES)]
+    pub audit_max_entries: usize,
+
+    /// Size of the audit log file at which it is rotated, in bytes.
+    #[default(audit::DEFAULT_MAX_FILE_BYTES)]
+    pub audit_max_file_bytes: u64,
+
     /// Global log level.
     #[default(log::LevelFilter::Warn)]
     pub verbosity: log::LevelFilter,
 
-    /// Concurrency for graph fetching
+    /// Log output format.
+    pub log_format: LogFormat,
+
+    /// Deployment profile.
+    pub profile: Profile,
+
+    /// Maximum number of tags fetched at once by the registry scraper.
     #[default(cincinnati::plugins::internal::release_scrape_dockerv2::DEFAULT_FETCH_CONCURRENCY)]
     pub fetch_concurrency: usize,
 
+    /// Optional directory for the persistent release metadata cache.
+    pub cache_path: Option<PathBuf>,
+
+    /// Maximum number of entries in the persistent release metadata cache.
+    #[default(cincinnati::plugins::internal::release_scrape_dockerv2::DEFAULT_CACHE_MAX_ENTRIES)]
+    pub cache_max_entries: usize,
+
+    /// Maximum number of metadata entries per scraped release.
+    #[default(cincinnati::plugins::internal::release_scrape_dockerv2::DEFAULT_METADATA_MAX_KEYS)]
+    pub metadata_max_keys: usize,
+
+    /// Maximum length of a metadata key of scraped releases, in bytes.
+    #[default(
+        cincinnati::plugins::internal::release_scrape_dockerv2::DEFAULT_METADATA_MAX_KEY_LENGTH
+    )]
+    pub metadata_max_key_length: usize,
+
+    /// Maximum size of a metadata value of scraped releases, in bytes.
+    #[default(
+        cincinnati::plugins::internal::release_scrape_dockerv2::DEFAULT_METADATA_MAX_VALUE_SIZE
+    )]
+    pub metadata_max_value_size: usize,
+
+    /// Handling of scraped releases whose metadata exceeds the limits.
+    pub metadata_limit_action: MetadataLimitAction,
+
+    /// Where the registry scraper finds the release metadata.
+    pub metadata_source: MetadataSource,
+
+    /// Artifact type of the release metadata artifacts, if any.
+    pub metadata_artifact_type: Option<String>,
+
+    /// Manifest annotation carrying the release metadata, if any.
+    pub metadata_annotation: Option<String>,
+
+    /// Sources of the release metadata, tried in order, if any.
+    pub metadata_sources: Vec<MetadataSource>,
+
+    /// Label carrying the release metadata, if any.
+    pub metadata_label: Option<String>,
+
+    /// File containing the Quay API token for the Quay labels metadata source, if any.
+    pub metadata_quay_api_token_path: Option<PathBuf>,
+
     /// Metrics which are required to be registered, to be specified without the `METRICS_PREFIX`.
     /// If these are not registered by the time all plugins have been loaded an error will be thrown.
     #[default([
@@ -77,8 +340,153 @@ pub struct AppSettings {
     /// Plugin configuration.
     pub plugin_settings: Vec<Box<dyn PluginSettings>>,
 
+    /// Graphs of other products, each served under `<path_prefix>/<name>`.
+    pub tenants: Vec<TenantSettings>,
+
     /// Jaeger host and port for tracing support
     pub tracing_endpoint: Option<String>,
+
+    /// Sampling strategy of the traces.
+    pub tracing_sampler: SamplerKind,
+
+    /// Ratio of sampled traces, for the ratio based samplers.
+    #[default(commons::tracing::DEFAULT_SAMPLING_RATIO)]
+    pub tracing_sampling_ratio: f64,
+
+    /// Whether to run preflight checks before starting the services.
+    pub preflight_checks: bool,
+
+    /// Whether to build the graph once and exit, without starting the services.
+    pub once: bool,
+
+    /// Output of the graph built in one-shot mode, stdout if unset.
+    pub once_output: Option<PathBuf>,
+
+    /// Graph-data directory or archive URL to lint, before exiting without
+    /// starting the services.
+    pub validate_graph_data: Option<String>,
+
+    /// Whether to validate graphs before publishing them.
+    pub validation_enabled: bool,
+
+    /// Maximum percentage of releases which may disappear between two published graphs.
+    #[default(20.0)]
+    pub validation_max_releases_removed_percent: f64,
+
+    /// Maximum percentage of edges which may disappear between two published graphs.
+    #[default(20.0)]
+    pub validation_max_edges_removed_percent: f64,
+
+    /// Resident memory above which the caches are evicted.
+    pub budget_memory_soft_limit_bytes: Option<u64>,
+
+    /// Number of cache entries above which the caches are evicted.
+    pub budget_cache_soft_limit_entries: Option<usize>,
+
+    /// Maximum number of releases of a published graph.
+    #[default(Some(limits::DEFAULT_MAX_RELEASES))]
+    pub limits_max_releases: Option<u64>,
+
+    /// Maximum number of edges of a published graph.
+    #[default(Some(limits::DEFAULT_MAX_EDGES))]
+    pub limits_max_edges: Option<u64>,
+
+    /// Maximum size of the serialized graph, in bytes.
+    #[default(Some(limits::DEFAULT_MAX_GRAPH_BYTES))]
+    pub limits_max_graph_bytes: Option<usize>,
+
+    /// Maximum size of the metadata of a single release, in bytes.
+    #[default(Some(limits::DEFAULT_MAX_METADATA_BYTES))]
+    pub limits_max_metadata_bytes: Option<usize>,
+
+    /// Sinks for operational event notifications.
+    pub notification_sinks: Vec<notify::SinkConfig>,
+
+    /// Number of consecutive scrape failures which triggers a notification.
+    #[default(3)]
+    pub notification_scrape_failure_threshold: u64,
+
+    /// Period during which repeated events of the same type are not notified again.
+    #[default(notify::DEFAULT_DEBOUNCE)]
+    pub notification_debounce: time::Duration,
+
+    /// Windows during which new graphs are not published.
+    pub freeze_windows: Vec<freeze::FreezeWindow>,
+
+    /// File keeping the published graph, to serve it again if restarted during a freeze.
+    pub freeze_state_path: Option<PathBuf>,
+
+    /// Leader election across replicas, each replica scraping on its own if unset.
+    pub coordination: Option<coordination::CoordinationSettings>,
+
+    /// Cross-origin resource sharing on the main and status services, disabled if unset.
+    pub cors: Option<CorsSettings>,
+
+    /// Sustained requests per second allowed per client on the main service, unlimited if unset.
+    pub rate_limit_per_second: Option<f64>,
+
+    /// Number of requests a client may send at once to the main service.
+    #[default(20)]
+    pub rate_limit_burst: u32,
+
+    /// Query parameter identifying clusters, which are rate limited on their own.
+    pub rate_limit_cluster_id_param: Option<String>,
+
+    /// Identify clients by the forwarding headers set by a trusted proxy.
+    pub rate_limit_trust_forwarded_for: bool,
+
+    /// Name of this deployment, sent in the User-Agent of outbound requests.
+    pub deployment_name: Option<String>,
+
+    /// Further headers to send along with outbound requests.
+    pub outbound_headers: BTreeMap<String, String>,
+
+    /// Proxy of outbound plain HTTP requests, overriding `http_proxy`.
+    pub http_proxy: Option<ProxyUrl>,
+
+    /// Proxy of outbound HTTPS requests, overriding `https_proxy`.
+    pub https_proxy: Option<ProxyUrl>,
+
+    /// Hosts and domains reached without proxy, overriding `no_proxy`.
+    pub no_proxy: Option<String>,
+
+    /// File containing the `username:password` credentials for the proxies.
+    pub proxy_credentials_path: Option<PathBuf>,
+
+    /// Number of graph revisions kept for the changelog endpoint.
+    #[default(changelog::DEFAULT_MAX_REVISIONS)]
+    pub changelog_max_revisions: usize,
+
+    /// Number of recently published graphs kept as snapshots, 0 to disable.
+    #[default(snapshots::DEFAULT_MAX_SNAPSHOTS)]
+    pub snapshots_max: usize,
+
+    /// Lifetime of graph responses in HTTP caches, which revalidate them if zero.
+    pub cache_max_age: time::Duration,
+
+    /// Whether to serve the binary graph artifact to internal consumers.
+    pub internal_graph_api: bool,
+
+    /// zstd compression level of responses of the main service.
+    #[default(commons::compression::DEFAULT_ZSTD_LEVEL)]
+    pub compression_zstd_level: i32,
+
+    /// brotli compression level of responses of the main service.
+    #[default(commons::compression::DEFAULT_BROTLI_LEVEL)]
+    pub compression_brotli_level: u32,
+
+    /// Server certificate for TLS termination, TLS is disabled if unset.
+    pub tls_cert_path: Option<PathBuf>,
+
+    /// Private key of the server certificate.
+    pub tls_key_path: Option<PathBuf>,
+
+    /// CA certificates of clients of the main service, which are then required
+    /// to authenticate with a certificate.
+    pub tls_client_ca_path: Option<PathBuf>,
+
+    /// Whether the status service terminates TLS too, with the same certificate.
+    pub tls_status: bool,
 }
 
 impl AppSettings {
@@ -87,10 +495,10 @@ impl AppSettings {
     pub fn assemble() -> Fallible<Self> {
         // Source options.
         let cli_opts = cli::CliOptions::from_args();
-        let file_opts = match &cli_opts.config_path {
-            Some(ref path) => Some(file::FileOptions::read_filepath(path)?),
-            None => None,
-        };
+        let file_opts = file::FileOptions::read_layered(
+            cli_opts.config_path.as_deref().map(std::path::Path::new),
+            cli_opts.config_dir.as_deref(),
+        )?;
         let defaults = Self::default();
 
         // Combine options into a single config.
@@ -102,6 +510,211 @@ impl AppSettings {
         Self::try_validate(cfg)
     }
 
+    /// Read the bearer token for the admin endpoints, if configured.
+    pub fn read_admin_token(&self) -> Fallible<Option<String>> {
+        let path = match &self.admin_token_path {
+            Some(path) => path,
+            None => return Ok(None),
+        };
+
+        let token = std::fs::read_to_string(path)
+            .context(format!("reading admin token from {}", path.display()))?
+            .trim()
+            .to_string();
+        ensure!(!token.is_empty(), "empty admin token in {}", path.display());
+
+        Ok(Some(token))
+    }
+
+    /// Read the credentials required on the status endpoints, if configured.
+    pub fn status_credentials(&self) -> Fallible<Option<Credentials>> {
+        Credentials::read(
+            self.status_auth_token_path.as_deref(),
+            self.status_auth_credentials_path.as_deref(),
+        )
+    }
+
+    /// Return the identity to use for outbound requests.
+    pub fn client_identity(&self) -> ClientIdentity {
+        ClientIdentity {
+            deployment: self.deployment_name.clone(),
+            headers: self.outbound_headers.clone(),
+            ..ClientIdentity::new(SERVICE_NAME, env!("CARGO_PKG_VERSION"))
+        }
+    }
+
+    /// Return the proxies to use for outbound requests.
+    ///
+    /// The configured proxies take precedence over the environment variables.
+    pub fn proxy_settings(&self) -> Fallible<ProxySettings> {
+        let mut proxy = ProxySettings::from_env();
+        assign_if_some!(
+            proxy.http_proxy,
+            self.http_proxy.as_ref().map(|proxy| proxy.0.clone())
+        );
+        assign_if_some!(
+            proxy.https_proxy,
+            self.https_proxy.as_ref().map(|proxy| proxy.0.clone())
+        );
+        assign_if_some!(proxy.no_proxy, self.no_proxy.clone());
+        proxy.credentials = self
+            .proxy_credentials_path
+            .as_deref()
+            .map(ProxySettings::read_credentials)
+            .transpose()?;
+        Ok(proxy)
+    }
+
+    /// Return the socket addresses the main service listens on, none if it listens on a Unix socket.
+    pub fn service_addrs(&self) -> Vec<SocketAddr> {
+        if self.socket_path.is_some() {
+            return vec![];
+        }
+        socket_addrs(self.address, &self.addresses, self.port)
+    }
+
+    /// Return the socket addresses the status service listens on, none if it listens on a Unix socket.
+    pub fn status_addrs(&self) -> Vec<SocketAddr> {
+        if self.status_socket_path.is_some() {
+            return vec![];
+        }
+        socket_addrs(
+            self.status_address,
+            &self.status_addresses,
+            self.status_port,
+        )
+    }
+
+    /// Return the sampling settings of the tracer.
+    pub fn tracing_sampling(&self) -> SamplingSettings {
+        SamplingSettings {
+            sampler: self.tracing_sampler,
+            ratio: self.tracing_sampling_ratio,
+        }
+    }
+
+    /// Return the OTLP metrics export settings, if enabled.
+    pub fn otlp_settings(&self) -> Option<OtlpSettings> {
+        self.metrics_otlp_endpoint
+            .as_ref()
+            .map(|endpoint| OtlpSettings {
+                endpoint: endpoint.clone(),
+                interval: self.metrics_otlp_interval,
+            })
+    }
+
+    /// Return the TLS settings for the main service, if enabled.
+    pub fn tls_settings(&self) -> Option<TlsSettings> {
+        match (&self.tls_cert_path, &self.tls_key_path) {
+            (Some(cert_path), Some(key_path)) => Some(TlsSettings {
+                cert_path: cert_path.clone(),
+                key_path: key_path.clone(),
+                client_ca_path: self.tls_client_ca_path.clone(),
+            }),
+            _ => None,
+        }
+    }
+
+    /// Return the TLS settings for the status service, if enabled with `tls.status`.
+    ///
+    /// Client certificates are not required on the status service, so that
+    /// probes and metrics scraping keep working.
+    pub fn status_tls_settings(&self) -> Option<TlsSettings> {
+        if !self.tls_status {
+            return None;
+        }
+        self.tls_settings().map(|tls| TlsSettings {
+            client_ca_path: None,
+            ..tls
+        })
+    }
+
+    /// Return the response compression settings for the main service.
+    pub fn compression_settings(&self) -> CompressionSettings {
+        CompressionSettings {
+            zstd_level: self.compression_zstd_level,
+            brotli_level: self.compression_brotli_level,
+        }
+    }
+
+    /// Return a description of the graph sources, as reported by the build-info endpoint.
+    ///
+    /// Registry sources are listed in scrape order, the main repository first,
+    /// separated by commas.
+    pub fn upstream(&self) -> String {
+        match self.upstream_method {
+            UpstreamMethod::File => self
+                .graph_file_path
+                .as_ref()
+                .map(|path| format!("file:{}", path.display()))
+                .unwrap_or_else(|| "file".to_string()),
+            UpstreamMethod::Registry => std::iter::once(format!(
+                "registry:{}/{}",
+                self.registry,
+                self.repository_pattern.as_ref().unwrap_or(&self.repository)
+            ))
+            .chain(
+                self.additional_sources
+                    .iter()
+                    .map(|source| format!("registry:{}/{}", source.registry, source.repository)),
+            )
+            .collect::<Vec<_>>()
+            .join(", "),
+        }
+    }
+
+    /// Return the self-test of the main service, reached on its first address.
+    ///
+    /// The self-test is unavailable if the status service is unauthenticated,
+    /// as each run sends several requests, or if the main service listens on a
+    /// Unix socket.
+    pub fn selftest(&self) -> Fallible<Option<SelfTest>> {
+        if self.status_auth_token_path.is_none() && self.status_auth_credentials_path.is_none() {
+            return Ok(None);
+        }
+        let addr = match self.service_addrs().first() {
+            Some(addr) => *addr,
+            None => return Ok(None),
+        };
+
+        let mut target = SelfTestTarget::new(
+            addr,
+            self.tls_cert_path.is_some(),
+            &self.path_prefix,
+            &self.selftest_query,
+        )?;
+        if let Some(name) = &self.selftest_server_name {
+            target = target.with_server_name(name)?;
+        }
+        SelfTest::new(target, self.selftest_ca_path.as_deref()).map(Some)
+    }
+
+    /// Return the rate limiting settings for the main service, if enabled.
+    pub fn rate_limit_settings(&self) -> Option<RateLimitSettings> {
+        self.rate_limit_per_second
+            .map(|requests_per_second| RateLimitSettings {
+                requests_per_second,
+                burst: self.rate_limit_burst,
+                cluster_id_param: self.rate_limit_cluster_id_param.clone(),
+                trust_forwarded_for: self.rate_limit_trust_forwarded_for,
+            })
+    }
+
+    /// Turn these settings into the ones of the tenant `name`.
+    ///
+    /// The tenant's plugins replace the configured ones, all other settings apply
+    /// to every tenant.
+    pub fn into_tenant(mut self, name: &str) -> Fallible<Self> {
+        let index = self
+            .tenants
+            .iter()
+            .position(|tenant| tenant.name == name)
+            .ok_or_else(|| format_err!("tenant '{}' is not configured", name))?;
+        self.plugin_settings = self.tenants.swap_remove(index).plugin_settings;
+        self.tenants.clear();
+        Ok(self)
+    }
+
     /// Validate and return configured plugins.
     pub fn validate_and_build_plugins(
         &self,
@@ -115,7 +728,20 @@ impl AppSettings {
             &self.plugin_settings
         };
 
-        build_plugins(plugin_settings, registry)
+        let plugins = build_plugins(plugin_settings, registry)?;
+        cincinnati::plugins::lint::check(&plugins)?;
+        Ok(plugins)
+    }
+
+    /// Keys of the release metadata, with the prefix of the configured plugins.
+    pub fn metadata_keys(&self) -> Fallible<MetadataKeys> {
+        if self.plugin_settings.is_empty() {
+            Ok(MetadataKeys::from_plugins(
+                &self.default_openshift_plugin_settings()?,
+            ))
+        } else {
+            Ok(MetadataKeys::from_plugins(&self.plugin_settings))
+        }
     }
 
     /// Validate and build runtime settings.
@@ -123,6 +749,214 @@ impl AppSettings {
         if self.pause_secs.as_secs() == 0 {
             bail!("unexpected 0s pause");
         }
+        ensure!(
+            self.client_cert_path.is_some() == self.client_key_path.is_some(),
+            "upstream.registry.client_cert_path and client_key_path must be set together"
+        );
+
+        ensure!(
+            self.once || self.once_output.is_none(),
+            "--output requires --once"
+        );
+        ensure!(
+            !(self.once && self.validate_graph_data.is_some()),
+            "--once and --validate-graph-data are mutually exclusive"
+        );
+
+        ensure!(
+            self.upstream_method != UpstreamMethod::File || self.graph_file_path.is_some(),
+            "upstream.method 'file' requires upstream.file.path"
+        );
+
+        validate_listeners(&self.service_addrs(), &self.status_addrs())?;
+        validate_sockets(
+            self.socket_path.as_deref(),
+            self.status_socket_path.as_deref(),
+            self.tls_cert_path.is_some(),
+            self.status_tls_settings().is_some(),
+        )?;
+        self.service_server.validate("service")?;
+        self.status_server.validate("status")?;
+
+        ensure!(
+            self.fetch_concurrency > 0,
+            "upstream.registry.fetch_concurrency must be greater than 0"
+        );
+
+        if let Some(endpoint) = &self.metrics_otlp_endpoint {
+            url::Url::parse(endpoint).context(format!(
+                "invalid status.metrics_otlp_endpoint '{}'",
+                endpoint
+            ))?;
+        }
+        ensure!(
+            self.metrics_otlp_interval.as_secs() > 0,
+            "status.metrics_otlp_interval_secs must be positive"
+        );
+        self.tracing_sampling()
+            .validate()
+            .context("invalid service.tracing_sampling_ratio")?;
+        ensure!(
+            self.max_graph_staleness
+                .is_none_or(|staleness| staleness.as_secs() > 0),
+            "status.max_graph_staleness_secs must be positive"
+        );
+
+        if let Some(requests_per_second) = self.rate_limit_per_second {
+            ensure!(
+                requests_per_second.is_finite() && requests_per_second > 0.0,
+                "service.rate_limit_per_second must be positive, got {}",
+                requests_per_second
+            );
+        }
+        ensure!(
+            self.rate_limit_burst > 0,
+            "service.rate_limit_burst must be positive"
+        );
+
+        self.client_identity()
+            .headers()
+            .context("invalid service.deployment_name or service.outbound_headers")?;
+        ProxySettings {
+            http_proxy: self.http_proxy.as_ref().map(|proxy| proxy.0.clone()),
+            https_proxy: self.https_proxy.as_ref().map(|proxy| proxy.0.clone()),
+            ..Default::default()
+        }
+        .validate()
+        .context("invalid service.http_proxy or service.https_proxy")?;
+
+        ensure!(
+            self.changelog_max_revisions > 0,
+            "service.changelog_max_revisions must be positive"
+        );
+        ensure!(
+            self.audit_max_entries > 0,
+            "status.audit_max_entries must be positive"
+        );
+        ensure!(
+            self.audit_max_file_bytes > 0,
+            "status.audit_max_file_bytes must be positive"
+        );
+
+        ensure!(
+            self.tls_cert_path.is_some() == self.tls_key_path.is_some(),
+            "tls.cert_path and tls.key_path must be set together"
+        );
+        ensure!(
+            self.tls_client_ca_path.is_none() || self.tls_cert_path.is_some(),
+            "tls.client_ca_path requires tls.cert_path and tls.key_path"
+        );
+        ensure!(
+            !self.tls_status || self.tls_cert_path.is_some(),
+            "tls.status requires tls.cert_path and tls.key_path"
+        );
+        ensure!(
+            commons::compression::ZSTD_LEVELS.contains(&self.compression_zstd_level),
+            "service.compression_zstd_level must be between 1 and 22, got {}",
+            self.compression_zstd_level
+        );
+        ensure!(
+            commons::compression::BROTLI_LEVELS.contains(&self.compression_brotli_level),
+            "service.compression_brotli_level must be between 0 and 11, got {}",
+            self.compression_brotli_level
+        );
+
+        ensure!(
+            self.budget_memory_soft_limit_bytes != Some(0),
+            "budget.memory_soft_limit_bytes must be positive"
+        );
+        ensure!(
+            self.budget_cache_soft_limit_entries != Some(0),
+            "budget.cache_soft_limit_entries must be positive"
+        );
+        for (name, zero) in &[
+            ("limits.max_releases", self.limits_max_releases == Some(0)),
+            ("limits.max_edges", self.limits_max_edges == Some(0)),
+            (
+                "limits.max_graph_bytes",
+                self.limits_max_graph_bytes == Some(0),
+            ),
+            (
+                "limits.max_metadata_bytes",
+                self.limits_max_metadata_bytes == Some(0),
+            ),
+        ] {
+            ensure!(!zero, "{} must be positive", name);
+        }
+
+        ensure!(
+            self.notification_scrape_failure_threshold > 0,
+            "notifications.scrape_failure_threshold must be positive"
+        );
+
+        for (index, window) in self.freeze_windows.iter().enumerate() {
+            window
+                .validate()
+                .context(format!("invalid freeze window #{}", index))?;
+        }
+
+        if let Some(cors) = &self.cors {
+            cors.validate().context("invalid CORS settings")?;
+        }
+
+        let mut tenant_names = HashSet::new();
+        for tenant in &self.tenants {
+            ensure!(
+                !tenant.name.is_empty()
+                    && tenant
+                        .name
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_'),
+                "invalid tenant name '{}', expected letters, digits, '-' and '_'",
+                tenant.name
+            );
+            ensure!(
+                !RESERVED_TENANT_NAMES.contains(&tenant.name.as_str()),
+                "tenant name '{}' is reserved",
+                tenant.name
+            );
+            ensure!(
+                tenant_names.insert(&tenant.name),
+                "duplicate tenant '{}'",
+                tenant.name
+            );
+            ensure!(
+                !tenant.plugin_settings.is_empty(),
+                "tenant '{}' has no plugin settings",
+                tenant.name
+            );
+        }
+        ensure!(
+            self.tenants.is_empty() || self.coordination.is_none(),
+            "tenants can't be combined with coordination"
+        );
+
+        if let Some(coordination) = &self.coordination {
+            coordination.validate()?;
+            // Any replica may become the leader, serving the graph to the others.
+            ensure!(
+                self.internal_graph_api,
+                "coordination requires service.internal_graph_api"
+            );
+        }
+
+        for (name, percent) in &[
+            (
+                "validation.max_releases_removed_percent",
+                self.validation_max_releases_removed_percent,
+            ),
+            (
+                "validation.max_edges_removed_percent",
+                self.validation_max_edges_removed_percent,
+            ),
+        ] {
+            ensure!(
+                (0.0..=100.0).contains(percent),
+                "{} must be between 0 and 100, got {}",
+                name,
+                percent
+            );
+        }
 
         Ok(self)
     }
@@ -131,11 +965,58 @@ impl AppSettings {
         use cincinnati::plugins::internal::github_openshift_secondary_metadata_scraper::GITHUB_SCRAPER_TOKEN_PATH_ENV;
         use cincinnati::plugins::prelude::*;
 
+        #[derive(Serialize)]
+        struct ScrapeOptions<'a> {
+            metadata_limit_action: MetadataLimitAction,
+            metadata_source: MetadataSource,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            metadata_artifact_type: Option<&'a str>,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            metadata_annotation: Option<&'a str>,
+            metadata_sources: &'a [MetadataSource],
+            #[serde(skip_serializing_if = "Option::is_none")]
+            metadata_label: Option<&'a str>,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            metadata_quay_api_token_path: Option<&'a std::path::Path>,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            mirror_timeout_secs: Option<u64>,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            repository_pattern: Option<&'a str>,
+            discovery_max_repositories: usize,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            tag_pattern: Option<&'a str>,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            tag_version_range: Option<&'a str>,
+            architectures: &'a [String],
+            adaptive_throttling: bool,
+            throttle_min_rate: f64,
+            additional_sources: &'a [ScrapeSource],
+            mirrors: &'a [ScrapeSource],
+            retry: &'a RetryPolicy,
+        }
+
         lazy_static! {
             static ref GRAPH_DATA_DIR: tempfile::TempDir =
                 tempfile::tempdir().expect("failed to create tempdir");
         };
 
+        if self.upstream_method == UpstreamMethod::File {
+            let path = self
+                .graph_file_path
+                .as_ref()
+                .ok_or_else(|| format_err!("upstream.method 'file' requires upstream.file.path"))?;
+            return Ok(vec![GraphFileLoadSettings::deserialize_config(
+                toml::from_str(&format!(
+                    r#"
+                        path = {:?}
+                        watch = {}
+                    "#,
+                    path, self.graph_file_watch,
+                ))
+                .context("Parsing config string to settings")?,
+            )?]);
+        }
+
         let plugins = vec![
             ReleaseScrapeDockerv2Settings::deserialize_config(toml::from_str(&format!(
                 r#"
@@ -144,6 +1025,13 @@ impl AppSettings {
                     repository = "{}"
                     manifestref_key = "{}"
                     fetch_concurrency = {}
+                    cache_max_entries = {}
+                    metadata_max_keys = {}
+                    metadata_max_key_length = {}
+                    metadata_max_value_size = {}
+                    {}
+                    {}
+                    {}
                     {}
                 "#,
                 ReleaseScrapeDockerv2Plugin::PLUGIN_NAME,
@@ -151,12 +1039,53 @@ impl AppSettings {
                 &self.repository,
                 &self.manifestref_key,
                 self.fetch_concurrency,
+                self.cache_max_entries,
+                self.metadata_max_keys,
+                self.metadata_max_key_length,
+                self.metadata_max_value_size,
+                self.cache_path
+                    .as_ref()
+                    .and_then(|pathbuf| pathbuf.to_str())
+                    .map(|path| format!("\ncache_path = {:?}", path))
+                    .unwrap_or_default(),
                 self.credentials_path
                     .as_ref()
-                    .map(|pathbuf| pathbuf.to_str())
-                    .flatten()
+                    .and_then(|pathbuf| pathbuf.to_str())
                     .map(|path| format!("\ncredentials_path = {:?}", path))
-                    .unwrap_or_default()
+                    .unwrap_or_default(),
+                [
+                    ("ca_bundle_path", &self.ca_bundle_path),
+                    ("client_cert_path", &self.client_cert_path),
+                    ("client_key_path", &self.client_key_path),
+                ]
+                .iter()
+                .filter_map(|(key, path)| path
+                    .as_ref()
+                    .and_then(|pathbuf| pathbuf.to_str())
+                    .map(|path| format!("\n{} = {:?}", key, path)))
+                .collect::<String>(),
+                // Going through a value emits the plain values before the tables,
+                // whatever the order of the fields and whether the lists are empty.
+                toml::to_string(&toml::Value::try_from(ScrapeOptions {
+                    metadata_limit_action: self.metadata_limit_action,
+                    metadata_source: self.metadata_source,
+                    metadata_artifact_type: self.metadata_artifact_type.as_deref(),
+                    metadata_annotation: self.metadata_annotation.as_deref(),
+                    metadata_sources: &self.metadata_sources,
+                    metadata_label: self.metadata_label.as_deref(),
+                    metadata_quay_api_token_path: self.metadata_quay_api_token_path.as_deref(),
+                    mirror_timeout_secs: self.mirror_timeout_secs.map(|timeout| timeout.as_secs()),
+                    repository_pattern: self.repository_pattern.as_deref(),
+                    discovery_max_repositories: self.discovery_max_repositories,
+                    tag_pattern: self.tag_pattern.as_deref(),
+                    tag_version_range: self.tag_version_range.as_deref(),
+                    architectures: &self.architectures,
+                    adaptive_throttling: self.adaptive_throttling,
+                    throttle_min_rate: self.throttle_min_rate,
+                    additional_sources: &self.additional_sources,
+                    mirrors: &self.mirrors,
+                    retry: &self.retry_policy,
+                })?)?,
             ))?)?,
             GithubOpenshiftSecondaryMetadataScraperSettings::deserialize_config(toml::from_str(
                 &format!(
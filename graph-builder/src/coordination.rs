@@ -0,0 +1,489 @@
+//! Leader election across graph-builder replicas.
+//!
+//! Without coordination, every replica scrapes the upstream on its own, which
+//! multiplies the load on the registry. With it, replicas compete for a
+//! Kubernetes `Lease`: its holder scrapes and publishes as usual, while the
+//! other replicas fetch the graph it built from its internal graph endpoint.
+//! Replicas identify themselves in the lease by the URL of that endpoint, so
+//! that followers know where to fetch from.
+//!
+//! The lease is renewed in the background, three times per lease duration. A
+//! replica which couldn't observe the lease for a whole lease duration, for
+//! example because the Kubernetes API is unreachable, scrapes on its own.
+
+use crate::graph::State;
+use chrono::{DateTime, Utc};
+use cincinnati::artifact;
+use cincinnati::plugins::internal::release_scrape_dockerv2::registry;
+use cincinnati::plugins::InternalIO;
+use cincinnati::provenance::Provenance;
+use commons::prelude_errors::*;
+use parking_lot::RwLock;
+use prometheus::IntGauge;
+use reqwest::header::{HeaderValue, ACCEPT};
+use reqwest::StatusCode;
+use serde_json::{json, Value};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Default name of the lease.
+pub static DEFAULT_LEASE_NAME: &str = "cincinnati-graph-builder";
+
+/// Default duration of the lease, in seconds.
+pub static DEFAULT_LEASE_DURATION_SECS: u64 = 15;
+
+/// Default timeout of requests to the Kubernetes API and to the leader, in seconds.
+pub static DEFAULT_TIMEOUT_SECS: u64 = 30;
+
+/// Directory of the service account credentials mounted in pods.
+static SERVICE_ACCOUNT_DIR: &str = "/var/run/secrets/kubernetes.io/serviceaccount";
+
+lazy_static! {
+    static ref LEADER: IntGauge = IntGauge::new(
+        "coordination_leader",
+        "Whether this replica holds the lease and scrapes the upstream"
+    )
+    .unwrap();
+}
+
+/// Register relevant metrics to a prometheus registry.
+pub fn register_metrics(registry: &prometheus::Registry) -> Fallible<()> {
+    registry.register(Box::new(LEADER.clone()))?;
+    Ok(())
+}
+
+/// Leader election settings.
+#[derive(Clone, Debug, Deserialize, PartialEq, SmartDefault)]
+#[serde(default, deny_unknown_fields)]
+pub struct CoordinationSettings {
+    /// URL of the internal graph endpoint of this replica, as reached by the other replicas.
+    pub advertise_url: String,
+
+    /// Name of the lease.
+    #[default(DEFAULT_LEASE_NAME.to_string())]
+    pub lease_name: String,
+
+    /// Namespace of the lease, by default the namespace of the pod.
+    pub namespace: Option<String>,
+
+    /// Duration of the lease, in seconds.
+    #[default(DEFAULT_LEASE_DURATION_SECS)]
+    pub lease_duration_secs: u64,
+
+    /// URL of the Kubernetes API, by default the in-cluster one.
+    pub api_url: Option<String>,
+
+    /// File with the bearer token for the Kubernetes API, by default the service account one.
+    pub api_token_path: Option<PathBuf>,
+
+    /// PEM file with CA certificates to trust for the Kubernetes API, by default the service account one.
+    pub api_ca_path: Option<PathBuf>,
+
+    /// PEM file with the client certificate presented to the leader.
+    pub client_cert_path: Option<PathBuf>,
+
+    /// PEM file with the PKCS#8 private key of the client certificate.
+    pub client_key_path: Option<PathBuf>,
+
+    /// PEM file with CA certificates to trust for the leader.
+    pub ca_bundle_path: Option<PathBuf>,
+
+    /// Timeout of requests to the Kubernetes API and to the leader, in seconds.
+    #[default(DEFAULT_TIMEOUT_SECS)]
+    pub timeout_secs: u64,
+}
+
+impl CoordinationSettings {
+    /// Validate the settings.
+    pub fn validate(&self) -> Fallible<()> {
+        ensure!(
+            !self.advertise_url.is_empty(),
+            "coordination.advertise_url must be set"
+        );
+        ensure!(
+            !self.lease_name.is_empty(),
+            "coordination.lease_name must not be empty"
+        );
+        ensure!(
+            self.lease_duration_secs > 0,
+            "coordination.lease_duration_secs must be positive"
+        );
+        ensure!(
+            self.client_cert_path.is_some() == self.client_key_path.is_some(),
+            "coordination.client_cert_path and coordination.client_key_path must be set together"
+        );
+        Ok(())
+    }
+}
+
+/// Role of a replica.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Role {
+    /// Scrapes the upstream and publishes the graph.
+    Leader,
+    /// Fetches the graph from the internal endpoint of the leader, at the given URL.
+    Follower(String),
+    /// Waits for its first election round, neither scraping nor fetching.
+    Candidate,
+}
+
+/// Takes part in the leader election, and fetches the graph from the leader.
+#[derive(Clone, Debug)]
+pub struct Coordinator {
+    identity: String,
+    lease_duration: Duration,
+    lease_url: String,
+    api_token_path: PathBuf,
+    api_client: reqwest::Client,
+    leader_client: reqwest::Client,
+    /// Role observed at the last election round, along with its time.
+    observed: Arc<RwLock<Option<(Role, Instant)>>>,
+}
+
+impl Coordinator {
+    /// Create a coordinator, reading the credentials of the Kubernetes API and the leader.
+    pub fn new(settings: &CoordinationSettings) -> Fallible<Self> {
+        let service_account = Path::new(SERVICE_ACCOUNT_DIR);
+        let namespace = match &settings.namespace {
+            Some(namespace) => namespace.clone(),
+            None => std::fs::read_to_string(service_account.join("namespace"))
+                .context("reading the namespace of the pod, set coordination.namespace")?
+                .trim()
+                .to_string(),
+        };
+        let api_url = match &settings.api_url {
+            Some(api_url) => api_url.trim_end_matches('/').to_string(),
+            None => in_cluster_api_url()?,
+        };
+        let timeout = Duration::from_secs(settings.timeout_secs);
+
+        let api_ca_path = settings
+            .api_ca_path
+            .clone()
+            .unwrap_or_else(|| service_account.join("ca.crt"));
+        let mut api_client = commons::outbound::client_builder().timeout(timeout);
+        for certificate in registry::read_ca_bundle(&api_ca_path)? {
+            api_client = api_client.add_root_certificate(certificate);
+        }
+
+        let mut leader_client = commons::outbound::client_builder().timeout(timeout);
+        if let Some(ca_bundle_path) = &settings.ca_bundle_path {
+            for certificate in registry::read_ca_bundle(ca_bundle_path)? {
+                leader_client = leader_client.add_root_certificate(certificate);
+            }
+        }
+        if let (Some(cert_path), Some(key_path)) =
+            (&settings.client_cert_path, &settings.client_key_path)
+        {
+            leader_client = leader_client.identity(registry::read_identity(cert_path, key_path)?);
+        }
+
+        Ok(Self {
+            identity: settings.advertise_url.clone(),
+            lease_duration: Duration::from_secs(settings.lease_duration_secs),
+            lease_url: format!(
+                "{}/apis/coordination.k8s.io/v1/namespaces/{}/leases/{}",
+                api_url, namespace, settings.lease_name
+            ),
+            api_token_path: settings
+                .api_token_path
+                .clone()
+                .unwrap_or_else(|| service_account.join("token")),
+            api_client: api_client.build().context("Building reqwest client")?,
+            leader_client: leader_client.build().context("Building reqwest client")?,
+            observed: Default::default(),
+        })
+    }
+
+    /// Returns the role of this replica.
+    ///
+    /// A replica is a candidate until its first election round, and one which
+    /// didn't observe the lease for a whole lease duration since acts as leader.
+    pub fn role(&self) -> Role {
+        match &*self.observed.read() {
+            None => Role::Candidate,
+            Some((role, observed)) if observed.elapsed() < self.lease_duration => role.clone(),
+            Some(_) => Role::Leader,
+        }
+    }
+
+    /// Take part in the leader election until the process shuts down.
+    ///
+    /// A scrape, or a fetch from the leader, is triggered as soon as the role
+    /// of this replica changes.
+    pub async fn run(self, state: State) {
+        let renew_interval = self.lease_duration / 3;
+        while !state.is_shutting_down() {
+            match self.elect().await {
+                Ok(role) => {
+                    let previous = self
+                        .observed
+                        .write()
+                        .replace((role.clone(), Instant::now()));
+                    if previous.map(|(previous, _)| previous).as_ref() != Some(&role) {
+                        match &role {
+                            Role::Leader => {
+                                info!("acquired the lease, scraping the upstream");
+                                state.trigger_rescrape();
+                            }
+                            Role::Follower(leader) => {
+                                info!("following the leader at {}", leader);
+                                state.trigger_rescrape();
+                            }
+                            Role::Candidate => {}
+                        }
+                    }
+                    LEADER.set((role == Role::Leader) as i64);
+                }
+                Err(err) => warn!("failed to take part in the leader election: {:#}", err),
+            }
+            tokio::time::sleep(renew_interval).await;
+        }
+    }
+
+    /// Run an election round: create, renew or take over the lease if
+    /// possible, or follow its holder.
+    async fn elect(&self) -> Fallible<Role> {
+        let token = std::fs::read_to_string(&self.api_token_path).context(format!(
+            "reading the Kubernetes API token from {:?}",
+            self.api_token_path
+        ))?;
+        let token = token.trim();
+
+        let response = self
+            .api_client
+            .get(&self.lease_url)
+            .bearer_auth(token)
+            .send()
+            .await
+            .context("getting the lease")?;
+        let (request, mut lease) = if response.status() == StatusCode::NOT_FOUND {
+            let (collection_url, name) = self
+                .lease_url
+                .rsplit_once('/')
+                .ok_or_else(|| format_err!("invalid lease URL {}", self.lease_url))?;
+            let lease = json!({
+                "apiVersion": "coordination.k8s.io/v1",
+                "kind": "Lease",
+                "metadata": {"name": name},
+                "spec": {},
+            });
+            (self.api_client.post(collection_url), lease)
+        } else {
+            ensure!(
+                response.status().is_success(),
+                "getting the lease: {}",
+                response.status()
+            );
+            let lease: Value = response.json().await.context("parsing the lease")?;
+            (self.api_client.put(&self.lease_url), lease)
+        };
+
+        let role = decide(
+            &mut lease["spec"],
+            &self.identity,
+            self.lease_duration,
+            Utc::now(),
+        );
+        if role == Role::Leader {
+            // Updates are rejected if the lease changed since it was read.
+            let response = request
+                .bearer_auth(token)
+                .json(&lease)
+                .send()
+                .await
+                .context("updating the lease")?;
+            ensure!(
+                response.status().is_success(),
+                "updating the lease: {}",
+                response.status()
+            );
+        }
+        Ok(role)
+    }
+
+    /// Fetch the graph built by the leader from its internal endpoint.
+    pub async fn fetch(&self, leader: &str) -> Fallible<InternalIO> {
+        let response = self
+            .leader_client
+            .get(leader)
+            .header(ACCEPT, HeaderValue::from_static(artifact::CONTENT_TYPE))
+            .send()
+            .await
+            .context(format!("fetching the graph from the leader at {}", leader))?;
+        ensure!(
+            response.status().is_success(),
+            "fetching the graph from the leader at {}: {}",
+            leader,
+            response.status()
+        );
+
+        let provenance = Provenance::from_headers(response.headers());
+        let body = response.bytes().await?;
+        let artifact = artifact::decode(&body)?;
+        debug!(
+            "fetched the graph of revision {} from the leader",
+            artifact.revision
+        );
+
+        let mut parameters = Default::default();
+        if let Some(provenance) = provenance {
+            provenance.insert_into(&mut parameters);
+        }
+        Ok(InternalIO {
+            graph: artifact.graph,
+            parameters,
+        })
+    }
+}
+
+/// Returns the URL of the Kubernetes API, as advertised to pods.
+fn in_cluster_api_url() -> Fallible<String> {
+    let host = std::env::var("KUBERNETES_SERVICE_HOST")
+        .context("KUBERNETES_SERVICE_HOST is not set, set coordination.api_url")?;
+    let port = std::env::var("KUBERNETES_SERVICE_PORT").unwrap_or_else(|_| "443".to_string());
+    if host.contains(':') {
+        Ok(format!("https://[{}]:{}", host, port))
+    } else {
+        Ok(format!("https://{}:{}", host, port))
+    }
+}
+
+/// Decide the role of this replica from the spec of the lease, updating the
+/// spec if this replica keeps or takes the lease.
+///
+/// The lease is taken if it has no holder, or if its holder didn't renew it in time.
+fn decide(spec: &mut Value, identity: &str, lease_duration: Duration, now: DateTime<Utc>) -> Role {
+    let holder = spec["holderIdentity"]
+        .as_str()
+        .unwrap_or_default()
+        .to_string();
+    let renewed = spec["renewTime"]
+        .as_str()
+        .and_then(|time| DateTime::parse_from_rfc3339(time).ok())
+        .map(|time| time.with_timezone(&Utc));
+    let duration = spec["leaseDurationSeconds"]
+        .as_i64()
+        .map(chrono::Duration::seconds)
+        .unwrap_or_else(|| chrono::Duration::seconds(lease_duration.as_secs() as i64));
+    let expired = match renewed {
+        Some(renewed) => renewed + duration < now,
+        None => true,
+    };
+    if !holder.is_empty() && holder != identity && !expired {
+        return Role::Follower(holder);
+    }
+
+    // Kubernetes expects times with microseconds.
+    let now = now.format("%Y-%m-%dT%H:%M:%S%.6fZ").to_string();
+    if holder != identity {
+        let transitions = spec["leaseTransitions"].as_i64().unwrap_or_default();
+        spec["holderIdentity"] = identity.into();
+        spec["acquireTime"] = now.clone().into();
+        spec["leaseTransitions"] = (transitions + 1).into();
+    }
+    spec["renewTime"] = now.into();
+    spec["leaseDurationSeconds"] = lease_duration.as_secs().into();
+    Role::Leader
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decide_role() {
+        let now = Utc::now();
+        let lease_duration = Duration::from_secs(15);
+        let me = "https://10.0.0.1:8080/internal/v1/graph";
+        let other = "https://10.0.0.2:8080/internal/v1/graph";
+
+        // A new lease is taken.
+        let mut spec = json!({});
+        assert_eq!(decide(&mut spec, me, lease_duration, now), Role::Leader);
+        assert_eq!(spec["holderIdentity"], me);
+        assert_eq!(spec["leaseDurationSeconds"], 15);
+        assert_eq!(spec["leaseTransitions"], 1);
+
+        // A lease held by another replica is followed, until it expires.
+        let mut spec = json!({
+            "holderIdentity": other,
+            "renewTime": (now - chrono::Duration::seconds(10)).to_rfc3339(),
+            "leaseDurationSeconds": 15,
+            "leaseTransitions": 3,
+        });
+        assert_eq!(
+            decide(&mut spec, me, lease_duration, now),
+            Role::Follower(other.to_string())
+        );
+        assert_eq!(spec["holderIdentity"], other);
+        assert_eq!(
+            decide(
+                &mut spec,
+                me,
+                lease_duration,
+                now + chrono::Duration::seconds(10)
+            ),
+            Role::Leader
+        );
+        assert_eq!(spec["holderIdentity"], me);
+        assert_eq!(spec["leaseTransitions"], 4);
+
+        // The holder renews its lease.
+        let acquired = spec["acquireTime"].clone();
+        let later = now + chrono::Duration::seconds(15);
+        assert_eq!(decide(&mut spec, me, lease_duration, later), Role::Leader);
+        assert_eq!(spec["acquireTime"], acquired);
+        assert_eq!(spec["leaseTransitions"], 4);
+        let renewed = DateTime::parse_from_rfc3339(spec["renewTime"].as_str().unwrap()).unwrap();
+        assert_eq!(renewed.timestamp(), later.timestamp());
+    }
+
+    #[test]
+    fn validate_settings() {
+        let settings = CoordinationSettings {
+            advertise_url: "https://10.0.0.1:8080/internal/v1/graph".to_string(),
+            ..Default::default()
+        };
+        assert!(settings.validate().is_ok());
+
+        assert!(CoordinationSettings::default().validate().is_err());
+        assert!(CoordinationSettings {
+            client_cert_path: Some("/etc/tls/tls.crt".into()),
+            ..settings.clone()
+        }
+        .validate()
+        .is_err());
+        assert!(CoordinationSettings {
+            lease_duration_secs: 0,
+            ..settings
+        }
+        .validate()
+        .is_err());
+    }
+
+    #[test]
+    fn candidate_until_elected() {
+        let coordinator = Coordinator {
+            identity: "https://10.0.0.1:8080/internal/v1/graph".to_string(),
+            lease_duration: Duration::from_secs(15),
+            lease_url: "https://kubernetes.default.svc/leases/graph-builder".to_string(),
+            api_token_path: "/var/run/secrets/token".into(),
+            api_client: reqwest::Client::new(),
+            leader_client: reqwest::Client::new(),
+            observed: Default::default(),
+        };
+        assert_eq!(coordinator.role(), Role::Candidate);
+
+        let leader = "https://10.0.0.2:8080/internal/v1/graph".to_string();
+        *coordinator.observed.write() = Some((Role::Follower(leader.clone()), Instant::now()));
+        assert_eq!(coordinator.role(), Role::Follower(leader.clone()));
+
+        *coordinator.observed.write() = Some((
+            Role::Follower(leader),
+            Instant::now() - Duration::from_secs(30),
+        ));
+        assert_eq!(coordinator.role(), Role::Leader);
+    }
+}
@@ -0,0 +1,283 @@
+//! Debug information about the scraper and its plugins.
+//!
+//! The scrape loop records the outcome of each scrape and plugin run, along
+//! with a summary of the published graph. The status service exposes it
+//! under `/debug`.
+//!
+//! The input of the graph-data parser is kept as well, so that candidate
+//! graph-data can be applied to the scraped releases.
+
+use async_trait::async_trait;
+use cincinnati::plugins::internal::openshift_secondary_metadata_parser::OpenshiftSecondaryMetadataParserPlugin;
+use cincinnati::plugins::{BoxedPlugin, InternalIO, Plugin, PluginIO};
+use cincinnati::Graph;
+use commons::Fallible;
+use parking_lot::RwLock;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Outcome of the last scrapes.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct ScrapeStatus {
+    /// UTC timestamp of the end of the last scrape, successful or not.
+    pub last_scrape_timestamp: Option<i64>,
+    /// UTC timestamp of the end of the last successful scrape.
+    pub last_success_timestamp: Option<i64>,
+    /// Error of the last scrape, if it failed.
+    pub last_error: Option<String>,
+    /// Maximum expected time between the end of two scrapes, in seconds.
+    pub max_interval_secs: Option<u64>,
+}
+
+/// Outcome of the last runs of a plugin.
+#[derive(Clone, Debug, Serialize)]
+pub struct PluginStatus {
+    /// Plugin name.
+    pub name: &'static str,
+    /// UTC timestamp of the last successful run.
+    pub last_success_timestamp: Option<i64>,
+    /// UTC timestamp of the last failed run.
+    pub last_error_timestamp: Option<i64>,
+    /// Error of the last failed run.
+    pub last_error: Option<String>,
+}
+
+/// An edge which is only recommended under conditions.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct BlockedEdge {
+    /// Source version.
+    pub from: String,
+    /// Target version.
+    pub to: String,
+    /// Names of the risks which block the edge.
+    pub risks: Vec<String>,
+}
+
+/// Summary of the published graph.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct GraphStatus {
+    /// Number of releases.
+    pub releases: u64,
+    /// Number of edges.
+    pub edges: u64,
+    /// Edges blocked by conditional update risks.
+    pub blocked_edges: Vec<BlockedEdge>,
+}
+
+impl GraphStatus {
+    /// Summarize the given graph.
+    pub fn of(graph: &Graph) -> Self {
+        Self {
+            releases: graph.releases_count(),
+            edges: graph.edges_count(),
+            blocked_edges: graph
+                .conditional_edges()
+                .into_iter()
+                .map(|(from, to, risks)| BlockedEdge {
+                    from: from.to_string(),
+                    to: to.to_string(),
+                    risks: risks.into_iter().map(str::to_string).collect(),
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Debug information, as served by the status service.
+#[derive(Clone, Debug, Serialize)]
+pub struct DebugInfo {
+    /// Outcome of the last scrapes.
+    pub scrape: ScrapeStatus,
+    /// Outcome of the last plugin runs, in execution order.
+    pub plugins: Vec<PluginStatus>,
+    /// Summary of the published graph.
+    pub graph: GraphStatus,
+}
+
+/// Shared recorder of debug information.
+#[derive(Clone, Default)]
+pub struct DebugState {
+    scrape: Arc<RwLock<ScrapeStatus>>,
+    plugins: Arc<RwLock<Vec<PluginStatus>>>,
+    graph: Arc<RwLock<GraphStatus>>,
+    graph_data_input: Arc<RwLock<Option<InternalIO>>>,
+}
+
+impl DebugState {
+    /// Wrap the plugins so that the outcome of their runs is recorded.
+    ///
+    /// This replaces the status of previously tracked plugins.
+    pub fn track(&self, plugins: &[Arc<BoxedPlugin>]) -> Vec<Arc<BoxedPlugin>> {
+        *self.plugins.write() = plugins
+            .iter()
+            .map(|plugin| PluginStatus {
+                name: plugin.get_name(),
+                last_success_timestamp: None,
+                last_error_timestamp: None,
+                last_error: None,
+            })
+            .collect();
+
+        plugins
+            .iter()
+            .enumerate()
+            .map(|(index, inner)| {
+                let capture_input =
+                    if inner.get_name() == OpenshiftSecondaryMetadataParserPlugin::PLUGIN_NAME {
+                        Some(self.graph_data_input.clone())
+                    } else {
+                        None
+                    };
+
+                let tracked: BoxedPlugin = Box::new(TrackedPlugin {
+                    inner: inner.clone(),
+                    index,
+                    statuses: self.plugins.clone(),
+                    capture_input,
+                });
+                Arc::new(tracked)
+            })
+            .collect()
+    }
+
+    /// Record the outcome of a scrape.
+    pub fn record_scrape(&self, error: Option<String>) {
+        let now = chrono::Utc::now().timestamp();
+        let mut scrape = self.scrape.write();
+        scrape.last_scrape_timestamp = Some(now);
+        if error.is_none() {
+            scrape.last_success_timestamp = Some(now);
+        }
+        scrape.last_error = error;
+    }
+
+    /// Returns the UTC timestamp of the end of the last successful scrape.
+    pub fn last_success_timestamp(&self) -> Option<i64> {
+        self.scrape.read().last_success_timestamp
+    }
+
+    /// Record the maximum expected time between the end of two scrapes.
+    pub fn record_scrape_interval(&self, interval: Duration) {
+        self.scrape.write().max_interval_secs = Some(interval.as_secs());
+    }
+
+    /// Record the summary of a newly published graph.
+    pub fn record_graph(&self, graph: &Graph) {
+        *self.graph.write() = GraphStatus::of(graph);
+    }
+
+    /// Returns the input of the last graph-data parser run.
+    ///
+    /// This holds the scraped releases, before any graph-data is applied.
+    pub fn graph_data_input(&self) -> Option<InternalIO> {
+        self.graph_data_input.read().clone()
+    }
+
+    /// Returns the current debug information.
+    pub fn snapshot(&self) -> DebugInfo {
+        DebugInfo {
+            scrape: self.scrape.read().clone(),
+            plugins: self.plugins.read().clone(),
+            graph: self.graph.read().clone(),
+        }
+    }
+}
+
+/// Plugin wrapper which records the outcome of each run.
+#[derive(Debug)]
+struct TrackedPlugin {
+    inner: Arc<BoxedPlugin>,
+    index: usize,
+    statuses: Arc<RwLock<Vec<PluginStatus>>>,
+    /// Where to keep a copy of the input, if set.
+    capture_input: Option<Arc<RwLock<Option<InternalIO>>>>,
+}
+
+#[async_trait]
+impl Plugin<PluginIO> for TrackedPlugin {
+    async fn run(&self, io: PluginIO) -> Fallible<PluginIO> {
+        if let (Some(capture_input), PluginIO::InternalIO(internal_io)) = (&self.capture_input, &io)
+        {
+            *capture_input.write() = Some(internal_io.clone());
+        }
+
+        let result = self.inner.run(io).await;
+
+        let now = chrono::Utc::now().timestamp();
+        if let Some(status) = self.statuses.write().get_mut(self.index) {
+            match &result {
+                Ok(_) => status.last_success_timestamp = Some(now),
+                Err(e) => {
+                    status.last_error_timestamp = Some(now);
+                    status.last_error = Some(format!("{:#}", e));
+                }
+            }
+        }
+
+        result
+    }
+
+    fn get_name(&self) -> &'static str {
+        self.inner.get_name()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cincinnati::plugins::prelude::*;
+    use cincinnati::plugins::InternalIO;
+
+    #[test]
+    fn graph_status() {
+        let graph = cincinnati::testing::generate_graph(true, false);
+        let status = GraphStatus::of(&graph);
+
+        assert_eq!(status.releases, graph.releases_count());
+        assert_eq!(status.edges, graph.edges_count());
+        assert_eq!(
+            status.blocked_edges,
+            vec![BlockedEdge {
+                from: "1.0.0".to_string(),
+                to: "2.0.0".to_string(),
+                risks: vec!["BrokenUpdates".to_string()],
+            }]
+        );
+    }
+
+    #[test]
+    fn tracks_plugin_runs() -> Fallible<()> {
+        let debug = DebugState::default();
+        let plugins: Vec<BoxedPlugin> =
+            new_plugins!(InternalPluginWrapper(EdgeAddRemovePlugin::default()));
+        let plugins: Vec<Arc<BoxedPlugin>> = plugins.into_iter().map(Arc::new).collect();
+        let tracked = debug.track(&plugins);
+        assert_eq!(tracked[0].get_name(), EdgeAddRemovePlugin::PLUGIN_NAME);
+
+        let rt = commons::testing::init_runtime()?;
+        rt.block_on(tracked[0].run(PluginIO::InternalIO(InternalIO {
+            graph: Default::default(),
+            parameters: Default::default(),
+        })))?;
+        debug.record_scrape(None);
+
+        let info = debug.snapshot();
+        assert_eq!(info.plugins.len(), 1);
+        assert!(info.plugins[0].last_success_timestamp.is_some());
+        assert!(info.plugins[0].last_error.is_none());
+        assert_eq!(
+            info.scrape.last_scrape_timestamp,
+            info.scrape.last_success_timestamp
+        );
+
+        debug.record_scrape(Some("upstream unavailable".to_string()));
+        let info = debug.snapshot();
+        assert_eq!(
+            info.scrape.last_error.as_deref(),
+            Some("upstream unavailable")
+        );
+        assert!(info.scrape.last_success_timestamp.is_some());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,257 @@
+//! Stream of the graph publications.
+//!
+//! Controllers subscribe to a server-sent events stream which emits an event
+//! whenever a new graph is published, so that they can fetch it right away
+//! instead of polling. Each event identifies the graph and summarizes its
+//! changes. Event IDs are the hashes of the graph content rather than the
+//! revisions, which are local to a process, so that a client reconnecting to
+//! another replica, or after a restart, and sending `Last-Event-ID` is only
+//! sent the latest graph if it missed it.
+
+use actix_web::web::Bytes;
+use cincinnati::diff::GraphDiff;
+use cincinnati::provenance::Provenance;
+use futures::Stream;
+use parking_lot::RwLock;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast::{self, error::RecvError};
+
+/// Media type of the event stream.
+pub static CONTENT_TYPE: &str = "text/event-stream";
+
+/// Number of events buffered for each subscriber. Slower subscribers skip the older events.
+pub static CHANNEL_CAPACITY: usize = 16;
+
+/// Maximum number of concurrent subscribers.
+pub static MAX_SUBSCRIBERS: usize = 1024;
+
+/// Seconds after which rejected subscribers may retry.
+pub static RETRY_AFTER_SECS: u64 = 30;
+
+/// Interval between the comments keeping idle streams open through proxies.
+pub static KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(15);
+
+/// Number of changes of a publication, by kind.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct ChangeSummary {
+    pub releases_added: usize,
+    pub releases_removed: usize,
+    pub edges_added: usize,
+    pub edges_removed: usize,
+    pub conditional_edges_added: usize,
+    pub conditional_edges_removed: usize,
+}
+
+impl From<&GraphDiff> for ChangeSummary {
+    fn from(diff: &GraphDiff) -> Self {
+        Self {
+            releases_added: diff.releases_added.len(),
+            releases_removed: diff.releases_removed.len(),
+            edges_added: diff.edges_added.len(),
+            edges_removed: diff.edges_removed.len(),
+            conditional_edges_added: diff.conditional_edges_added.len(),
+            conditional_edges_removed: diff.conditional_edges_removed.len(),
+        }
+    }
+}
+
+/// Publication of a new graph.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct GraphEvent {
+    /// Revision of the graph, local to the graph-builder process.
+    pub revision: u64,
+    /// Hash of the graph content, as `sha256:<hex>` and sent in the `ETag` of graph responses.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hash: Option<String>,
+    /// Publication time, in RFC 3339 format.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub published: Option<String>,
+    /// Changes compared to the previous revision.
+    pub changes: ChangeSummary,
+}
+
+impl GraphEvent {
+    /// Returns the ID of the event: the graph hash, or its revision if unknown.
+    pub fn id(&self) -> String {
+        self.hash
+            .clone()
+            .unwrap_or_else(|| self.revision.to_string())
+    }
+
+    /// Describe the publication of the graph with `provenance`.
+    pub fn new(revision: u64, provenance: &Provenance, diff: &GraphDiff) -> Self {
+        Self {
+            revision,
+            hash: provenance.hash.clone(),
+            published: provenance.generated.clone(),
+            changes: diff.into(),
+        }
+    }
+
+    /// Format the event as a server-sent event.
+    fn to_sse(&self) -> Bytes {
+        let data = serde_json::to_string(self).unwrap_or_else(|_| "{}".to_string());
+        Bytes::from(format!(
+            "id: {}\nevent: graph\ndata: {}\n\n",
+            self.id(),
+            data
+        ))
+    }
+}
+
+/// Broadcaster of the graph publications to the stream subscribers.
+#[derive(Clone, Debug)]
+pub struct GraphEvents {
+    sender: broadcast::Sender<Arc<GraphEvent>>,
+    /// Latest event, sent to new subscribers which missed it.
+    latest: Arc<RwLock<Option<Arc<GraphEvent>>>>,
+}
+
+impl Default for GraphEvents {
+    fn default() -> Self {
+        Self {
+            sender: broadcast::channel(CHANNEL_CAPACITY).0,
+            latest: Default::default(),
+        }
+    }
+}
+
+impl GraphEvents {
+    /// Send an event to all subscribers.
+    pub fn publish(&self, event: GraphEvent) {
+        let event = Arc::new(event);
+        *self.latest.write() = Some(event.clone());
+        // Failing only if there are no subscribers.
+        let _ = self.sender.send(event);
+    }
+
+    /// Returns the number of current subscribers.
+    pub fn subscribers(&self) -> usize {
+        self.sender.receiver_count()
+    }
+
+    /// Subscribe to the events, as a stream of server-sent events.
+    ///
+    /// The latest event is sent first, unless its ID is `last_event_id`.
+    /// Comments are sent every `keep_alive` while no graph is published.
+    pub fn subscribe(
+        &self,
+        last_event_id: Option<String>,
+        keep_alive: Duration,
+    ) -> impl Stream<Item = Result<Bytes, actix_web::Error>> {
+        // Subscribing first, so that no event goes missing in between.
+        let receiver = self.sender.subscribe();
+        let latest = self
+            .latest
+            .read()
+            .clone()
+            .filter(|latest| Some(latest.id()) != last_event_id);
+
+        futures::stream::unfold(
+            (receiver, latest, last_event_id),
+            move |(mut receiver, pending, mut last_sent)| async move {
+                if let Some(event) = pending {
+                    let sse = event.to_sse();
+                    return Some((Ok(sse), (receiver, None, Some(event.id()))));
+                }
+
+                loop {
+                    match tokio::time::timeout(keep_alive, receiver.recv()).await {
+                        // The latest event may also be received once subscribed.
+                        Ok(Ok(event)) if last_sent.as_deref() == Some(event.id().as_str()) => {}
+                        Ok(Ok(event)) => {
+                            last_sent = Some(event.id());
+                            return Some((Ok(event.to_sse()), (receiver, None, last_sent)));
+                        }
+                        Ok(Err(RecvError::Lagged(skipped))) => {
+                            debug!("graph event subscriber skipped {} events", skipped);
+                        }
+                        Ok(Err(RecvError::Closed)) => return None,
+                        Err(_) => {
+                            let comment = Bytes::from_static(b": keep-alive\n\n");
+                            return Some((Ok(comment), (receiver, None, last_sent)));
+                        }
+                    }
+                }
+            },
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::StreamExt;
+
+    fn event(revision: u64) -> GraphEvent {
+        GraphEvent {
+            revision,
+            hash: Some(format!("sha256:{}", revision)),
+            published: None,
+            changes: ChangeSummary {
+                releases_added: 1,
+                ..Default::default()
+            },
+        }
+    }
+
+    #[test]
+    fn stream_events() {
+        let runtime = commons::testing::init_runtime().unwrap();
+        let events = GraphEvents::default();
+        events.publish(event(1));
+
+        runtime.block_on(async {
+            let mut stream = Box::pin(events.subscribe(None, Duration::from_secs(60)));
+            let mut up_to_date = Box::pin(
+                events.subscribe(Some("sha256:1".to_string()), Duration::from_millis(10)),
+            );
+            assert_eq!(events.subscribers(), 2);
+
+            let first = stream.next().await.unwrap().unwrap();
+            assert_eq!(
+                first,
+                Bytes::from(
+                    "id: sha256:1\nevent: graph\ndata: {\"revision\":1,\"hash\":\"sha256:1\",\"changes\":{\"releases_added\":1,\"releases_removed\":0,\"edges_added\":0,\"edges_removed\":0,\"conditional_edges_added\":0,\"conditional_edges_removed\":0}}\n\n"
+                )
+            );
+            assert_eq!(
+                up_to_date.next().await.unwrap().unwrap(),
+                Bytes::from_static(b": keep-alive\n\n")
+            );
+
+            events.publish(event(2));
+            let second = stream.next().await.unwrap().unwrap();
+            assert!(second.starts_with(b"id: sha256:2\nevent: graph\n"));
+            let second = up_to_date.next().await.unwrap().unwrap();
+            assert!(second.starts_with(b"id: sha256:2\nevent: graph\n"));
+        });
+    }
+
+    #[test]
+    fn ids_survive_restarts() {
+        let runtime = commons::testing::init_runtime().unwrap();
+        let events = GraphEvents::default();
+        // A restarted replica publishes the same graph under another revision.
+        events.publish(GraphEvent {
+            revision: 1,
+            ..event(7)
+        });
+
+        runtime.block_on(async {
+            let mut stream =
+                Box::pin(events.subscribe(Some("sha256:7".to_string()), Duration::from_millis(10)));
+            assert_eq!(
+                stream.next().await.unwrap().unwrap(),
+                Bytes::from_static(b": keep-alive\n\n")
+            );
+
+            // An older revision elsewhere is still a change.
+            let mut stream =
+                Box::pin(events.subscribe(Some("sha256:9".to_string()), Duration::from_millis(10)));
+            let first = stream.next().await.unwrap().unwrap();
+            assert!(first.starts_with(b"id: sha256:7\nevent: graph\n"));
+        });
+    }
+}
@@ -0,0 +1,204 @@
+//! Publication freeze windows.
+//!
+//! During a freeze window the upstream is still scraped, but new graphs are not
+//! published: clients keep being served the graph published before the window,
+//! along with a header telling until when it is frozen. Windows are either
+//! one-off, between two points in time, or recurring, starting on a cron
+//! schedule and lasting for a fixed duration. The graph is never frozen before
+//! the first one was published: a replica restarted during a freeze publishes
+//! the graph it kept from before the restart, if any, and the scraped one
+//! otherwise.
+
+use chrono::{DateTime, Duration, Utc};
+use cincinnati::{artifact, Graph};
+use commons::cron::Schedule;
+use commons::prelude_errors::*;
+use std::path::Path;
+
+/// Header carrying the end of the freeze of the served graph, in RFC 3339 format.
+pub static FROZEN_UNTIL_HEADER: &str = "cincinnati-graph-frozen-until";
+
+/// Maximum duration of recurring windows, bounding the search for their start.
+pub static MAX_RECURRING_DURATION_SECS: u64 = 31 * 24 * 60 * 60;
+
+/// A freeze window, as configured.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct FreezeWindow {
+    /// Reason of the freeze, for logging.
+    #[serde(default)]
+    pub reason: Option<String>,
+
+    /// Start of a one-off window, in RFC 3339 format.
+    #[serde(default)]
+    pub start: Option<String>,
+
+    /// End of a one-off window, in RFC 3339 format.
+    #[serde(default)]
+    pub end: Option<String>,
+
+    /// Cron schedule of the start of recurring windows, in UTC.
+    #[serde(default)]
+    pub schedule: Option<String>,
+
+    /// Duration of recurring windows, in seconds.
+    #[serde(default)]
+    pub duration_secs: Option<u64>,
+}
+
+/// An ongoing freeze.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Freeze {
+    /// End of the freeze.
+    pub until: DateTime<Utc>,
+    /// Reason of the freeze, if given.
+    pub reason: Option<String>,
+}
+
+impl FreezeWindow {
+    /// Validate the window configuration.
+    pub fn validate(&self) -> Fallible<()> {
+        self.active_until(Utc::now()).map(|_| ())
+    }
+
+    /// Returns the end of the window if it contains the given time.
+    fn active_until(&self, now: DateTime<Utc>) -> Fallible<Option<DateTime<Utc>>> {
+        match (&self.start, &self.end, &self.schedule, self.duration_secs) {
+            (Some(start), Some(end), None, None) => {
+                let start = parse_time(start)?;
+                let end = parse_time(end)?;
+                ensure!(start < end, "freeze window ends before it starts");
+                Ok(Some(end).filter(|_| start <= now && now < end))
+            }
+            (None, None, Some(schedule), Some(duration_secs)) => {
+                ensure!(
+                    duration_secs > 0 && duration_secs <= MAX_RECURRING_DURATION_SECS,
+                    "freeze window duration_secs must be between 1 and {}",
+                    MAX_RECURRING_DURATION_SECS
+                );
+                let schedule: Schedule = schedule.parse()?;
+                Ok(schedule.window_end(now, Duration::seconds(duration_secs as i64)))
+            }
+            _ => bail!("freeze windows need either start and end, or schedule and duration_secs"),
+        }
+    }
+}
+
+/// Returns the ongoing freeze of the windows containing the given time, lasting until the latest of their ends.
+pub fn active(windows: &[FreezeWindow], now: DateTime<Utc>) -> Option<Freeze> {
+    windows
+        .iter()
+        .filter_map(|window| match window.active_until(now) {
+            Ok(until) => until.map(|until| Freeze {
+                until,
+                reason: window.reason.clone(),
+            }),
+            Err(e) => {
+                warn!("Ignoring invalid freeze window: {}", e);
+                None
+            }
+        })
+        .max_by_key(|freeze| freeze.until)
+}
+
+/// Read the graph kept from before a restart, if any.
+pub fn read_state(path: &Path) -> Fallible<Option<Graph>> {
+    match std::fs::read(path) {
+        Ok(content) => artifact::decode(&content)
+            .map(|artifact| Some(artifact.graph))
+            .context(format!("parsing {}", path.display())),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e).context(format!("reading {}", path.display())),
+    }
+}
+
+/// Atomically replace the graph kept for restarts with the published one.
+pub fn write_state(path: &Path, revision: u64, graph: &Graph) -> Fallible<()> {
+    let encoded = artifact::encode(revision, Utc::now().timestamp(), graph)?;
+    let tmp_path = path.with_extension("tmp");
+    std::fs::write(&tmp_path, encoded).context(format!("writing {}", tmp_path.display()))?;
+    std::fs::rename(&tmp_path, path).context(format!("replacing {}", path.display()))?;
+    Ok(())
+}
+
+fn parse_time(time: &str) -> Fallible<DateTime<Utc>> {
+    Ok(DateTime::parse_from_rfc3339(time)
+        .context(format!("invalid freeze window time '{}'", time))?
+        .with_timezone(&Utc))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn time(time: &str) -> DateTime<Utc> {
+        parse_time(time).unwrap()
+    }
+
+    #[test]
+    fn active_windows() {
+        let windows = vec![
+            FreezeWindow {
+                reason: Some("holidays".to_string()),
+                start: Some("2022-12-20T00:00:00Z".to_string()),
+                end: Some("2023-01-03T00:00:00Z".to_string()),
+                schedule: None,
+                duration_secs: None,
+            },
+            // From Friday 18:00 to Monday 06:00.
+            FreezeWindow {
+                reason: None,
+                start: None,
+                end: None,
+                schedule: Some("0 18 * * 5".to_string()),
+                duration_secs: Some(60 * 60 * 60),
+            },
+        ];
+        for window in &windows {
+            window.validate().unwrap();
+        }
+
+        assert_eq!(active(&windows, time("2022-10-06T12:00:00Z")), None);
+        assert_eq!(
+            active(&windows, time("2022-10-08T12:00:00Z")),
+            Some(Freeze {
+                until: time("2022-10-10T06:00:00Z"),
+                reason: None,
+            })
+        );
+        assert_eq!(active(&windows, time("2022-10-10T06:00:00Z")), None);
+        // 2022-12-30 is a Friday, the one-off window ends later.
+        assert_eq!(
+            active(&windows, time("2022-12-31T12:00:00Z")),
+            Some(Freeze {
+                until: time("2023-01-03T00:00:00Z"),
+                reason: Some("holidays".to_string()),
+            })
+        );
+
+        let invalid = FreezeWindow {
+            reason: None,
+            start: Some("2022-12-20T00:00:00Z".to_string()),
+            end: None,
+            schedule: Some("0 18 * * 5".to_string()),
+            duration_secs: None,
+        };
+        assert!(invalid.validate().is_err());
+    }
+
+    #[test]
+    fn state_roundtrip() -> Fallible<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("published.cbor");
+        assert!(read_state(&path)?.is_none());
+
+        let graph = cincinnati::testing::generate_graph(true, false);
+        write_state(&path, 3, &graph)?;
+        assert_eq!(read_state(&path)?, Some(graph));
+
+        std::fs::write(&path, b"garbage")?;
+        assert!(read_state(&path).is_err());
+
+        Ok(())
+    }
+}
@@ -12,58 +12,143 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use crate::audit;
+use crate::budget;
 use crate::built_info;
+use crate::changelog;
 use crate::config;
+use crate::coordination::{self, Coordinator, Role};
+use crate::debug;
+use crate::events;
+use crate::freeze;
+use crate::limits::{self, LimitExceeded, Limits};
+use crate::notify;
+use crate::reload;
+use crate::secondary_metadata::{self, SecondaryMetadata};
+use crate::snapshots;
+use crate::validation;
 use actix_web::http::header;
-use actix_web::{HttpRequest, HttpResponse};
+use actix_web::{HttpRequest, HttpResponse, HttpResponseBuilder};
+use arc_swap::ArcSwap;
+use bytes::Bytes;
+use chrono::TimeZone;
+use cincinnati::artifact;
+use cincinnati::change_thresholds::OVERRIDE_PARAM_KEY;
+use cincinnati::metadata_keys::MetadataKeys;
+use cincinnati::plugins::internal::arch_filter;
+use cincinnati::plugins::internal::release_cosign_verify::QUARANTINED_RELEASES_PARAM_KEY;
+use cincinnati::plugins::internal::release_scrape_dockerv2::plugin::SERVING_SOURCE_PARAM_KEY;
 use cincinnati::plugins::prelude::*;
-use cincinnati::CONTENT_TYPE;
+use cincinnati::provenance::{self, Provenance};
+use cincinnati::v2::{self, GraphV2};
+use cincinnati::{Graph, CONTENT_TYPE};
+use commons::compression::{CompressionSettings, PrecompressedBody};
 use commons::metrics::HasRegistry;
+use commons::openapi::{ApiRoutes, Endpoint, Param, QueryParam};
+use commons::selftest::SelfTest;
 use commons::tracing::get_tracer;
-use commons::{Fallible, GraphError};
+use commons::{Fallible, GraphError, ProblemDetails};
+use futures::future;
+use futures::StreamExt;
 use lazy_static;
-use opentelemetry::trace::{mark_span_as_active, Tracer};
+use opentelemetry::trace::{get_active_span, mark_span_as_active, StatusCode, Tracer};
+use opentelemetry::Key;
 pub use parking_lot::RwLock;
+use parking_lot::{Condvar, Mutex};
 use prometheus::{
-    self, histogram_opts, labels, opts, Counter, Gauge, Histogram, IntCounterVec, IntGauge, Opts,
+    self, histogram_opts, labels, opts, Counter, CounterVec, GaugeVec, HistogramVec, IntCounterVec,
+    IntGaugeVec, Opts,
 };
 use serde_json;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
-use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+use tokio::sync::watch;
 
 lazy_static! {
-    static ref GRAPH_FINAL_RELEASES: IntGauge = IntGauge::new(
-        "graph_final_releases",
-        "Number of releases in the final graph, after processing"
+    // The metrics of the scrape loop are labeled by tenant, empty for the default graph.
+    static ref GRAPH_FINAL_RELEASES: IntGaugeVec = IntGaugeVec::new(
+        Opts::new(
+            "graph_final_releases",
+            "Number of releases in the final graph, after processing"
+        ),
+        &["tenant"]
     )
     .unwrap();
-    static ref GRAPH_LAST_SUCCESSFUL_REFRESH: IntGauge = IntGauge::new(
-        "graph_last_successful_refresh_timestamp",
-        "UTC timestamp of last successful graph refresh"
+    static ref GRAPH_LAST_SUCCESSFUL_REFRESH: IntGaugeVec = IntGaugeVec::new(
+        Opts::new(
+            "graph_last_successful_refresh_timestamp",
+            "UTC timestamp of last successful graph refresh"
+        ),
+        &["tenant"]
     )
     .unwrap();
-    static ref UPSTREAM_ERRORS: Counter = Counter::new(
-        "graph_upstream_errors_total",
-        "Total number of upstream scraping errors"
+    static ref UPSTREAM_ERRORS: CounterVec = CounterVec::new(
+        Opts::new(
+            "graph_upstream_errors_total",
+            "Total number of upstream scraping errors"
+        ),
+        &["tenant"]
     )
     .unwrap();
-    static ref UPSTREAM_SCRAPES: Counter = Counter::new(
-        "graph_upstream_scrapes_total",
-        "Total number of upstream scrapes"
+    static ref UPSTREAM_SCRAPES: CounterVec = CounterVec::new(
+        Opts::new(
+            "graph_upstream_scrapes_total",
+            "Total number of upstream scrapes"
+        ),
+        &["tenant"]
     )
     .unwrap();
-    static ref GRAPH_UPSTREAM_INITIAL_SCRAPE: Gauge = Gauge::new(
-        "graph_initial_upstream_scrape_duration",
-        "Duration of initial upstream scrape"
+    static ref GRAPH_UPSTREAM_INITIAL_SCRAPE: GaugeVec = GaugeVec::new(
+        Opts::new(
+            "graph_initial_upstream_scrape_duration",
+            "Duration of initial upstream scrape"
+        ),
+        &["tenant"]
     )
     .unwrap();
     /// Histogram with custom bucket values for upstream scraping duration in seconds
-    static ref UPSTREAM_SCRAPES_DURATION: Histogram = Histogram::with_opts(histogram_opts!(
-        "graph_upstream_scrapes_duration",
-        "Upstream scrape duration in seconds",
-        vec![5.0, 6.0, 7.0, 8.0, 9.0, 10.0, 15.0, 20.0, 30.0 ]
-    ))
+    static ref UPSTREAM_SCRAPES_DURATION: HistogramVec = HistogramVec::new(
+        histogram_opts!(
+            "graph_upstream_scrapes_duration",
+            "Upstream scrape duration in seconds",
+            vec![5.0, 6.0, 7.0, 8.0, 9.0, 10.0, 15.0, 20.0, 30.0 ]
+        ),
+        &["tenant"]
+    )
+    .unwrap();
+    static ref GRAPH_VALIDATION_FAILURES: CounterVec = CounterVec::new(
+        Opts::new(
+            "graph_validation_failures_total",
+            "Total number of graphs which were not published because they failed validation"
+        ),
+        &["tenant"]
+    )
+    .unwrap();
+    static ref GRAPH_PUBLICATION_FROZEN: IntGaugeVec = IntGaugeVec::new(
+        Opts::new(
+            "graph_publication_frozen",
+            "Whether the publication of new graphs is deferred by a freeze window"
+        ),
+        &["tenant"]
+    )
+    .unwrap();
+    static ref GRAPH_RESCRAPE_REQUESTS: CounterVec = CounterVec::new(
+        Opts::new(
+            "graph_rescrape_requests_total",
+            "Total number of accepted requests for an immediate rescrape"
+        ),
+        &["tenant"]
+    )
+    .unwrap();
+    static ref CONFIG_RELOAD_FAILURES: CounterVec = CounterVec::new(
+        Opts::new(
+            "config_reload_failures_total",
+            "Total number of configuration reloads which failed and kept the previous configuration"
+        ),
+        &["tenant"]
+    )
     .unwrap();
     static ref GRAPH_INCOMING_REQS: IntCounterVec = IntCounterVec::new(
         Opts::new("graph_incoming_requests_total",
@@ -84,17 +169,180 @@ lazy_static! {
 /// Register relevant metrics to a prometheus registry.
 pub fn register_metrics(registry: &prometheus::Registry) -> Fallible<()> {
     commons::register_metrics(registry)?;
+    cincinnati::semver_cache::register_metrics(registry)?;
+    cincinnati::plugins::internal::release_scrape_dockerv2::registry::register_metrics(registry)?;
     registry.register(Box::new(GRAPH_FINAL_RELEASES.clone()))?;
     registry.register(Box::new(GRAPH_LAST_SUCCESSFUL_REFRESH.clone()))?;
     registry.register(Box::new(UPSTREAM_ERRORS.clone()))?;
     registry.register(Box::new(UPSTREAM_SCRAPES.clone()))?;
     registry.register(Box::new(GRAPH_UPSTREAM_INITIAL_SCRAPE.clone()))?;
     registry.register(Box::new(UPSTREAM_SCRAPES_DURATION.clone()))?;
+    registry.register(Box::new(GRAPH_VALIDATION_FAILURES.clone()))?;
+    registry.register(Box::new(GRAPH_PUBLICATION_FROZEN.clone()))?;
+    registry.register(Box::new(GRAPH_RESCRAPE_REQUESTS.clone()))?;
+    registry.register(Box::new(CONFIG_RELOAD_FAILURES.clone()))?;
+    registry.register(Box::new(notify::NOTIFICATION_FAILURES.clone()))?;
+    registry.register(Box::new(notify::NOTIFICATIONS_SUPPRESSED.clone()))?;
+    registry.register(Box::new(audit::AUDIT_LOG_WRITE_FAILURES.clone()))?;
+    registry.register(Box::new(limits::GRAPH_LIMIT_REJECTIONS.clone()))?;
     registry.register(Box::new(GRAPH_INCOMING_REQS.clone()))?;
     registry.register(Box::new(BUILD_INFO.clone()))?;
+    budget::register_metrics(registry)?;
+    coordination::register_metrics(registry)?;
     Ok(())
 }
 
+/// Query parameter selecting the graph of a single architecture.
+pub static ARCH_PARAM: QueryParam =
+    QueryParam::new("arch", "Return the graph of a single architecture");
+
+/// Query parameter selecting a pinned snapshot of the graph.
+pub static SNAPSHOT_PARAM: QueryParam = QueryParam::new(
+    "snapshot",
+    "Return a pinned snapshot of the graph, can't be combined with arch",
+);
+
+/// Query parameter requesting to wait for a new graph revision.
+pub static WAIT_FOR_CHANGE_PARAM: QueryParam = QueryParam::new(
+    "wait_for_change",
+    "Wait for a graph revision other than the one in If-None-Match, 'true' or 'false'",
+);
+
+/// Query parameter bounding the wait for a new graph revision.
+pub static TIMEOUT_PARAM: QueryParam =
+    QueryParam::new("timeout", "Maximum time to wait for a change, in seconds");
+
+/// Routes of the main service, under `prefix`.
+pub fn routes(
+    prefix: &str,
+    mandatory_params: &HashSet<String>,
+    internal_graph_api: bool,
+) -> ApiRoutes {
+    let graph = |path: String| {
+        let endpoint = Endpoint::get(&path, "Get the update graph")
+            .param(Param::header(
+                "Accept",
+                "Media type of the graph, application/json or the Cincinnati v1 type",
+            ))
+            .query(&[
+                &ARCH_PARAM,
+                &SNAPSHOT_PARAM,
+                &WAIT_FOR_CHANGE_PARAM,
+                &TIMEOUT_PARAM,
+            ])
+            .param(Param::header(
+                "If-None-Match",
+                "ETag of the graph revision known to the client",
+            ));
+        mandatory_params
+            .iter()
+            .fold(endpoint, |endpoint, name| {
+                endpoint.param(Param::query(name, "Mandatory client parameter").required())
+            })
+            .response(200, "The update graph")
+            .response(304, "No change before the timeout")
+            .response(400, "Invalid or missing parameters")
+            .response(406, "Unacceptable media type")
+    };
+
+    let routes = ApiRoutes::default()
+        // keeping this for backward compatibility
+        .route(graph(format!("{}/v1/graph", prefix)), index)
+        .route(graph(format!("{}/graph", prefix)), index)
+        .route(
+            mandatory_params
+                .iter()
+                .fold(
+                    Endpoint::get(
+                        &format!("{}/v2/graph", prefix),
+                        "Get the update graph in the version 2 format",
+                    )
+                    .param(Param::header(
+                        "Accept",
+                        "Media type of the graph, application/json or the Cincinnati v2 type",
+                    ))
+                    .query(&[&WAIT_FOR_CHANGE_PARAM, &TIMEOUT_PARAM])
+                    .param(Param::header(
+                        "If-None-Match",
+                        "ETag of the graph revision known to the client",
+                    )),
+                    |endpoint, name| {
+                        endpoint.param(Param::query(name, "Mandatory client parameter").required())
+                    },
+                )
+                .response(200, "The update graph, with edges as objects")
+                .response(304, "No change before the timeout")
+                .response(400, "Invalid or missing parameters")
+                .response(406, "Unacceptable media type")
+                .response(503, "No graph published yet"),
+            index_v2,
+        )
+        .route(
+            Endpoint::get(
+                &format!("{}/v1/changelog", prefix),
+                "Get the revisions of the graph since a given one",
+            )
+            .query(&[&changelog::SINCE_PARAM, &changelog::LIMIT_PARAM])
+            .response(200, "The revisions since the given one")
+            .response(400, "Invalid parameters"),
+            changelog,
+        )
+        .route(
+            Endpoint::get(
+                &format!("{}/graph/events", prefix),
+                "Stream the publications of new graphs as server-sent events",
+            )
+            .param(Param::header(
+                "Last-Event-ID",
+                "Revision of the last graph known to the client",
+            ))
+            .response(200, "Stream of graph publications")
+            .response(406, "Unacceptable media type")
+            .response(429, "Too many subscribers"),
+            graph_events,
+        )
+        .route(
+            Endpoint::get(
+                &format!("{}/graph-data/releases", prefix),
+                "Get the channels and release information of the published releases",
+            )
+            .query(secondary_metadata::QUERY_PARAMS)
+            .response(200, "The matching releases")
+            .response(400, "Invalid parameters")
+            .response(503, "No graph published yet"),
+            graph_data_releases,
+        )
+        .route(
+            Endpoint::get(
+                &format!("{}/graph-data/risks", prefix),
+                "Get the risks of the published conditional updates",
+            )
+            .query(secondary_metadata::QUERY_PARAMS)
+            .response(200, "The matching conditional updates")
+            .response(400, "Invalid parameters")
+            .response(503, "No graph published yet"),
+            graph_data_risks,
+        );
+    if !internal_graph_api {
+        return routes;
+    }
+
+    routes.route(
+        Endpoint::get(
+            &format!("{}/internal/v1/graph", prefix),
+            "Get the published graph as binary artifact",
+        )
+        .param(Param::header(
+            "If-None-Match",
+            "ETag of the graph revision known to the client",
+        ))
+        .response(200, "The graph artifact")
+        .response(304, "The graph didn't change")
+        .response(503, "No graph published yet"),
+        internal_graph,
+    )
+}
+
 /// Serve Cincinnati graph requests.
 pub async fn index(
     req: HttpRequest,
@@ -119,43 +367,1103 @@ pub async fn index(
     let mandatory_params = &app_data.mandatory_params;
     commons::ensure_query_params(mandatory_params, req.query_string())?;
 
-    let resp = HttpResponse::Ok()
-        .content_type(CONTENT_TYPE)
-        .body(app_data.json.read().clone());
-    Ok(resp)
+    if let Some(id) = SNAPSHOT_PARAM.get(req.query_string()) {
+        if ARCH_PARAM.get(req.query_string()).is_some() {
+            return Err(GraphError::InvalidParams(
+                "snapshot can't be combined with arch".to_string(),
+            ));
+        }
+        let snapshot = app_data
+            .snapshots()
+            .get(&id)
+            .ok_or_else(|| GraphError::NotFound(format!("unknown or expired snapshot '{}'", id)))?;
+
+        let mut resp = HttpResponse::Ok();
+        resp.content_type(CONTENT_TYPE);
+        if let Some(hash) = &snapshot.provenance.hash {
+            resp.insert_header((header::ETAG, format_etag(hash)));
+        }
+        for provenance_header in snapshot.provenance.headers() {
+            resp.insert_header(provenance_header);
+        }
+        return Ok(resp.body(snapshot.json));
+    }
+
+    // Unknown architectures are rejected before waiting for a change of their subgraph.
+    let arch = ARCH_PARAM.get(req.query_string());
+    app_data.published.load().subgraph(arch.as_deref())?;
+    if let Some(resp) = long_poll(&req, &app_data, arch.as_deref()).await? {
+        return Ok(resp);
+    }
+
+    let published = app_data.published.load_full();
+    let (hash, json) = published.subgraph(arch.as_deref())?;
+    let etag = format_etag(hash);
+    if is_not_modified(&req, published.modified) {
+        let mut resp = HttpResponse::NotModified();
+        resp.insert_header((header::ETAG, etag));
+        set_cache_headers(&mut resp, published.modified, app_data.cache_max_age);
+        return Ok(resp.finish());
+    }
+
+    let (encoding, body) = json.negotiate(req.headers());
+    let mut resp = HttpResponse::Ok();
+    resp.content_type(CONTENT_TYPE)
+        .insert_header((header::ETAG, etag));
+    set_content_encoding(&mut resp, encoding);
+    set_cache_headers(&mut resp, published.modified, app_data.cache_max_age);
+    let provenance = Provenance {
+        hash: Some(hash.clone()),
+        ..published.provenance.clone()
+    };
+    for provenance_header in provenance.headers() {
+        resp.insert_header(provenance_header);
+    }
+    set_refreshed_header(&mut resp, &app_data);
+    if let Some(freeze) = app_data.freeze() {
+        resp.insert_header((freeze::FROZEN_UNTIL_HEADER, freeze.until.to_rfc3339()));
+    }
+    Ok(resp.body(body))
+}
+
+/// Serve Cincinnati graph requests in the version 2 format.
+///
+/// The graph is serialized once per revision, when it is published.
+pub async fn index_v2(
+    req: HttpRequest,
+    app_data: actix_web::web::Data<State>,
+) -> Result<HttpResponse, GraphError> {
+    let span = get_tracer().start("index_v2");
+    let _active_span = mark_span_as_active(span);
+
+    let path = req.uri().path();
+    GRAPH_INCOMING_REQS.with_label_values(&[path]).inc();
+
+    // The format is selected by the path, so any acceptable type gets the v2 one.
+    let accept_default = header::HeaderValue::from_static(v2::CONTENT_TYPE);
+    commons::validate_content_type(req.headers(), vec![accept_default.clone()], accept_default)?;
+
+    let mandatory_params = &app_data.mandatory_params;
+    commons::ensure_query_params(mandatory_params, req.query_string())?;
+
+    for unsupported in &[&ARCH_PARAM, &SNAPSHOT_PARAM] {
+        if unsupported.get(req.query_string()).is_some() {
+            return Err(GraphError::InvalidParams(format!(
+                "{} is not supported by the v2 graph API",
+                unsupported.name
+            )));
+        }
+    }
+
+    if let Some(resp) = long_poll(&req, &app_data, None).await? {
+        return Ok(resp);
+    }
+
+    let published = app_data.published.load_full();
+    let body = match &published.v2_json {
+        Some(body) => body,
+        None => return Ok(not_ready()),
+    };
+    let (encoding, body) = body.negotiate(req.headers());
+    let mut resp = HttpResponse::Ok();
+    resp.content_type(v2::CONTENT_TYPE)
+        .insert_header((header::ETAG, format_etag(&published.hash)));
+    set_content_encoding(&mut resp, encoding);
+    for provenance_header in published.provenance.headers() {
+        resp.insert_header(provenance_header);
+    }
+    set_refreshed_header(&mut resp, &app_data);
+    if let Some(freeze) = app_data.freeze() {
+        resp.insert_header((freeze::FROZEN_UNTIL_HEADER, freeze.until.to_rfc3339()));
+    }
+    Ok(resp.body(body))
+}
+
+/// Mark a response as encoded with a precompressed variant of its body, if any.
+///
+/// The compression middleware leaves such responses alone. The body depends
+/// on `Accept-Encoding` either way.
+fn set_content_encoding(resp: &mut HttpResponseBuilder, encoding: Option<&'static str>) {
+    if let Some(encoding) = encoding {
+        resp.insert_header((header::CONTENT_ENCODING, encoding));
+    }
+    resp.insert_header((header::VARY, "accept-encoding"));
+}
+
+/// Set the time of the last successful scrape, which tells clients whether
+/// the graph is up to date even if it didn't change for a while.
+fn set_refreshed_header(resp: &mut HttpResponseBuilder, app_data: &State) {
+    let refreshed = app_data
+        .debug
+        .last_success_timestamp()
+        .and_then(|timestamp| chrono::Utc.timestamp_opt(timestamp, 0).single());
+    if let Some(refreshed) = refreshed {
+        resp.insert_header((provenance::REFRESHED_HEADER, refreshed.to_rfc3339()));
+    }
+}
+
+/// Response of the graph endpoints until a graph is published.
+fn not_ready() -> HttpResponse {
+    ProblemDetails::new(
+        actix_web::http::StatusCode::SERVICE_UNAVAILABLE,
+        "not_ready",
+        "Service not ready",
+        "no graph is available yet".to_string(),
+    )
+    .into_response()
+}
+
+/// Returns the time a graph was generated at according to its provenance,
+/// or now if unknown.
+fn generated_at(provenance: &Provenance) -> SystemTime {
+    provenance
+        .generated
+        .as_deref()
+        .and_then(|generated| chrono::DateTime::parse_from_rfc3339(generated).ok())
+        .map(SystemTime::from)
+        .unwrap_or_else(SystemTime::now)
+}
+
+/// Set the `Last-Modified`, `Vary` and `Cache-Control` headers of a graph
+/// response, for the HTTP caches in front of the service.
+///
+/// No `Age` header is set, as it is meant to be set by the caches themselves.
+fn set_cache_headers(
+    resp: &mut HttpResponseBuilder,
+    modified: Option<SystemTime>,
+    max_age: Duration,
+) {
+    if let Some(modified) = modified {
+        resp.insert_header((header::LAST_MODIFIED, header::HttpDate::from(modified)));
+    }
+    resp.insert_header((header::VARY, "accept, accept-encoding"));
+    if max_age.as_secs() == 0 {
+        resp.insert_header((header::CACHE_CONTROL, "public, no-cache"));
+    } else {
+        resp.insert_header((
+            header::CACHE_CONTROL,
+            format!("public, max-age={}", max_age.as_secs()),
+        ));
+    }
+}
+
+/// Returns whether the graph didn't change since the `If-Modified-Since`
+/// time of a request.
+///
+/// As in RFC 7232, the header is ignored if `If-None-Match` is present.
+fn is_not_modified(req: &HttpRequest, modified: Option<SystemTime>) -> bool {
+    if req.headers().contains_key(header::IF_NONE_MATCH) {
+        return false;
+    }
+    let since = match req
+        .headers()
+        .get(header::IF_MODIFIED_SINCE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<header::HttpDate>().ok())
+    {
+        Some(since) => SystemTime::from(since),
+        None => return false,
+    };
+
+    // HTTP dates have a resolution of one second.
+    let secs = |time: SystemTime| {
+        time.duration_since(SystemTime::UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_secs())
+            .unwrap_or_default()
+    };
+    modified.is_some_and(|modified| secs(modified) <= secs(since))
+}
+
+/// Compress a serialized graph ahead of serving it, falling back to the
+/// uncompressed graph if that fails.
+fn precompress(settings: CompressionSettings, json: String) -> PrecompressedBody {
+    let json = Bytes::from(json);
+    PrecompressedBody::new(settings, json.clone()).unwrap_or_else(|err| {
+        error!("Failed to compress graph: {}", err);
+        json.into()
+    })
+}
+
+/// Wait for a change of the graph, or of the subgraph of `arch`, if requested,
+/// returning a `304 Not Modified` response if there was none before the timeout.
+async fn long_poll(
+    req: &HttpRequest,
+    app_data: &State,
+    arch: Option<&str>,
+) -> Result<Option<HttpResponse>, GraphError> {
+    let timeout = match long_poll_timeout(req.query_string())? {
+        Some(timeout) => timeout,
+        None => return Ok(None),
+    };
+
+    let baseline = req
+        .headers()
+        .get(header::IF_NONE_MATCH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(parse_etag);
+    if app_data
+        .wait_for_change(arch, baseline.as_deref(), timeout)
+        .await
+    {
+        return Ok(None);
+    }
+    let mut resp = HttpResponse::NotModified();
+    if let Some(hash) = app_data.subgraph_hash(arch) {
+        resp.insert_header((header::ETAG, format_etag(&hash)));
+    }
+    Ok(Some(resp.finish()))
+}
+
+/// Serialize the graph, and the subgraph of each architecture found in its releases.
+///
+/// The architectures include `multi` and those of the images of multi-arch
+/// image indexes. Subgraphs keep the release versions and metadata as they are.
+fn serialize_graph(
+    graph: &Graph,
+    keys: &MetadataKeys,
+) -> Fallible<(String, HashMap<String, String>)> {
+    let json = serde_json::to_string(graph)?;
+
+    let arch_keys = keys.arch_keys();
+    let arch_json: HashMap<String, String> = arch_filter::architectures(graph, &arch_keys)
+        .into_iter()
+        .map(|arch| {
+            let mut subgraph = graph.clone();
+            arch_filter::retain_arch(&mut subgraph, &arch_keys, &arch);
+            Ok((arch, serde_json::to_string(&subgraph)?))
+        })
+        .collect::<Fallible<_>>()?;
+
+    Ok((json, arch_json))
+}
+
+/// Serve the published graph as binary artifact, for internal consumers like policy-engine.
+///
+/// Only routed if enabled. The artifact is shared, not copied, by responses.
+pub async fn internal_graph(
+    req: HttpRequest,
+    app_data: actix_web::web::Data<State>,
+) -> Result<HttpResponse, GraphError> {
+    let path = req.uri().path();
+    GRAPH_INCOMING_REQS.with_label_values(&[path]).inc();
+
+    let published = app_data.published.load_full();
+    let body = match &published.artifact {
+        Some(artifact) => artifact.clone(),
+        None => return Ok(not_ready()),
+    };
+    let etag = format_etag(&published.hash);
+
+    let known = req
+        .headers()
+        .get(header::IF_NONE_MATCH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(parse_etag);
+    if known.as_ref() == Some(&published.hash) {
+        return Ok(HttpResponse::NotModified()
+            .insert_header((header::ETAG, etag))
+            .finish());
+    }
+
+    let mut resp = HttpResponse::Ok();
+    resp.content_type(artifact::CONTENT_TYPE)
+        .insert_header((header::ETAG, etag));
+    for provenance_header in published.provenance.headers() {
+        resp.insert_header(provenance_header);
+    }
+    set_refreshed_header(&mut resp, &app_data);
+    if let Some(freeze) = app_data.freeze() {
+        resp.insert_header((freeze::FROZEN_UNTIL_HEADER, freeze.until.to_rfc3339()));
+    }
+    Ok(resp.body(body))
+}
+
+/// Serve the revisions of the published graph since a given one.
+///
+/// The `since` parameter is the hash of the last graph known to the client, as
+/// sent in the `ETag` of graph responses, and `limit` the maximum number of
+/// revisions to return.
+pub async fn changelog(
+    req: HttpRequest,
+    app_data: actix_web::web::Data<State>,
+) -> Result<HttpResponse, GraphError> {
+    let path = req.uri().path();
+    GRAPH_INCOMING_REQS.with_label_values(&[path]).inc();
+
+    let (since, limit) = changelog::parse_params(req.query_string())?;
+    let page = app_data.changelog().since(since.as_deref(), limit);
+
+    Ok(HttpResponse::Ok().json(page))
+}
+
+/// Serve the secondary metadata of the published releases matching the query.
+pub async fn graph_data_releases(
+    req: HttpRequest,
+    app_data: actix_web::web::Data<State>,
+) -> Result<HttpResponse, GraphError> {
+    let path = req.uri().path();
+    GRAPH_INCOMING_REQS.with_label_values(&[path]).inc();
+
+    let query = secondary_metadata::Query::parse(req.query_string())?;
+    let published = app_data.published.load_full();
+    let metadata = match &published.secondary_metadata {
+        Some(metadata) => metadata,
+        None => return Ok(not_ready()),
+    };
+
+    Ok(HttpResponse::Ok().json(metadata.releases(&query)))
+}
+
+/// Serve the risks of the published conditional updates matching the query.
+///
+/// Updates match by their source release, so that `version` lists the risks
+/// of updating from that version.
+pub async fn graph_data_risks(
+    req: HttpRequest,
+    app_data: actix_web::web::Data<State>,
+) -> Result<HttpResponse, GraphError> {
+    let path = req.uri().path();
+    GRAPH_INCOMING_REQS.with_label_values(&[path]).inc();
+
+    let query = secondary_metadata::Query::parse(req.query_string())?;
+    let published = app_data.published.load_full();
+    let metadata = match &published.secondary_metadata {
+        Some(metadata) => metadata,
+        None => return Ok(not_ready()),
+    };
+
+    Ok(HttpResponse::Ok().json(metadata.risks(&query)))
+}
+
+/// Stream the publications of new graphs as server-sent events.
+///
+/// The latest publication is sent first, unless it is the one given in the
+/// `Last-Event-ID` header. Streams end when the process shuts down.
+pub async fn graph_events(
+    req: HttpRequest,
+    app_data: actix_web::web::Data<State>,
+) -> Result<HttpResponse, GraphError> {
+    let path = req.uri().path();
+    GRAPH_INCOMING_REQS.with_label_values(&[path]).inc();
+
+    let accept_default = header::HeaderValue::from_static(events::CONTENT_TYPE);
+    commons::validate_content_type(req.headers(), vec![accept_default.clone()], accept_default)?;
+
+    let graph_events = app_data.graph_events();
+    if graph_events.subscribers() >= events::MAX_SUBSCRIBERS {
+        return Err(GraphError::TooManyRequests(events::RETRY_AFTER_SECS));
+    }
+
+    let last_event_id = req
+        .headers()
+        .get("last-event-id")
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.trim().to_string());
+    let state = app_data.clone();
+    let stream = graph_events
+        .subscribe(last_event_id, events::KEEP_ALIVE_INTERVAL)
+        .take_while(move |_| future::ready(!state.is_shutting_down()));
+
+    Ok(HttpResponse::Ok()
+        .content_type(events::CONTENT_TYPE)
+        .insert_header((header::CACHE_CONTROL, "no-cache"))
+        .streaming(stream))
+}
+
+/// Default timeout for long-polling requests.
+pub static DEFAULT_LONG_POLL_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Maximum timeout for long-polling requests.
+pub static MAX_LONG_POLL_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Return the long-polling timeout if `wait_for_change` was requested.
+///
+/// The timeout is given in seconds, with an optional `s` suffix.
+fn long_poll_timeout(query: &str) -> Result<Option<Duration>, GraphError> {
+    match WAIT_FOR_CHANGE_PARAM.get(query).as_deref() {
+        None | Some("false") => return Ok(None),
+        Some("true") => {}
+        Some(other) => {
+            return Err(GraphError::InvalidParams(format!(
+                "{} must be 'true' or 'false', got '{}'",
+                WAIT_FOR_CHANGE_PARAM.name, other
+            )))
+        }
+    }
+
+    let timeout = match TIMEOUT_PARAM.get(query) {
+        Some(timeout) => timeout
+            .strip_suffix('s')
+            .unwrap_or(&timeout)
+            .parse::<u64>()
+            .map(Duration::from_secs)
+            .map_err(|_| GraphError::InvalidParams(format!("invalid timeout '{}'", timeout)))?,
+        None => DEFAULT_LONG_POLL_TIMEOUT,
+    };
+
+    Ok(Some(std::cmp::min(timeout, MAX_LONG_POLL_TIMEOUT)))
+}
+
+/// Format the hash of a graph content as entity tag.
+///
+/// Unlike the revision, which is local to the process, the hash is the same on
+/// every replica and across restarts.
+fn format_etag(hash: &str) -> String {
+    format!("\"{}\"", hash)
+}
+
+/// Parse the hash of a graph content from an entity tag.
+fn parse_etag(etag: &str) -> Option<String> {
+    let hash = etag.trim().trim_start_matches("W/").trim_matches('"');
+    if hash.is_empty() || hash == "*" {
+        return None;
+    }
+    Some(hash.to_string())
+}
+
+/// Wakes up the scrape loop before the end of its pause.
+#[derive(Debug, Default)]
+pub struct RescrapeTrigger {
+    pending: Mutex<bool>,
+    condvar: Condvar,
+}
+
+impl RescrapeTrigger {
+    /// Request a scrape as soon as possible.
+    pub fn trigger(&self) {
+        *self.pending.lock() = true;
+        self.condvar.notify_all();
+    }
+
+    /// Wait until `timeout` elapsed or a scrape is requested.
+    ///
+    /// Returns whether a scrape was requested.
+    pub fn wait(&self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        let mut pending = self.pending.lock();
+        while !*pending {
+            if self.condvar.wait_until(&mut pending, deadline).timed_out() {
+                break;
+            }
+        }
+        std::mem::replace(&mut *pending, false)
+    }
+}
+
+/// Plugins of the current configuration, dropped once replaced and no longer running.
+#[derive(Clone)]
+struct Plugins {
+    configured: Vec<Arc<BoxedPlugin>>,
+    /// Wrapped to record debug information.
+    tracked: Vec<Arc<BoxedPlugin>>,
+}
+
+impl Plugins {
+    fn new(plugins: Vec<BoxedPlugin>, debug: &debug::DebugState) -> Self {
+        let configured: Vec<Arc<BoxedPlugin>> = plugins.into_iter().map(Arc::new).collect();
+        let tracked = debug.track(&configured);
+        Plugins {
+            configured,
+            tracked,
+        }
+    }
+}
+
+/// A graph to publish, in all its representations.
+#[derive(Debug, Default)]
+pub struct Publication {
+    /// Revision of the graph, as returned by `State::next_revision`.
+    pub revision: u64,
+    /// The graph serialized as JSON.
+    pub json: String,
+    /// Subgraph of each architecture, serialized as JSON.
+    pub arch_json: HashMap<String, String>,
+    /// Provenance of the graph.
+    pub provenance: Provenance,
+    /// The graph serialized in the version 2 format, if it could be.
+    pub v2_json: Option<String>,
+    /// The graph encoded as binary artifact, if the internal graph API is enabled.
+    pub artifact: Option<Vec<u8>>,
+    /// Index of the secondary metadata of the graph.
+    pub secondary_metadata: Option<SecondaryMetadata>,
+}
+
+/// The published subgraph of an architecture.
+#[derive(Debug)]
+struct ArchGraph {
+    /// Hash of the subgraph content, sent as `ETag` and provenance hash.
+    hash: String,
+    /// The subgraph serialized as JSON, along with its compressed variants.
+    json: PrecompressedBody,
+}
+
+/// The published graph in all its representations, replaced at once on
+/// publication so that a response never mixes two revisions.
+#[derive(Debug, Default)]
+struct Published {
+    /// Revision of the graph, 0 until a graph is published.
+    revision: u64,
+    /// Hash of the graph content, sent as `ETag`, empty until a graph is published.
+    hash: String,
+    /// The graph serialized as JSON, along with its compressed variants.
+    json: PrecompressedBody,
+    /// Subgraph of each architecture.
+    arch_json: HashMap<String, ArchGraph>,
+    /// Time of the publication, unset until a graph is published.
+    modified: Option<SystemTime>,
+    provenance: Provenance,
+    /// The graph serialized in the version 2 format, along with its compressed variants.
+    v2_json: Option<PrecompressedBody>,
+    /// The graph encoded as binary artifact.
+    artifact: Option<Bytes>,
+    secondary_metadata: Option<Arc<SecondaryMetadata>>,
+}
+
+impl Published {
+    /// Returns the architectures of the graph.
+    fn archs(&self) -> Vec<String> {
+        let mut archs: Vec<String> = self.arch_json.keys().cloned().collect();
+        archs.sort();
+        archs
+    }
+
+    /// Returns the hash and the content of the subgraph of `arch`, or of the whole graph.
+    ///
+    /// The subgraph of an architecture is identified by its own hash.
+    fn subgraph(&self, arch: Option<&str>) -> Result<(&String, &PrecompressedBody), GraphError> {
+        match arch {
+            Some(arch) => {
+                let subgraph = self.arch_json.get(arch).ok_or_else(|| {
+                    GraphError::InvalidParams(format!(
+                        "unknown arch '{}', available: {}",
+                        arch,
+                        self.archs().join(", ")
+                    ))
+                })?;
+                Ok((&subgraph.hash, &subgraph.json))
+            }
+            None => Ok((&self.hash, &self.json)),
+        }
+    }
 }
 
 #[derive(Clone)]
 pub struct State {
-    json: Arc<RwLock<String>>,
+    /// Published graph, swapped atomically so that readers never wait for the scrape loop.
+    published: Arc<ArcSwap<Published>>,
+    /// Compression levels of the published graphs.
+    compression: CompressionSettings,
+    /// Lifetime of graph responses in HTTP caches, which revalidate them if zero.
+    cache_max_age: Duration,
+    /// Revision of the published graph, to wait for changes.
+    revision: watch::Receiver<u64>,
+    revision_tx: Arc<watch::Sender<u64>>,
     /// Query parameters that must be present in all client requests.
     mandatory_params: HashSet<String>,
     live: Arc<RwLock<bool>>,
     ready: Arc<RwLock<bool>>,
-    plugins: &'static [BoxedPlugin],
+    /// Plugins of the current configuration, replaced on reload.
+    plugins: Arc<RwLock<Plugins>>,
+    plugin_registry: reload::PluginRegistry,
+    debug: debug::DebugState,
     registry: &'static prometheus::Registry,
+    /// Whether the next graph may bypass the validation change thresholds.
+    validation_override: Arc<AtomicBool>,
+    /// Whether the next graph may be published despite a freeze.
+    freeze_override: Arc<AtomicBool>,
+    rescrape: Arc<RescrapeTrigger>,
+    /// Whether the configuration should be reloaded before the next scrape.
+    reload_requested: Arc<AtomicBool>,
+    /// Whether the process is shutting down, which stops the scrape loop.
+    shutdown: Arc<AtomicBool>,
+    /// Bearer token required by the admin endpoints, which are disabled if unset.
+    admin_token: Option<Arc<String>>,
+    notifier: notify::Notifier,
+    changelog: changelog::Changelog,
+    audit: audit::AuditLog,
+    graph_events: events::GraphEvents,
+    snapshots: snapshots::Snapshots,
+    /// Ongoing freeze of the publication, as of the last scrape.
+    freeze: Arc<RwLock<Option<freeze::Freeze>>>,
+    /// Self-test of the main service, which is unavailable if unset.
+    selftest: Option<Arc<SelfTest>>,
+    /// Age of the published graph after which the service isn't ready, if any.
+    max_graph_staleness: Option<Duration>,
+    /// Leader election across replicas, if enabled.
+    coordinator: Option<Coordinator>,
+    /// Description of the configured graph source, replaced on reload.
+    upstream: Arc<RwLock<String>>,
+    /// Keys of the release metadata, with the prefix of the configured plugins.
+    metadata_keys: Arc<RwLock<MetadataKeys>>,
+    /// Name of the tenant whose graph is served, unset for the default graph.
+    tenant: Option<Arc<String>>,
+    /// States of the tenants, served along with the default graph.
+    tenants: Arc<Vec<State>>,
 }
 
 impl State {
     /// Creates a new State with the given arguments
     pub fn new(
-        json: Arc<RwLock<String>>,
         mandatory_params: HashSet<String>,
         live: Arc<RwLock<bool>>,
         ready: Arc<RwLock<bool>>,
-        plugins: &'static [BoxedPlugin],
+        plugins: Vec<BoxedPlugin>,
         registry: &'static prometheus::Registry,
     ) -> State {
+        let (revision_tx, revision) = watch::channel(0);
+        let debug = debug::DebugState::default();
+
         State {
-            json,
+            published: Default::default(),
+            compression: Default::default(),
+            cache_max_age: Duration::from_secs(0),
+            revision,
+            revision_tx: Arc::new(revision_tx),
             mandatory_params,
             live,
             ready,
-            plugins,
+            plugins: Arc::new(RwLock::new(Plugins::new(plugins, &debug))),
+            plugin_registry: Default::default(),
+            debug,
             registry,
+            validation_override: Arc::new(AtomicBool::new(false)),
+            freeze_override: Arc::new(AtomicBool::new(false)),
+            rescrape: Default::default(),
+            reload_requested: Arc::new(AtomicBool::new(false)),
+            shutdown: Arc::new(AtomicBool::new(false)),
+            admin_token: None,
+            notifier: Default::default(),
+            changelog: Default::default(),
+            audit: Default::default(),
+            graph_events: Default::default(),
+            snapshots: Default::default(),
+            freeze: Default::default(),
+            selftest: None,
+            max_graph_staleness: None,
+            coordinator: None,
+            upstream: Default::default(),
+            metadata_keys: Default::default(),
+            tenant: None,
+            tenants: Default::default(),
         }
     }
 
+    /// Creates the State of the tenant `name`, whose graph is built by `plugins`.
+    ///
+    /// The tenant shares the liveness, the metrics registry and the notifier of
+    /// this State. Its graph, readiness and history are its own.
+    pub fn for_tenant(&self, name: &str, plugins: Vec<BoxedPlugin>) -> State {
+        let mut tenant = State::new(
+            self.mandatory_params.clone(),
+            self.live.clone(),
+            Arc::new(RwLock::new(false)),
+            plugins,
+            self.registry,
+        );
+        tenant.compression = self.compression;
+        tenant.cache_max_age = self.cache_max_age;
+        tenant.notifier = self.notifier.clone();
+        tenant.max_graph_staleness = self.max_graph_staleness;
+        tenant.tenant = Some(Arc::new(name.to_string()));
+        tenant
+    }
+
+    /// Sets the states of the tenants served along with the default graph.
+    pub fn with_tenants(mut self, tenants: Vec<State>) -> State {
+        self.tenants = Arc::new(tenants);
+        self
+    }
+
+    /// Sets the bearer token which enables the admin endpoints.
+    pub fn with_admin_token(mut self, admin_token: Option<String>) -> State {
+        self.admin_token = admin_token.map(Arc::new);
+        self
+    }
+
+    /// Sets the registry the metrics of the current plugins are registered in.
+    pub fn with_plugin_registry(mut self, plugin_registry: reload::PluginRegistry) -> State {
+        self.plugin_registry = plugin_registry;
+        self
+    }
+
+    /// Sets the notifier for operational events.
+    pub fn with_notifier(mut self, notifier: notify::Notifier) -> State {
+        self.notifier = notifier;
+        self
+    }
+
+    /// Sets the history of the published revisions.
+    pub fn with_changelog(mut self, changelog: changelog::Changelog) -> State {
+        self.changelog = changelog;
+        self
+    }
+
+    /// Sets the audit log of the published graph transitions.
+    pub fn with_audit_log(mut self, audit: audit::AuditLog) -> State {
+        self.audit = audit;
+        self
+    }
+
+    /// Sets the ring buffer of the recently published graphs.
+    pub fn with_snapshots(mut self, snapshots: snapshots::Snapshots) -> State {
+        self.snapshots = snapshots;
+        self
+    }
+
+    /// Sets the age of the published graph after which the service isn't ready.
+    pub fn with_max_graph_staleness(mut self, max_graph_staleness: Option<Duration>) -> State {
+        self.max_graph_staleness = max_graph_staleness;
+        self
+    }
+
+    /// Sets the leader election across replicas.
+    pub fn with_coordinator(mut self, coordinator: Option<Coordinator>) -> State {
+        self.coordinator = coordinator;
+        self
+    }
+
+    /// Sets the compression levels of the published graphs.
+    pub fn with_compression(mut self, compression: CompressionSettings) -> State {
+        self.compression = compression;
+        self
+    }
+
+    /// Sets the lifetime of graph responses in HTTP caches.
+    pub fn with_cache_max_age(mut self, cache_max_age: Duration) -> State {
+        self.cache_max_age = cache_max_age;
+        self
+    }
+
+    /// Sets the self-test of the main service.
+    pub fn with_selftest(mut self, selftest: Option<SelfTest>) -> State {
+        self.selftest = selftest.map(Arc::new);
+        self
+    }
+
+    /// Returns the plugins of the current configuration, as run by the scrape loop.
+    pub fn plugins(&self) -> Vec<Arc<BoxedPlugin>> {
+        self.plugins.read().tracked.clone()
+    }
+
+    /// Returns the plugins of the current configuration, as configured.
+    pub fn configured_plugins(&self) -> Vec<Arc<BoxedPlugin>> {
+        self.plugins.read().configured.clone()
+    }
+
+    /// Replace the plugins, along with the registry holding their metrics.
+    pub fn replace_plugins(&self, plugins: Vec<BoxedPlugin>, registry: prometheus::Registry) {
+        *self.plugins.write() = Plugins::new(plugins, &self.debug);
+        self.plugin_registry.replace(registry);
+    }
+
+    /// Returns the registry the metrics of the current plugins are registered in.
+    pub fn plugin_registry(&self) -> &reload::PluginRegistry {
+        &self.plugin_registry
+    }
+
+    /// Returns the notifier for operational events.
+    pub fn notifier(&self) -> &notify::Notifier {
+        &self.notifier
+    }
+
+    /// Returns the history of the published revisions.
+    pub fn changelog(&self) -> &changelog::Changelog {
+        &self.changelog
+    }
+
+    /// Returns the audit log of the published graph transitions.
+    pub fn audit(&self) -> &audit::AuditLog {
+        &self.audit
+    }
+
+    /// Returns the broadcaster of the graph publications.
+    pub fn graph_events(&self) -> &events::GraphEvents {
+        &self.graph_events
+    }
+
+    /// Returns the recently published graphs.
+    pub fn snapshots(&self) -> &snapshots::Snapshots {
+        &self.snapshots
+    }
+
+    /// Returns the leader election across replicas, if enabled.
+    pub fn coordinator(&self) -> Option<&Coordinator> {
+        self.coordinator.as_ref()
+    }
+
+    /// Returns the self-test of the main service, if any.
+    pub fn selftest(&self) -> Option<&SelfTest> {
+        self.selftest.as_deref()
+    }
+
+    /// Record the description of the configured graph source.
+    pub fn record_upstream(&self, upstream: String) {
+        *self.upstream.write() = upstream;
+    }
+
+    /// Returns the description of the configured graph source.
+    pub fn upstream(&self) -> String {
+        self.upstream.read().clone()
+    }
+
+    /// Record the keys of the release metadata, as configured.
+    pub fn record_metadata_keys(&self, keys: MetadataKeys) {
+        *self.metadata_keys.write() = keys;
+    }
+
+    /// Returns the keys of the release metadata, as configured.
+    pub fn metadata_keys(&self) -> MetadataKeys {
+        self.metadata_keys.read().clone()
+    }
+
+    /// Returns the name of the tenant whose graph is served, if any.
+    pub fn tenant(&self) -> Option<&str> {
+        self.tenant.as_deref().map(String::as_str)
+    }
+
+    /// Returns the states of the tenants served along with the default graph.
+    pub fn tenants(&self) -> &[State] {
+        &self.tenants
+    }
+
+    /// Returns the value of the `tenant` metrics label, empty for the default graph.
+    pub(crate) fn tenant_label(&self) -> &str {
+        self.tenant().unwrap_or_default()
+    }
+
+    /// Record the ongoing freeze of the publication, if any.
+    pub fn record_freeze(&self, freeze: Option<freeze::Freeze>) {
+        GRAPH_PUBLICATION_FROZEN
+            .with_label_values(&[self.tenant_label()])
+            .set(freeze.is_some() as i64);
+        *self.freeze.write() = freeze;
+    }
+
+    /// Returns the ongoing freeze of the publication, if any.
+    pub fn freeze(&self) -> Option<freeze::Freeze> {
+        self.freeze
+            .read()
+            .clone()
+            .filter(|freeze| freeze.until > chrono::Utc::now())
+    }
+
+    /// Returns the recorder of debug information.
+    pub fn debug(&self) -> &debug::DebugState {
+        &self.debug
+    }
+
+    /// Reload the configuration and wake up the scrape loop.
+    pub fn request_reload(&self) {
+        self.reload_requested.store(true, Ordering::SeqCst);
+        self.rescrape.trigger();
+    }
+
+    /// Stop reporting readiness and let the scrape loop exit after the ongoing scrape.
+    pub fn request_shutdown(&self) {
+        self.shutdown.store(true, Ordering::SeqCst);
+        *self.ready.write() = false;
+        self.rescrape.trigger();
+    }
+
+    /// Returns whether the process is shutting down.
+    pub fn is_shutting_down(&self) -> bool {
+        self.shutdown.load(Ordering::SeqCst)
+    }
+
+    /// Returns whether a reload was requested, and clears the request.
+    fn take_reload_request(&self) -> bool {
+        self.reload_requested.swap(false, Ordering::SeqCst)
+    }
+
+    /// Returns the bearer token required by the admin endpoints.
+    pub fn admin_token(&self) -> Option<&str> {
+        self.admin_token.as_deref().map(String::as_str)
+    }
+
+    /// Wake up the scrape loop immediately.
+    pub fn trigger_rescrape(&self) {
+        GRAPH_RESCRAPE_REQUESTS
+            .with_label_values(&[self.tenant_label()])
+            .inc();
+        self.rescrape.trigger();
+    }
+
+    /// Returns the revision under which the JSON graph would be published, or
+    /// `None` if it is the published graph already.
+    pub fn next_revision(&self, json: &str) -> Option<u64> {
+        let published = self.published.load();
+        if published.modified.is_some() && published.json.identity() == json.as_bytes() {
+            return None;
+        }
+        Some(published.revision + 1)
+    }
+
+    /// Publish a graph, replacing all representations of the published one at
+    /// once and recording its snapshot.
+    ///
+    /// All of them are compressed with every supported encoding here, once per
+    /// revision, so that requests are served without compressing them again.
+    pub fn publish(&self, publication: Publication) {
+        let hash = publication
+            .provenance
+            .hash
+            .clone()
+            .unwrap_or_else(|| provenance::content_hash(publication.json.as_bytes()));
+        let json = precompress(self.compression, publication.json);
+        self.snapshots
+            .record(publication.provenance.clone(), json.identity().clone());
+
+        let revision = publication.revision;
+        self.published.store(Arc::new(Published {
+            revision,
+            hash,
+            json,
+            arch_json: publication
+                .arch_json
+                .into_iter()
+                .map(|(arch, json)| {
+                    let subgraph = ArchGraph {
+                        hash: provenance::content_hash(json.as_bytes()),
+                        json: precompress(self.compression, json),
+                    };
+                    (arch, subgraph)
+                })
+                .collect(),
+            modified: Some(generated_at(&publication.provenance)),
+            provenance: publication.provenance,
+            v2_json: publication
+                .v2_json
+                .map(|json| precompress(self.compression, json)),
+            artifact: publication.artifact.map(Bytes::from),
+            secondary_metadata: publication.secondary_metadata.map(Arc::new),
+        }));
+        // The state itself holds a receiver, so this can't fail.
+        let _ = self.revision_tx.send(revision);
+    }
+
+    /// Returns the provenance of the published graph.
+    pub fn provenance(&self) -> Provenance {
+        self.published.load().provenance.clone()
+    }
+
+    /// Returns the size in bytes of each representation of the published graph.
+    pub fn published_sizes(&self) -> Vec<(&'static str, usize)> {
+        let published = self.published.load();
+        vec![
+            ("json", published.json.identity().len()),
+            ("json_compressed", published.json.encoded_len()),
+            (
+                "arch_json",
+                published
+                    .arch_json
+                    .values()
+                    .map(|subgraph| subgraph.json.identity().len())
+                    .sum(),
+            ),
+            (
+                "arch_json_compressed",
+                published
+                    .arch_json
+                    .values()
+                    .map(|subgraph| subgraph.json.encoded_len())
+                    .sum(),
+            ),
+            (
+                "artifact",
+                published.artifact.as_ref().map_or(0, Bytes::len),
+            ),
+            (
+                "v2_json",
+                published
+                    .v2_json
+                    .as_ref()
+                    .map_or(0, |v2_json| v2_json.identity().len()),
+            ),
+            (
+                "v2_json_compressed",
+                published
+                    .v2_json
+                    .as_ref()
+                    .map_or(0, PrecompressedBody::encoded_len),
+            ),
+        ]
+    }
+
+    /// Returns the published graph, serialized as JSON.
+    pub fn published_graph(&self) -> Bytes {
+        self.published.load().json.identity().clone()
+    }
+
+    /// Returns the published subgraph of an architecture, serialized as JSON.
+    pub fn published_arch_graph(&self, arch: &str) -> Option<Bytes> {
+        self.published
+            .load()
+            .arch_json
+            .get(arch)
+            .map(|subgraph| subgraph.json.identity().clone())
+    }
+
+    /// Returns the architectures of the published graph.
+    pub fn published_archs(&self) -> Vec<String> {
+        self.published.load().archs()
+    }
+
+    /// Returns the revision of the published graph.
+    pub fn revision(&self) -> u64 {
+        self.published.load().revision
+    }
+
+    /// Returns the hash of the published graph content, empty until a graph is published.
+    pub fn graph_hash(&self) -> String {
+        self.published.load().hash.clone()
+    }
+
+    /// Returns the hash of the published subgraph of `arch`, or of the whole
+    /// graph, if the architecture is part of it.
+    pub fn subgraph_hash(&self, arch: Option<&str>) -> Option<String> {
+        self.published
+            .load()
+            .subgraph(arch)
+            .ok()
+            .map(|(hash, _)| hash.clone())
+    }
+
+    /// Wait until the published subgraph of `arch`, or the whole graph, differs
+    /// from the one whose content hashes to `baseline`, or `timeout` elapsed.
+    ///
+    /// Without a baseline the current graph is used. Returns whether the
+    /// graph differs.
+    pub async fn wait_for_change(
+        &self,
+        arch: Option<&str>,
+        baseline: Option<&str>,
+        timeout: Duration,
+    ) -> bool {
+        let mut revision = self.revision.clone();
+        // The graph is published before its revision is announced, so the
+        // hash is up to date whenever the revision changed.
+        let baseline = match baseline
+            .map(str::to_string)
+            .or_else(|| self.subgraph_hash(arch))
+        {
+            Some(baseline) => baseline,
+            None => return true,
+        };
+
+        let changed = async {
+            while self.subgraph_hash(arch).as_deref() == Some(baseline.as_str()) {
+                if revision.changed().await.is_err() {
+                    return false;
+                }
+            }
+            true
+        };
+
+        tokio::time::timeout(timeout, changed)
+            .await
+            .unwrap_or(false)
+    }
+
     /// Returns the boolean inside self.live
     pub fn is_live(&self) -> bool {
         *self.live.read()
@@ -165,6 +1473,33 @@ impl State {
     pub fn is_ready(&self) -> bool {
         *self.ready.read()
     }
+
+    /// Returns the age of the published graph if it exceeds the maximum staleness.
+    pub fn staleness(&self, now: i64) -> Option<Duration> {
+        let max_graph_staleness = self.max_graph_staleness?;
+        let age = now - self.debug.last_success_timestamp()?;
+        Some(Duration::from_secs(age.max(0) as u64)).filter(|age| *age > max_graph_staleness)
+    }
+
+    /// Allow the next graph to bypass the validation change thresholds.
+    pub fn request_validation_override(&self) {
+        self.validation_override.store(true, Ordering::SeqCst);
+    }
+
+    /// Returns whether a validation override is pending.
+    pub fn is_validation_override_pending(&self) -> bool {
+        self.validation_override.load(Ordering::SeqCst)
+    }
+
+    /// Allow the next graph to be published despite an ongoing freeze.
+    pub fn request_freeze_override(&self) {
+        self.freeze_override.store(true, Ordering::SeqCst);
+    }
+
+    /// Returns whether a freeze override is pending.
+    pub fn is_freeze_override_pending(&self) -> bool {
+        self.freeze_override.load(Ordering::SeqCst)
+    }
 }
 
 impl HasRegistry for State {
@@ -174,7 +1509,10 @@ impl HasRegistry for State {
 }
 
 #[allow(clippy::useless_let_if_seq)]
-pub fn run(settings: &config::AppSettings, state: &State) -> ! {
+/// Run the scrape loop, publishing a new graph after each successful scrape.
+///
+/// Returns once a shutdown is requested, after the ongoing scrape.
+pub fn run(mut settings: config::AppSettings, state: &State) {
     // Indicate if a panic happens
     let previous_hook = std::panic::take_hook();
     let panic_live = state.live.clone();
@@ -187,74 +1525,992 @@ pub fn run(settings: &config::AppSettings, state: &State) -> ! {
     let mut first_iteration = true;
     let mut first_success = true;
 
-    BUILD_INFO.inc();
+    let tenant = state.tenant_label();
+    if state.tenant().is_none() {
+        BUILD_INFO.inc();
+    }
+
+    // Export the metrics of this graph before its first scrape.
+    GRAPH_FINAL_RELEASES.with_label_values(&[tenant]);
+    GRAPH_LAST_SUCCESSFUL_REFRESH.with_label_values(&[tenant]);
+    UPSTREAM_ERRORS.with_label_values(&[tenant]);
+    UPSTREAM_SCRAPES.with_label_values(&[tenant]);
+    GRAPH_UPSTREAM_INITIAL_SCRAPE.with_label_values(&[tenant]);
+    GRAPH_VALIDATION_FAILURES.with_label_values(&[tenant]);
+    GRAPH_PUBLICATION_FROZEN.with_label_values(&[tenant]);
 
     // Store amount of nodes in the graph for metrics
     let mut nodes_count: i64;
 
+    // Summary of the last published graph, for the validation change thresholds
+    let mut last_published: Option<validation::GraphSummary> = None;
+
+    // Scrapes failed in a row, to notify once they reach the threshold
+    let mut consecutive_failures: u64 = 0;
+
+    // Releases quarantined by the last scrape, to only notify about new ones
+    let mut quarantined: HashSet<String> = HashSet::new();
+
+    // Last published graph, to record the changes of each revision, starting
+    // from the one audited before a restart
+    let mut published_graph = state.audit.last_graph().unwrap_or_default();
+
+    // Graph published before a restart, to keep serving it if restarted during a freeze
+    let mut restored_graph = match settings
+        .freeze_state_path
+        .as_deref()
+        .map(freeze::read_state)
+        .transpose()
+    {
+        Ok(graph) => graph.flatten(),
+        Err(err) => {
+            error!(
+                "failed to restore the graph published before the restart: {:#}",
+                err
+            );
+            None
+        }
+    };
+
+    // Scrapes are identified by the start of the process and their sequence number
+    let started = chrono::Utc::now().timestamp();
+    let mut scrapes: u64 = 0;
+
+    // Runtime fetching the graph of the leader, created once following one
+    let mut follower_runtime: Option<tokio::runtime::Runtime> = None;
+
+    budget::check(state, &settings);
+
     loop {
         // Store scrape duration value. It would be used for initial scrape gauge or scrape histogram
-        let scrape_value: f64;
 
         if first_iteration {
             *state.live.write() = true;
             first_iteration = false;
         } else {
-            thread::sleep(settings.pause_secs);
+            if state.rescrape.wait(settings.pause_secs) && !state.is_shutting_down() {
+                info!("rescrape requested, not waiting for the end of the pause");
+            }
+        }
+
+        if state.is_shutting_down() {
+            info!("shutting down, stopping the scrape loop");
+            return;
         }
 
-        debug!("graph update triggered");
-        let scrape_timer = UPSTREAM_SCRAPES_DURATION.start_timer();
+        if state.take_reload_request() {
+            match reload::reload(&mut settings, state) {
+                Ok(()) => info!("configuration reloaded"),
+                Err(err) => {
+                    CONFIG_RELOAD_FAILURES.with_label_values(&[tenant]).inc();
+                    error!("failed to reload configuration, keeping the previous one");
+                    err.chain().for_each(|cause| error!("{}", cause));
+                }
+            }
+        }
 
-        let scrape = cincinnati::plugins::process_blocking(
-            state.plugins.iter(),
-            cincinnati::plugins::PluginIO::InternalIO(cincinnati::plugins::InternalIO {
-                // the first plugin will produce the initial graph
-                graph: Default::default(),
-                // the plugins used in the graph-builder don't expect any parameters yet
-                parameters: Default::default(),
-            }),
-            settings.scrape_timeout_secs,
+        state.debug.record_scrape_interval(
+            settings.pause_secs + settings.scrape_timeout_secs.unwrap_or_default(),
         );
-        UPSTREAM_SCRAPES.inc();
+
+        // Each iteration is traced in its own span, ending along with it.
+        let _active_span = mark_span_as_active(get_tracer().start("scrape"));
+        scrapes += 1;
+        let scrape_id = format!("{}-{}", started, scrapes);
+        get_active_span(|span| span.set_attribute(Key::new("scrape_id").string(scrape_id.clone())));
+        if let Some(tenant) = state.tenant() {
+            get_active_span(|span| {
+                span.set_attribute(Key::new("tenant").string(tenant.to_string()))
+            });
+        }
+
+        // Replicas neither scrape nor fetch until they took part in an election round.
+        let role = state.coordinator().map(Coordinator::role);
+        if role == Some(Role::Candidate) {
+            debug!(
+                "waiting for the first election round, skipping scrape {}",
+                scrape_id
+            );
+            continue;
+        }
+
+        debug!("graph update triggered, scrape {}", scrape_id);
+        let scrape_timer = UPSTREAM_SCRAPES_DURATION
+            .with_label_values(&[tenant])
+            .start_timer();
+
+        // An override requested during the scrape applies to the next one.
+        let override_thresholds = state.validation_override.load(Ordering::SeqCst);
+        let override_freeze = state.freeze_override.load(Ordering::SeqCst);
+        let scrape = match (state.coordinator(), role) {
+            // Followers don't scrape, the graph of the leader went through the plugins already.
+            (Some(coordinator), Some(Role::Follower(leader))) => {
+                let runtime = match follower_runtime.take() {
+                    Some(runtime) => Ok(runtime),
+                    None => tokio::runtime::Builder::new_current_thread()
+                        .enable_all()
+                        .build(),
+                };
+                match runtime {
+                    Ok(runtime) => {
+                        let fetched = runtime.block_on(coordinator.fetch(&leader));
+                        follower_runtime = Some(runtime);
+                        fetched
+                    }
+                    Err(err) => Err(err.into()),
+                }
+            }
+            _ => cincinnati::plugins::process_blocking(
+                state.plugins().into_iter(),
+                cincinnati::plugins::PluginIO::InternalIO(cincinnati::plugins::InternalIO {
+                    // the first plugin will produce the initial graph
+                    graph: Default::default(),
+                    // plugins honoring the change thresholds are told about an override
+                    parameters: if override_thresholds {
+                        [(OVERRIDE_PARAM_KEY.to_string(), "true".to_string())]
+                            .iter()
+                            .cloned()
+                            .collect()
+                    } else {
+                        Default::default()
+                    },
+                }),
+                settings.scrape_timeout_secs,
+            ),
+        };
+        UPSTREAM_SCRAPES.with_label_values(&[tenant]).inc();
 
         {
-            let internal_io = match scrape {
+            let mut internal_io = match scrape {
                 Ok(internal_io) => internal_io,
                 Err(err) => {
-                    UPSTREAM_ERRORS.inc();
+                    UPSTREAM_ERRORS.with_label_values(&[tenant]).inc();
                     err.chain().for_each(|cause| error!("{}", cause));
+                    state.debug.record_scrape(Some(format!("{:#}", err)));
+                    get_active_span(|span| {
+                        span.set_status(StatusCode::Error, format!("{:#}", err))
+                    });
+
+                    consecutive_failures += 1;
+                    if consecutive_failures == settings.notification_scrape_failure_threshold {
+                        state.notifier.notify(notify::Event::ScrapeFailure {
+                            consecutive_failures,
+                            error: format!("{:#}", err),
+                        });
+                    }
                     continue;
                 }
             };
+            consecutive_failures = 0;
+            state.notifier.resolve(notify::EventKind::ScrapeFailure);
+
+            // A replica restarted during a freeze publishes the graph it published before.
+            if let Some(restored) = restored_graph.take() {
+                if !override_freeze
+                    && freeze::active(&settings.freeze_windows, chrono::Utc::now()).is_some()
+                {
+                    info!("restarted during a freeze, publishing the graph kept from before");
+                    internal_io.graph = restored;
+                }
+            }
+
+            let new_quarantined: HashSet<String> = internal_io
+                .parameters
+                .get(QUARANTINED_RELEASES_PARAM_KEY)
+                .map(|payloads| payloads.split(',').map(str::to_string).collect())
+                .unwrap_or_default();
+            let mut newly_quarantined: Vec<String> =
+                new_quarantined.difference(&quarantined).cloned().collect();
+            if !newly_quarantined.is_empty() {
+                newly_quarantined.sort();
+                state.notifier.notify(notify::Event::Quarantine {
+                    releases: newly_quarantined,
+                });
+            }
+            quarantined = new_quarantined;
+
+            // Oversized graphs are refused like invalid ones, keeping the previous graph.
+            let limits = Limits::from_settings(&settings);
+            let reject_oversized = |exceeded: LimitExceeded| {
+                exceeded.record(tenant);
+                error!("refusing to publish graph, {}", exceeded);
+                get_active_span(|span| {
+                    span.set_status(StatusCode::Error, format!("limits: {}", exceeded))
+                });
+                state.debug.record_scrape(Some(exceeded.to_string()));
+                state.notifier.notify(notify::Event::LimitExceeded {
+                    limit: exceeded.limit.to_string(),
+                    reason: exceeded.reason.clone(),
+                });
+            };
+            if let Err(exceeded) = limits.check_graph(&internal_io.graph) {
+                reject_oversized(exceeded);
+                continue;
+            }
+
+            let keys = state.metadata_keys();
+            let thresholds = validation::Thresholds {
+                max_releases_removed_percent: settings.validation_max_releases_removed_percent,
+                max_edges_removed_percent: settings.validation_max_edges_removed_percent,
+            };
+            let summary = if settings.validation_enabled {
+                match validation::validate(
+                    &internal_io.graph,
+                    &keys,
+                    last_published.as_ref(),
+                    &thresholds,
+                    override_thresholds,
+                ) {
+                    Ok(summary) => Some(summary),
+                    Err(err) => {
+                        GRAPH_VALIDATION_FAILURES.with_label_values(&[tenant]).inc();
+                        error!("refusing to publish graph, {}", err);
+                        get_active_span(|span| {
+                            span.set_status(StatusCode::Error, format!("validation: {}", err))
+                        });
+                        state.debug.record_scrape(Some(err.to_string()));
+                        state.notifier.notify(notify::Event::ValidationRejection {
+                            reason: err.to_string(),
+                        });
+                        continue;
+                    }
+                }
+            } else {
+                None
+            };
 
-            let json_graph = match serde_json::to_string(&internal_io.graph) {
-                Ok(json) => json,
+            let (json_graph, arch_json) = match serialize_graph(&internal_io.graph, &keys) {
+                Ok(serialized) => serialized,
                 Err(err) => {
-                    UPSTREAM_ERRORS.inc();
+                    UPSTREAM_ERRORS.with_label_values(&[tenant]).inc();
                     error!("Failed to serialize graph: {}", err);
+                    state
+                        .debug
+                        .record_scrape(Some(format!("failed to serialize graph: {}", err)));
                     continue;
                 }
             };
+            if let Err(exceeded) = limits.check_serialized(json_graph.len()) {
+                reject_oversized(exceeded);
+                continue;
+            }
+
+            // Scrapes go on during a freeze, but the first graph is always published.
+            let freeze = freeze::active(&settings.freeze_windows, chrono::Utc::now())
+                .filter(|_| state.revision() > 0 && !override_freeze);
+            state.record_freeze(freeze.clone());
 
-            *state.json.write() = json_graph;
+            let hash = provenance::content_hash(json_graph.as_bytes());
+            if let Some(freeze) = &freeze {
+                if json_graph.as_bytes() != state.published_graph() {
+                    info!(
+                        "publication frozen until {} ({}), deferring the new graph",
+                        freeze.until.to_rfc3339(),
+                        freeze.reason.as_deref().unwrap_or("no reason given")
+                    );
+                }
+            } else if let Some(revision) = state.next_revision(&json_graph) {
+                // A graph fetched from the leader keeps the time it was built at,
+                // so that all replicas serve the same `Last-Modified`.
+                let generated = Provenance::from_parameters(&internal_io.parameters)
+                    .filter(|upstream| upstream.hash.as_ref() == Some(&hash))
+                    .and_then(|upstream| upstream.generated)
+                    .unwrap_or_else(|| chrono::Utc::now().to_rfc3339());
+                let provenance = Provenance {
+                    revision: Some(revision),
+                    hash: Some(hash),
+                    generated: Some(generated),
+                    source: internal_io
+                        .parameters
+                        .get(SERVING_SOURCE_PARAM_KEY)
+                        .cloned(),
+                };
+                let v2_json = serde_json::to_string(&GraphV2::new(
+                    &internal_io.graph,
+                    Some(provenance.clone()),
+                    &keys,
+                ))
+                .map_err(|err| error!("Failed to serialize v2 graph: {}", err))
+                .ok();
+                let artifact = if settings.internal_graph_api {
+                    let published = chrono::Utc::now().timestamp();
+                    artifact::encode(revision, published, &internal_io.graph)
+                        .map_err(|err| error!("Failed to encode graph artifact: {}", err))
+                        .ok()
+                } else {
+                    None
+                };
+                let event = audit::AuditEvent::new(
+                    &scrape_id,
+                    &provenance,
+                    &published_graph,
+                    &internal_io.graph,
+                );
+                let graph_event = events::GraphEvent::new(revision, &provenance, &event.diff);
+                state.publish(Publication {
+                    revision,
+                    json: json_graph,
+                    arch_json,
+                    provenance,
+                    v2_json,
+                    artifact,
+                    secondary_metadata: Some(SecondaryMetadata::new(
+                        revision,
+                        &internal_io.graph,
+                        &keys,
+                    )),
+                });
+                if let Some(path) = &settings.freeze_state_path {
+                    if let Err(err) = freeze::write_state(path, revision, &internal_io.graph) {
+                        error!("failed to keep the published graph: {:#}", err);
+                    }
+                }
+                state
+                    .changelog
+                    .record(revision, state.graph_hash(), event.diff.clone());
+                state.audit.record(event, &internal_io.graph);
+                state.graph_events.publish(graph_event);
+            }
+            state.debug.record_scrape(None);
+            state
+                .notifier
+                .resolve(notify::EventKind::ValidationRejection);
+            state.notifier.resolve(notify::EventKind::LimitExceeded);
+            state.debug.record_graph(&internal_io.graph);
+            get_active_span(|span| {
+                span.set_attribute(
+                    Key::new("releases").i64(internal_io.graph.releases_count() as i64),
+                );
+                span.set_attribute(Key::new("edges").i64(internal_io.graph.edges_count() as i64));
+                span.set_attribute(Key::new("revision").i64(state.revision() as i64));
+                span.set_attribute(Key::new("frozen").bool(freeze.is_some()));
+            });
             nodes_count = internal_io.graph.releases_count() as i64;
+            if freeze.is_none() {
+                if summary.is_some() {
+                    last_published = summary;
+                }
+                if override_thresholds {
+                    state.validation_override.store(false, Ordering::SeqCst);
+                }
+                if override_freeze {
+                    state.freeze_override.store(false, Ordering::SeqCst);
+                }
+                published_graph = internal_io.graph;
+            }
         }
 
         // Record scrape duration
-        scrape_value = scrape_timer.stop_and_discard();
+        let scrape_value: f64 = scrape_timer.stop_and_discard();
 
         if first_success {
-            *state.ready.write() = true;
+            *state.ready.write() = !state.is_shutting_down();
             first_success = false;
-            GRAPH_UPSTREAM_INITIAL_SCRAPE.set(scrape_value);
+            GRAPH_UPSTREAM_INITIAL_SCRAPE
+                .with_label_values(&[tenant])
+                .set(scrape_value);
         } else {
-            UPSTREAM_SCRAPES_DURATION.observe(scrape_value);
+            UPSTREAM_SCRAPES_DURATION
+                .with_label_values(&[tenant])
+                .observe(scrape_value);
         }
 
-        GRAPH_LAST_SUCCESSFUL_REFRESH.set(chrono::Utc::now().timestamp() as i64);
-
-        GRAPH_FINAL_RELEASES.set(nodes_count);
+        GRAPH_LAST_SUCCESSFUL_REFRESH
+            .with_label_values(&[tenant])
+            .set(chrono::Utc::now().timestamp());
+        GRAPH_FINAL_RELEASES
+            .with_label_values(&[tenant])
+            .set(nodes_count);
         debug!("graph update completed, {} valid releases", nodes_count);
+
+        budget::check(state, &settings);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mock_state() -> State {
+        State::new(
+            HashSet::new(),
+            Arc::new(RwLock::new(true)),
+            Arc::new(RwLock::new(true)),
+            vec![],
+            Box::leak(Box::new(prometheus::Registry::new())),
+        )
+    }
+
+    /// Publish the JSON graph under the next revision, unless it is published already.
+    fn publish(state: &State, json: String, arch_json: HashMap<String, String>) -> Option<u64> {
+        let revision = state.next_revision(&json)?;
+        state.publish(Publication {
+            revision,
+            json,
+            arch_json,
+            ..Default::default()
+        });
+        Some(revision)
+    }
+
+    #[test]
+    fn shutdown() {
+        let state = mock_state();
+        assert!(state.is_ready());
+
+        state.request_shutdown();
+        assert!(state.is_shutting_down());
+        assert!(!state.is_ready());
+        // The scrape loop is woken up from its pause.
+        assert!(state.rescrape.wait(Duration::from_secs(60)));
+    }
+
+    #[test]
+    fn long_poll_params() {
+        assert_eq!(long_poll_timeout("channel=stable").unwrap(), None);
+        assert_eq!(long_poll_timeout("wait_for_change=false").unwrap(), None);
+        assert_eq!(
+            long_poll_timeout("wait_for_change=true").unwrap(),
+            Some(DEFAULT_LONG_POLL_TIMEOUT)
+        );
+        assert_eq!(
+            long_poll_timeout("wait_for_change=true&timeout=30s").unwrap(),
+            Some(Duration::from_secs(30))
+        );
+        assert_eq!(
+            long_poll_timeout("wait_for_change=true&timeout=3600").unwrap(),
+            Some(MAX_LONG_POLL_TIMEOUT)
+        );
+        assert!(long_poll_timeout("wait_for_change=yes").is_err());
+        assert!(long_poll_timeout("wait_for_change=true&timeout=soon").is_err());
+
+        assert_eq!(
+            parse_etag(&format_etag("sha256:4c6f")).as_deref(),
+            Some("sha256:4c6f")
+        );
+        assert_eq!(
+            parse_etag("W/\"sha256:4c6f\"").as_deref(),
+            Some("sha256:4c6f")
+        );
+        assert_eq!(parse_etag("*"), None);
+    }
+
+    #[test]
+    fn long_poll_wakes_up_on_change() -> Fallible<()> {
+        let rt = commons::testing::init_runtime()?;
+        let state = mock_state();
+
+        assert_eq!(publish(&state, "{}".to_string(), HashMap::new()), Some(1));
+        assert_eq!(state.revision(), 1);
+        assert_eq!(publish(&state, "{}".to_string(), HashMap::new()), None);
+        assert_eq!(state.revision(), 1);
+
+        // Nothing changes, so the wait times out.
+        assert!(!rt.block_on(state.wait_for_change(None, None, Duration::from_millis(10))));
+
+        // The client has an outdated graph, e.g. from another replica.
+        assert!(rt.block_on(state.wait_for_change(
+            None,
+            Some("sha256:0000"),
+            Duration::from_secs(60)
+        )));
+        let hash = state.graph_hash();
+        assert_eq!(hash, provenance::content_hash(b"{}"));
+
+        let publisher = {
+            let state = state.clone();
+            std::thread::spawn(move || {
+                std::thread::sleep(Duration::from_millis(50));
+                publish(&state, r#"{"nodes":[]}"#.to_string(), HashMap::new());
+            })
+        };
+        assert!(rt.block_on(state.wait_for_change(None, Some(&hash), Duration::from_secs(60))));
+        publisher.join().unwrap();
+        assert_eq!(state.revision(), 2);
+        assert_ne!(state.graph_hash(), hash);
+
+        Ok(())
+    }
+
+    #[test]
+    fn long_poll_arch_subgraph() -> Fallible<()> {
+        let rt = commons::testing::init_runtime()?;
+        let state = mock_state();
+        let arch_json = |json: &str| -> HashMap<String, String> {
+            vec![("arm64".to_string(), json.to_string())]
+                .into_iter()
+                .collect()
+        };
+        let serve = |etag: &str| {
+            rt.block_on(index(
+                actix_web::test::TestRequest::with_uri(
+                    "/v1/graph?arch=arm64&wait_for_change=true&timeout=0",
+                )
+                .insert_header((header::IF_NONE_MATCH, etag.to_string()))
+                .to_http_request(),
+                actix_web::web::Data::new(state.clone()),
+            ))
+        };
+
+        publish(&state, "{}".to_string(), arch_json(r#"{"nodes":[]}"#));
+        let arm64_etag = format_etag(&provenance::content_hash(br#"{"nodes":[]}"#));
+        assert_ne!(arm64_etag, format_etag(&state.graph_hash()));
+
+        // The client has the current subgraph, so the wait times out with its ETag.
+        let resp = serve(&arm64_etag)?;
+        assert_eq!(resp.status(), 304);
+        assert_eq!(resp.headers().get(header::ETAG).unwrap(), &arm64_etag);
+
+        // A change of another part of the graph leaves the subgraph unchanged.
+        publish(&state, "{ }".to_string(), arch_json(r#"{"nodes":[]}"#));
+        let resp = serve(&arm64_etag)?;
+        assert_eq!(resp.status(), 304);
+        assert_eq!(resp.headers().get(header::ETAG).unwrap(), &arm64_etag);
+
+        // The client has an outdated subgraph.
+        let resp = serve(&format_etag(&state.graph_hash()))?;
+        assert_eq!(resp.status(), 200);
+        assert_eq!(resp.headers().get(header::ETAG).unwrap(), &arm64_etag);
+
+        let publisher = {
+            let state = state.clone();
+            std::thread::spawn(move || {
+                std::thread::sleep(Duration::from_millis(50));
+                publish(&state, "{  }".to_string(), arch_json("{}"));
+            })
+        };
+        let hash = provenance::content_hash(br#"{"nodes":[]}"#);
+        assert!(rt.block_on(state.wait_for_change(
+            Some("arm64"),
+            Some(&hash),
+            Duration::from_secs(60)
+        )));
+        publisher.join().unwrap();
+        assert_ne!(state.subgraph_hash(Some("arm64")), Some(hash));
+
+        Ok(())
+    }
+
+    #[test]
+    fn arch_subgraphs() -> Fallible<()> {
+        let arch_metadata = |arch: &str| -> cincinnati::MapImpl<String, String> {
+            [
+                (String::from("version_suffix"), format!("+{}", arch)),
+                (
+                    String::from("io.openshift.upgrades.graph.release.arch"),
+                    arch.to_string(),
+                ),
+            ]
+            .iter()
+            .cloned()
+            .collect()
+        };
+        let graph = cincinnati::testing::generate_custom_graph(
+            "image",
+            vec![
+                (0, arch_metadata("amd64")),
+                (1, arch_metadata("amd64")),
+                (2, arch_metadata("arm64")),
+                (3, {
+                    let mut metadata = arch_metadata("multi");
+                    metadata.insert(
+                        String::from("io.openshift.upgrades.graph.release.architectures"),
+                        String::from("arm64,s390x"),
+                    );
+                    metadata
+                }),
+            ],
+            Some(vec![(0, 1)]),
+        );
+
+        let (json, arch_json) = serialize_graph(&graph, &MetadataKeys::default())?;
+        assert_eq!(json, serde_json::to_string(&graph)?);
+        assert_eq!(arch_json.len(), 4);
+        let arm64: serde_json::Value = serde_json::from_str(&arch_json["arm64"])?;
+        let mut versions: Vec<&str> = arm64["nodes"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter_map(|node| node["version"].as_str())
+            .collect();
+        versions.sort_unstable();
+        assert_eq!(versions, vec!["2.0.0+arm64", "3.0.0+multi"]);
+        let multi: serde_json::Value = serde_json::from_str(&arch_json["multi"])?;
+        assert_eq!(multi["nodes"].as_array().unwrap().len(), 1);
+
+        let arm64_hash = provenance::content_hash(arch_json["arm64"].as_bytes());
+
+        let state = mock_state();
+        publish(&state, json, arch_json);
+        assert_eq!(
+            state.published_archs(),
+            vec!["amd64", "arm64", "multi", "s390x"]
+        );
+        assert!(state.published_arch_graph("ppc64le").is_none());
+
+        // The subgraph is served with its own hash.
+        let rt = commons::testing::init_runtime()?;
+        let resp = rt.block_on(index(
+            actix_web::test::TestRequest::with_uri("/v1/graph?arch=arm64").to_http_request(),
+            actix_web::web::Data::new(state.clone()),
+        ))?;
+        assert_eq!(
+            resp.headers().get(provenance::HASH_HEADER).unwrap(),
+            arm64_hash.as_str()
+        );
+        assert_eq!(
+            resp.headers().get(header::ETAG).unwrap(),
+            format_etag(&arm64_hash).as_str()
+        );
+
+        assert_eq!(
+            ARCH_PARAM.get("channel=stable&arch=arm64").unwrap(),
+            "arm64"
+        );
+        assert_eq!(ARCH_PARAM.get("channel=stable"), None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn internal_graph_artifact() -> Fallible<()> {
+        let rt = commons::testing::init_runtime()?;
+        let state = mock_state();
+        let serve = |etag: Option<&str>| {
+            let mut req = actix_web::test::TestRequest::default();
+            if let Some(etag) = etag {
+                req = req.insert_header((header::IF_NONE_MATCH, etag));
+            }
+            rt.block_on(internal_graph(
+                req.to_http_request(),
+                actix_web::web::Data::new(state.clone()),
+            ))
+        };
+
+        assert_eq!(
+            serve(None)?.status(),
+            actix_web::http::StatusCode::SERVICE_UNAVAILABLE
+        );
+
+        let graph = cincinnati::testing::generate_graph(true, false);
+        state.publish(Publication {
+            revision: 3,
+            json: "{}".to_string(),
+            artifact: Some(artifact::encode(3, 0, &graph)?),
+            ..Default::default()
+        });
+        let etag = format_etag(&provenance::content_hash(b"{}"));
+        let resp = serve(None)?;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::OK);
+        assert_eq!(
+            resp.headers().get(header::CONTENT_TYPE).unwrap(),
+            artifact::CONTENT_TYPE
+        );
+        assert_eq!(resp.headers().get(header::ETAG).unwrap(), etag.as_str());
+
+        assert_eq!(
+            serve(Some(&etag))?.status(),
+            actix_web::http::StatusCode::NOT_MODIFIED
+        );
+        assert_eq!(
+            serve(Some("\"3\""))?.status(),
+            actix_web::http::StatusCode::OK
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn serve_snapshot() -> Fallible<()> {
+        let rt = commons::testing::init_runtime()?;
+        let state = mock_state();
+        let serve = |query: &str| {
+            rt.block_on(index(
+                actix_web::test::TestRequest::with_uri(&format!("/v1/graph?{}", query))
+                    .to_http_request(),
+                actix_web::web::Data::new(state.clone()),
+            ))
+        };
+
+        let json = r#"{"nodes":[],"edges":[]}"#.to_string();
+        let hash = provenance::content_hash(json.as_bytes());
+        state.snapshots().record(
+            Provenance {
+                revision: Some(1),
+                hash: Some(hash.clone()),
+                ..Default::default()
+            },
+            json.clone().into(),
+        );
+        publish(&state, r#"{"nodes":[]}"#.to_string(), HashMap::new());
+
+        let resp = serve(&format!("snapshot={}", hash))?;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::OK);
+        assert_eq!(
+            resp.headers().get(header::ETAG).unwrap(),
+            format_etag(&hash).as_str()
+        );
+        assert_eq!(
+            resp.headers().get(provenance::HASH_HEADER).unwrap(),
+            hash.as_str()
+        );
+        let body = actix_web::body::MessageBody::try_into_bytes(resp.into_body()).ok();
+        assert_eq!(body.as_deref(), Some(json.as_bytes()));
+
+        assert_eq!(
+            serve("snapshot=sha256:0000").unwrap_err().status_code(),
+            actix_web::http::StatusCode::NOT_FOUND
+        );
+        assert_eq!(
+            serve(&format!("snapshot={}&arch=arm64", hash))
+                .unwrap_err()
+                .status_code(),
+            actix_web::http::StatusCode::BAD_REQUEST
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn serve_v2_graph() -> Fallible<()> {
+        let rt = commons::testing::init_runtime()?;
+        let state = mock_state();
+        let serve = |query: &str| {
+            rt.block_on(index_v2(
+                actix_web::test::TestRequest::with_uri(&format!("/v2/graph?{}", query))
+                    .to_http_request(),
+                actix_web::web::Data::new(state.clone()),
+            ))
+        };
+
+        let resp = serve("")?;
+        assert_eq!(
+            resp.status(),
+            actix_web::http::StatusCode::SERVICE_UNAVAILABLE
+        );
+        assert_eq!(
+            resp.headers().get(header::CONTENT_TYPE).unwrap(),
+            commons::PROBLEM_JSON_CONTENT_TYPE
+        );
+
+        let graph = cincinnati::testing::generate_graph(true, false);
+        let provenance = Provenance {
+            revision: Some(1),
+            ..Default::default()
+        };
+        state.publish(Publication {
+            revision: 1,
+            json: "{}".to_string(),
+            v2_json: Some(serde_json::to_string(&GraphV2::new(
+                &graph,
+                Some(provenance.clone()),
+                &MetadataKeys::default(),
+            ))?),
+            provenance,
+            ..Default::default()
+        });
+
+        let resp = serve("")?;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::OK);
+        assert_eq!(
+            resp.headers().get(header::ETAG).unwrap(),
+            format_etag(&provenance::content_hash(b"{}")).as_str()
+        );
+        assert_eq!(
+            resp.headers().get(header::CONTENT_TYPE).unwrap(),
+            v2::CONTENT_TYPE
+        );
+        let body = actix_web::body::MessageBody::try_into_bytes(resp.into_body())
+            .ok()
+            .unwrap_or_default();
+        let json: serde_json::Value = serde_json::from_slice(&body)?;
+        assert_eq!(json["version"], v2::VERSION);
+        assert_eq!(json["provenance"]["revision"], 1);
+        assert_eq!(json["edges"].as_array().unwrap().len(), 3);
+        assert_eq!(json["edges"][0]["risks"][0]["name"], "BrokenUpdates");
+
+        assert!(serve("arch=amd64").is_err());
+        assert!(serve("snapshot=sha256:0000").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn serve_precompressed_graph() -> Fallible<()> {
+        let rt = commons::testing::init_runtime()?;
+        let state = mock_state();
+        let serve = |accept_encoding: &str| {
+            rt.block_on(index(
+                actix_web::test::TestRequest::with_uri("/v1/graph")
+                    .insert_header((header::ACCEPT_ENCODING, accept_encoding))
+                    .to_http_request(),
+                actix_web::web::Data::new(state.clone()),
+            ))
+        };
+
+        let graph = cincinnati::testing::generate_custom_graph(
+            "image",
+            (0..50).map(|i| (i, Default::default())).collect(),
+            None,
+        );
+        let json = serde_json::to_string(&graph)?;
+        publish(&state, json.clone(), HashMap::new());
+        assert_eq!(state.published_graph(), json.as_bytes());
+
+        let resp = serve("gzip")?;
+        assert_eq!(
+            resp.headers().get(header::CONTENT_ENCODING).unwrap(),
+            "gzip"
+        );
+        let body = actix_web::body::MessageBody::try_into_bytes(resp.into_body())
+            .ok()
+            .unwrap_or_default();
+        let mut decompressed = String::new();
+        std::io::Read::read_to_string(
+            &mut flate2::read::GzDecoder::new(&body[..]),
+            &mut decompressed,
+        )?;
+        assert_eq!(decompressed, json);
+
+        let resp = serve("identity")?;
+        assert!(resp.headers().get(header::CONTENT_ENCODING).is_none());
+        let body = actix_web::body::MessageBody::try_into_bytes(resp.into_body()).ok();
+        assert_eq!(body.as_deref(), Some(json.as_bytes()));
+
+        // Per-architecture subgraphs are precompressed as well.
+        let arch_json: HashMap<String, String> = vec![("arm64".to_string(), json.clone())]
+            .into_iter()
+            .collect();
+        publish(&state, format!("{} ", json), arch_json);
+        let resp = rt.block_on(index(
+            actix_web::test::TestRequest::with_uri("/v1/graph?arch=arm64")
+                .insert_header((header::ACCEPT_ENCODING, "zstd"))
+                .to_http_request(),
+            actix_web::web::Data::new(state.clone()),
+        ))?;
+        assert_eq!(
+            resp.headers().get(header::CONTENT_ENCODING).unwrap(),
+            "zstd"
+        );
+        assert_eq!(
+            state.published_arch_graph("arm64").unwrap(),
+            json.as_bytes()
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn serve_cache_headers() -> Fallible<()> {
+        let rt = commons::testing::init_runtime()?;
+        let state = mock_state().with_cache_max_age(Duration::from_secs(60));
+        let serve = |headers: Vec<(header::HeaderName, String)>| {
+            let mut req = actix_web::test::TestRequest::with_uri("/v1/graph");
+            for header in headers {
+                req = req.insert_header(header);
+            }
+            rt.block_on(index(
+                req.to_http_request(),
+                actix_web::web::Data::new(state.clone()),
+            ))
+        };
+
+        // Nothing was published yet.
+        let resp = serve(vec![])?;
+        assert!(resp.headers().get(header::LAST_MODIFIED).is_none());
+
+        publish(&state, r#"{"nodes":[]}"#.to_string(), HashMap::new());
+        let resp = serve(vec![])?;
+        assert_eq!(resp.status(), 200);
+        assert_eq!(
+            resp.headers().get(header::CACHE_CONTROL).unwrap(),
+            "public, max-age=60"
+        );
+        assert!(resp.headers().get(header::AGE).is_none());
+        assert_eq!(
+            resp.headers().get(header::VARY).unwrap(),
+            "accept, accept-encoding"
+        );
+        let last_modified = resp
+            .headers()
+            .get(header::LAST_MODIFIED)
+            .unwrap()
+            .to_str()?
+            .to_string();
+
+        let resp = serve(vec![(header::IF_MODIFIED_SINCE, last_modified.clone())])?;
+        assert_eq!(resp.status(), 304);
+        assert_eq!(
+            resp.headers().get(header::ETAG).unwrap(),
+            &format_etag(&state.graph_hash())
+        );
+
+        let earlier = header::HttpDate::from(SystemTime::now() - Duration::from_secs(3600));
+        let resp = serve(vec![(header::IF_MODIFIED_SINCE, earlier.to_string())])?;
+        assert_eq!(resp.status(), 200);
+
+        // If-None-Match takes precedence.
+        let resp = serve(vec![
+            (header::IF_MODIFIED_SINCE, last_modified),
+            (header::IF_NONE_MATCH, "\"0\"".to_string()),
+        ])?;
+        assert_eq!(resp.status(), 200);
+
+        Ok(())
+    }
+
+    #[test]
+    fn serve_frozen_graph() -> Fallible<()> {
+        let rt = commons::testing::init_runtime()?;
+        let state = mock_state();
+        let serve = || {
+            rt.block_on(index(
+                actix_web::test::TestRequest::with_uri("/v1/graph").to_http_request(),
+                actix_web::web::Data::new(state.clone()),
+            ))
+        };
+        publish(&state, r#"{"nodes":[]}"#.to_string(), HashMap::new());
+
+        let resp = serve()?;
+        assert!(resp.headers().get(freeze::FROZEN_UNTIL_HEADER).is_none());
+
+        let until = chrono::Utc::now() + chrono::Duration::hours(1);
+        state.record_freeze(Some(freeze::Freeze {
+            until,
+            reason: None,
+        }));
+        let resp = serve()?;
+        assert_eq!(
+            resp.headers().get(freeze::FROZEN_UNTIL_HEADER).unwrap(),
+            until.to_rfc3339().as_str()
+        );
+
+        // A freeze which ended since the last scrape is not advertised.
+        state.record_freeze(Some(freeze::Freeze {
+            until: chrono::Utc::now() - chrono::Duration::hours(1),
+            reason: None,
+        }));
+        let resp = serve()?;
+        assert!(resp.headers().get(freeze::FROZEN_UNTIL_HEADER).is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn rescrape_trigger() {
+        let trigger = Arc::new(RescrapeTrigger::default());
+        assert!(!trigger.wait(Duration::from_millis(10)));
+
+        trigger.trigger();
+        assert!(trigger.wait(Duration::from_secs(60)));
+        assert!(!trigger.wait(Duration::from_millis(10)));
+
+        let waiter = {
+            let trigger = trigger.clone();
+            std::thread::spawn(move || trigger.wait(Duration::from_secs(60)))
+        };
+        std::thread::sleep(Duration::from_millis(50));
+        trigger.trigger();
+        assert!(waiter.join().unwrap());
     }
 }
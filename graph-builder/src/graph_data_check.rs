@@ -0,0 +1,214 @@
+//! Check candidate graph-data against the scraped releases.
+//!
+//! CI jobs of the graph-data repository upload an archive of the candidate
+//! graph-data. It is applied to the releases of the last scrape by the
+//! configured plugins, from the graph-data parser on. The resulting graph is
+//! validated and compared with the published one.
+
+use crate::graph::State;
+use crate::validation;
+use cincinnati::diff::GraphDiff;
+use cincinnati::plugins::internal::github_openshift_secondary_metadata_scraper::plugin::GRAPH_DATA_DIR_PARAM_KEY;
+use cincinnati::plugins::internal::openshift_secondary_metadata_parser::OpenshiftSecondaryMetadataParserPlugin;
+use cincinnati::plugins::PluginIO;
+use cincinnati::Graph;
+use commons::prelude_errors::*;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Maximum size of an uploaded archive.
+pub static MAX_ARCHIVE_SIZE: usize = 32 * 1024 * 1024;
+
+/// Maximum size of the extracted archive content.
+static MAX_EXTRACTED_SIZE: u64 = 256 * 1024 * 1024;
+
+/// Timeout for processing the candidate graph-data.
+static CHECK_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Reasons for not checking candidate graph-data.
+#[derive(Debug)]
+pub enum CheckError {
+    /// The scraped releases or the graph-data parser are not available.
+    NotReady(String),
+    /// The uploaded archive can't be used.
+    InvalidArchive(String),
+}
+
+impl std::error::Error for CheckError {}
+
+impl fmt::Display for CheckError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CheckError::NotReady(reason) | CheckError::InvalidArchive(reason) => {
+                write!(f, "{}", reason)
+            }
+        }
+    }
+}
+
+/// Outcome of a graph-data check.
+#[derive(Debug, Default, Serialize)]
+pub struct CheckReport {
+    /// Errors which would prevent the graph from being published.
+    pub errors: Vec<String>,
+    /// Non-blocking findings.
+    pub lints: Vec<String>,
+    /// Changes compared to the published graph, if the graph could be built.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub diff: Option<GraphDiff>,
+}
+
+/// Apply the candidate graph-data in `archive`, a gzipped tarball, and report the outcome.
+pub async fn check(state: &State, archive: Vec<u8>) -> Result<CheckReport, CheckError> {
+    let plugins = state.configured_plugins();
+    let parser = plugins
+        .iter()
+        .position(|plugin| plugin.get_name() == OpenshiftSecondaryMetadataParserPlugin::PLUGIN_NAME)
+        .ok_or_else(|| {
+            CheckError::NotReady("the plugin chain has no graph-data parser".to_string())
+        })?;
+    let mut io = state
+        .debug()
+        .graph_data_input()
+        .ok_or_else(|| CheckError::NotReady("no releases have been scraped yet".to_string()))?;
+
+    let tmpdir = tempfile::tempdir()
+        .map_err(|e| CheckError::NotReady(format!("creating temporary directory: {}", e)))?;
+    let data_dir = {
+        let target = tmpdir.path().to_owned();
+        tokio::task::spawn_blocking(move || extract(&archive, &target))
+            .await
+            .map_err(|e| CheckError::InvalidArchive(e.to_string()))?
+            .map_err(|e| CheckError::InvalidArchive(format!("{:#}", e)))?
+    };
+    io.parameters.insert(
+        GRAPH_DATA_DIR_PARAM_KEY.to_string(),
+        data_dir.to_string_lossy().to_string(),
+    );
+
+    let mut report = CheckReport::default();
+    let result = tokio::time::timeout(
+        CHECK_TIMEOUT,
+        cincinnati::plugins::process(plugins.into_iter().skip(parser), PluginIO::InternalIO(io)),
+    )
+    .await;
+
+    match result {
+        Err(_) => report
+            .errors
+            .push(format!("processing timed out after {:?}", CHECK_TIMEOUT)),
+        Ok(Err(e)) => report.errors.push(format!("{:#}", e)),
+        Ok(Ok(io)) => {
+            report.errors = validation::check_structure(&io.graph);
+            report.lints = validation::lint(&io.graph, &state.metadata_keys());
+
+            let published = state.published_graph();
+            let published: Graph = if published.is_empty() {
+                Graph::default()
+            } else {
+                serde_json::from_slice(&published).map_err(|e| {
+                    CheckError::NotReady(format!("parsing the published graph: {}", e))
+                })?
+            };
+            report.diff = Some(GraphDiff::between(&published, &io.graph));
+        }
+    }
+
+    Ok(report)
+}
+
+/// Extract a gzipped tarball into `target` and return the graph-data directory.
+///
+/// Only regular files and directories are extracted. Repository archives
+/// usually wrap their content in a single top-level directory, which is then
+/// used as graph-data directory.
+pub(crate) fn extract(archive: &[u8], target: &Path) -> Fallible<PathBuf> {
+    use flate2::read::GzDecoder;
+    use tar::{Archive, EntryType};
+
+    let mut archive = Archive::new(GzDecoder::new(archive));
+    let mut extracted_size = 0;
+
+    for entry in archive.entries().context("reading archive")? {
+        let mut entry = entry.context("reading archive entry")?;
+        let path = entry.path()?.to_path_buf();
+
+        match entry.header().entry_type() {
+            EntryType::Regular | EntryType::Directory => {}
+            // Repository archives carry the commit in a global header.
+            EntryType::XGlobalHeader => continue,
+            other => bail!("unsupported entry type {:?} for {:?}", other, path),
+        }
+
+        extracted_size += entry.header().size()?;
+        ensure!(
+            extracted_size <= MAX_EXTRACTED_SIZE,
+            "extracted archive exceeds {} bytes",
+            MAX_EXTRACTED_SIZE
+        );
+
+        // This refuses paths outside of the target directory.
+        entry
+            .unpack_in(target)
+            .context(format!("unpacking {:?}", path))?;
+    }
+
+    if target.join("version").is_file() {
+        return Ok(target.to_path_buf());
+    }
+
+    let entries = std::fs::read_dir(target)?.collect::<Result<Vec<_>, _>>()?;
+    match entries.as_slice() {
+        [entry] if entry.file_type()?.is_dir() && entry.path().join("version").is_file() => {
+            Ok(entry.path())
+        }
+        _ => bail!("no 'version' file at the root of the archive or of its top-level directory"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn archive(files: &[(&str, &str)]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(flate2::write::GzEncoder::new(
+            vec![],
+            flate2::Compression::default(),
+        ));
+        for (path, content) in files {
+            let mut header = tar::Header::new_gnu();
+            header.set_entry_type(tar::EntryType::Regular);
+            header.set_size(content.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder
+                .append_data(&mut header, path, content.as_bytes())
+                .unwrap();
+        }
+        builder.into_inner().unwrap().finish().unwrap()
+    }
+
+    #[test]
+    fn extract_archive() -> Fallible<()> {
+        let tmpdir = tempfile::tempdir()?;
+        let data_dir = extract(
+            &archive(&[
+                ("cincinnati-graph-data-abc/version", "1.0.0"),
+                ("cincinnati-graph-data-abc/channels/stable-4.10.yaml", ""),
+            ]),
+            tmpdir.path(),
+        )?;
+        assert_eq!(data_dir, tmpdir.path().join("cincinnati-graph-data-abc"));
+
+        let tmpdir = tempfile::tempdir()?;
+        let data_dir = extract(&archive(&[("version", "1.0.0")]), tmpdir.path())?;
+        assert_eq!(data_dir, tmpdir.path());
+
+        let tmpdir = tempfile::tempdir()?;
+        assert!(extract(&archive(&[("channels/a.yaml", "")]), tmpdir.path()).is_err());
+        assert!(extract(b"not an archive", tmpdir.path()).is_err());
+
+        Ok(())
+    }
+}
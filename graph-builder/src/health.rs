@@ -0,0 +1,336 @@
+//! Detailed health of the scraper and the published graph.
+//!
+//! Unlike the liveness and readiness probes, the health report tells apart
+//! the subsystems of graph-builder and whether they are fully working,
+//! degraded or failed. It is derived from the debug information recorded by
+//! the scrape loop.
+
+use crate::debug::{DebugInfo, PluginStatus};
+use std::time::Duration;
+
+/// Number of scrape intervals after which the last successful scrape is stale.
+pub static STALE_SCRAPE_INTERVALS: u32 = 3;
+
+/// Health of a subsystem, from best to worst.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Health {
+    /// Fully working.
+    Ok,
+    /// Working, but not as expected.
+    Degraded,
+    /// Not working.
+    Failed,
+}
+
+/// Outcome of the check of a subsystem.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Check {
+    /// Health of the subsystem.
+    pub status: Health,
+    /// Explanation of the status.
+    pub detail: String,
+}
+
+impl Check {
+    fn new(status: Health, detail: impl Into<String>) -> Self {
+        Self {
+            status,
+            detail: detail.into(),
+        }
+    }
+}
+
+/// Freshness of the scraped data.
+#[derive(Clone, Debug, Serialize)]
+pub struct ScraperCheck {
+    /// Health of the scraper.
+    #[serde(flatten)]
+    pub check: Check,
+    /// UTC timestamp of the end of the last successful scrape.
+    pub last_success_timestamp: Option<i64>,
+    /// Seconds since the end of the last successful scrape.
+    pub last_success_age_secs: Option<i64>,
+}
+
+/// Size of the published graph.
+#[derive(Clone, Debug, Serialize)]
+pub struct GraphCheck {
+    /// Health of the graph.
+    #[serde(flatten)]
+    pub check: Check,
+    /// Number of releases.
+    pub releases: u64,
+    /// Number of edges.
+    pub edges: u64,
+}
+
+/// Health of all subsystems, as served by the status service.
+#[derive(Clone, Debug, Serialize)]
+pub struct HealthReport {
+    /// Worst health of all subsystems.
+    pub status: Health,
+    /// Freshness of the last successful scrape.
+    pub scraper: ScraperCheck,
+    /// Reachability of the upstream registries and repositories.
+    pub upstream: Check,
+    /// Outcome of the last runs of the plugins.
+    pub plugins: Check,
+    /// Published graph.
+    pub graph: GraphCheck,
+}
+
+/// Returns whether the last run of a plugin failed.
+fn last_run_failed(plugin: &PluginStatus) -> bool {
+    match (plugin.last_error_timestamp, plugin.last_success_timestamp) {
+        (Some(error), Some(success)) => error >= success,
+        (Some(_), None) => true,
+        (None, _) => false,
+    }
+}
+
+/// Names of the plugins fetching releases or graph-data from an upstream.
+static UPSTREAM_PLUGINS: &[&str] = &[
+    "release-scrape-dockerv2",
+    "github-secondary-metadata-scrape",
+    "dkrv2-secondary-metadata-scrape",
+    "s3-secondary-metadata-scrape",
+    "cincinnati-graph-fetch",
+];
+
+/// Returns whether a plugin talks to an upstream, based on its name.
+fn is_upstream_plugin(plugin: &PluginStatus) -> bool {
+    UPSTREAM_PLUGINS.contains(&plugin.name)
+}
+
+fn check_scraper(info: &DebugInfo, now: i64) -> ScraperCheck {
+    let scrape = &info.scrape;
+    let age = scrape.last_success_timestamp.map(|success| now - success);
+    let stale_after = scrape
+        .max_interval_secs
+        .map(|interval| Duration::from_secs(interval) * STALE_SCRAPE_INTERVALS);
+
+    let check = match (age, &scrape.last_error) {
+        (None, Some(error)) => Check::new(
+            Health::Failed,
+            format!("no successful scrape yet: {}", error),
+        ),
+        (None, None) => Check::new(Health::Degraded, "no scrape has completed yet"),
+        (Some(age), _)
+            if stale_after.is_some_and(|stale_after| age > stale_after.as_secs() as i64) =>
+        {
+            Check::new(
+                Health::Failed,
+                format!("last successful scrape {}s ago", age),
+            )
+        }
+        (Some(_), Some(error)) => {
+            Check::new(Health::Degraded, format!("last scrape failed: {}", error))
+        }
+        (Some(age), None) => Check::new(Health::Ok, format!("last successful scrape {}s ago", age)),
+    };
+
+    ScraperCheck {
+        check,
+        last_success_timestamp: scrape.last_success_timestamp,
+        last_success_age_secs: age,
+    }
+}
+
+fn check_upstream(info: &DebugInfo) -> Check {
+    let upstream: Vec<&PluginStatus> = info
+        .plugins
+        .iter()
+        .filter(|plugin| is_upstream_plugin(plugin))
+        .collect();
+    if upstream.is_empty() {
+        return Check::new(Health::Ok, "no upstream configured");
+    }
+
+    let failing: Vec<&str> = upstream
+        .iter()
+        .filter(|plugin| last_run_failed(plugin))
+        .map(|plugin| plugin.name)
+        .collect();
+    let reached = upstream
+        .iter()
+        .filter(|plugin| plugin.last_success_timestamp.is_some() && !last_run_failed(plugin))
+        .count();
+
+    if failing.is_empty() && reached == upstream.len() {
+        Check::new(Health::Ok, format!("{} upstreams reachable", reached))
+    } else if failing.len() == upstream.len() {
+        Check::new(
+            Health::Failed,
+            format!("no upstream reachable: {}", failing.join(", ")),
+        )
+    } else if failing.is_empty() {
+        Check::new(Health::Degraded, "upstreams not contacted yet")
+    } else {
+        Check::new(
+            Health::Degraded,
+            format!("upstreams unreachable: {}", failing.join(", ")),
+        )
+    }
+}
+
+fn check_plugins(info: &DebugInfo, ready: bool) -> Check {
+    let failing: Vec<&str> = info
+        .plugins
+        .iter()
+        .filter(|plugin| last_run_failed(plugin))
+        .map(|plugin| plugin.name)
+        .collect();
+
+    match (failing.is_empty(), ready) {
+        (true, _) => Check::new(
+            Health::Ok,
+            format!("{} plugins configured", info.plugins.len()),
+        ),
+        // The previously published graph is still served.
+        (false, true) => Check::new(
+            Health::Degraded,
+            format!("plugins failing: {}", failing.join(", ")),
+        ),
+        (false, false) => Check::new(
+            Health::Failed,
+            format!("plugins failing: {}", failing.join(", ")),
+        ),
+    }
+}
+
+fn check_graph(info: &DebugInfo, ready: bool) -> GraphCheck {
+    let check = if !ready {
+        Check::new(Health::Failed, "no graph published yet")
+    } else if info.graph.releases == 0 {
+        Check::new(Health::Degraded, "published graph is empty")
+    } else {
+        Check::new(Health::Ok, "graph published")
+    };
+
+    GraphCheck {
+        check,
+        releases: info.graph.releases,
+        edges: info.graph.edges,
+    }
+}
+
+/// Assess the health of all subsystems at UTC timestamp `now`.
+pub fn assess(info: &DebugInfo, ready: bool, now: i64) -> HealthReport {
+    let scraper = check_scraper(info, now);
+    let upstream = check_upstream(info);
+    let plugins = check_plugins(info, ready);
+    let graph = check_graph(info, ready);
+
+    let status = [
+        scraper.check.status,
+        upstream.status,
+        plugins.status,
+        graph.check.status,
+    ]
+    .iter()
+    .copied()
+    .max()
+    .unwrap_or(Health::Ok);
+
+    HealthReport {
+        status,
+        scraper,
+        upstream,
+        plugins,
+        graph,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::debug::{GraphStatus, ScrapeStatus};
+
+    fn plugin(name: &'static str, success: Option<i64>, error: Option<i64>) -> PluginStatus {
+        PluginStatus {
+            name,
+            last_success_timestamp: success,
+            last_error_timestamp: error,
+            last_error: error.map(|_| "unreachable".to_string()),
+        }
+    }
+
+    #[test]
+    fn upstream_plugins() {
+        assert!(is_upstream_plugin(&plugin(
+            "release-scrape-dockerv2",
+            None,
+            None
+        )));
+        assert!(is_upstream_plugin(&plugin(
+            "cincinnati-graph-fetch",
+            None,
+            None
+        )));
+        assert!(!is_upstream_plugin(&plugin(
+            "local-secondary-metadata-scrape",
+            None,
+            None
+        )));
+        assert!(!is_upstream_plugin(&plugin("my-fetch-wrapper", None, None)));
+    }
+
+    #[test]
+    fn assess_subsystems() {
+        let mut info = DebugInfo {
+            scrape: ScrapeStatus {
+                last_scrape_timestamp: Some(1000),
+                last_success_timestamp: Some(1000),
+                last_error: None,
+                max_interval_secs: Some(60),
+            },
+            plugins: vec![
+                plugin("release-scrape-dockerv2", Some(1000), None),
+                plugin("github-secondary-metadata-scrape", Some(1000), None),
+                plugin("openshift-secondary-metadata-parse", Some(1000), None),
+            ],
+            graph: GraphStatus {
+                releases: 10,
+                edges: 20,
+                blocked_edges: vec![],
+            },
+        };
+
+        let report = assess(&info, true, 1030);
+        assert_eq!(report.status, Health::Ok);
+        assert_eq!(report.scraper.last_success_age_secs, Some(30));
+        assert_eq!(report.graph.releases, 10);
+
+        // One upstream is down, the previous graph is still served.
+        info.scrape.last_scrape_timestamp = Some(1060);
+        info.scrape.last_error = Some("fetching graph-data".to_string());
+        info.plugins[1] = plugin("github-secondary-metadata-scrape", Some(1000), Some(1060));
+        let report = assess(&info, true, 1070);
+        assert_eq!(report.status, Health::Degraded);
+        assert_eq!(report.scraper.check.status, Health::Degraded);
+        assert_eq!(report.upstream.status, Health::Degraded);
+        assert_eq!(report.plugins.status, Health::Degraded);
+        assert_eq!(report.graph.check.status, Health::Ok);
+
+        // The graph is stale after three intervals.
+        let report = assess(&info, true, 1000 + 181);
+        assert_eq!(report.status, Health::Failed);
+        assert_eq!(report.scraper.check.status, Health::Failed);
+
+        // Nothing has been published yet.
+        let report = assess(
+            &DebugInfo {
+                scrape: Default::default(),
+                plugins: vec![plugin("release-scrape-dockerv2", None, None)],
+                graph: Default::default(),
+            },
+            false,
+            1000,
+        );
+        assert_eq!(report.status, Health::Failed);
+        assert_eq!(report.scraper.check.status, Health::Degraded);
+        assert_eq!(report.upstream.status, Health::Degraded);
+        assert_eq!(report.graph.check.status, Health::Failed);
+    }
+}
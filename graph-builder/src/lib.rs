@@ -13,9 +13,28 @@ extern crate structopt;
 #[macro_use]
 extern crate cincinnati;
 
+pub mod audit;
+pub mod budget;
+pub mod changelog;
 pub mod config;
+pub mod coordination;
+pub mod debug;
+pub mod events;
+pub mod freeze;
 pub mod graph;
+pub mod graph_data_check;
+pub mod health;
+pub mod limits;
+pub mod notify;
+pub mod once;
+pub mod preflight;
+pub mod reload;
+pub mod secondary_metadata;
+pub mod shutdown;
+pub mod snapshots;
 pub mod status;
+pub mod validate_graph_data;
+pub mod validation;
 
 #[allow(dead_code)]
 /// Build info
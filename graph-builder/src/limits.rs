@@ -0,0 +1,215 @@
+//! Protective limits of the published graph.
+//!
+//! A runaway upstream, e.g. a repository flooded with tags or releases with
+//! huge metadata, must neither get a multi-hundred-megabyte graph published
+//! nor make the service run out of memory serving it. Graphs produced by the
+//! plugin chain which exceed one of the configured limits are refused like
+//! graphs failing validation: the previously published graph keeps being
+//! served. The number of scraped tags is bounded by the registry scraper
+//! itself, so that a flooded repository fails the scrape before its releases
+//! are all held in memory.
+
+use crate::config::AppSettings;
+use cincinnati::{Graph, Release};
+use prometheus::{IntCounterVec, Opts};
+use std::fmt;
+
+lazy_static! {
+    pub(crate) static ref GRAPH_LIMIT_REJECTIONS: IntCounterVec = IntCounterVec::new(
+        Opts::new(
+            "graph_limit_rejections_total",
+            "Total number of graphs which were not published because they exceeded a protective limit"
+        ),
+        &["limit", "tenant"]
+    )
+    .unwrap();
+}
+
+/// Default maximum number of releases.
+pub static DEFAULT_MAX_RELEASES: u64 = 50_000;
+
+/// Default maximum number of edges.
+pub static DEFAULT_MAX_EDGES: u64 = 5_000_000;
+
+/// Default maximum size of the serialized graph, in bytes.
+pub static DEFAULT_MAX_GRAPH_BYTES: usize = 512 * 1024 * 1024;
+
+/// Default maximum size of the metadata of a single release, in bytes.
+pub static DEFAULT_MAX_METADATA_BYTES: usize = 1024 * 1024;
+
+/// Maximum size of a published graph, unlimited if unset.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Limits {
+    /// Maximum number of releases.
+    pub max_releases: Option<u64>,
+    /// Maximum number of edges.
+    pub max_edges: Option<u64>,
+    /// Maximum size of the serialized graph, in bytes.
+    pub max_graph_bytes: Option<usize>,
+    /// Maximum size of the metadata of a single release, in bytes.
+    pub max_metadata_bytes: Option<usize>,
+}
+
+/// A limit exceeded by a graph.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LimitExceeded {
+    /// Name of the limit, as used in the metric labels.
+    pub limit: &'static str,
+    /// Description of the violation.
+    pub reason: String,
+}
+
+impl std::error::Error for LimitExceeded {}
+
+impl fmt::Display for LimitExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "graph exceeds protective limit: {}", self.reason)
+    }
+}
+
+impl LimitExceeded {
+    /// Count the rejection of a graph of `tenant` because of this limit.
+    pub fn record(&self, tenant: &str) {
+        GRAPH_LIMIT_REJECTIONS
+            .with_label_values(&[self.limit, tenant])
+            .inc();
+    }
+}
+
+impl Limits {
+    /// Returns the limits configured in the settings.
+    pub fn from_settings(settings: &AppSettings) -> Self {
+        Self {
+            max_releases: settings.limits_max_releases,
+            max_edges: settings.limits_max_edges,
+            max_graph_bytes: settings.limits_max_graph_bytes,
+            max_metadata_bytes: settings.limits_max_metadata_bytes,
+        }
+    }
+
+    /// Check the release and edge counts of the graph, and the size of the
+    /// metadata of its releases.
+    pub fn check_graph(&self, graph: &Graph) -> Result<(), LimitExceeded> {
+        let exceeds = |count: u64, max: Option<u64>| max.filter(|max| count > *max);
+
+        if let Some(max) = exceeds(graph.releases_count(), self.max_releases) {
+            return Err(LimitExceeded {
+                limit: "releases",
+                reason: format!("{} releases, maximum is {}", graph.releases_count(), max),
+            });
+        }
+        if let Some(max) = exceeds(graph.edges_count(), self.max_edges) {
+            return Err(LimitExceeded {
+                limit: "edges",
+                reason: format!("{} edges, maximum is {}", graph.edges_count(), max),
+            });
+        }
+
+        if let Some(max) = self.max_metadata_bytes {
+            let largest = graph
+                .iter_releases()
+                .filter_map(|release| match release {
+                    Release::Concrete(release) => Some((
+                        release
+                            .metadata
+                            .iter()
+                            .map(|(key, value)| key.len() + value.len())
+                            .sum::<usize>(),
+                        &release.version,
+                    )),
+                    Release::Abstract(_) => None,
+                })
+                .max();
+            if let Some((size, version)) = largest.filter(|(size, _)| *size > max) {
+                return Err(LimitExceeded {
+                    limit: "metadata_bytes",
+                    reason: format!(
+                        "metadata of release {} has {} bytes, maximum is {}",
+                        version, size, max
+                    ),
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Check the size of the serialized graph.
+    pub fn check_serialized(&self, size: usize) -> Result<(), LimitExceeded> {
+        match self.max_graph_bytes {
+            Some(max) if size > max => Err(LimitExceeded {
+                limit: "graph_bytes",
+                reason: format!("serialized graph has {} bytes, maximum is {}", size, max),
+            }),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cincinnati::testing::generate_custom_graph;
+
+    fn graph() -> Graph {
+        let metadata = [("key".to_string(), "value".to_string())]
+            .iter()
+            .cloned()
+            .collect();
+        generate_custom_graph(
+            "image",
+            vec![
+                (0, Default::default()),
+                (1, metadata),
+                (2, Default::default()),
+            ],
+            Some(vec![(0, 1), (1, 2)]),
+        )
+    }
+
+    #[test]
+    fn check_limits() {
+        let graph = graph();
+        assert_eq!(Limits::default().check_graph(&graph), Ok(()));
+
+        let limits = Limits {
+            max_releases: Some(3),
+            max_edges: Some(2),
+            max_graph_bytes: Some(100),
+            max_metadata_bytes: Some(8),
+        };
+        assert_eq!(limits.check_graph(&graph), Ok(()));
+        assert_eq!(limits.check_serialized(100), Ok(()));
+
+        let exceeded = |limits: Limits| limits.check_graph(&graph).unwrap_err().limit;
+        assert_eq!(
+            exceeded(Limits {
+                max_releases: Some(2),
+                ..limits
+            }),
+            "releases"
+        );
+        assert_eq!(
+            exceeded(Limits {
+                max_edges: Some(1),
+                ..limits
+            }),
+            "edges"
+        );
+        assert_eq!(
+            Limits {
+                max_metadata_bytes: Some(7),
+                ..limits
+            }
+            .check_graph(&graph),
+            Err(LimitExceeded {
+                limit: "metadata_bytes",
+                reason: "metadata of release 1.0.0 has 8 bytes, maximum is 7".to_string(),
+            })
+        );
+        assert_eq!(
+            limits.check_serialized(101).unwrap_err().limit,
+            "graph_bytes"
+        );
+    }
+}
@@ -13,12 +13,25 @@
 // limitations under the License.
 
 use actix_service::Service;
-use actix_web::{middleware, App, HttpServer};
+use actix_web::middleware::Condition;
+use actix_web::{App, HttpServer};
+use cincinnati::plugins::internal::local_openshift_secondary_metadata_scraper;
+use commons::auth;
+use commons::compression;
+use commons::listen;
+use commons::logging;
 use commons::metrics::{self, HasRegistry};
+use commons::otlp::OtlpExporter;
+use commons::outbound;
 use commons::prelude_errors::*;
+use commons::ratelimit;
+use commons::request_id;
 use commons::tracing::{get_context, get_tracer, init_tracer, set_span_tags};
 use futures::future;
-use graph_builder::{self, config, graph, status};
+use graph_builder::{
+    self, audit, changelog, config, coordination, graph, notify, once, preflight, reload, shutdown,
+    snapshots, status, validate_graph_data,
+};
 use log::debug;
 use opentelemetry::{
     trace::{mark_span_as_active, FutureExt, Tracer},
@@ -28,24 +41,56 @@ use parking_lot::RwLock;
 use std::collections::HashSet;
 use std::sync::Arc;
 use std::thread;
-use std::time::Duration;
 
-#[actix_web::main]
-async fn main() -> Result<(), Error> {
+/// Title of the OpenAPI documents of the services.
+static OPENAPI_TITLE: &str = "Cincinnati graph-builder";
+
+fn main() -> Result<(), Error> {
     let settings = config::AppSettings::assemble().context("could not assemble AppSettings")?;
-    env_logger::Builder::from_default_env()
-        .filter(Some(module_path!()), settings.verbosity)
-        .filter(Some("cincinnati"), settings.verbosity)
-        .init();
+    logging::init(
+        &[module_path!(), "cincinnati"],
+        settings.verbosity,
+        settings.log_format,
+    );
     debug!("application settings:\n{:#?}", settings);
 
+    // The environment is only changed while the process is single-threaded.
+    let proxy = settings.proxy_settings()?;
+    outbound::export_proxy_env(&proxy)?;
+
+    actix_web::rt::System::new().block_on(run(settings, proxy))
+}
+
+async fn run(settings: config::AppSettings, proxy: outbound::ProxySettings) -> Result<(), Error> {
+    outbound::set_identity(&settings.client_identity())?;
+    outbound::set_proxy(&proxy)?;
+
+    if let Some(source) = &settings.validate_graph_data {
+        return validate_graph_data::run(source).await;
+    }
+
+    if settings.preflight_checks {
+        preflight::run(&settings).await?;
+    }
+
     let registry: prometheus::Registry =
         metrics::new_registry(Some(config::METRICS_PREFIX.to_string()))?;
 
     // Enable tracing
-    init_tracer("graph-builder", settings.tracing_endpoint.clone())?;
+    init_tracer(
+        "graph-builder",
+        settings.tracing_endpoint.clone(),
+        settings.tracing_sampling(),
+    )?;
+
+    // Plugin metrics live in their own registry, which is replaced on reload.
+    let plugin_registry = reload::PluginRegistry::default();
+    registry.register(Box::new(plugin_registry.clone()))?;
+    let plugins = settings.validate_and_build_plugins(Some(&plugin_registry.current()))?;
 
-    let plugins = settings.validate_and_build_plugins(Some(&registry))?;
+    if settings.once {
+        return once::run(&settings, Box::leak(Box::new(plugins))).await;
+    }
 
     ensure_registered_metrics(
         &registry,
@@ -53,31 +98,137 @@ async fn main() -> Result<(), Error> {
         &settings.metrics_required,
     )?;
 
-    let service_addr = (settings.address, settings.port);
-    let status_addr = (settings.status_address, settings.status_port);
+    let service_addrs = settings.service_addrs();
+    let status_addrs = settings.status_addrs();
+    let service_socket = settings.socket_path.clone();
+    let status_socket = settings.status_socket_path.clone();
+    let listen::ActivatedListeners {
+        main: activated_main,
+        status: activated_status,
+    } = listen::ActivatedListeners::from_env()?;
+    let service_server = settings.service_server.clone();
+    let status_server = settings.status_server.clone();
     let app_prefix = settings.path_prefix.clone();
+    let internal_graph_api = settings.internal_graph_api;
+    let mandatory_params = settings.mandatory_client_parameters.clone();
+    let rate_limiter = settings
+        .rate_limit_settings()
+        .map(ratelimit::RateLimiter::new);
+    let compression_settings = settings.compression_settings();
+    let cors = settings.cors.clone();
+    let status_credentials = settings.status_credentials()?;
+    let main_tls = settings
+        .tls_settings()
+        .map(|tls| tls.server_config())
+        .transpose()?;
+    let status_tls = settings
+        .status_tls_settings()
+        .map(|tls| tls.server_config())
+        .transpose()?;
+
+    let notifier = notify::Notifier::new(tokio::runtime::Handle::current());
+    notifier.configure(&settings.notification_sinks, settings.notification_debounce)?;
 
     // Shared state.
     let state = {
-        let json_graph = Arc::new(RwLock::new(String::new()));
         let live = Arc::new(RwLock::new(false));
         let ready = Arc::new(RwLock::new(false));
 
         graph::State::new(
-            json_graph,
             settings.mandatory_client_parameters.clone(),
             live,
             ready,
-            Box::leak(Box::new(plugins)),
+            plugins,
             Box::leak(Box::new(registry)),
         )
+        .with_admin_token(settings.read_admin_token()?)
+        .with_plugin_registry(plugin_registry.clone())
+        .with_notifier(notifier)
+        .with_changelog(changelog::Changelog::new(settings.changelog_max_revisions))
+        .with_audit_log(audit::AuditLog::open(
+            settings.audit_log_path.as_deref(),
+            settings.audit_max_entries,
+            settings.audit_max_file_bytes,
+        )?)
+        .with_snapshots(snapshots::Snapshots::new(settings.snapshots_max))
+        .with_selftest(settings.selftest()?)
+        .with_max_graph_staleness(settings.max_graph_staleness)
+        .with_compression(compression_settings)
+        .with_cache_max_age(settings.cache_max_age)
+        .with_coordinator(
+            settings
+                .coordination
+                .as_ref()
+                .map(coordination::Coordinator::new)
+                .transpose()?,
+        )
     };
 
-    // Graph scraper
+    state.record_upstream(settings.upstream());
+    state.record_metadata_keys(settings.metadata_keys()?);
+
+    // Tenants, each with its own plugins and scrape loop.
+    let mut tenants = Vec::with_capacity(settings.tenants.len());
+    for tenant in &settings.tenants {
+        let tenant_settings = config::AppSettings::assemble()
+            .and_then(|tenant_settings| tenant_settings.into_tenant(&tenant.name))
+            .context(format!(
+                "could not assemble settings of tenant '{}'",
+                tenant.name
+            ))?;
+        let tenant_plugin_registry = plugin_registry.for_tenant(&tenant.name)?;
+        let plugins = tenant_settings
+            .validate_and_build_plugins(Some(&tenant_plugin_registry.current()))
+            .context(format!(
+                "could not build plugins of tenant '{}'",
+                tenant.name
+            ))?;
+        let tenant_state = state
+            .for_tenant(&tenant.name, plugins)
+            .with_plugin_registry(tenant_plugin_registry)
+            .with_changelog(changelog::Changelog::new(settings.changelog_max_revisions))
+            .with_snapshots(snapshots::Snapshots::new(settings.snapshots_max));
+        tenant_state.record_metadata_keys(tenant_settings.metadata_keys()?);
+        tenants.push((tenant_state, tenant_settings));
+    }
+    let tenant_states: Vec<graph::State> = tenants.iter().map(|(state, _)| state.clone()).collect();
+    let state = state.with_tenants(tenant_states.clone());
+
+    // Configuration reload.
+    actix_web::rt::spawn(reload::watch_sighup(state.clone())?);
+    for tenant_state in &tenant_states {
+        actix_web::rt::spawn(reload::watch_sighup(tenant_state.clone())?);
+    }
+
+    // Leader election.
+    if let Some(coordinator) = state.coordinator() {
+        actix_web::rt::spawn(coordinator.clone().run(state.clone()));
+    }
+
+    // Metrics push export.
+    if let Some(otlp_settings) = settings.otlp_settings() {
+        let exporter = OtlpExporter::try_new(otlp_settings, "graph-builder", state.registry())?;
+        actix_web::rt::spawn(exporter.run());
+    }
+
+    // Changes to a local graph-data directory.
     {
-        let graph_state = state.clone();
+        let watch_state = state.clone();
+        local_openshift_secondary_metadata_scraper::set_change_hook(move || {
+            watch_state.trigger_rescrape()
+        });
+    }
+
+    // Graph scrapers
+    let shutdown_grace = settings.shutdown_grace;
+    let shutdown_drain = settings.shutdown_drain;
+    let mut scrapers_done = Vec::with_capacity(tenants.len() + 1);
+    for (graph_state, graph_settings) in std::iter::once((state.clone(), settings)).chain(tenants) {
+        let (scraper_done_tx, scraper_done) = tokio::sync::oneshot::channel();
+        scrapers_done.push(scraper_done);
         thread::spawn(move || {
-            graph::run(&settings, &graph_state);
+            graph::run(graph_settings, &graph_state);
+            let _ = scraper_done_tx.send(());
         });
     }
 
@@ -85,30 +236,78 @@ async fn main() -> Result<(), Error> {
     graph::register_metrics(state.registry())?;
 
     let status_state = state.clone();
+    let status_cors = cors.clone();
+    let status_payload = status_server.payload_config();
     let metrics_server = HttpServer::new(move || {
+        let status_credentials = status_credentials.clone();
         App::new()
+            .wrap_fn(move |req, srv| {
+                auth::authenticate(status_credentials.as_ref(), &status::ADMIN_PATHS, req, srv)
+            })
+            .wrap(commons::problem_json_handlers())
+            .wrap_fn(|req, srv| request_id::propagate(req, srv))
+            .wrap(Condition::new(
+                status_cors.is_some(),
+                status_cors.clone().unwrap_or_default().middleware(),
+            ))
             .app_data(actix_web::web::Data::new(status_state.clone()))
-            .service(
-                actix_web::web::resource("/liveness")
-                    .route(actix_web::web::get().to(status::serve_liveness)),
-            )
-            .service(
-                actix_web::web::resource("/metrics")
-                    .route(actix_web::web::get().to(metrics::serve::<graph::State>)),
-            )
-            .service(
-                actix_web::web::resource("/readiness")
-                    .route(actix_web::web::get().to(status::serve_readiness)),
-            )
+            .app_data(status_payload.clone())
+            .configure(|cfg| {
+                status::routes().register(
+                    cfg,
+                    "/openapi.json",
+                    OPENAPI_TITLE,
+                    env!("CARGO_PKG_VERSION"),
+                )
+            })
     })
-    .bind(status_addr)?
-    .run();
+    .keep_alive(status_server.keep_alive)
+    .client_request_timeout(status_server.client_request_timeout);
+    let mut metrics_server = match status_server.workers {
+        Some(workers) => metrics_server.workers(workers),
+        None => metrics_server,
+    };
+    let status_listeners = if activated_status.is_empty() {
+        listen::bind(status_socket.as_deref(), &status_addrs)
+            .context("Binding the status service")?
+    } else {
+        activated_status
+    };
+    for listener in status_listeners {
+        let name = listener.to_string();
+        metrics_server = match (listener, &status_tls) {
+            (listen::Listener::Tcp(listener), Some(config)) => {
+                metrics_server.listen_rustls(listener, config.clone())
+            }
+            (listen::Listener::Tcp(listener), None) if status_server.http2 => {
+                metrics_server.listen_auto_h2c(listener)
+            }
+            (listen::Listener::Tcp(listener), None) => metrics_server.listen(listener),
+            (listen::Listener::Unix(listener), None) => metrics_server.listen_uds(listener),
+            (listen::Listener::Unix(_), Some(_)) => {
+                bail!("TLS termination is not supported on Unix sockets")
+            }
+        }
+        .context(format!("Listening with the status service on {}", name))?;
+    }
+    let metrics_server = metrics_server
+        .disable_signals()
+        .shutdown_timeout(shutdown_grace.as_secs())
+        .run();
 
     // Main service.
-    let main_state = state;
+    let main_state = state.clone();
+    let main_tenant_states = tenant_states.clone();
+    let main_payload = service_server.payload_config();
     let main_server = HttpServer::new(move || {
+        let rate_limiter = rate_limiter.clone();
+        let tenant_states = main_tenant_states.clone();
         App::new()
-            .wrap(middleware::Compress::default())
+            .wrap(commons::problem_json_handlers())
+            // Inside CORS and request IDs, so that refused requests get their headers.
+            .wrap_fn(move |req, srv| ratelimit::limit(rate_limiter.as_ref(), req, srv))
+            .wrap_fn(move |req, srv| compression::compress(compression_settings, req, srv))
+            .wrap_fn(|req, srv| request_id::propagate(req, srv))
             .wrap_fn(|req, srv| {
                 let parent_context = get_context(&req);
                 let mut span = get_tracer().start_with_context("request", parent_context);
@@ -117,20 +316,79 @@ async fn main() -> Result<(), Error> {
                 let cx = ot_context::current();
                 srv.call(req).with_context(cx)
             })
+            .wrap(Condition::new(
+                cors.is_some(),
+                cors.clone().unwrap_or_default().middleware(),
+            ))
             .app_data(actix_web::web::Data::new(main_state.clone()))
-            .service(
-                // keeping this for backward compatibility
-                actix_web::web::resource(&format!("{}/v1/graph", app_prefix.clone()))
-                    .route(actix_web::web::get().to(graph::index)),
-            )
-            .service(
-                actix_web::web::resource(&format!("{}/graph", app_prefix.clone()))
-                    .route(actix_web::web::get().to(graph::index)),
-            )
+            .app_data(main_payload.clone())
+            .configure(|cfg| {
+                graph::routes(&app_prefix, &mandatory_params, internal_graph_api).register(
+                    cfg,
+                    &format!("{}/openapi.json", app_prefix),
+                    OPENAPI_TITLE,
+                    env!("CARGO_PKG_VERSION"),
+                );
+                for tenant_state in tenant_states {
+                    let tenant_prefix = format!(
+                        "{}/{}",
+                        app_prefix,
+                        tenant_state.tenant().unwrap_or_default()
+                    );
+                    graph::routes(&tenant_prefix, &mandatory_params, internal_graph_api)
+                        .app_data(actix_web::web::Data::new(tenant_state))
+                        .register(
+                            cfg,
+                            &format!("{}/openapi.json", tenant_prefix),
+                            OPENAPI_TITLE,
+                            env!("CARGO_PKG_VERSION"),
+                        );
+                }
+            })
     })
-    .keep_alive(Duration::new(10, 0))
-    .bind(service_addr)?
-    .run();
+    .keep_alive(service_server.keep_alive)
+    .client_request_timeout(service_server.client_request_timeout);
+    let mut main_server = match service_server.workers {
+        Some(workers) => main_server.workers(workers),
+        None => main_server,
+    };
+    let main_listeners = if activated_main.is_empty() {
+        listen::bind(service_socket.as_deref(), &service_addrs)
+            .context("Binding the main service")?
+    } else {
+        activated_main
+    };
+    for listener in main_listeners {
+        let name = listener.to_string();
+        main_server = match (listener, &main_tls) {
+            (listen::Listener::Tcp(listener), Some(config)) => {
+                main_server.listen_rustls(listener, config.clone())
+            }
+            (listen::Listener::Tcp(listener), None) if service_server.http2 => {
+                main_server.listen_auto_h2c(listener)
+            }
+            (listen::Listener::Tcp(listener), None) => main_server.listen(listener),
+            (listen::Listener::Unix(listener), None) => main_server.listen_uds(listener),
+            (listen::Listener::Unix(_), Some(_)) => {
+                bail!("TLS termination is not supported on Unix sockets")
+            }
+        }
+        .context(format!("Listening with the main service on {}", name))?;
+    }
+    let main_server = main_server
+        .disable_signals()
+        .shutdown_timeout(shutdown_grace.as_secs())
+        .run();
+
+    // Graceful shutdown.
+    actix_web::rt::spawn(shutdown::watch_termination(
+        std::iter::once(state).chain(tenant_states).collect(),
+        shutdown_grace,
+        shutdown_drain,
+        main_server.handle(),
+        metrics_server.handle(),
+        scrapers_done,
+    )?);
 
     future::try_join(metrics_server, main_server).await?;
 
@@ -169,24 +427,27 @@ mod tests {
     use commons::metrics::HasRegistry;
     use commons::metrics::RegistryWrapper;
     use commons::testing;
-    use graph_builder::status::{serve_liveness, serve_readiness};
+    use graph_builder::status::{
+        serve_audit, serve_debug, serve_freeze_override, serve_liveness, serve_readiness,
+        serve_rescrape, serve_validation_override, serve_version,
+    };
     use memchr::memmem;
     use parking_lot::RwLock;
     use prometheus::Registry;
     use std::collections::HashSet;
     use std::sync::Arc;
+    use std::time::Duration;
 
     fn mock_state(is_live: bool, is_ready: bool) -> State {
-        let json_graph = Arc::new(RwLock::new(String::new()));
         let live = Arc::new(RwLock::new(is_live));
         let ready = Arc::new(RwLock::new(is_ready));
 
-        let plugins = Box::leak(Box::new([]));
+        let plugins = vec![];
         let registry: &'static Registry = Box::leak(Box::new(
             metrics::new_registry(Some(config::METRICS_PREFIX.to_string())).unwrap(),
         ));
 
-        State::new(json_graph, HashSet::new(), live, ready, plugins, registry)
+        State::new(HashSet::new(), live, ready, plugins, registry)
     }
 
     #[test]
@@ -255,6 +516,229 @@ mod tests {
             resp.status()
         );
 
+        let state = mock_state(true, true).with_max_graph_staleness(Some(Duration::from_secs(60)));
+        state.debug().record_scrape(None);
+        let resp = rt.block_on(serve_readiness(actix_web::web::Data::new(state.clone())));
+        assert!(
+            resp.status().is_success(),
+            "readiness check failed. Application returned {}, expected success",
+            resp.status()
+        );
+        let now = chrono::Utc::now().timestamp();
+        assert_eq!(state.staleness(now + 30), None);
+        assert_eq!(
+            state.staleness(now + 3600).map(|age| age.as_secs() >= 3600),
+            Some(true)
+        );
+
+        // A tenant without a graph makes the service not ready.
+        let state = mock_state(true, true);
+        let tenant = state.for_tenant("okd", vec![]);
+        let state = state.with_tenants(vec![tenant]);
+        let resp = rt.block_on(serve_readiness(actix_web::web::Data::new(state)));
+        assert_eq!(resp.status(), 503);
+        let body = resp
+            .into_body()
+            .try_into_bytes()
+            .map_err(|_| format_err!("expected bytes in body"))?;
+        let body: serde_json::Value = serde_json::from_slice(&body)?;
+        assert_eq!(body["detail"], "no graph is available yet for tenant 'okd'");
+
+        Ok(())
+    }
+
+    #[test]
+    fn build_info() -> Fallible<()> {
+        let rt = testing::init_runtime()?;
+        let state = mock_state(true, true);
+        state.record_upstream("registry:quay.io/openshift-release-dev/ocp-release".to_string());
+
+        let resp = rt.block_on(serve_version(actix_web::web::Data::new(state)));
+        assert_eq!(resp.status(), 200);
+        let body = resp
+            .into_body()
+            .try_into_bytes()
+            .map_err(|_| format_err!("expected bytes in body"))?;
+        let body: serde_json::Value = serde_json::from_slice(&body)?;
+        assert_eq!(body["version"], env!("CARGO_PKG_VERSION"));
+        assert_eq!(
+            body["upstream"],
+            "registry:quay.io/openshift-release-dev/ocp-release"
+        );
+        assert!(body["build_timestamp"].is_string());
+        assert!(body["plugins"].as_array().unwrap().is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn validation_override() -> Fallible<()> {
+        let rt = testing::init_runtime()?;
+        let state = mock_state(true, true).with_admin_token(Some("secret".to_string()));
+        assert!(!state.is_validation_override_pending());
+
+        let req = actix_web::test::TestRequest::post()
+            .insert_header(("Authorization", "Bearer secret"))
+            .to_http_request();
+        let resp = rt.block_on(serve_validation_override(
+            req,
+            actix_web::web::Data::new(state.clone()),
+        ));
+        assert_eq!(resp.status(), 202);
+        assert!(state.is_validation_override_pending());
+
+        Ok(())
+    }
+
+    #[test]
+    fn freeze_override() -> Fallible<()> {
+        let rt = testing::init_runtime()?;
+        let state = mock_state(true, true).with_admin_token(Some("secret".to_string()));
+        assert!(!state.is_freeze_override_pending());
+
+        let req = actix_web::test::TestRequest::post()
+            .insert_header(("Authorization", "Bearer secret"))
+            .to_http_request();
+        let resp = rt.block_on(serve_freeze_override(
+            req,
+            actix_web::web::Data::new(state.clone()),
+        ));
+        assert_eq!(resp.status(), 202);
+        assert!(state.is_freeze_override_pending());
+
+        Ok(())
+    }
+
+    #[test]
+    fn admin_endpoints_require_token() -> Fallible<()> {
+        let rt = testing::init_runtime()?;
+
+        let request = |authorization: Option<&str>| {
+            let mut req = actix_web::test::TestRequest::post();
+            if let Some(authorization) = authorization {
+                req = req.insert_header(("Authorization", authorization));
+            }
+            req.to_http_request()
+        };
+
+        let disabled = actix_web::web::Data::new(mock_state(true, true));
+        let resp = rt.block_on(serve_rescrape(request(Some("Bearer secret")), disabled));
+        assert_eq!(resp.status(), 403);
+
+        let state = actix_web::web::Data::new(
+            mock_state(true, true).with_admin_token(Some("secret".to_string())),
+        );
+        for authorization in &[None, Some("Bearer wrong"), Some("secret")] {
+            let resp = rt.block_on(serve_rescrape(request(*authorization), state.clone()));
+            assert_eq!(resp.status(), 401, "authorization: {:?}", authorization);
+        }
+
+        let resp = rt.block_on(serve_rescrape(request(Some("Bearer secret")), state));
+        assert_eq!(resp.status(), 202);
+
+        Ok(())
+    }
+
+    #[test]
+    fn debug_info() -> Fallible<()> {
+        let rt = testing::init_runtime()?;
+        let state = mock_state(true, true).with_admin_token(Some("secret".to_string()));
+        state
+            .debug()
+            .record_scrape(Some("upstream unavailable".to_string()));
+
+        let request = |path: &str, section: Option<&str>| {
+            let mut req = actix_web::test::TestRequest::get()
+                .uri(path)
+                .insert_header(("Authorization", "Bearer secret"));
+            if let Some(section) = section {
+                req = req.param("section", section.to_string());
+            }
+            req.to_http_request()
+        };
+
+        let resp = rt.block_on(serve_debug(
+            request("/debug", None),
+            actix_web::web::Data::new(state.clone()),
+        ));
+        assert_eq!(resp.status(), 200);
+        let body = resp
+            .into_body()
+            .try_into_bytes()
+            .map_err(|_| format_err!("expected bytes in body"))?;
+        let body: serde_json::Value = serde_json::from_slice(&body)?;
+        assert_eq!(body["scrape"]["last_error"], "upstream unavailable");
+        assert_eq!(body["graph"]["releases"], 0);
+        assert!(body["plugins"].as_array().unwrap().is_empty());
+
+        let resp = rt.block_on(serve_debug(
+            request("/debug/unknown", Some("unknown")),
+            actix_web::web::Data::new(state),
+        ));
+        assert_eq!(resp.status(), 404);
+
+        Ok(())
+    }
+
+    #[test]
+    fn audit_events() -> Fallible<()> {
+        let rt = testing::init_runtime()?;
+        let state = mock_state(true, true).with_admin_token(Some("secret".to_string()));
+        for revision in 1..=3 {
+            state.audit().record(
+                audit::AuditEvent {
+                    id: String::new(),
+                    revision,
+                    published: "2021-06-01T00:00:00+00:00".to_string(),
+                    scrape_id: format!("1622505600-{}", revision),
+                    hash: None,
+                    previous_hash: None,
+                    source: None,
+                    diff: Default::default(),
+                },
+                &Default::default(),
+            );
+        }
+
+        let request = |uri: &str, authorization: Option<&str>| {
+            let mut req = actix_web::test::TestRequest::get().uri(uri);
+            if let Some(authorization) = authorization {
+                req = req.insert_header(("Authorization", authorization.to_string()));
+            }
+            req.to_http_request()
+        };
+
+        let resp = rt.block_on(serve_audit(
+            request("/audit?limit=2", Some("Bearer secret")),
+            actix_web::web::Data::new(state.clone()),
+        ))?;
+        assert_eq!(resp.status(), 200);
+        let body = resp
+            .into_body()
+            .try_into_bytes()
+            .map_err(|_| format_err!("expected bytes in body"))?;
+        let body: serde_json::Value = serde_json::from_slice(&body)?;
+        let revisions: Vec<_> = body["events"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|event| event["revision"].as_u64().unwrap())
+            .collect();
+        assert_eq!(revisions, vec![3, 2]);
+
+        let resp = rt.block_on(serve_audit(
+            request("/audit", None),
+            actix_web::web::Data::new(state.clone()),
+        ))?;
+        assert_eq!(resp.status(), 401);
+
+        assert!(rt
+            .block_on(serve_audit(
+                request("/audit?limit=0", Some("Bearer secret")),
+                actix_web::web::Data::new(state),
+            ))
+            .is_err());
+
         Ok(())
     }
 }
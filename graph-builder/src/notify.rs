@@ -0,0 +1,461 @@
+//! Notifications about operational events.
+//!
+//! The scrape loop emits events for repeated scrape failures, graphs refused
+//! by the validation gate and releases quarantined for lack of a valid
+//! signature. Each configured sink receives the event types it subscribed to.
+//! Events are delivered in the background, failures are logged and counted
+//! but never affect the scrape loop.
+//!
+//! A graph refused by the validation gate or a limit is refused again on every
+//! scrape until it is fixed upstream, so repeated events of the same type are
+//! debounced: only the first one within the debounce period is delivered.
+
+use async_trait::async_trait;
+use commons::prelude_errors::*;
+use parking_lot::{Mutex, RwLock};
+use prometheus::Counter;
+use reqwest::header::CONTENT_TYPE;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Timeout for delivering an event to a webhook.
+static WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Default period during which repeated events of the same type are suppressed.
+pub static DEFAULT_DEBOUNCE: Duration = Duration::from_secs(60 * 60);
+
+lazy_static! {
+    pub(crate) static ref NOTIFICATION_FAILURES: Counter = Counter::new(
+        "notification_failures_total",
+        "Total number of events which could not be delivered to a notification sink"
+    )
+    .unwrap();
+    pub(crate) static ref NOTIFICATIONS_SUPPRESSED: Counter = Counter::new(
+        "notifications_suppressed_total",
+        "Total number of events not delivered because an event of the same type was delivered recently"
+    )
+    .unwrap();
+}
+
+/// Type of an operational event.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    ScrapeFailure,
+    ValidationRejection,
+    LimitExceeded,
+    Quarantine,
+}
+
+/// An operational event.
+#[derive(Clone, Debug, Serialize, PartialEq)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    /// Scrapes failed repeatedly.
+    ScrapeFailure {
+        consecutive_failures: u64,
+        error: String,
+    },
+    /// A graph was not published because it failed validation.
+    ValidationRejection { reason: String },
+    /// A graph was not published because it exceeded a protective limit.
+    LimitExceeded { limit: String, reason: String },
+    /// Releases were removed from the graph because their signature is invalid.
+    Quarantine { releases: Vec<String> },
+}
+
+impl EventKind {
+    /// Whether repeated events of this type are debounced.
+    ///
+    /// Quarantine events each report newly quarantined releases, so none of
+    /// them is a repetition.
+    fn debounced(self) -> bool {
+        self != EventKind::Quarantine
+    }
+}
+
+impl Event {
+    /// Returns the type of this event.
+    pub fn kind(&self) -> EventKind {
+        match self {
+            Event::ScrapeFailure { .. } => EventKind::ScrapeFailure,
+            Event::ValidationRejection { .. } => EventKind::ValidationRejection,
+            Event::LimitExceeded { .. } => EventKind::LimitExceeded,
+            Event::Quarantine { .. } => EventKind::Quarantine,
+        }
+    }
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Event::ScrapeFailure {
+                consecutive_failures,
+                error,
+            } => write!(
+                f,
+                "{} consecutive scrapes failed, last error: {}",
+                consecutive_failures, error
+            ),
+            Event::ValidationRejection { reason } => {
+                write!(f, "refused to publish graph: {}", reason)
+            }
+            Event::LimitExceeded { limit, reason } => write!(
+                f,
+                "refused to publish graph exceeding the {} limit: {}",
+                limit, reason
+            ),
+            Event::Quarantine { releases } => write!(
+                f,
+                "quarantined {} releases without a valid signature: {}",
+                releases.len(),
+                releases.join(", ")
+            ),
+        }
+    }
+}
+
+/// Destination for events.
+#[async_trait]
+pub trait Sink: fmt::Debug + Send + Sync {
+    /// Deliver an event.
+    async fn send(&self, event: &Event) -> Fallible<()>;
+}
+
+/// Posts events as messages to a Slack incoming webhook.
+#[derive(Debug)]
+pub struct SlackSink {
+    url: String,
+    client: reqwest::Client,
+}
+
+#[async_trait]
+impl Sink for SlackSink {
+    async fn send(&self, event: &Event) -> Fallible<()> {
+        let body = serde_json::json!({ "text": format!("graph-builder: {}", event) });
+        post_json(&self.client, &self.url, &body).await
+    }
+}
+
+/// Posts events as JSON documents to a webhook.
+#[derive(Debug)]
+pub struct WebhookSink {
+    url: String,
+    client: reqwest::Client,
+}
+
+#[async_trait]
+impl Sink for WebhookSink {
+    async fn send(&self, event: &Event) -> Fallible<()> {
+        post_json(&self.client, &self.url, event).await
+    }
+}
+
+/// Prints events as JSON lines on standard output.
+#[derive(Debug)]
+pub struct StdoutSink;
+
+#[async_trait]
+impl Sink for StdoutSink {
+    async fn send(&self, event: &Event) -> Fallible<()> {
+        println!("{}", serde_json::to_string(event)?);
+        Ok(())
+    }
+}
+
+async fn post_json<T: serde::Serialize + ?Sized>(
+    client: &reqwest::Client,
+    url: &str,
+    body: &T,
+) -> Fallible<()> {
+    let response = client
+        .post(url)
+        .header(CONTENT_TYPE, "application/json")
+        .body(serde_json::to_vec(body)?)
+        .send()
+        .await
+        .context(format!("posting to {}", url))?;
+
+    ensure!(
+        response.status().is_success(),
+        "{} returned status {}",
+        url,
+        response.status()
+    );
+
+    Ok(())
+}
+
+/// Destination of a sink.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SinkTarget {
+    Slack { url: String },
+    Webhook { url: String },
+    Stdout,
+}
+
+/// Configuration of a sink.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct SinkConfig {
+    #[serde(flatten)]
+    pub target: SinkTarget,
+
+    /// Event types to deliver, all if empty.
+    #[serde(default)]
+    pub events: HashSet<EventKind>,
+}
+
+impl SinkConfig {
+    /// Build the configured sink.
+    pub fn build(&self) -> Fallible<Box<dyn Sink>> {
+        let webhook_client = |url: &str| -> Fallible<reqwest::Client> {
+            url::Url::parse(url).context(format!("parsing notification url '{}'", url))?;
+            commons::outbound::client_builder()
+                .timeout(WEBHOOK_TIMEOUT)
+                .build()
+                .context("Building reqwest client")
+        };
+
+        let sink: Box<dyn Sink> = match &self.target {
+            SinkTarget::Slack { url } => Box::new(SlackSink {
+                client: webhook_client(url)?,
+                url: url.clone(),
+            }),
+            SinkTarget::Webhook { url } => Box::new(WebhookSink {
+                client: webhook_client(url)?,
+                url: url.clone(),
+            }),
+            SinkTarget::Stdout => Box::new(StdoutSink),
+        };
+
+        Ok(sink)
+    }
+}
+
+/// A sink along with the event types it receives.
+#[derive(Debug)]
+struct Subscription {
+    sink: Arc<dyn Sink>,
+    events: HashSet<EventKind>,
+}
+
+impl Subscription {
+    fn wants(&self, kind: EventKind) -> bool {
+        self.events.is_empty() || self.events.contains(&kind)
+    }
+}
+
+/// Delivery times of the debounced events.
+#[derive(Debug, Default)]
+struct Debouncer {
+    period: Duration,
+    last_sent: HashMap<EventKind, Instant>,
+}
+
+impl Debouncer {
+    /// Returns whether an event of type `kind` is to be delivered at `now`,
+    /// recording its delivery if so.
+    fn admit(&mut self, kind: EventKind, now: Instant) -> bool {
+        if !kind.debounced() {
+            return true;
+        }
+        match self.last_sent.get(&kind) {
+            Some(last_sent) if now.saturating_duration_since(*last_sent) < self.period => false,
+            _ => {
+                self.last_sent.insert(kind, now);
+                true
+            }
+        }
+    }
+}
+
+/// Dispatches events to the configured sinks.
+///
+/// Events are dropped if no runtime was given.
+#[derive(Clone, Debug, Default)]
+pub struct Notifier {
+    subscriptions: Arc<RwLock<Vec<Subscription>>>,
+    debouncer: Arc<Mutex<Debouncer>>,
+    runtime: Option<tokio::runtime::Handle>,
+}
+
+impl Notifier {
+    /// Creates a notifier delivering events on the given runtime.
+    pub fn new(runtime: tokio::runtime::Handle) -> Self {
+        Self {
+            subscriptions: Default::default(),
+            debouncer: Default::default(),
+            runtime: Some(runtime),
+        }
+    }
+
+    /// Replace the sinks with the configured ones, debouncing repeated events
+    /// for `debounce`.
+    ///
+    /// Nothing is changed if a sink can't be built.
+    pub fn configure(&self, configs: &[SinkConfig], debounce: Duration) -> Fallible<()> {
+        let subscriptions = configs
+            .iter()
+            .map(|config| -> Fallible<Subscription> {
+                Ok(Subscription {
+                    sink: Arc::from(config.build()?),
+                    events: config.events.clone(),
+                })
+            })
+            .collect::<Fallible<Vec<_>>>()?;
+
+        *self.subscriptions.write() = subscriptions;
+        self.debouncer.lock().period = debounce;
+        Ok(())
+    }
+
+    /// Mark the condition behind events of type `kind` as resolved, so that
+    /// the next such event is delivered without waiting for the debounce period.
+    pub fn resolve(&self, kind: EventKind) {
+        self.debouncer.lock().last_sent.remove(&kind);
+    }
+
+    /// Deliver an event to the sinks which subscribed to its type, unless an
+    /// event of the same type was delivered within the debounce period.
+    pub fn notify(&self, event: Event) {
+        let sinks: Vec<Arc<dyn Sink>> = self
+            .subscriptions
+            .read()
+            .iter()
+            .filter(|subscription| subscription.wants(event.kind()))
+            .map(|subscription| subscription.sink.clone())
+            .collect();
+        if sinks.is_empty() {
+            return;
+        }
+        if !self.debouncer.lock().admit(event.kind(), Instant::now()) {
+            NOTIFICATIONS_SUPPRESSED.inc();
+            debug!("suppressing repeated notification: {}", event);
+            return;
+        }
+
+        let runtime = match &self.runtime {
+            Some(runtime) => runtime,
+            None => {
+                debug!("no runtime for notifications, dropping event: {}", event);
+                return;
+            }
+        };
+
+        runtime.spawn(async move {
+            for sink in sinks {
+                if let Err(e) = sink.send(&event).await {
+                    NOTIFICATION_FAILURES.inc();
+                    warn!("failed to deliver notification to {:?}: {:#}", sink, e);
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deserialize_sink_configs() -> Fallible<()> {
+        #[derive(Deserialize)]
+        struct Sinks {
+            sinks: Vec<SinkConfig>,
+        }
+
+        let sinks: Sinks = toml::from_str(
+            r#"
+            [[sinks]]
+            type = "slack"
+            url = "https://hooks.slack.com/services/T0/B0/X"
+            events = ["scrape_failure", "quarantine"]
+
+            [[sinks]]
+            type = "stdout"
+            "#,
+        )?;
+
+        assert_eq!(
+            sinks.sinks,
+            vec![
+                SinkConfig {
+                    target: SinkTarget::Slack {
+                        url: "https://hooks.slack.com/services/T0/B0/X".to_string()
+                    },
+                    events: vec![EventKind::ScrapeFailure, EventKind::Quarantine]
+                        .into_iter()
+                        .collect(),
+                },
+                SinkConfig {
+                    target: SinkTarget::Stdout,
+                    events: HashSet::new(),
+                },
+            ]
+        );
+
+        let invalid = SinkConfig {
+            target: SinkTarget::Webhook {
+                url: "not a url".to_string(),
+            },
+            events: HashSet::new(),
+        };
+        assert!(Notifier::default()
+            .configure(&[invalid], DEFAULT_DEBOUNCE)
+            .is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn serialize_event() -> Fallible<()> {
+        let event = Event::Quarantine {
+            releases: vec!["quay.io/a@sha256:1".to_string()],
+        };
+        assert_eq!(
+            serde_json::to_string(&event)?,
+            r#"{"event":"quarantine","releases":["quay.io/a@sha256:1"]}"#
+        );
+        assert_eq!(event.kind(), EventKind::Quarantine);
+
+        Ok(())
+    }
+
+    #[test]
+    fn debounce_repeated_events() {
+        let mut debouncer = Debouncer {
+            period: Duration::from_secs(60),
+            ..Default::default()
+        };
+        let start = Instant::now();
+
+        assert!(debouncer.admit(EventKind::ValidationRejection, start));
+        assert!(!debouncer.admit(
+            EventKind::ValidationRejection,
+            start + Duration::from_secs(30)
+        ));
+        // Other types are debounced independently, quarantines not at all.
+        assert!(debouncer.admit(EventKind::LimitExceeded, start + Duration::from_secs(30)));
+        assert!(debouncer.admit(EventKind::Quarantine, start));
+        assert!(debouncer.admit(EventKind::Quarantine, start));
+
+        assert!(debouncer.admit(
+            EventKind::ValidationRejection,
+            start + Duration::from_secs(60)
+        ));
+        assert!(!debouncer.admit(
+            EventKind::ValidationRejection,
+            start + Duration::from_secs(90)
+        ));
+
+        // Once resolved, the next event is delivered right away.
+        debouncer.last_sent.remove(&EventKind::ValidationRejection);
+        assert!(debouncer.admit(
+            EventKind::ValidationRejection,
+            start + Duration::from_secs(90)
+        ));
+    }
+}
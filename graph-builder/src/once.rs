@@ -0,0 +1,121 @@
+//! One-shot mode.
+//!
+//! The plugin chain is run a single time and the resulting graph is written
+//! out, without starting the services. This lets CI pipelines check the graph
+//! produced by a configuration.
+
+use crate::config::AppSettings;
+use crate::limits::Limits;
+use crate::validation;
+use cincinnati::plugins::{BoxedPlugin, InternalIO, PluginIO};
+use commons::prelude_errors::*;
+use std::io::Write;
+use std::path::Path;
+
+/// Build the graph once and write it to the configured output.
+///
+/// The graph goes through the same validation and protective limits as
+/// published graphs, except for the change thresholds, which need a previous
+/// graph.
+pub async fn run(settings: &AppSettings, plugins: &'static [BoxedPlugin]) -> Fallible<()> {
+    let scrape = cincinnati::plugins::process(
+        plugins.iter(),
+        PluginIO::InternalIO(InternalIO {
+            graph: Default::default(),
+            parameters: Default::default(),
+        }),
+    );
+    let internal_io = match settings.scrape_timeout_secs {
+        Some(timeout) => tokio::time::timeout(timeout, scrape)
+            .await
+            .map_err(|_| format_err!("scrape timed out after {}s", timeout.as_secs()))??,
+        None => scrape.await?,
+    };
+
+    let limits = Limits::from_settings(settings);
+    limits.check_graph(&internal_io.graph)?;
+
+    if settings.validation_enabled {
+        let thresholds = validation::Thresholds {
+            max_releases_removed_percent: settings.validation_max_releases_removed_percent,
+            max_edges_removed_percent: settings.validation_max_edges_removed_percent,
+        };
+        validation::validate(
+            &internal_io.graph,
+            &settings.metadata_keys()?,
+            None,
+            &thresholds,
+            false,
+        )?;
+    }
+
+    let json = serde_json::to_string(&internal_io.graph)?;
+    limits.check_serialized(json.len())?;
+    write_graph(settings.once_output.as_deref(), &json)?;
+    info!(
+        "built graph with {} releases",
+        internal_io.graph.releases_count()
+    );
+
+    Ok(())
+}
+
+/// Write the graph to `output`, or to stdout if unset or "-".
+fn write_graph(output: Option<&Path>, json: &str) -> Fallible<()> {
+    match output {
+        Some(path) if path != Path::new("-") => {
+            std::fs::write(path, json).context(format!("writing the graph to {}", path.display()))
+        }
+        _ => {
+            let mut stdout = std::io::stdout();
+            writeln!(stdout, "{}", json).context("writing the graph to stdout")?;
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cincinnati::plugins::prelude_plugin_impl::*;
+    use cincinnati::Graph;
+
+    #[test]
+    fn build_once() -> Fallible<()> {
+        let rt = commons::testing::init_runtime()?;
+        let tmpdir = tempfile::tempdir()?;
+        let output = tmpdir.path().join("graph.json");
+        let settings = AppSettings {
+            once: true,
+            once_output: Some(output.clone()),
+            ..Default::default()
+        };
+
+        let plugins: &'static [BoxedPlugin] = Box::leak(Box::new([]));
+        rt.block_on(run(&settings, plugins))?;
+        let graph: Graph = serde_json::from_str(&std::fs::read_to_string(&output)?)?;
+        assert_eq!(graph, Graph::default());
+
+        // Failing plugin chains fail the run, without writing a graph.
+        std::fs::remove_file(&output)?;
+        let plugins: Vec<BoxedPlugin> = new_plugins!(InternalPluginWrapper(FailingPlugin));
+        assert!(rt
+            .block_on(run(&settings, Box::leak(Box::new(plugins))))
+            .is_err());
+        assert!(!output.exists());
+
+        Ok(())
+    }
+
+    #[derive(Debug)]
+    struct FailingPlugin;
+
+    #[async_trait]
+    impl InternalPlugin for FailingPlugin {
+        const PLUGIN_NAME: &'static str = "failing";
+
+        async fn run_internal(self: &Self, _: InternalIO) -> Fallible<InternalIO> {
+            bail!("upstream unavailable")
+        }
+    }
+}
@@ -0,0 +1,327 @@
+//! Startup preflight checks.
+//!
+//! These run once before any server is bound, and verify that the external
+//! dependencies of the default scraping pipeline are usable. All checks are
+//! run to completion so that every problem is reported at once.
+
+use crate::config::{AppSettings, UpstreamMethod};
+use cincinnati::plugins::internal::github_openshift_secondary_metadata_scraper::GITHUB_SCRAPER_TOKEN_PATH_ENV;
+use cincinnati::plugins::internal::release_scrape_dockerv2::registry;
+use commons::prelude_errors::*;
+use std::fmt;
+use std::path::Path;
+use std::time::Duration;
+
+/// GitHub repository queried by the default secondary-metadata scraper.
+static DEFAULT_GRAPH_DATA_REPO_URL: &str =
+    "https://api.github.com/repos/openshift/cincinnati-graph-data";
+
+/// Timeout for network probes.
+static PROBE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A single failed preflight check.
+#[derive(Debug)]
+pub struct PreflightFailure {
+    /// Name of the failed check.
+    pub check: &'static str,
+    /// What went wrong.
+    pub error: String,
+    /// How to fix it.
+    pub hint: &'static str,
+}
+
+impl fmt::Display for PreflightFailure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}\n    hint: {}", self.check, self.error, self.hint)
+    }
+}
+
+/// All failed preflight checks.
+#[derive(Debug)]
+pub struct PreflightError(pub Vec<PreflightFailure>);
+
+impl std::error::Error for PreflightError {}
+
+impl fmt::Display for PreflightError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{} preflight check(s) failed:", self.0.len())?;
+        for failure in &self.0 {
+            writeln!(f, "  - {}", failure)?;
+        }
+        Ok(())
+    }
+}
+
+/// Run all preflight checks applicable to the given settings.
+pub async fn run(settings: &AppSettings) -> Result<(), PreflightError> {
+    let mut failures = vec![];
+
+    failures.extend(check_state_dir(settings.cache_path.as_deref()));
+
+    match settings.upstream_method {
+        // Offline deployments don't reach out to the registry nor to the
+        // graph-data repository.
+        UpstreamMethod::File => {
+            failures.extend(check_graph_file(settings.graph_file_path.as_deref()));
+        }
+        UpstreamMethod::Registry => {
+            failures.extend(check_registry(settings).await);
+
+            // Custom plugin chains can't be introspected, so the graph-data
+            // source is only checked for the default one.
+            if settings.plugin_settings.is_empty() {
+                failures.extend(check_graph_data_source().await);
+            }
+        }
+    }
+
+    if failures.is_empty() {
+        info!("all preflight checks passed");
+        Ok(())
+    } else {
+        Err(PreflightError(failures))
+    }
+}
+
+/// Check that the state directory exists (or can be created) and is writable.
+fn check_state_dir(path: Option<&Path>) -> Option<PreflightFailure> {
+    let path = path?;
+
+    let result = std::fs::create_dir_all(path)
+        .context(format!("creating {}", path.display()))
+        .and_then(|_| {
+            tempfile::tempfile_in(path).context(format!("writing to {}", path.display()))
+        });
+
+    result.err().map(|e| PreflightFailure {
+        check: "state directory",
+        error: format!("{:#}", e),
+        hint: "make sure `upstream.registry.cache_path` points to a writable directory",
+    })
+}
+
+/// Check that the pre-built graph file is readable and holds a graph.
+fn check_graph_file(path: Option<&Path>) -> Option<PreflightFailure> {
+    let path = path?;
+
+    let result = std::fs::read(path)
+        .context(format!("reading {}", path.display()))
+        .and_then(|json| {
+            serde_json::from_slice::<cincinnati::Graph>(&json)
+                .context(format!("parsing {}", path.display()))
+        });
+
+    result.err().map(|e| PreflightFailure {
+        check: "graph file",
+        error: format!("{:#}", e),
+        hint: "make sure `upstream.file.path` points to a readable graph in the Cincinnati JSON format",
+    })
+}
+
+/// Check that the registry is reachable and the credentials are accepted.
+async fn check_registry(settings: &AppSettings) -> Vec<PreflightFailure> {
+    let registry = match registry::Registry::try_from_str(&settings.registry) {
+        Ok(registry) => registry,
+        Err(e) => {
+            return vec![PreflightFailure {
+                check: "registry",
+                error: format!("invalid registry '{}': {:#}", settings.registry, e),
+                hint: "set `upstream.registry.url` to a valid registry host, e.g. 'quay.io'",
+            }]
+        }
+    };
+
+    let mut failures = vec![];
+    let (username, password) = match registry::read_credentials(
+        settings.credentials_path.as_ref(),
+        &registry.host_port_string(),
+    ) {
+        Ok(credentials) => credentials,
+        Err(e) => {
+            failures.push(PreflightFailure {
+                check: "registry credentials",
+                error: format!("{:#}", e),
+                hint: "make sure `upstream.registry.credentials_path` is a readable dockercfg file with an entry for the registry",
+            });
+            (None, None)
+        }
+    };
+
+    let tls = match registry::ClientTls::try_new(
+        settings.ca_bundle_path.as_deref(),
+        settings.client_cert_path.as_deref(),
+        settings.client_key_path.as_deref(),
+    ) {
+        Ok(tls) => tls,
+        Err(e) => {
+            failures.push(PreflightFailure {
+                check: "registry TLS",
+                error: format!("{:#}", e),
+                hint: "make sure `upstream.registry.ca_bundle_path` is a readable file with PEM-encoded certificates, and `upstream.registry.client_cert_path` and `client_key_path` a PEM-encoded certificate and its PKCS#8 key",
+            });
+            return failures;
+        }
+    };
+
+    let client = match registry::http::RepositoryClient::try_new(
+        &registry,
+        &settings.repository,
+        username.as_deref(),
+        password.as_deref(),
+        &tls,
+    ) {
+        Ok(client) => client,
+        Err(e) => {
+            failures.push(PreflightFailure {
+                check: "registry",
+                error: format!("{:#}", e),
+                hint: "verify the registry URL and the repository name",
+            });
+            return failures;
+        }
+    };
+    let probe = async {
+        let url = client.base().join("tags/list")?;
+        let response = client.get(url, &["application/json"]).await?;
+        response.ok_or_else(|| format_err!("repository not found"))
+    };
+    match tokio::time::timeout(PROBE_TIMEOUT, probe).await {
+        Ok(Ok(_)) => {}
+        Ok(Err(e)) => failures.push(PreflightFailure {
+            check: "registry",
+            error: format!(
+                "authenticating against {}/{}: {:#}",
+                registry.host_port_string(),
+                settings.repository,
+                e
+            ),
+            hint: "verify the registry URL, the repository name, and that the credentials grant pull access",
+        }),
+        Err(_) => failures.push(PreflightFailure {
+            check: "registry",
+            error: format!(
+                "no response from {} within {}s",
+                registry.host_port_string(),
+                PROBE_TIMEOUT.as_secs()
+            ),
+            hint: "check network connectivity and proxy settings towards the registry",
+        }),
+    }
+
+    failures
+}
+
+/// Check that the default graph-data repository can be queried.
+async fn check_graph_data_source() -> Vec<PreflightFailure> {
+    let mut failures = vec![];
+
+    let token = match std::env::var(GITHUB_SCRAPER_TOKEN_PATH_ENV) {
+        Ok(path) => match std::fs::read_to_string(&path) {
+            Ok(token) => Some(token.trim().to_string()),
+            Err(e) => {
+                failures.push(PreflightFailure {
+                    check: "graph-data credentials",
+                    error: format!("reading {}: {}", path, e),
+                    hint: "make sure the file referenced by CINCINNATI_GITHUB_SCRAPER_OAUTH_TOKEN_PATH exists and is readable",
+                });
+                None
+            }
+        },
+        Err(_) => None,
+    };
+
+    let client = match commons::outbound::client_builder()
+        .timeout(PROBE_TIMEOUT)
+        .build()
+    {
+        Ok(client) => client,
+        Err(e) => {
+            failures.push(PreflightFailure {
+                check: "graph-data source",
+                error: format!("building HTTP client: {}", e),
+                hint: "this is likely a bug, please report it",
+            });
+            return failures;
+        }
+    };
+
+    let mut request = client.get(DEFAULT_GRAPH_DATA_REPO_URL);
+    if let Some(token) = token {
+        request = request.header(reqwest::header::AUTHORIZATION, format!("token {}", token));
+    }
+
+    match request.send().await {
+        Ok(response) if response.status().is_success() => {}
+        Ok(response) => failures.push(PreflightFailure {
+            check: "graph-data source",
+            error: format!(
+                "{} returned status {}",
+                DEFAULT_GRAPH_DATA_REPO_URL,
+                response.status()
+            ),
+            hint: "check the GitHub token for validity and remaining rate limit",
+        }),
+        Err(e) => failures.push(PreflightFailure {
+            check: "graph-data source",
+            error: format!("querying {}: {}", DEFAULT_GRAPH_DATA_REPO_URL, e),
+            hint: "check network connectivity and proxy settings towards api.github.com",
+        }),
+    }
+
+    failures
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn state_dir_check() {
+        assert!(check_state_dir(None).is_none());
+
+        let tmpdir = tempfile::tempdir().unwrap();
+        assert!(check_state_dir(Some(tmpdir.path().join("cache").as_path())).is_none());
+
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let failure = check_state_dir(Some(file.path())).unwrap();
+        assert_eq!(failure.check, "state directory");
+    }
+
+    #[test]
+    fn graph_file_check() {
+        assert!(check_graph_file(None).is_none());
+
+        let tmpdir = tempfile::tempdir().unwrap();
+        let path = tmpdir.path().join("graph.json");
+        std::fs::write(&path, r#"{"nodes":[],"edges":[]}"#).unwrap();
+        assert!(check_graph_file(Some(path.as_path())).is_none());
+
+        std::fs::write(&path, "{").unwrap();
+        let failure = check_graph_file(Some(path.as_path())).unwrap();
+        assert_eq!(failure.check, "graph file");
+
+        let missing = tmpdir.path().join("missing.json");
+        assert!(check_graph_file(Some(missing.as_path())).is_some());
+    }
+
+    #[test]
+    fn error_lists_all_failures() {
+        let err = PreflightError(vec![
+            PreflightFailure {
+                check: "first",
+                error: "broken".to_string(),
+                hint: "fix it",
+            },
+            PreflightFailure {
+                check: "second",
+                error: "also broken".to_string(),
+                hint: "fix it too",
+            },
+        ]);
+
+        let msg = err.to_string();
+        assert!(msg.starts_with("2 preflight check(s) failed:"));
+        assert!(msg.contains("  - first: broken\n    hint: fix it"));
+        assert!(msg.contains("  - second: also broken\n    hint: fix it too"));
+    }
+}
@@ -0,0 +1,332 @@
+//! Configuration reload on SIGHUP.
+//!
+//! On SIGHUP the settings are assembled again from the command line and the
+//! configuration file, and the plugins are rebuilt from them before the next
+//! scrape. This picks up new upstream credentials, scrape periods and plugin
+//! settings without restarting the process. Settings which are bound to the
+//! HTTP listeners or to process initialization keep their current value.
+
+use crate::config::AppSettings;
+use crate::graph::State;
+use commons::prelude_errors::*;
+use parking_lot::RwLock;
+use prometheus::core::{Collector, Desc};
+use prometheus::proto::MetricFamily;
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::sync::Arc;
+use tokio::signal::unix::{signal, SignalKind};
+
+/// Registries holding the metrics of the current plugins of each graph.
+///
+/// Plugins register their metrics when they are built, so reloaded plugins
+/// get a fresh registry. This collector is registered once in the main
+/// registry and always exposes the metrics of the current registries, those
+/// of a tenant being labeled with its name.
+#[derive(Clone)]
+pub struct PluginRegistry {
+    /// Tenant whose registry is handled, unset for the default graph.
+    tenant: Option<String>,
+    registries: Arc<RwLock<BTreeMap<Option<String>, prometheus::Registry>>>,
+}
+
+impl Default for PluginRegistry {
+    fn default() -> Self {
+        let registries = vec![(None, prometheus::Registry::new())]
+            .into_iter()
+            .collect();
+        PluginRegistry {
+            tenant: None,
+            registries: Arc::new(RwLock::new(registries)),
+        }
+    }
+}
+
+impl PluginRegistry {
+    /// Returns the plugin registry of the tenant `name`, exposed along with this one.
+    pub fn for_tenant(&self, name: &str) -> Fallible<PluginRegistry> {
+        let tenant = PluginRegistry {
+            tenant: Some(name.to_string()),
+            registries: self.registries.clone(),
+        };
+        tenant.replace(tenant.new_registry()?);
+        Ok(tenant)
+    }
+
+    /// Returns a new registry for the plugins, labeled with the tenant if any.
+    pub fn new_registry(&self) -> Fallible<prometheus::Registry> {
+        let labels = self.tenant.as_ref().map(|tenant| {
+            vec![("tenant".to_string(), tenant.clone())]
+                .into_iter()
+                .collect()
+        });
+        prometheus::Registry::new_custom(None, labels).context("creating plugin registry")
+    }
+
+    /// Returns the registry of the current plugins.
+    pub fn current(&self) -> prometheus::Registry {
+        self.registries
+            .read()
+            .get(&self.tenant)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Replace the registry of the current plugins.
+    pub fn replace(&self, registry: prometheus::Registry) {
+        self.registries
+            .write()
+            .insert(self.tenant.clone(), registry);
+    }
+}
+
+impl Collector for PluginRegistry {
+    fn desc(&self) -> Vec<&Desc> {
+        vec![]
+    }
+
+    fn collect(&self) -> Vec<MetricFamily> {
+        self.registries
+            .read()
+            .values()
+            .flat_map(prometheus::Registry::gather)
+            .collect()
+    }
+}
+
+/// Request a configuration reload whenever the process receives SIGHUP.
+///
+/// The signal handler is installed immediately, the returned future forwards
+/// the signals to the scrape loop.
+pub fn watch_sighup(state: State) -> Fallible<impl Future<Output = ()>> {
+    let mut hangup = signal(SignalKind::hangup()).context("installing SIGHUP handler")?;
+
+    Ok(async move {
+        while hangup.recv().await.is_some() {
+            info!("received SIGHUP, reloading configuration before the next scrape");
+            state.request_reload();
+        }
+    })
+}
+
+/// Assemble the settings again and apply them.
+///
+/// The scrape loop of a tenant picks up the plugins of its tenant. Tenants
+/// can't be added or removed without a restart.
+pub fn reload(settings: &mut AppSettings, state: &State) -> Fallible<()> {
+    let mut new_settings = AppSettings::assemble().context("could not assemble AppSettings")?;
+    if let Some(tenant) = state.tenant() {
+        new_settings = new_settings.into_tenant(tenant)?;
+    }
+    apply(settings, new_settings, state)
+}
+
+/// Replace the current settings and plugins with the ones built from `new_settings`.
+///
+/// Nothing is changed if the plugins or the notification sinks can't be built.
+fn apply(settings: &mut AppSettings, mut new_settings: AppSettings, state: &State) -> Fallible<()> {
+    keep_fixed_settings(settings, &mut new_settings);
+
+    let registry = state.plugin_registry().new_registry()?;
+    let plugins = new_settings
+        .validate_and_build_plugins(Some(&registry))
+        .context("could not build plugins")?;
+    let metadata_keys = new_settings.metadata_keys()?;
+    state
+        .notifier()
+        .configure(
+            &new_settings.notification_sinks,
+            new_settings.notification_debounce,
+        )
+        .context("could not build notification sinks")?;
+
+    state.replace_plugins(plugins, registry);
+    state.record_upstream(new_settings.upstream());
+    state.record_metadata_keys(metadata_keys);
+    *settings = new_settings;
+
+    Ok(())
+}
+
+/// Restore the settings which can't be changed without a restart.
+fn keep_fixed_settings(current: &AppSettings, new: &mut AppSettings) {
+    macro_rules! keep {
+        ($($field:ident),* $(,)?) => {
+            $(
+                if current.$field != new.$field {
+                    warn!(
+                        "'{}' can't be changed without a restart, keeping {:?}",
+                        stringify!($field),
+                        current.$field
+                    );
+                    new.$field = current.$field.clone();
+                }
+            )*
+        };
+    }
+
+    keep!(
+        address,
+        addresses,
+        port,
+        socket_path,
+        path_prefix,
+        status_address,
+        status_addresses,
+        status_port,
+        status_socket_path,
+        metrics_otlp_endpoint,
+        metrics_otlp_interval,
+        admin_token_path,
+        status_auth_token_path,
+        status_auth_credentials_path,
+        audit_log_path,
+        audit_max_entries,
+        audit_max_file_bytes,
+        selftest_query,
+        selftest_server_name,
+        selftest_ca_path,
+        max_graph_staleness,
+        mandatory_client_parameters,
+        metrics_required,
+        verbosity,
+        log_format,
+        tracing_endpoint,
+        tracing_sampler,
+        tracing_sampling_ratio,
+        rate_limit_per_second,
+        rate_limit_burst,
+        rate_limit_cluster_id_param,
+        rate_limit_trust_forwarded_for,
+        deployment_name,
+        outbound_headers,
+        http_proxy,
+        https_proxy,
+        no_proxy,
+        proxy_credentials_path,
+        changelog_max_revisions,
+        shutdown_grace,
+        shutdown_drain,
+        snapshots_max,
+        cache_max_age,
+        internal_graph_api,
+        coordination,
+        cors,
+        compression_zstd_level,
+        compression_brotli_level,
+        tls_cert_path,
+        tls_key_path,
+        tls_client_ca_path,
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cincinnati::plugins::prelude::*;
+    use commons::testing;
+    use std::collections::HashSet;
+    use std::time::Duration;
+
+    #[test]
+    fn plugin_registry_collects_current() -> Fallible<()> {
+        let registry = commons::metrics::new_registry(Some("prefix".to_string()))?;
+        let plugin_registry = PluginRegistry::default();
+        registry.register(Box::new(plugin_registry.clone()))?;
+
+        testing::dummy_gauge(&plugin_registry.current(), 42.0)?;
+        let names: Vec<String> = registry
+            .gather()
+            .iter()
+            .map(|mf| mf.get_name().to_string())
+            .collect();
+        assert_eq!(names, vec!["prefix_dummy_gauge"]);
+
+        // The same metric can be registered again in a new registry.
+        let new_registry = prometheus::Registry::new();
+        testing::dummy_gauge(&new_registry, 21.0)?;
+        plugin_registry.replace(new_registry);
+        let families = registry.gather();
+        assert_eq!(families.len(), 1);
+        assert_eq!(families[0].get_metric()[0].get_gauge().get_value(), 21.0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn plugin_registry_labels_tenants() -> Fallible<()> {
+        let registry = commons::metrics::new_registry(Some("prefix".to_string()))?;
+        let plugin_registry = PluginRegistry::default();
+        registry.register(Box::new(plugin_registry.clone()))?;
+
+        testing::dummy_gauge(&plugin_registry.current(), 42.0)?;
+        let tenant_registry = plugin_registry.for_tenant("okd")?;
+        testing::dummy_gauge(&tenant_registry.current(), 21.0)?;
+
+        let families = registry.gather();
+        assert_eq!(families.len(), 1);
+        let metrics = families[0].get_metric();
+        assert_eq!(metrics.len(), 2);
+        let tenant_metric = metrics
+            .iter()
+            .find(|metric| metric.get_gauge().get_value() == 21.0)
+            .expect("tenant gauge");
+        assert_eq!(tenant_metric.get_label()[0].get_name(), "tenant");
+        assert_eq!(tenant_metric.get_label()[0].get_value(), "okd");
+
+        // A reloaded tenant registry keeps the label.
+        let new_registry = tenant_registry.new_registry()?;
+        testing::dummy_gauge(&new_registry, 7.0)?;
+        tenant_registry.replace(new_registry);
+        assert_eq!(
+            plugin_registry.current().gather()[0].get_metric()[0]
+                .get_gauge()
+                .get_value(),
+            42.0
+        );
+        let metrics = tenant_registry.current().gather()[0].get_metric().to_vec();
+        assert_eq!(metrics[0].get_gauge().get_value(), 7.0);
+        assert_eq!(metrics[0].get_label()[0].get_value(), "okd");
+
+        Ok(())
+    }
+
+    #[test]
+    fn keeps_fixed_settings() {
+        let current = AppSettings::default();
+        let mut new = AppSettings {
+            port: current.port + 1,
+            path_prefix: "/api".to_string(),
+            pause_secs: current.pause_secs + Duration::from_secs(30),
+            ..Default::default()
+        };
+
+        keep_fixed_settings(&current, &mut new);
+        assert_eq!(new.port, current.port);
+        assert_eq!(new.path_prefix, current.path_prefix);
+        assert_eq!(new.pause_secs, current.pause_secs + Duration::from_secs(30));
+    }
+
+    #[test]
+    fn apply_replaces_plugins() -> Fallible<()> {
+        let state = State::new(
+            HashSet::new(),
+            Arc::new(RwLock::new(true)),
+            Arc::new(RwLock::new(true)),
+            vec![],
+            Box::leak(Box::new(prometheus::Registry::new())),
+        );
+        let mut settings = AppSettings::default();
+        let new_settings = AppSettings {
+            pause_secs: Duration::from_secs(10),
+            plugin_settings: vec![plugin_config!(("name", EdgeAddRemovePlugin::PLUGIN_NAME))?],
+            ..Default::default()
+        };
+
+        apply(&mut settings, new_settings, &state)?;
+        assert_eq!(settings.pause_secs, Duration::from_secs(10));
+        assert_eq!(state.plugins().len(), 1);
+
+        Ok(())
+    }
+}
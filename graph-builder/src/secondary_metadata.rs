@@ -0,0 +1,326 @@
+//! Queryable secondary metadata of the published graph.
+//!
+//! The secondary metadata graph-data applies to the scraped releases, i.e.
+//! their channels and release information and the risks of the conditional
+//! updates, is indexed on publication. Clients can then query it for a
+//! version, a range of versions or a channel, rather than downloading the
+//! whole graph or the graph-data archive.
+
+use cincinnati::metadata_keys::MetadataKeys;
+use cincinnati::v2::GraphV2;
+use cincinnati::{ConditionalUpdateRisk, Graph};
+use commons::openapi::QueryParam;
+use commons::GraphError;
+use semver::Version;
+
+/// Secondary metadata of a release.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReleaseMetadata {
+    pub version: String,
+    /// Channels the release is part of.
+    pub channels: Vec<String>,
+    /// Architecture of the release payload.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub architecture: Option<String>,
+    /// Date the release was published at.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub release_date: Option<String>,
+    /// Identifier of the errata of the release.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub errata: Option<String>,
+    /// URL of the release notes.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub release_notes: Option<String>,
+}
+
+/// Risks of a conditional update.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct UpdateRisks {
+    pub from: String,
+    pub to: String,
+    /// Channels both releases are part of.
+    pub channels: Vec<String>,
+    pub risks: Vec<ConditionalUpdateRisk>,
+}
+
+/// Releases matching a query.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct ReleasesPage<'a> {
+    /// Revision of the published graph the metadata was taken from.
+    pub revision: u64,
+    /// Matching releases, by ascending version.
+    pub releases: Vec<&'a ReleaseMetadata>,
+}
+
+/// Conditional updates matching a query.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct RisksPage<'a> {
+    /// Revision of the published graph the metadata was taken from.
+    pub revision: u64,
+    /// Matching conditional updates, by ascending source version.
+    pub risks: Vec<&'a UpdateRisks>,
+}
+
+/// Index of the secondary metadata of a published graph.
+#[derive(Debug, Default)]
+pub struct SecondaryMetadata {
+    revision: u64,
+    /// Releases along with their parsed version, sorted by version.
+    releases: Vec<(Option<Version>, ReleaseMetadata)>,
+    /// Conditional updates along with the parsed version of their source, sorted by source.
+    risks: Vec<(Option<Version>, UpdateRisks)>,
+}
+
+impl SecondaryMetadata {
+    /// Index the secondary metadata of the graph published as `revision`,
+    /// reading the release metadata from the given keys.
+    pub fn new(revision: u64, graph: &Graph, keys: &MetadataKeys) -> Self {
+        let v2 = GraphV2::new(graph, None, keys);
+        let owned = |values: &[&str]| -> Vec<String> {
+            values.iter().map(|value| value.to_string()).collect()
+        };
+
+        let mut releases: Vec<_> = v2
+            .nodes
+            .iter()
+            .map(|node| {
+                let metadata = ReleaseMetadata {
+                    version: node.version.to_string(),
+                    channels: owned(&node.channels),
+                    architecture: node.architecture.map(str::to_string),
+                    release_date: node.release_date.map(str::to_string),
+                    errata: node.errata.map(str::to_string),
+                    release_notes: node.release_notes.map(str::to_string),
+                };
+                (Version::parse(node.version).ok(), metadata)
+            })
+            .collect();
+        releases.sort_by(|(a, a_meta), (b, b_meta)| {
+            a.cmp(b).then_with(|| a_meta.version.cmp(&b_meta.version))
+        });
+
+        let mut risks: Vec<_> = v2
+            .edges
+            .iter()
+            .filter(|edge| !edge.risks.is_empty())
+            .map(|edge| {
+                let update = UpdateRisks {
+                    from: edge.from.to_string(),
+                    to: edge.to.to_string(),
+                    channels: owned(&edge.channels),
+                    risks: edge.risks.iter().map(|risk| (*risk).clone()).collect(),
+                };
+                (Version::parse(edge.from).ok(), update)
+            })
+            .collect();
+        risks.sort_by(|(a, a_update), (b, b_update)| {
+            a.cmp(b)
+                .then_with(|| a_update.from.cmp(&b_update.from))
+                .then_with(|| a_update.to.cmp(&b_update.to))
+        });
+
+        Self {
+            revision,
+            releases,
+            risks,
+        }
+    }
+
+    /// Returns the releases matching `query`.
+    pub fn releases(&self, query: &Query) -> ReleasesPage<'_> {
+        ReleasesPage {
+            revision: self.revision,
+            releases: self
+                .releases
+                .iter()
+                .filter(|(version, release)| query.matches(version.as_ref(), &release.channels))
+                .map(|(_, release)| release)
+                .collect(),
+        }
+    }
+
+    /// Returns the conditional updates whose source release matches `query`.
+    ///
+    /// With a channel, only the updates within that channel match.
+    pub fn risks(&self, query: &Query) -> RisksPage<'_> {
+        RisksPage {
+            revision: self.revision,
+            risks: self
+                .risks
+                .iter()
+                .filter(|(version, update)| query.matches(version.as_ref(), &update.channels))
+                .map(|(_, update)| update)
+                .collect(),
+        }
+    }
+}
+
+/// Query parameter matching the exact version of a release.
+pub static VERSION_PARAM: QueryParam = QueryParam::new(
+    "version",
+    "Exact version of the releases, or of the source release of the updates",
+);
+
+/// Query parameter matching versions from a lower bound.
+pub static MIN_VERSION_PARAM: QueryParam =
+    QueryParam::new("min_version", "Lowest version, inclusive");
+
+/// Query parameter matching versions up to an upper bound.
+pub static MAX_VERSION_PARAM: QueryParam =
+    QueryParam::new("max_version", "Highest version, inclusive");
+
+/// Query parameter matching the releases of a channel.
+pub static CHANNEL_PARAM: QueryParam =
+    QueryParam::new("channel", "Channel the releases are part of");
+
+/// Query parameters read by [`Query::parse`].
+pub static QUERY_PARAMS: &[&QueryParam] = &[
+    &VERSION_PARAM,
+    &MIN_VERSION_PARAM,
+    &MAX_VERSION_PARAM,
+    &CHANNEL_PARAM,
+];
+
+/// Query of the secondary metadata.
+///
+/// All given criteria must match.
+#[derive(Debug, Default, PartialEq)]
+pub struct Query {
+    /// Exact version, regardless of build metadata such as the `+arch` suffix.
+    pub version: Option<Version>,
+    /// Lowest version, inclusive.
+    pub min_version: Option<Version>,
+    /// Highest version, inclusive.
+    pub max_version: Option<Version>,
+    pub channel: Option<String>,
+}
+
+impl Query {
+    /// Parse the [`QUERY_PARAMS`].
+    pub fn parse(query: &str) -> Result<Self, GraphError> {
+        let version = |param: &QueryParam| match param.get(query) {
+            Some(version) => Version::parse(&version).map(Some).map_err(|e| {
+                GraphError::InvalidParams(format!("invalid {} '{}': {}", param.name, version, e))
+            }),
+            None => Ok(None),
+        };
+
+        Ok(Self {
+            version: version(&VERSION_PARAM)?,
+            min_version: version(&MIN_VERSION_PARAM)?,
+            max_version: version(&MAX_VERSION_PARAM)?,
+            channel: CHANNEL_PARAM.get(query),
+        })
+    }
+
+    fn matches(&self, parsed: Option<&Version>, channels: &[String]) -> bool {
+        let in_range = |bound: &Option<Version>, ok: fn(&Version, &Version) -> bool| match bound {
+            Some(bound) => parsed.is_some_and(|parsed| ok(parsed, bound)),
+            None => true,
+        };
+
+        // Version equality ignores build metadata.
+        in_range(&self.version, |version, wanted| version == wanted)
+            && in_range(&self.min_version, |version, min| version >= min)
+            && in_range(&self.max_version, |version, max| version <= max)
+            && self
+                .channel
+                .as_ref()
+                .is_none_or(|channel| channels.contains(channel))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cincinnati::testing::generate_graph;
+
+    fn metadata() -> SecondaryMetadata {
+        let keys = MetadataKeys::default();
+        let mut graph = generate_graph(true, false);
+        for (version, channels) in &[
+            ("1.0.0", "stable-1,fast-1"),
+            ("2.0.0", "stable-1,fast-1"),
+            ("3.0.0", "fast-1"),
+        ] {
+            let id = graph.find_by_version(version).unwrap();
+            let metadata = graph.get_metadata_as_ref_mut(&id).unwrap();
+            metadata.insert(keys.channels.as_str().into(), (*channels).into());
+        }
+        SecondaryMetadata::new(7, &graph, &keys)
+    }
+
+    fn versions(page: ReleasesPage<'_>) -> Vec<&str> {
+        page.releases
+            .iter()
+            .map(|release| release.version.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn query_releases() {
+        let metadata = metadata();
+        let query = |query: &str| Query::parse(query).unwrap();
+
+        let page = metadata.releases(&query(""));
+        assert_eq!(page.revision, 7);
+        assert_eq!(versions(page), vec!["1.0.0", "2.0.0", "3.0.0"]);
+        assert_eq!(
+            versions(metadata.releases(&query("version=2.0.0"))),
+            vec!["2.0.0"]
+        );
+        assert_eq!(
+            versions(metadata.releases(&query("min_version=2.0.0&max_version=3.0.0"))),
+            vec!["2.0.0", "3.0.0"]
+        );
+        assert_eq!(
+            versions(metadata.releases(&query("channel=stable-1&min_version=1.5.0"))),
+            vec!["2.0.0"]
+        );
+        assert_eq!(
+            metadata.releases(&query("channel=stable-1")).releases[0].channels,
+            vec!["stable-1", "fast-1"]
+        );
+
+        assert!(Query::parse("min_version=4.14").is_err());
+        assert!(Query::parse("version=4.14").is_err());
+    }
+
+    #[test]
+    fn query_ignores_build_metadata() {
+        let query = Query::parse("version=4.14.3").unwrap();
+        let channels = vec!["stable-4.14".to_string()];
+
+        for (version, matches) in &[
+            ("4.14.3+amd64", true),
+            ("4.14.3", true),
+            ("4.14.3-rc.1+amd64", false),
+            ("4.14.30+amd64", false),
+        ] {
+            let parsed = Version::parse(version).unwrap();
+            assert_eq!(
+                query.matches(Some(&parsed), &channels),
+                *matches,
+                "{}",
+                version
+            );
+        }
+        assert!(!query.matches(None, &channels));
+    }
+
+    #[test]
+    fn query_risks() {
+        let metadata = metadata();
+        let query = |query: &str| Query::parse(query).unwrap();
+
+        let page = metadata.risks(&query("version=1.0.0"));
+        assert_eq!(page.risks.len(), 1);
+        assert_eq!(page.risks[0].to, "2.0.0");
+        assert_eq!(page.risks[0].risks[0].name, "BrokenUpdates");
+        assert_eq!(metadata.risks(&query("channel=stable-1")).risks.len(), 1);
+
+        assert!(metadata.risks(&query("version=2.0.0")).risks.is_empty());
+        assert!(metadata.risks(&query("channel=fast-2")).risks.is_empty());
+    }
+}
@@ -0,0 +1,68 @@
+//! Graceful shutdown on SIGTERM and SIGINT.
+//!
+//! Rolling updates terminate the process while clients are being served and
+//! while the scrape loop may be publishing a new graph. On termination the
+//! readiness is flipped so that load balancers stop routing new requests, and
+//! once they had the drain delay to notice, the main service stops accepting
+//! connections and drains the in-flight requests. Meanwhile the scrape loop
+//! exits once its ongoing scrape is done. Both are bounded by the configured
+//! grace period.
+
+use crate::graph::State;
+use actix_web::dev::ServerHandle;
+use commons::prelude_errors::*;
+use futures::future;
+use std::future::Future;
+use std::time::Duration;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::oneshot;
+
+/// Default grace period for in-flight requests and the ongoing scrape.
+pub static DEFAULT_GRACE_PERIOD: Duration = Duration::from_secs(30);
+
+/// Default delay between flipping the readiness and stopping the main service.
+pub static DEFAULT_DRAIN_DELAY: Duration = Duration::from_secs(5);
+
+/// Shut down gracefully when the process receives SIGTERM or SIGINT.
+///
+/// The signal handlers are installed immediately. The returned future stops
+/// the main service `drain_delay` after flipping the readiness, while waiting for the scrape loops of the default graph
+/// and of the tenants, `states`, to signal their exit on `scrapers_done`, and
+/// stops the status service last, so that probes keep being answered until
+/// the end.
+pub fn watch_termination(
+    states: Vec<State>,
+    grace_period: Duration,
+    drain_delay: Duration,
+    main_server: ServerHandle,
+    status_server: ServerHandle,
+    scrapers_done: Vec<oneshot::Receiver<()>>,
+) -> Fallible<impl Future<Output = ()>> {
+    let mut terminate = signal(SignalKind::terminate()).context("installing SIGTERM handler")?;
+    let mut interrupt = signal(SignalKind::interrupt()).context("installing SIGINT handler")?;
+
+    Ok(async move {
+        future::select(Box::pin(terminate.recv()), Box::pin(interrupt.recv())).await;
+        info!(
+            "received termination signal, shutting down within {}s",
+            grace_period.as_secs()
+        );
+        states.iter().for_each(State::request_shutdown);
+
+        let scrapers = future::join_all(scrapers_done.into_iter().map(|scraper_done| async move {
+            match tokio::time::timeout(grace_period, scraper_done).await {
+                Ok(Ok(())) => info!("scraper stopped"),
+                Ok(Err(_)) => error!("scraper thread exited abnormally"),
+                Err(_) => warn!("scraper still busy at the end of the grace period"),
+            }
+        }));
+        let main_service = async move {
+            tokio::time::sleep(drain_delay).await;
+            main_server.stop(true).await;
+            info!("main service stopped");
+        };
+        future::join(main_service, scrapers).await;
+
+        status_server.stop(true).await;
+    })
+}
@@ -0,0 +1,134 @@
+//! Recent snapshots of the published graph.
+//!
+//! Every published graph is identified by the hash of its content, sent in the
+//! `Cincinnati-Graph-Hash` header of graph responses. The latest published
+//! graphs are kept, in memory, so that the exact graph a cluster was served can
+//! be fetched again with `?snapshot=<hash>` while investigating an update.
+
+use bytes::Bytes;
+use cincinnati::provenance::Provenance;
+use parking_lot::RwLock;
+use std::collections::VecDeque;
+use std::sync::Arc;
+
+/// Default number of snapshots kept.
+pub static DEFAULT_MAX_SNAPSHOTS: usize = 10;
+
+/// A published graph, along with its provenance.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Snapshot {
+    /// Provenance of the graph, its hash being the snapshot identifier.
+    pub provenance: Provenance,
+    /// The graph, serialized as JSON.
+    pub json: Bytes,
+}
+
+/// Bounded ring buffer of the latest published graphs.
+#[derive(Clone, Debug)]
+pub struct Snapshots {
+    snapshots: Arc<RwLock<VecDeque<Snapshot>>>,
+    max_snapshots: usize,
+}
+
+impl Default for Snapshots {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_SNAPSHOTS)
+    }
+}
+
+impl Snapshots {
+    /// Create a ring buffer keeping the latest `max_snapshots` graphs.
+    pub fn new(max_snapshots: usize) -> Self {
+        Self {
+            snapshots: Default::default(),
+            max_snapshots,
+        }
+    }
+
+    /// Record a newly published graph, dropping the oldest one if full.
+    pub fn record(&self, provenance: Provenance, json: Bytes) {
+        if self.max_snapshots == 0 {
+            return;
+        }
+
+        let mut snapshots = self.snapshots.write();
+        // The same content may be published again after a revert.
+        snapshots.retain(|snapshot| snapshot.provenance.hash != provenance.hash);
+        snapshots.push_back(Snapshot { provenance, json });
+        while snapshots.len() > self.max_snapshots {
+            snapshots.pop_front();
+        }
+    }
+
+    /// Returns the snapshot with the given identifier, with or without its `sha256:` prefix.
+    pub fn get(&self, id: &str) -> Option<Snapshot> {
+        let id = id.trim_start_matches("sha256:");
+        self.snapshots
+            .read()
+            .iter()
+            .rev()
+            .find(|snapshot| {
+                snapshot
+                    .provenance
+                    .hash
+                    .as_deref()
+                    .map(|hash| hash.trim_start_matches("sha256:"))
+                    == Some(id)
+            })
+            .cloned()
+    }
+
+    /// Returns the identifiers of the kept snapshots, oldest first.
+    pub fn ids(&self) -> Vec<String> {
+        self.snapshots
+            .read()
+            .iter()
+            .filter_map(|snapshot| snapshot.provenance.hash.clone())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cincinnati::provenance::content_hash;
+
+    fn record(snapshots: &Snapshots, json: &str) -> String {
+        let hash = content_hash(json.as_bytes());
+        snapshots.record(
+            Provenance {
+                hash: Some(hash.clone()),
+                ..Default::default()
+            },
+            Bytes::copy_from_slice(json.as_bytes()),
+        );
+        hash
+    }
+
+    #[test]
+    fn ring_buffer() {
+        let snapshots = Snapshots::new(2);
+        let first = record(&snapshots, r#"{"nodes":[1]}"#);
+        let second = record(&snapshots, r#"{"nodes":[2]}"#);
+        assert_eq!(snapshots.ids(), vec![first.clone(), second.clone()]);
+
+        let snapshot = snapshots.get(&first).unwrap();
+        assert_eq!(snapshot.json, r#"{"nodes":[1]}"#);
+        assert_eq!(
+            snapshots.get(first.trim_start_matches("sha256:")),
+            Some(snapshot)
+        );
+
+        let third = record(&snapshots, r#"{"nodes":[3]}"#);
+        assert_eq!(snapshots.ids(), vec![second.clone(), third.clone()]);
+        assert!(snapshots.get(&first).is_none());
+
+        // Publishing the same content again moves it to the end.
+        record(&snapshots, r#"{"nodes":[2]}"#);
+        assert_eq!(snapshots.ids(), vec![third, second]);
+
+        let disabled = Snapshots::new(0);
+        record(&disabled, "{}");
+        assert!(disabled.ids().is_empty());
+    }
+}
@@ -1,7 +1,18 @@
 //! Status service.
 
+use crate::audit;
+use crate::built_info;
 use crate::graph::State;
-use actix_web::HttpResponse;
+use crate::graph_data_check::{self, CheckError};
+use crate::health::{self, Health};
+use actix_web::http::StatusCode;
+use actix_web::web::Bytes;
+use actix_web::{HttpRequest, HttpResponse, ResponseError};
+use cincinnati::plugins::internal::openshift_secondary_metadata_parser::schema;
+use commons::auth;
+use commons::metrics;
+use commons::openapi::{ApiRoutes, Endpoint, Param};
+use commons::{GraphError, ProblemDetails};
 
 /// Expose liveness status.
 ///
@@ -12,19 +23,464 @@ pub async fn serve_liveness(app_data: actix_web::web::Data<State>) -> HttpRespon
     if app_data.is_live() {
         HttpResponse::Ok().finish()
     } else {
-        HttpResponse::ServiceUnavailable().finish()
+        ProblemDetails::new(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "not_live",
+            "Service not live",
+            "the service has not started yet".to_string(),
+        )
+        .into_response()
     }
 }
 
 /// Expose readiness status.
 ///
 /// Status:
-///  * Ready (200 code): a JSON graph as the result of a successful scrape is available.
-///  * Not Ready (503 code): no JSON graph available yet.
+///  * Ready (200 code): a JSON graph as the result of a successful scrape is
+///    available, for the default graph and for each tenant.
+///  * Not Ready (503 code): no JSON graph available yet, or the last successful
+///    refresh is older than the maximum staleness, for any of the graphs.
 pub async fn serve_readiness(app_data: actix_web::web::Data<State>) -> HttpResponse {
-    if app_data.is_ready() {
-        HttpResponse::Ok().finish()
+    let now = chrono::Utc::now().timestamp();
+    for state in std::iter::once(&**app_data).chain(app_data.tenants()) {
+        let of_tenant = state
+            .tenant()
+            .map(|tenant| format!(" for tenant '{}'", tenant))
+            .unwrap_or_default();
+
+        if !state.is_ready() {
+            return ProblemDetails::new(
+                StatusCode::SERVICE_UNAVAILABLE,
+                "not_ready",
+                "Service not ready",
+                format!("no graph is available yet{}", of_tenant),
+            )
+            .into_response();
+        }
+
+        if let Some(age) = state.staleness(now) {
+            return ProblemDetails::new(
+                StatusCode::SERVICE_UNAVAILABLE,
+                "stale_graph",
+                "Graph is stale",
+                format!(
+                    "last successful graph refresh{} {}s ago",
+                    of_tenant,
+                    age.as_secs()
+                ),
+            )
+            .into_response();
+        }
+    }
+
+    HttpResponse::Ok().finish()
+}
+
+/// Expose the health of each subsystem.
+///
+/// The report tells the freshness of the scraped data, the reachability of
+/// the upstreams, the outcome of the last plugin runs and the size of the
+/// published graph, each either `ok`, `degraded` or `failed`.
+///
+/// Status:
+///  * OK (200 code): no subsystem failed.
+///  * Service Unavailable (503 code): at least one subsystem failed.
+pub async fn serve_health_detail(app_data: actix_web::web::Data<State>) -> HttpResponse {
+    let report = health::assess(
+        &app_data.debug().snapshot(),
+        app_data.is_ready(),
+        chrono::Utc::now().timestamp(),
+    );
+
+    if report.status == Health::Failed {
+        HttpResponse::ServiceUnavailable().json(report)
+    } else {
+        HttpResponse::Ok().json(report)
+    }
+}
+
+/// Exercise the graph endpoint of the main service as clients do.
+///
+/// Status:
+///  * OK (200 code): all checks passed.
+///  * Too Many Requests (429 code): the previous run is too recent.
+///  * Service Unavailable (503 code): at least one check failed.
+pub async fn serve_selftest(app_data: actix_web::web::Data<State>) -> HttpResponse {
+    let selftest = match app_data.selftest() {
+        Some(selftest) => selftest,
+        None => {
+            return ProblemDetails::new(
+                StatusCode::SERVICE_UNAVAILABLE,
+                "selftest_unavailable",
+                "Self-test unavailable",
+                "the self-test requires status authentication and a main service listening on a TCP address"
+                    .to_string(),
+            )
+            .into_response()
+        }
+    };
+
+    if let Err(wait) = selftest.try_start() {
+        return GraphError::TooManyRequests(wait.as_secs() + 1).error_response();
+    }
+    let report = selftest.run().await;
+    if report.passed {
+        HttpResponse::Ok().json(report)
     } else {
-        HttpResponse::ServiceUnavailable().finish()
+        HttpResponse::ServiceUnavailable().json(report)
+    }
+}
+
+/// Build and configuration of the running process.
+#[derive(Debug, Serialize)]
+pub struct BuildInfo {
+    /// Crate version.
+    pub version: &'static str,
+    /// Git commit the binary was built from, if known.
+    pub git_commit: Option<&'static str>,
+    /// Build time, in RFC 2822 format.
+    pub build_timestamp: &'static str,
+    /// Names of the configured plugins, in order.
+    pub plugins: Vec<&'static str>,
+    /// Configured graph source.
+    pub upstream: String,
+}
+
+impl BuildInfo {
+    /// Collect the build information along with the current configuration.
+    pub fn new(state: &State) -> Self {
+        BuildInfo {
+            version: built_info::PKG_VERSION,
+            git_commit: built_info::GIT_COMMIT_HASH,
+            build_timestamp: built_info::BUILT_TIME_UTC,
+            plugins: state
+                .configured_plugins()
+                .iter()
+                .map(|plugin| plugin.get_name())
+                .collect(),
+            upstream: state.upstream(),
+        }
+    }
+}
+
+/// Expose the build and configuration of this replica.
+pub async fn serve_version(app_data: actix_web::web::Data<State>) -> HttpResponse {
+    HttpResponse::Ok().json(BuildInfo::new(&app_data))
+}
+
+/// Paths of the admin endpoints, which are authenticated with the admin token
+/// rather than with the status credentials.
+pub static ADMIN_PATHS: [&str; 6] = [
+    "/rescrape",
+    "/validation/override",
+    "/freeze/override",
+    "/graph-data/check",
+    "/debug",
+    "/audit",
+];
+
+/// Check the bearer token of a request to an admin endpoint.
+fn authorize_admin(req: &HttpRequest, state: &State) -> Result<(), HttpResponse> {
+    let expected = match state.admin_token() {
+        Some(token) => token,
+        None => {
+            return Err(ProblemDetails::new(
+                StatusCode::FORBIDDEN,
+                "admin_disabled",
+                "Admin endpoints disabled",
+                "no admin token has been configured".to_string(),
+            )
+            .into_response())
+        }
+    };
+
+    if auth::has_bearer_token(req.headers(), expected) {
+        return Ok(());
+    }
+    Err(ProblemDetails::new(
+        StatusCode::UNAUTHORIZED,
+        "unauthorized",
+        "Unauthorized",
+        "missing or invalid bearer token".to_string(),
+    )
+    .into_response())
+}
+
+/// Trigger an immediate rescrape.
+///
+/// Status:
+///  * Accepted (202 code): the scraper will start a new scrape as soon as the
+///    current one, if any, has finished.
+///  * Unauthorized (401 code) / Forbidden (403 code): see `authorize_admin`.
+pub async fn serve_rescrape(
+    req: HttpRequest,
+    app_data: actix_web::web::Data<State>,
+) -> HttpResponse {
+    if let Err(response) = authorize_admin(&req, &app_data) {
+        return response;
+    }
+
+    app_data.trigger_rescrape();
+    info!("immediate rescrape requested");
+    HttpResponse::Accepted().finish()
+}
+
+/// Allow the next scraped graph to bypass the validation change thresholds.
+///
+/// Structural validation still applies. The override is consumed by the
+/// first graph which is published afterwards.
+pub async fn serve_validation_override(
+    req: HttpRequest,
+    app_data: actix_web::web::Data<State>,
+) -> HttpResponse {
+    if let Err(response) = authorize_admin(&req, &app_data) {
+        return response;
+    }
+
+    app_data.request_validation_override();
+    warn!("validation change thresholds will be overridden for the next graph");
+    HttpResponse::Accepted().finish()
+}
+
+/// Allow the next scraped graph to be published despite an ongoing freeze.
+///
+/// The override is consumed by the first graph which is published afterwards.
+pub async fn serve_freeze_override(
+    req: HttpRequest,
+    app_data: actix_web::web::Data<State>,
+) -> HttpResponse {
+    if let Err(response) = authorize_admin(&req, &app_data) {
+        return response;
     }
+
+    app_data.request_freeze_override();
+    warn!("the freeze will be overridden for the next graph");
+    HttpResponse::Accepted().finish()
+}
+
+/// Expose the state of the scraper and its plugins.
+///
+/// Returns the last scrape timestamps and error, the outcome of the last
+/// plugin runs and a summary of the published graph, including the edges
+/// blocked by conditional update risks. `section` restricts the response to
+/// one of `scrape`, `plugins` or `graph`.
+pub async fn serve_debug(req: HttpRequest, app_data: actix_web::web::Data<State>) -> HttpResponse {
+    if let Err(response) = authorize_admin(&req, &app_data) {
+        return response;
+    }
+
+    let info = app_data.debug().snapshot();
+    match req.match_info().get("section") {
+        None => HttpResponse::Ok().json(info),
+        Some("scrape") => HttpResponse::Ok().json(info.scrape),
+        Some("plugins") => HttpResponse::Ok().json(info.plugins),
+        Some("graph") => HttpResponse::Ok().json(info.graph),
+        Some(section) => ProblemDetails::new(
+            StatusCode::NOT_FOUND,
+            "not_found",
+            "Not found",
+            format!("unknown debug section '{}'", section),
+        )
+        .into_response(),
+    }
+}
+
+/// Expose the latest published graph transitions, newest first.
+///
+/// `limit` is the maximum number of transitions to return.
+///
+/// Status:
+///  * OK (200 code): the transitions kept in memory, up to `limit`.
+///  * Bad Request (400 code): invalid `limit`.
+///  * Unauthorized (401 code) / Forbidden (403 code): see `authorize_admin`.
+pub async fn serve_audit(
+    req: HttpRequest,
+    app_data: actix_web::web::Data<State>,
+) -> Result<HttpResponse, GraphError> {
+    if let Err(response) = authorize_admin(&req, &app_data) {
+        return Ok(response);
+    }
+
+    let limit = audit::parse_limit(req.query_string())?;
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "events": app_data.audit().recent(limit),
+    })))
+}
+
+/// Expose the JSON schema graph-data is validated against.
+///
+/// The `version` field holds the graph-data format version the schema
+/// describes, `supportedVersions` the versions which are accepted.
+pub async fn serve_graph_data_schema() -> HttpResponse {
+    HttpResponse::Ok().json(schema::schema())
+}
+
+/// Check candidate graph-data against the scraped releases.
+///
+/// The body is a gzipped tarball of the graph-data repository. The response
+/// lists the errors and lints of the resulting graph, and its differences
+/// with the published one.
+///
+/// Status:
+///  * OK (200 code): the graph-data has been processed, see the report for errors.
+///  * Bad Request (400 code): the archive can't be extracted.
+///  * Service Unavailable (503 code): no releases have been scraped yet.
+///  * Unauthorized (401 code) / Forbidden (403 code): see `authorize_admin`.
+pub async fn serve_graph_data_check(
+    req: HttpRequest,
+    body: Bytes,
+    app_data: actix_web::web::Data<State>,
+) -> HttpResponse {
+    if let Err(response) = authorize_admin(&req, &app_data) {
+        return response;
+    }
+
+    match graph_data_check::check(&app_data, body.to_vec()).await {
+        Ok(report) => HttpResponse::Ok().json(report),
+        Err(CheckError::InvalidArchive(reason)) => ProblemDetails::new(
+            StatusCode::BAD_REQUEST,
+            "invalid_archive",
+            "Invalid graph-data archive",
+            reason,
+        )
+        .into_response(),
+        Err(CheckError::NotReady(reason)) => ProblemDetails::new(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "not_ready",
+            "Service not ready",
+            reason,
+        )
+        .into_response(),
+    }
+}
+
+/// Routes of the status service.
+pub fn routes() -> ApiRoutes {
+    let bearer = || Param::header("Authorization", "Admin bearer token").required();
+
+    ApiRoutes::default()
+        .route(
+            Endpoint::get("/liveness", "Liveness of the scrape loop")
+                .response(200, "Live")
+                .response(503, "Not live"),
+            serve_liveness,
+        )
+        .route(
+            Endpoint::get("/metrics", "Prometheus metrics").response(200, "The metrics"),
+            metrics::serve::<State>,
+        )
+        .route(
+            Endpoint::get("/readiness", "Readiness to serve graphs")
+                .response(200, "Ready")
+                .response(503, "Not ready"),
+            serve_readiness,
+        )
+        .route(
+            Endpoint::get("/healthz/detail", "Detailed health of the service")
+                .response(200, "Healthy")
+                .response(503, "Unhealthy"),
+            serve_health_detail,
+        )
+        .route(
+            Endpoint::get("/version", "Build and configuration of the service")
+                .response(200, "Build information"),
+            serve_version,
+        )
+        .route(
+            Endpoint::get(
+                "/status/selftest",
+                "Run a self-test against the published graph",
+            )
+            .response(200, "Self-test passed")
+            .response(429, "Previous self-test too recent")
+            .response(503, "Self-test failed"),
+            serve_selftest,
+        )
+        .route(
+            Endpoint::post("/rescrape", "Trigger an immediate rescrape")
+                .param(bearer())
+                .response(202, "Rescrape scheduled")
+                .response(401, "Missing or invalid bearer token")
+                .response(403, "Admin endpoints disabled"),
+            serve_rescrape,
+        )
+        .route(
+            Endpoint::post(
+                "/validation/override",
+                "Let the next graph bypass the validation change thresholds",
+            )
+            .param(bearer())
+            .response(202, "Override scheduled")
+            .response(401, "Missing or invalid bearer token")
+            .response(403, "Admin endpoints disabled"),
+            serve_validation_override,
+        )
+        .route(
+            Endpoint::post(
+                "/freeze/override",
+                "Let the next graph be published despite an ongoing freeze",
+            )
+            .param(bearer())
+            .response(202, "Override scheduled")
+            .response(401, "Missing or invalid bearer token")
+            .response(403, "Admin endpoints disabled"),
+            serve_freeze_override,
+        )
+        .route(
+            Endpoint::get(
+                "/graph-data/schema",
+                "JSON schema graph-data is validated against",
+            )
+            .response(200, "The schema"),
+            serve_graph_data_schema,
+        )
+        .route_with(
+            Endpoint::post(
+                "/graph-data/check",
+                "Check a gzipped tarball of graph-data against the scraped releases",
+            )
+            .param(bearer())
+            .response(200, "Check report")
+            .response(400, "Invalid archive")
+            .response(401, "Missing or invalid bearer token")
+            .response(403, "Admin endpoints disabled")
+            .response(503, "No releases scraped yet"),
+            serve_graph_data_check,
+            |resource| {
+                resource.app_data(actix_web::web::PayloadConfig::new(
+                    graph_data_check::MAX_ARCHIVE_SIZE,
+                ))
+            },
+        )
+        .route(
+            Endpoint::get("/debug", "State of the scraper and its plugins")
+                .param(bearer())
+                .response(200, "Debug information")
+                .response(401, "Missing or invalid bearer token")
+                .response(403, "Admin endpoints disabled"),
+            serve_debug,
+        )
+        .route(
+            Endpoint::get("/debug/{section}", "One section of the debug information")
+                .param(Param::path(
+                    "section",
+                    "One of 'scrape', 'plugins' or 'graph'",
+                ))
+                .param(bearer())
+                .response(200, "Debug information")
+                .response(401, "Missing or invalid bearer token")
+                .response(403, "Admin endpoints disabled")
+                .response(404, "Unknown section"),
+            serve_debug,
+        )
+        .route(
+            Endpoint::get("/audit", "Latest published graph transitions")
+                .query(&[&audit::LIMIT_PARAM])
+                .param(bearer())
+                .response(200, "Graph transitions, newest first")
+                .response(400, "Invalid limit")
+                .response(401, "Missing or invalid bearer token")
+                .response(403, "Admin endpoints disabled"),
+            serve_audit,
+        )
 }
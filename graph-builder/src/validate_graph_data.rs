@@ -0,0 +1,122 @@
+//! Graph-data validation mode.
+//!
+//! Graph-data is linted from a checkout, or from a repository archive at a
+//! URL, without starting the services or scraping any release. This lets
+//! CI jobs of the graph-data repository catch mistakes before deployment.
+
+use crate::graph_data_check::{self, MAX_ARCHIVE_SIZE};
+use cincinnati::plugins::internal::openshift_secondary_metadata_parser::lint;
+use commons::prelude_errors::*;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Lint the graph-data at `source` and print the findings to stdout.
+///
+/// Fails if there is any finding, so that the process exits with a non-zero
+/// status.
+pub async fn run(source: &str) -> Fallible<()> {
+    let tmpdir = tempfile::tempdir().context("creating temporary directory")?;
+    let data_dir = if source.starts_with("http://") || source.starts_with("https://") {
+        let archive = download(source).await?;
+        let target = tmpdir.path().to_owned();
+        tokio::task::spawn_blocking(move || graph_data_check::extract(&archive, &target))
+            .await?
+            .context(format!("extracting {}", source))?
+    } else {
+        PathBuf::from(source)
+    };
+
+    let findings = check(&data_dir).await?;
+    let mut stdout = std::io::stdout();
+    for finding in &findings {
+        writeln!(stdout, "{}", finding).context("writing to stdout")?;
+    }
+    ensure!(
+        findings.is_empty(),
+        "graph-data has {} problem(s)",
+        findings.len()
+    );
+    info!("graph-data at {} is valid", source);
+
+    Ok(())
+}
+
+/// Lint the graph-data directory at `data_dir`.
+async fn check(data_dir: &Path) -> Fallible<Vec<String>> {
+    ensure!(
+        data_dir.is_dir(),
+        "{} is not a directory",
+        data_dir.display()
+    );
+    lint::lint(data_dir).await
+}
+
+/// Download a gzipped tarball of graph-data, of at most `MAX_ARCHIVE_SIZE` bytes.
+async fn download(url: &str) -> Fallible<Vec<u8>> {
+    let mut response = commons::outbound::client_builder()
+        .build()?
+        .get(url)
+        .send()
+        .await
+        .context(format!("downloading {}", url))?
+        .error_for_status()
+        .context(format!("downloading {}", url))?;
+    ensure!(
+        response
+            .content_length()
+            .is_none_or(|length| length <= MAX_ARCHIVE_SIZE as u64),
+        "{} exceeds {} bytes",
+        url,
+        MAX_ARCHIVE_SIZE
+    );
+
+    let mut archive = Vec::new();
+    while let Some(chunk) = response
+        .chunk()
+        .await
+        .context(format!("downloading {}", url))?
+    {
+        ensure!(
+            archive.len() + chunk.len() <= MAX_ARCHIVE_SIZE,
+            "{} exceeds {} bytes",
+            url,
+            MAX_ARCHIVE_SIZE
+        );
+        archive.extend_from_slice(&chunk);
+    }
+
+    Ok(archive)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn validate_checkout() -> Fallible<()> {
+        let rt = commons::testing::init_runtime()?;
+        let tmpdir = tempfile::tempdir()?;
+        let data_dir = tmpdir.path();
+        fs::create_dir_all(data_dir.join("channels"))?;
+        fs::write(data_dir.join("version"), "1.1.0\n")?;
+        fs::write(
+            data_dir.join("channels/stable-4.10.yaml"),
+            "name: stable-4.10\nversions:\n- 4.10.1\n",
+        )?;
+        let source = data_dir.to_string_lossy().to_string();
+        rt.block_on(run(&source))?;
+
+        fs::create_dir_all(data_dir.join("blocked-edges"))?;
+        fs::write(
+            data_dir.join("blocked-edges/4.11.0.yaml"),
+            "to: 4.11.0\nfrom: 4\\.10\\..*\n",
+        )?;
+        let error = rt.block_on(run(&source)).unwrap_err();
+        assert_eq!(error.to_string(), "graph-data has 1 problem(s)");
+
+        assert!(rt.block_on(check(&data_dir.join("missing"))).is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,249 @@
+//! Pre-publication validation of the graph produced by the plugin chain.
+//!
+//! Every freshly built graph goes through three kinds of checks before it
+//! replaces the published one:
+//!
+//!  * structural checks, which always block publication;
+//!  * lint rules, which are only logged;
+//!  * change thresholds, which block publication if too many releases or
+//!    edges disappeared compared to the last published graph. These can be
+//!    bypassed once through the admin override endpoint.
+
+use cincinnati::metadata_keys::MetadataKeys;
+use cincinnati::{Graph, Release};
+use std::fmt;
+
+pub use cincinnati::change_thresholds::{GraphSummary, Thresholds};
+
+/// Reasons for refusing to publish a graph.
+#[derive(Debug, Default)]
+pub struct ValidationError {
+    /// Violations which can't be overridden.
+    pub structural: Vec<String>,
+    /// Change threshold violations, which can be overridden.
+    pub thresholds: Vec<String>,
+}
+
+impl ValidationError {
+    fn is_empty(&self) -> bool {
+        self.structural.is_empty() && self.thresholds.is_empty()
+    }
+}
+
+impl std::error::Error for ValidationError {}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "graph validation failed:")?;
+        for violation in self.structural.iter().chain(self.thresholds.iter()) {
+            write!(f, "\n  - {}", violation)?;
+        }
+        Ok(())
+    }
+}
+
+/// Validate a graph against the last published one.
+///
+/// Threshold violations are ignored if `override_thresholds` is set.
+/// Returns the summary of the validated graph.
+pub fn validate(
+    graph: &Graph,
+    keys: &MetadataKeys,
+    previous: Option<&GraphSummary>,
+    thresholds: &Thresholds,
+    override_thresholds: bool,
+) -> Result<GraphSummary, ValidationError> {
+    let summary = GraphSummary::of(graph);
+    let mut error = ValidationError {
+        structural: check_structure(graph),
+        ..Default::default()
+    };
+
+    for lint in lint(graph, keys) {
+        warn!("graph lint: {}", lint);
+    }
+
+    if let Some(previous) = previous {
+        let violations = check_thresholds(previous, &summary, thresholds);
+        if override_thresholds {
+            for violation in violations {
+                warn!("overriding change threshold: {}", violation);
+            }
+        } else {
+            error.thresholds = violations;
+        }
+    }
+
+    if error.is_empty() {
+        Ok(summary)
+    } else {
+        Err(error)
+    }
+}
+
+/// Structural checks on the releases of the graph.
+pub fn check_structure(graph: &Graph) -> Vec<String> {
+    let mut violations = vec![];
+
+    for release in graph.iter_releases() {
+        match release {
+            Release::Abstract(release) => violations.push(format!(
+                "release {} is abstract and has no payload",
+                release.version
+            )),
+            Release::Concrete(release) => {
+                if let Err(e) = cincinnati::semver_cache::parse_version(&release.version) {
+                    violations.push(format!(
+                        "release {} has an invalid version: {}",
+                        release.version, e
+                    ));
+                }
+                if release.payload.is_empty() {
+                    violations.push(format!("release {} has an empty payload", release.version));
+                }
+            }
+        }
+    }
+
+    violations
+}
+
+/// Non-blocking sanity checks, reading the release metadata from the given keys.
+pub fn lint(graph: &Graph, keys: &MetadataKeys) -> Vec<String> {
+    let mut lints = vec![];
+
+    if graph.releases_count() > 0 && graph.edges_count() == 0 {
+        lints.push("graph has releases but no edges".to_string());
+    }
+
+    let without_channels = graph
+        .iter_releases()
+        .filter(|release| match release {
+            Release::Concrete(release) => !release.metadata.contains_key(keys.channels.as_str()),
+            Release::Abstract(_) => false,
+        })
+        .count();
+    if without_channels > 0 {
+        lints.push(format!(
+            "{} release(s) are not part of any channel",
+            without_channels
+        ));
+    }
+
+    lints
+}
+
+/// Compare the new graph with the previous one against the thresholds.
+fn check_thresholds(
+    previous: &GraphSummary,
+    current: &GraphSummary,
+    thresholds: &Thresholds,
+) -> Vec<String> {
+    thresholds
+        .check(previous, current)
+        .into_iter()
+        .map(|(_, violation)| violation)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cincinnati::testing::generate_custom_graph;
+
+    static THRESHOLDS: Thresholds = Thresholds {
+        max_releases_removed_percent: 20.0,
+        max_edges_removed_percent: 20.0,
+    };
+
+    fn graph(releases: usize, edges: Vec<(usize, usize)>) -> Graph {
+        generate_custom_graph(
+            "image",
+            (0..releases).map(|i| (i, Default::default())).collect(),
+            Some(edges),
+        )
+    }
+
+    #[test]
+    fn accepts_first_graph() {
+        let graph = graph(3, vec![(0, 1), (1, 2)]);
+        let summary = validate(&graph, &MetadataKeys::default(), None, &THRESHOLDS, false).unwrap();
+        assert_eq!(
+            summary,
+            GraphSummary {
+                releases: 3,
+                edges: 2
+            }
+        );
+    }
+
+    #[test]
+    fn rejects_disappearing_edges() {
+        let previous = GraphSummary {
+            releases: 3,
+            edges: 3,
+        };
+        let graph = graph(3, vec![(0, 1)]);
+
+        let keys = MetadataKeys::default();
+        let error = validate(&graph, &keys, Some(&previous), &THRESHOLDS, false).unwrap_err();
+        assert!(error.structural.is_empty());
+        assert_eq!(error.thresholds.len(), 1);
+        assert!(error.thresholds[0].contains("of edges disappeared"));
+
+        assert!(validate(&graph, &keys, Some(&previous), &THRESHOLDS, true).is_ok());
+    }
+
+    #[test]
+    fn threshold_tolerates_growth_and_small_changes() {
+        let previous = GraphSummary {
+            releases: 10,
+            edges: 10,
+        };
+        let current = GraphSummary {
+            releases: 9,
+            edges: 20,
+        };
+        assert!(check_thresholds(&previous, &current, &THRESHOLDS).is_empty());
+
+        let previous = GraphSummary::default();
+        assert!(check_thresholds(&previous, &current, &THRESHOLDS).is_empty());
+    }
+
+    #[test]
+    fn rejects_invalid_versions() {
+        let mut graph = graph(2, vec![(0, 1)]);
+        graph
+            .iter_releases_mut(|release| {
+                if let Release::Concrete(release) = release {
+                    release.version = format!("v{}", release.version).into();
+                }
+                Ok(())
+            })
+            .unwrap();
+
+        let error =
+            validate(&graph, &MetadataKeys::default(), None, &THRESHOLDS, true).unwrap_err();
+        assert_eq!(error.structural.len(), 2);
+    }
+
+    #[test]
+    fn lints_releases_without_channels() {
+        let keys = MetadataKeys::with_prefix("com.example.graph");
+        let mut graph = graph(2, vec![(0, 1)]);
+        let id = graph.find_by_version("0.0.0").unwrap();
+        graph
+            .get_metadata_as_ref_mut(&id)
+            .unwrap()
+            .insert(keys.channels.as_str().into(), "stable-1".into());
+
+        assert_eq!(
+            lint(&graph, &keys),
+            vec!["1 release(s) are not part of any channel".to_string()]
+        );
+        assert_eq!(
+            lint(&graph, &MetadataKeys::default()),
+            vec!["2 release(s) are not part of any channel".to_string()]
+        );
+    }
+}
@@ -0,0 +1,345 @@
+//! Batch graph queries.
+//!
+//! Fleet-management tools query recommendations for many clusters at once.
+//! Instead of one graph request per cluster, they can POST a list of
+//! `(version, channel, arch)` tuples and receive the update recommendations
+//! for each of them. The plugin chain runs once per distinct
+//! `(channel, arch)` pair, with up to [`MAX_CONCURRENT_GROUPS`] runs at once.
+
+use crate::graph::{api_response_error, run_plugins};
+use crate::AppState;
+use actix_web::http::header;
+use actix_web::web::{Bytes, Query};
+use actix_web::{HttpRequest, HttpResponse};
+use cincinnati::{ConcreteRelease, Graph, Release, CONTENT_TYPE};
+use commons::tracing::get_tracer;
+use commons::{Fallible, GraphError, ProblemDetails};
+use futures::stream::{self, StreamExt};
+use opentelemetry::trace::{mark_span_as_active, Tracer};
+use prometheus::{IntCounter, Registry};
+use std::collections::{BTreeMap, HashMap};
+
+/// Maximum number of queries in a single batch request.
+pub(crate) static MAX_BATCH_QUERIES: usize = 1000;
+
+/// Maximum number of plugin chain runs of a batch request executing at once.
+pub(crate) static MAX_CONCURRENT_GROUPS: usize = 8;
+
+lazy_static! {
+    static ref BATCH_QUERIES: IntCounter = IntCounter::new(
+        "graph_batch_queries_total",
+        "Total number of queries received through batch requests"
+    )
+    .unwrap();
+}
+
+/// Register relevant metrics to a prometheus registry.
+pub(crate) fn register_metrics(registry: &Registry) -> Fallible<()> {
+    registry.register(Box::new(BATCH_QUERIES.clone()))?;
+    Ok(())
+}
+
+/// Body of a batch request.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct BatchRequest {
+    queries: Vec<BatchQuery>,
+}
+
+/// A single query of a batch request.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct BatchQuery {
+    version: String,
+    channel: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    arch: Option<String>,
+}
+
+/// Body of a batch response, with one result per query in request order.
+#[derive(Debug, Serialize)]
+pub(crate) struct BatchResponse {
+    results: Vec<BatchResult>,
+}
+
+/// Result of a single query; exactly one of `recommendations` and `error` is set.
+#[derive(Debug, Serialize)]
+pub(crate) struct BatchResult {
+    #[serde(flatten)]
+    query: BatchQuery,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    recommendations: Option<Vec<ConcreteRelease>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<ProblemDetails>,
+}
+
+impl BatchResult {
+    fn new(query: BatchQuery, result: Result<Vec<ConcreteRelease>, GraphError>) -> Self {
+        match result {
+            Ok(recommendations) => Self {
+                query,
+                recommendations: Some(recommendations),
+                error: None,
+            },
+            Err(e) => Self {
+                query,
+                recommendations: None,
+                error: Some(e.problem_details()),
+            },
+        }
+    }
+}
+
+/// Serve batch graph queries.
+pub(crate) async fn index(
+    req: HttpRequest,
+    body: Bytes,
+    app_data: actix_web::web::Data<AppState>,
+) -> Result<HttpResponse, GraphError> {
+    _index(&req, body, app_data)
+        .await
+        .map_err(|e| api_response_error(&req, e))
+}
+
+async fn _index(
+    req: &HttpRequest,
+    body: Bytes,
+    app_data: actix_web::web::Data<AppState>,
+) -> Result<HttpResponse, GraphError> {
+    let span = get_tracer().start("batch");
+    let _active_span = mark_span_as_active(span);
+
+    let accept_default = header::HeaderValue::from_static(CONTENT_TYPE);
+    commons::validate_content_type(req.headers(), vec![accept_default.clone()], accept_default)?;
+
+    let queries = parse_request(&body)?;
+    BATCH_QUERIES.inc_by(queries.len() as u64);
+
+    let common_params = Query::<HashMap<String, String>>::from_query(req.query_string())
+        .map(|query| query.into_inner())
+        .map_err(|e| GraphError::InvalidParams(e.to_string()))?;
+
+    // Group queries sharing the same plugin parameters, so that the plugin
+    // chain runs once per group.
+    let mut groups: BTreeMap<Vec<(String, String)>, Vec<usize>> = BTreeMap::new();
+    for (index, query) in queries.iter().enumerate() {
+        let params = query_params(&common_params, query);
+        groups
+            .entry(params.into_iter().collect())
+            .or_default()
+            .push(index);
+    }
+
+    let mut outcomes = stream::iter(groups)
+        .map(|(params, indices)| {
+            let app_data = &app_data;
+            async move {
+                let params: HashMap<String, String> = params.into_iter().collect();
+                let outcome = match check_mandatory_params(&app_data.mandatory_params, &params) {
+                    Ok(()) => run_plugins(app_data.plugins.iter(), params).await,
+                    Err(e) => Err(e),
+                };
+                (indices, outcome)
+            }
+        })
+        .buffer_unordered(MAX_CONCURRENT_GROUPS);
+
+    let mut results: Vec<Option<Result<Vec<ConcreteRelease>, GraphError>>> =
+        queries.iter().map(|_| None).collect();
+    while let Some((indices, outcome)) = outcomes.next().await {
+        match outcome {
+            Ok(io) => {
+                for index in indices {
+                    let result = recommendations(&io.graph, &queries[index].version);
+                    if let (Some(exposure), Ok(releases)) = (&app_data.exposure, &result) {
+                        let from = queries[index].version.as_str();
+                        exposure.record(
+                            releases
+                                .iter()
+                                .map(|release| (from, release.version.as_str())),
+                            std::time::SystemTime::now(),
+                        );
+                    }
+                    results[index] = Some(result);
+                }
+            }
+            Err(e) => {
+                for index in indices {
+                    results[index] = Some(Err(e.clone()));
+                }
+            }
+        }
+    }
+
+    let response = BatchResponse {
+        results: queries
+            .into_iter()
+            .zip(results)
+            .map(|(query, result)| {
+                BatchResult::new(
+                    query,
+                    result.unwrap_or_else(|| {
+                        Err(GraphError::FailedPluginExecution(
+                            "query was not processed".to_string(),
+                        ))
+                    }),
+                )
+            })
+            .collect(),
+    };
+
+    Ok(HttpResponse::Ok().json(response))
+}
+
+/// Parse and check the body of a batch request.
+fn parse_request(body: &[u8]) -> Result<Vec<BatchQuery>, GraphError> {
+    let request: BatchRequest = serde_json::from_slice(body)
+        .map_err(|e| GraphError::InvalidParams(format!("invalid batch request: {}", e)))?;
+
+    if request.queries.len() > MAX_BATCH_QUERIES {
+        return Err(GraphError::InvalidParams(format!(
+            "too many queries in batch: {}, maximum is {}",
+            request.queries.len(),
+            MAX_BATCH_QUERIES
+        )));
+    }
+
+    Ok(request.queries)
+}
+
+/// Build the plugin parameters for a query.
+///
+/// The query fields take precedence over the common query-string parameters.
+fn query_params(
+    common_params: &HashMap<String, String>,
+    query: &BatchQuery,
+) -> BTreeMap<String, String> {
+    let mut params: BTreeMap<String, String> = common_params
+        .iter()
+        .map(|(k, v)| (k.clone(), v.clone()))
+        .collect();
+    params.insert("channel".to_string(), query.channel.clone());
+    if let Some(arch) = &query.arch {
+        params.insert("arch".to_string(), arch.clone());
+    }
+    params.insert("content_type".to_string(), CONTENT_TYPE.to_string());
+    params
+}
+
+/// Make sure all mandatory parameters are present.
+fn check_mandatory_params(
+    mandatory_params: &std::collections::HashSet<String>,
+    params: &HashMap<String, String>,
+) -> Result<(), GraphError> {
+    // The version is always part of a batch query.
+    let mut missing: Vec<String> = mandatory_params
+        .iter()
+        .filter(|param| param.as_str() != "version" && !params.contains_key(*param))
+        .cloned()
+        .collect();
+
+    if missing.is_empty() {
+        Ok(())
+    } else {
+        missing.sort();
+        Err(GraphError::MissingParams(missing))
+    }
+}
+
+/// Return the releases a cluster running `version` can update to.
+fn recommendations(graph: &Graph, version: &str) -> Result<Vec<ConcreteRelease>, GraphError> {
+    let release_id = graph.find_by_version(version).ok_or_else(|| {
+        GraphError::InvalidParams(format!("version {} not found in the graph", version))
+    })?;
+
+    let mut recommendations: Vec<ConcreteRelease> = graph
+        .next_releases(&release_id)
+        .filter_map(|(_, _, release)| match release {
+            Release::Concrete(release) => Some(release.clone()),
+            Release::Abstract(_) => None,
+        })
+        .collect();
+    recommendations.sort_by(|a, b| a.version.cmp(&b.version));
+
+    Ok(recommendations)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cincinnati::testing::generate_custom_graph;
+
+    #[test]
+    fn recommendations_for_version() {
+        let graph = generate_custom_graph(
+            "image",
+            (0..4).map(|i| (i, Default::default())).collect(),
+            Some(vec![(0, 1), (0, 2), (1, 3)]),
+        );
+
+        let versions = |version| -> Vec<String> {
+            recommendations(&graph, version)
+                .unwrap()
+                .into_iter()
+                .map(|release| release.version.to_string())
+                .collect()
+        };
+        assert_eq!(versions("0.0.0"), vec!["1.0.0", "2.0.0"]);
+        assert_eq!(versions("1.0.0"), vec!["3.0.0"]);
+        assert!(versions("3.0.0").is_empty());
+
+        assert!(matches!(
+            recommendations(&graph, "9.0.0"),
+            Err(GraphError::InvalidParams(_))
+        ));
+    }
+
+    #[test]
+    fn parse_batch_request() {
+        let queries = parse_request(
+            br#"{"queries": [
+                {"version": "4.10.1", "channel": "stable-4.10"},
+                {"version": "4.10.1", "channel": "stable-4.10", "arch": "arm64"}
+            ]}"#,
+        )
+        .unwrap();
+        assert_eq!(queries.len(), 2);
+        assert_eq!(queries[0].arch, None);
+        assert_eq!(queries[1].arch, Some("arm64".to_string()));
+
+        assert!(parse_request(br#"{"queries": [{"version": "4.10.1"}]}"#).is_err());
+        assert!(parse_request(b"not json").is_err());
+
+        let too_many = serde_json::json!({
+            "queries": vec![
+                serde_json::json!({"version": "4.10.1", "channel": "stable-4.10"});
+                MAX_BATCH_QUERIES + 1
+            ]
+        });
+        assert!(parse_request(too_many.to_string().as_bytes()).is_err());
+    }
+
+    #[test]
+    fn mandatory_params() {
+        let mandatory = vec!["channel".to_string(), "id".to_string()]
+            .into_iter()
+            .collect();
+        let query = BatchQuery {
+            version: "4.10.1".to_string(),
+            channel: "stable-4.10".to_string(),
+            arch: None,
+        };
+
+        let params = query_params(&HashMap::new(), &query).into_iter().collect();
+        assert_eq!(
+            check_mandatory_params(&mandatory, &params),
+            Err(GraphError::MissingParams(vec!["id".to_string()]))
+        );
+
+        let common = vec![("id".to_string(), "cluster".to_string())]
+            .into_iter()
+            .collect();
+        let params = query_params(&common, &query).into_iter().collect();
+        assert!(check_mandatory_params(&mandatory, &params).is_ok());
+    }
+}
@@ -0,0 +1,272 @@
+//! Graph response cache.
+//!
+//! Many clusters send the same parameters, e.g. all clusters of a version in a
+//! channel, and the plugin chain would otherwise run again for each of them.
+//! Responses are cached by the client parameters the plugins consume, ignoring
+//! those identifying the cluster which would make every entry single-use, for the
+//! generation of the upstream graph they were computed from, identified by its
+//! content hash: the whole cache is dropped as soon as the upstream graph
+//! changes. Entries also expire after a TTL, as some plugins depend on the
+//! current time, e.g. the phased rollouts.
+
+use crate::graph::GraphResponse;
+use cached::{Cached, TimedSizedCache};
+use cincinnati::GraphFields;
+use commons::prelude_errors::*;
+use parking_lot::Mutex;
+use prometheus::{IntCounter, Registry};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::time::Duration;
+
+/// Default maximum number of cached responses.
+pub static DEFAULT_MAX_ENTRIES: usize = 1024;
+
+/// Default lifetime of cached responses.
+pub static DEFAULT_TTL: Duration = Duration::from_secs(60);
+
+/// Default client parameters which responses are cached by.
+pub static DEFAULT_KEY_PARAMS: &[&str] = &["channel", "arch", "version"];
+
+/// Plugin parameter carrying the accepted content type, always part of the key.
+static CONTENT_TYPE_PARAM: &str = "content_type";
+
+/// Returns the default client parameters which responses are cached by.
+pub fn default_key_params() -> HashSet<String> {
+    DEFAULT_KEY_PARAMS
+        .iter()
+        .map(|param| param.to_string())
+        .collect()
+}
+
+lazy_static! {
+    static ref CACHE_HITS: IntCounter = IntCounter::new(
+        "graph_response_cache_hits_total",
+        "Total number of graph responses served from the cache"
+    )
+    .unwrap();
+    static ref CACHE_MISSES: IntCounter = IntCounter::new(
+        "graph_response_cache_misses_total",
+        "Total number of graph responses which had to be computed"
+    )
+    .unwrap();
+    static ref CACHE_INVALIDATIONS: IntCounter = IntCounter::new(
+        "graph_response_cache_invalidations_total",
+        "Total number of times the cache was dropped because the upstream graph changed"
+    )
+    .unwrap();
+}
+
+/// Register relevant metrics to a prometheus registry.
+pub(crate) fn register_metrics(registry: &Registry) -> Fallible<()> {
+    registry.register(Box::new(CACHE_HITS.clone()))?;
+    registry.register(Box::new(CACHE_MISSES.clone()))?;
+    registry.register(Box::new(CACHE_INVALIDATIONS.clone()))?;
+    Ok(())
+}
+
+/// Settings of the response cache.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CacheSettings {
+    /// Maximum number of cached responses, the least recently used ones are dropped first.
+    pub max_entries: usize,
+    /// Lifetime of cached responses.
+    pub ttl: Duration,
+    /// Client parameters which responses are cached by, the others are ignored.
+    pub key_params: HashSet<String>,
+}
+
+/// Normalized client parameters of a graph request.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub(crate) struct CacheKey {
+    params: BTreeMap<String, String>,
+    fields: Option<GraphFields>,
+}
+
+impl CacheKey {
+    /// Build the key of the given plugin parameters and field selection,
+    /// keeping only the parameters in `key_params` and the content type.
+    fn new(
+        params: &HashMap<String, String>,
+        key_params: &HashSet<String>,
+        fields: Option<GraphFields>,
+    ) -> Self {
+        Self {
+            params: params
+                .iter()
+                .filter(|(key, _)| key.as_str() == CONTENT_TYPE_PARAM || key_params.contains(*key))
+                .map(|(key, value)| (key.clone(), value.clone()))
+                .collect(),
+            // Selecting all fields is the same as selecting none.
+            fields: fields.filter(|fields| *fields != GraphFields::all()),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Entries {
+    /// Hash of the upstream graph the responses were computed from.
+    generation: Option<String>,
+    responses: TimedSizedCache<CacheKey, GraphResponse>,
+}
+
+/// Bounded cache of graph responses, for a single generation of the upstream graph.
+#[derive(Debug)]
+pub struct ResponseCache {
+    entries: Mutex<Entries>,
+    key_params: HashSet<String>,
+}
+
+impl ResponseCache {
+    /// Create an empty cache.
+    pub fn try_new(settings: CacheSettings) -> Fallible<Self> {
+        ensure!(
+            settings.max_entries > 0,
+            "max_entries must be greater than 0"
+        );
+        ensure!(settings.ttl.as_secs() > 0, "ttl must be at least 1s");
+
+        Ok(Self {
+            entries: Mutex::new(Entries {
+                generation: None,
+                responses: TimedSizedCache::with_size_and_lifespan(
+                    settings.max_entries,
+                    settings.ttl.as_secs(),
+                ),
+            }),
+            key_params: settings.key_params,
+        })
+    }
+
+    /// Returns the key of a request with the given plugin parameters and field selection.
+    pub(crate) fn key(
+        &self,
+        params: &HashMap<String, String>,
+        fields: Option<GraphFields>,
+    ) -> CacheKey {
+        CacheKey::new(params, &self.key_params, fields)
+    }
+
+    /// Returns the response cached for the given generation of the upstream graph, if any.
+    pub(crate) fn get(&self, generation: &str, key: &CacheKey) -> Option<GraphResponse> {
+        let mut entries = self.entries.lock();
+        let response = if entries.generation.as_deref() == Some(generation) {
+            entries.responses.cache_get(key).cloned()
+        } else {
+            None
+        };
+
+        match response {
+            Some(_) => CACHE_HITS.inc(),
+            None => CACHE_MISSES.inc(),
+        }
+        response
+    }
+
+    /// Cache a response computed from the given generation of the upstream graph,
+    /// dropping the responses of other generations.
+    pub(crate) fn insert(&self, generation: String, key: CacheKey, response: GraphResponse) {
+        let mut entries = self.entries.lock();
+        if entries.generation.as_deref() != Some(generation.as_str()) {
+            if entries.generation.is_some() {
+                debug!(
+                    "upstream graph changed to {}, dropping cached responses",
+                    generation
+                );
+                CACHE_INVALIDATIONS.inc();
+            }
+            entries.responses.cache_clear();
+            entries.generation = Some(generation);
+        }
+        entries.responses.cache_set(key, response);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response(body: &str) -> GraphResponse {
+        GraphResponse {
+            body: body.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn generations() {
+        let cache = ResponseCache::try_new(CacheSettings {
+            max_entries: 2,
+            ttl: Duration::from_secs(60),
+            key_params: default_key_params(),
+        })
+        .unwrap();
+        let params = |channel: &str| {
+            vec![("channel".to_string(), channel.to_string())]
+                .into_iter()
+                .collect::<HashMap<_, _>>()
+        };
+        let stable = cache.key(&params("stable-4.11"), None);
+        let fast = cache.key(&params("fast-4.11"), None);
+
+        assert_eq!(cache.get("sha256:01", &stable), None);
+        cache.insert("sha256:01".to_string(), stable.clone(), response("stable"));
+        cache.insert("sha256:01".to_string(), fast.clone(), response("fast"));
+        assert_eq!(cache.get("sha256:01", &stable), Some(response("stable")));
+        assert_eq!(
+            cache.key(&params("stable-4.11"), Some(GraphFields::all())),
+            stable
+        );
+        assert_ne!(
+            cache.key(&params("stable-4.11"), Some("version".parse().unwrap())),
+            stable
+        );
+
+        // Responses of an older upstream graph are not served.
+        assert_eq!(cache.get("sha256:02", &stable), None);
+        cache.insert("sha256:02".to_string(), stable.clone(), response("stable2"));
+        assert_eq!(cache.get("sha256:02", &stable), Some(response("stable2")));
+        assert_eq!(cache.get("sha256:02", &fast), None);
+
+        assert!(ResponseCache::try_new(CacheSettings {
+            max_entries: 0,
+            ttl: Duration::from_secs(60),
+            key_params: default_key_params(),
+        })
+        .is_err());
+    }
+
+    #[test]
+    fn cluster_ids_are_ignored() {
+        let cache = ResponseCache::try_new(CacheSettings {
+            max_entries: 2,
+            ttl: Duration::from_secs(60),
+            key_params: default_key_params(),
+        })
+        .unwrap();
+        let params = |id: &str, content_type: &str| {
+            vec![
+                ("channel", "stable-4.11"),
+                ("arch", "amd64"),
+                ("version", "4.11.0"),
+                ("id", id),
+                ("content_type", content_type),
+            ]
+            .into_iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect::<HashMap<_, _>>()
+        };
+
+        let key = cache.key(&params("cluster-a", "application/json"), None);
+        cache.insert("sha256:01".to_string(), key, response("graph"));
+        let other_cluster = cache.key(&params("cluster-b", "application/json"), None);
+        assert_eq!(
+            cache.get("sha256:01", &other_cluster),
+            Some(response("graph"))
+        );
+        let other_type = cache.key(
+            &params("cluster-a", "application/vnd.redhat.cincinnati.v1+json"),
+            None,
+        );
+        assert_eq!(cache.get("sha256:01", &other_type), None);
+    }
+}
@@ -0,0 +1,182 @@
+//! Channel listing.
+//!
+//! Console UIs and `oc` only need the channels of the graph to let users pick
+//! one, and would otherwise have to download and walk the whole graph. The
+//! plugin chain runs without the channel filter, so that all channels of the
+//! graph are listed along with the newest version in each of them.
+
+use crate::graph::{add_provenance_headers, api_response_error, run_plugins};
+use crate::AppState;
+use actix_web::http::header;
+use actix_web::web::Query;
+use actix_web::{HttpRequest, HttpResponse};
+use cincinnati::channel_lifecycle::Lifecycle;
+use cincinnati::metadata_keys::MetadataKeys;
+use cincinnati::plugins::internal::channel_filter::ChannelFilterPlugin;
+use cincinnati::{Graph, Release, CONTENT_TYPE};
+use commons::tracing::get_tracer;
+use commons::GraphError;
+use opentelemetry::trace::{mark_span_as_active, Tracer};
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+/// Body of a channel listing response.
+#[derive(Debug, Serialize, PartialEq)]
+pub(crate) struct ChannelsResponse {
+    channels: Vec<Channel>,
+}
+
+/// A channel of the graph.
+#[derive(Debug, Serialize, PartialEq)]
+pub(crate) struct Channel {
+    name: String,
+    /// Newest version in the channel.
+    latest: String,
+    /// Lifecycle of the channel, if it is being retired.
+    #[serde(flatten)]
+    lifecycle: Option<Lifecycle>,
+}
+
+/// Serve the channels of the graph.
+pub(crate) async fn index(
+    req: HttpRequest,
+    app_data: actix_web::web::Data<AppState>,
+) -> Result<HttpResponse, GraphError> {
+    _index(&req, app_data)
+        .await
+        .map_err(|e| api_response_error(&req, e))
+}
+
+async fn _index(
+    req: &HttpRequest,
+    app_data: actix_web::web::Data<AppState>,
+) -> Result<HttpResponse, GraphError> {
+    let span = get_tracer().start("channels");
+    let _active_span = mark_span_as_active(span);
+
+    let accept_default = header::HeaderValue::from_static(CONTENT_TYPE);
+    commons::validate_content_type(req.headers(), vec![accept_default.clone()], accept_default)?;
+
+    // The channel is what is being listed, so it can't be mandatory here.
+    let mandatory_params: HashSet<String> = app_data
+        .mandatory_params
+        .iter()
+        .filter(|param| param.as_str() != "channel")
+        .cloned()
+        .collect();
+    commons::ensure_query_params(&mandatory_params, req.query_string())?;
+
+    let mut params = Query::<HashMap<String, String>>::from_query(req.query_string())
+        .map(|query| query.into_inner())
+        .map_err(|e| GraphError::InvalidParams(e.to_string()))?;
+    params.insert("content_type".to_string(), CONTENT_TYPE.to_string());
+
+    let plugins = app_data
+        .plugins
+        .iter()
+        .filter(|plugin| plugin.get_name() != ChannelFilterPlugin::PLUGIN_NAME);
+    let io = run_plugins(plugins, params).await?;
+
+    let mut resp = HttpResponse::Ok();
+    add_provenance_headers(&mut resp, &io);
+    Ok(resp.json(ChannelsResponse {
+        channels: channels(&io.graph, &app_data.metadata_keys),
+    }))
+}
+
+/// List the channels of the graph, sorted by name, with the newest version in each of them.
+fn channels(graph: &Graph, keys: &MetadataKeys) -> Vec<Channel> {
+    let mut latest: BTreeMap<String, (semver::Version, String)> = BTreeMap::new();
+    for release in graph.iter_releases() {
+        let release = match release {
+            Release::Concrete(release) => release,
+            Release::Abstract(_) => continue,
+        };
+        let channels = match release.metadata.get(keys.channels.as_str()) {
+            Some(channels) => channels,
+            None => continue,
+        };
+        let version = match cincinnati::semver_cache::parse_version(&release.version) {
+            Ok(version) => version,
+            Err(e) => {
+                warn!("Parsing {} as SemVer: {}", release.version, e);
+                continue;
+            }
+        };
+
+        for channel in channels.split(',').map(str::trim) {
+            if channel.is_empty() {
+                continue;
+            }
+            let newer = latest
+                .get(channel)
+                .is_none_or(|(current, _)| version > *current);
+            if newer {
+                latest.insert(
+                    channel.to_string(),
+                    (version.clone(), release.version.to_string()),
+                );
+            }
+        }
+    }
+
+    latest
+        .into_iter()
+        .map(|(name, (_, version))| Channel {
+            lifecycle: graph.channel_lifecycle(&name).cloned(),
+            name,
+            latest: version,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cincinnati::channel_lifecycle::Status;
+    use cincinnati::testing::generate_custom_graph;
+
+    #[test]
+    fn list_channels() {
+        let keys = MetadataKeys::with_prefix("com.example.graph");
+        let channels_metadata = |channels: &str| {
+            vec![(keys.channels.clone(), channels.to_string())]
+                .into_iter()
+                .collect()
+        };
+        let lifecycle = Lifecycle::new(Status::EndOfLife, Some("2023-01-31".to_string())).unwrap();
+        let mut graph = generate_custom_graph(
+            "image",
+            vec![
+                (0, channels_metadata("stable-4.10,fast-4.10")),
+                (1, channels_metadata("fast-4.10")),
+                (2, channels_metadata("candidate-4.11")),
+                (3, Default::default()),
+            ],
+            None,
+        );
+        graph.set_channel_lifecycle("stable-4.10", lifecycle.clone());
+
+        assert_eq!(
+            channels(&graph, &keys),
+            vec![
+                Channel {
+                    name: "candidate-4.11".to_string(),
+                    latest: "2.0.0".to_string(),
+                    lifecycle: None,
+                },
+                Channel {
+                    name: "fast-4.10".to_string(),
+                    latest: "1.0.0".to_string(),
+                    lifecycle: None,
+                },
+                Channel {
+                    name: "stable-4.10".to_string(),
+                    latest: "0.0.0".to_string(),
+                    lifecycle: Some(lifecycle),
+                },
+            ]
+        );
+        assert!(channels(&Graph::default(), &keys).is_empty());
+        assert!(channels(&graph, &MetadataKeys::default()).is_empty());
+    }
+}
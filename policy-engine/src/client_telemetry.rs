@@ -0,0 +1,489 @@
+//! Anonymous client telemetry.
+//!
+//! Aggregates the client parameters of graph requests into fleet-level
+//! metrics: requests are counted by minor version, the per-channel and
+//! per-architecture counts being those of the request metrics, and the number
+//! of distinct clusters is estimated per channel with HyperLogLog sketches.
+//! Cluster IDs are hashed with a key shared by all replicas and only the
+//! sketch registers are kept, so no identifier is ever stored. The estimates
+//! cover windows aligned on the epoch, the same on all replicas, so that the
+//! sketches they export can be merged into fleet-wide estimates.
+
+use crate::request_metrics::{
+    minor_version, KnownLabels, LabelKind, LabelValues, NONE_LABEL, OTHER_LABEL,
+};
+use crate::AppState;
+use actix_web::HttpResponse;
+use commons::prelude_errors::*;
+use commons::GraphError;
+use custom_debug_derive::Debug as CustomDebug;
+use hmac::{Hmac, Mac};
+use parking_lot::{Mutex, MutexGuard};
+use prometheus::{IntCounterVec, IntGauge, IntGaugeVec, Opts, Registry};
+use sha2::Sha256;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::convert::TryInto;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Default duration of an estimation window.
+pub static DEFAULT_WINDOW: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Query parameter identifying the cluster.
+static ID_PARAM: &str = "id";
+
+/// Number of bits of the hash selecting the register of a sketch.
+static SKETCH_PRECISION: u32 = 12;
+
+lazy_static! {
+    static ref CLIENT_REQS: IntCounterVec = IntCounterVec::new(
+        Opts::new(
+            "client_telemetry_requests_total",
+            "Total number of graph requests by minor version of the client"
+        ),
+        &["version"]
+    )
+    .unwrap();
+    static ref CLIENT_CLUSTERS: IntGaugeVec = IntGaugeVec::new(
+        Opts::new(
+            "client_telemetry_clusters_estimate",
+            "Estimated number of distinct clusters querying a channel in the current window"
+        ),
+        &["channel"]
+    )
+    .unwrap();
+    static ref CLIENT_ALL_CLUSTERS: IntGauge = IntGauge::new(
+        "client_telemetry_all_clusters_estimate",
+        "Estimated number of distinct clusters querying the graph in the current window"
+    )
+    .unwrap();
+}
+
+/// Register relevant metrics to a prometheus registry.
+pub(crate) fn register_metrics(registry: &Registry) -> Fallible<()> {
+    registry.register(Box::new(CLIENT_REQS.clone()))?;
+    registry.register(Box::new(CLIENT_CLUSTERS.clone()))?;
+    registry.register(Box::new(CLIENT_ALL_CLUSTERS.clone()))?;
+    Ok(())
+}
+
+/// Settings of the client telemetry.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ClientTelemetrySettings {
+    /// Channels which are always tracked.
+    pub channels: HashSet<String>,
+    /// Maximum number of distinct non-allowed values per label.
+    pub max_label_values: usize,
+    /// Duration after which the cluster estimates start over.
+    pub window: Duration,
+    /// File containing the key of the cluster ID hashes, shared by all replicas.
+    pub key_path: PathBuf,
+}
+
+/// HyperLogLog sketch estimating the number of distinct hashes.
+#[derive(Debug)]
+struct Sketch {
+    /// Highest rank seen by each register.
+    registers: Vec<u8>,
+    /// Sum of `2^-rank` over all registers, maintained on updates.
+    sum: f64,
+    /// Number of registers still at rank 0.
+    zeros: usize,
+}
+
+impl Sketch {
+    fn new() -> Self {
+        let size = 1 << SKETCH_PRECISION;
+        Self {
+            registers: vec![0; size],
+            sum: size as f64,
+            zeros: size,
+        }
+    }
+
+    fn insert(&mut self, hash: u64) {
+        let index = (hash >> (64 - SKETCH_PRECISION)) as usize;
+        // Position of the first set bit in the rest of the hash, capped for all-zero rests.
+        let rank =
+            ((hash << SKETCH_PRECISION).leading_zeros() + 1).min(64 - SKETCH_PRECISION + 1) as u8;
+
+        let register = &mut self.registers[index];
+        if rank > *register {
+            if *register == 0 {
+                self.zeros -= 1;
+            }
+            self.sum += 2f64.powi(-i32::from(rank)) - 2f64.powi(-i32::from(*register));
+            *register = rank;
+        }
+    }
+
+    fn estimate(&self) -> u64 {
+        let size = self.registers.len() as f64;
+        let alpha = 0.7213 / (1.0 + 1.079 / size);
+        let raw = alpha * size * size / self.sum;
+
+        // Linear counting is more accurate for small cardinalities.
+        if raw <= 2.5 * size && self.zeros > 0 {
+            (size * (size / self.zeros as f64).ln()).round() as u64
+        } else {
+            raw.round() as u64
+        }
+    }
+
+    /// Sketch with the given registers, e.g. merged from exported sketches.
+    #[cfg(test)]
+    fn from_registers(registers: Vec<u8>) -> Self {
+        let sum = registers
+            .iter()
+            .map(|rank| 2f64.powi(-i32::from(*rank)))
+            .sum();
+        let zeros = registers.iter().filter(|rank| **rank == 0).count();
+        Self {
+            registers,
+            sum,
+            zeros,
+        }
+    }
+
+    /// The registers, encoded as base64.
+    fn encode(&self) -> String {
+        base64::encode(&self.registers)
+    }
+}
+
+/// Sketches of the current window.
+#[derive(Debug)]
+struct Window {
+    /// Number of windows since the epoch.
+    index: u64,
+    all: Sketch,
+    channels: HashMap<String, Sketch>,
+}
+
+impl Window {
+    fn new(index: u64) -> Self {
+        Self {
+            index,
+            all: Sketch::new(),
+            channels: HashMap::new(),
+        }
+    }
+}
+
+/// Sketches of the current window, as exported.
+#[derive(Debug, Serialize)]
+pub(crate) struct Sketches {
+    /// Start of the window, in seconds since the epoch.
+    window_start: u64,
+    window_secs: u64,
+    /// Number of bits of the hash selecting the register.
+    precision: u32,
+    /// Registers of the sketch of all clusters, encoded as base64.
+    all: String,
+    /// Registers of the sketch of each channel, encoded as base64.
+    channels: BTreeMap<String, String>,
+}
+
+/// Aggregates the client parameters of graph requests.
+#[derive(CustomDebug)]
+pub struct ClientTelemetry {
+    channels: LabelValues,
+    versions: LabelValues,
+    window_duration: Duration,
+    /// Key of the cluster ID hashes.
+    #[debug(skip)]
+    hash_key: Vec<u8>,
+    window: Mutex<Window>,
+}
+
+impl ClientTelemetry {
+    /// Create the aggregation, tracking the `known` values beyond the allowlists.
+    pub fn try_new(settings: ClientTelemetrySettings, known: Arc<KnownLabels>) -> Fallible<Self> {
+        let hash_key = std::fs::read_to_string(&settings.key_path).context(format!(
+            "reading client telemetry key {}",
+            settings.key_path.display()
+        ))?;
+        let hash_key = hash_key.trim();
+        ensure!(
+            !hash_key.is_empty(),
+            "client telemetry key {} is empty",
+            settings.key_path.display()
+        );
+
+        Ok(Self {
+            channels: LabelValues::new(
+                LabelKind::Channel,
+                settings.channels,
+                known.clone(),
+                settings.max_label_values,
+            ),
+            versions: LabelValues::new(
+                LabelKind::MinorVersion,
+                HashSet::new(),
+                known,
+                settings.max_label_values,
+            ),
+            window_duration: settings.window,
+            hash_key: hash_key.as_bytes().to_vec(),
+            window: Mutex::new(Window::new(0)),
+        })
+    }
+
+    /// Returns the sketches of the window `now` is in, starting it over if it ended.
+    fn window_at(&self, now: SystemTime) -> MutexGuard<'_, Window> {
+        let index = now.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs()
+            / self.window_duration.as_secs();
+        let mut window = self.window.lock();
+        if window.index != index {
+            *window = Window::new(index);
+            CLIENT_CLUSTERS.reset();
+            CLIENT_ALL_CLUSTERS.set(0);
+        }
+        window
+    }
+
+    /// Hash a cluster ID, the same way on all replicas sharing the key.
+    fn hash(&self, id: &str) -> u64 {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.hash_key)
+            .expect("HMAC can take a key of any size");
+        mac.update(id.as_bytes());
+        let digest = mac.finalize().into_bytes();
+        u64::from_be_bytes(
+            digest[..8]
+                .try_into()
+                .expect("SHA-256 digests have 32 bytes"),
+        )
+    }
+
+    /// Aggregate the query parameters of a graph request.
+    pub fn record(&self, params: &HashMap<String, String>) {
+        self.record_at(params, SystemTime::now())
+    }
+
+    fn record_at(&self, params: &HashMap<String, String>, now: SystemTime) {
+        let version = match params.get("version").map(String::as_str) {
+            Some(version) => match minor_version(version) {
+                Some(minor) => self.versions.label(Some(&minor)),
+                None => OTHER_LABEL.to_string(),
+            },
+            None => NONE_LABEL.to_string(),
+        };
+        CLIENT_REQS.with_label_values(&[&version]).inc();
+
+        let id = match params.get(ID_PARAM).filter(|id| !id.is_empty()) {
+            Some(id) => id,
+            None => return,
+        };
+        let hash = self.hash(id);
+        let channel = self
+            .channels
+            .label(params.get("channel").map(String::as_str));
+
+        let mut window = self.window_at(now);
+        window.all.insert(hash);
+        CLIENT_ALL_CLUSTERS.set(window.all.estimate() as i64);
+        let sketch = window
+            .channels
+            .entry(channel.clone())
+            .or_insert_with(Sketch::new);
+        sketch.insert(hash);
+        CLIENT_CLUSTERS
+            .with_label_values(&[&channel])
+            .set(sketch.estimate() as i64);
+    }
+
+    /// Export the sketches of the current window.
+    pub(crate) fn sketches(&self) -> Sketches {
+        self.sketches_at(SystemTime::now())
+    }
+
+    fn sketches_at(&self, now: SystemTime) -> Sketches {
+        let window = self.window_at(now);
+        Sketches {
+            window_start: window.index * self.window_duration.as_secs(),
+            window_secs: self.window_duration.as_secs(),
+            precision: SKETCH_PRECISION,
+            all: window.all.encode(),
+            channels: window
+                .channels
+                .iter()
+                .map(|(channel, sketch)| (channel.clone(), sketch.encode()))
+                .collect(),
+        }
+    }
+}
+
+/// Serve the sketches of the current window, for merging those of all replicas.
+pub(crate) async fn serve_sketches(
+    app_data: actix_web::web::Data<AppState>,
+) -> Result<HttpResponse, GraphError> {
+    let client_telemetry = app_data
+        .client_telemetry
+        .as_ref()
+        .ok_or_else(|| GraphError::NotFound("client telemetry is disabled".to_string()))?;
+
+    Ok(HttpResponse::Ok().json(client_telemetry.sketches()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::request_metrics::tests::known_labels;
+    use std::collections::hash_map::RandomState;
+    use std::hash::BuildHasher;
+
+    /// Client telemetry hashing with `key`, along with the directory of the key file.
+    fn telemetry(key: &str, window: Duration) -> (ClientTelemetry, tempfile::TempDir) {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let key_path = tmpdir.path().join("key");
+        std::fs::write(&key_path, key).unwrap();
+        let settings = ClientTelemetrySettings {
+            channels: vec!["telemetry-4.99".to_string()].into_iter().collect(),
+            max_label_values: 0,
+            window,
+            key_path,
+        };
+        let known = known_labels(&[("telemetry-4.99", "amd64"), ("fast-4.99", "arm64")]);
+        (ClientTelemetry::try_new(settings, known).unwrap(), tmpdir)
+    }
+
+    fn params(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    fn at(secs: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(secs)
+    }
+
+    #[test]
+    fn sketch_estimates() {
+        let key = RandomState::new();
+        let hash = |value: usize| key.hash_one(value);
+
+        let mut sketch = Sketch::new();
+        assert_eq!(sketch.estimate(), 0);
+
+        for count in &[10, 1_000, 100_000] {
+            let mut sketch = Sketch::new();
+            for value in 0..*count {
+                // Duplicates don't count.
+                sketch.insert(hash(value));
+                sketch.insert(hash(value));
+            }
+            let error = (sketch.estimate() as f64 - *count as f64).abs() / *count as f64;
+            assert!(
+                error < 0.05,
+                "estimated {} for {}",
+                sketch.estimate(),
+                count
+            );
+        }
+
+        sketch.insert(hash(0));
+        assert_eq!(sketch.estimate(), 1);
+    }
+
+    #[test]
+    fn minor_versions() {
+        assert_eq!(minor_version("4.15.3"), Some("4.15".to_string()));
+        assert_eq!(minor_version("4.16.0-rc.1"), Some("4.16".to_string()));
+        assert_eq!(minor_version("4.15"), None);
+    }
+
+    #[test]
+    fn record_requests() {
+        let (telemetry, _tmpdir) = telemetry("secret", DEFAULT_WINDOW);
+        let count = |version: &str| CLIENT_REQS.with_label_values(&[version]).get();
+        let clusters = || CLIENT_CLUSTERS.with_label_values(&["telemetry-4.99"]).get();
+
+        let before = count(OTHER_LABEL);
+        for id in &["cluster-a", "cluster-b", "cluster-a", ""] {
+            telemetry.record_at(
+                &params(&[
+                    ("channel", "telemetry-4.99"),
+                    ("version", "4.99.1"),
+                    ("id", id),
+                ]),
+                at(10),
+            );
+        }
+        // Minor versions are bounded like the request metrics labels.
+        assert_eq!(count(OTHER_LABEL), before + 4);
+        assert_eq!(clusters(), 2);
+
+        let before = count(NONE_LABEL);
+        telemetry.record_at(&params(&[("channel", "telemetry-4.99")]), at(20));
+        assert_eq!(count(NONE_LABEL), before + 1);
+        assert_eq!(clusters(), 2);
+
+        // The estimates start over with the next window.
+        let next_window = DEFAULT_WINDOW.as_secs();
+        telemetry.record_at(
+            &params(&[("channel", "telemetry-4.99"), ("id", "cluster-c")]),
+            at(next_window + 10),
+        );
+        assert_eq!(clusters(), 1);
+        let sketches = telemetry.sketches_at(at(next_window + 20));
+        assert_eq!(sketches.window_start, next_window);
+        assert_eq!(
+            sketches.channels.keys().collect::<Vec<_>>(),
+            vec!["telemetry-4.99"]
+        );
+    }
+
+    #[test]
+    fn require_key() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let key_path = tmpdir.path().join("key");
+        let settings = ClientTelemetrySettings {
+            channels: HashSet::new(),
+            max_label_values: 0,
+            window: DEFAULT_WINDOW,
+            key_path: key_path.clone(),
+        };
+        let known = known_labels(&[]);
+        assert!(ClientTelemetry::try_new(settings.clone(), known.clone()).is_err());
+
+        std::fs::write(&key_path, " \n").unwrap();
+        assert!(ClientTelemetry::try_new(settings, known).is_err());
+    }
+
+    #[test]
+    fn merge_replica_sketches() {
+        let window = Duration::from_secs(1_000_000);
+        let (first, _first_dir) = telemetry("shared", window);
+        let (second, _second_dir) = telemetry("shared", window);
+        let (other_key, _other_dir) = telemetry("other", window);
+        let record = |telemetry: &ClientTelemetry, ids: std::ops::Range<usize>| {
+            for id in ids {
+                let id = format!("cluster-{}", id);
+                telemetry.record_at(&params(&[("id", &id)]), at(10));
+            }
+        };
+        record(&first, 0..600);
+        record(&second, 400..1000);
+        assert!(first.hash("cluster-0") == second.hash("cluster-0"));
+        assert!(first.hash("cluster-0") != other_key.hash("cluster-0"));
+
+        // Merging the registers of both replicas by maximum counts each cluster once.
+        let decode = |telemetry: &ClientTelemetry| {
+            let sketches = telemetry.sketches_at(at(20));
+            assert_eq!(sketches.window_start, 0);
+            assert_eq!(sketches.precision, SKETCH_PRECISION);
+            base64::decode(sketches.all).unwrap()
+        };
+        let merged = Sketch::from_registers(
+            decode(&first)
+                .into_iter()
+                .zip(decode(&second))
+                .map(|(first, second)| first.max(second))
+                .collect(),
+        );
+        let error = (merged.estimate() as f64 - 1000.0).abs() / 1000.0;
+        assert!(error < 0.05, "estimated {} for 1000", merged.estimate());
+    }
+}
@@ -2,8 +2,10 @@
 
 use super::options;
 use super::AppSettings;
+use commons::logging::LogFormat;
 use commons::prelude_errors::*;
 use commons::MergeOptions;
+use std::path::PathBuf;
 
 /// CLI configuration flags, top-level.
 #[derive(Debug, StructOpt)]
@@ -12,10 +14,18 @@ pub struct CliOptions {
     #[structopt(short = "v", parse(from_occurrences))]
     pub verbosity: u64,
 
+    /// Log output format, 'text' or 'json'
+    #[structopt(long = "log_format")]
+    pub log_format: Option<LogFormat>,
+
     /// Path to configuration file
     #[structopt(short = "c")]
     pub config_path: Option<String>,
 
+    /// Directory of configuration fragments, merged over the configuration file in lexical order
+    #[structopt(long = "config-dir", parse(from_os_str))]
+    pub config_dir: Option<PathBuf>,
+
     // Status service options
     #[structopt(flatten)]
     pub service: options::ServiceOptions,
@@ -31,6 +41,18 @@ pub struct CliOptions {
     // Cincinnati upstream options
     #[structopt(flatten)]
     pub upstream_cincinnati: options::UpCincinnatiOptions,
+
+    // TLS options
+    #[structopt(flatten)]
+    pub tls: options::TlsOptions,
+
+    // Analytics options
+    #[structopt(flatten)]
+    pub analytics: options::AnalyticsOptions,
+
+    // Signing options
+    #[structopt(flatten)]
+    pub signing: options::SigningOptions,
 }
 
 impl MergeOptions<CliOptions> for AppSettings {
@@ -41,10 +63,14 @@ impl MergeOptions<CliOptions> for AppSettings {
             2 => log::LevelFilter::Debug,
             _ => log::LevelFilter::Trace,
         };
+        assign_if_some!(self.log_format, opts.log_format);
 
         self.try_merge(Some(opts.service))?;
         self.try_merge(Some(opts.status))?;
         self.try_merge(Some(opts.upstream_cincinnati))?;
+        self.try_merge(Some(opts.tls))?;
+        self.try_merge(Some(opts.analytics))?;
+        self.try_merge(Some(opts.signing))?;
 
         Ok(())
     }
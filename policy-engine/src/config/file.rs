@@ -2,11 +2,12 @@
 
 use super::options;
 use super::AppSettings;
+use commons::cors::CorsSettings;
 use commons::de::de_loglevel;
+use commons::logging::LogFormat;
 use commons::prelude_errors::*;
 use commons::MergeOptions;
-use std::io::Read;
-use std::{fs, io, path};
+use std::path;
 
 /// TOML configuration, top-level.
 #[derive(Debug, Deserialize)]
@@ -15,6 +16,9 @@ pub struct FileOptions {
     #[serde(default = "Option::default", deserialize_with = "de_loglevel")]
     pub verbosity: Option<log::LevelFilter>,
 
+    /// Log output format.
+    pub log_format: Option<LogFormat>,
+
     /// Upstream options.
     pub upstream: Option<UpstreamOptions>,
 
@@ -26,29 +30,38 @@ pub struct FileOptions {
 
     /// Status service options.
     pub status: Option<options::StatusOptions>,
+
+    /// TLS termination options.
+    pub tls: Option<options::TlsOptions>,
+
+    /// Usage analytics options.
+    pub analytics: Option<options::AnalyticsOptions>,
+
+    /// Graph response signing options.
+    pub signing: Option<options::SigningOptions>,
+
+    /// Cross-origin resource sharing.
+    pub cors: Option<CorsSettings>,
 }
 
 impl FileOptions {
     /// Parse a TOML configuration from path.
+    #[cfg(test)]
     pub fn read_filepath<P>(cfg_path: P) -> Fallible<Self>
     where
         P: AsRef<path::Path>,
     {
-        let cfg_file = fs::File::open(&cfg_path).context(format!(
-            "failed to open config path {:?}",
-            cfg_path.as_ref()
-        ))?;
-        let mut bufrd = io::BufReader::new(cfg_file);
-
-        let mut content = vec![];
-        bufrd.read_to_end(&mut content)?;
-        let cfg = toml::from_slice(&content).context(format!(
-            "failed to parse config file {}:\n{}",
-            cfg_path.as_ref().display(),
-            std::str::from_utf8(&content).unwrap_or("file not decodable")
-        ))?;
-
-        Ok(cfg)
+        Self::read_layered(Some(cfg_path.as_ref()), None)?
+            .ok_or_else(|| format_err!("no configuration read"))
+    }
+
+    /// Parse and merge a TOML configuration file and the fragments of a
+    /// configuration directory, if any, expanding environment variables.
+    pub fn read_layered(
+        path: Option<&path::Path>,
+        dir: Option<&path::Path>,
+    ) -> Fallible<Option<Self>> {
+        commons::config_files::read_layered(path, dir)
     }
 }
 
@@ -56,10 +69,15 @@ impl MergeOptions<Option<FileOptions>> for AppSettings {
     fn try_merge(&mut self, opts: Option<FileOptions>) -> Fallible<()> {
         if let Some(file) = opts {
             assign_if_some!(self.verbosity, file.verbosity);
+            assign_if_some!(self.log_format, file.log_format);
             self.try_merge(file.policy)?;
             self.try_merge(file.service)?;
             self.try_merge(file.status)?;
             self.try_merge(file.upstream)?;
+            self.try_merge(file.tls)?;
+            self.try_merge(file.analytics)?;
+            self.try_merge(file.signing)?;
+            assign_if_some!(self.cors, file.cors);
         }
         Ok(())
     }
@@ -124,6 +142,74 @@ mod tests {
         assert_eq!(settings.status_port, 2222);
     }
 
+    #[test]
+    fn toml_server_tuning() {
+        let mut settings = AppSettings::default();
+        assert_eq!(settings.service_server.keep_alive, None);
+
+        let toml_input = "[service]\nkeep_alive = 30\nworkers = 8\n[status]\nhttp2 = true";
+        let file_opts: FileOptions = toml::from_str(toml_input).unwrap();
+        settings.try_merge(Some(file_opts)).unwrap();
+
+        // Later layers without the options keep the merged values.
+        let file_opts: FileOptions = toml::from_str("status.port = 2222").unwrap();
+        settings.try_merge(Some(file_opts)).unwrap();
+
+        assert_eq!(
+            settings.service_server.keep_alive,
+            Some(std::time::Duration::from_secs(30))
+        );
+        assert_eq!(settings.service_server.workers, Some(8));
+        assert!(!settings.service_server.http2);
+        assert!(settings.status_server.http2);
+    }
+
+    #[test]
+    fn toml_signing() {
+        use crate::signing::SigningAlgorithm;
+
+        let mut settings = AppSettings::default();
+        assert_eq!(settings.signing_algorithm, SigningAlgorithm::ES256);
+
+        let toml_input = "[signing]\nkey_path = '/etc/cincinnati/signing.pem'\nalgorithm = 'ES384'\nvalidity_secs = 3600";
+        let file_opts: FileOptions = toml::from_str(toml_input).unwrap();
+
+        settings.try_merge(Some(file_opts)).unwrap();
+        assert_eq!(
+            settings.signing_key_path,
+            Some(std::path::PathBuf::from("/etc/cincinnati/signing.pem"))
+        );
+        assert_eq!(settings.signing_algorithm, SigningAlgorithm::ES384);
+        assert_eq!(settings.signing_key_id, None);
+        assert_eq!(
+            settings.signing_validity,
+            std::time::Duration::from_secs(3600)
+        );
+    }
+
+    #[test]
+    fn toml_cors() {
+        let mut settings = AppSettings::default();
+        assert_eq!(settings.cors.allowed_origins, vec!["*".to_string()]);
+
+        let toml_input = "[cors]\nallowed_origins = ['https://console.example.com']\nallowed_headers = ['authorization']";
+        let file_opts: FileOptions = toml::from_str(toml_input).unwrap();
+
+        settings.try_merge(Some(file_opts)).unwrap();
+        assert_eq!(
+            settings.cors.allowed_origins,
+            vec!["https://console.example.com".to_string()]
+        );
+        assert_eq!(
+            settings.cors.allowed_methods,
+            vec!["HEAD".to_string(), "GET".to_string(), "POST".to_string()]
+        );
+        assert_eq!(
+            settings.cors.allowed_headers,
+            vec!["authorization".to_string()]
+        );
+    }
+
     #[test]
     fn toml_sample_config() {
         use super::FileOptions;
@@ -15,4 +15,3 @@ mod settings;
 pub(crate) use self::file::FileOptions;
 
 pub use self::settings::AppSettings;
-pub use self::settings::DEFAULT_UPSTREAM_URL;
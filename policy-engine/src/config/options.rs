@@ -1,10 +1,13 @@
 //! Options shared by CLI and TOML.
 
 use super::AppSettings;
+use crate::signing::SigningAlgorithm;
 use commons::prelude_errors::*;
+use commons::tracing::SamplerKind;
 use commons::{de_path_prefix, parse_params_set, parse_path_prefix, MergeOptions};
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashSet};
 use std::net::IpAddr;
+use std::path::PathBuf;
 use std::time::Duration;
 
 /// Status service options.
@@ -14,16 +17,105 @@ pub struct StatusOptions {
     #[structopt(name = "status_address", long = "status.address")]
     pub address: Option<IpAddr>,
 
+    /// Comma-separated addresses on which the status service will listen, overriding the address
+    #[structopt(
+        name = "status_addresses",
+        long = "status.addresses",
+        use_delimiter = true,
+        require_delimiter = true
+    )]
+    pub addresses: Option<Vec<IpAddr>>,
+
     /// Port to which the status service will bind
     #[structopt(name = "status_port", long = "status.port")]
     pub port: Option<u16>,
+
+    /// Unix socket on which the status service will listen, instead of TCP
+    #[structopt(name = "status_socket_path", long = "status.socket_path")]
+    pub socket_path: Option<PathBuf>,
+
+    /// OTLP/HTTP endpoint to push metrics to, in addition to serving them
+    #[structopt(long = "status.metrics_otlp_endpoint")]
+    pub metrics_otlp_endpoint: Option<String>,
+
+    /// Interval between OTLP metrics exports, in seconds
+    #[structopt(long = "status.metrics_otlp_interval_secs")]
+    pub metrics_otlp_interval_secs: Option<u64>,
+
+    /// Query parameters of the graph requests of the self-test, e.g. "channel=stable-4.11"
+    #[structopt(long = "status.selftest_query")]
+    pub selftest_query: Option<String>,
+
+    /// Host name the self-test reaches the main service by, e.g. one its certificate is valid for
+    #[structopt(long = "status.selftest_server_name")]
+    pub selftest_server_name: Option<String>,
+
+    /// PEM file with additional CA certificates the self-test trusts
+    #[structopt(long = "status.selftest_ca_path")]
+    pub selftest_ca_path: Option<PathBuf>,
+
+    /// File containing the bearer token required on the status endpoints
+    #[structopt(long = "status.auth_token_path")]
+    pub auth_token_path: Option<PathBuf>,
+
+    /// File of "user:password" lines accepted with basic authentication on the status endpoints
+    #[structopt(long = "status.auth_credentials_path")]
+    pub auth_credentials_path: Option<PathBuf>,
+
+    /// Keep-alive of idle connections of the status service, in seconds, 0 to disable
+    #[structopt(name = "status_keep_alive", long = "status.keep_alive")]
+    pub keep_alive: Option<u64>,
+
+    /// Time a client of the status service has to send its first request, in seconds
+    #[structopt(name = "status_client_timeout", long = "status.client_timeout")]
+    pub client_timeout: Option<u64>,
+
+    /// Maximum size of request bodies on the status service, in bytes
+    #[structopt(name = "status_max_payload_bytes", long = "status.max_payload_bytes")]
+    pub max_payload_bytes: Option<usize>,
+
+    /// Number of worker threads of the status service, one per physical CPU if unset
+    #[structopt(name = "status_workers", long = "status.workers")]
+    pub workers: Option<usize>,
+
+    /// Accept HTTP/2 without TLS on the status service, in addition to HTTP/1.1
+    #[structopt(name = "status_http2", long = "status.http2")]
+    pub http2: Option<bool>,
 }
 
 impl MergeOptions<Option<StatusOptions>> for AppSettings {
     fn try_merge(&mut self, opts: Option<StatusOptions>) -> Fallible<()> {
         if let Some(status) = opts {
             assign_if_some!(self.status_address, status.address);
+            assign_if_some!(self.status_addresses, status.addresses);
             assign_if_some!(self.status_port, status.port);
+            assign_if_some!(self.status_socket_path, status.socket_path);
+            assign_if_some!(self.metrics_otlp_endpoint, status.metrics_otlp_endpoint);
+            if let Some(secs) = status.metrics_otlp_interval_secs {
+                self.metrics_otlp_interval = Duration::from_secs(secs);
+            }
+            assign_if_some!(self.selftest_query, status.selftest_query);
+            assign_if_some!(self.selftest_server_name, status.selftest_server_name);
+            assign_if_some!(self.selftest_ca_path, status.selftest_ca_path);
+            assign_if_some!(self.status_auth_token_path, status.auth_token_path);
+            assign_if_some!(
+                self.status_auth_credentials_path,
+                status.auth_credentials_path
+            );
+            if let Some(secs) = status.keep_alive {
+                self.status_server.set_keep_alive_secs(secs);
+            }
+            if let Some(secs) = status.client_timeout {
+                self.status_server.client_request_timeout = Duration::from_secs(secs);
+            }
+            assign_if_some!(
+                self.status_server.max_payload_bytes,
+                status.max_payload_bytes
+            );
+            if let Some(workers) = status.workers {
+                self.status_server.workers = Some(workers);
+            }
+            assign_if_some!(self.status_server.http2, status.http2);
         }
         Ok(())
     }
@@ -36,10 +128,23 @@ pub struct ServiceOptions {
     #[structopt(name = "service_address", long = "service.address")]
     pub address: Option<IpAddr>,
 
+    /// Comma-separated addresses on which the server will listen, overriding the address
+    #[structopt(
+        name = "service_addresses",
+        long = "service.addresses",
+        use_delimiter = true,
+        require_delimiter = true
+    )]
+    pub addresses: Option<Vec<IpAddr>>,
+
     /// Port to which the server will bind
     #[structopt(name = "service_port", long = "service.port")]
     pub port: Option<u16>,
 
+    /// Unix socket on which the server will listen, instead of TCP
+    #[structopt(name = "service_socket_path", long = "service.socket_path")]
+    pub socket_path: Option<PathBuf>,
+
     /// Namespace prefix for all service endpoints (e.g. '/<prefix>/graph')
     #[structopt(long = "service.path_prefix", parse(from_str = parse_path_prefix))]
     #[serde(default = "Option::default", deserialize_with = "de_path_prefix")]
@@ -56,34 +161,150 @@ pub struct ServiceOptions {
     #[structopt(name = "tracing_endpoint", long = "service.tracing_endpoint")]
     pub tracing_endpoint: Option<String>,
 
+    /// Sampling strategy of the traces, 'always_on', 'ratio' or 'parent_based'
+    #[structopt(long = "service.tracing_sampler")]
+    pub tracing_sampler: Option<SamplerKind>,
+
+    /// Ratio of sampled traces, between 0 and 1, for the ratio based samplers
+    #[structopt(long = "service.tracing_sampling_ratio")]
+    pub tracing_sampling_ratio: Option<f64>,
+
     #[structopt(name = "backlog", long = "service.backlog")]
     pub backlog: Option<u32>,
     #[structopt(name = "max_connections", long = "service.max_connections")]
     pub max_connections: Option<usize>,
     #[structopt(name = "max_connection_rate", long = "service.max_connection_rate")]
     pub max_connection_rate: Option<usize>,
+    /// Keep-alive of idle connections of the main service, in seconds, 0 to disable
     #[structopt(name = "keep_alive", long = "service.keep_alive")]
     pub keep_alive: Option<u64>,
+
+    /// Time a client of the main service has to send its first request, in seconds
     #[structopt(name = "client_timeout", long = "service.client_timeout")]
     pub client_timeout: Option<u64>,
+
+    /// Maximum size of request bodies on the main service, in bytes
+    #[structopt(name = "service_max_payload_bytes", long = "service.max_payload_bytes")]
+    pub max_payload_bytes: Option<usize>,
+
+    /// Number of worker threads of the main service, one per physical CPU if unset
+    #[structopt(name = "service_workers", long = "service.workers")]
+    pub workers: Option<usize>,
+
+    /// Accept HTTP/2 without TLS on the main service, in addition to HTTP/1.1
+    #[structopt(name = "service_http2", long = "service.http2")]
+    pub http2: Option<bool>,
+
+    /// Sustained requests per second allowed per client, unlimited if unset
+    #[structopt(long = "service.rate_limit_per_second")]
+    pub rate_limit_per_second: Option<f64>,
+
+    /// Number of requests a client may send at once
+    #[structopt(long = "service.rate_limit_burst")]
+    pub rate_limit_burst: Option<u32>,
+
+    /// Query parameter identifying clusters, which are rate limited on their own
+    #[structopt(long = "service.rate_limit_cluster_id_param")]
+    pub rate_limit_cluster_id_param: Option<String>,
+
+    /// Identify clients by the forwarding headers set by a trusted proxy
+    #[structopt(long = "service.rate_limit_trust_forwarded_for")]
+    pub rate_limit_trust_forwarded_for: Option<bool>,
+
+    /// Name of this deployment, sent in the User-Agent of outbound requests
+    #[structopt(long = "service.deployment_name")]
+    pub deployment_name: Option<String>,
+
+    /// Further headers to send along with outbound requests
+    #[structopt(skip)]
+    pub outbound_headers: Option<BTreeMap<String, String>>,
+
+    /// Maximum number of cached graph responses, 0 disables the cache
+    #[structopt(long = "service.response_cache_max_entries")]
+    pub response_cache_max_entries: Option<usize>,
+
+    /// Lifetime of cached graph responses, in seconds
+    #[structopt(long = "service.response_cache_ttl_secs")]
+    pub response_cache_ttl_secs: Option<u64>,
+
+    /// Comma-separated set of client parameters which graph responses are cached by
+    #[structopt(
+        long = "service.response_cache_key_params",
+        parse(from_str = parse_params_set)
+    )]
+    pub response_cache_key_params: Option<HashSet<String>>,
+
+    /// File containing the bearer token for the debug mode, which is disabled if unset
+    #[structopt(long = "service.debug_token_path")]
+    pub debug_token_path: Option<PathBuf>,
+
+    /// zstd compression level of responses, between 1 and 22
+    #[structopt(long = "service.compression_zstd_level")]
+    pub compression_zstd_level: Option<i32>,
+
+    /// brotli compression level of responses, between 0 and 11
+    #[structopt(long = "service.compression_brotli_level")]
+    pub compression_brotli_level: Option<u32>,
 }
 
 impl MergeOptions<Option<ServiceOptions>> for AppSettings {
     fn try_merge(&mut self, opts: Option<ServiceOptions>) -> Fallible<()> {
         if let Some(service) = opts {
             assign_if_some!(self.address, service.address);
+            assign_if_some!(self.addresses, service.addresses);
             assign_if_some!(self.port, service.port);
+            assign_if_some!(self.socket_path, service.socket_path);
             assign_if_some!(self.path_prefix, service.path_prefix);
             assign_if_some!(self.tracing_endpoint, service.tracing_endpoint);
+            assign_if_some!(self.tracing_sampler, service.tracing_sampler);
+            assign_if_some!(self.tracing_sampling_ratio, service.tracing_sampling_ratio);
             assign_if_some!(self.backlog, service.backlog);
             assign_if_some!(self.max_connections, service.max_connections);
             assign_if_some!(self.max_connection_rate, service.max_connection_rate);
-            self.keep_alive = match service.keep_alive {
-                Some(x) => Some(Duration::new(x, 0)),
-                None => None,
-            };
-            if let Some(duration) = service.client_timeout {
-                self.client_timeout = Duration::new(duration, 0);
+            assign_if_some!(self.rate_limit_per_second, service.rate_limit_per_second);
+            assign_if_some!(self.rate_limit_burst, service.rate_limit_burst);
+            assign_if_some!(
+                self.rate_limit_cluster_id_param,
+                service.rate_limit_cluster_id_param
+            );
+            assign_if_some!(
+                self.rate_limit_trust_forwarded_for,
+                service.rate_limit_trust_forwarded_for
+            );
+            if let Some(secs) = service.keep_alive {
+                self.service_server.set_keep_alive_secs(secs);
+            }
+            if let Some(secs) = service.client_timeout {
+                self.service_server.client_request_timeout = Duration::from_secs(secs);
+            }
+            assign_if_some!(
+                self.service_server.max_payload_bytes,
+                service.max_payload_bytes
+            );
+            if let Some(workers) = service.workers {
+                self.service_server.workers = Some(workers);
+            }
+            assign_if_some!(self.service_server.http2, service.http2);
+            assign_if_some!(self.deployment_name, service.deployment_name);
+            assign_if_some!(
+                self.response_cache_max_entries,
+                service.response_cache_max_entries
+            );
+            if let Some(secs) = service.response_cache_ttl_secs {
+                self.response_cache_ttl = Duration::from_secs(secs);
+            }
+            assign_if_some!(
+                self.response_cache_key_params,
+                service.response_cache_key_params
+            );
+            assign_if_some!(self.debug_token_path, service.debug_token_path);
+            assign_if_some!(self.compression_zstd_level, service.compression_zstd_level);
+            assign_if_some!(
+                self.compression_brotli_level,
+                service.compression_brotli_level
+            );
+            if let Some(headers) = service.outbound_headers {
+                self.outbound_headers.extend(headers);
             }
             if let Some(params) = service.mandatory_client_parameters {
                 self.mandatory_client_parameters.extend(params);
@@ -93,6 +314,148 @@ impl MergeOptions<Option<ServiceOptions>> for AppSettings {
     }
 }
 
+/// Options for TLS termination.
+#[derive(Debug, Deserialize, Serialize, StructOpt)]
+pub struct TlsOptions {
+    /// PEM file with the server certificate, TLS is disabled if unset
+    #[structopt(long = "tls.cert_path")]
+    pub cert_path: Option<PathBuf>,
+
+    /// PEM file with the private key of the server certificate
+    #[structopt(long = "tls.key_path")]
+    pub key_path: Option<PathBuf>,
+
+    /// PEM file with the CA certificates of clients of the main service
+    #[structopt(long = "tls.client_ca_path")]
+    pub client_ca_path: Option<PathBuf>,
+
+    /// Whether the status service terminates TLS too
+    #[structopt(long = "tls.status")]
+    pub status: Option<bool>,
+}
+
+impl MergeOptions<Option<TlsOptions>> for AppSettings {
+    fn try_merge(&mut self, opts: Option<TlsOptions>) -> Fallible<()> {
+        if let Some(tls) = opts {
+            assign_if_some!(self.tls_cert_path, tls.cert_path);
+            assign_if_some!(self.tls_key_path, tls.key_path);
+            assign_if_some!(self.tls_client_ca_path, tls.client_ca_path);
+            assign_if_some!(self.tls_status, tls.status);
+        }
+        Ok(())
+    }
+}
+
+/// Options for signing graph responses.
+#[derive(Debug, Deserialize, Serialize, StructOpt)]
+pub struct SigningOptions {
+    /// PEM file with the private key signing graph responses, which are unsigned if unset
+    #[structopt(name = "signing_key_path", long = "signing.key_path")]
+    pub key_path: Option<PathBuf>,
+
+    /// JWS algorithm of the signatures, 'ES256' or 'ES384'
+    #[structopt(long = "signing.algorithm")]
+    pub algorithm: Option<SigningAlgorithm>,
+
+    /// Key identifier announced in the signatures
+    #[structopt(long = "signing.key_id")]
+    pub key_id: Option<String>,
+
+    /// Duration in seconds after which the signatures expire
+    #[structopt(long = "signing.validity_secs")]
+    pub validity_secs: Option<u64>,
+}
+
+impl MergeOptions<Option<SigningOptions>> for AppSettings {
+    fn try_merge(&mut self, opts: Option<SigningOptions>) -> Fallible<()> {
+        if let Some(signing) = opts {
+            assign_if_some!(self.signing_key_path, signing.key_path);
+            assign_if_some!(self.signing_algorithm, signing.algorithm);
+            assign_if_some!(self.signing_key_id, signing.key_id);
+            if let Some(secs) = signing.validity_secs {
+                self.signing_validity = Duration::from_secs(secs);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Options for usage analytics.
+#[derive(Debug, Deserialize, Serialize, StructOpt)]
+pub struct AnalyticsOptions {
+    /// Count how many times each edge is offered to clients
+    #[structopt(long = "analytics.edge_exposure")]
+    pub edge_exposure: Option<bool>,
+
+    /// Maximum number of edges to keep counters for
+    #[structopt(long = "analytics.max_edges")]
+    pub max_edges: Option<usize>,
+
+    /// JSON file the edge counters are persisted to, kept in memory only if unset
+    #[structopt(long = "analytics.state_path")]
+    pub state_path: Option<PathBuf>,
+
+    /// Interval between writes of the edge counters, in seconds
+    #[structopt(long = "analytics.persist_interval_secs")]
+    pub persist_interval_secs: Option<u64>,
+
+    /// Comma-separated set of channels always tracked by the request metrics
+    #[structopt(long = "analytics.channels", parse(from_str = parse_params_set))]
+    pub channels: Option<HashSet<String>>,
+
+    /// Comma-separated set of architectures always tracked by the request metrics
+    #[structopt(long = "analytics.arches", parse(from_str = parse_params_set))]
+    pub arches: Option<HashSet<String>>,
+
+    /// Maximum number of further channels and architectures tracked by the request metrics
+    #[structopt(long = "analytics.max_label_values")]
+    pub max_label_values: Option<usize>,
+
+    /// Aggregate the client parameters into anonymous telemetry metrics
+    #[structopt(long = "analytics.client_telemetry")]
+    pub client_telemetry: Option<bool>,
+
+    /// Duration after which the client telemetry cluster estimates start over, in seconds
+    #[structopt(long = "analytics.client_telemetry_window_secs")]
+    pub client_telemetry_window_secs: Option<u64>,
+
+    /// File containing the key of the client telemetry cluster ID hashes, shared by all replicas
+    #[structopt(long = "analytics.client_telemetry_key_path")]
+    pub client_telemetry_key_path: Option<PathBuf>,
+}
+
+impl MergeOptions<Option<AnalyticsOptions>> for AppSettings {
+    fn try_merge(&mut self, opts: Option<AnalyticsOptions>) -> Fallible<()> {
+        if let Some(analytics) = opts {
+            assign_if_some!(self.edge_exposure, analytics.edge_exposure);
+            assign_if_some!(self.edge_exposure_max_edges, analytics.max_edges);
+            assign_if_some!(self.edge_exposure_state_path, analytics.state_path);
+            if let Some(secs) = analytics.persist_interval_secs {
+                self.edge_exposure_persist_interval = Duration::from_secs(secs);
+            }
+            if let Some(channels) = analytics.channels {
+                self.request_metrics_channels.extend(channels);
+            }
+            if let Some(arches) = analytics.arches {
+                self.request_metrics_arches.extend(arches);
+            }
+            assign_if_some!(
+                self.request_metrics_max_label_values,
+                analytics.max_label_values
+            );
+            assign_if_some!(self.client_telemetry, analytics.client_telemetry);
+            if let Some(secs) = analytics.client_telemetry_window_secs {
+                self.client_telemetry_window = Duration::from_secs(secs);
+            }
+            assign_if_some!(
+                self.client_telemetry_key_path,
+                analytics.client_telemetry_key_path
+            );
+        }
+        Ok(())
+    }
+}
+
 /// Options for a Cincinnati upstream.
 #[derive(Debug, Deserialize, StructOpt)]
 pub struct UpCincinnatiOptions {
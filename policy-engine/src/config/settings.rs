@@ -1,19 +1,41 @@
 //! Application settings for policy-engine.
 
 use super::{cli, file};
+use crate::cache::CacheSettings;
+use crate::client_telemetry::ClientTelemetrySettings;
+use crate::exposure::ExposureSettings;
+use crate::request_metrics::RequestMetricsSettings;
+use crate::signing::{GraphSigner, SigningAlgorithm};
+use cincinnati::metadata_keys::MetadataKeys;
 use cincinnati::plugins::catalog::{self, PluginSettings};
 use cincinnati::plugins::BoxedPlugin;
+use commons::auth::Credentials;
+use commons::compression::CompressionSettings;
+use commons::cors::CorsSettings;
+use commons::listen::{socket_addrs, validate_listeners, validate_sockets};
+use commons::logging::LogFormat;
+use commons::otlp::OtlpSettings;
+use commons::outbound::ClientIdentity;
 use commons::prelude_errors::*;
+use commons::ratelimit::RateLimitSettings;
+use commons::selftest::{SelfTest, SelfTestTarget};
+use commons::server::HttpServerSettings;
+use commons::tls::TlsSettings;
+use commons::tracing::{SamplerKind, SamplingSettings};
 use custom_debug_derive::Debug as CustomDebug;
 use hyper::Uri;
-use std::collections::HashSet;
-use std::net::{IpAddr, Ipv4Addr};
+use std::collections::{BTreeMap, HashSet};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::PathBuf;
 use std::time::Duration;
 use structopt::StructOpt;
 
 /// Default URL to upstream graph provider.
 pub static DEFAULT_UPSTREAM_URL: &str = "http://localhost:8080/graph";
 
+/// Name of this service in the User-Agent of outbound requests.
+static SERVICE_NAME: &str = "cincinnati-policy-engine";
+
 /// Runtime application settings (validated config).
 #[derive(CustomDebug, SmartDefault)]
 pub struct AppSettings {
@@ -21,6 +43,9 @@ pub struct AppSettings {
     #[default(log::LevelFilter::Warn)]
     pub verbosity: log::LevelFilter,
 
+    /// Log output format.
+    pub log_format: LogFormat,
+
     /// URL for the upstream graph builder or policy engine
     #[default(Uri::from_static(DEFAULT_UPSTREAM_URL))]
     pub upstream: Uri,
@@ -29,18 +54,56 @@ pub struct AppSettings {
     #[default(IpAddr::V4(Ipv4Addr::LOCALHOST))]
     pub address: IpAddr,
 
+    /// Listening addresses for the main service, overriding `address` if not empty.
+    pub addresses: Vec<IpAddr>,
+
     /// Listening port for the main service.
     #[default(8081)]
     pub port: u16,
 
+    /// Unix socket for the main service, replacing the TCP listeners if set.
+    pub socket_path: Option<PathBuf>,
+
     /// Listening address for the status service.
     #[default(IpAddr::V4(Ipv4Addr::LOCALHOST))]
     pub status_address: IpAddr,
 
+    /// Listening addresses for the status service, overriding `status_address` if not empty.
+    pub status_addresses: Vec<IpAddr>,
+
     /// Listening port for the status service.
     #[default(9081)]
     pub status_port: u16,
 
+    /// Unix socket for the status service, replacing the TCP listeners if set.
+    pub status_socket_path: Option<PathBuf>,
+
+    /// Tuning of the HTTP server of the status service.
+    pub status_server: HttpServerSettings,
+
+    /// Optional OTLP/HTTP endpoint to push metrics to.
+    pub metrics_otlp_endpoint: Option<String>,
+
+    /// Interval between OTLP metrics exports.
+    #[default(commons::otlp::DEFAULT_EXPORT_INTERVAL)]
+    pub metrics_otlp_interval: Duration,
+
+    /// Query parameters of the graph requests of the self-test.
+    pub selftest_query: String,
+
+    /// Optional host name the self-test reaches the main service by.
+    pub selftest_server_name: Option<String>,
+
+    /// Optional PEM file with additional CA certificates trusted by the self-test.
+    pub selftest_ca_path: Option<PathBuf>,
+
+    /// Optional file containing the bearer token required on the status endpoints.
+    pub status_auth_token_path: Option<PathBuf>,
+
+    /// Optional file of basic authentication credentials accepted on the status endpoints.
+    /// The status endpoints are unauthenticated if neither this nor the token is set.
+    pub status_auth_credentials_path: Option<PathBuf>,
+
     /// Endpoints namespace for the main service.
     pub path_prefix: String,
 
@@ -53,6 +116,13 @@ pub struct AppSettings {
     /// Jaeger host and port for tracing support
     pub tracing_endpoint: Option<String>,
 
+    /// Sampling strategy of the traces.
+    pub tracing_sampler: SamplerKind,
+
+    /// Ratio of sampled traces, for the ratio based samplers.
+    #[default(commons::tracing::DEFAULT_SAMPLING_RATIO)]
+    pub tracing_sampling_ratio: f64,
+
     /// Actix-web maximum number of pending connections, defaults to 2048: https://docs.rs/actix-web/latest/actix_web/struct.HttpServer.html#method.backlog
     #[default(10)]
     pub backlog: u32,
@@ -62,12 +132,117 @@ pub struct AppSettings {
     /// Actix-web maximum per-worker concurrent connection establish process, defaults to 256: https://docs.rs/actix-web/latest/actix_web/struct.HttpServer.html#method.max_connections
     #[default(64)]
     pub max_connection_rate: usize,
-    /// Actix-web server keepalive, defaults to 5s: https://docs.rs/actix-web/latest/actix_web/struct.HttpServer.html#method.keep_alive
-    #[default(None)]
-    pub keep_alive: Option<Duration>,
-    /// Actix-web server client timeout for first request, defaults to 5s: https://docs.rs/actix-web/latest/actix_web/struct.HttpServer.html#method.client_timeout
-    #[default(Duration::new(5, 0))]
-    pub client_timeout: Duration,
+    /// Tuning of the HTTP server of the main service, without keep-alive by default.
+    #[default(HttpServerSettings {
+        keep_alive: None,
+        ..Default::default()
+    })]
+    pub service_server: HttpServerSettings,
+
+    /// Sustained requests per second allowed per client on the main service, unlimited if unset.
+    pub rate_limit_per_second: Option<f64>,
+
+    /// Number of requests a client may send at once to the main service.
+    #[default(20)]
+    pub rate_limit_burst: u32,
+
+    /// Query parameter identifying clusters, which are rate limited on their own.
+    pub rate_limit_cluster_id_param: Option<String>,
+
+    /// Identify clients by the forwarding headers set by a trusted proxy.
+    pub rate_limit_trust_forwarded_for: bool,
+
+    /// Name of this deployment, sent in the User-Agent of outbound requests.
+    pub deployment_name: Option<String>,
+
+    /// Further headers to send along with outbound requests.
+    pub outbound_headers: BTreeMap<String, String>,
+
+    /// Maximum number of cached graph responses, the cache is disabled if 0.
+    #[default(crate::cache::DEFAULT_MAX_ENTRIES)]
+    pub response_cache_max_entries: usize,
+
+    /// Lifetime of cached graph responses.
+    #[default(crate::cache::DEFAULT_TTL)]
+    pub response_cache_ttl: Duration,
+
+    /// Client parameters which graph responses are cached by.
+    #[default(crate::cache::default_key_params())]
+    pub response_cache_key_params: HashSet<String>,
+
+    /// File containing the bearer token for the debug mode.
+    pub debug_token_path: Option<PathBuf>,
+
+    /// zstd compression level of responses of the main service.
+    #[default(commons::compression::DEFAULT_ZSTD_LEVEL)]
+    pub compression_zstd_level: i32,
+
+    /// brotli compression level of responses of the main service.
+    #[default(commons::compression::DEFAULT_BROTLI_LEVEL)]
+    pub compression_brotli_level: u32,
+
+    /// Server certificate for TLS termination, TLS is disabled if unset.
+    pub tls_cert_path: Option<PathBuf>,
+
+    /// Private key of the server certificate.
+    pub tls_key_path: Option<PathBuf>,
+
+    /// CA certificates of clients of the main service, which are then required
+    /// to authenticate with a certificate.
+    pub tls_client_ca_path: Option<PathBuf>,
+
+    /// Whether the status service terminates TLS too, with the same certificate.
+    pub tls_status: bool,
+
+    /// Cross-origin resource sharing on the main service.
+    pub cors: CorsSettings,
+
+    /// Count how many times each edge is offered to clients.
+    pub edge_exposure: bool,
+
+    /// Maximum number of edges to keep exposure counters for.
+    #[default(crate::exposure::DEFAULT_MAX_EDGES)]
+    pub edge_exposure_max_edges: usize,
+
+    /// File the exposure counters are persisted to.
+    pub edge_exposure_state_path: Option<PathBuf>,
+
+    /// Interval between writes of the exposure counters.
+    #[default(crate::exposure::DEFAULT_PERSIST_INTERVAL)]
+    pub edge_exposure_persist_interval: Duration,
+
+    /// Channels always tracked by the request metrics.
+    pub request_metrics_channels: HashSet<String>,
+
+    /// Architectures always tracked by the request metrics.
+    pub request_metrics_arches: HashSet<String>,
+
+    /// Maximum number of further channels and architectures tracked by the request metrics.
+    #[default(crate::request_metrics::DEFAULT_MAX_LABEL_VALUES)]
+    pub request_metrics_max_label_values: usize,
+
+    /// Aggregate the client parameters into anonymous telemetry metrics.
+    pub client_telemetry: bool,
+
+    /// Duration after which the client telemetry cluster estimates start over.
+    #[default(crate::client_telemetry::DEFAULT_WINDOW)]
+    pub client_telemetry_window: Duration,
+
+    /// File containing the key of the client telemetry cluster ID hashes, shared by all replicas.
+    pub client_telemetry_key_path: Option<PathBuf>,
+
+    /// Private key signing the graph responses, which are unsigned if unset.
+    pub signing_key_path: Option<PathBuf>,
+
+    /// JWS algorithm of the signatures.
+    pub signing_algorithm: SigningAlgorithm,
+
+    /// Key identifier announced in the signatures.
+    pub signing_key_id: Option<String>,
+
+    /// Duration after which the signatures expire.
+    #[default(crate::signing::DEFAULT_SIGNATURE_VALIDITY)]
+    pub signing_validity: Duration,
 }
 
 impl AppSettings {
@@ -80,10 +255,10 @@ impl AppSettings {
 
         // Source options.
         let cli_opts = cli::CliOptions::from_args();
-        let file_opts = match &cli_opts.config_path {
-            Some(ref path) => Some(file::FileOptions::read_filepath(path)?),
-            None => None,
-        };
+        let file_opts = file::FileOptions::read_layered(
+            cli_opts.config_path.as_deref().map(std::path::Path::new),
+            cli_opts.config_dir.as_deref(),
+        )?;
 
         // Combine options into a single config.
         let mut cfg = defaults;
@@ -94,6 +269,195 @@ impl AppSettings {
         Self::try_validate(cfg)
     }
 
+    /// Read the credentials required on the status endpoints, if configured.
+    pub fn status_credentials(&self) -> Fallible<Option<Credentials>> {
+        Credentials::read(
+            self.status_auth_token_path.as_deref(),
+            self.status_auth_credentials_path.as_deref(),
+        )
+    }
+
+    /// Return the identity to use for outbound requests.
+    pub fn client_identity(&self) -> ClientIdentity {
+        ClientIdentity {
+            deployment: self.deployment_name.clone(),
+            headers: self.outbound_headers.clone(),
+            ..ClientIdentity::new(SERVICE_NAME, env!("CARGO_PKG_VERSION"))
+        }
+    }
+
+    /// Return the socket addresses the main service listens on, none if it listens on a Unix socket.
+    pub fn service_addrs(&self) -> Vec<SocketAddr> {
+        if self.socket_path.is_some() {
+            return vec![];
+        }
+        socket_addrs(self.address, &self.addresses, self.port)
+    }
+
+    /// Return the socket addresses the status service listens on, none if it listens on a Unix socket.
+    pub fn status_addrs(&self) -> Vec<SocketAddr> {
+        if self.status_socket_path.is_some() {
+            return vec![];
+        }
+        socket_addrs(
+            self.status_address,
+            &self.status_addresses,
+            self.status_port,
+        )
+    }
+
+    /// Return the sampling settings of the tracer.
+    pub fn tracing_sampling(&self) -> SamplingSettings {
+        SamplingSettings {
+            sampler: self.tracing_sampler,
+            ratio: self.tracing_sampling_ratio,
+        }
+    }
+
+    /// Return the OTLP metrics export settings, if enabled.
+    pub fn otlp_settings(&self) -> Option<OtlpSettings> {
+        self.metrics_otlp_endpoint
+            .as_ref()
+            .map(|endpoint| OtlpSettings {
+                endpoint: endpoint.clone(),
+                interval: self.metrics_otlp_interval,
+            })
+    }
+
+    /// Return the edge exposure settings, if enabled.
+    pub fn exposure_settings(&self) -> Option<ExposureSettings> {
+        if !self.edge_exposure {
+            return None;
+        }
+        Some(ExposureSettings {
+            max_edges: self.edge_exposure_max_edges,
+            state_path: self.edge_exposure_state_path.clone(),
+            persist_interval: self.edge_exposure_persist_interval,
+        })
+    }
+
+    /// Return the per-channel request metrics settings.
+    pub fn request_metrics_settings(&self) -> RequestMetricsSettings {
+        RequestMetricsSettings {
+            channels: self.request_metrics_channels.clone(),
+            arches: self.request_metrics_arches.clone(),
+            max_label_values: self.request_metrics_max_label_values,
+        }
+    }
+
+    /// Return the client telemetry settings, if enabled.
+    ///
+    /// The key path is required when enabled, as checked by the validation.
+    pub fn client_telemetry_settings(&self) -> Option<ClientTelemetrySettings> {
+        if !self.client_telemetry {
+            return None;
+        }
+        Some(ClientTelemetrySettings {
+            channels: self.request_metrics_channels.clone(),
+            max_label_values: self.request_metrics_max_label_values,
+            window: self.client_telemetry_window,
+            key_path: self.client_telemetry_key_path.clone()?,
+        })
+    }
+
+    /// Return the graph response cache settings, if enabled.
+    pub fn response_cache_settings(&self) -> Option<CacheSettings> {
+        if self.response_cache_max_entries == 0 {
+            return None;
+        }
+        Some(CacheSettings {
+            max_entries: self.response_cache_max_entries,
+            ttl: self.response_cache_ttl,
+            key_params: self.response_cache_key_params.clone(),
+        })
+    }
+
+    /// Return the response compression settings for the main service.
+    pub fn compression_settings(&self) -> CompressionSettings {
+        CompressionSettings {
+            zstd_level: self.compression_zstd_level,
+            brotli_level: self.compression_brotli_level,
+        }
+    }
+
+    /// Return the self-test of the main service, reached on its first address.
+    ///
+    /// The self-test is unavailable if the status service is unauthenticated,
+    /// as each run sends several requests, or if the main service listens on a
+    /// Unix socket.
+    pub fn selftest(&self) -> Fallible<Option<SelfTest>> {
+        if self.status_auth_token_path.is_none() && self.status_auth_credentials_path.is_none() {
+            return Ok(None);
+        }
+        let addr = match self.service_addrs().first() {
+            Some(addr) => *addr,
+            None => return Ok(None),
+        };
+
+        let mut target = SelfTestTarget::new(
+            addr,
+            self.tls_cert_path.is_some(),
+            &self.path_prefix,
+            &self.selftest_query,
+        )?;
+        if let Some(name) = &self.selftest_server_name {
+            target = target.with_server_name(name)?;
+        }
+        SelfTest::new(target, self.selftest_ca_path.as_deref()).map(Some)
+    }
+
+    /// Return the TLS settings for the main service, if enabled.
+    pub fn tls_settings(&self) -> Option<TlsSettings> {
+        match (&self.tls_cert_path, &self.tls_key_path) {
+            (Some(cert_path), Some(key_path)) => Some(TlsSettings {
+                cert_path: cert_path.clone(),
+                key_path: key_path.clone(),
+                client_ca_path: self.tls_client_ca_path.clone(),
+            }),
+            _ => None,
+        }
+    }
+
+    /// Return the TLS settings for the status service, if enabled with `tls.status`.
+    ///
+    /// Client certificates are not required on the status service, so that
+    /// probes and metrics scraping keep working.
+    pub fn status_tls_settings(&self) -> Option<TlsSettings> {
+        if !self.tls_status {
+            return None;
+        }
+        self.tls_settings().map(|tls| TlsSettings {
+            client_ca_path: None,
+            ..tls
+        })
+    }
+
+    /// Load the signer of the graph responses, if enabled.
+    pub fn graph_signer(&self) -> Fallible<Option<GraphSigner>> {
+        self.signing_key_path
+            .as_deref()
+            .map(|path| {
+                GraphSigner::from_pem_file(
+                    path,
+                    self.signing_algorithm,
+                    self.signing_key_id.as_deref(),
+                    self.signing_validity,
+                )
+            })
+            .transpose()
+    }
+
+    /// Return the rate limiting settings for the main service, if enabled.
+    pub fn rate_limit_settings(&self) -> Option<RateLimitSettings> {
+        self.rate_limit_per_second
+            .map(|requests_per_second| RateLimitSettings {
+                requests_per_second,
+                burst: self.rate_limit_burst,
+                cluster_id_param: self.rate_limit_cluster_id_param.clone(),
+                trust_forwarded_for: self.rate_limit_trust_forwarded_for,
+            })
+    }
+
     /// Validate and the configured plugins.
     pub fn validate_and_build_plugins(
         &self,
@@ -107,14 +471,117 @@ impl AppSettings {
             &self.plugin_settings
         };
 
-        catalog::build_plugins(plugin_settings, registry)
+        let plugins = catalog::build_plugins(plugin_settings, registry)?;
+        cincinnati::plugins::lint::check(&plugins)?;
+        Ok(plugins)
+    }
+
+    /// Keys of the release metadata, with the prefix of the configured plugins.
+    pub fn metadata_keys(&self) -> Fallible<MetadataKeys> {
+        if self.plugin_settings.is_empty() {
+            Ok(MetadataKeys::from_plugins(
+                &self.default_openshift_plugin_settings()?,
+            ))
+        } else {
+            Ok(MetadataKeys::from_plugins(&self.plugin_settings))
+        }
     }
 
     /// Validate and build runtime settings.
     fn try_validate(self) -> Fallible<Self> {
-        if self.address == self.status_address && self.port == self.status_port {
-            bail!("main and status service configured with the same address and port");
+        validate_listeners(&self.service_addrs(), &self.status_addrs())?;
+        validate_sockets(
+            self.socket_path.as_deref(),
+            self.status_socket_path.as_deref(),
+            self.tls_cert_path.is_some(),
+            self.status_tls_settings().is_some(),
+        )?;
+        self.service_server.validate("service")?;
+        self.status_server.validate("status")?;
+
+        if let Some(endpoint) = &self.metrics_otlp_endpoint {
+            url::Url::parse(endpoint).context(format!(
+                "invalid status.metrics_otlp_endpoint '{}'",
+                endpoint
+            ))?;
         }
+        ensure!(
+            self.metrics_otlp_interval.as_secs() > 0,
+            "status.metrics_otlp_interval_secs must be positive"
+        );
+        self.tracing_sampling()
+            .validate()
+            .context("invalid service.tracing_sampling_ratio")?;
+        self.cors.validate().context("invalid CORS settings")?;
+
+        if let Some(requests_per_second) = self.rate_limit_per_second {
+            ensure!(
+                requests_per_second.is_finite() && requests_per_second > 0.0,
+                "service.rate_limit_per_second must be positive, got {}",
+                requests_per_second
+            );
+        }
+        ensure!(
+            self.rate_limit_burst > 0,
+            "service.rate_limit_burst must be positive"
+        );
+
+        self.client_identity()
+            .headers()
+            .context("invalid service.deployment_name or service.outbound_headers")?;
+
+        ensure!(
+            self.tls_cert_path.is_some() == self.tls_key_path.is_some(),
+            "tls.cert_path and tls.key_path must be set together"
+        );
+        ensure!(
+            self.tls_client_ca_path.is_none() || self.tls_cert_path.is_some(),
+            "tls.client_ca_path requires tls.cert_path and tls.key_path"
+        );
+        ensure!(
+            !self.tls_status || self.tls_cert_path.is_some(),
+            "tls.status requires tls.cert_path and tls.key_path"
+        );
+
+        ensure!(
+            self.signing_key_id.is_none() || self.signing_key_path.is_some(),
+            "signing.key_id requires signing.key_path"
+        );
+        ensure!(
+            !self.signing_validity.is_zero(),
+            "signing.validity_secs must be positive"
+        );
+
+        ensure!(
+            self.edge_exposure_max_edges > 0,
+            "analytics.max_edges must be positive"
+        );
+        ensure!(
+            self.edge_exposure_persist_interval.as_secs() > 0,
+            "analytics.persist_interval_secs must be positive"
+        );
+        ensure!(
+            self.client_telemetry_window.as_secs() > 0,
+            "analytics.client_telemetry_window_secs must be positive"
+        );
+        ensure!(
+            !self.client_telemetry || self.client_telemetry_key_path.is_some(),
+            "analytics.client_telemetry_key_path is required by the client telemetry"
+        );
+        ensure!(
+            self.response_cache_ttl.as_secs() > 0,
+            "service.response_cache_ttl_secs must be positive"
+        );
+        ensure!(
+            commons::compression::ZSTD_LEVELS.contains(&self.compression_zstd_level),
+            "service.compression_zstd_level must be between 1 and 22, got {}",
+            self.compression_zstd_level
+        );
+        ensure!(
+            commons::compression::BROTLI_LEVELS.contains(&self.compression_brotli_level),
+            "service.compression_brotli_level must be between 0 and 11, got {}",
+            self.compression_brotli_level
+        );
 
         // Deprecates options
         if self.upstream.to_string() != hyper::Uri::default().to_string() {
@@ -160,6 +627,7 @@ impl AppSettings {
                     cincinnati::plugins::internal::arch_filter::DEFAULT_DEFAULT_ARCH_THRESHOLD_VERSION
                 )
             )?,
+            plugin_config!(("name", ClusterProfileFilterPlugin::PLUGIN_NAME))?,
         ])
     }
 }
@@ -0,0 +1,290 @@
+//! Per-request decision log.
+//!
+//! Support cases often boil down to "why is this cluster not offered that
+//! release". With `debug=true` and the debug bearer token, the plugin chain
+//! runs one plugin at a time and the graph response is augmented with what each
+//! plugin removed from, or added to, the graph of this very request.
+
+use crate::graph::run_plugins_on;
+use crate::AppState;
+use actix_web::HttpRequest;
+use cincinnati::plugins::{BoxedPlugin, InternalIO};
+use cincinnati::Graph;
+use commons::auth;
+use commons::prelude_errors::*;
+use commons::GraphError;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::Path;
+
+/// Query parameter enabling the decision log.
+pub(crate) static DEBUG_PARAM: &str = "debug";
+
+/// Read the bearer token for the debug mode from a file.
+pub fn read_token(path: &Path) -> Fallible<String> {
+    let token = std::fs::read_to_string(path)
+        .context(format!("reading debug token from {}", path.display()))?
+        .trim()
+        .to_string();
+    ensure!(!token.is_empty(), "empty debug token in {}", path.display());
+
+    Ok(token)
+}
+
+/// Remove the debug parameter, returning whether the decision log was requested.
+pub(crate) fn requested(params: &mut HashMap<String, String>) -> Result<bool, GraphError> {
+    match params.remove(DEBUG_PARAM).as_deref() {
+        None | Some("false") => Ok(false),
+        Some("true") => Ok(true),
+        Some(value) => Err(GraphError::InvalidParams(format!(
+            "{} must be 'true' or 'false', got '{}'",
+            DEBUG_PARAM, value
+        ))),
+    }
+}
+
+/// Check the bearer token of a request for the decision log.
+pub(crate) fn authorize(req: &HttpRequest, app_data: &AppState) -> Result<(), GraphError> {
+    let expected = app_data
+        .debug_token
+        .as_deref()
+        .ok_or_else(|| GraphError::Unauthorized("debug mode is disabled".to_string()))?;
+
+    if auth::has_bearer_token(req.headers(), expected) {
+        return Ok(());
+    }
+    Err(GraphError::Unauthorized(
+        "missing or invalid bearer token".to_string(),
+    ))
+}
+
+/// Decision log, added to the graph response as `debug`.
+#[derive(Debug, Default, Serialize, PartialEq)]
+pub(crate) struct DecisionLog {
+    decisions: Vec<Decision>,
+}
+
+/// Changes made to the graph by a plugin.
+#[derive(Debug, Default, Serialize, PartialEq)]
+pub(crate) struct Decision {
+    plugin: &'static str,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    removed_releases: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    removed_edges: Vec<Edge>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    added_edges: Vec<Edge>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    removed_conditional_edges: Vec<Edge>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    added_conditional_edges: Vec<Edge>,
+}
+
+/// An edge, along with the risks of conditional edges.
+#[derive(Debug, Serialize, PartialEq)]
+pub(crate) struct Edge {
+    from: String,
+    to: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    risks: Vec<String>,
+}
+
+/// Releases and edges of a graph, by version.
+#[derive(Default)]
+struct GraphContent {
+    releases: BTreeSet<String>,
+    edges: BTreeSet<(String, String)>,
+    conditional_edges: BTreeMap<(String, String), Vec<String>>,
+}
+
+impl GraphContent {
+    fn of(graph: &Graph) -> Self {
+        Self {
+            releases: graph
+                .iter_releases()
+                .map(|release| release.version().to_string())
+                .collect(),
+            edges: graph
+                .iter_edges()
+                .map(|(from, to)| (from.version().to_string(), to.version().to_string()))
+                .collect(),
+            conditional_edges: graph
+                .conditional_edges()
+                .into_iter()
+                .map(|(from, to, risks)| {
+                    (
+                        (from.to_string(), to.to_string()),
+                        risks.into_iter().map(str::to_string).collect(),
+                    )
+                })
+                .collect(),
+        }
+    }
+}
+
+impl Decision {
+    /// Returns the changes between the graph before and after a plugin, if any.
+    fn between(plugin: &'static str, before: &GraphContent, after: &GraphContent) -> Option<Self> {
+        let edges = |from: &BTreeSet<(String, String)>, without: &BTreeSet<(String, String)>| {
+            from.difference(without)
+                .map(|(from, to)| Edge {
+                    from: from.clone(),
+                    to: to.clone(),
+                    risks: vec![],
+                })
+                .collect()
+        };
+        let conditional_edges =
+            |from: &BTreeMap<(String, String), Vec<String>>,
+             without: &BTreeMap<(String, String), Vec<String>>| {
+                from.iter()
+                    .filter(|(edge, _)| !without.contains_key(*edge))
+                    .map(|((from, to), risks)| Edge {
+                        from: from.clone(),
+                        to: to.clone(),
+                        risks: risks.clone(),
+                    })
+                    .collect()
+            };
+
+        let decision = Self {
+            plugin,
+            removed_releases: before
+                .releases
+                .difference(&after.releases)
+                .cloned()
+                .collect(),
+            removed_edges: edges(&before.edges, &after.edges),
+            added_edges: edges(&after.edges, &before.edges),
+            removed_conditional_edges: conditional_edges(
+                &before.conditional_edges,
+                &after.conditional_edges,
+            ),
+            added_conditional_edges: conditional_edges(
+                &after.conditional_edges,
+                &before.conditional_edges,
+            ),
+        };
+
+        Some(decision).filter(|decision| {
+            *decision
+                != Self {
+                    plugin,
+                    ..Default::default()
+                }
+        })
+    }
+}
+
+/// Run the plugin chain one plugin at a time, logging the changes of each of them.
+///
+/// The plugins building the graph from scratch are not logged.
+pub(crate) async fn run_plugins_logged(
+    plugins: &'static [BoxedPlugin],
+    plugin_params: HashMap<String, String>,
+) -> Result<(InternalIO, DecisionLog), GraphError> {
+    let mut io = InternalIO {
+        graph: Default::default(),
+        parameters: plugin_params,
+    };
+    let mut log = DecisionLog::default();
+
+    for plugin in plugins {
+        let before = GraphContent::of(&io.graph);
+        io = run_plugins_on(std::iter::once(plugin), io).await?;
+        if before.releases.is_empty() {
+            continue;
+        }
+
+        let after = GraphContent::of(&io.graph);
+        if let Some(decision) = Decision::between(plugin.get_name(), &before, &after) {
+            log.decisions.push(decision);
+        }
+    }
+
+    Ok((io, log))
+}
+
+/// Add the decision log to a serialized graph response.
+pub(crate) fn augment(graph_json: &str, log: &DecisionLog) -> Result<String, GraphError> {
+    let mut response: serde_json::Map<String, serde_json::Value> =
+        serde_json::from_str(graph_json).map_err(|e| GraphError::FailedJsonOut(e.to_string()))?;
+    response.insert(
+        DEBUG_PARAM.to_string(),
+        serde_json::to_value(log).map_err(|e| GraphError::FailedJsonOut(e.to_string()))?,
+    );
+    serde_json::to_string(&response).map_err(|e| GraphError::FailedJsonOut(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cincinnati::testing::generate_custom_graph;
+
+    #[test]
+    fn decisions() {
+        let graph = generate_custom_graph(
+            "image",
+            vec![
+                (0, Default::default()),
+                (1, Default::default()),
+                (2, Default::default()),
+            ],
+            Some(vec![(0, 1), (1, 2), (0, 2)]),
+        );
+        let before = GraphContent::of(&graph);
+
+        let mut filtered = graph.clone();
+        let release_id = filtered.find_by_version("2.0.0").unwrap();
+        filtered.remove_releases(vec![release_id]);
+        let after = GraphContent::of(&filtered);
+
+        assert_eq!(Decision::between("unchanged", &before, &before), None);
+        assert_eq!(
+            Decision::between("node-remove", &before, &after),
+            Some(Decision {
+                plugin: "node-remove",
+                removed_releases: vec!["2.0.0".to_string()],
+                removed_edges: vec![
+                    Edge {
+                        from: "0.0.0".to_string(),
+                        to: "2.0.0".to_string(),
+                        risks: vec![],
+                    },
+                    Edge {
+                        from: "1.0.0".to_string(),
+                        to: "2.0.0".to_string(),
+                        risks: vec![],
+                    },
+                ],
+                ..Default::default()
+            })
+        );
+
+        let mut params: HashMap<String, String> = vec![("debug".to_string(), "true".to_string())]
+            .into_iter()
+            .collect();
+        assert_eq!(requested(&mut params), Ok(true));
+        assert!(params.is_empty());
+        assert_eq!(requested(&mut params), Ok(false));
+        params.insert("debug".to_string(), "yes".to_string());
+        assert!(requested(&mut params).is_err());
+
+        let augmented = augment(
+            r#"{"version":1,"nodes":[]}"#,
+            &DecisionLog {
+                decisions: vec![Decision {
+                    plugin: "channel-filter",
+                    removed_releases: vec!["1.0.0".to_string()],
+                    ..Default::default()
+                }],
+            },
+        )
+        .unwrap();
+        let augmented: serde_json::Value = serde_json::from_str(&augmented).unwrap();
+        assert_eq!(
+            augmented["debug"]["decisions"][0]["removed_releases"][0],
+            "1.0.0"
+        );
+        assert_eq!(augmented["version"], 1);
+    }
+}
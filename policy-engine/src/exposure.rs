@@ -0,0 +1,327 @@
+//! Edge exposure analytics.
+//!
+//! Counts how many times each update edge has been offered to clients, so
+//! that release managers can check whether a newly published edge actually
+//! reaches clusters. An edge is offered to a cluster when it starts from the
+//! version the cluster runs. The number of tracked edges is bounded, the least
+//! recently served ones are dropped first. Counters are optionally persisted
+//! to a JSON file, which is loaded again on startup.
+
+use crate::AppState;
+use actix_web::web::Query;
+use actix_web::HttpResponse;
+use cached::{Cached, SizedCache};
+use cincinnati::{Graph, GraphFields};
+use commons::prelude_errors::*;
+use commons::GraphError;
+use parking_lot::Mutex;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Default maximum number of tracked edges.
+pub static DEFAULT_MAX_EDGES: usize = 10_000;
+
+/// Default interval between writes of the counters to disk.
+pub static DEFAULT_PERSIST_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Settings of the edge exposure counters.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ExposureSettings {
+    /// Maximum number of tracked edges.
+    pub max_edges: usize,
+    /// File the counters are persisted to, kept in memory only if unset.
+    pub state_path: Option<PathBuf>,
+    /// Interval between writes of the counters to disk.
+    pub persist_interval: Duration,
+}
+
+/// Exposure of a single edge.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct EdgeStats {
+    /// Number of responses which offered the edge.
+    pub count: u64,
+    /// Time the edge was first served, in seconds since the Unix epoch.
+    pub first_served: u64,
+    /// Time the edge was last served, in seconds since the Unix epoch.
+    pub last_served: u64,
+}
+
+/// Persisted exposure of a single edge.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+struct EdgeEntry {
+    from: String,
+    to: String,
+    #[serde(flatten)]
+    stats: EdgeStats,
+}
+
+/// Exposure counters of all tracked edges, least recently served evicted first.
+#[derive(Debug)]
+pub struct EdgeExposure {
+    settings: ExposureSettings,
+    edges: Mutex<SizedCache<(String, String), EdgeStats>>,
+}
+
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+impl EdgeExposure {
+    /// Create the counters, loading the persisted ones if any.
+    pub fn try_new(settings: ExposureSettings) -> Fallible<Self> {
+        ensure!(settings.max_edges > 0, "max_edges must be greater than 0");
+
+        let exposure = Self {
+            edges: Mutex::new(SizedCache::with_size(settings.max_edges)),
+            settings,
+        };
+        if let Some(path) = &exposure.settings.state_path {
+            if path.exists() {
+                let content = std::fs::read(path)
+                    .context(format!("reading edge exposure from {}", path.display()))?;
+                let mut entries: Vec<EdgeEntry> = serde_json::from_slice(&content)
+                    .context(format!("parsing edge exposure from {}", path.display()))?;
+                info!("loaded exposure of {} edges", entries.len());
+
+                // Insert the most recently served edges last, so that they are
+                // the last ones evicted.
+                entries.sort_by_key(|entry| entry.stats.last_served);
+                let mut edges = exposure.edges.lock();
+                for entry in entries {
+                    edges.cache_set((entry.from, entry.to), entry.stats);
+                }
+            }
+        }
+
+        Ok(exposure)
+    }
+
+    /// Count one offering of each of the given `(from, to)` edges.
+    pub fn record<'a, I>(&self, edges: I, now: SystemTime)
+    where
+        I: IntoIterator<Item = (&'a str, &'a str)>,
+    {
+        let now = unix_secs(now);
+        let mut tracked = self.edges.lock();
+        for (from, to) in edges {
+            let stats =
+                tracked.cache_get_or_set_with((from.to_string(), to.to_string()), || EdgeStats {
+                    count: 0,
+                    first_served: now,
+                    last_served: now,
+                });
+            stats.count += 1;
+            stats.last_served = now;
+        }
+    }
+
+    /// Count the edges of a graph served to a cluster running `version`, as
+    /// selected by the client.
+    pub fn record_graph(&self, graph: &Graph, fields: Option<GraphFields>, version: &str) {
+        self.record_edges(&Self::served_edges(graph, fields, version));
+    }
+
+    /// Count one offering of each of the given `(from, to)` edges, now.
+    pub fn record_edges(&self, edges: &[(String, String)]) {
+        self.record(
+            edges.iter().map(|(from, to)| (from.as_str(), to.as_str())),
+            SystemTime::now(),
+        );
+    }
+
+    /// Returns the edges offered to a cluster running `version` by a served
+    /// graph, as selected by the client.
+    pub fn served_edges(
+        graph: &Graph,
+        fields: Option<GraphFields>,
+        version: &str,
+    ) -> Vec<(String, String)> {
+        let fields = fields.unwrap_or_else(GraphFields::all);
+        let conditional_edges = graph.conditional_edges();
+
+        let edges = graph
+            .iter_edges()
+            .map(|(from, to)| (from.version(), to.version()))
+            .filter(|_| fields.edges);
+        let conditional_edges = conditional_edges
+            .iter()
+            .map(|(from, to, _)| (*from, *to))
+            .filter(|_| fields.conditional_edges);
+
+        edges
+            .chain(conditional_edges)
+            .filter(|(from, _)| *from == version)
+            .map(|(from, to)| (from.to_string(), to.to_string()))
+            .collect()
+    }
+
+    /// Return the tracked edges, most served first.
+    fn entries(&self) -> Vec<EdgeEntry> {
+        // Only copy the entries while holding the lock.
+        let mut entries: Vec<EdgeEntry> = {
+            let edges = self.edges.lock();
+            edges
+                .key_order()
+                .zip(edges.value_order())
+                .map(|((from, to), stats)| EdgeEntry {
+                    from: from.clone(),
+                    to: to.clone(),
+                    stats: stats.clone(),
+                })
+                .collect()
+        };
+        entries.sort_by(|a, b| {
+            b.stats
+                .count
+                .cmp(&a.stats.count)
+                .then_with(|| a.from.cmp(&b.from))
+                .then_with(|| a.to.cmp(&b.to))
+        });
+        entries
+    }
+
+    /// Write the counters to the state file, if configured.
+    ///
+    /// The file is replaced atomically, so that a crash never leaves a
+    /// truncated file behind.
+    pub fn persist(&self) -> Fallible<()> {
+        let path = match &self.settings.state_path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => std::path::Path::new("."),
+        };
+
+        let mut file = tempfile::NamedTempFile::new_in(dir)
+            .context(format!("creating temporary file in {}", dir.display()))?;
+        file.write_all(&serde_json::to_vec(&self.entries())?)?;
+        file.persist(path)
+            .context(format!("writing edge exposure to {}", path.display()))?;
+
+        Ok(())
+    }
+
+    /// Persist the counters periodically, forever.
+    pub async fn run_persist(self: Arc<Self>) {
+        let mut interval = actix_web::rt::time::interval(self.settings.persist_interval);
+        loop {
+            interval.tick().await;
+            if let Err(e) = self.persist() {
+                warn!("failed to persist edge exposure: {:#}", e);
+            }
+        }
+    }
+}
+
+/// Query parameters of the analytics endpoint.
+#[derive(Debug, Deserialize)]
+pub(crate) struct EdgesQuery {
+    from: Option<String>,
+    to: Option<String>,
+}
+
+/// Serve the exposure of the tracked edges.
+///
+/// The edges can be narrowed down with the `from` and `to` query parameters.
+pub(crate) async fn serve_edges(
+    query: Query<EdgesQuery>,
+    app_data: actix_web::web::Data<AppState>,
+) -> Result<HttpResponse, GraphError> {
+    let exposure = app_data
+        .exposure
+        .as_ref()
+        .ok_or_else(|| GraphError::NotFound("edge exposure tracking is disabled".to_string()))?;
+
+    let edges: Vec<EdgeEntry> = exposure
+        .entries()
+        .into_iter()
+        .filter(|entry| query.from.as_ref().is_none_or(|from| *from == entry.from))
+        .filter(|entry| query.to.as_ref().is_none_or(|to| *to == entry.to))
+        .collect();
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "edges": edges })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bounded_persisted_counters() -> Fallible<()> {
+        let tmpdir = tempfile::tempdir()?;
+        let settings = ExposureSettings {
+            max_edges: 2,
+            state_path: Some(tmpdir.path().join("exposure.json")),
+            persist_interval: DEFAULT_PERSIST_INTERVAL,
+        };
+        let at = |secs| UNIX_EPOCH + Duration::from_secs(secs);
+
+        let exposure = EdgeExposure::try_new(settings.clone())?;
+        exposure.record(vec![("4.10.1", "4.10.2"), ("4.10.1", "4.10.3")], at(10));
+        exposure.record(vec![("4.10.1", "4.10.3")], at(20));
+        exposure.record(vec![("4.10.2", "4.10.3")], at(30));
+
+        // The least recently served edge was dropped.
+        let entries = exposure.entries();
+        assert_eq!(
+            entries,
+            vec![
+                EdgeEntry {
+                    from: "4.10.1".to_string(),
+                    to: "4.10.3".to_string(),
+                    stats: EdgeStats {
+                        count: 2,
+                        first_served: 10,
+                        last_served: 20,
+                    },
+                },
+                EdgeEntry {
+                    from: "4.10.2".to_string(),
+                    to: "4.10.3".to_string(),
+                    stats: EdgeStats {
+                        count: 1,
+                        first_served: 30,
+                        last_served: 30,
+                    },
+                },
+            ]
+        );
+
+        exposure.persist()?;
+        let reloaded = EdgeExposure::try_new(settings)?;
+        assert_eq!(reloaded.entries(), entries);
+
+        Ok(())
+    }
+
+    #[test]
+    fn record_selected_fields() {
+        let graph = cincinnati::testing::generate_custom_graph(
+            "image",
+            (0..3).map(|i| (i, Default::default())).collect(),
+            Some(vec![(0, 1), (1, 2)]),
+        );
+        let exposure = EdgeExposure::try_new(ExposureSettings {
+            max_edges: DEFAULT_MAX_EDGES,
+            state_path: None,
+            persist_interval: DEFAULT_PERSIST_INTERVAL,
+        })
+        .unwrap();
+
+        exposure.record_graph(&graph, None, "1.0.0");
+        exposure.record_graph(&graph, Some("version".parse().unwrap()), "1.0.0");
+        exposure.record_graph(&graph, None, "3.0.0");
+        let counts: Vec<(String, String, u64)> = exposure
+            .entries()
+            .into_iter()
+            .map(|entry| (entry.from, entry.to, entry.stats.count))
+            .collect();
+        assert_eq!(counts, vec![("1.0.0".to_string(), "2.0.0".to_string(), 1)]);
+    }
+}
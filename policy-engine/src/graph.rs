@@ -1,12 +1,22 @@
 //! Cincinnati graph service.
 
+use crate::cache::ResponseCache;
+use crate::debug;
+use crate::exposure::EdgeExposure;
+use crate::request_metrics::KnownLabels;
+use crate::signing::{GraphSigner, SignatureBinding, SIGNATURE_HEADER};
 use crate::AppState;
 use actix_web::http::header;
 use actix_web::web::Query;
 use actix_web::{HttpRequest, HttpResponse};
+use cincinnati::channel_lifecycle::{Lifecycle, LIFECYCLE_HEADER};
+use cincinnati::metadata_keys::MetadataKeys;
+use cincinnati::plugins::internal::cincinnati_graph_fetch::CincinnatiGraphFetchPlugin;
 use cincinnati::plugins::internal::versioned_graph::VersionedGraph;
 use cincinnati::plugins::{BoxedPlugin, InternalIO};
-use cincinnati::CONTENT_TYPE;
+use cincinnati::provenance::Provenance;
+use cincinnati::v2::GraphV2;
+use cincinnati::{GraphFields, CONTENT_TYPE};
 use commons::tracing::get_tracer;
 use commons::{self, Fallible, GraphError};
 use opentelemetry::{
@@ -15,6 +25,7 @@ use opentelemetry::{
 };
 use prometheus::{histogram_opts, Histogram, IntCounterVec, Opts, Registry};
 use std::collections::HashMap;
+use std::sync::Arc;
 
 lazy_static! {
     static ref GRAPH_INCOMING_REQS: IntCounterVec = IntCounterVec::new(
@@ -35,6 +46,7 @@ lazy_static! {
 /// Register relevant metrics to a prometheus registry.
 pub(crate) fn register_metrics(registry: &Registry) -> Fallible<()> {
     commons::register_metrics(registry)?;
+    cincinnati::semver_cache::register_metrics(registry)?;
     registry.register(Box::new(GRAPH_INCOMING_REQS.clone()))?;
     registry.register(Box::new(GRAPH_SERVE_HIST.clone()))?;
     Ok(())
@@ -45,7 +57,17 @@ pub(crate) async fn index(
     req: HttpRequest,
     app_data: actix_web::web::Data<AppState>,
 ) -> Result<HttpResponse, GraphError> {
-    _index(&req, app_data)
+    _index(&req, app_data, false)
+        .await
+        .map_err(|e| api_response_error(&req, e))
+}
+
+/// Serve Cincinnati graph requests in the version 2 format.
+pub(crate) async fn index_v2(
+    req: HttpRequest,
+    app_data: actix_web::web::Data<AppState>,
+) -> Result<HttpResponse, GraphError> {
+    _index(&req, app_data, true)
         .await
         .map_err(|e| api_response_error(&req, e))
 }
@@ -53,6 +75,7 @@ pub(crate) async fn index(
 async fn _index(
     req: &HttpRequest,
     app_data: actix_web::web::Data<AppState>,
+    v2: bool,
 ) -> Result<HttpResponse, GraphError> {
     let span = get_tracer().start("index");
     let _active_span = mark_span_as_active(span);
@@ -60,16 +83,29 @@ async fn _index(
     let path = req.uri().path();
     GRAPH_INCOMING_REQS.with_label_values(&[path]).inc();
 
-    let accept_default = header::HeaderValue::from_static(CONTENT_TYPE);
+    // Check that the client can accept media type.
+    let content_type: String = if v2 {
+        // The format is selected by the path, so any acceptable type gets the v2 one.
+        let accept_default = header::HeaderValue::from_static(cincinnati::v2::CONTENT_TYPE);
+        commons::validate_content_type(
+            req.headers(),
+            vec![
+                accept_default.clone(),
+                header::HeaderValue::from_static(CONTENT_TYPE),
+            ],
+            accept_default,
+        )?;
+        cincinnati::v2::CONTENT_TYPE.to_string()
+    } else {
+        let accept_default = header::HeaderValue::from_static(CONTENT_TYPE);
 
-    let accept_versions: Vec<header::HeaderValue> = commons::CINCINNATI_VERSION
-        .keys()
-        .map(|val| header::HeaderValue::from_static(val))
-        .collect();
+        let accept_versions: Vec<header::HeaderValue> = commons::CINCINNATI_VERSION
+            .keys()
+            .map(|val| header::HeaderValue::from_static(val))
+            .collect();
 
-    // Check that the client can accept media type.
-    let content_type: String =
-        commons::validate_content_type(req.headers(), accept_versions, accept_default)?;
+        commons::validate_content_type(req.headers(), accept_versions, accept_default)?
+    };
 
     // Check for required client parameters.
     let mandatory_params = &app_data.mandatory_params;
@@ -79,21 +115,82 @@ async fn _index(
         .map(|query| query.into_inner())
         .map_err(|e| commons::GraphError::InvalidParams(e.to_string()))?;
 
+    if let Some(request_metrics) = &app_data.request_metrics {
+        request_metrics.record(&plugin_params);
+    }
+    if let Some(client_telemetry) = &app_data.client_telemetry {
+        client_telemetry.record(&plugin_params);
+    }
+
     plugin_params.insert(String::from("content_type"), content_type);
 
+    // The projection only applies to the response, the plugins don't need it.
+    let fields = match plugin_params.remove("fields") {
+        Some(fields) => Some(
+            fields
+                .parse::<GraphFields>()
+                .map_err(|e| GraphError::InvalidParams(e.to_string()))?,
+        ),
+        None => None,
+    };
+    if v2 && fields.is_some() {
+        return Err(GraphError::InvalidParams(
+            "fields are not supported by the v2 graph API".to_string(),
+        ));
+    }
+
+    if debug::requested(&mut plugin_params)? {
+        debug::authorize(req, &app_data)?;
+        let (internal_io, log) = debug::run_plugins_logged(app_data.plugins, plugin_params).await?;
+        let mut response = render_graph(&internal_io, fields, false, &app_data.metadata_keys)?;
+        response.body = debug::augment(&response.body, &log)?;
+        let mut resp = response.serve(None, app_data.signer.as_deref());
+        resp.headers_mut().insert(
+            header::CACHE_CONTROL,
+            header::HeaderValue::from_static("no-store"),
+        );
+        return Ok(resp);
+    }
+
     let timer = GRAPH_SERVE_HIST.start_timer();
 
     let cx = ot_context::current();
-    let response = process_plugins(app_data.plugins.iter(), plugin_params)
-        .with_context(cx)
-        .await;
+    let response = match app_data.cache.clone() {
+        Some(cache) => {
+            process_plugins_cached(
+                app_data.plugins,
+                plugin_params,
+                fields,
+                app_data.exposure.clone(),
+                app_data.signer.clone(),
+                app_data.known_labels.clone(),
+                app_data.metadata_keys.clone(),
+                cache,
+            )
+            .with_context(cx)
+            .await
+        }
+        None => {
+            process_plugins(
+                app_data.plugins,
+                plugin_params,
+                fields,
+                app_data.exposure.clone(),
+                app_data.signer.clone(),
+                app_data.known_labels.clone(),
+                app_data.metadata_keys.clone(),
+            )
+            .with_context(cx)
+            .await
+        }
+    };
 
     timer.observe_duration();
     response
 }
 
 // logs api request error
-fn api_response_error(req: &HttpRequest, e: GraphError) -> GraphError {
+pub(crate) fn api_response_error(req: &HttpRequest, e: GraphError) -> GraphError {
     error!(
         "Error serving request \"{}\" from '{}': {:?}",
         format_request(req),
@@ -130,39 +227,269 @@ pub fn format_request(req: &HttpRequest) -> String {
     )
 }
 
-async fn process_plugins<P>(
+/// Run the plugin chain with the given parameters.
+pub(crate) async fn run_plugins<P>(
     plugins: P,
     plugin_params: HashMap<String, String>,
-) -> Result<HttpResponse, GraphError>
+) -> Result<InternalIO, GraphError>
 where
     P: std::iter::Iterator<Item = &'static BoxedPlugin>,
     P: 'static + Sync + Send,
 {
-    let internal_io = cincinnati::plugins::process(
+    run_plugins_on(
         plugins,
-        cincinnati::plugins::PluginIO::InternalIO(cincinnati::plugins::InternalIO {
+        InternalIO {
             graph: Default::default(),
             parameters: plugin_params,
-        }),
+        },
     )
     .await
-    .map_err(|e| match e.downcast::<GraphError>() {
-        Ok(graph_error) => graph_error,
-        Err(other_error) => GraphError::FailedPluginExecution(other_error.to_string()),
-    })?;
+}
+
+/// Run the plugin chain on the output of previous plugins.
+pub(crate) async fn run_plugins_on<P>(plugins: P, io: InternalIO) -> Result<InternalIO, GraphError>
+where
+    P: std::iter::Iterator<Item = &'static BoxedPlugin>,
+    P: 'static + Sync + Send,
+{
+    cincinnati::plugins::process(plugins, cincinnati::plugins::PluginIO::InternalIO(io))
+        .await
+        .map_err(|e| match e.downcast::<GraphError>() {
+            Ok(graph_error) => graph_error,
+            Err(other_error) => GraphError::FailedPluginExecution(other_error.to_string()),
+        })
+}
+
+/// Position in the plugin chain right after the plugin fetching the upstream graph.
+fn fetched_position(plugins: &[BoxedPlugin]) -> Option<usize> {
+    plugins
+        .iter()
+        .position(|plugin| plugin.get_name() == CincinnatiGraphFetchPlugin::PLUGIN_NAME)
+        .map(|position| position + 1)
+}
+
+/// Run the plugins fetching the upstream graph, collecting its label values.
+async fn fetch_upstream(
+    plugins: &'static [BoxedPlugin],
+    plugin_params: HashMap<String, String>,
+    known_labels: Option<&KnownLabels>,
+) -> Result<InternalIO, GraphError> {
+    let upstream = run_plugins(plugins.iter(), plugin_params).await?;
+    if let Some(known_labels) = known_labels {
+        known_labels.observe(&upstream);
+    }
+    Ok(upstream)
+}
+
+async fn process_plugins(
+    plugins: &'static [BoxedPlugin],
+    plugin_params: HashMap<String, String>,
+    fields: Option<GraphFields>,
+    exposure: Option<Arc<EdgeExposure>>,
+    signer: Option<Arc<GraphSigner>>,
+    known_labels: Option<Arc<KnownLabels>>,
+    keys: Arc<MetadataKeys>,
+) -> Result<HttpResponse, GraphError> {
+    let internal_io = match fetched_position(plugins) {
+        Some(fetched) => {
+            let upstream =
+                fetch_upstream(&plugins[..fetched], plugin_params, known_labels.as_deref()).await?;
+            run_plugins_on(plugins[fetched..].iter(), upstream).await?
+        }
+        None => run_plugins(plugins.iter(), plugin_params).await?,
+    };
+    let response = render_graph(&internal_io, fields, exposure.is_some(), &keys)?;
+    Ok(response.serve(exposure.as_deref(), signer.as_deref()))
+}
+
+/// Run the plugin chain, serving the responses cached for the current upstream graph.
+#[allow(clippy::too_many_arguments)]
+async fn process_plugins_cached(
+    plugins: &'static [BoxedPlugin],
+    plugin_params: HashMap<String, String>,
+    fields: Option<GraphFields>,
+    exposure: Option<Arc<EdgeExposure>>,
+    signer: Option<Arc<GraphSigner>>,
+    known_labels: Option<Arc<KnownLabels>>,
+    keys: Arc<MetadataKeys>,
+    cache: Arc<ResponseCache>,
+) -> Result<HttpResponse, GraphError> {
+    // The hash of the upstream graph identifies the generation of the responses,
+    // so the chain is split after the plugin fetching it.
+    let fetched = match fetched_position(plugins) {
+        Some(fetched) => fetched,
+        None => {
+            return process_plugins(
+                plugins,
+                plugin_params,
+                fields,
+                exposure,
+                signer,
+                known_labels,
+                keys,
+            )
+            .await
+        }
+    };
+
+    let key = cache.key(&plugin_params, fields);
+    let upstream =
+        fetch_upstream(&plugins[..fetched], plugin_params, known_labels.as_deref()).await?;
+    let generation =
+        Provenance::from_parameters(&upstream.parameters).and_then(|provenance| provenance.hash);
+    if let Some(response) = generation
+        .as_deref()
+        .and_then(|generation| cache.get(generation, &key))
+    {
+        return Ok(response.serve(exposure.as_deref(), signer.as_deref()));
+    }
 
-    let versioned_graph = add_version_information(&internal_io);
+    let internal_io = run_plugins_on(plugins[fetched..].iter(), upstream).await?;
+    let response = render_graph(&internal_io, fields, exposure.is_some(), &keys)?;
+    if let Some(generation) = generation {
+        cache.insert(generation, key, response.clone());
+    }
+    Ok(response.serve(exposure.as_deref(), signer.as_deref()))
+}
+
+/// A rendered graph response, as cached.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) struct GraphResponse {
+    pub(crate) content_type: String,
+    pub(crate) provenance: Option<Provenance>,
+    pub(crate) body: String,
+    /// Edges offered by the response, only listed if they are counted.
+    pub(crate) edges: Vec<(String, String)>,
+    /// Request parameters the signature of the body is bound to.
+    pub(crate) binding: SignatureBinding,
+    /// Lifecycle of the requested channel, if it is being retired.
+    pub(crate) lifecycle: Option<Lifecycle>,
+}
 
-    let graph_json = serde_json::to_string(&versioned_graph)
-        .map_err(|e| GraphError::FailedJsonOut(e.to_string()))?;
+impl GraphResponse {
+    /// Build the HTTP response, counting the offered edges.
+    ///
+    /// The body is signed here rather than when rendered, so that responses
+    /// served from the cache carry a signature issued now.
+    fn serve(self, exposure: Option<&EdgeExposure>, signer: Option<&GraphSigner>) -> HttpResponse {
+        if let Some(exposure) = exposure {
+            exposure.record_edges(&self.edges);
+        }
+
+        let mut resp = HttpResponse::Ok();
+        resp.content_type(self.content_type);
+        if let Some(provenance) = self.provenance {
+            for provenance_header in provenance.headers() {
+                resp.insert_header(provenance_header);
+            }
+        }
+        if let Some(signer) = signer {
+            let signature = signer.sign(self.body.as_bytes(), &self.binding);
+            resp.insert_header((SIGNATURE_HEADER, signature));
+        }
+        if let Some(lifecycle) = self.lifecycle {
+            resp.insert_header((LIFECYCLE_HEADER, lifecycle.header_value()));
+        }
+        resp.body(self.body)
+    }
+}
 
+/// Serialize the graph computed by the plugin chain.
+///
+/// The v2 format reads the well-known release metadata from the given keys.
+fn render_graph(
+    internal_io: &InternalIO,
+    fields: Option<GraphFields>,
+    with_edges: bool,
+    keys: &MetadataKeys,
+) -> Result<GraphResponse, GraphError> {
     let content_type = match &internal_io.parameters.get("content_type") {
         Some(version) => *version,
         None => *commons::MIN_CINCINNATI_VERSION,
     };
-    Ok(HttpResponse::Ok()
-        .content_type(content_type)
-        .body(graph_json))
+
+    let lifecycle = internal_io
+        .parameters
+        .get("channel")
+        .and_then(|channel| internal_io.graph.channel_lifecycle(channel))
+        .cloned();
+
+    let reachable_io = internal_io
+        .parameters
+        .get("version")
+        .and_then(|version| reachable_graph(&internal_io.graph, version))
+        .map(|graph| InternalIO {
+            graph,
+            parameters: internal_io.parameters.clone(),
+        });
+    let internal_io = reachable_io.as_ref().unwrap_or(internal_io);
+
+    let graph_json = if content_type == cincinnati::v2::CONTENT_TYPE {
+        serde_json::to_string(&GraphV2::new(
+            &internal_io.graph,
+            Provenance::from_parameters(&internal_io.parameters),
+            keys,
+        ))
+    } else {
+        let versioned_graph = add_version_information(internal_io);
+        match fields {
+            Some(fields) => serde_json::to_string(&ProjectedVersionedGraph {
+                version: versioned_graph.version,
+                graph: versioned_graph.graph.project(fields),
+            }),
+            None => serde_json::to_string(&versioned_graph),
+        }
+    }
+    .map_err(|e| GraphError::FailedJsonOut(e.to_string()))?;
+
+    let edges = match internal_io.parameters.get("version") {
+        Some(version) if with_edges => {
+            EdgeExposure::served_edges(&internal_io.graph, fields, version)
+        }
+        _ => vec![],
+    };
+    Ok(GraphResponse {
+        content_type: content_type.to_string(),
+        provenance: Provenance::from_parameters(&internal_io.parameters),
+        body: graph_json,
+        edges,
+        binding: SignatureBinding::of(&internal_io.parameters),
+        lifecycle,
+    })
+}
+
+/// Pass on the provenance of the upstream graph, if known.
+pub(crate) fn add_provenance_headers(resp: &mut actix_web::HttpResponseBuilder, io: &InternalIO) {
+    if let Some(provenance) = Provenance::from_parameters(&io.parameters) {
+        for provenance_header in provenance.headers() {
+            resp.insert_header(provenance_header);
+        }
+    }
+}
+
+/// Versioned graph serializing only the fields selected by the client.
+#[derive(Serialize)]
+struct ProjectedVersionedGraph<'a> {
+    version: i32,
+    #[serde(flatten)]
+    graph: cincinnati::ProjectedGraph<'a>,
+}
+
+/// Prune the graph to the releases reachable from the client's version.
+///
+/// Returns `None` if the version isn't part of the graph, in which case the
+/// whole graph is served.
+fn reachable_graph(graph: &cincinnati::Graph, version: &str) -> Option<cincinnati::Graph> {
+    let span = get_tracer().start("reachable_graph");
+    let _active_span = mark_span_as_active(span);
+    let reachable = graph.reachable_from(version)?;
+    log::trace!(
+        "serving {} of {} releases reachable from {}",
+        reachable.releases_count(),
+        graph.releases_count(),
+        version
+    );
+    Some(reachable)
 }
 
 /// add version information to the graph json
@@ -180,6 +507,7 @@ pub(crate) mod tests {
     use crate::AppState;
     use actix_web::body::MessageBody;
     use actix_web::http;
+    use cincinnati::metadata_keys::MetadataKeys;
     use cincinnati::plugins::prelude::*;
     use tokio::runtime::Runtime;
 
@@ -288,6 +616,8 @@ pub(crate) mod tests {
         }
 
         static SERVED_GRAPH_BODY: &str = r#"{"nodes":[],"edges":[]}"#;
+        static RESPONSE_GRAPH_BODY: &str =
+            r#"{"version":1,"nodes":[],"edges":[],"conditionalEdges":[]}"#;
 
         fn run_test(
             mandatory_params: &[&str],
@@ -315,7 +645,7 @@ pub(crate) mod tests {
             let _m = mockito::mock("GET", "/")
                 .with_status(200)
                 .with_header("content-type", "application/json")
-                .with_body(SERVED_GRAPH_BODY.to_string())
+                .with_body(SERVED_GRAPH_BODY)
                 .create();
 
             // prepare and run the policy-engine test-service
@@ -407,7 +737,7 @@ pub(crate) mod tests {
                     ("name", CincinnatiGraphFetchPlugin::PLUGIN_NAME),
                     ("upstream", &mockito::server_url())
                 )?],
-                expected_result: TestResult::Success(SERVED_GRAPH_BODY.to_string()),
+                expected_result: TestResult::Success(RESPONSE_GRAPH_BODY.to_string()),
             },
             TestParams {
                 name: "offline upstream",
@@ -460,4 +790,217 @@ pub(crate) mod tests {
             .map_err(|e| format_err!("test '{}' failed: {}", test_param.name, e))
         })
     }
+
+    #[test]
+    fn v2_graph() -> Result<(), Error> {
+        let rt = common_init();
+
+        let plugins = cincinnati::plugins::catalog::build_plugins(
+            &[plugin_config!(
+                ("name", CincinnatiGraphFetchPlugin::PLUGIN_NAME),
+                ("upstream", &format!("{}/v2-graph", mockito::server_url()))
+            )?],
+            None,
+        )?;
+        let _m = mockito::mock("GET", "/v2-graph")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(
+                r#"{
+                    "nodes": [
+                        {"version": "1.0.0", "payload": "image/1.0.0", "metadata": {
+                            "io.openshift.upgrades.graph.release.channels": "stable-1,fast-1"
+                        }},
+                        {"version": "2.0.0", "payload": "image/2.0.0", "metadata": {
+                            "io.openshift.upgrades.graph.release.channels": "fast-1",
+                            "io.openshift.upgrades.graph.release.arch": "amd64"
+                        }}
+                    ],
+                    "edges": [[0, 1]]
+                }"#,
+            )
+            .create();
+
+        let app_data = actix_web::web::Data::new(AppState {
+            plugins: Box::leak(Box::new(plugins)),
+            ..Default::default()
+        });
+        let request = |query: &str, accept: &'static str| {
+            actix_web::test::TestRequest::get()
+                .uri(&format!("http://unused.test/v2/graph{}", query))
+                .insert_header((http::header::ACCEPT, accept))
+                .to_http_request()
+        };
+
+        let resp = rt
+            .block_on(graph::index_v2(
+                request("", "application/json"),
+                app_data.clone(),
+            ))
+            .map_err(|e| format_err!("{:?}", e))?;
+        assert_eq!(
+            resp.headers().get(http::header::CONTENT_TYPE).unwrap(),
+            cincinnati::v2::CONTENT_TYPE
+        );
+        let body = match resp.into_body().try_into_bytes() {
+            Ok(bytes) => bytes,
+            Err(_) => bail!("expected bytes in body"),
+        };
+        let json: serde_json::Value = serde_json::from_slice(&body)?;
+        assert_eq!(json["version"], 2);
+        assert_eq!(json["nodes"][1]["architecture"], "amd64");
+        assert_eq!(
+            json["edges"],
+            serde_json::json!([
+                {"from": "1.0.0", "to": "2.0.0", "channels": ["fast-1"], "risks": []}
+            ])
+        );
+
+        let err = rt
+            .block_on(graph::index_v2(
+                request("?fields=nodes", "application/json"),
+                app_data.clone(),
+            ))
+            .unwrap_err();
+        assert_eq!(err.kind(), "invalid_params");
+        rt.block_on(graph::index_v2(request("", "text/html"), app_data))
+            .unwrap_err();
+
+        Ok(())
+    }
+
+    #[test]
+    fn reachable_graph() -> Result<(), Error> {
+        let graph = cincinnati::testing::generate_graph(true, false);
+        let render = |version: Option<&str>| -> Result<serde_json::Value, Error> {
+            let mut parameters = std::collections::HashMap::new();
+            if let Some(version) = version {
+                parameters.insert("version".to_string(), version.to_string());
+            }
+            let response = graph::render_graph(
+                &cincinnati::plugins::InternalIO {
+                    graph: graph.clone(),
+                    parameters,
+                },
+                None,
+                false,
+                &MetadataKeys::default(),
+            )
+            .map_err(|e| format_err!("{:?}", e))?;
+            Ok(serde_json::from_str(&response.body)?)
+        };
+        let versions = |json: &serde_json::Value| {
+            let mut versions: Vec<String> = json["nodes"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .map(|node| node["version"].as_str().unwrap_or_default().to_string())
+                .collect();
+            versions.sort();
+            versions
+        };
+
+        let json = render(Some("2.0.0"))?;
+        assert_eq!(versions(&json), vec!["2.0.0", "3.0.0"]);
+        assert_eq!(json["edges"].as_array().map(Vec::len), Some(1));
+        assert_eq!(json["conditionalEdges"], serde_json::json!([]));
+
+        // The conditional edge is kept along with its releases.
+        let json = render(Some("1.0.0"))?;
+        assert_eq!(versions(&json), vec!["1.0.0", "2.0.0", "3.0.0"]);
+        assert_eq!(json["conditionalEdges"].as_array().map(Vec::len), Some(1));
+
+        // Unknown versions get the whole graph.
+        assert_eq!(render(Some("4.0.0"))?, render(None)?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn signed_response() -> Result<(), Error> {
+        use crate::signing::{
+            GraphSigner, SignatureBinding, SigningAlgorithm, DEFAULT_SIGNATURE_VALIDITY,
+            SIGNATURE_HEADER,
+        };
+        use p256::ecdsa::signature::Verifier;
+        use p256::pkcs8::{EncodePrivateKey, LineEnding};
+
+        let secret = p256::SecretKey::from_slice(&[7u8; 32])?;
+        let pem = secret.to_pkcs8_pem(LineEnding::LF)?;
+        let signer = GraphSigner::from_pem(
+            &pem,
+            SigningAlgorithm::ES256,
+            None,
+            DEFAULT_SIGNATURE_VALIDITY,
+        )?;
+        let response = graph::GraphResponse {
+            content_type: cincinnati::CONTENT_TYPE.to_string(),
+            body: r#"{"version":1,"nodes":[],"edges":[],"conditionalEdges":[]}"#.to_string(),
+            binding: SignatureBinding {
+                channel: Some("stable-4.14".to_string()),
+                arch: Some("amd64".to_string()),
+            },
+            ..Default::default()
+        };
+
+        assert!(response
+            .clone()
+            .serve(None, None)
+            .headers()
+            .get(SIGNATURE_HEADER)
+            .is_none());
+
+        let resp = response.clone().serve(None, Some(&signer));
+        let jws = resp.headers().get(SIGNATURE_HEADER).unwrap().to_str()?;
+        let parts: Vec<&str> = jws.split('.').collect();
+        let header: serde_json::Value =
+            serde_json::from_slice(&base64::decode_config(parts[0], base64::URL_SAFE_NO_PAD)?)?;
+        assert_eq!(header["channel"], "stable-4.14");
+        assert_eq!(header["arch"], "amd64");
+
+        let signature = p256::ecdsa::Signature::from_slice(&base64::decode_config(
+            parts[2],
+            base64::URL_SAFE_NO_PAD,
+        )?)?;
+        let input = format!(
+            "{}.{}",
+            parts[0],
+            base64::encode_config(&response.body, base64::URL_SAFE_NO_PAD)
+        );
+        p256::ecdsa::VerifyingKey::from(&secret.public_key())
+            .verify(input.as_bytes(), &signature)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn lifecycle_header() -> Result<(), Error> {
+        use cincinnati::channel_lifecycle::{Lifecycle, Status, LIFECYCLE_HEADER};
+
+        let response = graph::GraphResponse {
+            content_type: cincinnati::CONTENT_TYPE.to_string(),
+            ..Default::default()
+        };
+        assert!(response
+            .clone()
+            .serve(None, None)
+            .headers()
+            .get(LIFECYCLE_HEADER)
+            .is_none());
+
+        let resp = graph::GraphResponse {
+            lifecycle: Some(Lifecycle::new(
+                Status::Deprecated,
+                Some("2024-06-30".to_string()),
+            )?),
+            ..response
+        }
+        .serve(None, None);
+        assert_eq!(
+            resp.headers().get(LIFECYCLE_HEADER).unwrap().to_str()?,
+            "deprecated; sunset=2024-06-30"
+        );
+
+        Ok(())
+    }
 }
@@ -19,19 +19,39 @@ extern crate structopt;
 #[macro_use]
 extern crate custom_debug_derive;
 
+mod batch;
+mod cache;
+mod channels;
+mod client_telemetry;
 mod config;
+mod debug;
+mod exposure;
 mod graph;
 mod openapi;
+mod path;
+mod request_metrics;
+mod signing;
 mod status;
 
-use actix_cors::Cors;
 use actix_service::Service;
-use actix_web::http::StatusCode;
 use actix_web::{http, middleware, App, HttpRequest, HttpResponse, HttpServer};
+use cache::ResponseCache;
+use cincinnati::metadata_keys::MetadataKeys;
 use cincinnati::plugins::BoxedPlugin;
+use client_telemetry::ClientTelemetry;
+use commons::auth;
+use commons::compression;
+use commons::listen;
+use commons::logging;
 use commons::metrics::{self, HasRegistry};
+use commons::otlp::OtlpExporter;
+use commons::outbound;
 use commons::prelude_errors::*;
+use commons::ratelimit;
+use commons::request_id;
+use commons::selftest::SelfTest;
 use commons::tracing::{get_tracer, init_tracer, set_span_tags};
+use exposure::EdgeExposure;
 use futures::future;
 use opentelemetry::{
     trace::{mark_span_as_active, FutureExt, Tracer},
@@ -39,6 +59,8 @@ use opentelemetry::{
 };
 use parking_lot::RwLock;
 use prometheus::{labels, opts, Counter, Registry};
+use request_metrics::{KnownLabels, RequestMetrics};
+use signing::GraphSigner;
 use std::collections::HashSet;
 use std::sync::Arc;
 use std::thread;
@@ -67,11 +89,27 @@ lazy_static! {
 #[actix_web::main]
 async fn main() -> Result<(), Error> {
     let settings = config::AppSettings::assemble()?;
-    env_logger::Builder::from_default_env()
-        .filter(Some(module_path!()), settings.verbosity)
-        .filter(Some("cincinnati"), settings.verbosity)
-        .init();
+    logging::init(
+        &[module_path!(), "cincinnati"],
+        settings.verbosity,
+        settings.log_format,
+    );
     debug!("application settings:\n{:#?}", &settings);
+    let listen::ActivatedListeners {
+        main: activated_main,
+        status: activated_status,
+    } = listen::ActivatedListeners::from_env()?;
+
+    outbound::set_identity(&settings.client_identity())?;
+
+    let main_tls = settings
+        .tls_settings()
+        .map(|tls| tls.server_config())
+        .transpose()?;
+    let status_tls = settings
+        .status_tls_settings()
+        .map(|tls| tls.server_config())
+        .transpose()?;
 
     // Metrics service.
     let registry: &'static Registry = Box::leak(Box::new(metrics::new_registry(Some(
@@ -82,6 +120,23 @@ async fn main() -> Result<(), Error> {
     // Main service.
     let plugins = settings.validate_and_build_plugins(Some(registry))?;
 
+    // Edge exposure analytics.
+    let exposure = settings
+        .exposure_settings()
+        .map(EdgeExposure::try_new)
+        .transpose()?
+        .map(Arc::new);
+    if let Some(exposure) = &exposure {
+        actix_web::rt::spawn(exposure.clone().run_persist());
+    }
+
+    // Graph response cache.
+    let cache = settings
+        .response_cache_settings()
+        .map(ResponseCache::try_new)
+        .transpose()?
+        .map(Arc::new);
+
     // Shared state.
     let state = {
         let mandatory_params = settings.mandatory_client_parameters.clone();
@@ -89,6 +144,8 @@ async fn main() -> Result<(), Error> {
         let plugins = Box::leak(Box::new(plugins));
         let live = Arc::new(RwLock::new(false));
         let ready = Arc::new(RwLock::new(false));
+        let metadata_keys = settings.metadata_keys()?;
+        let known_labels = Arc::new(KnownLabels::new(metadata_keys.clone()));
 
         AppState::new(
             mandatory_params,
@@ -97,15 +154,60 @@ async fn main() -> Result<(), Error> {
             live,
             ready,
             registry,
+            exposure,
+            cache,
+        )
+        .with_debug_token(
+            settings
+                .debug_token_path
+                .as_deref()
+                .map(debug::read_token)
+                .transpose()?,
+        )
+        .with_selftest(settings.selftest()?)
+        .with_signer(settings.graph_signer()?)
+        .with_metadata_keys(metadata_keys)
+        .with_known_labels(Some(known_labels.clone()))
+        .with_request_metrics(Some(RequestMetrics::new(
+            settings.request_metrics_settings(),
+            known_labels.clone(),
+        )))
+        .with_client_telemetry(
+            settings
+                .client_telemetry_settings()
+                .map(|telemetry| ClientTelemetry::try_new(telemetry, known_labels))
+                .transpose()?,
         )
     };
+    if let Some(signer) = &state.signer {
+        info!("signing graph responses with {}", signer.algorithm().name());
+    }
 
     graph::register_metrics(state.registry())?;
+    batch::register_metrics(state.registry())?;
+    cache::register_metrics(state.registry())?;
+    request_metrics::register_metrics(state.registry())?;
+    client_telemetry::register_metrics(state.registry())?;
+
+    // Metrics push export.
+    if let Some(otlp_settings) = settings.otlp_settings() {
+        let exporter = OtlpExporter::try_new(otlp_settings, "policy-engine", state.registry())?;
+        actix_web::rt::spawn(exporter.run());
+    }
+
     let metric_state = state.clone();
+    let status_credentials = settings.status_credentials()?;
+    let status_server = settings.status_server.clone();
+    let status_payload = status_server.payload_config();
     let metrics_server = HttpServer::new(move || {
+        let status_credentials = status_credentials.clone();
         App::new()
+            .wrap_fn(move |req, srv| auth::authenticate(status_credentials.as_ref(), &[], req, srv))
+            .wrap(commons::problem_json_handlers())
             .wrap(middleware::Compress::default())
+            .wrap_fn(|req, srv| request_id::propagate(req, srv))
             .app_data(actix_web::web::Data::new(metric_state.clone()))
+            .app_data(status_payload.clone())
             .service(
                 actix_web::web::resource("/metrics")
                     .route(actix_web::web::get().to(metrics::serve::<AppState>)),
@@ -118,16 +220,76 @@ async fn main() -> Result<(), Error> {
                 actix_web::web::resource("/readyz")
                     .route(actix_web::web::get().to(status::serve_readiness)),
             )
+            .service(
+                actix_web::web::resource("/status/selftest")
+                    .route(actix_web::web::get().to(status::serve_selftest)),
+            )
+            .service(
+                actix_web::web::resource("/analytics/edges")
+                    .route(actix_web::web::get().to(exposure::serve_edges)),
+            )
+            .service(
+                actix_web::web::resource("/analytics/clusters")
+                    .route(actix_web::web::get().to(client_telemetry::serve_sketches)),
+            )
     })
-    .bind((settings.status_address, settings.status_port))?
-    .run();
+    .keep_alive(status_server.keep_alive)
+    .client_request_timeout(status_server.client_request_timeout);
+    let mut metrics_server = match status_server.workers {
+        Some(workers) => metrics_server.workers(workers),
+        None => metrics_server,
+    };
+    let status_listeners = if activated_status.is_empty() {
+        listen::bind(
+            settings.status_socket_path.as_deref(),
+            &settings.status_addrs(),
+        )
+        .context("Binding the status service")?
+    } else {
+        activated_status
+    };
+    for listener in status_listeners {
+        let name = listener.to_string();
+        metrics_server = match (listener, &status_tls) {
+            (listen::Listener::Tcp(listener), Some(config)) => {
+                metrics_server.listen_rustls(listener, config.clone())
+            }
+            (listen::Listener::Tcp(listener), None) if status_server.http2 => {
+                metrics_server.listen_auto_h2c(listener)
+            }
+            (listen::Listener::Tcp(listener), None) => metrics_server.listen(listener),
+            (listen::Listener::Unix(listener), None) => metrics_server.listen_uds(listener),
+            (listen::Listener::Unix(_), Some(_)) => {
+                bail!("TLS termination is not supported on Unix sockets")
+            }
+        }
+        .context(format!("Listening with the status service on {}", name))?;
+    }
+    let metrics_server = metrics_server.run();
 
     // Enable tracing
-    init_tracer("policy-engine", settings.tracing_endpoint.clone())?;
+    init_tracer(
+        "policy-engine",
+        settings.tracing_endpoint.clone(),
+        settings.tracing_sampling(),
+    )?;
     let main_state = state.clone();
+    let rate_limiter = settings
+        .rate_limit_settings()
+        .map(ratelimit::RateLimiter::new);
+    let compression_settings = settings.compression_settings();
+    let cors = settings.cors.clone();
+    let service_server = settings.service_server.clone();
+    let main_payload = service_server.payload_config();
     let main_server = HttpServer::new(move || {
         let app_prefix = main_state.path_prefix.clone();
+        let rate_limiter = rate_limiter.clone();
         App::new()
+            .wrap(commons::problem_json_handlers())
+            // Inside CORS and request IDs, so that refused requests get their headers.
+            .wrap_fn(move |req, srv| ratelimit::limit(rate_limiter.as_ref(), req, srv))
+            .wrap_fn(move |req, srv| compression::compress(compression_settings, req, srv))
+            .wrap_fn(|req, srv| request_id::propagate(req, srv))
             .wrap_fn(|req, srv| {
                 let mut span = get_tracer().start("request");
                 set_span_tags(req.path(), req.headers(), &mut span);
@@ -135,27 +297,48 @@ async fn main() -> Result<(), Error> {
                 let cx = ot_context::current();
                 srv.call(req).with_context(cx)
             })
-            .wrap(
-                Cors::default()
-                    .allow_any_origin()
-                    .allowed_methods(vec!["HEAD", "GET"]),
-            )
+            .wrap(cors.middleware())
             .app_data(actix_web::web::Data::<AppState>::new(main_state.clone()))
+            .app_data(main_payload.clone())
             .service(
                 // keeping this for backward compatibility
-                actix_web::web::resource(&format!("{}/v1/graph", app_prefix))
+                actix_web::web::resource(format!("{}/v1/graph", app_prefix))
                     .route(actix_web::web::get().to(graph::index)),
             )
             .service(
-                actix_web::web::resource(&format!("{}/graph", app_prefix))
+                actix_web::web::resource(format!("{}/graph", app_prefix))
                     .route(actix_web::web::get().to(graph::index)),
             )
             .service(
-                actix_web::web::resource(&format!("{}/openapi", app_prefix))
+                actix_web::web::resource(format!("{}/v2/graph", app_prefix))
+                    .route(actix_web::web::get().to(graph::index_v2)),
+            )
+            .service(
+                actix_web::web::resource(format!("{}/v1/graph/batch", app_prefix))
+                    .route(actix_web::web::post().to(batch::index)),
+            )
+            .service(
+                actix_web::web::resource(format!("{}/graph/batch", app_prefix))
+                    .route(actix_web::web::post().to(batch::index)),
+            )
+            .service(
+                actix_web::web::resource(format!("{}/v1/channels", app_prefix))
+                    .route(actix_web::web::get().to(channels::index)),
+            )
+            .service(
+                actix_web::web::resource(format!("{}/v1/path", app_prefix))
+                    .route(actix_web::web::get().to(path::index)),
+            )
+            .service(
+                actix_web::web::resource(format!("{}/v1/signing-keys", app_prefix))
+                    .route(actix_web::web::get().to(signing::serve_keys)),
+            )
+            .service(
+                actix_web::web::resource(format!("{}/openapi", app_prefix))
                     .route(actix_web::web::get().to(openapi::index)),
             )
             .service(
-                actix_web::web::resource(&format!("{}/v1/openapi", app_prefix))
+                actix_web::web::resource(format!("{}/v1/openapi", app_prefix))
                     .route(actix_web::web::get().to(openapi::index)),
             )
             .default_service(actix_web::web::route().to(default_response))
@@ -163,10 +346,36 @@ async fn main() -> Result<(), Error> {
     .backlog(settings.backlog)
     .max_connections(settings.max_connections)
     .max_connection_rate(settings.max_connection_rate)
-    .keep_alive(settings.keep_alive)
-    .client_request_timeout(settings.client_timeout)
-    .bind((settings.address, settings.port))?
-    .run();
+    .keep_alive(service_server.keep_alive)
+    .client_request_timeout(service_server.client_request_timeout);
+    let mut main_server = match service_server.workers {
+        Some(workers) => main_server.workers(workers),
+        None => main_server,
+    };
+    let main_listeners = if activated_main.is_empty() {
+        listen::bind(settings.socket_path.as_deref(), &settings.service_addrs())
+            .context("Binding the main service")?
+    } else {
+        activated_main
+    };
+    for listener in main_listeners {
+        let name = listener.to_string();
+        main_server = match (listener, &main_tls) {
+            (listen::Listener::Tcp(listener), Some(config)) => {
+                main_server.listen_rustls(listener, config.clone())
+            }
+            (listen::Listener::Tcp(listener), None) if service_server.http2 => {
+                main_server.listen_auto_h2c(listener)
+            }
+            (listen::Listener::Tcp(listener), None) => main_server.listen(listener),
+            (listen::Listener::Unix(listener), None) => main_server.listen_uds(listener),
+            (listen::Listener::Unix(_), Some(_)) => {
+                bail!("TLS termination is not supported on Unix sockets")
+            }
+        }
+        .context(format!("Listening with the main service on {}", name))?;
+    }
+    let main_server = main_server.run();
 
     // metrics endpoints has started running
     *state.live.write() = true;
@@ -184,12 +393,18 @@ async fn main() -> Result<(), Error> {
 
     debug!("waiting for the application to be ready");
 
+    // Readiness probes are not offered to clients.
+    let probe_state = AppState {
+        exposure: None,
+        ..state.clone()
+    };
+
     // wait for the application to be initialized and the cache refreshed.
-    while *state.ready.read() == false {
+    while !*state.ready.read() {
         thread::sleep(Duration::new(10, 0));
         let resp = graph::index(
             http_req.clone(),
-            actix_web::web::Data::<AppState>::new(state.clone()),
+            actix_web::web::Data::<AppState>::new(probe_state.clone()),
         )
         .await;
         let status =
@@ -214,25 +429,46 @@ async fn default_response(req: HttpRequest) -> HttpResponse {
             .map(|addr| addr.to_string())
             .unwrap_or_else(|| "<not available>".into())
     );
-    HttpResponse::new(StatusCode::NOT_FOUND)
+    commons::GraphError::NotFound(format!("{} {}", req.method(), req.path())).as_json_error()
 }
 
 /// Shared application configuration (cloned per-thread).
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, SmartDefault)]
 pub struct AppState {
     /// Query parameters that must be present in all client requests.
     mandatory_params: HashSet<String>,
     /// Upstream cincinnati service.
     path_prefix: String,
     /// Policy plugins.
+    #[default(&[])]
     plugins: &'static [BoxedPlugin],
     live: Arc<RwLock<bool>>,
     ready: Arc<RwLock<bool>>,
+    #[default(Box::leak(Box::new(Registry::new())))]
     registry: &'static Registry,
+    /// Edge exposure counters, if enabled.
+    exposure: Option<Arc<EdgeExposure>>,
+    /// Graph response cache, if enabled.
+    cache: Option<Arc<ResponseCache>>,
+    /// Bearer token required by the debug mode, which is disabled if unset.
+    debug_token: Option<Arc<String>>,
+    /// Self-test of the main service, which is unavailable if unset.
+    selftest: Option<Arc<SelfTest>>,
+    /// Signer of the graph responses, which are unsigned if unset.
+    signer: Option<Arc<GraphSigner>>,
+    /// Keys of the release metadata, with the prefix of the configured plugins.
+    metadata_keys: Arc<MetadataKeys>,
+    /// Values of the upstream graph the metric labels are restricted to.
+    known_labels: Option<Arc<KnownLabels>>,
+    /// Per-channel request counters, which are disabled if unset.
+    request_metrics: Option<Arc<RequestMetrics>>,
+    /// Anonymous client telemetry, which is disabled if unset.
+    client_telemetry: Option<Arc<ClientTelemetry>>,
 }
 
 impl AppState {
     /// Creates a new State with the given arguments
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         mandatory_params: HashSet<String>,
         path_prefix: String,
@@ -240,6 +476,8 @@ impl AppState {
         live: Arc<RwLock<bool>>,
         ready: Arc<RwLock<bool>>,
         registry: &'static Registry,
+        exposure: Option<Arc<EdgeExposure>>,
+        cache: Option<Arc<ResponseCache>>,
     ) -> AppState {
         AppState {
             mandatory_params,
@@ -248,9 +486,60 @@ impl AppState {
             live,
             ready,
             registry,
+            exposure,
+            cache,
+            debug_token: None,
+            selftest: None,
+            signer: None,
+            metadata_keys: Default::default(),
+            known_labels: None,
+            request_metrics: None,
+            client_telemetry: None,
         }
     }
 
+    /// Sets the bearer token which enables the debug mode.
+    pub fn with_debug_token(mut self, debug_token: Option<String>) -> AppState {
+        self.debug_token = debug_token.map(Arc::new);
+        self
+    }
+
+    /// Sets the self-test of the main service.
+    pub fn with_selftest(mut self, selftest: Option<SelfTest>) -> AppState {
+        self.selftest = selftest.map(Arc::new);
+        self
+    }
+
+    /// Sets the signer of the graph responses.
+    pub fn with_signer(mut self, signer: Option<GraphSigner>) -> AppState {
+        self.signer = signer.map(Arc::new);
+        self
+    }
+
+    /// Sets the keys of the release metadata.
+    pub fn with_metadata_keys(mut self, metadata_keys: MetadataKeys) -> AppState {
+        self.metadata_keys = Arc::new(metadata_keys);
+        self
+    }
+
+    /// Sets the values of the upstream graph, collected when it is fetched.
+    pub fn with_known_labels(mut self, known_labels: Option<Arc<KnownLabels>>) -> AppState {
+        self.known_labels = known_labels;
+        self
+    }
+
+    /// Sets the per-channel request counters.
+    pub fn with_request_metrics(mut self, request_metrics: Option<RequestMetrics>) -> AppState {
+        self.request_metrics = request_metrics.map(Arc::new);
+        self
+    }
+
+    /// Sets the anonymous client telemetry.
+    pub fn with_client_telemetry(mut self, client_telemetry: Option<ClientTelemetry>) -> AppState {
+        self.client_telemetry = client_telemetry.map(Arc::new);
+        self
+    }
+
     /// Returns the boolean inside self.live
     pub fn is_live(&self) -> bool {
         *self.live.read()
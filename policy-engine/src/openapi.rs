@@ -1,5 +1,5 @@
 use crate::AppState;
-use actix_web::{dev::Response, HttpResponse};
+use actix_web::{dev::Response, http::StatusCode, HttpResponse};
 use commons::prelude_errors::*;
 use openapiv3::{OpenAPI, ReferenceOr};
 use std::collections::HashSet;
@@ -15,13 +15,15 @@ pub(crate) async fn index(app_data: actix_web::web::Data<AppState>) -> HttpRespo
             Ok(o) => o,
             Err(e) => {
                 error!("{}", e);
-                return HttpResponse::InternalServerError().body(e.to_string());
+                return internal_error(e);
             }
         };
 
-    // Add mandatory parameters to the `graph` endpoint.
-    if let Some(path) = spec_object.paths.paths.get_mut("/graph") {
-        add_mandatory_params(path, &app_data.mandatory_params);
+    // Add mandatory parameters to the `graph` endpoints.
+    for graph_path in &["/graph", "/v2/graph"] {
+        if let Some(path) = spec_object.paths.paths.get_mut(*graph_path) {
+            add_mandatory_params(path, &app_data.mandatory_params);
+        }
     }
 
     // Prefix all paths with `path_prefix`
@@ -34,10 +36,20 @@ pub(crate) async fn index(app_data: actix_web::web::Data<AppState>) -> HttpRespo
         .map(HttpResponse::from)
         .unwrap_or_else(|e| {
             error!("{:?}", e);
-            HttpResponse::InternalServerError().body(e.to_string())
+            internal_error(e)
         })
 }
 
+fn internal_error(e: Error) -> HttpResponse {
+    commons::ProblemDetails::new(
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "openapi_error",
+        "Failed to render the OpenAPI document",
+        format!("{:#}", e),
+    )
+    .into_response()
+}
+
 fn rewrite_paths(paths: openapiv3::Paths, path_prefix: &str) -> openapiv3::Paths {
     let mut new_paths = paths.clone();
     new_paths.paths = paths
@@ -156,12 +168,13 @@ mod tests {
             .cloned()
             .map(String::from)
             .collect();
-        let path_prefix = "test_prefix".to_string();
+        let path_prefix = "/test_prefix".to_string();
 
         let data = actix_web::web::Data::new(AppState {
             mandatory_params: mandatory_params.clone(),
             path_prefix: path_prefix.clone(),
             plugins: Box::leak(Box::new([])),
+            ..Default::default()
         });
         let resource =
             actix_web::web::resource(service_uri).route(actix_web::web::get().to(super::index));
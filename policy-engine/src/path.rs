@@ -0,0 +1,122 @@
+//! Update-path computation.
+//!
+//! Clients planning an update across several releases would otherwise have to
+//! download the whole graph and search it themselves. The graph is computed by
+//! the plugin chain as for graph requests, and the shortest update path between
+//! the `from` and `to` versions is returned along with the risks of the
+//! conditional edges it goes through.
+
+use crate::graph::{add_provenance_headers, api_response_error, run_plugins};
+use crate::AppState;
+use actix_web::http::header;
+use actix_web::web::Query;
+use actix_web::{HttpRequest, HttpResponse};
+use cincinnati::{Graph, PathStep, CONTENT_TYPE};
+use commons::tracing::get_tracer;
+use commons::GraphError;
+use opentelemetry::trace::{mark_span_as_active, Tracer};
+use std::collections::HashMap;
+
+/// Body of an update-path response.
+#[derive(Debug, Serialize, PartialEq)]
+pub(crate) struct PathResponse<'a> {
+    from: &'a str,
+    to: &'a str,
+    /// Edges along the path, in update order.
+    path: Vec<PathStep<'a>>,
+}
+
+/// Serve the shortest update path between two versions.
+pub(crate) async fn index(
+    req: HttpRequest,
+    app_data: actix_web::web::Data<AppState>,
+) -> Result<HttpResponse, GraphError> {
+    _index(&req, app_data)
+        .await
+        .map_err(|e| api_response_error(&req, e))
+}
+
+async fn _index(
+    req: &HttpRequest,
+    app_data: actix_web::web::Data<AppState>,
+) -> Result<HttpResponse, GraphError> {
+    let span = get_tracer().start("path");
+    let _active_span = mark_span_as_active(span);
+
+    let accept_default = header::HeaderValue::from_static(CONTENT_TYPE);
+    commons::validate_content_type(req.headers(), vec![accept_default.clone()], accept_default)?;
+
+    let mut mandatory_params = app_data.mandatory_params.clone();
+    mandatory_params.insert("from".to_string());
+    mandatory_params.insert("to".to_string());
+    commons::ensure_query_params(&mandatory_params, req.query_string())?;
+
+    let mut params = Query::<HashMap<String, String>>::from_query(req.query_string())
+        .map(|query| query.into_inner())
+        .map_err(|e| GraphError::InvalidParams(e.to_string()))?;
+    // The endpoints of the path only apply to the response, the plugins don't need them.
+    let from = params.remove("from").unwrap_or_default();
+    let to = params.remove("to").unwrap_or_default();
+    params.insert("content_type".to_string(), CONTENT_TYPE.to_string());
+
+    let io = run_plugins(app_data.plugins.iter(), params).await?;
+    let path = shortest_path(&io.graph, &from, &to)?;
+
+    let mut resp = HttpResponse::Ok();
+    add_provenance_headers(&mut resp, &io);
+    Ok(resp.json(PathResponse {
+        from: &from,
+        to: &to,
+        path,
+    }))
+}
+
+/// Compute the shortest update path, telling unknown versions apart from unreachable ones.
+fn shortest_path<'a>(
+    graph: &'a Graph,
+    from: &str,
+    to: &str,
+) -> Result<Vec<PathStep<'a>>, GraphError> {
+    for version in &[from, to] {
+        if graph.find_by_version(version).is_none() {
+            return Err(GraphError::NoUpdatePath(format!(
+                "version {} is not in the graph",
+                version
+            )));
+        }
+    }
+
+    graph
+        .shortest_path(from, to)
+        .ok_or_else(|| GraphError::NoUpdatePath(format!("{} can't be updated to {}", from, to)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cincinnati::testing::generate_graph;
+
+    #[test]
+    fn update_path() {
+        let graph = generate_graph(true, false);
+
+        let path = shortest_path(&graph, "1.0.0", "3.0.0").unwrap();
+        assert_eq!(
+            serde_json::to_value(&path).unwrap(),
+            serde_json::json!([{"from": "1.0.0", "to": "3.0.0", "risks": []}])
+        );
+
+        match shortest_path(&graph, "3.0.0", "1.0.0") {
+            Err(GraphError::NoUpdatePath(msg)) => {
+                assert_eq!(msg, "3.0.0 can't be updated to 1.0.0")
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+        match shortest_path(&graph, "1.0.0", "9.0.0") {
+            Err(GraphError::NoUpdatePath(msg)) => {
+                assert_eq!(msg, "version 9.0.0 is not in the graph")
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+}
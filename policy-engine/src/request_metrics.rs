@@ -0,0 +1,353 @@
+//! Per-channel request metrics.
+//!
+//! Counts graph requests by requested channel and architecture, so that
+//! operators see which channels are actually queried. Both are client input,
+//! so the label values are bounded: allowed values are always tracked, values
+//! of the upstream graph only until a maximum number of distinct values is
+//! reached, and any other value is counted as "other".
+
+use cincinnati::metadata_keys::MetadataKeys;
+use cincinnati::plugins::InternalIO;
+use cincinnati::provenance::Provenance;
+use cincinnati::Release;
+use commons::prelude_errors::*;
+use parking_lot::{Mutex, RwLock};
+use prometheus::{IntCounterVec, Opts, Registry};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+/// Default maximum number of distinct non-allowed values per label.
+pub static DEFAULT_MAX_LABEL_VALUES: usize = 100;
+
+/// Label of values beyond the limit.
+pub(crate) static OTHER_LABEL: &str = "other";
+
+/// Label of requests without the parameter.
+pub(crate) static NONE_LABEL: &str = "none";
+
+lazy_static! {
+    static ref GRAPH_CHANNEL_REQS: IntCounterVec = IntCounterVec::new(
+        Opts::new(
+            "graph_channel_requests_total",
+            "Total number of graph requests by requested channel and architecture"
+        ),
+        &["channel", "arch"]
+    )
+    .unwrap();
+}
+
+/// Register relevant metrics to a prometheus registry.
+pub(crate) fn register_metrics(registry: &Registry) -> Fallible<()> {
+    registry.register(Box::new(GRAPH_CHANNEL_REQS.clone()))?;
+    Ok(())
+}
+
+/// Settings of the per-channel request metrics.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RequestMetricsSettings {
+    /// Channels which are always tracked.
+    pub channels: HashSet<String>,
+    /// Architectures which are always tracked.
+    pub arches: HashSet<String>,
+    /// Maximum number of distinct non-allowed values per label.
+    pub max_label_values: usize,
+}
+
+/// Kind of the values of a label.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum LabelKind {
+    Channel,
+    Arch,
+    /// `MAJOR.MINOR` versions.
+    MinorVersion,
+}
+
+/// Values of the upstream graph.
+#[derive(Debug, Default)]
+struct Known {
+    /// Hash of the graph the values were collected from.
+    generation: Option<String>,
+    channels: HashSet<String>,
+    arches: HashSet<String>,
+    minor_versions: HashSet<String>,
+}
+
+/// Channels, architectures and minor versions of the upstream graph.
+///
+/// Only these values are tracked besides the allowlists, so that made-up
+/// request parameters never take a label value.
+#[derive(Debug, Default)]
+pub struct KnownLabels {
+    /// Keys of the release metadata the values are read from.
+    keys: MetadataKeys,
+    known: RwLock<Known>,
+}
+
+impl KnownLabels {
+    /// Known labels read from the release metadata at the given keys.
+    pub fn new(keys: MetadataKeys) -> Self {
+        Self {
+            keys,
+            known: Default::default(),
+        }
+    }
+
+    /// Collect the values of the upstream graph, unless it was already seen.
+    pub(crate) fn observe(&self, upstream: &InternalIO) {
+        let generation = Provenance::from_parameters(&upstream.parameters)
+            .and_then(|provenance| provenance.hash);
+        if generation.is_some() && self.known.read().generation == generation {
+            return;
+        }
+
+        let mut known = Known {
+            generation,
+            ..Default::default()
+        };
+        for release in upstream.graph.iter_releases() {
+            let release = match release {
+                Release::Concrete(release) => release,
+                Release::Abstract(_) => continue,
+            };
+            if let Some(channels) = release.metadata.get(self.keys.channels.as_str()) {
+                known.channels.extend(
+                    channels
+                        .split(',')
+                        .map(str::trim)
+                        .filter(|channel| !channel.is_empty())
+                        .map(str::to_string),
+                );
+            }
+            if let Some(arch) = release.metadata.get(self.keys.arch.as_str()) {
+                known.arches.insert(arch.to_string());
+            }
+            if let Some(minor) = minor_version(&release.version) {
+                known.minor_versions.insert(minor);
+            }
+        }
+        *self.known.write() = known;
+    }
+
+    fn contains(&self, kind: LabelKind, value: &str) -> bool {
+        let known = self.known.read();
+        match kind {
+            LabelKind::Channel => known.channels.contains(value),
+            LabelKind::Arch => known.arches.contains(value),
+            LabelKind::MinorVersion => known.minor_versions.contains(value),
+        }
+    }
+}
+
+/// The `MAJOR.MINOR` part of a version, if valid.
+pub(crate) fn minor_version(version: &str) -> Option<String> {
+    let version = semver::Version::parse(version).ok()?;
+    Some(format!("{}.{}", version.major, version.minor))
+}
+
+/// Bounded set of values of a single label.
+#[derive(Debug)]
+pub(crate) struct LabelValues {
+    kind: LabelKind,
+    allowed: HashSet<String>,
+    known: Arc<KnownLabels>,
+    max_values: usize,
+    seen: Mutex<HashSet<String>>,
+}
+
+impl LabelValues {
+    pub(crate) fn new(
+        kind: LabelKind,
+        allowed: HashSet<String>,
+        known: Arc<KnownLabels>,
+        max_values: usize,
+    ) -> Self {
+        Self {
+            kind,
+            allowed,
+            known,
+            max_values,
+            seen: Mutex::new(HashSet::new()),
+        }
+    }
+
+    /// Return the label of a requested value.
+    pub(crate) fn label(&self, value: Option<&str>) -> String {
+        let value = match value {
+            Some(value) => value,
+            None => return NONE_LABEL.to_string(),
+        };
+        if self.allowed.contains(value) {
+            return value.to_string();
+        }
+        if !self.known.contains(self.kind, value) {
+            return OTHER_LABEL.to_string();
+        }
+
+        let mut seen = self.seen.lock();
+        if seen.contains(value) {
+            return value.to_string();
+        }
+        if seen.len() < self.max_values {
+            seen.insert(value.to_string());
+            return value.to_string();
+        }
+        OTHER_LABEL.to_string()
+    }
+}
+
+/// Request counters by channel and architecture.
+#[derive(Debug)]
+pub struct RequestMetrics {
+    channels: LabelValues,
+    arches: LabelValues,
+}
+
+impl RequestMetrics {
+    /// Create the counters, tracking the `known` values beyond the allowlists.
+    pub fn new(settings: RequestMetricsSettings, known: Arc<KnownLabels>) -> Self {
+        Self {
+            channels: LabelValues::new(
+                LabelKind::Channel,
+                settings.channels,
+                known.clone(),
+                settings.max_label_values,
+            ),
+            arches: LabelValues::new(
+                LabelKind::Arch,
+                settings.arches,
+                known,
+                settings.max_label_values,
+            ),
+        }
+    }
+
+    /// Count a graph request with the given query parameters.
+    pub fn record(&self, params: &HashMap<String, String>) {
+        let channel = self
+            .channels
+            .label(params.get("channel").map(String::as_str));
+        let arch = self.arches.label(params.get("arch").map(String::as_str));
+        GRAPH_CHANNEL_REQS
+            .with_label_values(&[&channel, &arch])
+            .inc();
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use cincinnati::testing::generate_custom_graph;
+
+    /// Known labels of a graph with the given channels and architecture of each release.
+    pub(crate) fn known_labels(releases: &[(&str, &str)]) -> Arc<KnownLabels> {
+        let keys = MetadataKeys::with_prefix("com.example.graph");
+        let metadata = releases
+            .iter()
+            .enumerate()
+            .map(|(i, (channels, arch))| {
+                let metadata = vec![
+                    (keys.channels.clone(), channels.to_string()),
+                    (keys.arch.clone(), arch.to_string()),
+                ];
+                (i, metadata.into_iter().collect())
+            })
+            .collect();
+        let known = Arc::new(KnownLabels::new(keys.clone()));
+        known.observe(&InternalIO {
+            // The labels don't depend on the edges, this also allows no releases.
+            graph: generate_custom_graph("image", metadata, Some(vec![])),
+            parameters: HashMap::new(),
+        });
+        known
+    }
+
+    #[test]
+    fn observe_graph() {
+        let known = known_labels(&[("stable-4.15, fast-4.15", "amd64"), ("", "arm64")]);
+
+        assert!(known.contains(LabelKind::Channel, "stable-4.15"));
+        assert!(known.contains(LabelKind::Channel, "fast-4.15"));
+        assert!(!known.contains(LabelKind::Channel, ""));
+        assert!(known.contains(LabelKind::Arch, "arm64"));
+        assert!(!known.contains(LabelKind::Arch, "s390x"));
+        assert!(known.contains(LabelKind::MinorVersion, "1.0"));
+        assert!(!known.contains(LabelKind::MinorVersion, "2.0"));
+    }
+
+    #[test]
+    fn bounded_labels() {
+        let known = known_labels(&[
+            (
+                "stable-4.15,fast-4.15,candidate-4.15,candidate-4.16",
+                "amd64",
+            ),
+            ("", "arm64"),
+        ]);
+        let values = LabelValues::new(
+            LabelKind::Channel,
+            vec!["stable-4.15".to_string()].into_iter().collect(),
+            known.clone(),
+            2,
+        );
+
+        assert_eq!(values.label(None), NONE_LABEL);
+        assert_eq!(values.label(Some("fast-4.15")), "fast-4.15");
+
+        // Values which aren't in the graph never take a slot.
+        assert_eq!(values.label(Some("<script>")), OTHER_LABEL);
+        assert_eq!(values.label(Some("candidate-4.15")), "candidate-4.15");
+
+        // Once the limit is reached, new values are bucketed.
+        assert_eq!(values.label(Some("candidate-4.16")), OTHER_LABEL);
+        assert_eq!(values.label(Some("fast-4.15")), "fast-4.15");
+
+        // Allowed values don't count towards the limit.
+        assert_eq!(values.label(Some("stable-4.15")), "stable-4.15");
+
+        // Only allowed values are tracked without a limit.
+        let strict = LabelValues::new(
+            LabelKind::Arch,
+            vec!["amd64".to_string()].into_iter().collect(),
+            known,
+            0,
+        );
+        assert_eq!(strict.label(Some("amd64")), "amd64");
+        assert_eq!(strict.label(Some("arm64")), OTHER_LABEL);
+    }
+
+    #[test]
+    fn record_requests() {
+        let metrics = RequestMetrics::new(
+            RequestMetricsSettings {
+                channels: vec!["candidate-4.99".to_string()].into_iter().collect(),
+                arches: HashSet::new(),
+                max_label_values: 1,
+            },
+            known_labels(&[("fast-4.99", "s390x")]),
+        );
+        let params = |pairs: &[(&str, &str)]| {
+            pairs
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect::<HashMap<_, _>>()
+        };
+        let count = |channel: &str, arch: &str| {
+            GRAPH_CHANNEL_REQS.with_label_values(&[channel, arch]).get()
+        };
+
+        let before = count("candidate-4.99", OTHER_LABEL);
+        metrics.record(&params(&[
+            ("channel", "candidate-4.99"),
+            ("arch", "ppc64le"),
+        ]));
+        assert_eq!(count("candidate-4.99", OTHER_LABEL), before + 1);
+
+        let before = count("fast-4.99", "s390x");
+        metrics.record(&params(&[("channel", "fast-4.99"), ("arch", "s390x")]));
+        assert_eq!(count("fast-4.99", "s390x"), before + 1);
+
+        let before = count(OTHER_LABEL, NONE_LABEL);
+        metrics.record(&params(&[("channel", "bogus-4.99")]));
+        assert_eq!(count(OTHER_LABEL, NONE_LABEL), before + 1);
+    }
+}
@@ -0,0 +1,355 @@
+//! Signed graph responses.
+//!
+//! Clients behind caching mirrors can't rely on TLS to tell whether the graph
+//! they got is the one served by Cincinnati. With a signing key configured,
+//! graph responses carry a detached JWS (RFC 7515, appendix F) of their body,
+//! which clients verify against the public key, served as a JWK set.
+//!
+//! So that a signed graph can't be replayed to other clusters or long after
+//! it was served, the protected header also carries the time of the signature,
+//! its expiry, and the channel and architecture requested by the client.
+
+use crate::AppState;
+use actix_web::HttpResponse;
+use commons::prelude_errors::*;
+use commons::GraphError;
+use custom_debug_derive::Debug as CustomDebug;
+use std::collections::HashMap;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Header carrying the detached JWS of the response body.
+pub static SIGNATURE_HEADER: &str = "cincinnati-graph-signature";
+
+/// Default validity of the signatures.
+pub static DEFAULT_SIGNATURE_VALIDITY: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Content type of the public keys.
+static JWK_SET_CONTENT_TYPE: &str = "application/jwk-set+json";
+
+/// Protected header parameters which clients must check, per RFC 7515 section 4.1.11.
+static CRITICAL_PARAMS: &[&str] = &["iat", "exp", "channel", "arch"];
+
+/// JWS algorithm signing the graph responses.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Serialize, Default)]
+pub enum SigningAlgorithm {
+    /// ECDSA using P-256 and SHA-256.
+    #[default]
+    ES256,
+    /// ECDSA using P-384 and SHA-384.
+    ES384,
+}
+
+impl FromStr for SigningAlgorithm {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ES256" => Ok(SigningAlgorithm::ES256),
+            "ES384" => Ok(SigningAlgorithm::ES384),
+            x => Err(format!(
+                "unknown signing algorithm '{}', expected 'ES256' or 'ES384'",
+                x
+            )),
+        }
+    }
+}
+
+impl SigningAlgorithm {
+    /// Name of the algorithm in the JWS header.
+    pub fn name(self) -> &'static str {
+        match self {
+            SigningAlgorithm::ES256 => "ES256",
+            SigningAlgorithm::ES384 => "ES384",
+        }
+    }
+}
+
+enum SigningKey {
+    ES256(p256::ecdsa::SigningKey),
+    ES384(p384::ecdsa::SigningKey),
+}
+
+/// Request parameters a signature is bound to.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SignatureBinding {
+    pub channel: Option<String>,
+    pub arch: Option<String>,
+}
+
+impl SignatureBinding {
+    /// Bind to the channel and architecture of the request parameters.
+    pub fn of(params: &HashMap<String, String>) -> Self {
+        Self {
+            channel: params.get("channel").cloned(),
+            arch: params.get("arch").cloned(),
+        }
+    }
+}
+
+/// Encode the coordinates of a public key as a JWK.
+macro_rules! public_jwk {
+    ($curve:ident, $crv:expr, $key:expr) => {{
+        use $curve::elliptic_curve::sec1::ToEncodedPoint;
+        let point = $curve::PublicKey::from($key.verifying_key()).to_encoded_point(false);
+        serde_json::json!({
+            "kty": "EC",
+            "crv": $crv,
+            "x": encode(point.x().map(|x| x.as_slice()).unwrap_or_default()),
+            "y": encode(point.y().map(|y| y.as_slice()).unwrap_or_default()),
+        })
+    }};
+}
+
+/// Decode a PKCS#8 or SEC1 PEM private key of the given curve.
+macro_rules! decode_key {
+    ($curve:ident, $pem:expr) => {{
+        use $curve::pkcs8::DecodePrivateKey;
+        $curve::SecretKey::from_pkcs8_pem($pem)
+            .or_else(|_| $curve::SecretKey::from_sec1_pem($pem))
+            .map(|key| $curve::ecdsa::SigningKey::from(key))
+            .map_err(|e| format_err!("not a {} private key: {}", stringify!($curve), e))
+    }};
+}
+
+/// Signs graph responses with a detached JWS.
+#[derive(CustomDebug)]
+pub struct GraphSigner {
+    algorithm: SigningAlgorithm,
+    key_id: Option<String>,
+    /// Time after which the signatures expire.
+    validity: Duration,
+    #[debug(skip)]
+    key: SigningKey,
+}
+
+impl GraphSigner {
+    /// Load the private key from a PEM file.
+    pub fn from_pem_file(
+        path: &Path,
+        algorithm: SigningAlgorithm,
+        key_id: Option<&str>,
+        validity: Duration,
+    ) -> Fallible<Self> {
+        let pem = std::fs::read_to_string(path)
+            .context(format!("reading signing key from {}", path.display()))?;
+        Self::from_pem(&pem, algorithm, key_id, validity)
+            .context(format!("loading signing key from {}", path.display()))
+    }
+
+    /// Load the private key from a PEM string.
+    pub fn from_pem(
+        pem: &str,
+        algorithm: SigningAlgorithm,
+        key_id: Option<&str>,
+        validity: Duration,
+    ) -> Fallible<Self> {
+        let key = match algorithm {
+            SigningAlgorithm::ES256 => SigningKey::ES256(decode_key!(p256, pem)?),
+            SigningAlgorithm::ES384 => SigningKey::ES384(decode_key!(p384, pem)?),
+        };
+
+        Ok(Self {
+            algorithm,
+            key_id: key_id.map(str::to_string),
+            validity,
+            key,
+        })
+    }
+
+    /// Return the algorithm of the signatures.
+    pub fn algorithm(&self) -> SigningAlgorithm {
+        self.algorithm
+    }
+
+    /// Return the public key as a JWK set, for clients to verify the signatures.
+    pub fn jwks(&self) -> serde_json::Value {
+        let mut jwk = match &self.key {
+            SigningKey::ES256(key) => public_jwk!(p256, "P-256", key),
+            SigningKey::ES384(key) => public_jwk!(p384, "P-384", key),
+        };
+        jwk["alg"] = self.algorithm.name().into();
+        jwk["use"] = "sig".into();
+        if let Some(key_id) = &self.key_id {
+            jwk["kid"] = key_id.as_str().into();
+        }
+        serde_json::json!({ "keys": [jwk] })
+    }
+
+    /// Return the detached JWS of `payload` bound to `binding`, in the compact
+    /// serialization with an empty payload part.
+    pub fn sign(&self, payload: &[u8], binding: &SignatureBinding) -> String {
+        self.sign_at(payload, binding, SystemTime::now())
+    }
+
+    fn sign_at(&self, payload: &[u8], binding: &SignatureBinding, now: SystemTime) -> String {
+        use p256::ecdsa::signature::Signer;
+
+        let issued_at = now.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        let mut header = serde_json::json!({
+            "alg": self.algorithm.name(),
+            "iat": issued_at,
+            "exp": issued_at + self.validity.as_secs(),
+            "channel": binding.channel,
+            "arch": binding.arch,
+            "crit": CRITICAL_PARAMS,
+        });
+        if let Some(key_id) = &self.key_id {
+            header["kid"] = key_id.as_str().into();
+        }
+        let header = encode(header.to_string().as_bytes());
+
+        let input = format!("{}.{}", header, encode(payload));
+        let signature = match &self.key {
+            SigningKey::ES256(key) => {
+                let signature: p256::ecdsa::Signature = key.sign(input.as_bytes());
+                signature.to_bytes().to_vec()
+            }
+            SigningKey::ES384(key) => {
+                let signature: p384::ecdsa::Signature = key.sign(input.as_bytes());
+                signature.to_bytes().to_vec()
+            }
+        };
+
+        format!("{}..{}", header, encode(&signature))
+    }
+}
+
+/// Serve the public key of the signatures, or 404 if responses are unsigned.
+pub(crate) async fn serve_keys(
+    app_data: actix_web::web::Data<AppState>,
+) -> Result<HttpResponse, GraphError> {
+    match &app_data.signer {
+        Some(signer) => Ok(HttpResponse::Ok()
+            .content_type(JWK_SET_CONTENT_TYPE)
+            .body(signer.jwks().to_string())),
+        None => Err(GraphError::NotFound(
+            "graph responses are not signed".to_string(),
+        )),
+    }
+}
+
+fn encode(data: &[u8]) -> String {
+    base64::encode_config(data, base64::URL_SAFE_NO_PAD)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use p256::ecdsa::signature::Verifier;
+    use p256::pkcs8::{EncodePrivateKey, LineEnding};
+
+    fn decode(data: &str) -> Vec<u8> {
+        base64::decode_config(data, base64::URL_SAFE_NO_PAD).unwrap()
+    }
+
+    #[test]
+    fn sign_detached() -> Fallible<()> {
+        let secret = p256::SecretKey::from_slice(&[7u8; 32])?;
+        let pem = secret.to_pkcs8_pem(LineEnding::LF)?;
+        let signer = GraphSigner::from_pem(
+            &pem,
+            SigningAlgorithm::ES256,
+            Some("graph-2024"),
+            Duration::from_secs(3600),
+        )?;
+
+        let body = br#"{"version":1,"nodes":[],"edges":[],"conditionalEdges":[]}"#;
+        let binding = SignatureBinding {
+            channel: Some("stable-4.14".to_string()),
+            arch: None,
+        };
+        let now = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let jws = signer.sign_at(body, &binding, now);
+        let parts: Vec<&str> = jws.split('.').collect();
+        assert_eq!(parts.len(), 3);
+        assert!(parts[1].is_empty(), "payload is detached");
+
+        let header: serde_json::Value = serde_json::from_slice(&decode(parts[0]))?;
+        assert_eq!(
+            header,
+            serde_json::json!({
+                "alg": "ES256",
+                "kid": "graph-2024",
+                "iat": 1_700_000_000u64,
+                "exp": 1_700_003_600u64,
+                "channel": "stable-4.14",
+                "arch": null,
+                "crit": ["iat", "exp", "channel", "arch"],
+            })
+        );
+
+        // Clients put the body back in to verify the signature.
+        let verifying_key = p256::ecdsa::VerifyingKey::from(&secret.public_key());
+        let signature = p256::ecdsa::Signature::from_slice(&decode(parts[2]))?;
+        let input = format!("{}.{}", parts[0], encode(body));
+        verifying_key.verify(input.as_bytes(), &signature)?;
+
+        let tampered = format!("{}.{}", parts[0], encode(b"{}"));
+        assert!(verifying_key
+            .verify(tampered.as_bytes(), &signature)
+            .is_err());
+
+        // The signature doesn't carry over to another channel.
+        let other = signer.sign_at(
+            body,
+            &SignatureBinding {
+                channel: Some("fast-4.14".to_string()),
+                arch: None,
+            },
+            now,
+        );
+        let other_header = other.split('.').next().unwrap();
+        let replayed = format!("{}.{}", other_header, encode(body));
+        assert!(verifying_key
+            .verify(replayed.as_bytes(), &signature)
+            .is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn public_jwks() -> Fallible<()> {
+        use p256::elliptic_curve::sec1::ToEncodedPoint;
+
+        let secret = p256::SecretKey::from_slice(&[7u8; 32])?;
+        let pem = secret.to_pkcs8_pem(LineEnding::LF)?;
+        let signer = GraphSigner::from_pem(
+            &pem,
+            SigningAlgorithm::ES256,
+            Some("graph-2024"),
+            DEFAULT_SIGNATURE_VALIDITY,
+        )?;
+
+        let point = secret.public_key().to_encoded_point(false);
+        assert_eq!(
+            signer.jwks(),
+            serde_json::json!({"keys": [{
+                "kty": "EC",
+                "crv": "P-256",
+                "x": encode(point.x().unwrap()),
+                "y": encode(point.y().unwrap()),
+                "alg": "ES256",
+                "use": "sig",
+                "kid": "graph-2024",
+            }]})
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn key_mismatch() -> Fallible<()> {
+        let secret = p256::SecretKey::from_slice(&[7u8; 32])?;
+        let pem = secret.to_pkcs8_pem(LineEnding::LF)?;
+
+        let validity = DEFAULT_SIGNATURE_VALIDITY;
+        assert!(GraphSigner::from_pem(&pem, SigningAlgorithm::ES384, None, validity).is_err());
+        assert!(GraphSigner::from_pem("", SigningAlgorithm::ES256, None, validity).is_err());
+        assert_eq!("ES384".parse(), Ok(SigningAlgorithm::ES384));
+        assert!("es256".parse::<SigningAlgorithm>().is_err());
+
+        Ok(())
+    }
+}
@@ -1,7 +1,9 @@
 //! Status service.
 
 use crate::AppState;
-use actix_web::HttpResponse;
+use actix_web::http::StatusCode;
+use actix_web::{HttpResponse, ResponseError};
+use commons::{GraphError, ProblemDetails};
 
 /// Expose liveness status.
 ///
@@ -12,7 +14,13 @@ pub async fn serve_liveness(app_data: actix_web::web::Data<AppState>) -> HttpRes
     if app_data.is_live() {
         HttpResponse::Ok().finish()
     } else {
-        HttpResponse::ServiceUnavailable().finish()
+        ProblemDetails::new(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "not_live",
+            "Service not live",
+            "the service has not started yet".to_string(),
+        )
+        .into_response()
     }
 }
 
@@ -25,6 +33,44 @@ pub async fn serve_readiness(app_data: actix_web::web::Data<AppState>) -> HttpRe
     if app_data.is_ready() {
         HttpResponse::Ok().finish()
     } else {
-        HttpResponse::ServiceUnavailable().finish()
+        ProblemDetails::new(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "not_ready",
+            "Service not ready",
+            "no graph is available yet".to_string(),
+        )
+        .into_response()
+    }
+}
+
+/// Exercise the graph endpoint of the main service as clients do.
+///
+/// Status:
+///  * OK (200 code): all checks passed.
+///  * Too Many Requests (429 code): the previous run is too recent.
+///  * Service Unavailable (503 code): at least one check failed.
+pub async fn serve_selftest(app_data: actix_web::web::Data<AppState>) -> HttpResponse {
+    let selftest = match &app_data.selftest {
+        Some(selftest) => selftest,
+        None => {
+            return ProblemDetails::new(
+                StatusCode::SERVICE_UNAVAILABLE,
+                "selftest_unavailable",
+                "Self-test unavailable",
+                "the self-test requires status authentication and a main service listening on a TCP address"
+                    .to_string(),
+            )
+            .into_response()
+        }
+    };
+
+    if let Err(wait) = selftest.try_start() {
+        return GraphError::TooManyRequests(wait.as_secs() + 1).error_response();
+    }
+    let report = selftest.run().await;
+    if report.passed {
+        HttpResponse::Ok().json(report)
+    } else {
+        HttpResponse::ServiceUnavailable().json(report)
     }
 }